@@ -0,0 +1,77 @@
+//! Runs a built-in adapter on arbitrary input, recursing into what it outputs like rga does.
+//! The first byte of the input selects the adapter. Adapters that run external programs are left
+//! out. Run with `cargo +nightly fuzz run adapters`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ripgrep_all::adapters::custom::BUILTIN_SPAWNING_ADAPTERS;
+use ripgrep_all::adapters::{AdaptInfo, FileAdapter, get_all_adapters};
+use ripgrep_all::config::{CacheConfig, RgaConfig};
+use ripgrep_all::matching::FileMatcher;
+use ripgrep_all::preproc::loop_adapt;
+use std::sync::{Arc, LazyLock};
+use tokio::io::AsyncReadExt;
+use tokio_stream::StreamExt;
+
+/// adapters that only wrap an external program
+const EXTERNAL: &[&str] = &["ffmpeg", "rar"];
+
+static ADAPTERS: LazyLock<Vec<Arc<dyn FileAdapter>>> = LazyLock::new(|| {
+    let (enabled, disabled) = get_all_adapters(None);
+    enabled
+        .into_iter()
+        .chain(disabled)
+        .filter(|a| {
+            let name = &a.metadata().name;
+            !EXTERNAL.contains(&name.as_str())
+                && !BUILTIN_SPAWNING_ADAPTERS.iter().any(|s| &s.name == name)
+        })
+        .collect()
+});
+
+static RUNTIME: LazyLock<tokio::runtime::Runtime> =
+    LazyLock::new(|| tokio::runtime::Runtime::new().unwrap());
+
+async fn adapt(adapter: &Arc<dyn FileAdapter>, data: Vec<u8>) {
+    let meta = adapter.metadata();
+    let Some(matcher) = meta.fast_matchers.first() else {
+        return;
+    };
+    let ripgrep_all::matching::FastFileMatcher::FileExtension(extension) = matcher;
+    let ai = AdaptInfo {
+        filepath_hint: format!("fuzz.{extension}").into(),
+        is_real_file: false,
+        file_mtime_unix_ms: None,
+        archive_recursion_depth: 0,
+        inp: Box::pin(std::io::Cursor::new(data)),
+        line_prefix: String::new(),
+        postprocess: true,
+        config: RgaConfig {
+            cache: CacheConfig {
+                disabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    };
+    // errors are fine, panics are what we are looking for
+    let detection_reason = FileMatcher::Fast(matcher.clone());
+    let Ok(mut files) = loop_adapt(adapter.as_ref(), detection_reason, ai, ADAPTERS.clone()).await
+    else {
+        return;
+    };
+    while let Some(Ok(mut file)) = files.next().await {
+        let mut out = vec![];
+        if file.inp.read_to_end(&mut out).await.is_err() {
+            return;
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&which, data)) = data.split_first() else {
+        return;
+    };
+    let adapter = &ADAPTERS[which as usize % ADAPTERS.len()];
+    RUNTIME.block_on(adapt(adapter, data.to_vec()));
+});
@@ -1,12 +1,92 @@
+pub mod ar;
+#[cfg(feature = "media")]
+pub mod audiotags;
+#[cfg(feature = "scientific")]
+pub mod avro;
+#[cfg(feature = "forensics")]
+pub mod cab;
+#[cfg(feature = "office")]
+pub mod chess;
+#[cfg(feature = "media")]
+pub mod comics;
+pub mod confidence;
+pub mod cpio;
 pub mod custom;
 pub mod decompress;
+#[cfg(feature = "scientific")]
+pub mod dicom;
+#[cfg(feature = "forensics")]
+pub mod dmg;
+#[cfg(feature = "office")]
+pub mod edi;
+#[cfg(feature = "forensics")]
+pub mod executable;
+#[cfg(feature = "media")]
+pub mod exif;
+#[cfg(feature = "media")]
 pub mod ffmpeg;
+#[cfg(feature = "office")]
+pub mod finance;
+#[cfg(feature = "forensics")]
+pub mod firmware;
+#[cfg(feature = "scientific")]
+pub mod fits;
+#[cfg(feature = "forensics")]
+pub mod game;
+#[cfg(feature = "scientific")]
+pub mod genomics;
+pub mod gitbundle;
+#[cfg(feature = "media")]
+pub mod gps;
+#[cfg(feature = "scientific")]
+pub mod hdf5;
+#[cfg(feature = "forensics")]
+pub mod installer;
+#[cfg(feature = "forensics")]
+pub mod iso;
+pub mod le;
+#[cfg(feature = "office")]
 pub mod mbox;
+#[cfg(feature = "office")]
+pub mod mhtml;
+#[cfg(feature = "scientific")]
+pub mod mlgraph;
+#[cfg(feature = "scientific")]
+pub mod mlmodel;
+#[cfg(feature = "forensics")]
+pub mod msi;
+#[cfg(feature = "scientific")]
+pub mod orc;
+#[cfg(feature = "scientific")]
+pub mod parquet;
+pub mod patch;
+#[cfg(feature = "forensics")]
+pub mod pcap;
+#[cfg(feature = "pdf-fallback")]
+pub mod pdf;
+pub mod plugin;
 pub mod postproc;
+#[cfg(feature = "media")]
+pub mod psd;
+pub mod rar;
+#[cfg(feature = "scientific")]
+pub mod serialized;
 use std::sync::Arc;
 pub mod sqlite;
+#[cfg(feature = "media")]
+pub mod svg;
 pub mod tar;
+#[cfg(feature = "ocr")]
+pub mod tesseract;
+pub mod wasm;
+#[cfg(feature = "scientific")]
+pub mod weights;
+#[cfg(feature = "media")]
+pub mod whisper;
 pub mod writing;
+#[cfg(feature = "office")]
+pub mod xbrl;
+pub mod zim;
 pub mod zip;
 use crate::{adapted_iter::AdaptedFilesIterBox, config::RgaConfig, matching::*};
 use anyhow::{Context, Result, format_err};
@@ -26,14 +106,56 @@ use std::pin::Pin;
 use self::postproc::PostprocPageBreaks;
 
 pub type ReadBox = Pin<Box<dyn AsyncRead + Send>>;
+
+/// what an adapter needs and produces. preproc uses this to decide how to feed, schedule and cache it
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterCapabilities {
+    /// needs random access to its input. Inputs that are not real files (e.g. in archives) are written to a temporary file first
+    pub seekable_input: bool,
+    /// output contains page breaks (\x0c) that are turned into `Page N:` prefixes
+    pub produces_pages: bool,
+    /// output consists of files that are adapted again (=call rga_preproc again), so the cache key needs to include the list of active adapters
+    pub produces_subfiles: bool,
+    /// same input and config always gives the same output. Output of other adapters is not cached
+    pub deterministic: bool,
+    /// output takes long to compute (e.g. speech to text), so it is cached completely even if the search stops reading early
+    pub slow: bool,
+    /// external programs the adapter runs. Adapters with external programs share the `--rga-max-subprocesses` limit
+    pub external_deps: Vec<String>,
+    /// files next to the input (`<input><suffix>`) the adapter also reads. Their mtime is part of the cache key
+    pub sidecar_suffixes: Vec<String>,
+}
+impl Default for AdapterCapabilities {
+    fn default() -> Self {
+        Self {
+            seekable_input: false,
+            produces_pages: false,
+            produces_subfiles: false,
+            deterministic: true,
+            slow: false,
+            external_deps: vec![],
+            sidecar_suffixes: vec![],
+        }
+    }
+}
+impl AdapterCapabilities {
+    pub fn runs(exes: &[&str]) -> Self {
+        Self {
+            external_deps: exes.iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
 pub struct AdapterMeta {
     /// unique short name of this adapter (a-z0-9 only)
     pub name: String,
-    /// version identifier. used to key cache entries, change if your output format changes
+    /// version identifier. used to key cache entries, change if your output format changes.
+    ///
+    /// Cache entries are not invalidated by rga updates, so this must be bumped for every change to the output
     pub version: i32,
     pub description: String,
-    /// indicates whether this adapter can descend (=call rga_preproc again). if true, the cache key needs to include the list of active adapters
-    pub recurses: bool,
+    pub capabilities: AdapterCapabilities,
     /// list of matchers (interpreted as a OR b OR ...)
     pub fast_matchers: Vec<FastFileMatcher>,
     /// list of matchers when we have mime type detection active (interpreted as ORed)
@@ -108,6 +230,55 @@ pub struct AdaptInfo {
     pub config: RgaConfig,
 }
 
+impl AdaptInfo {
+    /// split off the input stream, keeping what is needed to create the inputs of contained files
+    pub fn into_parts(self) -> (ReadBox, ContainerInfo) {
+        (
+            self.inp,
+            ContainerInfo {
+                filepath_hint: self.filepath_hint,
+                archive_recursion_depth: self.archive_recursion_depth,
+                line_prefix: self.line_prefix,
+                postprocess: self.postprocess,
+                config: self.config,
+            },
+        )
+    }
+}
+
+/// the parts of an [`AdaptInfo`] of a container (archive, file system image, ...) that its members inherit
+#[derive(Clone)]
+pub struct ContainerInfo {
+    pub filepath_hint: PathBuf,
+    pub archive_recursion_depth: i32,
+    pub line_prefix: String,
+    pub postprocess: bool,
+    pub config: RgaConfig,
+}
+
+impl ContainerInfo {
+    /// input for the member `path` of this container. Its lines are prefixed with the member path
+    /// and it is one level deeper, so `--rga-max-archive-recursion` applies.
+    pub fn member(&self, path: impl Into<PathBuf>, inp: ReadBox) -> AdaptInfo {
+        let path = path.into();
+        AdaptInfo {
+            line_prefix: format!(
+                "{}{}{}",
+                self.line_prefix,
+                postproc::prefix_field(&path.to_string_lossy()),
+                postproc::prefix_separator(&self.config.postproc.options)
+            ),
+            filepath_hint: path,
+            is_real_file: false,
+            file_mtime_unix_ms: None,
+            archive_recursion_depth: self.archive_recursion_depth + 1,
+            inp,
+            postprocess: self.postprocess,
+            config: self.config.clone(),
+        }
+    }
+}
+
 /// (enabledAdapters, disabledAdapters)
 type AdaptersTuple = (Vec<Arc<dyn FileAdapter>>, Vec<Arc<dyn FileAdapter>>);
 
@@ -129,7 +300,38 @@ impl FileAdapter for AdapterOverride {
     }
 }
 
+/// the cargo features that group optional adapters, with whether they were compiled in
+pub static FEATURE_GROUPS: &[(&str, bool)] = &[
+    ("media", cfg!(feature = "media")),
+    ("ocr", cfg!(feature = "ocr")),
+    ("office", cfg!(feature = "office")),
+    ("forensics", cfg!(feature = "forensics")),
+    ("scientific", cfg!(feature = "scientific")),
+];
+
 pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> AdaptersTuple {
+    all_adapters(custom_adapters, vec![])
+}
+
+/// all adapters including the wasm adapters from the config and the plugins in `config.plugins_dir`
+pub fn get_configured_adapters(
+    custom_adapters: Option<Vec<CustomAdapterConfig>>,
+    config: &RgaConfig,
+) -> Result<AdaptersTuple> {
+    let mut plugins: Vec<Arc<dyn FileAdapter>> = vec![];
+    for adapter_config in config.wasm_adapters.iter().flatten() {
+        plugins.push(Arc::new(adapter_config.to_adapter()));
+    }
+    if let Some(dir) = &config.plugins_dir {
+        plugins.extend(plugin::load_plugins(std::path::Path::new(dir))?);
+    }
+    Ok(all_adapters(custom_adapters, plugins))
+}
+
+fn all_adapters(
+    custom_adapters: Option<Vec<CustomAdapterConfig>>,
+    plugins: Vec<Arc<dyn FileAdapter>>,
+) -> AdaptersTuple {
     // order in descending priority
     let mut adapters: Vec<Arc<dyn FileAdapter>> = vec![];
     if let Some(custom_adapters) = custom_adapters {
@@ -137,21 +339,109 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
             adapters.push(Arc::new(adapter_config.to_adapter()));
         }
     }
+    adapters.extend(plugins);
 
     let internal_adapters: Vec<Arc<dyn FileAdapter>> = vec![
         Arc::new(PostprocPageBreaks::default()),
+        #[cfg(feature = "media")]
+        Arc::new(whisper::WhisperAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(audiotags::AudioTagsAdapter::new()),
+        #[cfg(feature = "media")]
         Arc::new(ffmpeg::FFmpegAdapter::new()),
+        #[cfg(feature = "ocr")]
+        Arc::new(tesseract::TesseractAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(exif::ExifAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(svg::SvgAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(psd::PsdAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(dicom::DicomAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(fits::FitsAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(gps::GpsAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(comics::ComicsAdapter::new()),
         Arc::new(zip::ZipAdapter::new()),
         Arc::new(decompress::DecompressAdapter::new()),
+        #[cfg(feature = "office")]
         Arc::new(mbox::MboxAdapter::new()),
+        #[cfg(feature = "office")]
+        Arc::new(mhtml::MhtmlAdapter::new()),
+        Arc::new(patch::PatchAdapter::new()),
+        Arc::new(gitbundle::GitBundleAdapter::new()),
         Arc::new(tar::TarAdapter::new()),
+        Arc::new(ar::ArAdapter::new()),
+        Arc::new(cpio::CpioAdapter::new()),
+        Arc::new(rar::RarAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(iso::IsoAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(dmg::DmgAdapter::new()),
+        Arc::new(zim::ZimAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(cab::CabAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(msi::MsiAdapter::new()),
         Arc::new(sqlite::SqliteAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(parquet::ParquetAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(avro::AvroAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(orc::OrcAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(hdf5::Hdf5Adapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(mlmodel::MlModelAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(mlgraph::MlGraphAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(weights::WeightsAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(pcap::PcapAdapter::new()),
+        #[cfg(feature = "office")]
+        Arc::new(chess::ChessAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(genomics::GenomicsAdapter::new()),
+        #[cfg(feature = "office")]
+        Arc::new(finance::FinanceAdapter::new()),
+        #[cfg(feature = "office")]
+        Arc::new(edi::EdiAdapter::new()),
+        #[cfg(feature = "office")]
+        Arc::new(xbrl::XbrlAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(firmware::FirmwareAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(game::GameAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(installer::InstallerAdapter::new()),
+        #[cfg(feature = "forensics")]
+        Arc::new(executable::ExecutableAdapter::new()),
+        #[cfg(feature = "scientific")]
+        Arc::new(serialized::SerializedAdapter::new()),
     ];
-    adapters.extend(
-        BUILTIN_SPAWNING_ADAPTERS
-            .iter()
-            .map(|e| -> Arc<dyn FileAdapter> { Arc::new(e.to_adapter()) }),
-    );
+    // without pdftotext the pure-Rust PDF adapter takes poppler's place, else it is only used when listed
+    #[cfg(feature = "pdf-fallback")]
+    let pdf_fallback = !pdf::pdftotext_installed();
+    #[cfg(feature = "pdf-fallback")]
+    adapters.push(Arc::new(pdf::PdfAdapter::new(!pdf_fallback)));
+    adapters.extend(BUILTIN_SPAWNING_ADAPTERS.iter().map(|e| -> Arc<dyn FileAdapter> {
+        #[cfg(feature = "pdf-fallback")]
+        if pdf_fallback && e.name == "poppler" {
+            return Arc::new(
+                CustomAdapterConfig {
+                    disabled_by_default: Some(true),
+                    ..e.clone()
+                }
+                .to_adapter(),
+            );
+        }
+        Arc::new(e.to_adapter())
+    }));
     adapters.extend(internal_adapters);
 
     adapters
@@ -172,7 +462,8 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
     adapter_names: &[T],
     config: &RgaConfig,
 ) -> Result<Vec<Arc<dyn FileAdapter>>> {
-    let (def_enabled_adapters, def_disabled_adapters) = get_all_adapters(custom_adapters);
+    let (def_enabled_adapters, def_disabled_adapters) =
+        get_configured_adapters(custom_adapters, config)?;
     let adapters = if !adapter_names.is_empty() {
         let adapters_map: HashMap<_, _> = def_enabled_adapters
             .iter()
@@ -226,15 +517,29 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
         def_enabled_adapters
     };
     // apply extension overrides
+    #[cfg(feature = "media")]
+    let audio_tags = adapters.iter().any(|a| a.metadata().name == "audiotags");
     let adapters = adapters
         .into_iter()
         .map(|a| {
             let name = a.metadata().name.clone();
             let override_exts = match name.as_str() {
-                "zip" => config.zip_extensions.as_ref(),
-                "ffmpeg" => config.ffmpeg_extensions.as_ref(),
+                "zip" => config.zip_extensions.clone(),
+                "ffmpeg" | "whisper" => config.ffmpeg_extensions.clone(),
                 _ => None,
             };
+            // unless it is disabled, the audiotags adapter handles the audio files
+            #[cfg(feature = "media")]
+            let override_exts = match override_exts {
+                None if name == "ffmpeg" && audio_tags => Some(
+                    ffmpeg::EXTENSIONS
+                        .iter()
+                        .filter(|e| !audiotags::EXTENSIONS.contains(e))
+                        .map(|e| e.to_string())
+                        .collect(),
+                ),
+                exts => exts,
+            };
             if let Some(exts) = override_exts {
                 let fast_matchers = exts
                     .iter()
@@ -245,7 +550,7 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
                     name: m.name.clone(),
                     version: m.version,
                     description: m.description.clone(),
-                    recurses: m.recurses,
+                    capabilities: m.capabilities.clone(),
                     fast_matchers,
                     slow_matchers: m.slow_matchers.clone(),
                     keep_fast_matchers_if_accurate: m.keep_fast_matchers_if_accurate,
@@ -282,6 +587,7 @@ mod tests {
         assert!(fm.len() == 1);
         match &fm[0] { FastFileMatcher::FileExtension(s) => assert_eq!(s, "zzz") };
     }
+    #[cfg(feature = "media")]
     #[test]
     fn ffmpeg_extensions_override_applied() {
         let mut cfg = RgaConfig::default();
@@ -293,4 +599,31 @@ mod tests {
         match &fm[0] { FastFileMatcher::FileExtension(s) => assert_eq!(s, "abc") };
         match &fm[1] { FastFileMatcher::FileExtension(s) => assert_eq!(s, "DEF") };
     }
+    #[cfg(feature = "media")]
+    #[test]
+    fn audiotags_takes_audio_extensions() {
+        let has_mp3 = |adapters: &[&str]| {
+            let adapters = get_adapters_filtered(None, adapters, &RgaConfig::default()).unwrap();
+            let ff = adapters.into_iter().find(|a| a.metadata().name == "ffmpeg").unwrap();
+            ff.metadata().fast_matchers.iter().any(|m| match m {
+                FastFileMatcher::FileExtension(s) => s == "mp3",
+            })
+        };
+        assert!(!has_mp3(&[]));
+        assert!(has_mp3(&["-audiotags"]));
+    }
+    #[test]
+    fn member_prefix_framing() {
+        let mut container = ContainerInfo {
+            filepath_hint: PathBuf::from("a.zip"),
+            archive_recursion_depth: 0,
+            line_prefix: "a.zip: ".to_string(),
+            postprocess: true,
+            config: RgaConfig::default(),
+        };
+        let inp = || -> ReadBox { Box::pin(tokio::io::empty()) };
+        assert_eq!(container.member("new\nline.txt", inp()).line_prefix, "a.zip: new\\nline.txt: ");
+        container.config.postproc.options.prefix_separator = Some("\t".to_string());
+        assert_eq!(container.member("b.txt", inp()).line_prefix, "a.zip: b.txt\t");
+    }
 }
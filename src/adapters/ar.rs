@@ -0,0 +1,186 @@
+//! Unix ar archives: static libraries (.a) and Debian packages. Members are read one after the
+//! other, with the GNU (`//` table) and BSD (`#1/len`) ways of storing long names.
+use super::*;
+use anyhow::*;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+static EXTENSIONS: &[&str] = &["a", "ar", "deb", "lib"];
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const HEADER_LEN: usize = 60;
+const HEADER_END: &[u8] = b"`\n";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ar".to_owned(),
+        version: 1,
+        description: "Reads Unix ar archives (static libraries, Debian packages) as a stream and recurses into their members".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-archive".to_owned()),
+            FileMatcher::MimeType("application/vnd.debian.binary-package".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ArAdapter;
+
+impl ArAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ArAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn field(header: &[u8], range: std::ops::Range<usize>) -> Result<u64> {
+    let text = std::str::from_utf8(&header[range])?.trim_end();
+    text.parse()
+        .with_context(|| format!("invalid ar header field {text:?}"))
+}
+
+/// the next member as (name, data), None at the end of the archive. Symbol tables are returned
+/// with an empty name
+async fn next_member(
+    inp: &mut (impl AsyncRead + Unpin),
+    long_names: &[u8],
+) -> Result<Option<(String, Vec<u8>)>> {
+    let mut header = [0; HEADER_LEN];
+    // members start at even offsets
+    let mut first = [0; 1];
+    loop {
+        if inp.read(&mut first).await? == 0 {
+            return Ok(None);
+        }
+        if first[0] != b'\n' {
+            break;
+        }
+    }
+    header[0] = first[0];
+    inp.read_exact(&mut header[1..]).await?;
+    if &header[58..] != HEADER_END {
+        return Err(format_err!("invalid ar member header"));
+    }
+    let size = field(&header, 48..58)?;
+    let mut data = vec![];
+    inp.take(size).read_to_end(&mut data).await?;
+    if (data.len() as u64) < size {
+        return Err(format_err!("truncated ar member"));
+    }
+    let raw_name = std::str::from_utf8(&header[..16])?.trim_end();
+    let name = if raw_name == "/" || raw_name == "/SYM64/" || raw_name.starts_with("__.SYMDEF") {
+        String::new()
+    } else if raw_name == "//" {
+        "//".to_string()
+    } else if let Some(len) = raw_name.strip_prefix("#1/") {
+        let len: usize = len.parse()?;
+        let name = data.get(..len).context("truncated ar member name")?;
+        let name = String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .to_string();
+        data.drain(..len);
+        name
+    } else if let Some(offset) = raw_name.strip_prefix('/') {
+        let rest = long_names
+            .get(offset.parse::<usize>()?..)
+            .context("invalid ar long name offset")?;
+        let end = memchr::memchr(b'\n', rest).unwrap_or(rest.len());
+        let name = String::from_utf8_lossy(&rest[..end]);
+        name.strip_suffix('/').unwrap_or(&name).to_string()
+    } else {
+        raw_name.strip_suffix('/').unwrap_or(raw_name).to_string()
+    };
+    Ok(Some((name, data)))
+}
+
+#[async_trait]
+impl FileAdapter for ArAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let s = stream! {
+            let mut magic = [0; MAGIC.len()];
+            inp.read_exact(&mut magic).await?;
+            if magic != MAGIC {
+                Err(format_err!("not an ar archive"))?;
+            }
+            let mut long_names = vec![];
+            while let Some((name, data)) = next_member(&mut inp, &long_names).await? {
+                match name.as_str() {
+                    "" => {}
+                    "//" => long_names = data,
+                    _ => yield Ok(container.member(name, Box::pin(Cursor::new(data)))),
+                }
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    fn member(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = format!(
+            "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            0,
+            0,
+            0,
+            644,
+            data.len()
+        )
+        .into_bytes();
+        out.extend(data);
+        if data.len() % 2 == 1 {
+            out.push(b'\n');
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn gnu_and_bsd_names() -> Result<()> {
+        let long = "a_rather_long_file_name.txt";
+        let archive = [
+            MAGIC,
+            &member("/", b"\0\0\0\0"),
+            &member("//", format!("{long}/\n").as_bytes()),
+            &member("short.txt/", b"hello"),
+            &member("/0", b"from the table"),
+            &member("#1/9", b"bsd.txt\0\0bsd"),
+        ]
+        .concat();
+        let (a, d) = simple_adapt_info(&PathBuf::from("lib.a"), Box::pin(Cursor::new(archive)));
+        let r = loop_adapt(&ArAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            format!(
+                "PREFIX:short.txt: hello\nPREFIX:{long}: from the table\nPREFIX:bsd.txt: bsd\n"
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! The tags of audio files (title, artist, album, lyrics, comments) without ffmpeg: ID3 tags of
+//! mp3 files, Vorbis comments of FLAC, Ogg Vorbis and Opus files and the iTunes atoms of MP4
+//! audio. Only the tags are read, never the audio.
+mod id3;
+mod mp4;
+
+use super::exif::ReadAt;
+use super::{writing::WritingFileAdapter, *};
+use crate::adapters::le::u32_at;
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::fs::File;
+use tokio::io::AsyncWrite;
+use writing::async_writeln;
+
+pub(crate) static EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga", "opus", "m4a", "m4b"];
+
+/// larger tags are cut off
+pub(crate) const MAX_TAG_LEN: usize = 16 << 20;
+/// the most Ogg pages or FLAC metadata blocks read before giving up on finding the tags
+const MAX_BLOCKS: usize = 4096;
+/// Vorbis comments that hold cover images
+const PICTURE_FIELDS: &[&str] = &["metadata_block_picture", "coverart", "coverartmime"];
+
+/// the genres of ID3v1, which ID3v2 and MP4 tags also refer to by number
+pub(crate) static GENRES: &[&str] = &[
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "Alternative Rock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychedelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "audiotags".to_owned(),
+        version: 1,
+        description: "Extracts the tags (title, artist, album, lyrics, comments) of mp3, FLAC, Ogg Vorbis, Opus and MP4 audio files without ffmpeg. Disable it to have the ffmpeg adapter handle these files".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            ["audio/mpeg", "audio/flac", "audio/ogg", "audio/opus", "audio/mp4", "audio/x-m4a"]
+                .iter()
+                .map(|m| FileMatcher::MimeType(m.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct AudioTagsAdapter;
+
+impl AudioTagsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for AudioTagsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the tags as `name: value` lines, multi-line values (lyrics) as one line each
+#[derive(Default)]
+pub struct Tags {
+    lines: Vec<String>,
+}
+
+impl Tags {
+    pub fn push(&mut self, name: &str, value: String) {
+        for line in value.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            self.lines.push(format!("{name}: {line}"));
+        }
+    }
+}
+
+/// a genre that is a number or `(number)` of the ID3v1 list by its name
+pub(crate) fn genre(value: &str) -> String {
+    let number = value
+        .strip_prefix('(')
+        .and_then(|v| v.strip_suffix(')'))
+        .unwrap_or(value);
+    match number.parse::<usize>().ok().and_then(|n| GENRES.get(n)) {
+        Some(genre) => genre.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// a Vorbis comment block: the vendor string, then `NAME=value` fields
+fn vorbis_comments(data: &[u8], tags: &mut Tags) -> Option<()> {
+    let mut at = 4 + u32_at(data, 0)? as usize;
+    let count = u32_at(data, at)? as usize;
+    at += 4;
+    for _ in 0..count {
+        let len = u32_at(data, at)? as usize;
+        let field = data.get(at + 4..(at + 4).checked_add(len)?)?;
+        at += 4 + len;
+        let field = String::from_utf8_lossy(field);
+        let Some((name, value)) = field.split_once('=') else {
+            continue;
+        };
+        let name = name.to_lowercase();
+        if PICTURE_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        let name = match name.as_str() {
+            "albumartist" => "album artist",
+            "tracknumber" => "track",
+            "unsyncedlyrics" => "lyrics",
+            name => name,
+        };
+        tags.push(name, value.to_string());
+    }
+    Some(())
+}
+
+/// the metadata blocks of the FLAC stream at `start`
+fn flac(r: &mut dyn ReadAt, start: u64, tags: &mut Tags) -> Option<()> {
+    let mut at = start + 4;
+    for _ in 0..MAX_BLOCKS {
+        let header = r.read_at(at, 4)?;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        if header[0] & 0x7f == 4 {
+            return vorbis_comments(&r.read_at(at + 4, len.min(MAX_TAG_LEN))?, tags);
+        }
+        if header[0] & 0x80 != 0 {
+            return None;
+        }
+        at += 4 + len as u64;
+    }
+    None
+}
+
+/// the second packet of the first logical stream of the Ogg file at `start`, the comment header
+fn ogg_comment_packet(r: &mut dyn ReadAt, start: u64) -> Option<Vec<u8>> {
+    let mut at = start;
+    let mut serial = None;
+    let mut packets = 0;
+    let mut packet = vec![];
+    for _ in 0..MAX_BLOCKS {
+        let header = r.read_at(at, 27)?;
+        if &header[..4] != b"OggS" {
+            return None;
+        }
+        let lacing = r.read_at(at + 27, header[26] as usize)?;
+        let data_len: u64 = lacing.iter().map(|&l| l as u64).sum();
+        let page_serial = &header[14..18];
+        if *serial.get_or_insert_with(|| page_serial.to_vec()) == page_serial {
+            let data = r.read_at(at + 27 + lacing.len() as u64, data_len as usize)?;
+            let mut offset = 0;
+            for &l in &lacing {
+                if packets == 1 && packet.len() < MAX_TAG_LEN {
+                    packet.extend_from_slice(&data[offset..offset + l as usize]);
+                }
+                offset += l as usize;
+                // a lacing value below 255 ends the packet
+                if l < 255 {
+                    if packets == 1 {
+                        return Some(packet);
+                    }
+                    packets += 1;
+                }
+            }
+        }
+        at += 27 + lacing.len() as u64 + data_len;
+    }
+    None
+}
+
+fn ogg(r: &mut dyn ReadAt, start: u64, tags: &mut Tags) -> Option<()> {
+    let packet = ogg_comment_packet(r, start)?;
+    if let Some(comments) = packet
+        .strip_prefix(b"\x03vorbis")
+        .or_else(|| packet.strip_prefix(b"OpusTags"))
+    {
+        vorbis_comments(comments, tags)
+    } else if packet.first()? & 0x7f == 4 {
+        // FLAC in Ogg: a metadata block with its header
+        vorbis_comments(packet.get(4..)?, tags)
+    } else {
+        None
+    }
+}
+
+/// the tags of the audio file as lines, by its format
+pub fn tag_lines(r: &mut dyn ReadAt, len: u64) -> Vec<String> {
+    let mut tags = Tags::default();
+    // FLAC files (and others) can start with an ID3v2 tag as well
+    let start = id3::v2(r, &mut tags).unwrap_or(0);
+    let head = r.read_at(start, 8).unwrap_or_default();
+    if head.starts_with(b"fLaC") {
+        flac(r, start, &mut tags);
+    } else if head.starts_with(b"OggS") {
+        ogg(r, start, &mut tags);
+    } else if head.get(4..8) == Some(b"ftyp") {
+        mp4::tags(r, len, &mut tags);
+    }
+    if tags.lines.is_empty() {
+        id3::v1(r, len, &mut tags);
+    }
+    tags.lines
+}
+
+#[async_trait]
+impl WritingFileAdapter for AudioTagsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let path = ai.filepath_hint.clone();
+        let lines = tokio::task::spawn_blocking(move || -> Result<Vec<String>> {
+            let mut file = File::open(&path)?;
+            let len = file.metadata()?.len();
+            Ok(tag_lines(&mut file, len))
+        })
+        .await??;
+        for line in lines {
+            async_writeln!(oup, "{}{line}", ai.line_prefix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn id3_frame(id: &str, data: &[u8]) -> Vec<u8> {
+        [
+            id.as_bytes(),
+            &(data.len() as u32).to_be_bytes(),
+            &[0, 0],
+            data,
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn mp3_tags() -> Result<()> {
+        let frames = [
+            id3_frame("TIT2", b"\x03Yesterday"),
+            id3_frame("TPE1", b"\x01\xff\xfeT\0h\0e\0 \0B\0e\0a\0t\0l\0e\0s\0\0\0"),
+            id3_frame("TCON", b"\x00(17)"),
+            id3_frame("COMM", b"\x00engiTunNORM\0 0000"),
+            id3_frame(
+                "USLT",
+                b"\x03eng\0Yesterday\nall my troubles seemed so far away",
+            ),
+            id3_frame("TXXX", b"\x00MusicBrainz Album Id\0abc-123"),
+            id3_frame("APIC", b"\x00image/jpeg\0\x03\0cover"),
+        ]
+        .concat();
+        let size = frames.len() + 16;
+        let syncsafe = [3, 2, 1, 0].map(|shift| (size >> (7 * shift) & 0x7f) as u8);
+        let mp3 = [
+            &b"ID3\x03\0\0"[..],
+            &syncsafe,
+            &frames,
+            &[0; 16],
+            b"\xff\xfbaudio",
+        ]
+        .concat();
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("yesterday.mp3");
+        std::fs::write(&path, mp3)?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let r = AudioTagsAdapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:title: Yesterday
+PREFIX:artist: The Beatles
+PREFIX:genre: Rock
+PREFIX:lyrics: Yesterday
+PREFIX:lyrics: all my troubles seemed so far away
+PREFIX:musicbrainz album id: abc-123
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn flac_and_mp4_tags() {
+        let comments = [
+            &b"\x09\0\0\0reference\x03\0\0\0"[..],
+            b"\x0b\0\0\0TITLE=Intro",
+            b"\x0f\0\0\0ALBUMARTIST=Foo",
+            b"\x15\0\0\0COVERART=aGVsbG8gd29y",
+        ]
+        .concat();
+        let flac = [
+            &b"fLaC\x00\0\0\x02si"[..],
+            &[0x84, 0, 0, comments.len() as u8],
+            &comments,
+        ]
+        .concat();
+        assert_eq!(
+            tag_lines(&mut &flac[..], flac.len() as u64),
+            vec!["title: Intro", "album artist: Foo"]
+        );
+
+        let atom = |name: &[u8], content: &[u8]| {
+            [&(content.len() as u32 + 8).to_be_bytes()[..], name, content].concat()
+        };
+        let data = |typ: u32, value: &[u8]| {
+            atom(b"data", &[&typ.to_be_bytes()[..], &[0; 4], value].concat())
+        };
+        let ilst = atom(
+            b"ilst",
+            &[
+                atom(b"\xa9nam", &data(1, b"Chapter One")),
+                atom(b"trkn", &data(0, &[0, 0, 0, 3, 0, 12, 0, 0])),
+                atom(b"covr", &data(13, b"jpeg")),
+            ]
+            .concat(),
+        );
+        let meta = atom(b"meta", &[&[0; 4][..], &ilst].concat());
+        let mp4 = [
+            atom(b"ftyp", b"M4A \0\0\0\0"),
+            atom(b"moov", &atom(b"udta", &meta)),
+            atom(b"mdat", b"audio"),
+        ]
+        .concat();
+        assert_eq!(
+            tag_lines(&mut &mp4[..], mp4.len() as u64),
+            vec!["title: Chapter One", "track: 3/12"]
+        );
+    }
+}
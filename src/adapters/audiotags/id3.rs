@@ -0,0 +1,248 @@
+//! ID3 tags of mp3 (and other) files: ID3v2.2 to 2.4 at the start of the file, ID3v1 at its end.
+use super::{GENRES, MAX_TAG_LEN, Tags, genre};
+use crate::adapters::exif::ReadAt;
+use std::io::Read;
+
+const HEADER_LEN: usize = 10;
+const V1_LEN: u64 = 128;
+
+/// (ID3v2.3+ frame, ID3v2.2 frame, name)
+static FRAMES: &[(&str, &str, &str)] = &[
+    ("TIT2", "TT2", "title"),
+    ("TIT3", "TT3", "subtitle"),
+    ("TPE1", "TP1", "artist"),
+    ("TPE2", "TP2", "album artist"),
+    ("TALB", "TAL", "album"),
+    ("TCOM", "TCM", "composer"),
+    ("TEXT", "TXT", "lyricist"),
+    ("TCON", "TCO", "genre"),
+    ("TYER", "TYE", "date"),
+    ("TDRC", "", "date"),
+    ("TRCK", "TRK", "track"),
+    ("TIT1", "TT1", "grouping"),
+    ("TPUB", "TPB", "publisher"),
+    ("TCOP", "TCR", "copyright"),
+    ("COMM", "COM", "comment"),
+    ("USLT", "ULT", "lyrics"),
+    ("TXXX", "TXX", ""),
+];
+
+fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |n, &b| n << 7 | (b & 0x7f) as usize)
+}
+
+/// undo the unsynchronisation scheme: a 0 is inserted after each 0xff
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if !(b == 0 && i > 0 && data[i - 1] == 0xff) {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn latin1(b: &[u8]) -> String {
+    b.iter().map(|&b| b as char).collect()
+}
+
+fn utf16(b: &[u8], big_endian: bool) -> String {
+    let units: Vec<u16> = b
+        .chunks_exact(2)
+        .map(|u| {
+            if big_endian {
+                u16::from_be_bytes([u[0], u[1]])
+            } else {
+                u16::from_le_bytes([u[0], u[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// the text in an encoding of ID3 (0: Latin-1, 1: UTF-16 with BOM, 2: UTF-16BE, 3: UTF-8)
+fn decode(encoding: u8, b: &[u8]) -> String {
+    match encoding {
+        1 => match b {
+            [0xfe, 0xff, rest @ ..] => utf16(rest, true),
+            [0xff, 0xfe, rest @ ..] => utf16(rest, false),
+            _ => utf16(b, false),
+        },
+        2 => utf16(b, true),
+        3 => String::from_utf8_lossy(b).into_owned(),
+        _ => latin1(b),
+    }
+}
+
+/// split at the first NUL terminator of the encoding
+fn split_terminated(encoding: u8, b: &[u8]) -> (&[u8], &[u8]) {
+    let pos = if matches!(encoding, 1 | 2) {
+        (0..b.len() / 2)
+            .map(|i| i * 2)
+            .find(|&i| b[i] == 0 && b[i + 1] == 0)
+            .map(|i| (i, i + 2))
+    } else {
+        b.iter().position(|&c| c == 0).map(|i| (i, i + 1))
+    };
+    match pos {
+        Some((end, rest)) => (&b[..end], &b[rest..]),
+        None => (b, &[]),
+    }
+}
+
+fn frame(name: &str, data: &[u8], tags: &mut Tags) {
+    let Some((&encoding, body)) = data.split_first() else {
+        return;
+    };
+    match name {
+        "comment" | "lyrics" => {
+            // language, short description, then the text
+            let Some(body) = body.get(3..) else {
+                return;
+            };
+            let (description, text) = split_terminated(encoding, body);
+            let description = decode(encoding, description);
+            // iTunes keeps its own data in comments
+            if description.starts_with("iTun") {
+                return;
+            }
+            tags.push(name, decode(encoding, text));
+        }
+        "" => {
+            let (description, value) = split_terminated(encoding, body);
+            tags.push(
+                &decode(encoding, description).to_lowercase(),
+                decode(encoding, value),
+            );
+        }
+        _ => {
+            // ID3v2.4 separates multiple values with NUL
+            let text = decode(encoding, body);
+            for value in text.split('\0') {
+                let value = if name == "genre" {
+                    genre(value)
+                } else {
+                    value.to_string()
+                };
+                tags.push(name, value);
+            }
+        }
+    }
+}
+
+/// the ID3v2 tag at the start, returns its size
+pub fn v2(r: &mut dyn ReadAt, tags: &mut Tags) -> Option<u64> {
+    let header = r.read_at(0, HEADER_LEN)?;
+    if &header[..3] != b"ID3" {
+        return None;
+    }
+    let (version, flags) = (header[3], header[5]);
+    let size = syncsafe(&header[6..10]);
+    let total = (HEADER_LEN + size + if flags & 0x10 != 0 { 10 } else { 0 }) as u64;
+    let mut data = r.read_at(HEADER_LEN as u64, size.min(MAX_TAG_LEN))?;
+    if flags & 0x80 != 0 && version < 4 {
+        data = resync(&data);
+    }
+    let mut at = 0;
+    if flags & 0x40 != 0 {
+        // the extended header, its size includes itself in 2.4 but not in 2.3
+        let len = if version >= 4 {
+            syncsafe(data.get(..4)?)
+        } else {
+            4 + u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize
+        };
+        at = len;
+    }
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    while let Some(frame_header) = data.get(at..at + header_len) {
+        if frame_header[0] == 0 {
+            // padding
+            break;
+        }
+        let id = String::from_utf8_lossy(&frame_header[..id_len]).into_owned();
+        let size = match version {
+            2 => frame_header[3..6]
+                .iter()
+                .fold(0, |n, &b| n << 8 | b as usize),
+            3 => u32::from_be_bytes(frame_header[4..8].try_into().unwrap()) as usize,
+            _ => syncsafe(&frame_header[4..8]),
+        };
+        let Some(body) = data.get(at + header_len..(at + header_len).saturating_add(size)) else {
+            break;
+        };
+        at += header_len + size;
+        let Some(&(_, _, name)) = FRAMES
+            .iter()
+            .find(|(v3, v2, _)| if version == 2 { *v2 == id } else { *v3 == id })
+        else {
+            continue;
+        };
+        let format_flags = if version == 2 { 0 } else { frame_header[9] };
+        let mut body = body.to_vec();
+        let (compressed, encrypted) = match version {
+            2 => (false, false),
+            3 => {
+                if format_flags & 0x20 != 0 {
+                    body = body.get(1..).unwrap_or_default().to_vec(); // group id
+                }
+                if format_flags & 0x80 != 0 {
+                    body = body.get(4..).unwrap_or_default().to_vec(); // decompressed size
+                }
+                (format_flags & 0x80 != 0, format_flags & 0x40 != 0)
+            }
+            _ => {
+                if format_flags & 0x40 != 0 {
+                    body = body.get(1..).unwrap_or_default().to_vec(); // group id
+                }
+                if format_flags & 0x01 != 0 {
+                    body = body.get(4..).unwrap_or_default().to_vec(); // data length indicator
+                }
+                if format_flags & 0x02 != 0 {
+                    body = resync(&body);
+                }
+                (format_flags & 0x08 != 0, format_flags & 0x04 != 0)
+            }
+        };
+        if encrypted {
+            continue;
+        }
+        if compressed {
+            let mut inflated = vec![];
+            if flate2::read::ZlibDecoder::new(&body[..])
+                .take(MAX_TAG_LEN as u64)
+                .read_to_end(&mut inflated)
+                .is_err()
+            {
+                continue;
+            }
+            body = inflated;
+        }
+        frame(name, &body, tags);
+    }
+    Some(total)
+}
+
+/// the ID3v1 tag at the end of the file
+pub fn v1(r: &mut dyn ReadAt, len: u64, tags: &mut Tags) -> Option<()> {
+    let tag = r.read_at(len.checked_sub(V1_LEN)?, V1_LEN as usize)?;
+    if &tag[..3] != b"TAG" {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| {
+        let b = &tag[range];
+        latin1(&b[..b.iter().position(|&c| c == 0).unwrap_or(b.len())])
+    };
+    tags.push("title", field(3..33));
+    tags.push("artist", field(33..63));
+    tags.push("album", field(63..93));
+    tags.push("date", field(93..97));
+    tags.push("comment", field(97..127));
+    // ID3v1.1 keeps the track number in the last byte of the comment
+    if tag[125] == 0 && tag[126] != 0 {
+        tags.push("track", tag[126].to_string());
+    }
+    if let Some(genre) = GENRES.get(tag[127] as usize) {
+        tags.push("genre", genre.to_string());
+    }
+    Some(())
+}
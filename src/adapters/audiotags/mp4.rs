@@ -0,0 +1,141 @@
+//! iTunes style tags of MP4 audio (.m4a, .m4b): the items of the moov/udta/meta/ilst atoms.
+use super::{GENRES, MAX_TAG_LEN, Tags};
+use crate::adapters::exif::ReadAt;
+
+static ITEMS: &[(&[u8; 4], &str)] = &[
+    (b"\xa9nam", "title"),
+    (b"\xa9ART", "artist"),
+    (b"aART", "album artist"),
+    (b"\xa9alb", "album"),
+    (b"\xa9wrt", "composer"),
+    (b"\xa9gen", "genre"),
+    (b"\xa9day", "date"),
+    (b"\xa9grp", "grouping"),
+    (b"\xa9cmt", "comment"),
+    (b"\xa9lyr", "lyrics"),
+    (b"desc", "description"),
+    (b"ldes", "description"),
+    (b"cprt", "copyright"),
+    (b"\xa9pub", "publisher"),
+    (b"trkn", "track"),
+    (b"gnre", "genre"),
+];
+
+/// the child atoms in `data` as (type, content)
+fn atoms(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut out = vec![];
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, data.len()),
+            1 => match data.get(8..16) {
+                Some(s) => (16, u64::from_be_bytes(s.try_into().unwrap()) as usize),
+                None => break,
+            },
+            size => (8, size),
+        };
+        if size < header || size > data.len() {
+            break;
+        }
+        out.push((&data[4..8], &data[header..size]));
+        data = &data[size..];
+    }
+    out
+}
+
+fn child<'a>(data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    atoms(data)
+        .into_iter()
+        .find(|(t, _)| *t == name)
+        .map(|(_, c)| c)
+}
+
+/// the content of the top-level moov atom
+fn moov(r: &mut dyn ReadAt, len: u64) -> Option<Vec<u8>> {
+    let mut at = 0u64;
+    while at + 8 <= len {
+        let header = r.read_at(at, 8)?;
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            1 => (
+                u64::from_be_bytes(r.read_at(at + 8, 8)?.try_into().unwrap()),
+                16,
+            ),
+            0 => (len - at, 8),
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        if &header[4..8] == b"moov" {
+            let content = (size - header_len).min(MAX_TAG_LEN as u64) as usize;
+            return r.read_at(at + header_len, content);
+        }
+        at = at.checked_add(size)?;
+    }
+    None
+}
+
+/// the value of a `data` atom: its type, the locale and the value
+fn value(name: &str, data: &[u8]) -> Option<String> {
+    let typ = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) & 0xffffff;
+    let value = data.get(8..)?;
+    Some(match (name, typ) {
+        ("track", _) => {
+            let track = u16::from_be_bytes(value.get(2..4)?.try_into().ok()?);
+            match value.get(4..6).map(|t| u16::from_be_bytes([t[0], t[1]])) {
+                Some(total) if total > 0 => format!("{track}/{total}"),
+                _ => track.to_string(),
+            }
+        }
+        ("genre", 0) => {
+            let index = u16::from_be_bytes(value.get(..2)?.try_into().ok()?) as usize;
+            GENRES.get(index.checked_sub(1)?)?.to_string()
+        }
+        (_, 1) => String::from_utf8_lossy(value).into_owned(),
+        (_, 2) => {
+            let units: Vec<u16> = value
+                .chunks_exact(2)
+                .map(|u| u16::from_be_bytes([u[0], u[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => return None,
+    })
+}
+
+pub fn tags(r: &mut dyn ReadAt, len: u64, tags: &mut Tags) -> Option<()> {
+    let moov = moov(r, len)?;
+    let meta = child(child(&moov, b"udta")?, b"meta")?;
+    // a full atom, except in some QuickTime files
+    let meta = if meta.get(4..8) == Some(&b"hdlr"[..]) {
+        meta
+    } else {
+        meta.get(4..)?
+    };
+    for (item, content) in atoms(child(meta, b"ilst")?) {
+        let children = atoms(content);
+        let data = children.iter().filter(|(t, _)| *t == b"data");
+        if item == b"----" {
+            // freeform: reverse DNS mean, name and the data
+            let Some(name) = child(content, b"name").and_then(|n| n.get(4..)) else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(name).to_lowercase();
+            for (_, d) in data {
+                if let Some(v) = value(&name, d) {
+                    tags.push(&name, v);
+                }
+            }
+            continue;
+        }
+        let Some((_, name)) = ITEMS.iter().find(|(i, _)| &i[..] == item) else {
+            continue;
+        };
+        for (_, d) in data {
+            if let Some(v) = value(name, d) {
+                tags.push(name, v);
+            }
+        }
+    }
+    Some(())
+}
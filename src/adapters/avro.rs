@@ -0,0 +1,573 @@
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::*;
+use serde_json::{Map, Value};
+use std::io::{BufReader, Read, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["avro"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "avro".to_owned(),
+        version: 1,
+        description:
+            "Decodes Avro container files into their schema followed by one JSON line per record"
+                .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct AvroAdapter;
+
+impl AvroAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for AvroAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: &[u8; 4] = b"Obj\x01";
+
+/// the subset of an avro schema needed to decode the binary encoding.
+/// logical types are decoded as their underlying type.
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// reference to a named type, resolved while decoding so recursive types work
+    Named(String),
+}
+
+/// named types (records, enums, fixed) by full name
+type Names = HashMap<String, Schema>;
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(ns) if !ns.is_empty() && !name.contains('.') => format!("{ns}.{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// how deeply schemas and values may nest. Named types can refer to themselves, so without a limit a
+/// crafted file overflows the stack
+const MAX_DEPTH: usize = 256;
+
+/// the most array and map items (and records) in a block that take no bytes at all, e.g. nulls. Other
+/// items take at least a byte of the block each
+const MAX_EMPTY_ITEMS: usize = 1 << 16;
+
+fn parse_schema(v: &Value, namespace: Option<&str>, names: &mut Names) -> Result<Schema> {
+    parse_schema_at(v, namespace, names, 0)
+}
+
+fn parse_schema_at(
+    v: &Value,
+    namespace: Option<&str>,
+    names: &mut Names,
+    depth: usize,
+) -> Result<Schema> {
+    if depth > MAX_DEPTH {
+        return Err(format_err!("avro schema nested more than {} levels", MAX_DEPTH));
+    }
+    let parse_schema = |v: &Value, namespace: Option<&str>, names: &mut Names| {
+        parse_schema_at(v, namespace, names, depth + 1)
+    };
+    Ok(match v {
+        Value::String(s) => match s.as_str() {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            name => Schema::Named(full_name(name, namespace)),
+        },
+        Value::Array(variants) => Schema::Union(
+            variants
+                .iter()
+                .map(|v| parse_schema(v, namespace, names))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(o) => {
+            let typ = o.get("type").context("schema object without type")?;
+            let name = o.get("name").and_then(|n| n.as_str());
+            let namespace = o.get("namespace").and_then(|n| n.as_str()).or(namespace);
+            let named = |names: &mut Names, schema: Schema| -> Result<Schema> {
+                let name = full_name(name.context("named type without name")?, namespace);
+                names.insert(name.clone(), schema);
+                Ok(Schema::Named(name))
+            };
+            match typ.as_str() {
+                Some("record") | Some("error") => {
+                    let name = full_name(name.context("record without name")?, namespace);
+                    // register before parsing the fields so they can refer to the record itself
+                    names.insert(name.clone(), Schema::Record(vec![]));
+                    let inner_ns = name.rsplit_once('.').map(|(ns, _)| ns.to_string());
+                    let fields = o
+                        .get("fields")
+                        .and_then(|f| f.as_array())
+                        .context("record without fields")?
+                        .iter()
+                        .map(|f| {
+                            let fname = f
+                                .get("name")
+                                .and_then(|n| n.as_str())
+                                .context("field without name")?;
+                            let ftype = f.get("type").context("field without type")?;
+                            Ok((
+                                fname.to_string(),
+                                parse_schema(ftype, inner_ns.as_deref(), names)?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?;
+                    names.insert(name.clone(), Schema::Record(fields));
+                    Schema::Named(name)
+                }
+                Some("enum") => {
+                    let symbols = o
+                        .get("symbols")
+                        .and_then(|s| s.as_array())
+                        .context("enum without symbols")?
+                        .iter()
+                        .map(|s| s.as_str().unwrap_or_default().to_string())
+                        .collect();
+                    named(names, Schema::Enum(symbols))?
+                }
+                Some("fixed") => {
+                    let size = o
+                        .get("size")
+                        .and_then(|s| s.as_u64())
+                        .context("fixed without size")?;
+                    named(names, Schema::Fixed(size as usize))?
+                }
+                Some("array") => Schema::Array(Box::new(parse_schema(
+                    o.get("items").context("array without items")?,
+                    namespace,
+                    names,
+                )?)),
+                Some("map") => Schema::Map(Box::new(parse_schema(
+                    o.get("values").context("map without values")?,
+                    namespace,
+                    names,
+                )?)),
+                // primitive with attributes, e.g. {"type": "long", "logicalType": "timestamp-millis"}
+                _ => parse_schema(typ, namespace, names)?,
+            }
+        }
+        other => return Err(format_err!("invalid avro schema: {}", other)),
+    })
+}
+
+/// zigzag varint. Returns None on a clean EOF before the first byte
+fn read_long_opt(r: &mut impl Read) -> Result<Option<i64>> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    let mut buf = [0u8; 1];
+    loop {
+        if r.read(&mut buf)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(format_err!("unexpected end of avro data in varint"));
+        }
+        value |= ((buf[0] & 0x7f) as u64) << shift;
+        if buf[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(format_err!("avro varint too long"));
+        }
+    }
+    Ok(Some((value >> 1) as i64 ^ -((value & 1) as i64)))
+}
+
+fn read_long(r: &mut impl Read) -> Result<i64> {
+    read_long_opt(r)?.context("unexpected end of avro data")
+}
+
+fn read_len(r: &mut impl Read) -> Result<usize> {
+    let len = read_long(r)?;
+    usize::try_from(len).map_err(|_| format_err!("negative avro length {}", len))
+}
+
+fn read_bytes(r: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_len(r)?;
+    let mut buf = Vec::new();
+    r.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(format_err!("unexpected end of avro data"));
+    }
+    Ok(buf)
+}
+
+/// the number of items of a block. `items_left` is how many more items the data can hold, see [`MAX_EMPTY_ITEMS`]
+fn read_count(r: &mut impl Read, items_left: &mut usize) -> Result<usize> {
+    let count = read_long(r)?;
+    let count = if count < 0 {
+        // negative count is followed by the block size in bytes
+        read_long(r)?;
+        count
+            .checked_neg()
+            .with_context(|| format!("invalid avro block count {count}"))?
+    } else {
+        count
+    };
+    let count = usize::try_from(count)
+        .ok()
+        .filter(|c| c <= items_left)
+        .with_context(|| format!("avro block of {count} items is larger than its data"))?;
+    *items_left -= count;
+    Ok(count)
+}
+
+/// arrays and maps are encoded as a sequence of blocks, terminated by an empty block
+fn read_blocks(
+    r: &mut impl Read,
+    items_left: &mut usize,
+    mut item: impl FnMut(&mut dyn Read, &mut usize) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let count = read_count(r, items_left)?;
+        if count == 0 {
+            return Ok(());
+        }
+        for _ in 0..count {
+            item(r, items_left)?;
+        }
+    }
+}
+
+fn decode(
+    r: &mut dyn Read,
+    schema: &Schema,
+    names: &Names,
+    depth: usize,
+    items_left: &mut usize,
+) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(format_err!("avro value nested more than {} levels", MAX_DEPTH));
+    }
+    let mut r = r;
+    let depth = depth + 1;
+    Ok(match schema {
+        Schema::Null => Value::Null,
+        Schema::Boolean => {
+            let mut b = [0u8; 1];
+            r.read_exact(&mut b)?;
+            Value::Bool(b[0] != 0)
+        }
+        Schema::Int | Schema::Long => Value::from(read_long(&mut r)?),
+        Schema::Float => {
+            let mut b = [0u8; 4];
+            r.read_exact(&mut b)?;
+            Value::from(f32::from_le_bytes(b) as f64)
+        }
+        Schema::Double => {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Value::from(f64::from_le_bytes(b))
+        }
+        Schema::Bytes | Schema::String => {
+            Value::String(String::from_utf8_lossy(&read_bytes(&mut r)?).into_owned())
+        }
+        Schema::Fixed(n) => {
+            // the size comes from the schema, so only allocate what is actually there
+            let mut b = Vec::new();
+            r.take(*n as u64).read_to_end(&mut b)?;
+            if b.len() != *n {
+                return Err(format_err!("unexpected end of avro data"));
+            }
+            Value::String(String::from_utf8_lossy(&b).into_owned())
+        }
+        Schema::Record(fields) => {
+            let mut obj = Map::new();
+            for (name, s) in fields {
+                obj.insert(name.clone(), decode(r, s, names, depth, items_left)?);
+            }
+            Value::Object(obj)
+        }
+        Schema::Enum(symbols) => {
+            let i = read_len(&mut r)?;
+            Value::String(symbols.get(i).cloned().unwrap_or_else(|| i.to_string()))
+        }
+        Schema::Array(items) => {
+            let mut arr = vec![];
+            read_blocks(&mut r, items_left, |r, items_left| {
+                arr.push(decode(r, items, names, depth, items_left)?);
+                Ok(())
+            })?;
+            Value::Array(arr)
+        }
+        Schema::Map(values) => {
+            let mut obj = Map::new();
+            read_blocks(&mut r, items_left, |mut r, items_left| {
+                let key = String::from_utf8_lossy(&read_bytes(&mut r)?).into_owned();
+                obj.insert(key, decode(r, values, names, depth, items_left)?);
+                Ok(())
+            })?;
+            Value::Object(obj)
+        }
+        Schema::Union(variants) => {
+            let i = read_len(&mut r)?;
+            let s = variants
+                .get(i)
+                .with_context(|| format!("avro union index {i} out of range"))?;
+            decode(r, s, names, depth, items_left)?
+        }
+        Schema::Named(name) => decode(
+            r,
+            names
+                .get(name)
+                .with_context(|| format!("unknown avro type {name}"))?,
+            names,
+            depth,
+            items_left,
+        )?,
+    })
+}
+
+fn decompress(codec: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+    Ok(match codec {
+        "null" => data,
+        "deflate" => {
+            let mut out = Vec::new();
+            flate2::read::DeflateDecoder::new(&data[..]).read_to_end(&mut out)?;
+            out
+        }
+        "snappy" => {
+            // the block ends with a crc32 of the uncompressed data
+            let compressed = &data[..data.len().saturating_sub(4)];
+            snap::raw::Decoder::new().decompress_vec(compressed)?
+        }
+        "zstandard" => zstd::stream::decode_all(&data[..])?,
+        other => return Err(format_err!("unsupported avro codec {}", other)),
+    })
+}
+
+fn synchronous_dump_avro(inp: impl Read, line_prefix: &str, mut s: impl Write) -> Result<()> {
+    let mut r = BufReader::new(inp);
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(format_err!("not an avro container file"));
+    }
+    let mut meta = HashMap::new();
+    // every entry takes at least two bytes of the header
+    let mut unlimited = usize::MAX;
+    read_blocks(&mut r, &mut unlimited, |mut r, _| {
+        let key = String::from_utf8_lossy(&read_bytes(&mut r)?).into_owned();
+        meta.insert(key, read_bytes(&mut r)?);
+        Ok(())
+    })?;
+    let mut sync = [0u8; 16];
+    r.read_exact(&mut sync)?;
+
+    let schema_json: Value = serde_json::from_slice(
+        meta.get("avro.schema")
+            .context("avro file without schema")?,
+    )?;
+    let codec = meta
+        .get("avro.codec")
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .unwrap_or_else(|| "null".to_string());
+    debug!("avro codec: {}", codec);
+    writeln!(s, "{line_prefix}schema: {schema_json}")?;
+    let mut names = Names::new();
+    let schema = parse_schema(&schema_json, None, &mut names)?;
+
+    while let Some(count) = read_long_opt(&mut r)? {
+        let data = read_bytes(&mut r)?;
+        let data = decompress(&codec, data)?;
+        let mut items_left = data.len() + MAX_EMPTY_ITEMS;
+        let count = usize::try_from(count)
+            .ok()
+            .filter(|c| *c <= items_left)
+            .with_context(|| format!("avro block of {count} records is larger than its data"))?;
+        items_left -= count;
+        let mut block = &data[..];
+        for _ in 0..count {
+            let record = decode(&mut block, &schema, &names, 0, &mut items_left)?;
+            writeln!(s, "{line_prefix}{record}")?;
+        }
+        let mut block_sync = [0u8; 16];
+        r.read_exact(&mut block_sync)?;
+        if block_sync != sync {
+            return Err(format_err!("avro sync marker mismatch, file is corrupt"));
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for AvroAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        // container files can be decoded front to back, so no need to buffer the input
+        let inp_sync = SyncIoBridge::new(inp);
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump_avro(inp_sync, &line_prefix, oup_sync)
+        })
+        .await?
+        .context("in synchronous avro task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn long(v: i64) -> Vec<u8> {
+        let mut n = ((v << 1) ^ (v >> 63)) as u64;
+        let mut out = vec![];
+        loop {
+            if n < 0x80 {
+                out.push(n as u8);
+                return out;
+            }
+            out.push((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+    }
+    fn bytes(b: &[u8]) -> Vec<u8> {
+        [long(b.len() as i64), b.to_vec()].concat()
+    }
+
+    fn example_avro() -> Vec<u8> {
+        let schema = r#"{"type":"record","name":"r","fields":[{"name":"id","type":"long"},{"name":"tags","type":{"type":"array","items":"string"}},{"name":"note","type":["null","string"]}]}"#;
+        let sync = [7u8; 16];
+        let records = [
+            long(-3),
+            long(2),
+            bytes(b"x"),
+            bytes(b"y"),
+            long(0),
+            long(1),
+            bytes(b"hello"),
+            long(1000),
+            long(0),
+            long(0),
+        ]
+        .concat();
+        [
+            MAGIC.to_vec(),
+            long(1),
+            bytes(b"avro.schema"),
+            bytes(schema.as_bytes()),
+            long(0),
+            sync.to_vec(),
+            long(2),
+            bytes(&records),
+            sync.to_vec(),
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn records() -> Result<()> {
+        let adapter: Box<dyn FileAdapter> = Box::<AvroAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("data.avro"),
+            Box::pin(std::io::Cursor::new(example_avro())),
+        );
+        let res = adapter.adapt(a, &d).await?;
+        let out = String::from_utf8(adapted_to_vec(res).await?)?;
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("PREFIX:schema: {"));
+        let record = |l: &str| -> Result<Value> {
+            Ok(serde_json::from_str(l.strip_prefix("PREFIX:").unwrap())?)
+        };
+        assert_eq!(
+            record(lines[1])?,
+            serde_json::json!({"id": -3, "tags": ["x", "y"], "note": "hello"})
+        );
+        assert_eq!(
+            record(lines[2])?,
+            serde_json::json!({"id": 1000, "tags": [], "note": null})
+        );
+        Ok(())
+    }
+
+    fn avro_with(schema: &str, records: Vec<u8>, count: i64) -> Vec<u8> {
+        let sync = [7u8; 16];
+        [
+            MAGIC.to_vec(),
+            long(1),
+            bytes(b"avro.schema"),
+            bytes(schema.as_bytes()),
+            long(0),
+            sync.to_vec(),
+            long(count),
+            bytes(&records),
+            sync.to_vec(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn crafted_files() {
+        let dump = |file: Vec<u8>| synchronous_dump_avro(&file[..], "", std::io::sink());
+        // a record that contains itself, with and without a union
+        let err = dump(avro_with(
+            r#"{"type":"record","name":"r","fields":[{"name":"f","type":"r"}]}"#,
+            vec![],
+            1,
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("nested"), "{err}");
+        let schema = r#"{"type":"record","name":"r","fields":[{"name":"f","type":["r","null"]}]}"#;
+        assert!(dump(avro_with(schema, vec![0; 100], 1)).is_err());
+        assert!(dump(avro_with(schema, [vec![0; 10], long(1)].concat(), 1)).is_ok());
+        // a count of i64::MIN
+        let nulls = r#"{"type":"array","items":"null"}"#;
+        assert!(dump(avro_with(nulls, long(i64::MIN), 1)).is_err());
+        // more empty items than allowed, and more records than the block can hold
+        assert!(dump(avro_with(nulls, [long(1 << 40), long(0)].concat(), 1)).is_err());
+        assert!(dump(avro_with(nulls, [long(1000), long(0)].concat(), 1)).is_ok());
+        assert!(dump(avro_with("null", vec![], 1 << 40)).is_err());
+        // a fixed larger than the file
+        let fixed = r#"{"type":"fixed","name":"f","size":1000000000000}"#;
+        assert!(dump(avro_with(fixed, vec![1, 2, 3], 1)).is_err());
+    }
+}
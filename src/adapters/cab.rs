@@ -0,0 +1,316 @@
+//! Microsoft cabinet files, stored or MSZIP compressed. Files are grouped in folders, each a single
+//! compressed stream split into data blocks of at most 32k.
+use super::*;
+use crate::adapters::le::{u16_at, u32_at};
+use crate::vfs::{MemoryFs, adapt_vfs};
+use anyhow::*;
+use lazy_static::lazy_static;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress, inflate_flags};
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["cab"];
+
+const SIGNATURE: &[u8] = b"MSCF";
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+/// the file name is utf-8 instead of the system codepage
+const ATTRIB_NAME_IS_UTF: u16 = 0x0080;
+/// folder indices at and above this are files continued from or to another cabinet
+const FOLDER_CONTINUED: u16 = 0xfffd;
+const COMPRESS_NONE: u16 = 0;
+const COMPRESS_MSZIP: u16 = 1;
+/// mszip blocks can refer back to this much of the previous blocks' output
+const MSZIP_WINDOW: usize = 32 * 1024;
+const TRUNCATED: &str = "truncated cabinet";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cab".to_owned(),
+        version: 1,
+        description: "Extracts the files of Microsoft cabinet (.cab) archives with stored or MSZIP compressed folders and recurses into them".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-cab-compressed".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CabAdapter;
+
+impl CabAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for CabAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a nul terminated string and the offset after it
+fn cstring_at(data: &[u8], at: usize) -> Result<(&[u8], usize)> {
+    let rest = data.get(at..).context(TRUNCATED)?;
+    let len = memchr::memchr(0, rest).context("unterminated string")?;
+    Ok((&rest[..len], at + len + 1))
+}
+
+struct Folder {
+    data_offset: usize,
+    blocks: u16,
+    compression: u16,
+}
+
+/// decode a folder's mszip blocks: each is "CK" followed by a deflate stream that may refer to the
+/// output of the previous blocks
+fn mszip(blocks: &[(&[u8], usize)]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    for (block, size) in blocks {
+        let stream = block.strip_prefix(b"CK").context("invalid mszip block")?;
+        let history = out.len().saturating_sub(MSZIP_WINDOW);
+        let mut buf = out[history..].to_vec();
+        let start = buf.len();
+        buf.resize(start + size, 0);
+        let mut state = DecompressorOxide::new();
+        let (status, _, written) = decompress(
+            &mut state,
+            stream,
+            &mut buf,
+            start,
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        if status != TINFLStatus::Done || written != *size {
+            return Err(format_err!("invalid mszip block ({status:?})"));
+        }
+        out.extend(&buf[start..]);
+    }
+    Ok(out)
+}
+
+/// the uncompressed content of a folder
+fn folder_data(data: &[u8], folder: &Folder, data_reserve: usize) -> Result<Vec<u8>> {
+    let mut blocks = vec![];
+    let mut pos = folder.data_offset;
+    for _ in 0..folder.blocks {
+        let compressed = u16_at(data, pos + 4).context(TRUNCATED)? as usize;
+        let size = u16_at(data, pos + 6).context(TRUNCATED)? as usize;
+        pos += 8 + data_reserve;
+        let block = data
+            .get(pos..pos + compressed)
+            .context("truncated data block")?;
+        blocks.push((block, size));
+        pos += compressed;
+    }
+    match folder.compression & 0x0f {
+        COMPRESS_NONE => Ok(blocks.iter().flat_map(|(b, _)| b.iter().copied()).collect()),
+        COMPRESS_MSZIP => mszip(&blocks),
+        2 => Err(format_err!("quantum compression is not supported")),
+        3 => Err(format_err!("lzx compression is not supported")),
+        c => Err(format_err!("unknown compression {c}")),
+    }
+}
+
+/// the files of a cabinet by path. Files of folders that can't be decompressed are skipped
+pub fn extract(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    if !data.starts_with(SIGNATURE) {
+        return Err(format_err!("not a cabinet"));
+    }
+    let files_offset = u32_at(data, 16).context(TRUNCATED)? as usize;
+    let folder_count = u16_at(data, 26).context(TRUNCATED)?;
+    let file_count = u16_at(data, 28).context(TRUNCATED)?;
+    let flags = u16_at(data, 30).context(TRUNCATED)?;
+    let mut pos = 36;
+    let (mut folder_reserve, mut data_reserve) = (0, 0);
+    if flags & FLAG_RESERVE_PRESENT != 0 {
+        let header_reserve = u16_at(data, pos).context(TRUNCATED)? as usize;
+        folder_reserve = *data.get(pos + 2).context(TRUNCATED)? as usize;
+        data_reserve = *data.get(pos + 3).context(TRUNCATED)? as usize;
+        pos += 4 + header_reserve;
+    }
+    for flag in [FLAG_PREV_CABINET, FLAG_NEXT_CABINET] {
+        if flags & flag != 0 {
+            // cabinet and disk name
+            pos = cstring_at(data, pos)?.1;
+            pos = cstring_at(data, pos)?.1;
+        }
+    }
+    let mut folders = vec![];
+    for _ in 0..folder_count {
+        folders.push(Folder {
+            data_offset: u32_at(data, pos).context(TRUNCATED)? as usize,
+            blocks: u16_at(data, pos + 4).context(TRUNCATED)?,
+            compression: u16_at(data, pos + 6).context(TRUNCATED)?,
+        });
+        pos += 8 + folder_reserve;
+    }
+    // decompressed lazily, as the files of a folder are usually listed together
+    let mut contents: Vec<Option<Result<Vec<u8>>>> = folders.iter().map(|_| None).collect();
+    let mut files = vec![];
+    let mut pos = files_offset;
+    for _ in 0..file_count {
+        let size = u32_at(data, pos).context(TRUNCATED)? as usize;
+        let offset = u32_at(data, pos + 4).context(TRUNCATED)? as usize;
+        let folder = u16_at(data, pos + 8).context(TRUNCATED)?;
+        let attributes = u16_at(data, pos + 14).context(TRUNCATED)?;
+        let (name, next) = cstring_at(data, pos + 16)?;
+        pos = next;
+        let name = if attributes & ATTRIB_NAME_IS_UTF != 0 {
+            String::from_utf8_lossy(name).into_owned()
+        } else {
+            encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(name)
+                .0
+                .into_owned()
+        };
+        let name = name.replace('\\', "/");
+        if folder >= FOLDER_CONTINUED {
+            warn!("skipping {name}, it is split across cabinets");
+            continue;
+        }
+        let Some(content) = contents.get_mut(folder as usize) else {
+            return Err(format_err!("{name} is in a missing folder"));
+        };
+        let content = content
+            .get_or_insert_with(|| folder_data(data, &folders[folder as usize], data_reserve));
+        match content {
+            Result::Ok(content) => {
+                let file = content
+                    .get(offset..offset + size)
+                    .with_context(|| format!("{name} is outside of its folder"))?;
+                files.push((name, file.to_vec()));
+            }
+            Err(e) => warn!("skipping {name}: {e}"),
+        }
+    }
+    Ok(files)
+}
+
+#[async_trait]
+impl FileAdapter for CabAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let files = tokio::task::spawn_blocking(move || extract(&data)).await??;
+        adapt_vfs(Arc::new(MemoryFs::new(files)), container).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// a fixed huffman deflate block that only copies 8 bytes from 8 bytes back, which is in
+    /// the previous block
+    fn back_reference_block() -> Vec<u8> {
+        let mut bits = vec![];
+        let mut lsb_first = |value: u32, n: u32| bits.extend((0..n).map(|i| value >> i & 1));
+        // final block, fixed huffman codes
+        lsb_first(1, 1);
+        lsb_first(1, 2);
+        // length 8: code 262. distance 8: code 5 with the extra bit set. end of block
+        for (code, n, extra) in [(6u32, 7, None), (5, 5, Some(1)), (0, 7, None)] {
+            // huffman codes are written starting with the most significant bit
+            lsb_first(code.reverse_bits() >> (32 - n), n);
+            if let Some(extra) = extra {
+                lsb_first(extra, 1);
+            }
+        }
+        let mut out = b"CK".to_vec();
+        out.extend(bits.chunks(8).map(|byte| {
+            byte.iter()
+                .enumerate()
+                .fold(0u8, |acc, (i, bit)| acc | (*bit as u8) << i)
+        }));
+        out
+    }
+
+    /// a cabinet with a stored folder and an mszip folder of two blocks
+    fn cabinet(files: &[(&str, u16, u32, u32)]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(b"CK".to_vec(), flate2::Compression::default());
+        encoder.write_all(b"[setup]\n").unwrap();
+        let folders = [
+            (COMPRESS_NONE, vec![(b"stored file".to_vec(), 11)]),
+            (
+                COMPRESS_MSZIP,
+                vec![(encoder.finish().unwrap(), 8), (back_reference_block(), 8)],
+            ),
+        ];
+
+        let mut file_table = vec![];
+        for (name, folder, offset, size) in files {
+            file_table.extend(size.to_le_bytes());
+            file_table.extend(offset.to_le_bytes());
+            file_table.extend(folder.to_le_bytes());
+            file_table.extend([0; 6]);
+            file_table.extend(format!("{name}\0").as_bytes());
+        }
+        let files_offset = 36 + 8 * folders.len();
+        let mut out = SIGNATURE.to_vec();
+        out.extend([0; 12]);
+        out.extend((files_offset as u32).to_le_bytes());
+        out.extend([0; 4]);
+        out.extend([3, 1]);
+        out.extend((folders.len() as u16).to_le_bytes());
+        out.extend((files.len() as u16).to_le_bytes());
+        out.extend([0; 6]);
+        let mut data = vec![];
+        for (compression, blocks) in &folders {
+            let data_offset = files_offset + file_table.len() + data.len();
+            out.extend((data_offset as u32).to_le_bytes());
+            out.extend((blocks.len() as u16).to_le_bytes());
+            out.extend(compression.to_le_bytes());
+            for (block, size) in blocks {
+                data.extend([0; 4]);
+                data.extend((block.len() as u16).to_le_bytes());
+                data.extend((*size as u16).to_le_bytes());
+                data.extend(block);
+            }
+        }
+        out.extend(file_table);
+        out.extend(data);
+        out
+    }
+
+    #[test]
+    fn stored_and_mszip() -> Result<()> {
+        let cab = cabinet(&[
+            ("readme.txt", 0, 0, 11),
+            ("bin\\setup.ini", 1, 0, 12),
+            ("bin\\other.ini", 1, 12, 4),
+        ]);
+        let extracted = extract(&cab)?;
+        let extracted: Vec<(&str, &[u8])> = extracted
+            .iter()
+            .map(|(n, d)| (n.as_str(), &d[..]))
+            .collect();
+        assert_eq!(
+            extracted,
+            vec![
+                ("readme.txt", &b"stored file"[..]),
+                ("bin/setup.ini", b"[setup]\n[set"),
+                ("bin/other.ini", b"up]\n"),
+            ]
+        );
+        Ok(())
+    }
+}
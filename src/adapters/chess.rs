@@ -0,0 +1,306 @@
+//! Scid chess databases (.si4, with the player, event, site and round names in the .sn4 next to
+//! it): the header of every game as one line of PGN tags, so `White "Carlsen.*Black "Nakamura`
+//! finds games. Compressed PGN files are plain text after the decompress adapter.
+use super::{writing::WritingFileAdapter, *};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::{Read, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["si4"];
+
+const INDEX_MAGIC: &[u8] = b"Scid.si\0";
+const NAMEBASE_MAGIC: &[u8] = b"Scid.sn\0";
+/// version 4 added the descriptions of the custom flags to the header
+const HEADER_LEN_V3: usize = 128;
+const HEADER_LEN_V4: usize = 182;
+const ENTRY_LEN: usize = 47;
+/// name types in the namebase
+const PLAYER: usize = 0;
+const EVENT: usize = 1;
+const SITE: usize = 2;
+const ROUND: usize = 3;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "chess".to_owned(),
+        version: 1,
+        description: "Writes the headers of the games in Scid chess databases (.si4 with its .sn4 name file) as one line of PGN tags per game".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ChessAdapter;
+
+impl ChessAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ChessAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn be(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u32)
+}
+
+/// the names of the four types by id. Names are sorted and front coded: each shares a prefix
+/// with the one before
+fn read_namebase(data: &[u8]) -> Result<[Vec<String>; 4]> {
+    if !data.starts_with(NAMEBASE_MAGIC) {
+        return Err(format_err!("not a scid name file"));
+    }
+    let mut pos = NAMEBASE_MAGIC.len() + 4;
+    let mut take = |n: usize| -> Result<u32> {
+        let bytes = data.get(pos..pos + n).context("truncated name file")?;
+        pos += n;
+        Ok(be(bytes))
+    };
+    let counts: Vec<u32> = (0..4).map(|_| take(3)).collect::<Result<_>>()?;
+    let max_frequencies: Vec<u32> = (0..4).map(|_| take(3)).collect::<Result<_>>()?;
+    let mut names: [Vec<String>; 4] = Default::default();
+    for kind in 0..4 {
+        let id_len = if counts[kind] >= 1 << 16 { 3 } else { 2 };
+        let frequency_len = match max_frequencies[kind] {
+            f if f >= 1 << 16 => 3,
+            f if f >= 1 << 8 => 2,
+            _ => 1,
+        };
+        names[kind] = vec![String::new(); counts[kind] as usize];
+        let mut previous: Vec<u8> = vec![];
+        for i in 0..counts[kind] {
+            let id = take(id_len)? as usize;
+            take(frequency_len)?;
+            let len = take(1)? as usize;
+            let prefix = if i > 0 { take(1)? as usize } else { 0 };
+            if prefix > len || prefix > previous.len() {
+                return Err(format_err!("invalid name prefix"));
+            }
+            previous.truncate(prefix);
+            for _ in prefix..len {
+                previous.push(take(1)? as u8);
+            }
+            let name = encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(&previous)
+                .0;
+            *names[kind].get_mut(id).context("invalid name id")? = name.into_owned();
+        }
+    }
+    Ok(names)
+}
+
+/// `year << 9 | month << 5 | day`, zero for unknown parts
+fn date(d: u32) -> String {
+    let (year, month, day) = (d >> 9, (d >> 5) & 0x0f, d & 0x1f);
+    let part = |v: u32, width: usize| match v {
+        0 => "?".repeat(width),
+        v => format!("{v:0width$}"),
+    };
+    format!("{}.{}.{}", part(year, 4), part(month, 2), part(day, 2))
+}
+
+/// codes like B90 are stored as `(letter * 100 + number) * 131 + 1`, with the rest for
+/// Scid's extensions like B90a1
+fn eco(code: u32) -> Option<String> {
+    let basic = code.checked_sub(1)? / 131;
+    let letter = char::from(b'A' + u8::try_from(basic / 100).ok().filter(|l| *l < 5)?);
+    Some(format!("{letter}{:02}", basic % 100))
+}
+
+fn write_games(
+    index: &[u8],
+    names: Option<&[Vec<String>; 4]>,
+    line_prefix: &str,
+    oup: &mut dyn Write,
+) -> Result<()> {
+    if !index.starts_with(INDEX_MAGIC) {
+        return Err(format_err!("not a scid index file"));
+    }
+    let version = be(&index[8..10]);
+    let header_len = if version >= 400 {
+        HEADER_LEN_V4
+    } else {
+        HEADER_LEN_V3
+    };
+    let count = be(index.get(14..17).context("truncated index")?) as usize;
+    let description = index.get(20..128).unwrap_or_default();
+    let description = description.split(|b| *b == 0).next().unwrap_or_default();
+    if !description.is_empty() {
+        writeln!(
+            oup,
+            "{line_prefix}description: {}",
+            String::from_utf8_lossy(description)
+        )?;
+    }
+    if names.is_none() {
+        writeln!(oup, "{line_prefix}names: missing .sn4 file, showing ids")?;
+    }
+    let name = |kind: usize, id: u32| match names {
+        Some(names) => names[kind].get(id as usize).cloned().unwrap_or_default(),
+        None => format!("#{id}"),
+    };
+    for i in 0..count {
+        let at = header_len + i * ENTRY_LEN;
+        let Some(e) = index.get(at..at + ENTRY_LEN) else {
+            break;
+        };
+        let white = (e[9] as u32 >> 4) << 16 | be(&e[10..12]);
+        let black = (e[9] as u32 & 0x0f) << 16 | be(&e[12..14]);
+        let event = (e[14] as u32 >> 5) << 16 | be(&e[15..17]);
+        let site = (e[14] as u32 >> 2 & 0x07) << 16 | be(&e[17..19]);
+        let round = (e[14] as u32 & 0x03) << 16 | be(&e[19..21]);
+        let result = match be(&e[21..23]) >> 12 {
+            1 => "1-0",
+            2 => "0-1",
+            3 => "1/2-1/2",
+            _ => "*",
+        };
+        let elo = |b: &[u8]| be(b) & 0x0fff;
+        let tags = [
+            ("Event", name(EVENT, event)),
+            ("Site", name(SITE, site)),
+            ("Date", date(be(&e[25..29]) & 0x000f_ffff)),
+            ("Round", name(ROUND, round)),
+            ("White", name(PLAYER, white)),
+            ("Black", name(PLAYER, black)),
+            ("Result", result.to_string()),
+            ("WhiteElo", elo(&e[29..31]).to_string()),
+            ("BlackElo", elo(&e[31..33]).to_string()),
+            ("ECO", eco(be(&e[23..25])).unwrap_or_default()),
+        ];
+        let tags: Vec<String> = tags
+            .iter()
+            .filter(|(_, v)| !matches!(v.as_str(), "" | "?" | "0" | "????.??.??"))
+            .map(|(k, v)| format!("[{k} \"{v}\"]"))
+            .collect();
+        writeln!(oup, "{line_prefix}game {}: {}", i + 1, tags.join(" "))?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for ChessAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        // the names are in a separate file, which only exists next to real files
+        let namebase = ai
+            .is_real_file
+            .then(|| ai.filepath_hint.with_extension("sn4"));
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut inp_sync = SyncIoBridge::new(inp);
+        let mut oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut index = vec![];
+            inp_sync.read_to_end(&mut index)?;
+            let names = match namebase.map(std::fs::read) {
+                Some(Result::Ok(data)) => Some(read_namebase(&data)?),
+                _ => None,
+            };
+            write_games(&index, names.as_ref(), &line_prefix, &mut oup_sync)?;
+            oup_sync.flush()?;
+            Ok(())
+        })
+        .await?
+        .context("in synchronous chess task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn namebase(names: [&[&str]; 4]) -> Vec<u8> {
+        let mut out = NAMEBASE_MAGIC.to_vec();
+        out.extend([0; 4]);
+        for kind in names {
+            out.extend(&(kind.len() as u32).to_be_bytes()[1..]);
+        }
+        out.extend([0; 12]);
+        for kind in names {
+            let mut sorted: Vec<(usize, &str)> = kind.iter().copied().enumerate().collect();
+            sorted.sort_by_key(|(_, n)| *n);
+            let mut previous = "";
+            for (i, (id, name)) in sorted.into_iter().enumerate() {
+                out.extend((id as u16).to_be_bytes());
+                out.push(1);
+                out.push(name.len() as u8);
+                let prefix = name
+                    .bytes()
+                    .zip(previous.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if i > 0 {
+                    out.push(prefix as u8);
+                }
+                out.extend(&name.as_bytes()[prefix..]);
+                previous = name;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn games() -> Result<()> {
+        let names = read_namebase(&namebase([
+            &["?", "Carlsen, Magnus", "Caruana, Fabiano"],
+            &["?", "World Championship"],
+            &["?", "London ENG"],
+            &["?", "12"],
+        ]))?;
+
+        let mut index = INDEX_MAGIC.to_vec();
+        index.extend(400u16.to_be_bytes());
+        index.extend([0; 4]);
+        index.extend(&2u32.to_be_bytes()[1..]);
+        index.extend([0; 3]);
+        index.extend(b"test base");
+        index.resize(HEADER_LEN_V4, 0);
+        let mut game = vec![0; ENTRY_LEN];
+        game[11] = 1;
+        game[13] = 2;
+        game[16] = 1;
+        game[18] = 1;
+        game[20] = 1;
+        game[21] = 0x30;
+        // B90
+        game[23..25].copy_from_slice(&((100 + 90) * 131 + 1u16).to_be_bytes());
+        game[25..29].copy_from_slice(&(2018 << 9 | 11 << 5 | 28u32).to_be_bytes());
+        game[29..31].copy_from_slice(&2835u16.to_be_bytes());
+        game[31..33].copy_from_slice(&2832u16.to_be_bytes());
+        index.extend(&game);
+        // unknown players and date
+        index.extend(vec![0; ENTRY_LEN]);
+
+        let mut out = vec![];
+        write_games(&index, Some(&names), "PREFIX:", &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "PREFIX:description: test base\n\
+             PREFIX:game 1: [Event \"World Championship\"] [Site \"London ENG\"] [Date \"2018.11.28\"] \
+             [Round \"12\"] [White \"Carlsen, Magnus\"] [Black \"Caruana, Fabiano\"] [Result \"1/2-1/2\"] \
+             [WhiteElo \"2835\"] [BlackElo \"2832\"] [ECO \"B90\"]\n\
+             PREFIX:game 2: [Result \"*\"]\n"
+        );
+        Ok(())
+    }
+}
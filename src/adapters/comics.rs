@@ -0,0 +1,317 @@
+//! Comic book archives: zip (.cbz), rar (.cbr) or tar (.cbt) files of page images, usually with a
+//! ComicInfo.xml. The archive is read by the adapter of its actual format (the extensions are often
+//! wrong). When the tesseract adapter is enabled, the pages are OCRed and their lines prefixed with
+//! `Page N:` instead of the image name, numbered in the order comic readers show them.
+use super::postproc::PageFormat;
+use super::*;
+use crate::vfs::Vfs;
+use anyhow::*;
+use async_stream::stream;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Read;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use tokio_stream::StreamExt;
+
+static EXTENSIONS: &[&str] = &["cbz", "cbr", "cbt"];
+
+/// the adapter that turns page images into text
+const OCR_ADAPTER: &str = "tesseract";
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "bmp", "tif", "tiff"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "comics".to_owned(),
+        version: 1,
+        description: "Recurses into the pages of comic book archives (.cbz, .cbr, .cbt). With the tesseract adapter enabled, pages are OCRed and prefixed with their page number".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.comicbook+zip".to_owned()),
+            FileMatcher::MimeType("application/vnd.comicbook-rar".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ComicsAdapter;
+
+impl ComicsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ComicsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Zip,
+    Rar,
+    Tar,
+}
+
+/// the format of the archive from its first bytes, or its extension if they are not known
+fn format(head: &[u8], path: &Path) -> Result<Format> {
+    if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        return Ok(Format::Zip);
+    }
+    if head.starts_with(b"Rar!\x1a\x07") {
+        return Ok(Format::Rar);
+    }
+    if head.get(257..262) == Some(b"ustar") {
+        return Ok(Format::Tar);
+    }
+    if head.starts_with(b"7z\xbc\xaf\x27\x1c") {
+        bail!("7z comic books (.cb7) are not supported");
+    }
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    match extension.as_str() {
+        "cbz" => Ok(Format::Zip),
+        "cbr" => Ok(Format::Rar),
+        "cbt" => Ok(Format::Tar),
+        _ => bail!("unknown comic book archive format"),
+    }
+}
+
+fn is_page(name: &str) -> bool {
+    // resource forks of files zipped on macOS
+    if name.starts_with("__MACOSX/") || name.rsplit('/').next().is_some_and(|n| n.starts_with("._"))
+    {
+        return false;
+    }
+    let extension = Path::new(name)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    IMAGE_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// the run of digits at the start of `chars`, without leading zeros
+fn number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(d) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(d);
+    }
+    digits.trim_start_matches('0').to_string()
+}
+
+/// compare file names like comic readers do, with runs of digits compared as numbers (page2 < page10)
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (number(&mut a), number(&mut b));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// the page number of each page image among `names`
+fn page_numbers(names: Vec<String>, first_page: i32) -> HashMap<String, i32> {
+    let mut pages: Vec<String> = names.into_iter().filter(|n| is_page(n)).collect();
+    pages.sort_by(|a, b| natural_cmp(a, b));
+    pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| (name, first_page + i as i32))
+        .collect()
+}
+
+async fn member_names(format: Format, path: &Path) -> Result<Vec<String>> {
+    Ok(match format {
+        Format::Zip => {
+            let path = path.to_owned();
+            tokio::task::spawn_blocking(move || zip::file_names(&path)).await??
+        }
+        Format::Rar => rar::RarFs::new(path)
+            .list()
+            .await?
+            .into_iter()
+            .map(|e| e.path.to_string_lossy().into_owned())
+            .collect(),
+        Format::Tar => tar::TarFs::new(path)
+            .list()
+            .await?
+            .into_iter()
+            .map(|e| e.path.to_string_lossy().into_owned())
+            .collect(),
+    })
+}
+
+fn ocr_enabled(config: &RgaConfig) -> Result<bool> {
+    Ok(
+        get_adapters_filtered(config.custom_adapters.clone(), &config.adapters, config)?
+            .iter()
+            .any(|a| a.metadata().name == OCR_ADAPTER),
+    )
+}
+
+#[async_trait]
+impl FileAdapter for ComicsAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let mut head = Vec::with_capacity(512);
+        std::fs::File::open(&ai.filepath_hint)?
+            .take(512)
+            .read_to_end(&mut head)?;
+        let format = format(&head, &ai.filepath_hint)?;
+        let line_prefix = ai.line_prefix.clone();
+        let page_format = PageFormat::new(&ai.config.postproc.options);
+        let pages = if ocr_enabled(&ai.config)? {
+            let names = member_names(format, &ai.filepath_hint)
+                .await
+                .with_context(|| format!("listing {}", ai.filepath_hint.display()))?;
+            page_numbers(names, page_format.first_page)
+        } else {
+            HashMap::new()
+        };
+        let mut members = match format {
+            Format::Zip => zip::ZipAdapter::new().adapt(ai, detection_reason).await?,
+            Format::Rar => rar::RarAdapter::new().adapt(ai, detection_reason).await?,
+            Format::Tar => tar::TarAdapter::new().adapt(ai, detection_reason).await?,
+        };
+        let s = stream! {
+            while let Some(member) = members.next().await {
+                let mut member = member?;
+                let name = member.filepath_hint.to_string_lossy().into_owned();
+                if name.starts_with("__MACOSX/") {
+                    crate::preproc::read_discard(member.inp).await?;
+                    continue;
+                }
+                if let Some(page) = pages.get(&name) {
+                    member.line_prefix = format!("{}{}", line_prefix, page_format.line_prefix(*page));
+                }
+                yield Ok(member);
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::custom::CustomAdapterConfig;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn page_order() {
+        let names = [
+            "p10.jpg",
+            "ComicInfo.xml",
+            "p2.jpg",
+            "P1.png",
+            "__MACOSX/._p1.png",
+            "p02b.jpg",
+        ];
+        let pages = page_numbers(names.iter().map(|n| n.to_string()).collect(), 1);
+        let mut pages: Vec<_> = pages.into_iter().collect();
+        pages.sort_by_key(|(_, page)| *page);
+        assert_eq!(
+            pages,
+            vec![
+                ("P1.png".to_string(), 1),
+                ("p2.jpg".to_string(), 2),
+                ("p02b.jpg".to_string(), 3),
+                ("p10.jpg".to_string(), 4),
+            ]
+        );
+    }
+
+    /// a .cbt with pages out of order, "OCRed" by cat
+    #[tokio::test]
+    async fn ocr_pages() -> Result<()> {
+        let mut builder = tokio_tar::Builder::new(vec![]);
+        for (name, content) in [
+            (
+                "ComicInfo.xml",
+                "<ComicInfo><Title>Test</Title></ComicInfo>",
+            ),
+            ("page10.png", "the end"),
+            ("page2.png", "meanwhile"),
+            ("page1.png", "once upon a time"),
+        ] {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .await?;
+        }
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("story.cbt");
+        std::fs::write(&path, builder.into_inner().await?)?;
+
+        let (mut a, d) = simple_fs_adapt_info(&path).await?;
+        a.config.custom_adapters = Some(vec![CustomAdapterConfig {
+            name: OCR_ADAPTER.to_string(),
+            description: "cat".to_string(),
+            version: 1,
+            extensions: vec!["png".to_string()],
+            mimetypes: None,
+            binary: "cat".to_string(),
+            args: vec![],
+            disabled_by_default: None,
+            match_only_by_mime: None,
+            output_path_hint: None,
+        }]);
+        let adapters = get_adapters_filtered(
+            a.config.custom_adapters.clone(),
+            &a.config.adapters,
+            &a.config,
+        )?;
+        let r = loop_adapt(&ComicsAdapter, d, a, adapters).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:ComicInfo.xml: <ComicInfo><Title>Test</Title></ComicInfo>
+PREFIX:Page 3: the end
+PREFIX:Page 2: meanwhile
+PREFIX:Page 1: once upon a time
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! How sure OCR and speech to text were of a line, 0 to 100. Lines below `--rga-min-confidence`
+//! are left out, so garbage read from noise does not match. For rga's JSON output the confidence
+//! is put before the text of a line as `[confidence 87] `, which the matches manifest takes out
+//! again into a field of its own. Plain output never has it.
+use crate::config::RgaConfig;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref MARKER: Regex = Regex::new(r"\[confidence (\d{1,3})\] ").unwrap();
+}
+
+/// `text` as it is written with its confidence, None if it is too low
+pub fn line(config: &RgaConfig, confidence: Option<f32>, text: &str) -> Option<String> {
+    let Some(confidence) = confidence else {
+        return Some(text.to_string());
+    };
+    if config
+        .min_confidence
+        .is_some_and(|min| confidence < min as f32)
+    {
+        return None;
+    }
+    Some(match config.confidence_markers {
+        true => format!("[confidence {confidence:.0}] {text}"),
+        false => text.to_string(),
+    })
+}
+
+/// the confidence marker in `text`: the confidence, and the start and end of the marker
+pub fn find_marker(text: &str) -> Option<(u8, usize, usize)> {
+    let m = MARKER.captures(text)?;
+    let whole = m.get(0)?;
+    Some((m[1].parse().ok()?, whole.start(), whole.end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn markers_and_threshold() {
+        let mut config = RgaConfig {
+            min_confidence: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(line(&config, Some(42.0), "l1ke th1s"), None);
+        assert_eq!(
+            line(&config, Some(91.4), "like this").as_deref(),
+            Some("like this")
+        );
+        assert_eq!(line(&config, None, "no score").as_deref(), Some("no score"));
+        config.confidence_markers = true;
+        let marked = line(&config, Some(91.4), "like this").unwrap();
+        assert_eq!(marked, "[confidence 91] like this");
+        assert_eq!(
+            find_marker(&format!("00:01 --> 00:02: {marked}")),
+            Some((91, 17, 33))
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! cpio archives in the portable ASCII formats (`newc`, `crc` and `odc`), like rpm payloads and
+//! initramfs images. An initramfs is often several archives one after the other, usually an
+//! uncompressed one with CPU microcode followed by a compressed one with the actual files. A
+//! compressed rest is yielded as a member so the decompress adapter can read it.
+use super::*;
+use anyhow::*;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+static EXTENSIONS: &[&str] = &["cpio"];
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const CRC_MAGIC: &[u8] = b"070702";
+const ODC_MAGIC: &[u8] = b"070707";
+const TRAILER: &str = "TRAILER!!!";
+const MODE_TYPE: u64 = 0o170000;
+const MODE_REGULAR: u64 = 0o100000;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cpio".to_owned(),
+        version: 1,
+        description: "Reads cpio archives (including concatenated initramfs images) as a stream and recurses into their files".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-cpio".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CpioAdapter;
+
+impl CpioAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for CpioAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the extension of what follows the last archive, by its magic bytes
+fn compressed_extension(magic: &[u8]) -> Option<&'static str> {
+    [
+        (&b"\x1f\x8b"[..], "cpio.gz"),
+        (b"\xfd7zXZ\0", "cpio.xz"),
+        (b"\x28\xb5\x2f\xfd", "cpio.zst"),
+        (b"BZh", "cpio.bz2"),
+        (b"\x02\x21\x4c\x18", "cpio.lz4"),
+    ]
+    .into_iter()
+    .find(|(m, _)| magic.starts_with(m))
+    .map(|(_, ext)| ext)
+}
+
+/// a header field of `len` ascii digits
+fn number(header: &[u8], at: usize, len: usize, radix: u32) -> Result<u64> {
+    let text = std::str::from_utf8(&header[at..at + len])?;
+    u64::from_str_radix(text, radix).with_context(|| format!("invalid cpio header field {text:?}"))
+}
+
+async fn skip(inp: &mut (impl AsyncRead + Unpin), len: u64) -> Result<()> {
+    tokio::io::copy(&mut inp.take(len), &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+enum Entry {
+    File(String, Vec<u8>),
+    Other,
+    Trailer,
+}
+
+/// the entry after the magic. newc and crc entries are 4 byte aligned, `read` is the offset
+/// into the archive
+async fn next_entry(
+    inp: &mut (impl AsyncRead + Unpin),
+    magic: &[u8],
+    read: &mut u64,
+) -> Result<Entry> {
+    let (mode, name_len, size, aligned) = if magic == ODC_MAGIC {
+        let mut header = [0; 70];
+        inp.read_exact(&mut header).await?;
+        (
+            number(&header, 12, 6, 8)?,
+            number(&header, 53, 6, 8)?,
+            number(&header, 59, 11, 8)?,
+            false,
+        )
+    } else {
+        let mut header = [0; 104];
+        inp.read_exact(&mut header).await?;
+        (
+            number(&header, 8, 8, 16)?,
+            number(&header, 88, 8, 16)?,
+            number(&header, 48, 8, 16)?,
+            true,
+        )
+    };
+    *read += 6 + if aligned { 104 } else { 70 };
+    let pad = |read: u64| {
+        if aligned {
+            read.next_multiple_of(4) - read
+        } else {
+            0
+        }
+    };
+    let mut name = vec![];
+    (&mut *inp).take(name_len).read_to_end(&mut name).await?;
+    *read += name_len;
+    skip(inp, pad(*read)).await?;
+    *read += pad(*read);
+    let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(&name)).into_owned();
+    let entry = if name == TRAILER {
+        skip(inp, size).await?;
+        Entry::Trailer
+    } else if mode & MODE_TYPE == MODE_REGULAR {
+        let mut data = vec![];
+        (&mut *inp).take(size).read_to_end(&mut data).await?;
+        if (data.len() as u64) < size {
+            return Err(format_err!("truncated cpio member {name}"));
+        }
+        Entry::File(name.trim_start_matches("./").to_string(), data)
+    } else {
+        // directories, devices, and the targets of symlinks
+        skip(inp, size).await?;
+        Entry::Other
+    };
+    *read += size;
+    skip(inp, pad(*read)).await?;
+    *read += pad(*read);
+    Ok(entry)
+}
+
+#[async_trait]
+impl FileAdapter for CpioAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let s = stream! {
+            let mut read = 0u64;
+            let mut archives = 0;
+            loop {
+                let mut magic = [0; 6];
+                let len = inp.read(&mut magic[..1]).await?;
+                if len == 0 {
+                    break;
+                }
+                // archives are padded with zeros, often to 512 bytes
+                if magic[0] == 0 {
+                    read += 1;
+                    continue;
+                }
+                inp.read_exact(&mut magic[1..]).await?;
+                if [NEWC_MAGIC, CRC_MAGIC, ODC_MAGIC].contains(&&magic[..]) {
+                    match next_entry(&mut inp, &magic, &mut read).await? {
+                        Entry::File(path, data) => {
+                            yield Ok(container.member(path, Box::pin(Cursor::new(data))));
+                        }
+                        Entry::Other => {}
+                        Entry::Trailer => archives += 1,
+                    }
+                    continue;
+                }
+                let Some(ext) = compressed_extension(&magic).filter(|_| archives > 0) else {
+                    Err(format_err!("not a cpio archive at offset {read}"))?;
+                    break;
+                };
+                let rest: ReadBox = Box::pin(Cursor::new(magic).chain(inp));
+                yield Ok(container.member(format!("archive-{}.{ext}", archives + 1), rest));
+                break;
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn newc(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+        let mut out = format!("070701{:08x}{mode:08x}", 1).into_bytes();
+        for value in [0, 0, 1, 0, data.len()] {
+            out.extend(format!("{value:08x}").bytes());
+        }
+        for value in [0, 0, 0, 0, name.len() + 1, 0] {
+            out.extend(format!("{value:08x}").bytes());
+        }
+        out.extend(name.as_bytes());
+        out.push(0);
+        out.resize(out.len().next_multiple_of(4), 0);
+        out.extend(data);
+        out.resize(out.len().next_multiple_of(4), 0);
+        out
+    }
+
+    #[tokio::test]
+    async fn initramfs() -> Result<()> {
+        let mut early = [
+            newc("kernel", 0o40755, b""),
+            newc("kernel/microcode.txt", 0o100644, b"microcode"),
+            newc(TRAILER, 0, b""),
+        ]
+        .concat();
+        early.resize(512, 0);
+        let main = [
+            newc("./etc/hostname", 0o100644, b"box"),
+            newc("bin/sh", 0o120777, b"busybox"),
+            newc(TRAILER, 0, b""),
+        ]
+        .concat();
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(&main)?;
+        let image = [early, gz.finish()?].concat();
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("initramfs.cpio"),
+            Box::pin(Cursor::new(image)),
+        );
+        let r = loop_adapt(
+            &CpioAdapter,
+            d,
+            a,
+            crate::adapters::get_all_adapters(None).0,
+        )
+        .await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:kernel/microcode.txt: microcode\nPREFIX:archive-2.cpio.gz: etc/hostname: box\n"
+        );
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use super::*;
 use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
 use crate::adapted_iter::one_file;
+use crate::audit::Spawned;
 
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
@@ -134,7 +135,7 @@ lazy_static! {
         },
         CustomAdapterConfig {
             name: "poppler".to_owned(),
-            version: 1,
+            version: 2,
             description: "Uses pdftotext (from poppler-utils) to extract plain text from PDF files"
                 .to_owned(),
 
@@ -142,22 +143,11 @@ lazy_static! {
             mimetypes: Some(strs(&["application/pdf"])),
 
             binary: "pdftotext".to_string(),
-            args: strs(&["-opw", "$password", "-", "-"]),
+            // the password can be either one, the owner password also opens the file
+            args: strs(&["-opw", "$password", "-upw", "$password", "-", "-"]),
             disabled_by_default: None,
             match_only_by_mime: None,
             output_path_hint: Some("${input_virtual_path}.txt.asciipagebreaks".into())
-        },
-        CustomAdapterConfig {
-            name: "tesseract".to_owned(),
-            version: 1,
-            description: "Uses tesseract to extract text from images".to_owned(),
-            extensions: strs(&["jpg", "jpeg", "png", "webp", "tiff", "bmp", "gif"]),
-            mimetypes: Some(strs(&["image/jpeg", "image/png", "image/webp", "image/tiff", "image/bmp", "image/gif"])),
-            binary: "tesseract".to_string(),
-            args: strs(&["stdin", "stdout"]),
-            disabled_by_default: Some(true),
-            match_only_by_mime: None,
-            output_path_hint: None
         }
     ];
 }
@@ -172,9 +162,10 @@ pub fn map_exe_error(err: std::io::Error, exe_name: &str, help: &str) -> anyhow:
     }
 }
 
-fn proc_wait(mut child: Child, context: impl FnOnce() -> String) -> impl AsyncRead {
+fn proc_wait(mut child: Child, spawned: Spawned, context: impl FnOnce() -> String) -> impl AsyncRead {
     let s = stream! {
         let res = child.wait().await?;
+        spawned.exited(&res);
         if res.success() {
             yield std::io::Result::Ok(Bytes::new());
         } else {
@@ -198,12 +189,12 @@ pub fn pipe_output(
     help: &str,
 ) -> Result<ReadBox> {
     let cmd_log = format!("{:?}", cmd); // todo: perf
-    let mut cmd = cmd
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| map_exe_error(e, exe_name, help))?;
+    let (mut cmd, spawned) = crate::audit::spawn(
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .map_err(|e| map_exe_error(e, exe_name, help))?;
     let mut stdi = cmd.stdin.take().context("stdin not piped")?;
     let stdo = cmd.stdout.take().context("stdout not piped")?;
     let crlf = regex::bytes::Regex::new("\r\n").unwrap();
@@ -227,7 +218,7 @@ pub fn pipe_output(
         std::io::Result::Ok(())
     });
     Ok(Box::pin(stdo_norm.chain(
-        proc_wait(cmd, move || format!("subprocess: {cmd_log}")).chain(join_handle_to_stream(join)),
+        proc_wait(cmd, spawned, move || format!("subprocess: {cmd_log}")).chain(join_handle_to_stream(join)),
     )))
 }
 
@@ -242,7 +233,7 @@ impl GetMetadata for CustomSpawningFileAdapter {
         &self.meta
     }
 }
-fn arg_replacer(arg: &str, filepath_hint: &Path, config: &RgaConfig) -> Result<String> {
+pub(super) fn arg_replacer(arg: &str, filepath_hint: &Path, config: &RgaConfig) -> Result<String> {
     expand_str_ez(arg, |s| match s {
         "input_virtual_path" => Ok(filepath_hint.to_string_lossy()),
         "input_file_stem" => Ok(filepath_hint
@@ -253,7 +244,11 @@ fn arg_replacer(arg: &str, filepath_hint: &Path, config: &RgaConfig) -> Result<S
             .extension()
             .unwrap_or_default()
             .to_string_lossy()),
-        "password" => Ok(config.password.clone().unwrap_or_default().into()),
+        "password" => Ok(config
+            .document_password(filepath_hint)
+            .unwrap_or_default()
+            .to_string()
+            .into()),
         e => Err(anyhow::format_err!("unknown replacer ${{{e}}}")),
     })
 }
@@ -330,7 +325,14 @@ impl CustomAdapterConfig {
                     self.binary,
                     self.args.join(" ")
                 ),
-                recurses: true,
+                capabilities: AdapterCapabilities {
+                    produces_pages: self
+                        .output_path_hint
+                        .as_ref()
+                        .is_some_and(|h| h.ends_with(".asciipagebreaks")),
+                    produces_subfiles: true,
+                    ..AdapterCapabilities::runs(&[&self.binary])
+                },
                 fast_matchers: self
                     .extensions
                     .iter()
@@ -427,4 +429,21 @@ PREFIX:Page 1:
         println!("output: {}", String::from_utf8_lossy(&oup));
         Ok(())
     }
+
+    #[test]
+    fn password_by_glob() -> Result<()> {
+        let config = RgaConfig {
+            password: Some("fallback".to_string()),
+            document_passwords: [("*.pdf", "any pdf"), ("bank/*statement*.pdf", "bank")]
+                .into_iter()
+                .map(|(glob, password)| (glob.to_string(), password.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let password = |path: &str| arg_replacer("$password", Path::new(path), &config);
+        assert_eq!(password("./bank/2024-statement.pdf")?, "bank");
+        assert_eq!(password("docs/manual.pdf")?, "any pdf");
+        assert_eq!(password("notes.txt")?, "fallback");
+        Ok(())
+    }
 }
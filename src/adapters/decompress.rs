@@ -1,28 +1,36 @@
-use crate::adapted_iter::one_file;
+use crate::{adapted_iter::one_file, join_handle_to_stream, salvage::SalvageRead};
 
 use super::*;
 
 use anyhow::Result;
 use lazy_static::lazy_static;
-use tokio::io::BufReader;
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio_util::io::SyncIoBridge;
 
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-static EXTENSIONS: &[&str] = &["als", "bz2", "gz", "tbz", "tbz2", "tgz", "xz", "zst"];
+static EXTENSIONS: &[&str] = &[
+    "als", "bgz", "br", "bz2", "gz", "lz4", "tbz", "tbz2", "tgz", "tzst", "xz", "zst",
+];
 static MIME_TYPES: &[&str] = &[
     "application/gzip",
     "application/x-bzip",
+    "application/x-lz4",
     "application/x-xz",
     "application/zstd",
 ];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "decompress".to_owned(),
-        version: 1,
+        version: 3,
         description:
             "Reads compressed file as a stream and runs a different extractor on the contents."
                 .to_owned(),
-        recurses: true,
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -51,26 +59,66 @@ impl GetMetadata for DecompressAdapter {
     }
 }
 
-fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
+/// decoders that only exist as blocking readers (async-compression has no lz4 and needs an older
+/// brotli) run on a blocking thread that writes into a pipe
+fn decompress_blocking<R: Read + Send + 'static>(
+    inp: ReadBox,
+    decoder: fn(SyncIoBridge<ReadBox>) -> R,
+) -> ReadBox {
+    let (w, r) = tokio::io::duplex(128 * 1024);
+    let inp = SyncIoBridge::new(inp);
+    let mut oup = SyncIoBridge::new(w);
+    let joiner = tokio::task::spawn_blocking(move || {
+        std::io::copy(&mut decoder(inp), &mut oup)?;
+        oup.shutdown()
+    });
+    Box::pin(r.chain(join_handle_to_stream(joiner)))
+}
+
+/// `salvage` decodes gzip and bzip2 with the blocking decoders, which pass on the data before an
+/// error. The async decoders drop what they decoded in the read that fails
+fn decompress_any(reason: &FileMatcher, inp: ReadBox, salvage: bool) -> Result<ReadBox> {
     use FastFileMatcher::*;
     use FileMatcher::*;
     use async_compression::tokio::bufread;
-    let gz = |inp: ReadBox| Box::pin(bufread::GzipDecoder::new(BufReader::new(inp)));
-    let bz2 = |inp: ReadBox| Box::pin(bufread::BzDecoder::new(BufReader::new(inp)));
+    let gz = |inp: ReadBox| -> ReadBox {
+        if salvage {
+            return decompress_blocking(inp, flate2::read::MultiGzDecoder::new);
+        }
+        // bgzip (vcf.gz, bam) and parallel gzip tools write many concatenated members
+        let mut decoder = bufread::GzipDecoder::new(BufReader::new(inp));
+        decoder.multiple_members(true);
+        Box::pin(decoder)
+    };
+    let bz2 = |inp: ReadBox| -> ReadBox {
+        if salvage {
+            return decompress_blocking(inp, bzip2::read::MultiBzDecoder::new);
+        }
+        Box::pin(bufread::BzDecoder::new(BufReader::new(inp)))
+    };
     let xz = |inp: ReadBox| Box::pin(bufread::XzDecoder::new(BufReader::new(inp)));
-    let zst = |inp: ReadBox| Box::pin(bufread::ZstdDecoder::new(BufReader::new(inp)));
+    let zst = |inp: ReadBox| {
+        let mut decoder = bufread::ZstdDecoder::new(BufReader::new(inp));
+        decoder.multiple_members(true);
+        Box::pin(decoder)
+    };
+    let lz4 = |inp| decompress_blocking(inp, lz4_flex::frame::FrameDecoder::new);
+    let br = |inp| decompress_blocking(inp, |inp| brotli::Decompressor::new(inp, 64 * 1024));
 
     Ok(match reason {
         Fast(FileExtension(ext)) => match ext.as_ref() {
-            "als" | "gz" | "tgz" => gz(inp),
+            "als" | "bgz" | "gz" | "tgz" => gz(inp),
             "bz2" | "tbz" | "tbz2" => bz2(inp),
-            "zst" => zst(inp),
+            "zst" | "tzst" => zst(inp),
             "xz" => xz(inp),
+            "lz4" => lz4(inp),
+            "br" => br(inp),
             ext => Err(format_err!("don't know how to decompress {}", ext))?,
         },
         MimeType(mime) => match mime.as_ref() {
             "application/gzip" => gz(inp),
             "application/x-bzip" => bz2(inp),
+            "application/x-lz4" => lz4(inp),
             "application/x-xz" => xz(inp),
             "application/zstd" => zst(inp),
             mime => Err(format_err!("don't know how to decompress mime {}", mime))?,
@@ -87,7 +135,7 @@ fn get_inner_filename(filename: &Path) -> PathBuf {
         .expect("no filename given?")
         .to_string_lossy();
     let new_extension = match extension.as_ref() {
-        "tgz" | "tbz" | "tbz2" => ".tar",
+        "tgz" | "tbz" | "tbz2" | "tzst" => ".tar",
         _other => "",
     };
     filename.with_file_name(format!("{}{}", stem, new_extension))
@@ -100,12 +148,17 @@ impl FileAdapter for DecompressAdapter {
         ai: AdaptInfo,
         detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
+        let mut inp = decompress_any(detection_reason, ai.inp, ai.config.salvage)?;
+        if ai.config.salvage {
+            let name = format!("{}{}", ai.line_prefix, ai.filepath_hint.display());
+            inp = Box::pin(SalvageRead::new(inp, name));
+        }
         Ok(one_file(AdaptInfo {
             filepath_hint: get_inner_filename(&ai.filepath_hint),
             is_real_file: false,
             file_mtime_unix_ms: None,
             archive_recursion_depth: ai.archive_recursion_depth + 1,
-            inp: decompress_any(detection_reason, ai.inp)?,
+            inp,
             line_prefix: ai.line_prefix,
             config: ai.config.clone(),
             postprocess: ai.postprocess,
@@ -129,6 +182,8 @@ mod tests {
             ("a/b/initramfs", "a/b/initramfs"),
             ("hi/test.tbz2", "hi/test.tar"),
             ("hi/test.tbz", "hi/test.tar"),
+            ("hi/test.tzst", "hi/test.tar"),
+            ("logs/app.log.lz4", "logs/app.log"),
             ("hi/test.hi.bz2", "hi/test.hi"),
             ("hello.tar.gz", "hello.tar"),
         ] {
@@ -149,6 +204,51 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn concatenated_gz() -> Result<()> {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+        let adapter = DecompressAdapter;
+
+        let mut data = vec![];
+        for part in ["##fileformat=VCFv4.2\n", "chr1\t100\trs1\tA\tG\n"] {
+            let mut e = GzEncoder::new(vec![], Compression::default());
+            e.write_all(part.as_bytes())?;
+            data.extend(e.finish()?);
+        }
+        let filepath = PathBuf::from("variants.vcf.bgz");
+        let (a, d) = simple_adapt_info(&filepath, Box::pin(std::io::Cursor::new(data)));
+        let r = adapter.adapt(a, &d).await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "##fileformat=VCFv4.2\nchr1\t100\trs1\tA\tG\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lz4_and_brotli() -> Result<()> {
+        use std::io::Write;
+        let adapter = DecompressAdapter;
+        let text = "hello from a log file\n".repeat(1000);
+
+        let mut lz4 = lz4_flex::frame::FrameEncoder::new(vec![]);
+        lz4.write_all(text.as_bytes())?;
+        let mut br = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+        br.write_all(text.as_bytes())?;
+        for (name, data) in [
+            ("app.log.lz4", lz4.finish()?),
+            ("app.log.br", br.into_inner()),
+        ] {
+            let (a, d) =
+                simple_adapt_info(&PathBuf::from(name), Box::pin(std::io::Cursor::new(data)));
+            let o = adapted_to_vec(adapter.adapt(a, &d).await?).await?;
+            assert_eq!(String::from_utf8(o)?, text, "{name}");
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn pdf_gz() -> Result<()> {
         let adapter = DecompressAdapter;
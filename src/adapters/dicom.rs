@@ -0,0 +1,609 @@
+//! DICOM medical images: the data elements of the file as `Keyword: value` lines (patient, study,
+//! series, device, and the text of structured reports), with the elements of sequences prefixed by
+//! the sequence they are in. Binary values and the pixel data are left out. Only the first
+//! [`MAX_HEADER`] bytes are read, the pixel data is usually the last element of the file.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Read;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["dcm", "dicom"];
+
+/// the most of a file that is read
+pub const MAX_HEADER: u64 = 16 * 1024 * 1024;
+const PREAMBLE: usize = 128;
+const PIXEL_DATA: u32 = 0x7fe0_0010;
+const TRANSFER_SYNTAX: u32 = 0x0002_0010;
+const META_GROUP_LENGTH: u32 = 0x0002_0000;
+const IMPLICIT_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+const EXPLICIT_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+const EXPLICIT_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+const DEFLATED: &str = "1.2.840.10008.1.2.1.99";
+const UNDEFINED_LENGTH: u32 = 0xffff_ffff;
+const ITEM: u32 = 0xfffe_e000;
+const ITEM_END: u32 = 0xfffe_e00d;
+const SEQUENCE_END: u32 = 0xfffe_e0dd;
+/// values longer than this are not shown
+const MAX_VALUE: usize = 64 * 1024;
+
+/// the keywords and value representations of common elements, for names and for files without
+/// explicit value representations
+const DICTIONARY: &[(u32, &str, &str)] = &[
+    (0x0002_0000, "UL", "FileMetaInformationGroupLength"),
+    (0x0002_0001, "OB", "FileMetaInformationVersion"),
+    (0x0002_0002, "UI", "MediaStorageSOPClassUID"),
+    (0x0002_0003, "UI", "MediaStorageSOPInstanceUID"),
+    (0x0002_0010, "UI", "TransferSyntaxUID"),
+    (0x0002_0012, "UI", "ImplementationClassUID"),
+    (0x0002_0013, "SH", "ImplementationVersionName"),
+    (0x0002_0016, "AE", "SourceApplicationEntityTitle"),
+    (0x0008_0005, "CS", "SpecificCharacterSet"),
+    (0x0008_0008, "CS", "ImageType"),
+    (0x0008_0012, "DA", "InstanceCreationDate"),
+    (0x0008_0013, "TM", "InstanceCreationTime"),
+    (0x0008_0016, "UI", "SOPClassUID"),
+    (0x0008_0018, "UI", "SOPInstanceUID"),
+    (0x0008_0020, "DA", "StudyDate"),
+    (0x0008_0021, "DA", "SeriesDate"),
+    (0x0008_0022, "DA", "AcquisitionDate"),
+    (0x0008_0023, "DA", "ContentDate"),
+    (0x0008_0030, "TM", "StudyTime"),
+    (0x0008_0031, "TM", "SeriesTime"),
+    (0x0008_0032, "TM", "AcquisitionTime"),
+    (0x0008_0033, "TM", "ContentTime"),
+    (0x0008_0050, "SH", "AccessionNumber"),
+    (0x0008_0060, "CS", "Modality"),
+    (0x0008_0064, "CS", "ConversionType"),
+    (0x0008_0070, "LO", "Manufacturer"),
+    (0x0008_0080, "LO", "InstitutionName"),
+    (0x0008_0081, "ST", "InstitutionAddress"),
+    (0x0008_0090, "PN", "ReferringPhysicianName"),
+    (0x0008_0100, "SH", "CodeValue"),
+    (0x0008_0102, "SH", "CodingSchemeDesignator"),
+    (0x0008_0104, "LO", "CodeMeaning"),
+    (0x0008_0201, "SH", "TimezoneOffsetFromUTC"),
+    (0x0008_1010, "SH", "StationName"),
+    (0x0008_1030, "LO", "StudyDescription"),
+    (0x0008_1032, "SQ", "ProcedureCodeSequence"),
+    (0x0008_103e, "LO", "SeriesDescription"),
+    (0x0008_1040, "LO", "InstitutionalDepartmentName"),
+    (0x0008_1048, "PN", "PhysiciansOfRecord"),
+    (0x0008_1050, "PN", "PerformingPhysicianName"),
+    (0x0008_1060, "PN", "NameOfPhysiciansReadingStudy"),
+    (0x0008_1070, "PN", "OperatorsName"),
+    (0x0008_1080, "LO", "AdmittingDiagnosesDescription"),
+    (0x0008_1090, "LO", "ManufacturerModelName"),
+    (0x0008_1140, "SQ", "ReferencedImageSequence"),
+    (0x0008_2111, "ST", "DerivationDescription"),
+    (0x0010_0010, "PN", "PatientName"),
+    (0x0010_0020, "LO", "PatientID"),
+    (0x0010_0030, "DA", "PatientBirthDate"),
+    (0x0010_0040, "CS", "PatientSex"),
+    (0x0010_1000, "LO", "OtherPatientIDs"),
+    (0x0010_1001, "PN", "OtherPatientNames"),
+    (0x0010_1010, "AS", "PatientAge"),
+    (0x0010_1020, "DS", "PatientSize"),
+    (0x0010_1030, "DS", "PatientWeight"),
+    (0x0010_2160, "SH", "EthnicGroup"),
+    (0x0010_21b0, "LT", "AdditionalPatientHistory"),
+    (0x0010_4000, "LT", "PatientComments"),
+    (0x0018_0010, "LO", "ContrastBolusAgent"),
+    (0x0018_0015, "CS", "BodyPartExamined"),
+    (0x0018_0020, "CS", "ScanningSequence"),
+    (0x0018_0050, "DS", "SliceThickness"),
+    (0x0018_0060, "DS", "KVP"),
+    (0x0018_0087, "DS", "MagneticFieldStrength"),
+    (0x0018_0088, "DS", "SpacingBetweenSlices"),
+    (0x0018_1000, "LO", "DeviceSerialNumber"),
+    (0x0018_1020, "LO", "SoftwareVersions"),
+    (0x0018_1030, "LO", "ProtocolName"),
+    (0x0018_1150, "IS", "ExposureTime"),
+    (0x0018_1151, "IS", "XRayTubeCurrent"),
+    (0x0018_5100, "CS", "PatientPosition"),
+    (0x0020_000d, "UI", "StudyInstanceUID"),
+    (0x0020_000e, "UI", "SeriesInstanceUID"),
+    (0x0020_0010, "SH", "StudyID"),
+    (0x0020_0011, "IS", "SeriesNumber"),
+    (0x0020_0012, "IS", "AcquisitionNumber"),
+    (0x0020_0013, "IS", "InstanceNumber"),
+    (0x0020_0020, "CS", "PatientOrientation"),
+    (0x0020_0032, "DS", "ImagePositionPatient"),
+    (0x0020_0037, "DS", "ImageOrientationPatient"),
+    (0x0020_0052, "UI", "FrameOfReferenceUID"),
+    (0x0020_0060, "CS", "Laterality"),
+    (0x0020_4000, "LT", "ImageComments"),
+    (0x0028_0002, "US", "SamplesPerPixel"),
+    (0x0028_0004, "CS", "PhotometricInterpretation"),
+    (0x0028_0008, "IS", "NumberOfFrames"),
+    (0x0028_0010, "US", "Rows"),
+    (0x0028_0011, "US", "Columns"),
+    (0x0028_0030, "DS", "PixelSpacing"),
+    (0x0028_0100, "US", "BitsAllocated"),
+    (0x0028_0101, "US", "BitsStored"),
+    (0x0028_0102, "US", "HighBit"),
+    (0x0028_0103, "US", "PixelRepresentation"),
+    (0x0028_1050, "DS", "WindowCenter"),
+    (0x0028_1051, "DS", "WindowWidth"),
+    (0x0028_1052, "DS", "RescaleIntercept"),
+    (0x0028_1053, "DS", "RescaleSlope"),
+    (0x0032_1032, "PN", "RequestingPhysician"),
+    (0x0032_1060, "LO", "RequestedProcedureDescription"),
+    (0x0032_4000, "LT", "StudyComments"),
+    (0x0040_0244, "DA", "PerformedProcedureStepStartDate"),
+    (0x0040_0254, "LO", "PerformedProcedureStepDescription"),
+    (0x0040_0275, "SQ", "RequestAttributesSequence"),
+    (0x0040_1001, "SH", "RequestedProcedureID"),
+    (0x0040_1002, "LO", "ReasonForTheRequestedProcedure"),
+    (0x0040_a040, "CS", "ValueType"),
+    (0x0040_a043, "SQ", "ConceptNameCodeSequence"),
+    (0x0040_a160, "UT", "TextValue"),
+    (0x0040_a730, "SQ", "ContentSequence"),
+    (0x7fe0_0010, "OW", "PixelData"),
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dicom".to_owned(),
+        version: 1,
+        description: "Lists the data elements (patient, study, series, device, report text) of DICOM medical images".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/dicom".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DicomAdapter;
+
+impl DicomAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for DicomAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn lookup(tag: u32) -> Option<(&'static str, &'static str)> {
+    DICTIONARY
+        .binary_search_by_key(&tag, |(t, ..)| *t)
+        .ok()
+        .map(|i| (DICTIONARY[i].1, DICTIONARY[i].2))
+}
+
+fn is_text(vr: &str) -> bool {
+    matches!(
+        vr,
+        "AE" | "AS"
+            | "CS"
+            | "DA"
+            | "DS"
+            | "DT"
+            | "IS"
+            | "LO"
+            | "LT"
+            | "PN"
+            | "SH"
+            | "ST"
+            | "TM"
+            | "UC"
+            | "UI"
+            | "UR"
+            | "UT"
+    )
+}
+
+/// value representations with a 32 bit length in explicit VR encodings
+fn has_long_length(vr: &[u8]) -> bool {
+    matches!(
+        vr,
+        b"OB"
+            | b"OD"
+            | b"OF"
+            | b"OL"
+            | b"OV"
+            | b"OW"
+            | b"SQ"
+            | b"SV"
+            | b"UC"
+            | b"UN"
+            | b"UR"
+            | b"UT"
+            | b"UV"
+    )
+}
+
+/// text in the character set of the file: utf-8 (ISO_IR 192) or ascii, otherwise latin-1
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Result::Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// a text value as it is shown: multiple values separated by commas, dates as yyyy-mm-dd and the
+/// components of person names separated by spaces
+fn text_value(vr: &str, bytes: &[u8]) -> String {
+    let text = decode(bytes);
+    let text = text.trim_end_matches(['\0', ' ']);
+    if matches!(vr, "LT" | "ST" | "UT") {
+        return text.trim().to_string();
+    }
+    text.split('\\')
+        .map(|value| {
+            let value = value.trim();
+            match vr {
+                "DA" if value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()) => {
+                    format!("{}-{}-{}", &value[..4], &value[4..6], &value[6..])
+                }
+                "PN" => value
+                    .split(['^', '='])
+                    .filter(|c| !c.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                _ => value.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Clone, Copy)]
+struct Encoding {
+    explicit: bool,
+    big_endian: bool,
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    encoding: Encoding,
+}
+
+impl<'a> Parser<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let b: [u8; 2] = self.bytes(2)?.try_into().ok()?;
+        Some(if self.encoding.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let b: [u8; 4] = self.bytes(4)?.try_into().ok()?;
+        Some(if self.encoding.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn numbers(&self, vr: &str, bytes: &[u8]) -> Option<String> {
+        let size = match vr {
+            "US" | "SS" => 2,
+            "UL" | "SL" | "FL" => 4,
+            "FD" => 8,
+            _ => return None,
+        };
+        if bytes.is_empty() || !bytes.len().is_multiple_of(size) || bytes.len() / size > 16 {
+            return None;
+        }
+        let values = bytes.chunks_exact(size).map(|b| {
+            let mut b = b.to_vec();
+            if self.encoding.big_endian {
+                b.reverse();
+            }
+            match vr {
+                "US" => u16::from_le_bytes([b[0], b[1]]).to_string(),
+                "SS" => i16::from_le_bytes([b[0], b[1]]).to_string(),
+                "UL" => u32::from_le_bytes(b[..4].try_into().unwrap()).to_string(),
+                "SL" => i32::from_le_bytes(b[..4].try_into().unwrap()).to_string(),
+                "FL" => f32::from_le_bytes(b[..4].try_into().unwrap()).to_string(),
+                _ => f64::from_le_bytes(b[..8].try_into().unwrap()).to_string(),
+            }
+        });
+        Some(values.collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// an open sequence or item, until the given offset or its delimiter
+struct Open {
+    sequence: Option<String>,
+    end: Option<usize>,
+}
+
+/// the lines of the elements from the parser's position on, until the end of the data or the
+/// pixel data. With `meta` only the meta elements (group 2) are read, and the transfer syntax of
+/// the rest is set
+fn elements(
+    p: &mut Parser,
+    lines: &mut Vec<String>,
+    meta: bool,
+    transfer_syntax: &mut Option<String>,
+) -> Option<()> {
+    let mut open: Vec<Open> = vec![];
+    let mut meta_end = None;
+    loop {
+        while open
+            .last()
+            .is_some_and(|o| o.end.is_some_and(|end| p.pos >= end))
+        {
+            open.pop();
+        }
+        if meta && meta_end.is_some_and(|end| p.pos >= end) {
+            break;
+        }
+        let start = p.pos;
+        let (group, element) = (p.u16()?, p.u16()?);
+        if meta && group != 2 {
+            p.pos = start;
+            break;
+        }
+        let tag = ((group as u32) << 16) | element as u32;
+        if group == 0xfffe {
+            let len = p.u32()?;
+            match tag {
+                ITEM => open.push(Open {
+                    sequence: None,
+                    end: (len != UNDEFINED_LENGTH).then(|| p.pos + len as usize),
+                }),
+                ITEM_END if open.last().is_some_and(|o| o.sequence.is_none()) => {
+                    open.pop();
+                }
+                SEQUENCE_END => {
+                    while let Some(o) = open.pop() {
+                        if o.sequence.is_some() {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let known = lookup(tag);
+        let (vr, len) = if p.encoding.explicit {
+            let vr: [u8; 2] = p.bytes(2)?.try_into().ok()?;
+            let len = if has_long_length(&vr) {
+                p.bytes(2)?;
+                p.u32()?
+            } else {
+                p.u16()? as u32
+            };
+            (String::from_utf8_lossy(&vr).into_owned(), len)
+        } else {
+            let vr = known.map(|(vr, _)| vr).unwrap_or("UN");
+            (vr.to_string(), p.u32()?)
+        };
+        if tag == PIXEL_DATA {
+            // icons in sequences have their own pixel data
+            if open.is_empty() || len == UNDEFINED_LENGTH {
+                break;
+            }
+            p.bytes(len as usize)?;
+            continue;
+        }
+        let name = match known {
+            Some((_, keyword)) => keyword.to_string(),
+            None => format!("({group:04X},{element:04X})"),
+        };
+        if vr == "SQ" || len == UNDEFINED_LENGTH {
+            // an unknown element of undefined length in an implicit VR file is a sequence too
+            open.push(Open {
+                sequence: Some(name),
+                end: (len != UNDEFINED_LENGTH).then(|| p.pos + len as usize),
+            });
+            continue;
+        }
+        let value = p.bytes(len as usize)?;
+        if tag == META_GROUP_LENGTH
+            && let Some(len) = p.numbers("UL", value).and_then(|l| l.parse::<usize>().ok())
+        {
+            meta_end = Some(p.pos + len);
+        }
+        if tag == TRANSFER_SYNTAX {
+            *transfer_syntax = Some(text_value("UI", value));
+        }
+        // group lengths
+        if element == 0 || value.len() > MAX_VALUE {
+            continue;
+        }
+        let shown = if is_text(&vr) {
+            Some(text_value(&vr, value))
+        } else if vr == "UN"
+            && !value.is_empty()
+            && value.iter().all(|b| b.is_ascii_graphic() || *b == b' ')
+        {
+            // private elements of implicit VR files
+            Some(text_value("LO", value))
+        } else {
+            p.numbers(&vr, value)
+        };
+        let Some(shown) = shown.filter(|s| !s.is_empty()) else {
+            continue;
+        };
+        let path = open
+            .iter()
+            .filter_map(|o| o.sequence.as_deref())
+            .chain([name.as_str()])
+            .collect::<Vec<_>>()
+            .join(" > ");
+        lines.push(format!("{path}: {shown}"));
+    }
+    Some(())
+}
+
+/// the lines of a DICOM file, or of a data set without the preamble and meta information
+fn convert(data: &[u8]) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    let explicit_le = Encoding {
+        explicit: true,
+        big_endian: false,
+    };
+    let mut transfer_syntax = None;
+    let rest = if data.get(PREAMBLE..PREAMBLE + 4) == Some(b"DICM") {
+        let mut meta = Parser {
+            data,
+            pos: PREAMBLE + 4,
+            encoding: explicit_le,
+        };
+        elements(&mut meta, &mut lines, true, &mut transfer_syntax);
+        &data[meta.pos.min(data.len())..]
+    } else {
+        // old files are the data set only, the value representation is there if it is uppercase
+        let explicit = data
+            .get(4..6)
+            .is_some_and(|vr| vr.iter().all(u8::is_ascii_uppercase));
+        let syntax = if explicit {
+            EXPLICIT_LITTLE_ENDIAN
+        } else {
+            IMPLICIT_LITTLE_ENDIAN
+        };
+        transfer_syntax = Some(syntax.to_string());
+        data
+    };
+    let inflated;
+    let (data, encoding) = match transfer_syntax.as_deref() {
+        Some(IMPLICIT_LITTLE_ENDIAN) => (
+            rest,
+            Encoding {
+                explicit: false,
+                big_endian: false,
+            },
+        ),
+        Some(EXPLICIT_BIG_ENDIAN) => (
+            rest,
+            Encoding {
+                explicit: true,
+                big_endian: true,
+            },
+        ),
+        Some(DEFLATED) => {
+            let mut out = vec![];
+            // the header may be cut off in the middle of the deflated data
+            let _ = flate2::read::DeflateDecoder::new(rest).read_to_end(&mut out);
+            inflated = out;
+            (&inflated[..], explicit_le)
+        }
+        _ => (rest, explicit_le),
+    };
+    let mut parser = Parser {
+        data,
+        pos: 0,
+        encoding,
+    };
+    elements(&mut parser, &mut lines, false, &mut None);
+    Ok(lines)
+}
+
+#[async_trait]
+impl WritingFileAdapter for DicomAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut data = vec![];
+        inp.take(MAX_HEADER).read_to_end(&mut data).await?;
+        for line in convert(&data)? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// an explicit VR little endian element
+    fn element(group: u16, element: u16, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+        let mut out = [&group.to_le_bytes()[..], &element.to_le_bytes(), vr].concat();
+        if has_long_length(vr) {
+            out.extend([0, 0]);
+            out.extend((value.len() as u32).to_le_bytes());
+        } else {
+            out.extend((value.len() as u16).to_le_bytes());
+        }
+        out.extend(value);
+        out
+    }
+
+    #[test]
+    fn dictionary_sorted() {
+        assert!(DICTIONARY.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn explicit_with_sequence() -> Result<()> {
+        let syntax = b"1.2.840.10008.1.2.1\0";
+        let meta = element(0x0002, 0x0010, b"UI", syntax);
+        let item = [
+            element(0x0008, 0x0104, b"LO", b"Findings"),
+            element(0x0040, 0xa160, b"UT", b"No acute fracture. "),
+        ]
+        .concat();
+        let file = [
+            &[0; PREAMBLE][..],
+            b"DICM",
+            &element(0x0002, 0x0000, b"UL", &(meta.len() as u32).to_le_bytes()),
+            &meta,
+            &element(0x0008, 0x0020, b"DA", b"20230115"),
+            &element(0x0008, 0x0060, b"CS", b"CT"),
+            &element(0x0008, 0x1030, b"LO", b"CHEST W/O CONTRAST"),
+            &element(0x0009, 0x0010, b"LO", b"ACME private"),
+            &element(0x0010, 0x0010, b"PN", b"Doe^Jane "),
+            &element(0x0028, 0x0010, b"US", &512u16.to_le_bytes()),
+            // a sequence of undefined length with an item of undefined length
+            &[0x40, 0x00, 0x30, 0xa7],
+            b"SQ\0\0",
+            &[0xff; 4],
+            &[0xfe, 0xff, 0x00, 0xe0, 0xff, 0xff, 0xff, 0xff],
+            &item,
+            &[0xfe, 0xff, 0x0d, 0xe0, 0, 0, 0, 0],
+            &[0xfe, 0xff, 0xdd, 0xe0, 0, 0, 0, 0],
+            &element(0x0020, 0x4000, b"LT", b"after the sequence"),
+            &element(0x7fe0, 0x0010, b"OW", &[1, 2, 3, 4]),
+        ]
+        .concat();
+        assert_eq!(
+            convert(&file)?,
+            [
+                "TransferSyntaxUID: 1.2.840.10008.1.2.1",
+                "StudyDate: 2023-01-15",
+                "Modality: CT",
+                "StudyDescription: CHEST W/O CONTRAST",
+                "(0009,0010): ACME private",
+                "PatientName: Doe Jane",
+                "Rows: 512",
+                "ContentSequence > CodeMeaning: Findings",
+                "ContentSequence > TextValue: No acute fracture.",
+                "ImageComments: after the sequence",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,753 @@
+//! Apple disk images (UDIF). The image is a table of chunks (raw, zero filled or compressed with
+//! ADC, zlib, bzip2 or lzma) per partition, listed in the XML property list the `koly` trailer
+//! points to. The chunks are read as one seekable [`Disk`], on which HFS+ and APFS volumes are
+//! found by their signatures. LZFSE compressed images (ULFO) are not supported.
+mod apfs;
+mod hfs;
+
+use super::iso::{ImageFile, read_at};
+use super::*;
+use crate::adapters::le::{u32_at, u32_be_at, u64_at, u64_be_at};
+use crate::vfs::{Vfs, VfsEntry, adapt_vfs};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["dmg"];
+
+const SECTOR: u64 = 512;
+const TRAILER_LEN: u64 = 512;
+const MISH_HEADER_LEN: usize = 204;
+const CHUNK_LEN: usize = 40;
+
+const CHUNK_ZERO: u32 = 0;
+const CHUNK_RAW: u32 = 1;
+const CHUNK_IGNORE: u32 = 2;
+const CHUNK_ADC: u32 = 0x80000004;
+const CHUNK_ZLIB: u32 = 0x80000005;
+const CHUNK_BZIP2: u32 = 0x80000006;
+const CHUNK_LZFSE: u32 = 0x80000007;
+const CHUNK_LZMA: u32 = 0x80000008;
+const CHUNK_COMMENT: u32 = 0x7ffffffe;
+const CHUNK_END: u32 = 0xffffffff;
+
+/// bytes read from the disk at once when copying a file out of the image
+const COPY_LEN: u64 = 1024 * 1024;
+/// the largest chunk with data that is read. Imaging tools write chunks of at most a few MB, the sizes come
+/// from the image and the chunk is held in memory
+const MAX_CHUNK_LEN: u64 = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dmg".to_owned(),
+        version: 1,
+        description: "Lists the files of the HFS+ and APFS volumes in Apple disk images (.dmg) and recurses into them".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-apple-diskimage".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DmgAdapter;
+
+impl DmgAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for DmgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Apple Data Compression, the LZ77 variant of old disk images
+fn adc_decompress(input: &[u8], out_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(out_len);
+    let mut i = 0;
+    while i < input.len() && out.len() < out_len {
+        let b = input[i];
+        if b & 0x80 != 0 {
+            let len = (b & 0x7f) as usize + 1;
+            out.extend_from_slice(
+                input
+                    .get(i + 1..i + 1 + len)
+                    .context("truncated ADC literal")?,
+            );
+            i += 1 + len;
+            continue;
+        }
+        let (len, distance, size) = if b & 0x40 != 0 {
+            let d = input.get(i + 1..i + 3).context("truncated ADC match")?;
+            (
+                (b & 0x3f) as usize + 4,
+                u16::from_be_bytes([d[0], d[1]]) as usize,
+                3,
+            )
+        } else {
+            let d = *input.get(i + 1).context("truncated ADC match")?;
+            (
+                ((b & 0x3c) >> 2) as usize + 3,
+                ((b as usize & 3) << 8) | d as usize,
+                2,
+            )
+        };
+        let start = out
+            .len()
+            .checked_sub(distance + 1)
+            .context("invalid ADC match distance")?;
+        // the match may overlap what it produces
+        for j in 0..len {
+            out.push(out[start + j]);
+        }
+        i += size;
+    }
+    Ok(out)
+}
+
+/// a writer that fails instead of growing `out` past `limit`, for decoders that can't be read from
+struct Bounded<'a> {
+    out: &'a mut Vec<u8>,
+    limit: usize,
+}
+
+impl std::io::Write for Bounded<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.out.len() + buf.len() > self.limit {
+            return Err(std::io::Error::other("more data than the chunk length"));
+        }
+        self.out.extend_from_slice(buf);
+        std::io::Result::Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Result::Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Chunk {
+    kind: u32,
+    disk_offset: u64,
+    len: u64,
+    data_offset: u64,
+    data_len: u64,
+}
+
+impl Chunk {
+    /// the `len` bytes of the chunk. The decoders grow the output up to `len`, the rest is zero filled, which
+    /// is bounded as `len` is checked by [`block_table`]
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        match self.kind {
+            CHUNK_RAW => out.extend_from_slice(data),
+            CHUNK_ADC => out = adc_decompress(data, self.len as usize)?,
+            CHUNK_ZLIB => {
+                flate2::read::ZlibDecoder::new(data)
+                    .take(self.len)
+                    .read_to_end(&mut out)?;
+            }
+            CHUNK_BZIP2 => {
+                bzip2::read::BzDecoder::new(data)
+                    .take(self.len)
+                    .read_to_end(&mut out)?;
+            }
+            CHUNK_LZMA => {
+                let mut bounded = Bounded {
+                    out: &mut out,
+                    limit: self.len as usize,
+                };
+                lzma_rs::xz_decompress(&mut std::io::Cursor::new(data), &mut bounded)
+                    .map_err(|e| format_err!("{e:?}"))?;
+            }
+            CHUNK_LZFSE => bail!("LZFSE compressed disk images are not supported"),
+            kind => bail!("unknown disk image chunk type {kind:#x}"),
+        }
+        out.truncate(self.len as usize);
+        out.resize(self.len as usize, 0);
+        Ok(out)
+    }
+}
+
+/// a partition of the image, by its offset on the disk
+#[derive(Debug, Clone)]
+pub struct Partition {
+    name: String,
+    offset: u64,
+}
+
+/// the uncompressed disk of an image
+pub struct Disk<R> {
+    inner: R,
+    /// by disk offset
+    chunks: Vec<Chunk>,
+    len: u64,
+    pos: u64,
+    /// the last chunk that was decompressed
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+/// the chunks of a partition from its `mish` block table
+fn block_table(mish: &[u8], data_fork_offset: u64) -> Result<Vec<Chunk>> {
+    const INVALID: &str = "invalid disk image block table";
+    if mish.len() < MISH_HEADER_LEN || &mish[..4] != b"mish" {
+        bail!(INVALID);
+    }
+    let first_sector = u64_be_at(mish, 8).context(INVALID)?;
+    let data_offset = u64_be_at(mish, 24).context(INVALID)?;
+    let count = u32_be_at(mish, 200).context(INVALID)? as usize;
+    let mut chunks = vec![];
+    for i in 0..count {
+        let c = mish
+            .get(MISH_HEADER_LEN + i * CHUNK_LEN..MISH_HEADER_LEN + (i + 1) * CHUNK_LEN)
+            .context("truncated disk image block table")?;
+        let kind = u32_be_at(c, 0).context(INVALID)?;
+        match kind {
+            CHUNK_END => break,
+            CHUNK_COMMENT => continue,
+            _ => {}
+        }
+        let field = |at| u64_be_at(c, at).context(INVALID);
+        let (sector, sectors, offset) = (field(8)?, field(16)?, field(24)?);
+        let chunk = Chunk {
+            kind,
+            disk_offset: first_sector
+                .checked_add(sector)
+                .and_then(|sector| sector.checked_mul(SECTOR))
+                .context(INVALID)?,
+            len: sectors.checked_mul(SECTOR).context(INVALID)?,
+            data_offset: data_fork_offset
+                .checked_add(data_offset)
+                .and_then(|start| start.checked_add(offset))
+                .context(INVALID)?,
+            data_len: field(32)?,
+        };
+        if chunk.disk_offset.checked_add(chunk.len).is_none() {
+            bail!(INVALID);
+        }
+        if !matches!(kind, CHUNK_ZERO | CHUNK_IGNORE) && chunk.len > MAX_CHUNK_LEN {
+            bail!(
+                "disk image chunk of {} bytes, more than the {MAX_CHUNK_LEN} that are read",
+                chunk.len
+            );
+        }
+        chunks.push(chunk);
+    }
+    Ok(chunks)
+}
+
+impl<R: Read + Seek> Disk<R> {
+    /// the disk of a UDIF image. Files without the trailer are read as raw disks
+    pub fn open(mut inner: R) -> Result<(Self, Vec<Partition>)> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        let trailer = if file_len >= TRAILER_LEN {
+            read_at(&mut inner, file_len - TRAILER_LEN, TRAILER_LEN as usize)?
+        } else {
+            vec![]
+        };
+        if !trailer.starts_with(b"koly") {
+            let raw = Chunk {
+                kind: CHUNK_RAW,
+                disk_offset: 0,
+                len: file_len,
+                data_offset: 0,
+                data_len: file_len,
+            };
+            let whole = Partition {
+                name: "whole disk".to_string(),
+                offset: 0,
+            };
+            return Ok((Self::new(inner, vec![raw]), vec![whole]));
+        }
+        let field = |at| u64_be_at(&trailer, at).context("truncated disk image trailer");
+        let data_fork_offset = field(24)?;
+        let (xml_offset, xml_len) = (field(216)?, field(224)?);
+        if xml_len == 0 {
+            bail!(
+                "disk image without a property list (resource fork only images are not supported)"
+            );
+        }
+        let xml = read_at(&mut inner, xml_offset, xml_len as usize)?;
+        let plist = plist::Value::from_reader_xml(std::io::Cursor::new(xml))?;
+        let blkx = plist
+            .as_dictionary()
+            .and_then(|d| {
+                d.get("resource-fork")?
+                    .as_dictionary()?
+                    .get("blkx")?
+                    .as_array()
+            })
+            .context("no blkx table in the disk image property list")?;
+        let mut chunks = vec![];
+        let mut partitions = vec![];
+        for entry in blkx {
+            let entry = entry.as_dictionary().context("invalid blkx entry")?;
+            let name = ["Name", "CFName"]
+                .iter()
+                .find_map(|k| entry.get(k)?.as_string())
+                .unwrap_or_default();
+            let mish = entry
+                .get("Data")
+                .and_then(|d| d.as_data())
+                .context("blkx entry without data")?;
+            let table = block_table(mish, data_fork_offset)?;
+            partitions.push(Partition {
+                name: name.to_string(),
+                offset: u64_be_at(mish, 8)
+                    .and_then(|sector| sector.checked_mul(SECTOR))
+                    .context("invalid disk image block table")?,
+            });
+            chunks.extend(table);
+        }
+        chunks.sort_by_key(|c| c.disk_offset);
+        Ok((Self::new(inner, chunks), partitions))
+    }
+
+    fn new(inner: R, chunks: Vec<Chunk>) -> Self {
+        // block_table checks that this doesn't overflow
+        let len = chunks.last().map_or(0, |c| c.disk_offset + c.len);
+        Disk {
+            inner,
+            chunks,
+            len,
+            pos: 0,
+            cache: None,
+        }
+    }
+
+    fn chunk_data(&mut self, i: usize) -> Result<&[u8]> {
+        if self.cache.as_ref().is_none_or(|(cached, _)| *cached != i) {
+            let chunk = self.chunks[i];
+            let data = read_at(&mut self.inner, chunk.data_offset, chunk.data_len as usize)?;
+            let data = chunk.decompress(&data).with_context(|| {
+                format!(
+                    "decompressing the disk image chunk at {}",
+                    chunk.disk_offset
+                )
+            })?;
+            self.cache = Some((i, data));
+        }
+        Ok(&self.cache.as_ref().unwrap().1)
+    }
+}
+
+impl<R: Read + Seek> Read for Disk<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return std::io::Result::Ok(0);
+        }
+        let pos = self.pos;
+        let i = self
+            .chunks
+            .partition_point(|c| c.disk_offset + c.len <= pos);
+        let n = match self.chunks.get(i).copied() {
+            Some(c) if c.disk_offset <= pos => {
+                let within = (pos - c.disk_offset) as usize;
+                let n = ((c.len - within as u64) as usize).min(buf.len());
+                if c.kind == CHUNK_ZERO || c.kind == CHUNK_IGNORE {
+                    buf[..n].fill(0);
+                } else {
+                    let data = self.chunk_data(i).map_err(crate::to_io_err)?;
+                    buf[..n].copy_from_slice(&data[within..within + n]);
+                }
+                n
+            }
+            // gaps between partitions read as zeros
+            next => {
+                let end = next.map_or(self.len, |c| c.disk_offset);
+                let n = ((end - pos) as usize).min(buf.len());
+                buf[..n].fill(0);
+                n
+            }
+        };
+        self.pos += n as u64;
+        std::io::Result::Ok(n)
+    }
+}
+
+impl<R> Seek for Disk<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(p) => p,
+            SeekFrom::End(d) => self.len.saturating_add_signed(d),
+            SeekFrom::Current(d) => self.pos.saturating_add_signed(d),
+        };
+        std::io::Result::Ok(self.pos)
+    }
+}
+
+/// the start of the partitions of a GPT partitioned disk
+fn gpt_partitions<R: Read + Seek>(disk: &mut R) -> Result<Vec<Partition>> {
+    let header = read_at(disk, SECTOR, 92)?;
+    if &header[..8] != b"EFI PART" {
+        return Ok(vec![]);
+    }
+    let table = u64_at(&header, 72).context("truncated GPT header")?;
+    let (count, size) = (
+        u32_at(&header, 80)
+            .context("truncated GPT header")?
+            .min(256) as usize,
+        u32_at(&header, 84).context("truncated GPT header")? as usize,
+    );
+    // entries are 128 bytes, or a larger power of two
+    if size < 128 {
+        bail!("invalid GPT partition entry size {size}");
+    }
+    let table = table
+        .checked_mul(SECTOR)
+        .context("invalid GPT partition table offset")?;
+    let entries = read_at(disk, table, count * size)?;
+    entries
+        .chunks_exact(size)
+        .filter(|e| e[..16].iter().any(|b| *b != 0))
+        .enumerate()
+        .map(|(i, e)| {
+            Ok(Partition {
+                name: format!("partition {}", i + 1),
+                offset: u64_at(e, 32)
+                    .and_then(|sector| sector.checked_mul(SECTOR))
+                    .context("invalid GPT partition entry")?,
+            })
+        })
+        .collect()
+}
+
+/// a mounted file system: its name and files
+type Volume = (String, Vec<ImageFile>);
+
+/// the files of all volumes on the disk. With more than one volume the paths start with the
+/// volume name
+fn read_disk<R: Read + Seek>(disk: &mut R, partitions: &[Partition]) -> Result<Vec<ImageFile>> {
+    let mut volumes: Vec<Volume> = vec![];
+    let mut error = None;
+    let mut probe = |disk: &mut R, partitions: &[Partition], volumes: &mut Vec<Volume>| {
+        for p in partitions {
+            let found = apfs::read(disk, p.offset).and_then(|v| match v {
+                Some(v) => Ok(Some(v)),
+                None => Ok(hfs::read(disk, p.offset)?.map(|v| vec![v])),
+            });
+            match found {
+                Result::Ok(Some(v)) => volumes.extend(v),
+                Result::Ok(None) => {}
+                Err(e) => {
+                    warn!("could not read the file system of {}: {e:#}", p.name);
+                    error = Some(e);
+                }
+            }
+        }
+    };
+    probe(disk, partitions, &mut volumes);
+    // images of a whole disk have a single partition map entry
+    if volumes.is_empty() {
+        let gpt = gpt_partitions(disk).unwrap_or_default();
+        probe(disk, &gpt, &mut volumes);
+    }
+    if volumes.is_empty() {
+        return Err(
+            error.unwrap_or_else(|| format_err!("no HFS+ or APFS volume in the disk image"))
+        );
+    }
+    let prefix = volumes.len() > 1;
+    let mut files: Vec<ImageFile> = volumes
+        .into_iter()
+        .flat_map(|(name, files)| {
+            files.into_iter().map(move |mut f| {
+                if prefix {
+                    f.path = format!("{name}/{}", f.path);
+                }
+                f
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+struct Image {
+    files: Vec<ImageFile>,
+    index: HashMap<String, usize>,
+    disk: Mutex<Disk<std::io::BufReader<std::fs::File>>>,
+}
+
+/// the files of a disk image on the local file system
+pub struct DmgFs {
+    path: PathBuf,
+    image: tokio::sync::OnceCell<std::sync::Arc<Image>>,
+}
+
+impl DmgFs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            image: Default::default(),
+        }
+    }
+
+    async fn image(&self) -> Result<std::sync::Arc<Image>> {
+        self.image
+            .get_or_try_init(|| async {
+                let path = self.path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let file = std::fs::File::open(&path)
+                        .with_context(|| format!("opening {}", path.display()))?;
+                    let (mut disk, partitions) = Disk::open(std::io::BufReader::new(file))?;
+                    let files = read_disk(&mut disk, &partitions)?;
+                    let index = files
+                        .iter()
+                        .enumerate()
+                        .map(|(i, f)| (f.path.clone(), i))
+                        .collect();
+                    Ok(std::sync::Arc::new(Image {
+                        files,
+                        index,
+                        disk: Mutex::new(disk),
+                    }))
+                })
+                .await?
+            })
+            .await
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl Vfs for DmgFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let image = self.image().await?;
+        Ok(image
+            .files
+            .iter()
+            .map(|f| VfsEntry {
+                path: f.path.clone().into(),
+                size: f.size,
+                mtime_unix: f.mtime_unix,
+                version: f.mtime_unix.map(|m| m.to_string()).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let image = self.image().await?;
+        let file = image
+            .index
+            .get(entry.path.to_str().unwrap_or_default())
+            .map(|i| &image.files[*i])
+            .with_context(|| {
+                format!(
+                    "{} not found in {}",
+                    entry.path.display(),
+                    self.path.display()
+                )
+            })?;
+        let mut skip = offset;
+        let mut remaining = file.size.saturating_sub(offset);
+        for extent in &file.extents {
+            if skip >= extent.len {
+                skip -= extent.len;
+                continue;
+            }
+            let len = (extent.len - skip).min(remaining);
+            match extent.offset {
+                Some(start) => {
+                    let mut done = 0;
+                    while done < len {
+                        let n = (len - done).min(COPY_LEN);
+                        let image = image.clone();
+                        let at = start + skip + done;
+                        let buf = tokio::task::spawn_blocking(move || {
+                            read_at(&mut *image.disk.lock().unwrap(), at, n as usize)
+                        })
+                        .await??;
+                        oup.write_all(&buf).await?;
+                        done += n;
+                    }
+                }
+                None => {
+                    tokio::io::copy(&mut tokio::io::repeat(0).take(len), oup).await?;
+                }
+            }
+            skip = 0;
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl FileAdapter for DmgAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (_inp, container) = ai.into_parts();
+        let vfs = std::sync::Arc::new(DmgFs::new(&container.filepath_hint));
+        adapt_vfs(vfs, container).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    /// ADC with literal runs only
+    fn adc_literals(data: &[u8]) -> Vec<u8> {
+        data.chunks(128)
+            .flat_map(|run| [&[0x80 | (run.len() - 1) as u8][..], run].concat())
+            .collect()
+    }
+
+    /// a UDIF image of `disk`, with a zlib, an ADC and a zero chunk
+    fn udif(disk: &[u8]) -> Vec<u8> {
+        let sectors = disk.len() as u64 / SECTOR;
+        let (first, rest) = disk.split_at(disk.len() / 2);
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], Default::default());
+        zlib.write_all(first).unwrap();
+        let zlib = zlib.finish().unwrap();
+        let adc = adc_literals(rest);
+        let data = [&zlib[..], &adc].concat();
+
+        let mut mish = b"mish".to_vec();
+        mish.extend(1u32.to_be_bytes());
+        mish.extend(0u64.to_be_bytes());
+        mish.extend((sectors + 8).to_be_bytes());
+        mish.resize(200, 0);
+        mish.extend(4u32.to_be_bytes());
+        let half = sectors / 2;
+        for (kind, sector, count, offset, len) in [
+            (CHUNK_ZLIB, 0, half, 0, zlib.len()),
+            (CHUNK_ADC, half, sectors - half, zlib.len(), adc.len()),
+            (CHUNK_ZERO, sectors, 8, 0, 0),
+            (CHUNK_END, sectors + 8, 0, 0, 0),
+        ] {
+            mish.extend(kind.to_be_bytes());
+            mish.extend(0u32.to_be_bytes());
+            for v in [sector, count, offset as u64, len as u64] {
+                mish.extend(v.to_be_bytes());
+            }
+        }
+        let mut blkx = plist::Dictionary::new();
+        blkx.insert("Name".into(), "disk image (Apple_HFS : 0)".into());
+        blkx.insert("Data".into(), plist::Value::Data(mish));
+        let mut fork = plist::Dictionary::new();
+        fork.insert("blkx".into(), vec![plist::Value::from(blkx)].into());
+        let mut root = plist::Dictionary::new();
+        root.insert("resource-fork".into(), fork.into());
+        let mut xml = vec![];
+        plist::Value::from(root).to_writer_xml(&mut xml).unwrap();
+
+        let mut koly = b"koly".to_vec();
+        koly.resize(216, 0);
+        koly.extend((data.len() as u64).to_be_bytes());
+        koly.extend((xml.len() as u64).to_be_bytes());
+        koly.resize(TRAILER_LEN as usize, 0);
+        [data, xml, koly].concat()
+    }
+
+    #[test]
+    fn chunks() -> Result<()> {
+        let disk: Vec<u8> = (0..8 * SECTOR).map(|i| (i * 7 % 251) as u8).collect();
+        let (mut image, partitions) = Disk::open(std::io::Cursor::new(udif(&disk)))?;
+        assert_eq!(partitions[0].name, "disk image (Apple_HFS : 0)");
+        let mut all = vec![];
+        image.read_to_end(&mut all)?;
+        assert_eq!(all.len() as u64, 16 * SECTOR);
+        assert_eq!(all[..disk.len()], disk);
+        assert!(all[disk.len()..].iter().all(|b| *b == 0));
+        assert_eq!(read_at(&mut image, 2000, 100)?, disk[2000..2100]);
+        // overlapping matches
+        assert_eq!(adc_decompress(&[0x81, b'a', b'b', 0x08, 1], 7)?, b"abababa");
+        Ok(())
+    }
+
+    #[test]
+    fn crafted_block_tables() -> Result<()> {
+        let mish = |kind: u32, sector: u64, count: u64| {
+            let mut mish = b"mish".to_vec();
+            mish.resize(200, 0);
+            mish.extend(1u32.to_be_bytes());
+            mish.extend(kind.to_be_bytes());
+            mish.extend(0u32.to_be_bytes());
+            for v in [sector, count, 1, 16] {
+                mish.extend(v.to_be_bytes());
+            }
+            mish
+        };
+        assert_eq!(block_table(&mish(CHUNK_ZLIB, 0, 8), 0)?[0].len, 8 * SECTOR);
+        // a zlib chunk of 32 TiB
+        let err = block_table(&mish(CHUNK_ZLIB, 0, 1 << 36), 0).unwrap_err();
+        assert!(err.to_string().contains("disk image chunk of"), "{err}");
+        // but zeros can be that many
+        assert!(block_table(&mish(CHUNK_ZERO, 0, 1 << 36), 0).is_ok());
+        for (sector, count) in [
+            (u64::MAX / SECTOR + 1, 1),
+            (0, u64::MAX / SECTOR + 1),
+            (u64::MAX / SECTOR, 1),
+        ] {
+            let err = block_table(&mish(CHUNK_ZERO, sector, count), 0).unwrap_err();
+            assert_eq!(err.to_string(), "invalid disk image block table");
+        }
+        let err = block_table(&mish(CHUNK_ZERO, 0, 1), u64::MAX).unwrap_err();
+        assert_eq!(err.to_string(), "invalid disk image block table");
+
+        let chunk = Chunk {
+            kind: CHUNK_ZLIB,
+            disk_offset: 0,
+            len: 4,
+            data_offset: 0,
+            data_len: 0,
+        };
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], Default::default());
+        zlib.write_all(&[1; 100])?;
+        assert_eq!(chunk.decompress(&zlib.finish()?)?, [1; 4]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hfs_image() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.dmg");
+        std::fs::write(&path, udif(&hfs::tests::volume()))?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let r = loop_adapt(&DmgAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:docs/Read Me.txt: readme\nPREFIX:link.txt: linked\nPREFIX:top.txt: top\n"
+        );
+        Ok(())
+    }
+}
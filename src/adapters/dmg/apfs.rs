@@ -0,0 +1,557 @@
+//! APFS containers. The latest container superblock is taken from the checkpoint area, its object
+//! map gives the volume superblocks, and each volume's object map gives the nodes of its file
+//! system tree. Directory records, inodes and file extents of that tree make up the file list.
+//! Encrypted volumes and files compressed by the file system are skipped.
+use super::super::iso::{Extent, ImageFile, read_at};
+use super::Volume;
+use crate::adapters::le::{u16_at, u32_at, u64_at, uint};
+use anyhow::*;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+
+const NX_MAGIC: &[u8] = b"NXSB";
+const APFS_MAGIC: &[u8] = b"APSB";
+const OBJECT_TYPE_NX_SUPERBLOCK: u32 = 1;
+const BTNODE_ROOT: u16 = 1;
+const BTNODE_LEAF: u16 = 2;
+const BTNODE_FIXED_KV_SIZE: u16 = 4;
+const BTREE_INFO_LEN: usize = 40;
+const NODE_HEADER_LEN: usize = 56;
+const MAX_TREE_DEPTH: u16 = 32;
+const OMAP_VAL_DELETED: u32 = 1;
+const APFS_INCOMPAT_CASE_INSENSITIVE: u64 = 1;
+const APFS_INCOMPAT_NORMALIZATION_INSENSITIVE: u64 = 8;
+const APFS_FS_UNENCRYPTED: u64 = 1;
+const APFS_TYPE_INODE: u8 = 3;
+const APFS_TYPE_FILE_EXTENT: u8 = 8;
+const APFS_TYPE_DIR_REC: u8 = 9;
+const INO_EXT_TYPE_DSTREAM: u8 = 8;
+const ROOT_DIR_INO_NUM: u64 = 2;
+const DT_DIR: u16 = 4;
+const DT_REG: u16 = 8;
+const UF_COMPRESSED: u32 = 0x20;
+const OBJ_ID_MASK: u64 = 0x0fff_ffff_ffff_ffff;
+const EXTENT_LEN_MASK: u64 = 0x00ff_ffff_ffff_ffff;
+
+const TRUNCATED: &str = "truncated APFS object";
+
+/// the checksum of every APFS object, over all of it but the checksum itself
+fn fletcher64(block: &[u8]) -> u64 {
+    let (mut low, mut high) = (0u64, 0u64);
+    for word in block[8..].chunks_exact(4) {
+        low = (low + uint(word)) % 0xffffffff;
+        high = (high + low) % 0xffffffff;
+    }
+    let check_low = 0xffffffff - (low + high) % 0xffffffff;
+    let check_high = 0xffffffff - (low + check_low) % 0xffffffff;
+    (check_high << 32) | check_low
+}
+
+struct Container<'r, R> {
+    disk: &'r mut R,
+    base: u64,
+    block_size: u64,
+}
+
+/// physical addresses of virtual objects, by object id
+type ObjectMap = HashMap<u64, u64>;
+
+impl<R: Read + Seek> Container<'_, R> {
+    /// the offset on the disk of the block at `address`
+    fn offset(&self, address: u64) -> Result<u64> {
+        address
+            .checked_mul(self.block_size)
+            .and_then(|offset| offset.checked_add(self.base))
+            .with_context(|| format!("invalid APFS block address {address}"))
+    }
+
+    fn block(&mut self, address: u64) -> Result<Vec<u8>> {
+        let offset = self.offset(address)?;
+        read_at(self.disk, offset, self.block_size as usize)
+    }
+
+    /// the (key, value) pairs of the leaves of the B-tree at `root`. The child pointers of
+    /// trees of virtual objects go through `omap`
+    fn tree(
+        &mut self,
+        root: u64,
+        omap: Option<&ObjectMap>,
+        fixed: (usize, usize),
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut records = vec![];
+        let mut pending = vec![(root, MAX_TREE_DEPTH)];
+        let mut seen = HashSet::new();
+        while let Some((oid, depth_left)) = pending.pop() {
+            if !seen.insert(oid) || depth_left == 0 {
+                bail!("invalid APFS B-tree");
+            }
+            let address = match omap {
+                Some(omap) => *omap
+                    .get(&oid)
+                    .with_context(|| format!("APFS object {oid} not in the object map"))?,
+                None => oid,
+            };
+            let node = self.block(address)?;
+            let (flags, level, count) = (
+                u16_at(&node, 32).context(TRUNCATED)?,
+                u16_at(&node, 34).context(TRUNCATED)?,
+                u32_at(&node, 36).context(TRUNCATED)?,
+            );
+            let toc = NODE_HEADER_LEN + u16_at(&node, 40).context(TRUNCATED)? as usize;
+            let keys = toc + u16_at(&node, 42).context(TRUNCATED)? as usize;
+            let values = if flags & BTNODE_ROOT != 0 {
+                node.len() - BTREE_INFO_LEN
+            } else {
+                node.len()
+            };
+            let is_leaf = flags & BTNODE_LEAF != 0 || level == 0;
+            let mut children = vec![];
+            for i in 0..count as usize {
+                let (key, value) = if flags & BTNODE_FIXED_KV_SIZE != 0 {
+                    let entry = node
+                        .get(toc + 4 * i..toc + 4 * i + 4)
+                        .context("invalid APFS node")?;
+                    let (k, v) = (
+                        u16_at(entry, 0).context(TRUNCATED)? as usize,
+                        u16_at(entry, 2).context(TRUNCATED)? as usize,
+                    );
+                    let value_len = if is_leaf { fixed.1 } else { 8 };
+                    (
+                        (keys + k, fixed.0),
+                        (
+                            values.checked_sub(v).context("invalid APFS node")?,
+                            value_len,
+                        ),
+                    )
+                } else {
+                    let entry = node
+                        .get(toc + 8 * i..toc + 8 * i + 8)
+                        .context("invalid APFS node")?;
+                    let v = u16_at(entry, 4).context(TRUNCATED)? as usize;
+                    (
+                        (
+                            keys + u16_at(entry, 0).context(TRUNCATED)? as usize,
+                            u16_at(entry, 2).context(TRUNCATED)? as usize,
+                        ),
+                        (
+                            values.checked_sub(v).context("invalid APFS node")?,
+                            u16_at(entry, 6).context(TRUNCATED)? as usize,
+                        ),
+                    )
+                };
+                let key = node
+                    .get(key.0..key.0 + key.1)
+                    .context("invalid APFS node")?;
+                let value = node
+                    .get(value.0..value.0 + value.1)
+                    .context("invalid APFS node")?;
+                if is_leaf {
+                    records.push((key.to_vec(), value.to_vec()));
+                } else {
+                    children.push((u64_at(value, 0).context(TRUNCATED)?, depth_left - 1));
+                }
+            }
+            // in key order
+            pending.extend(children.into_iter().rev());
+        }
+        Ok(records)
+    }
+
+    /// the latest version of every object in the object map at `address`
+    fn object_map(&mut self, address: u64) -> Result<ObjectMap> {
+        let omap = self.block(address)?;
+        let mut latest: HashMap<u64, (u64, u64)> = HashMap::new();
+        for (key, value) in self.tree(u64_at(&omap, 48).context(TRUNCATED)?, None, (16, 16))? {
+            let (oid, xid) = (
+                u64_at(&key, 0).context(TRUNCATED)?,
+                u64_at(&key, 8).context(TRUNCATED)?,
+            );
+            if u32_at(&value, 0).context(TRUNCATED)? & OMAP_VAL_DELETED != 0 {
+                continue;
+            }
+            if latest.get(&oid).is_none_or(|(old, _)| *old < xid) {
+                latest.insert(oid, (xid, u64_at(&value, 8).context(TRUNCATED)?));
+            }
+        }
+        Ok(latest
+            .into_iter()
+            .map(|(oid, (_, paddr))| (oid, paddr))
+            .collect())
+    }
+
+    /// the container superblock with the highest transaction id
+    fn superblock(&mut self, first: Vec<u8>) -> Result<Vec<u8>> {
+        let (desc_blocks, desc_base) = (
+            u32_at(&first, 104).context(TRUNCATED)?,
+            u64_at(&first, 112).context(TRUNCATED)?,
+        );
+        let mut best = first;
+        // a checkpoint area that is a tree instead of contiguous blocks is rare, use block 0 then
+        if desc_blocks & 0x8000_0000 != 0 {
+            return Ok(best);
+        }
+        for i in 0..desc_blocks.min(1024) as u64 {
+            let block = self.block(desc_base.saturating_add(i))?;
+            if &block[32..36] == NX_MAGIC
+                && u32_at(&block, 24).context(TRUNCATED)? & 0xffff == OBJECT_TYPE_NX_SUPERBLOCK
+                && u64_at(&block, 0).context(TRUNCATED)? == fletcher64(&block)
+                && u64_at(&block, 16).context(TRUNCATED)? > u64_at(&best, 16).context(TRUNCATED)?
+            {
+                best = block;
+            }
+        }
+        Ok(best)
+    }
+
+    fn volume(&mut self, superblock: &[u8]) -> Result<Option<Volume>> {
+        let name_bytes = &superblock[704..960];
+        let name = String::from_utf8_lossy(
+            &name_bytes[..name_bytes.iter().position(|b| *b == 0).unwrap_or(256)],
+        )
+        .into_owned();
+        if u64_at(superblock, 264).context(TRUNCATED)? & APFS_FS_UNENCRYPTED == 0 {
+            warn!("skipping the encrypted APFS volume {name}");
+            return Ok(None);
+        }
+        let incompat = u64_at(superblock, 56).context(TRUNCATED)?;
+        let hashed = incompat
+            & (APFS_INCOMPAT_CASE_INSENSITIVE | APFS_INCOMPAT_NORMALIZATION_INSENSITIVE)
+            != 0;
+        let omap = self.object_map(u64_at(superblock, 128).context(TRUNCATED)?)?;
+        let records = self.tree(
+            u64_at(superblock, 136).context(TRUNCATED)?,
+            Some(&omap),
+            (0, 0),
+        )?;
+
+        // by inode: parent and name of directories, (parent, name) of regular files
+        let mut dirs: HashMap<u64, (u64, String)> = HashMap::new();
+        let mut files: Vec<(u64, String, u64)> = vec![];
+        // by inode: data stream id, modification time, size, bsd flags
+        let mut inodes: HashMap<u64, (u64, i64, u64, u32)> = HashMap::new();
+        // by data stream: (logical offset, length, physical block)
+        let mut extents: HashMap<u64, Vec<(u64, u64, u64)>> = HashMap::new();
+        for (key, value) in &records {
+            if key.len() < 8 {
+                continue;
+            }
+            let header = u64_at(key, 0).context(TRUNCATED)?;
+            let (id, kind) = (header & OBJ_ID_MASK, (header >> 60) as u8);
+            match kind {
+                APFS_TYPE_DIR_REC if value.len() >= 18 => {
+                    let (name_len, name_at) = if hashed {
+                        (u32_at(key, 8).context(TRUNCATED)? as usize & 0x3ff, 12)
+                    } else {
+                        (u16_at(key, 8).context(TRUNCATED)? as usize, 10)
+                    };
+                    let Some(name) = key.get(name_at..name_at + name_len) else {
+                        continue;
+                    };
+                    let name = String::from_utf8_lossy(name)
+                        .trim_end_matches('\0')
+                        .to_string();
+                    let file_id = u64_at(value, 0).context(TRUNCATED)?;
+                    match u16_at(value, 16).context(TRUNCATED)? & 0xf {
+                        DT_DIR => {
+                            dirs.insert(file_id, (id, name));
+                        }
+                        DT_REG => files.push((id, name, file_id)),
+                        _ => {}
+                    }
+                }
+                APFS_TYPE_INODE if value.len() >= 92 => {
+                    inodes.insert(
+                        id,
+                        (
+                            u64_at(value, 8).context(TRUNCATED)?,
+                            (u64_at(value, 24).context(TRUNCATED)? / 1_000_000_000) as i64,
+                            dstream_size(&value[92..]).unwrap_or(0),
+                            u32_at(value, 68).context(TRUNCATED)?,
+                        ),
+                    );
+                }
+                APFS_TYPE_FILE_EXTENT if key.len() >= 16 && value.len() >= 16 => {
+                    extents.entry(id).or_default().push((
+                        u64_at(key, 8).context(TRUNCATED)?,
+                        u64_at(value, 0).context(TRUNCATED)? & EXTENT_LEN_MASK,
+                        u64_at(value, 8).context(TRUNCATED)?,
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let path = |mut dir: u64, name: &str| -> Option<String> {
+            let mut parts = vec![name.to_string()];
+            while dir != ROOT_DIR_INO_NUM {
+                let (parent, name) = dirs.get(&dir)?;
+                parts.push(name.clone());
+                dir = *parent;
+                if parts.len() > dirs.len() + 1 {
+                    return None;
+                }
+            }
+            parts.reverse();
+            Some(parts.join("/"))
+        };
+        let mut out = vec![];
+        for (parent, name, inode) in &files {
+            let Some(path) = path(*parent, name) else {
+                continue;
+            };
+            let Some(&(stream, mtime, size, bsd_flags)) = inodes.get(inode) else {
+                debug!("{path}: no inode");
+                continue;
+            };
+            if bsd_flags & UF_COMPRESSED != 0 {
+                debug!("{path}: skipping file compressed by the file system");
+                continue;
+            }
+            let mut file_extents = vec![];
+            let mut at = 0;
+            let mut runs = extents.get(&stream).cloned().unwrap_or_default();
+            runs.sort();
+            for (logical, len, physical) in runs {
+                if at >= size {
+                    break;
+                }
+                if logical > at {
+                    file_extents.push(Extent {
+                        offset: None,
+                        len: logical - at,
+                    });
+                }
+                file_extents.push(Extent {
+                    offset: match physical {
+                        0 => None,
+                        physical => Some(self.offset(physical)?),
+                    },
+                    len,
+                });
+                at = logical
+                    .checked_add(len)
+                    .context("invalid APFS file extent")?;
+            }
+            if at < size {
+                file_extents.push(Extent {
+                    offset: None,
+                    len: size - at,
+                });
+            }
+            out.push(ImageFile {
+                path,
+                size,
+                mtime_unix: (mtime != 0).then_some(mtime),
+                extents: file_extents,
+            });
+        }
+        Ok(Some((name, out)))
+    }
+}
+
+/// the size of the data stream in the extended fields of an inode
+fn dstream_size(xfields: &[u8]) -> Option<u64> {
+    let count = u16_at(xfields, 0)? as usize;
+    let mut data = 4 + 4 * count;
+    for i in 0..count {
+        let field = xfields.get(4 + 4 * i..8 + 4 * i)?;
+        let len = u16_at(field, 2)? as usize;
+        if field[0] == INO_EXT_TYPE_DSTREAM {
+            return u64_at(xfields, data);
+        }
+        data += len.next_multiple_of(8);
+    }
+    None
+}
+
+/// the volumes of the APFS container at `base`, None if there is none
+pub fn read<R: Read + Seek>(disk: &mut R, base: u64) -> Result<Option<Vec<Volume>>> {
+    let Result::Ok(first) = read_at(disk, base, 4096) else {
+        return Ok(None);
+    };
+    if &first[32..36] != NX_MAGIC {
+        return Ok(None);
+    }
+    let block_size = u32_at(&first, 36).context(TRUNCATED)? as u64;
+    if !(4096..=65536).contains(&block_size) || !block_size.is_power_of_two() {
+        bail!("invalid APFS block size {block_size}");
+    }
+    let mut container = Container {
+        disk,
+        base,
+        block_size,
+    };
+    let first = container.block(0)?;
+    let superblock = container.superblock(first)?;
+    let omap = container.object_map(u64_at(&superblock, 160).context(TRUNCATED)?)?;
+    let mut volumes = vec![];
+    for i in 0..u32_at(&superblock, 180).context(TRUNCATED)?.min(100) as usize {
+        let oid = u64_at(&superblock, 184 + 8 * i).context(TRUNCATED)?;
+        if oid == 0 {
+            continue;
+        }
+        let Some(address) = omap.get(&oid) else {
+            warn!("APFS volume {oid} not in the object map");
+            continue;
+        };
+        let volume = container.block(*address)?;
+        if &volume[32..36] != APFS_MAGIC {
+            warn!("APFS volume {oid} without a volume superblock");
+            continue;
+        }
+        volumes.extend(container.volume(&volume)?);
+    }
+    Ok(Some(volumes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const BLOCK: usize = 4096;
+
+    /// a root leaf node with variable size keys and values
+    fn leaf(records: &[(Vec<u8>, Vec<u8>)], fixed: bool) -> Vec<u8> {
+        let mut node = vec![0; BLOCK];
+        let flags = BTNODE_ROOT | BTNODE_LEAF | if fixed { BTNODE_FIXED_KV_SIZE } else { 0 };
+        node[32..34].copy_from_slice(&flags.to_le_bytes());
+        node[36..40].copy_from_slice(&(records.len() as u32).to_le_bytes());
+        let toc_len = records.len() * if fixed { 4 } else { 8 };
+        node[42..44].copy_from_slice(&(toc_len as u16).to_le_bytes());
+        let (mut key_at, mut value_end) = (0, 0);
+        for (i, (key, value)) in records.iter().enumerate() {
+            let keys = NODE_HEADER_LEN + toc_len;
+            node[keys + key_at..keys + key_at + key.len()].copy_from_slice(key);
+            value_end += value.len();
+            let values = BLOCK - BTREE_INFO_LEN;
+            node[values - value_end..values - value_end + value.len()].copy_from_slice(value);
+            let toc = NODE_HEADER_LEN + i * toc_len / records.len();
+            let entry: Vec<u8> = if fixed {
+                [key_at as u16, value_end as u16]
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect()
+            } else {
+                [key_at, key.len(), value_end, value.len()]
+                    .iter()
+                    .flat_map(|v| (*v as u16).to_le_bytes())
+                    .collect()
+            };
+            node[toc..toc + entry.len()].copy_from_slice(&entry);
+            key_at += key.len();
+        }
+        node
+    }
+
+    fn omap(tree: u64) -> Vec<u8> {
+        let mut block = vec![0; BLOCK];
+        block[48..56].copy_from_slice(&tree.to_le_bytes());
+        block
+    }
+
+    fn omap_entry(oid: u64, paddr: u64) -> (Vec<u8>, Vec<u8>) {
+        let key = [oid.to_le_bytes(), 1u64.to_le_bytes()].concat();
+        let value = [0u64.to_le_bytes(), paddr.to_le_bytes()].concat();
+        (key, value)
+    }
+
+    fn key(id: u64, kind: u8) -> Vec<u8> {
+        (id | (kind as u64) << 60).to_le_bytes().to_vec()
+    }
+
+    fn drec(parent: u64, name: &str, id: u64, dt: u16) -> (Vec<u8>, Vec<u8>) {
+        let mut k = key(parent, APFS_TYPE_DIR_REC);
+        k.extend((name.len() as u32 + 1).to_le_bytes());
+        k.extend(name.as_bytes());
+        k.push(0);
+        let mut value = id.to_le_bytes().to_vec();
+        value.extend([0; 8]);
+        value.extend(dt.to_le_bytes());
+        (k, value)
+    }
+
+    fn inode(id: u64, size: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut value = vec![0; 92];
+        value[8..16].copy_from_slice(&id.to_le_bytes());
+        value[24..32].copy_from_slice(&1_700_000_000_000_000_000u64.to_le_bytes());
+        value.extend(1u16.to_le_bytes());
+        value.extend(40u16.to_le_bytes());
+        value.extend([INO_EXT_TYPE_DSTREAM, 0]);
+        value.extend(40u16.to_le_bytes());
+        value.extend(size.to_le_bytes());
+        value.extend([0; 32]);
+        (key(id, APFS_TYPE_INODE), value)
+    }
+
+    fn extent(id: u64, block: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut k = key(id, APFS_TYPE_FILE_EXTENT);
+        k.extend(0u64.to_le_bytes());
+        let value = [(BLOCK as u64).to_le_bytes(), block.to_le_bytes(), [0; 8]].concat();
+        (k, value)
+    }
+
+    #[test]
+    fn container() -> Result<()> {
+        let mut nx = vec![0; BLOCK];
+        nx[32..36].copy_from_slice(NX_MAGIC);
+        nx[36..40].copy_from_slice(&(BLOCK as u32).to_le_bytes());
+        nx[160..168].copy_from_slice(&1u64.to_le_bytes());
+        nx[180..184].copy_from_slice(&1u32.to_le_bytes());
+        nx[184..192].copy_from_slice(&1026u64.to_le_bytes());
+
+        let mut apsb = vec![0; BLOCK];
+        apsb[32..36].copy_from_slice(APFS_MAGIC);
+        apsb[56..64].copy_from_slice(&APFS_INCOMPAT_NORMALIZATION_INSENSITIVE.to_le_bytes());
+        apsb[128..136].copy_from_slice(&4u64.to_le_bytes());
+        apsb[136..144].copy_from_slice(&1027u64.to_le_bytes());
+        apsb[264..272].copy_from_slice(&APFS_FS_UNENCRYPTED.to_le_bytes());
+        apsb[704..708].copy_from_slice(b"Test");
+
+        let fs_tree = leaf(
+            &[
+                drec(ROOT_DIR_INO_NUM, "docs", 16, DT_DIR),
+                drec(ROOT_DIR_INO_NUM, "top.txt", 17, DT_REG),
+                drec(16, "Read Me.txt", 18, DT_REG),
+                inode(17, 3),
+                inode(18, 6),
+                extent(17, 8),
+                extent(18, 7),
+            ],
+            false,
+        );
+        let mut image = [
+            nx,
+            omap(2),
+            leaf(&[omap_entry(1026, 3)], true),
+            apsb,
+            omap(5),
+            leaf(&[omap_entry(1027, 6)], true),
+            fs_tree,
+        ]
+        .concat();
+        image.extend(b"readme");
+        image.resize(8 * BLOCK, 0);
+        image.extend(b"top");
+        image.resize(9 * BLOCK, 0);
+
+        let volumes = read(&mut std::io::Cursor::new(&image), 0)?.unwrap();
+        assert_eq!(volumes.len(), 1);
+        let (name, files) = &volumes[0];
+        assert_eq!(name, "Test");
+        let mut files: Vec<_> = files
+            .iter()
+            .map(|f| {
+                let start = f.extents[0].offset.unwrap() as usize;
+                (f.path.as_str(), &image[start..start + f.size as usize])
+            })
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [("docs/Read Me.txt", &b"readme"[..]), ("top.txt", b"top")]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+//! HFS+ and HFSX volumes. Files are listed from the leaf nodes of the catalog B-tree, their data
+//! fork extents beyond the first eight are in the extents overflow B-tree. Files compressed by
+//! the file system (decmpfs) keep their data in a resource fork or attribute and are skipped.
+use super::super::iso::{Extent, ImageFile, read_at};
+use crate::adapters::le::{u16_be_at, u32_be_at, u64_be_at};
+use anyhow::*;
+use log::*;
+use std::collections::HashMap;
+use std::io::{Read, Seek};
+
+const HEADER_OFFSET: u64 = 1024;
+const ROOT_PARENT_ID: u32 = 1;
+const ROOT_FOLDER_ID: u32 = 2;
+const EXTENTS_FILE_ID: u32 = 3;
+const CATALOG_FILE_ID: u32 = 4;
+const FOLDER_RECORD: u16 = 1;
+const FILE_RECORD: u16 = 2;
+const LEAF_NODE: u8 = 0xff;
+/// seconds between 1904 and 1970
+const HFS_EPOCH_OFFSET: i64 = 2082844800;
+const UF_COMPRESSED: u8 = 0x20;
+const S_IFMT: u16 = 0o170000;
+const S_IFREG: u16 = 0o100000;
+/// where the targets of file hard links are kept, as iNode<special>
+const PRIVATE_FOLDER: &str = "\0\0\0\0HFS+ Private Data";
+/// hidden folders and files of the file system itself
+const HIDDEN: &[&str] = &[
+    PRIVATE_FOLDER,
+    ".HFS+ Private Directory Data\r",
+    ".journal",
+    ".journal_info_block",
+];
+
+const TRUNCATED: &str = "truncated HFS+ record";
+
+/// the 80 byte HFSPlusForkData
+struct Fork {
+    size: u64,
+    total_blocks: u32,
+    /// (start block, block count) of the first eight extents
+    extents: Vec<(u32, u32)>,
+}
+
+impl Fork {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let field = |at| u32_be_at(data, at).context(TRUNCATED);
+        let mut extents = vec![];
+        for i in 0..8 {
+            let (start, count) = (field(16 + 8 * i)?, field(20 + 8 * i)?);
+            if count > 0 {
+                extents.push((start, count));
+            }
+        }
+        Ok(Fork {
+            size: u64_be_at(data, 0).context(TRUNCATED)?,
+            total_blocks: field(12)?,
+            extents,
+        })
+    }
+}
+
+struct Volume<'r, R> {
+    disk: &'r mut R,
+    base: u64,
+    block_size: u64,
+    /// extents beyond the first eight of data forks, by file id and start block
+    overflow: HashMap<u32, Vec<(u32, u32, u32)>>,
+}
+
+impl<R: Read + Seek> Volume<'_, R> {
+    /// (start block, block count) of all extents of a data fork
+    fn fork_blocks(&self, file_id: u32, fork: &Fork) -> Vec<(u32, u32)> {
+        let mut blocks = fork.extents.clone();
+        // as u64, the block counts of a crafted volume can add up to more than a u32
+        let mut found: u64 = blocks.iter().map(|(_, count)| *count as u64).sum();
+        if found < fork.total_blocks as u64
+            && let Some(more) = self.overflow.get(&file_id)
+        {
+            for (start_block, start, count) in more {
+                if *start_block as u64 == found {
+                    blocks.push((*start, *count));
+                    found += *count as u64;
+                }
+            }
+        }
+        blocks
+    }
+
+    fn file_extents(&self, blocks: &[(u32, u32)], size: u64) -> Result<Vec<Extent>> {
+        let mut remaining = size;
+        let mut extents = vec![];
+        for (start, count) in blocks {
+            if remaining == 0 {
+                break;
+            }
+            // the block size is a u32, so these products fit
+            let len = (*count as u64 * self.block_size).min(remaining);
+            let offset = (*start as u64 * self.block_size)
+                .checked_add(self.base)
+                .context("invalid HFS+ extent")?;
+            extents.push(Extent {
+                offset: Some(offset),
+                len,
+            });
+            remaining -= len;
+        }
+        Ok(extents)
+    }
+
+    fn read_fork(&mut self, file_id: u32, fork: &Fork) -> Result<Vec<u8>> {
+        let blocks = self.fork_blocks(file_id, fork);
+        let mut data = vec![];
+        for extent in self.file_extents(&blocks, fork.size)? {
+            data.extend(read_at(
+                self.disk,
+                extent.offset.unwrap(),
+                extent.len as usize,
+            )?);
+        }
+        Ok(data)
+    }
+}
+
+/// the (key, data) records of the leaf nodes of a B-tree file
+fn leaf_records(tree: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    // volumes without extents overflow
+    if tree.is_empty() {
+        return Ok(vec![]);
+    }
+    let header = tree.get(14..14 + 106).context("truncated B-tree header")?;
+    let mut node = u32_be_at(header, 10).context(TRUNCATED)?;
+    let node_size = u16_be_at(header, 18).context(TRUNCATED)? as usize;
+    if node_size < 512 {
+        bail!("invalid B-tree node size {node_size}");
+    }
+    let mut records = vec![];
+    let mut seen = 0;
+    while node != 0 {
+        seen += 1;
+        if seen > tree.len() / node_size {
+            bail!("cycle in the B-tree leaf nodes");
+        }
+        let start = node as usize * node_size;
+        let data = tree
+            .get(start..start + node_size)
+            .context("B-tree node beyond the end of the file")?;
+        if data[8] != LEAF_NODE {
+            bail!("B-tree leaf list reaches a node of kind {}", data[8] as i8);
+        }
+        for i in 0..u16_be_at(data, 10).context(TRUNCATED)? as usize {
+            // the record offsets are at the end of the node, backwards
+            let offset = node_size
+                .checked_sub(2 * (i + 1))
+                .and_then(|at| u16_be_at(data, at))
+                .context("more B-tree records than fit the node")?
+                as usize;
+            let key_len =
+                u16_be_at(data, offset).context("B-tree record beyond the end of the node")?;
+            let key_end = offset + 2 + key_len as usize;
+            let key = data
+                .get(offset + 2..key_end)
+                .context("truncated B-tree key")?;
+            records.push((key, &data[key_end..]));
+        }
+        node = u32_be_at(data, 0).context(TRUNCATED)?;
+    }
+    Ok(records)
+}
+
+fn name(key: &[u8]) -> Result<String> {
+    let len = u16_be_at(key, 4).context("truncated catalog key")? as usize;
+    let units: Vec<u16> = key
+        .get(6..6 + 2 * len)
+        .context("truncated catalog key")?
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    // a slash in a name is a colon to the Finder
+    Ok(String::from_utf16_lossy(&units).replace('/', ":"))
+}
+
+/// the name and files of the HFS+ volume at `base`, None if there is none
+pub fn read<R: Read + Seek>(disk: &mut R, base: u64) -> Result<Option<(String, Vec<ImageFile>)>> {
+    // small partitions at the end of the disk
+    let Result::Ok(header) = read_at(disk, base + HEADER_OFFSET, 512) else {
+        return Ok(None);
+    };
+    if &header[..2] != b"H+" && &header[..2] != b"HX" {
+        return Ok(None);
+    }
+    let block_size = u32_be_at(&header, 40).context(TRUNCATED)? as u64;
+    if !block_size.is_power_of_two() || block_size < 512 {
+        bail!("invalid HFS+ block size {block_size}");
+    }
+    let mut volume = Volume {
+        disk,
+        base,
+        block_size,
+        overflow: HashMap::new(),
+    };
+    let extents_tree = volume.read_fork(EXTENTS_FILE_ID, &Fork::parse(&header[192..272])?)?;
+    for (key, data) in leaf_records(&extents_tree)? {
+        // only data forks, type 0
+        if key.len() < 10 || key[0] != 0 || data.len() < 64 {
+            continue;
+        }
+        let file_id = u32_be_at(key, 2).context(TRUNCATED)?;
+        let more = volume.overflow.entry(file_id).or_default();
+        let mut start_block = u32_be_at(key, 6).context(TRUNCATED)?;
+        for i in 0..8 {
+            let start = u32_be_at(data, 8 * i).context(TRUNCATED)?;
+            let count = u32_be_at(data, 8 * i + 4).context(TRUNCATED)?;
+            if count > 0 {
+                more.push((start_block, start, count));
+                start_block = start_block
+                    .checked_add(count)
+                    .context("invalid HFS+ extents overflow record")?;
+            }
+        }
+    }
+    for more in volume.overflow.values_mut() {
+        more.sort();
+    }
+    let catalog = volume.read_fork(CATALOG_FILE_ID, &Fork::parse(&header[272..352])?)?;
+
+    let mut volume_name = String::new();
+    // id to parent and name
+    let mut folders: HashMap<u32, (u32, String)> = HashMap::new();
+    // parent, name, record
+    let mut files: Vec<(u32, String, &[u8])> = vec![];
+    for (key, data) in leaf_records(&catalog)? {
+        if key.len() < 6 || data.len() < 2 {
+            continue;
+        }
+        let parent = u32_be_at(key, 0).context(TRUNCATED)?;
+        match u16_be_at(data, 0).context(TRUNCATED)? {
+            FOLDER_RECORD if data.len() >= 12 => {
+                let name = name(key)?;
+                if parent == ROOT_PARENT_ID {
+                    volume_name = name.clone();
+                }
+                folders.insert(u32_be_at(data, 8).context(TRUNCATED)?, (parent, name));
+            }
+            FILE_RECORD if data.len() >= 248 => files.push((parent, name(key)?, data)),
+            _ => {}
+        }
+    }
+    let private_folder = folders
+        .iter()
+        .find(|(_, (parent, name))| *parent == ROOT_FOLDER_ID && name == PRIVATE_FOLDER)
+        .map(|(id, _)| *id);
+    let link_targets: HashMap<&str, &[u8]> = files
+        .iter()
+        .filter(|(parent, _, _)| Some(*parent) == private_folder)
+        .map(|(_, name, data)| (name.as_str(), *data))
+        .collect();
+
+    let path = |mut folder: u32, name: &str| -> Option<String> {
+        let mut parts = vec![name.to_string()];
+        while folder != ROOT_FOLDER_ID {
+            let (parent, name) = folders.get(&folder)?;
+            if *parent == ROOT_FOLDER_ID && HIDDEN.contains(&name.as_str()) {
+                return None;
+            }
+            parts.push(name.clone());
+            folder = *parent;
+            if parts.len() > folders.len() + 1 {
+                return None;
+            }
+        }
+        if parts.len() == 1 && HIDDEN.contains(&name) {
+            return None;
+        }
+        parts.reverse();
+        Some(parts.join("/"))
+    };
+
+    let mut out = vec![];
+    for (parent, name, mut data) in files.iter().map(|(p, n, d)| (*p, n, *d)) {
+        let Some(path) = path(parent, name) else {
+            continue;
+        };
+        if &data[48..56] == b"hlnkhfs+" {
+            let target = format!("iNode{}", u32_be_at(data, 44).context(TRUNCATED)?);
+            match link_targets.get(target.as_str()) {
+                Some(target) => data = target,
+                None => {
+                    debug!("{path}: hard link to missing {target}");
+                    continue;
+                }
+            }
+        }
+        let mode = u16_be_at(data, 42).context(TRUNCATED)?;
+        if mode != 0 && mode & S_IFMT != S_IFREG {
+            // symlinks, devices
+            continue;
+        }
+        if data[41] & UF_COMPRESSED != 0 {
+            debug!("{path}: skipping file compressed by the file system");
+            continue;
+        }
+        let fork = Fork::parse(&data[88..168])?;
+        let blocks = volume.fork_blocks(u32_be_at(data, 8).context(TRUNCATED)?, &fork);
+        let mtime = u32_be_at(data, 16).context(TRUNCATED)?;
+        out.push(ImageFile {
+            path,
+            size: fork.size,
+            mtime_unix: (mtime != 0).then(|| mtime as i64 - HFS_EPOCH_OFFSET),
+            extents: volume.file_extents(&blocks, fork.size)?,
+        });
+    }
+    Ok(Some((volume_name, out)))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const BLOCK: usize = 4096;
+
+    fn catalog_key(parent: u32, name: &str) -> Vec<u8> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let mut key = ((6 + 2 * units.len()) as u16).to_be_bytes().to_vec();
+        key.extend(parent.to_be_bytes());
+        key.extend((units.len() as u16).to_be_bytes());
+        key.extend(units.iter().flat_map(|u| u.to_be_bytes()));
+        key
+    }
+
+    fn folder(parent: u32, name: &str, id: u32) -> Vec<u8> {
+        let mut record = catalog_key(parent, name);
+        let mut data = vec![0; 88];
+        data[..2].copy_from_slice(&FOLDER_RECORD.to_be_bytes());
+        data[8..12].copy_from_slice(&id.to_be_bytes());
+        record.extend(data);
+        record
+    }
+
+    fn file(parent: u32, name: &str, id: u32, block: u32, size: u64) -> Vec<u8> {
+        let mut record = catalog_key(parent, name);
+        let mut data = vec![0; 248];
+        data[..2].copy_from_slice(&FILE_RECORD.to_be_bytes());
+        data[8..12].copy_from_slice(&id.to_be_bytes());
+        data[16..20].copy_from_slice(&((HFS_EPOCH_OFFSET + 1_700_000_000) as u32).to_be_bytes());
+        data[42..44].copy_from_slice(&(S_IFREG | 0o644).to_be_bytes());
+        data[88..96].copy_from_slice(&size.to_be_bytes());
+        data[100..104].copy_from_slice(&1u32.to_be_bytes());
+        data[104..108].copy_from_slice(&block.to_be_bytes());
+        data[108..112].copy_from_slice(&1u32.to_be_bytes());
+        record.extend(data);
+        record
+    }
+
+    /// a volume with its catalog in blocks 1 and 2 and the file contents after that
+    pub fn volume() -> Vec<u8> {
+        let mut image = vec![0; 7 * BLOCK];
+        let header = &mut image[HEADER_OFFSET as usize..];
+        header[..2].copy_from_slice(b"H+");
+        header[40..44].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+        let catalog_fork = &mut header[272..352];
+        catalog_fork[..8].copy_from_slice(&(2 * BLOCK as u64).to_be_bytes());
+        catalog_fork[12..16].copy_from_slice(&2u32.to_be_bytes());
+        catalog_fork[16..20].copy_from_slice(&1u32.to_be_bytes());
+        catalog_fork[20..24].copy_from_slice(&2u32.to_be_bytes());
+
+        let tree_header = &mut image[BLOCK + 14..];
+        tree_header[10..14].copy_from_slice(&1u32.to_be_bytes());
+        tree_header[18..20].copy_from_slice(&(BLOCK as u16).to_be_bytes());
+
+        let hlink = {
+            let mut record = file(ROOT_FOLDER_ID, "link.txt", 21, 0, 0);
+            let data = record.len() - 248;
+            record[data + 44..data + 48].copy_from_slice(&99u32.to_be_bytes());
+            record[data + 48..data + 56].copy_from_slice(b"hlnkhfs+");
+            record
+        };
+        let records = [
+            folder(ROOT_PARENT_ID, "Test", ROOT_FOLDER_ID),
+            folder(ROOT_FOLDER_ID, "docs", 16),
+            folder(ROOT_FOLDER_ID, PRIVATE_FOLDER, 17),
+            file(ROOT_FOLDER_ID, "top.txt", 18, 3, 3),
+            file(16, "Read Me.txt", 19, 4, 6),
+            file(17, "iNode99", 20, 5, 6),
+            hlink,
+        ];
+        let leaf = &mut image[2 * BLOCK..3 * BLOCK];
+        leaf[8] = LEAF_NODE;
+        leaf[10..12].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        let mut offset = 14;
+        for (i, record) in records.iter().enumerate() {
+            leaf[offset..offset + record.len()].copy_from_slice(record);
+            leaf[BLOCK - 2 * (i + 1)..BLOCK - 2 * i]
+                .copy_from_slice(&(offset as u16).to_be_bytes());
+            offset += record.len();
+        }
+        for (block, content) in [(3, "top"), (4, "readme"), (5, "linked")] {
+            image[block * BLOCK..block * BLOCK + content.len()].copy_from_slice(content.as_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn catalog() -> Result<()> {
+        let image = volume();
+        let (name, files) = read(&mut std::io::Cursor::new(&image), 0)?.unwrap();
+        assert_eq!(name, "Test");
+        let mut files: Vec<_> = files
+            .iter()
+            .map(|f| {
+                let start = f.extents[0].offset.unwrap() as usize;
+                (f.path.as_str(), &image[start..start + f.size as usize])
+            })
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                ("docs/Read Me.txt", &b"readme"[..]),
+                ("link.txt", b"linked"),
+                ("top.txt", b"top"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn crafted_leaf_nodes() {
+        let leaf = |count: u16, offset: u16| {
+            let mut image = volume();
+            let leaf = &mut image[2 * BLOCK..3 * BLOCK];
+            leaf[10..12].copy_from_slice(&count.to_be_bytes());
+            leaf[BLOCK - 2..].copy_from_slice(&offset.to_be_bytes());
+            read(&mut std::io::Cursor::new(&image), 0)
+                .err()
+                .map(|e| e.to_string())
+        };
+        // the offsets table runs into the records, so this may fail on either
+        assert!(leaf(u16::MAX, 14).is_some_and(|e| e.contains("B-tree record")));
+        assert_eq!(
+            leaf(1, BLOCK as u16 - 1).as_deref(),
+            Some("B-tree record beyond the end of the node")
+        );
+    }
+}
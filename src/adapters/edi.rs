@@ -0,0 +1,305 @@
+//! EDI interchanges, ANSI X12 and UN/EDIFACT. Every interchange picks its own separators (in the
+//! fixed length ISA segment or the UNA service string), they are replaced with the usual ones so
+//! the same pattern finds `BEG*00*SA` or `BGM+220` in any file. Segments of a transaction set
+//! (ST..SE) or message (UNH..UNT) are prefixed with its type and control number.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["edi", "x12", "edifact"];
+
+/// the ISA segment has fixed width fields, so its separators are at fixed offsets
+const ISA_LEN: usize = 106;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "edi".to_owned(),
+        version: 1,
+        description: "Splits X12 and EDIFACT interchanges into one segment per line with the standard separators, prefixed with the transaction set or message type".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/EDI-X12".to_owned()),
+            FileMatcher::MimeType("application/EDIFACT".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EdiAdapter;
+
+impl EdiAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for EdiAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Standard {
+    X12,
+    Edifact,
+}
+
+struct Syntax {
+    element: char,
+    component: char,
+    terminator: char,
+    /// EDIFACT's escape character
+    release: Option<char>,
+}
+
+impl Standard {
+    /// the separators written in the output
+    fn output_syntax(self) -> Syntax {
+        match self {
+            Standard::X12 => Syntax {
+                element: '*',
+                component: '>',
+                terminator: '~',
+                release: None,
+            },
+            Standard::Edifact => Syntax {
+                element: '+',
+                component: ':',
+                terminator: '\'',
+                release: Some('?'),
+            },
+        }
+    }
+}
+
+/// elements of components
+type Segment = Vec<Vec<String>>;
+
+/// the segments up to and including the one with `last_tag`, and the rest of the text
+fn segments<'a>(text: &'a str, syntax: &Syntax, last_tag: &str) -> (Vec<Segment>, &'a str) {
+    let mut segments = vec![];
+    let mut segment: Segment = vec![vec![String::new()]];
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        // line breaks between segments
+        let at_start = segment.len() == 1 && segment[0][0].is_empty();
+        let element = segment.last_mut().unwrap();
+        if Some(c) == syntax.release {
+            if let Some((_, next)) = chars.next() {
+                element.last_mut().unwrap().push(next);
+            }
+        } else if c == syntax.element {
+            segment.push(vec![String::new()]);
+        } else if c == syntax.component {
+            element.push(String::new());
+        } else if c == syntax.terminator {
+            let done = std::mem::replace(&mut segment, vec![vec![String::new()]]);
+            let is_last = done[0][0] == last_tag;
+            segments.push(done);
+            if is_last {
+                return (segments, &text[i + c.len_utf8()..]);
+            }
+        } else if !(c.is_whitespace() && at_start) {
+            element.last_mut().unwrap().push(c);
+        }
+    }
+    if segment.len() > 1 || !segment[0][0].is_empty() {
+        segments.push(segment);
+    }
+    (segments, "")
+}
+
+fn format_segment(segment: &Segment, syntax: &Syntax) -> String {
+    let escape = |value: &str| match syntax.release {
+        Some(release) => value
+            .chars()
+            .flat_map(|c| {
+                let special = [syntax.element, syntax.component, syntax.terminator, release];
+                special
+                    .contains(&c)
+                    .then_some(release)
+                    .into_iter()
+                    .chain([c])
+            })
+            .collect(),
+        None => value.to_string(),
+    };
+    let element = |components: &Vec<String>| {
+        components
+            .iter()
+            .map(|c| escape(c))
+            .collect::<Vec<_>>()
+            .join(&syntax.component.to_string())
+    };
+    let elements: Vec<String> = segment.iter().map(element).collect();
+    // trailing empty elements are optional
+    let len = elements
+        .iter()
+        .rposition(|e| !e.is_empty())
+        .map_or(0, |i| i + 1);
+    elements[..len].join(&syntax.element.to_string())
+}
+
+/// the lines of all interchanges in the file
+fn convert(text: &str) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    let mut rest = text.trim_start_matches('\u{feff}');
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        let (standard, syntax, body) = if rest.starts_with("ISA") {
+            let isa: Vec<char> = rest.chars().take(ISA_LEN).collect();
+            if isa.len() < ISA_LEN {
+                return Err(format_err!("truncated ISA segment"));
+            }
+            let syntax = Syntax {
+                element: isa[3],
+                component: isa[104],
+                terminator: isa[105],
+                release: None,
+            };
+            (Standard::X12, syntax, rest)
+        } else if let Some(una) = rest.strip_prefix("UNA") {
+            let una: Vec<char> = una.chars().take(6).collect();
+            if una.len() < 6 {
+                return Err(format_err!("truncated UNA segment"));
+            }
+            let syntax = Syntax {
+                component: una[0],
+                element: una[1],
+                release: Some(una[3]).filter(|c| *c != ' '),
+                terminator: una[5],
+            };
+            let skip: usize = una.iter().map(|c| c.len_utf8()).sum();
+            (Standard::Edifact, syntax, &rest[3 + skip..])
+        } else if rest.starts_with("UNB") {
+            (Standard::Edifact, Standard::Edifact.output_syntax(), rest)
+        } else if lines.is_empty() {
+            return Err(format_err!("not an X12 or EDIFACT interchange"));
+        } else {
+            return Err(format_err!("unexpected data after the interchange"));
+        };
+        let (last_tag, set_start, set_end) = match standard {
+            Standard::X12 => ("IEA", "ST", "SE"),
+            Standard::Edifact => ("UNZ", "UNH", "UNT"),
+        };
+        let output = standard.output_syntax();
+        let (interchange, next) = segments(body, &syntax, last_tag);
+        let mut set: Option<String> = None;
+        for segment in &interchange {
+            let tag = segment[0][0].as_str();
+            let component = |element: usize, component: usize| {
+                segment
+                    .get(element)
+                    .and_then(|e| e.get(component))
+                    .map_or("", |c| c.as_str())
+            };
+            if tag == set_start {
+                set = Some(match standard {
+                    // ST*850*0001
+                    Standard::X12 => format!("{} {}", component(1, 0), component(2, 0)),
+                    // UNH+1+ORDERS:D:96A:UN
+                    Standard::Edifact => format!("{} {}", component(2, 0), component(1, 0)),
+                });
+            }
+            let line = format_segment(segment, &output);
+            lines.push(match &set {
+                Some(set) => format!("{set}: {line}"),
+                None => line,
+            });
+            if tag == set_end {
+                set = None;
+            }
+        }
+        rest = next;
+    }
+    Ok(lines)
+}
+
+#[async_trait]
+impl WritingFileAdapter for EdiAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        // EDIFACT level B and X12's extended character sets are rare, latin-1 covers the rest
+        let text = match std::str::from_utf8(&data) {
+            Result::Ok(text) => text.into(),
+            Err(_) => {
+                encoding_rs::WINDOWS_1252
+                    .decode_without_bom_handling(&data)
+                    .0
+            }
+        };
+        for line in convert(&text)? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn x12() -> Result<()> {
+        let file = "ISA|00|          |00|          |ZZ|SENDER         |ZZ|RECEIVER       \
+            |240102|1200|U|00401|000000001|0|P|^!\n\
+            GS|PO|SENDER|RECEIVER|20240102|1200|1|X|004010!\n\
+            ST|850|0001!\nBEG|00|SA|4500001||20240102!\nPO1|1|10|EA|9.95||VP|WIDGET^BLUE!\n\
+            SE|4|0001!\nGE|1|1!\nIEA|1|000000001!\n";
+        let lines = convert(file)?;
+        assert_eq!(
+            lines[2..5],
+            [
+                "850 0001: ST*850*0001",
+                "850 0001: BEG*00*SA*4500001**20240102",
+                "850 0001: PO1*1*10*EA*9.95**VP*WIDGET>BLUE",
+            ]
+        );
+        assert_eq!(lines[6..], ["GE*1*1", "IEA*1*000000001"]);
+        Ok(())
+    }
+
+    #[test]
+    fn edifact() -> Result<()> {
+        let file = "UNA|#.\\ ~UNB#UNOC|3#SENDER#RECEIVER#240102|1200#1~\n\
+            UNH#1#ORDERS|D|96A|UN~BGM#220#PO 1\\#2~FTX#AAI+++#special: handle with care~\
+            UNT#4#1~UNZ#1#1~";
+        assert_eq!(
+            convert(file)?,
+            [
+                "UNB+UNOC:3+SENDER+RECEIVER+240102:1200+1",
+                "ORDERS 1: UNH+1+ORDERS:D:96A:UN",
+                "ORDERS 1: BGM+220+PO 1#2",
+                "ORDERS 1: FTX+AAI?+?+?++special?: handle with care",
+                "ORDERS 1: UNT+4+1",
+                "UNZ+1+1",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use object::read::macho::{FatArch, MachOFatFile32, MachOFatFile64};
+use object::{FileKind, Object, ObjectSection, SectionKind};
+use std::io::{Read, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["exe", "dll", "sys", "efi", "so", "dylib", "o", "ko", "elf"];
+
+/// shortest printable run reported as a string
+const MIN_STRING_LEN: usize = 4;
+/// code sections contain many short accidental runs, only report longer ones there
+const MIN_CODE_STRING_LEN: usize = 8;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "executable".to_owned(),
+        version: 1,
+        description: "Lists the sections, imports and exports of ELF, PE and Mach-O binaries and the printable strings in each section, prefixed with the section name".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-executable".to_owned()),
+            FileMatcher::MimeType("application/x-sharedlib".to_owned()),
+            FileMatcher::MimeType("application/x-mach-binary".to_owned()),
+            FileMatcher::MimeType("application/vnd.microsoft.portable-executable".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        // most trees contain lots of binaries that are better skipped, like rg does
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExecutableAdapter;
+
+impl ExecutableAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ExecutableAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// printable ASCII runs of at least `min_len` bytes
+pub(super) fn ascii_strings(data: &[u8], min_len: usize) -> impl Iterator<Item = &str> {
+    data.split(|b| !(b.is_ascii_graphic() || *b == b' ' || *b == b'\t'))
+        .filter(move |run| run.len() >= min_len)
+        // only ascii bytes are left
+        .map(|run| std::str::from_utf8(run).unwrap_or_default())
+}
+
+/// printable UTF-16LE runs of at least `min_len` characters, as used for most strings in PE files
+pub(super) fn utf16_strings(data: &[u8], min_len: usize) -> Vec<String> {
+    let mut out = vec![];
+    for start in 0..2 {
+        let mut current = String::new();
+        for unit in data.get(start..).unwrap_or_default().chunks_exact(2) {
+            match (unit[0], unit[1]) {
+                (b, 0) if b.is_ascii_graphic() || b == b' ' => current.push(b as char),
+                _ => {
+                    if current.len() >= min_len {
+                        out.push(std::mem::take(&mut current));
+                    }
+                    current.clear();
+                }
+            }
+        }
+        if current.len() >= min_len {
+            out.push(current);
+        }
+    }
+    out
+}
+
+fn dump_object(data: &[u8], line_prefix: &str, s: &mut dyn Write) -> Result<()> {
+    let file = object::File::parse(data)?;
+    writeln!(
+        s,
+        "{line_prefix}format: {:?} {:?}",
+        file.format(),
+        file.architecture()
+    )?;
+    for import in file.imports().unwrap_or_default() {
+        let name = String::from_utf8_lossy(import.name());
+        let library = String::from_utf8_lossy(import.library());
+        if library.is_empty() {
+            writeln!(s, "{line_prefix}import: {name}")?;
+        } else {
+            writeln!(s, "{line_prefix}import: {name} ({library})")?;
+        }
+    }
+    for export in file.exports().unwrap_or_default() {
+        writeln!(
+            s,
+            "{line_prefix}export: {}",
+            String::from_utf8_lossy(export.name())
+        )?;
+    }
+    let is_pe = matches!(file.format(), object::BinaryFormat::Pe);
+    let mut has_sections = false;
+    for section in file.sections() {
+        let name = section.name().unwrap_or("?");
+        if name.is_empty() {
+            continue;
+        }
+        has_sections = true;
+        writeln!(s, "{line_prefix}section: {name} ({} bytes)", section.size())?;
+        if section.kind() == SectionKind::UninitializedData {
+            continue;
+        }
+        let Ok(content) = section.data() else {
+            continue;
+        };
+        let min_len = if section.kind() == SectionKind::Text {
+            MIN_CODE_STRING_LEN
+        } else {
+            MIN_STRING_LEN
+        };
+        for string in ascii_strings(content, min_len) {
+            writeln!(s, "{line_prefix}{name}: {string}")?;
+        }
+        if is_pe {
+            for string in utf16_strings(content, min_len) {
+                writeln!(s, "{line_prefix}{name}: {string}")?;
+            }
+        }
+    }
+    // stripped section headers: fall back to the whole file
+    if !has_sections {
+        for string in ascii_strings(data, MIN_STRING_LEN) {
+            writeln!(s, "{line_prefix}file: {string}")?;
+        }
+    }
+    Ok(())
+}
+
+/// dump every architecture of a universal binary, prefixed with its name
+fn dump_fat<A: FatArch>(
+    data: &[u8],
+    arches: &[A],
+    line_prefix: &str,
+    s: &mut dyn Write,
+) -> Result<()> {
+    for arch in arches {
+        let prefix = format!("{line_prefix}{:?}: ", arch.architecture());
+        dump_object(arch.data(data)?, &prefix, s)?;
+    }
+    Ok(())
+}
+
+fn synchronous_dump_executable(
+    mut inp: impl Read,
+    line_prefix: &str,
+    mut s: impl Write,
+) -> Result<()> {
+    let mut data = vec![];
+    inp.read_to_end(&mut data)?;
+    match FileKind::parse(&*data).context("unknown executable format")? {
+        FileKind::MachOFat32 => {
+            let fat = MachOFatFile32::parse(&*data)?;
+            dump_fat(&data, fat.arches(), line_prefix, &mut s)
+        }
+        FileKind::MachOFat64 => {
+            let fat = MachOFatFile64::parse(&*data)?;
+            dump_fat(&data, fat.arches(), line_prefix, &mut s)
+        }
+        _ => dump_object(&data, line_prefix, &mut s),
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for ExecutableAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let inp_sync = SyncIoBridge::new(inp);
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump_executable(inp_sync, &line_prefix, oup_sync)
+        })
+        .await?
+        .context("in synchronous executable task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            ascii_strings(b"\x00\x01hello world\x7f\xffab\x00long enough", 4).collect::<Vec<_>>(),
+            vec!["hello world", "long enough"]
+        );
+        assert_eq!(
+            utf16_strings(b"\x00h\x00e\x00l\x00l\x00o\x00\x00\x00x\x00", 4),
+            vec!["hello"]
+        );
+    }
+
+    /// the test binary itself is an ELF / PE / Mach-O file
+    #[test]
+    fn own_binary() -> Result<()> {
+        let data = std::fs::read(std::env::current_exe()?)?;
+        let mut out = vec![];
+        synchronous_dump_executable(&data[..], "PREFIX:", &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert!(out.starts_with("PREFIX:format: "));
+        assert!(out.contains("PREFIX:section: "));
+        // string literal in this test, placed in a read-only data section
+        let marker = concat!("executable adapter ", "marker string");
+        assert!(
+            out.lines()
+                .any(|l| l.contains(marker) && !l.starts_with("PREFIX:file: "))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,432 @@
+//! Image metadata: the EXIF (camera, exposure, GPS position), XMP and IPTC metadata and the text
+//! chunks of JPEG, PNG, WebP, TIFF, HEIF/AVIF and TIFF based raw camera images. Only the
+//! structures around the image data are read, never the image itself.
+mod heif;
+mod iptc;
+mod tiff;
+mod xmp;
+
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use tokio::io::AsyncWrite;
+use writing::async_writeln;
+
+static EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "tif", "tiff", "heic", "heif", "avif", "dng", "cr2", "nef",
+    "nrw", "arw", "srf", "sr2", "orf", "rw2", "pef", "srw", "raf",
+];
+
+/// larger metadata values are skipped
+const MAX_VALUE_LEN: usize = 16 << 20;
+/// the most segments or chunks read before giving up on finding metadata
+const MAX_SEGMENTS: usize = 4096;
+const XMP_PNG_KEYWORD: &str = "XML:com.adobe.xmp";
+/// ImageMagick keeps the metadata of PNG files hex encoded in text chunks with these keywords
+const RAW_PROFILE_KEYWORD: &str = "Raw profile type ";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "exif".to_owned(),
+        version: 1,
+        description: "Extracts the EXIF (camera, exposure, GPS position), XMP and IPTC metadata of JPEG, PNG, WebP, TIFF, HEIF/AVIF and raw camera images".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            ["image/jpeg", "image/png", "image/webp", "image/tiff", "image/heif", "image/avif"]
+                .iter()
+                .map(|m| FileMatcher::MimeType(m.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExifAdapter;
+
+impl ExifAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ExifAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// random access to the image
+pub trait ReadAt {
+    /// the `len` bytes at `offset`, None if the input is shorter
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>>;
+}
+
+impl ReadAt for &[u8] {
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let start = usize::try_from(offset).ok()?;
+        self.get(start..start.checked_add(len)?).map(<[u8]>::to_vec)
+    }
+}
+
+impl ReadAt for File {
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        self.seek(SeekFrom::Start(offset)).ok()?;
+        let mut buf = Vec::with_capacity(len.min(MAX_VALUE_LEN));
+        self.take(len as u64).read_to_end(&mut buf).ok()?;
+        (buf.len() == len).then_some(buf)
+    }
+}
+
+fn exif(mut tiff: &[u8], out: &mut Vec<String>) {
+    // some writers keep the APP1 header
+    if tiff.starts_with(b"Exif\0\0") {
+        tiff = &tiff[6..];
+    }
+    tiff::lines(&mut tiff, 0, out);
+}
+
+/// the APP1 (EXIF, XMP), APP13 (IPTC) and comment segments of the JPEG at `start`
+fn jpeg(r: &mut dyn ReadAt, start: u64, out: &mut Vec<String>) -> Option<()> {
+    if r.read_at(start, 2)? != b"\xff\xd8" {
+        return None;
+    }
+    let mut at = start + 2;
+    for _ in 0..MAX_SEGMENTS {
+        let marker = r.read_at(at, 2)?;
+        if marker[0] != 0xff {
+            return None;
+        }
+        match marker[1] {
+            // fill bytes
+            0xff => {
+                at += 1;
+                continue;
+            }
+            // markers without a length
+            0x01 | 0xd0..=0xd7 => {
+                at += 2;
+                continue;
+            }
+            // start of scan: the image data, metadata is before it
+            0xda | 0xd9 => return Some(()),
+            _ => {}
+        }
+        let len = u16::from_be_bytes(r.read_at(at + 2, 2)?.try_into().unwrap()) as u64;
+        if len < 2 {
+            return None;
+        }
+        if matches!(marker[1], 0xe1 | 0xed | 0xfe) {
+            let data = r.read_at(at + 4, len as usize - 2)?;
+            match marker[1] {
+                0xe1 if data.starts_with(b"Exif\0") => exif(&data[6.min(data.len())..], out),
+                0xe1 if data.starts_with(b"http://ns.adobe.com/xap/1.0/\0") => {
+                    xmp::lines(&String::from_utf8_lossy(&data[29..]), out)
+                }
+                0xed if data.starts_with(b"Photoshop 3.0\0") => iptc::photoshop(&data[14..], out),
+                0xfe => {
+                    let comment = String::from_utf8_lossy(&data);
+                    for line in comment.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                        out.push(format!("jpeg: comment: {line}"));
+                    }
+                }
+                _ => {}
+            }
+        }
+        at += 2 + len;
+    }
+    None
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    flate2::read::ZlibDecoder::new(data)
+        .take(MAX_VALUE_LEN as u64)
+        .read_to_end(&mut out)
+        .ok()?;
+    Some(out)
+}
+
+/// a tEXt, zTXt or iTXt chunk as (keyword, text)
+fn png_text(typ: &[u8], data: &[u8]) -> Option<(String, String)> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    let keyword = String::from_utf8_lossy(&data[..nul]).into_owned();
+    let rest = &data[nul + 1..];
+    let latin1 = |b: &[u8]| b.iter().map(|&b| b as char).collect::<String>();
+    let text = match typ {
+        b"tEXt" => latin1(rest),
+        b"zTXt" => latin1(&inflate(rest.get(1..)?)?),
+        _ => {
+            // compression flag and method, then the language and the translated keyword
+            let (compressed, rest) = (*rest.first()? == 1, rest.get(2..)?);
+            let mut parts = rest.splitn(3, |&b| b == 0);
+            let text = parts.nth(2)?;
+            let text = if compressed {
+                inflate(text)?
+            } else {
+                text.to_vec()
+            };
+            String::from_utf8_lossy(&text).into_owned()
+        }
+    };
+    Some((keyword, text))
+}
+
+/// an ImageMagick raw profile: the type, the size and the hex encoded data, on separate lines
+fn raw_profile(kind: &str, text: &str, out: &mut Vec<String>) {
+    let hex: Vec<u8> = text
+        .lines()
+        .skip_while(|l| l.trim().is_empty())
+        .skip(2)
+        .flat_map(|l| l.trim().bytes())
+        .collect();
+    let data: Vec<u8> = hex
+        .chunks_exact(2)
+        .map_while(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok())
+        .collect();
+    match kind {
+        "exif" | "APP1" if data.starts_with(b"Exif") => exif(&data, out),
+        "xmp" => xmp::lines(&String::from_utf8_lossy(&data), out),
+        "iptc" | "8bim" if data.starts_with(b"8BIM") => iptc::photoshop(&data, out),
+        "iptc" => iptc::lines(&data, out),
+        _ => {}
+    }
+}
+
+fn png(r: &mut dyn ReadAt, out: &mut Vec<String>) -> Option<()> {
+    let mut at = 8;
+    for _ in 0..MAX_SEGMENTS {
+        let header = r.read_at(at, 8)?;
+        let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let typ = &header[4..8];
+        if typ == b"IEND" {
+            return Some(());
+        }
+        if matches!(typ, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt") && len <= MAX_VALUE_LEN as u64 {
+            let data = r.read_at(at + 8, len as usize)?;
+            if typ == b"eXIf" {
+                exif(&data, out);
+            } else if let Some((keyword, text)) = png_text(typ, &data) {
+                if keyword == XMP_PNG_KEYWORD {
+                    xmp::lines(&text, out);
+                } else if let Some(kind) = keyword.strip_prefix(RAW_PROFILE_KEYWORD) {
+                    raw_profile(kind, &text, out);
+                } else {
+                    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                        out.push(format!("png: {keyword}: {line}"));
+                    }
+                }
+            }
+        }
+        // length, type, data and crc
+        at += 12 + len;
+    }
+    None
+}
+
+fn webp(r: &mut dyn ReadAt, out: &mut Vec<String>) -> Option<()> {
+    let mut at = 12;
+    for _ in 0..MAX_SEGMENTS {
+        let header = r.read_at(at, 8)?;
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as u64;
+        if matches!(&header[..4], b"EXIF" | b"XMP ") && len <= MAX_VALUE_LEN as u64 {
+            let data = r.read_at(at + 8, len as usize)?;
+            if &header[..4] == b"EXIF" {
+                exif(&data, out);
+            } else {
+                xmp::lines(&String::from_utf8_lossy(&data), out);
+            }
+        }
+        // chunks are padded to an even size
+        at += 8 + len + (len & 1);
+    }
+    None
+}
+
+/// the metadata of the image as lines, by its format
+pub fn metadata_lines(r: &mut dyn ReadAt) -> Vec<String> {
+    let mut out = vec![];
+    let head = r.read_at(0, 12).unwrap_or_default();
+    if head.starts_with(b"\xff\xd8") {
+        jpeg(r, 0, &mut out);
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png(r, &mut out);
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        webp(r, &mut out);
+    } else if head.starts_with(b"II") || head.starts_with(b"MM") {
+        // also the raw formats with their own magic number instead of 42 (ORF, RW2)
+        tiff::lines(r, 0, &mut out);
+    } else if head.get(4..8) == Some(b"ftyp") {
+        for item in heif::items(r) {
+            match item {
+                heif::Item::Exif(tiff) => exif(&tiff, &mut out),
+                heif::Item::Xmp(xml) => xmp::lines(&String::from_utf8_lossy(&xml), &mut out),
+            }
+        }
+    } else if head.starts_with(b"FUJIFILMCCD-RAW") {
+        // the metadata is in the embedded JPEG preview
+        if let Some(offset) = r.read_at(84, 4) {
+            jpeg(
+                r,
+                u32::from_be_bytes(offset.try_into().unwrap()) as u64,
+                &mut out,
+            );
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl WritingFileAdapter for ExifAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let path = ai.filepath_hint.clone();
+        let lines = tokio::task::spawn_blocking(move || -> Result<Vec<String>> {
+            Ok(metadata_lines(&mut File::open(&path)?))
+        })
+        .await??;
+        for line in lines {
+            async_writeln!(oup, "{}{line}", ai.line_prefix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::path::PathBuf;
+
+    /// a big endian TIFF structure with IFD0, an EXIF and a GPS directory
+    fn tiff() -> Vec<u8> {
+        let entry = |tag: u16, typ: u16, count: u32, value: u32| {
+            [
+                &tag.to_be_bytes()[..],
+                &typ.to_be_bytes(),
+                &count.to_be_bytes(),
+                &value.to_be_bytes(),
+            ]
+            .concat()
+        };
+        let ifd = |entries: Vec<Vec<u8>>| {
+            [
+                &(entries.len() as u16).to_be_bytes()[..],
+                &entries.concat(),
+                &[0; 4],
+            ]
+            .concat()
+        };
+        let rationals = |v: &[u32]| v.iter().flat_map(|v| v.to_be_bytes()).collect::<Vec<_>>();
+        [
+            &b"MM\0\x2a\0\0\0\x08"[..],
+            // IFD0 at 8, EXIF at 62, GPS at 92, values at 146
+            &ifd(vec![
+                entry(0x010f, 2, 6, 146),
+                entry(0x0110, 2, 13, 152),
+                entry(0x8769, 4, 1, 62),
+                entry(0x8825, 4, 1, 92),
+            ]),
+            &ifd(vec![entry(0x829a, 5, 1, 166), entry(0x829d, 5, 1, 174)]),
+            &ifd(vec![
+                entry(1, 2, 2, u32::from_be_bytes(*b"N\0\0\0")),
+                entry(2, 5, 3, 182),
+                entry(3, 2, 2, u32::from_be_bytes(*b"W\0\0\0")),
+                entry(4, 5, 3, 206),
+            ]),
+            b"Canon\0Canon EOS 5D\0\0",
+            &rationals(&[1, 200, 28, 10]),
+            &rationals(&[48, 1, 51, 1, 30, 1]),
+            &rationals(&[2, 1, 17, 1, 40, 1]),
+        ]
+        .concat()
+    }
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        [
+            &[0xff, marker][..],
+            &(data.len() as u16 + 2).to_be_bytes(),
+            data,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn jpeg_metadata() {
+        let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+            <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:Rating="5">
+            <dc:description><rdf:Alt><rdf:li xml:lang="x-default">Sunset at the pier</rdf:li></rdf:Alt></dc:description>
+            <dc:subject><rdf:Bag><rdf:li>beach</rdf:li><rdf:li>holiday</rdf:li></rdf:Bag></dc:subject>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let iptc = [
+            &b"\x1c\x02\x19\x00\x05beach"[..],
+            b"\x1c\x02\x78\x00\x0bA long walk",
+        ]
+        .concat();
+        let photoshop = [
+            &b"Photoshop 3.0\08BIM\x04\x04\0\0"[..],
+            &(iptc.len() as u32).to_be_bytes(),
+            &iptc,
+            &[0],
+        ]
+        .concat();
+        let jpeg = [
+            &b"\xff\xd8"[..],
+            &segment(0xe1, &[&b"Exif\0\0"[..], &tiff()].concat()),
+            &segment(
+                0xe1,
+                &[&b"http://ns.adobe.com/xap/1.0/\0"[..], xmp.as_bytes()].concat(),
+            ),
+            &segment(0xed, &photoshop),
+            &segment(0xfe, b"scanned"),
+            &b"\xff\xda\0\x02image data"[..],
+        ]
+        .concat();
+        assert_eq!(
+            metadata_lines(&mut &jpeg[..]),
+            vec![
+                "exif: Make: Canon",
+                "exif: Model: Canon EOS 5D",
+                "exif: ExposureTime: 1/200 s",
+                "exif: FNumber: f/2.8",
+                "gps: 48.858333, -2.294444",
+                "xmp: xmp:Rating: 5",
+                "xmp: dc:description: Sunset at the pier",
+                "xmp: dc:subject: beach",
+                "xmp: dc:subject: holiday",
+                "iptc: Keywords: beach",
+                "iptc: Caption-Abstract: A long walk",
+                "jpeg: comment: scanned",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn png_parameters() -> Result<()> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("exampledir/exif.png");
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let r = ExifAdapter.adapt(a, &d).await?;
+        let text = String::from_utf8(adapted_to_vec(r).await?)?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("PREFIX:png: parameters: (illustration:1.0), 1girl, solo"));
+        assert!(
+            lines[4].starts_with("PREFIX:png: parameters: Steps: 22, Sampler: DPM++ SDE Karras")
+        );
+        Ok(())
+    }
+}
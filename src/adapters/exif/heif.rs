@@ -0,0 +1,176 @@
+//! HEIF and AVIF images (ISO base media file format): the EXIF and XMP items listed in the meta
+//! box, located by its iloc box either in the file or in its idat box.
+use super::{MAX_VALUE_LEN, ReadAt};
+use crate::adapters::le::{u16_be_at, u32_be_at, u64_be_at};
+
+pub enum Item {
+    /// the TIFF structure of the EXIF data
+    Exif(Vec<u8>),
+    Xmp(Vec<u8>),
+}
+
+/// the child boxes in `data` as (type, content)
+fn boxes(mut data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut out = vec![];
+    while data.len() >= 8 {
+        let (size, typ) = (u32_be_at(data, 0).unwrap_or(0) as u64, &data[4..8]);
+        let (header, size) = match size {
+            0 => (8, data.len() as u64),
+            1 => (16, u64_be_at(data, 8).unwrap_or(0)),
+            size => (8, size),
+        };
+        if size < header || size > data.len() as u64 {
+            break;
+        }
+        out.push((typ, &data[header as usize..size as usize]));
+        data = &data[size as usize..];
+    }
+    out
+}
+
+/// the content of the top-level meta box
+fn meta(r: &mut dyn ReadAt) -> Option<Vec<u8>> {
+    let mut at = 0u64;
+    loop {
+        let header = r.read_at(at, 16).or_else(|| r.read_at(at, 8))?;
+        let (size, header_len) = match u32_be_at(&header, 0)? {
+            1 => (u64_be_at(&header, 8)?, 16),
+            // to the end of the file
+            0 => (u64::MAX, 8),
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        if &header[4..8] == b"meta" {
+            let len = (size - header_len).min(MAX_VALUE_LEN as u64) as usize;
+            return r.read_at(at + header_len, len);
+        }
+        at = at.checked_add(size)?;
+    }
+}
+
+/// item id to its type (`Exif`, or the content type of `mime` items)
+fn item_types(iinf: &[u8]) -> Vec<(u32, String)> {
+    let entries_at = if iinf.first() == Some(&0) { 6 } else { 8 };
+    let Some(infes) = iinf.get(entries_at..) else {
+        return vec![];
+    };
+    boxes(infes)
+        .into_iter()
+        .filter(|(typ, _)| *typ == b"infe")
+        .filter_map(|(_, infe)| {
+            let (id, rest) = match infe.first()? {
+                2 => (u16_be_at(infe, 4)? as u32, infe.get(8..)?),
+                3 => (u32_be_at(infe, 4)?, infe.get(10..)?),
+                _ => return None,
+            };
+            let typ = rest.get(..4)?;
+            let typ = if typ == b"mime" {
+                // name and content type, NUL terminated
+                let mut strings = rest[4..].split(|&b| b == 0);
+                strings.next();
+                String::from_utf8_lossy(strings.next()?).into_owned()
+            } else {
+                String::from_utf8_lossy(typ).into_owned()
+            };
+            Some((id, typ))
+        })
+        .collect()
+}
+
+/// (construction method, extents as (offset, length)) of an item
+type Location = (u16, Vec<(u64, u64)>);
+
+fn locations(iloc: &[u8]) -> Option<Vec<(u32, Location)>> {
+    let version = *iloc.first()?;
+    let sizes = u16_be_at(iloc, 4)?;
+    let (offset_size, length_size, base_offset_size) =
+        (sizes >> 12, (sizes >> 8) & 0xf, (sizes >> 4) & 0xf);
+    let index_size = if version > 0 { sizes & 0xf } else { 0 };
+    let mut at = 6;
+    let mut read = |size: u16| -> Option<u64> {
+        let v = match size {
+            0 => 0,
+            2 => u16_be_at(iloc, at)? as u64,
+            4 => u32_be_at(iloc, at)? as u64,
+            8 => u64_be_at(iloc, at)?,
+            _ => return None,
+        };
+        at += size as usize;
+        Some(v)
+    };
+    let count = read(if version < 2 { 2 } else { 4 })?;
+    let mut out = vec![];
+    for _ in 0..count {
+        let id = read(if version < 2 { 2 } else { 4 })? as u32;
+        let method = if version > 0 {
+            read(2)? as u16 & 0xf
+        } else {
+            0
+        };
+        read(2)?; // data reference index
+        let base = read(base_offset_size)?;
+        let extents = read(2)?;
+        let mut location = vec![];
+        for _ in 0..extents {
+            read(index_size)?;
+            let offset = read(offset_size)?;
+            let length = read(length_size)?;
+            location.push((base.checked_add(offset)?, length));
+        }
+        out.push((id, (method, location)));
+    }
+    Some(out)
+}
+
+/// the EXIF and XMP items of the image
+pub fn items(r: &mut dyn ReadAt) -> Vec<Item> {
+    let Some(meta) = meta(r) else {
+        return vec![];
+    };
+    // a full box: version and flags before the children
+    let children = boxes(meta.get(4..).unwrap_or_default());
+    let child = |name: &[u8]| children.iter().find(|(t, _)| *t == name).map(|(_, c)| *c);
+    let (Some(iinf), Some(iloc)) = (child(b"iinf"), child(b"iloc")) else {
+        return vec![];
+    };
+    let idat = child(b"idat").unwrap_or_default();
+    let locations = locations(iloc).unwrap_or_default();
+    let mut out = vec![];
+    for (id, typ) in item_types(iinf) {
+        if typ != "Exif" && typ != "application/rdf+xml" {
+            continue;
+        }
+        let Some((method, extents)) = locations.iter().find(|(i, _)| *i == id).map(|(_, l)| l)
+        else {
+            continue;
+        };
+        let mut data = vec![];
+        for &(offset, length) in extents {
+            if data.len() as u64 + length > MAX_VALUE_LEN as u64 {
+                break;
+            }
+            let extent = match method {
+                0 => r.read_at(offset, length as usize),
+                1 => idat
+                    .get(offset as usize..(offset + length) as usize)
+                    .map(<[u8]>::to_vec),
+                _ => None,
+            };
+            data.extend(extent.unwrap_or_default());
+        }
+        if typ == "Exif" {
+            // the offset of the TIFF header after this field
+            let Some(tiff) = u32_be_at(&data, 0)
+                .and_then(|skip| data.get(4usize.saturating_add(skip as usize)..))
+            else {
+                continue;
+            };
+            out.push(Item::Exif(tiff.to_vec()));
+        } else {
+            out.push(Item::Xmp(data));
+        }
+    }
+    out
+}
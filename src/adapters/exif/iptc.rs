@@ -0,0 +1,95 @@
+//! IPTC IIM records (the captions and keywords of older photo software), in TIFF files and in the
+//! Photoshop resources of JPEG APP13 segments.
+
+/// the datasets of the application record (2) that are text
+static DATASETS: &[(u8, &str)] = &[
+    (5, "ObjectName"),
+    (15, "Category"),
+    (20, "SupplementalCategories"),
+    (25, "Keywords"),
+    (40, "SpecialInstructions"),
+    (55, "DateCreated"),
+    (80, "By-line"),
+    (85, "By-lineTitle"),
+    (90, "City"),
+    (92, "Sub-location"),
+    (95, "Province-State"),
+    (100, "Country-PrimaryLocationCode"),
+    (101, "Country-PrimaryLocationName"),
+    (103, "OriginalTransmissionReference"),
+    (105, "Headline"),
+    (110, "Credit"),
+    (115, "Source"),
+    (116, "CopyrightNotice"),
+    (118, "Contact"),
+    (120, "Caption-Abstract"),
+    (122, "Writer-Editor"),
+];
+/// the id of the IPTC resource among the Photoshop resources
+const IPTC_RESOURCE: u16 = 0x0404;
+
+/// UTF-8 if it is valid, Latin-1 otherwise (the character set is rarely declared)
+fn decode(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.to_string(),
+        Err(_) => data.iter().map(|&b| b as char).collect(),
+    }
+}
+
+pub fn lines(data: &[u8], out: &mut Vec<String>) {
+    let mut at = 0;
+    while let Some(header) = data.get(at..at + 5) {
+        if header[0] != 0x1c {
+            break;
+        }
+        let (record, dataset) = (header[1], header[2]);
+        let mut len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        at += 5;
+        // extended datasets: the high bit is set and the rest is the size of the length
+        if len & 0x8000 != 0 {
+            let size = len & 0x7fff;
+            let Some(bytes) = data.get(at..at + size).filter(|_| size <= 8) else {
+                break;
+            };
+            len = bytes.iter().fold(0, |n, &b| n << 8 | b as usize);
+            at += size;
+        }
+        let Some(value) = data.get(at..at.saturating_add(len)) else {
+            break;
+        };
+        at += len;
+        if record != 2 {
+            continue;
+        }
+        if let Some((_, name)) = DATASETS.iter().find(|(d, _)| *d == dataset) {
+            let value = decode(value);
+            for line in value.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                out.push(format!("iptc: {name}: {line}"));
+            }
+        }
+    }
+}
+
+/// the IPTC records among Photoshop image resources (`8BIM` blocks)
+pub fn photoshop(data: &[u8], out: &mut Vec<String>) {
+    let mut at = 0;
+    while data.get(at..at + 4) == Some(b"8BIM") {
+        let Some(&[id0, id1, name_len]) = data.get(at + 4..at + 7) else {
+            break;
+        };
+        // the pascal string name is padded to an even size
+        let name_size = (1 + name_len as usize + 1) & !1;
+        let size_at = at + 6 + name_size;
+        let Some(size) = data.get(size_at..size_at + 4) else {
+            break;
+        };
+        let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+        let Some(resource) = data.get(size_at + 4..(size_at + 4).saturating_add(size)) else {
+            break;
+        };
+        if u16::from_be_bytes([id0, id1]) == IPTC_RESOURCE {
+            lines(resource, out);
+        }
+        at = size_at + 4 + ((size + 1) & !1);
+    }
+}
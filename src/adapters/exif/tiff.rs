@@ -0,0 +1,312 @@
+//! The image file directories of TIFF files and TIFF based raw camera images, also the format of
+//! the EXIF data in JPEG, PNG, WebP and HEIF images. Only IFD0 and its EXIF and GPS directories
+//! are read, the thumbnail directories are skipped.
+use super::{MAX_VALUE_LEN, ReadAt, iptc, xmp};
+use crate::adapters::le::{u16_ordered_at, u32_ordered_at, u64_ordered_at};
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const XMP: u16 = 0x02bc;
+const IPTC: u16 = 0x83bb;
+const PHOTOSHOP: u16 = 0x8649;
+
+static IFD0_TAGS: &[(u16, &str)] = &[
+    (0x010e, "ImageDescription"),
+    (0x010f, "Make"),
+    (0x0110, "Model"),
+    (0x0131, "Software"),
+    (0x0132, "DateTime"),
+    (0x013b, "Artist"),
+    (0x8298, "Copyright"),
+    (0x9c9b, "XPTitle"),
+    (0x9c9c, "XPComment"),
+    (0x9c9d, "XPAuthor"),
+    (0x9c9e, "XPKeywords"),
+    (0x9c9f, "XPSubject"),
+];
+static EXIF_TAGS: &[(u16, &str)] = &[
+    (0x829a, "ExposureTime"),
+    (0x829d, "FNumber"),
+    (0x8827, "ISOSpeedRatings"),
+    (0x9003, "DateTimeOriginal"),
+    (0x9004, "DateTimeDigitized"),
+    (0x9011, "OffsetTimeOriginal"),
+    (0x920a, "FocalLength"),
+    (0x9286, "UserComment"),
+    (0xa405, "FocalLengthIn35mmFilm"),
+    (0xa420, "ImageUniqueID"),
+    (0xa430, "CameraOwnerName"),
+    (0xa431, "BodySerialNumber"),
+    (0xa433, "LensMake"),
+    (0xa434, "LensModel"),
+    (0xa435, "LensSerialNumber"),
+];
+
+#[derive(Clone, Copy)]
+struct Order {
+    little_endian: bool,
+}
+
+/// values past the end of the data read as 0, the callers only read within the entries and values they got
+impl Order {
+    fn u16(self, b: &[u8], at: usize) -> u16 {
+        u16_ordered_at(b, at, self.little_endian).unwrap_or_default()
+    }
+    fn u32(self, b: &[u8], at: usize) -> u32 {
+        u32_ordered_at(b, at, self.little_endian).unwrap_or_default()
+    }
+    fn u64(self, b: &[u8], at: usize) -> u64 {
+        u64_ordered_at(b, at, self.little_endian).unwrap_or_default()
+    }
+}
+
+struct Entry {
+    tag: u16,
+    typ: u16,
+    count: u32,
+    /// the value itself if it fits, otherwise its offset
+    value: [u8; 4],
+}
+
+fn type_len(typ: u16) -> Option<usize> {
+    Some(match typ {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => return None,
+    })
+}
+
+enum Value {
+    Text(String),
+    Numbers(Vec<f64>),
+    Bytes(Vec<u8>),
+}
+
+struct Ifd<'a> {
+    r: &'a mut dyn ReadAt,
+    base: u64,
+    order: Order,
+}
+
+impl Ifd<'_> {
+    fn entries(&mut self, offset: u32) -> Option<Vec<Entry>> {
+        let at = self.base + offset as u64;
+        let count = self.order.u16(&self.r.read_at(at, 2)?, 0) as usize;
+        let data = self.r.read_at(at + 2, count * 12)?;
+        Some(
+            data.chunks_exact(12)
+                .map(|e| Entry {
+                    tag: self.order.u16(e, 0),
+                    typ: self.order.u16(e, 2),
+                    count: self.order.u32(e, 4),
+                    value: e[8..12].try_into().unwrap(),
+                })
+                .collect(),
+        )
+    }
+
+    fn data(&mut self, entry: &Entry) -> Option<Vec<u8>> {
+        let len = type_len(entry.typ)?.checked_mul(entry.count as usize)?;
+        if len > MAX_VALUE_LEN {
+            return None;
+        }
+        if len <= 4 {
+            return Some(entry.value[..len].to_vec());
+        }
+        let offset = self.order.u32(&entry.value, 0) as u64;
+        self.r.read_at(self.base + offset, len)
+    }
+
+    fn value(&mut self, entry: &Entry) -> Option<Value> {
+        let data = self.data(entry)?;
+        let o = self.order;
+        let n = type_len(entry.typ)?;
+        let numbers =
+            |f: &dyn Fn(&[u8]) -> f64| Value::Numbers(data.chunks_exact(n).map(f).collect());
+        Some(match entry.typ {
+            2 => Value::Text(text(&data)),
+            1 | 7 => Value::Bytes(data),
+            3 => numbers(&|b| o.u16(b, 0) as f64),
+            8 => numbers(&|b| o.u16(b, 0) as i16 as f64),
+            4 | 13 => numbers(&|b| o.u32(b, 0) as f64),
+            9 => numbers(&|b| o.u32(b, 0) as i32 as f64),
+            5 => numbers(&|b| o.u32(b, 0) as f64 / o.u32(b, 4) as f64),
+            10 => numbers(&|b| o.u32(b, 0) as i32 as f64 / o.u32(b, 4) as i32 as f64),
+            6 => numbers(&|b| b[0] as i8 as f64),
+            11 => numbers(&|b| f32::from_bits(o.u32(b, 0)) as f64),
+            12 => numbers(&|b| f64::from_bits(o.u64(b, 0))),
+            _ => return None,
+        })
+    }
+}
+
+/// text without the NUL padding
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+fn utf16(data: &[u8], little_endian: bool) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|u| {
+            if little_endian {
+                u16::from_le_bytes([u[0], u[1]])
+            } else {
+                u16::from_be_bytes([u[0], u[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+fn number(v: f64) -> String {
+    if v.fract() == 0.0 && v.abs() < 1e15 {
+        format!("{}", v as i64)
+    } else {
+        let s = format!("{v:.4}");
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+fn format_value(name: &str, value: Value, order: Order) -> String {
+    match (name, value) {
+        // UTF-16LE whatever the byte order of the file
+        (n, Value::Bytes(b)) if n.starts_with("XP") => utf16(&b, true),
+        ("UserComment", Value::Bytes(b)) => match b.split_at_checked(8) {
+            Some((b"UNICODE\0", rest)) => utf16(rest, order.little_endian),
+            Some((_, rest)) => text(rest),
+            None => text(&b),
+        },
+        ("ExposureTime", Value::Numbers(v)) if v.first().is_some_and(|t| *t > 0.0 && *t < 1.0) => {
+            format!("1/{} s", number((1.0 / v[0]).round()))
+        }
+        ("ExposureTime", Value::Numbers(v)) if !v.is_empty() => format!("{} s", number(v[0])),
+        ("FNumber", Value::Numbers(v)) if !v.is_empty() => format!("f/{}", number(v[0])),
+        ("FocalLength" | "FocalLengthIn35mmFilm", Value::Numbers(v)) if !v.is_empty() => {
+            format!("{} mm", number(v[0]))
+        }
+        (_, Value::Text(t)) => t,
+        (_, Value::Numbers(v)) => v.into_iter().map(number).collect::<Vec<_>>().join(" "),
+        (_, Value::Bytes(b)) => text(&b),
+    }
+}
+
+/// degrees from degrees, minutes and seconds
+fn degrees(v: &Value) -> Option<f64> {
+    match v {
+        Value::Numbers(v) if v.len() == 3 => Some(v[0] + v[1] / 60.0 + v[2] / 3600.0),
+        _ => None,
+    }
+}
+
+fn gps_lines(ifd: &mut Ifd, entries: &[Entry], out: &mut Vec<String>) {
+    let mut get = |tag: u16| {
+        let entry = entries.iter().find(|e| e.tag == tag)?;
+        ifd.value(entry)
+    };
+    let reference = |v: Option<Value>| match v {
+        Some(Value::Text(t)) => t,
+        _ => String::new(),
+    };
+    let (lat_ref, lat) = (reference(get(1)), get(2).as_ref().and_then(degrees));
+    let (lon_ref, lon) = (reference(get(3)), get(4).as_ref().and_then(degrees));
+    if let (Some(lat), Some(lon)) = (lat, lon) {
+        let lat = if lat_ref == "S" { -lat } else { lat };
+        let lon = if lon_ref == "W" { -lon } else { lon };
+        out.push(format!("gps: {lat:.6}, {lon:.6}"));
+    }
+    let below_sea_level = matches!(get(5), Some(Value::Bytes(b)) if b.first() == Some(&1));
+    if let Some(Value::Numbers(alt)) = get(6)
+        && let Some(alt) = alt.first()
+    {
+        let alt = if below_sea_level { -alt } else { *alt };
+        out.push(format!(
+            "gps: altitude: {} m",
+            number((alt * 10.0).round() / 10.0)
+        ));
+    }
+    if let Some(Value::Numbers(t)) = get(7)
+        && t.len() == 3
+    {
+        let date = reference(get(0x1d));
+        let time = format!("{:02}:{:02}:{:02}", t[0] as u32, t[1] as u32, t[2] as u32);
+        out.push(format!("gps: time: {} UTC", [date, time].join(" ").trim()));
+    }
+}
+
+/// the lines of the TIFF structure at `base`. Offsets in it are relative to `base`
+pub fn lines(r: &mut dyn ReadAt, base: u64, out: &mut Vec<String>) -> Option<()> {
+    let header = r.read_at(base, 8)?;
+    let order = match &header[..2] {
+        b"II" => Order {
+            little_endian: true,
+        },
+        b"MM" => Order {
+            little_endian: false,
+        },
+        _ => return None,
+    };
+    let mut ifd = Ifd { r, base, order };
+    let ifd0 = ifd.entries(order.u32(&header, 4))?;
+    let (mut exif, mut gps) = (None, None);
+    for entry in &ifd0 {
+        let pointer = || order.u32(&entry.value, 0);
+        match entry.tag {
+            EXIF_IFD => exif = Some(pointer()),
+            GPS_IFD => gps = Some(pointer()),
+            XMP => {
+                if let Some(data) = ifd.data(entry) {
+                    xmp::lines(&String::from_utf8_lossy(&data), out);
+                }
+            }
+            // often wrongly typed as LONG
+            IPTC => {
+                let len = type_len(entry.typ).unwrap_or(1) * entry.count as usize;
+                if let Some(data) = ifd.data(&Entry {
+                    typ: 7,
+                    count: len as u32,
+                    ..*entry
+                }) {
+                    iptc::lines(&data, out);
+                }
+            }
+            PHOTOSHOP => {
+                if let Some(data) = ifd.data(entry) {
+                    iptc::photoshop(&data, out);
+                }
+            }
+            tag => {
+                if let Some((_, name)) = IFD0_TAGS.iter().find(|(t, _)| *t == tag) {
+                    push_tag(&mut ifd, entry, name, out);
+                }
+            }
+        }
+    }
+    if let Some(entries) = exif.and_then(|offset| ifd.entries(offset)) {
+        for entry in &entries {
+            if let Some((_, name)) = EXIF_TAGS.iter().find(|(t, _)| *t == entry.tag) {
+                push_tag(&mut ifd, entry, name, out);
+            }
+        }
+    }
+    if let Some(entries) = gps.and_then(|offset| ifd.entries(offset)) {
+        gps_lines(&mut ifd, &entries, out);
+    }
+    Some(())
+}
+
+fn push_tag(ifd: &mut Ifd, entry: &Entry, name: &str, out: &mut Vec<String>) {
+    let Some(value) = ifd.value(entry) else {
+        return;
+    };
+    let value = format_value(name, value, ifd.order);
+    for line in value.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        out.push(format!("exif: {name}: {line}"));
+    }
+}
@@ -0,0 +1,77 @@
+//! XMP packets: RDF/XML with the properties as elements or as attributes of rdf:Description.
+//! Every property becomes an `xmp: prefix:name: value` line, array items one line each.
+use quick_xml::events::{BytesStart, Event};
+
+/// names of the RDF structure itself, not of properties
+fn is_syntax(name: &str) -> bool {
+    name.starts_with("rdf:")
+        || name.starts_with("x:")
+        || name.starts_with("xml:")
+        || name.starts_with("xmlns")
+}
+
+fn push(name: &str, value: &str, out: &mut Vec<String>) {
+    for line in value.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        out.push(format!("xmp: {name}: {line}"));
+    }
+}
+
+fn attributes(e: &BytesStart, out: &mut Vec<String>) {
+    for a in e.attributes().with_checks(false).flatten() {
+        let key = String::from_utf8_lossy(a.key.as_ref()).into_owned();
+        if is_syntax(&key) {
+            continue;
+        }
+        if let Ok(value) = a.unescape_value() {
+            push(&key, &value, out);
+        }
+    }
+}
+
+/// the properties of the packet, up to the first xml error
+pub fn lines(xml: &str, out: &mut Vec<String>) {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().check_end_names = false;
+    let mut stack: Vec<String> = vec![];
+    let property = |stack: &[String]| stack.iter().rev().find(|n| !is_syntax(n)).cloned();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                attributes(&e, out);
+                stack.push(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+            }
+            Ok(Event::Empty(e)) => {
+                attributes(&e, out);
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let resource = e
+                    .attributes()
+                    .with_checks(false)
+                    .flatten()
+                    .find(|a| a.key.as_ref() == b"rdf:resource");
+                let name = Some(name)
+                    .filter(|n| !is_syntax(n))
+                    .or_else(|| property(&stack));
+                if let (Some(name), Some(resource)) = (name, resource)
+                    && let Ok(value) = resource.unescape_value()
+                {
+                    push(&name, &value, out);
+                }
+            }
+            Ok(Event::End(_)) => {
+                stack.pop();
+            }
+            Ok(Event::Text(t)) => {
+                if let (Some(name), Ok(text)) = (property(&stack), t.unescape()) {
+                    push(&name, &text, out);
+                }
+            }
+            Ok(Event::CData(t)) => {
+                if let Some(name) = property(&stack) {
+                    push(&name, &String::from_utf8_lossy(&t), out);
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+}
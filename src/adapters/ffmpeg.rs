@@ -1,29 +1,37 @@
 use super::*;
 use super::{custom::map_exe_error, writing::async_writeln};
+use crate::config::CacheConfig;
 use anyhow::*;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::process::Stdio;
 use tokio::io::AsyncWrite;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::process::Command;
 use writing::WritingFileAdapter;
 // todo:
 // maybe todo: read list of extensions from
 // ffmpeg -demuxers | tail -n+5 | awk '{print $2}' | while read demuxer; do echo MUX=$demuxer; ffmpeg -h demuxer=$demuxer | grep 'Common extensions'; done 2>/dev/null
 // but really, the probability of getting useful information from a .flv is low
-static EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "mp3", "ogg", "flac", "webm"];
+pub(crate) static EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "mp3", "ogg", "flac", "webm"];
+/// the adapter that turns video frames into text, see `video_ocr_interval`
+const OCR_ADAPTER: &str = "tesseract";
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "ffmpeg".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Uses ffmpeg to extract video metadata/chapters, subtitles, lyrics, and other metadata"
+            "Uses ffmpeg to extract video metadata/chapters, subtitles (tagged with their language), lyrics, and other metadata"
                 .to_owned(),
-        recurses: false,
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..AdapterCapabilities::runs(&["ffmpeg", "ffprobe"])
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -34,6 +42,11 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    /// `[Parsed_showinfo_1 @ 0x...] n:   3 pts:  90090 pts_time:3.003 ...` for each extracted frame
+    static ref SHOWINFO: Regex = Regex::new(r"Parsed_showinfo.*\bpts_time:\s*([0-9.]+)").unwrap();
+}
+
 #[derive(Default, Clone)]
 pub struct FFmpegAdapter;
 
@@ -48,13 +61,323 @@ impl GetMetadata for FFmpegAdapter {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 struct FFprobeOutput {
+    #[serde(default)]
+    format: FFprobeFormat,
+    #[serde(default)]
     streams: Vec<FFprobeStream>,
+    #[serde(default)]
+    chapters: Vec<FFprobeChapter>,
+}
+#[derive(Serialize, Deserialize, Default)]
+struct FFprobeFormat {
+    format_long_name: Option<String>,
+    duration: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
 }
 #[derive(Serialize, Deserialize)]
-struct FFprobeStream {
+pub(crate) struct FFprobeStream {
     index: i32, // stream index
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+#[derive(Serialize, Deserialize)]
+struct FFprobeChapter {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+impl FFprobeStream {
+    /// the language code of the stream, None if it is not tagged (or `und`)
+    fn language(&self) -> Option<&str> {
+        self.tags
+            .get("language")
+            .map(String::as_str)
+            .filter(|l| !l.is_empty() && *l != "und")
+    }
+}
+
+/// seconds as ffprobe prints them to hh:mm:ss.mmm
+fn timestamp(seconds: &str) -> String {
+    let Some(ms) = seconds
+        .parse::<f64>()
+        .ok()
+        .map(|s| (s * 1000.0).round() as u64)
+    else {
+        return seconds.to_string();
+    };
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// seconds to the `hh:mm:ss` prefix of the text of a frame
+fn frame_time(seconds: f64) -> String {
+    let s = seconds as u64;
+    format!("{:02}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// the times of the frames ffmpeg extracted, from the output of its showinfo filter
+fn frame_times(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter_map(|l| SHOWINFO.captures(l)?[1].parse().ok())
+        .collect()
+}
+
+/// whether subtitles of `stream` are extracted. `languages` selects streams by their language code,
+/// `und` selects those without one
+fn selected(stream: &FFprobeStream, languages: Option<&[String]>) -> bool {
+    let Some(languages) = languages else {
+        return true;
+    };
+    let language = stream.language().unwrap_or("und");
+    languages.iter().any(|l| l.eq_ignore_ascii_case(language))
+}
+
+/// the container metadata, the streams with their tags and the chapters, one per line
+fn metadata_lines(probe: &FFprobeOutput) -> Vec<String> {
+    let mut lines = vec![];
+    let mut tag_lines = |what: &str, tags: &BTreeMap<String, String>| {
+        for (key, value) in tags {
+            for value in value.lines() {
+                lines.push(format!("{what}{key}: {value}"));
+            }
+        }
+    };
+    tag_lines("metadata: ", &probe.format.tags);
+    for stream in &probe.streams {
+        tag_lines(&format!("stream {}: ", stream.index), &stream.tags);
+    }
+    if let Some(format) = &probe.format.format_long_name {
+        lines.push(format!("metadata: format: {format}"));
+    }
+    if let Some(duration) = &probe.format.duration {
+        lines.push(format!("metadata: duration: {}", timestamp(duration)));
+    }
+    for stream in &probe.streams {
+        let kind = [stream.codec_type.as_deref(), stream.codec_name.as_deref()];
+        let kind: Vec<&str> = kind.into_iter().flatten().collect();
+        lines.push(format!("stream {}: {}", stream.index, kind.join(" ")));
+    }
+    for chapter in &probe.chapters {
+        let time = |t: &Option<String>| t.as_deref().map(timestamp).unwrap_or_default();
+        let title = chapter.tags.get("title").map(String::as_str).unwrap_or("");
+        lines.push(format!(
+            "chapter {} --> {}: {title}",
+            time(&chapter.start_time),
+            time(&chapter.end_time)
+        ));
+    }
+    lines
+}
+
+async fn ffprobe(inp_fname: &Path, args: &[&str]) -> Result<FFprobeOutput> {
+    let spawn_fail = |e| map_exe_error(e, "ffprobe", "Make sure you have ffmpeg installed.");
+    let probe = crate::audit::output(
+        Command::new("ffprobe")
+            .args(["-v", "error", "-of", "json"]) // show all errors, use json as output format
+            .args(args)
+            .arg("-i")
+            .arg(inp_fname),
+    )
+    .await
+    .map_err(spawn_fail)?;
+    if !probe.status.success() {
+        return Err(format_err!(
+            "ffprobe failed: {:?}\n{}",
+            probe.status,
+            String::from_utf8_lossy(&probe.stderr)
+        ));
+    }
+    Ok(serde_json::from_slice(&probe.stdout)?)
+}
+
+/// the streams of a media file of a type (`s` for subtitles, `a` for audio, ...)
+pub(crate) async fn streams(inp_fname: &Path, stream_type: &str) -> Result<Vec<FFprobeStream>> {
+    let probe = ffprobe(
+        inp_fname,
+        &[
+            "-select_streams",
+            stream_type, // show only streams of this type
+            "-show_entries",
+            "stream=index,codec_type,codec_name:stream_tags=language,title",
+        ],
+    )
+    .await?;
+    Ok(probe.streams)
+}
+
+/// write the metadata and the subtitles of a media file, returns the number of subtitle streams
+/// (including the ones not selected by `subtitle_languages`)
+pub(crate) async fn write_metadata_and_subtitles(
+    inp_fname: &Path,
+    line_prefix: &str,
+    config: &RgaConfig,
+    mut oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<usize> {
+    let spawn_fail = |e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed.");
+    let subtitle_streams = streams(inp_fname, "s").await?;
+    // file metadata, especially chapter names, in a greppable format
+    let probe = ffprobe(
+        inp_fname,
+        &["-show_format", "-show_streams", "-show_chapters"],
+    )
+    .await?;
+    for line in metadata_lines(&probe) {
+        async_writeln!(oup, "{line_prefix}{line}")?;
+    }
+    let languages = config.subtitle_languages.as_deref();
+    let time_re = Regex::new(r".*\d.*-->.*\d.*").context("invalid subtitle time regex")?;
+    for probe_stream in subtitle_streams.iter().filter(|s| selected(s, languages)) {
+        // extract subtitles
+        let mut cmd = Command::new("ffmpeg");
+        cmd.arg("-hide_banner")
+            .arg("-loglevel")
+            .arg("panic")
+            .arg("-i")
+            .arg(inp_fname)
+            .arg("-map")
+            .arg(format!("0:{}", probe_stream.index)) // 0 for first input
+            .arg("-f")
+            .arg("webvtt")
+            .arg("-");
+        let (mut cmd, spawned) =
+            crate::audit::spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()))
+                .map_err(spawn_fail)?;
+        let stdo = cmd.stdout.as_mut().context("ffmpeg stdout not piped")?;
+        let language = probe_stream
+            .language()
+            .map(|l| format!("{l} "))
+            .unwrap_or_default();
+        let mut time: String = "".to_owned();
+        // rewrite subtitle times so they are shown as a prefix in every line
+        let mut lines = BufReader::new(stdo).lines();
+        while let Some(line) = lines.next_line().await? {
+            // 09:55.195 --> 09:56.730
+            if time_re.is_match(&line) {
+                time = line.to_owned();
+            } else if time.is_empty() {
+                // the WEBVTT header
+                continue;
+            } else if line.is_empty() {
+                async_writeln!(oup)?;
+            } else {
+                async_writeln!(oup, "{line_prefix}{language}{time}: {line}")?;
+            }
+        }
+        let exit = cmd.wait().await?;
+        spawned.exited(&exit);
+        if !exit.success() {
+            let mut stderr_str = String::new();
+            if let Some(mut stderr) = cmd.stderr.take() {
+                use tokio::io::AsyncReadExt as _;
+                let _ = stderr.read_to_string(&mut stderr_str).await;
+            }
+            return Err(format_err!("ffmpeg failed: {:?}\n{}", exit, stderr_str));
+        }
+    }
+    Ok(subtitle_streams.len())
+}
+
+/// the text of a frame image, from the OCR adapter
+async fn ocr(frame: &Path, config: &RgaConfig) -> Result<String> {
+    let config = RgaConfig {
+        adapters: vec![OCR_ADAPTER.to_string()],
+        // the frames are temporary files
+        cache: CacheConfig {
+            disabled: true,
+            ..config.cache.clone()
+        },
+        ..config.clone()
+    };
+    let ai = AdaptInfo {
+        inp: Box::pin(tokio::fs::File::open(frame).await?),
+        filepath_hint: frame.to_owned(),
+        is_real_file: true,
+        file_mtime_unix_ms: None,
+        archive_recursion_depth: 0,
+        line_prefix: String::new(),
+        postprocess: false,
+        config,
+    };
+    let mut text = String::new();
+    crate::preproc::rga_preproc(ai)
+        .await?
+        .read_to_string(&mut text)
+        .await?;
+    Ok(text)
+}
+
+/// OCR a keyframe every `interval` seconds of the first video stream, the text is prefixed with
+/// the time of its frame. Frames with the same text as the one before are skipped (slides)
+async fn write_frame_text(
+    inp_fname: &Path,
+    interval: u32,
+    line_prefix: &str,
+    config: &RgaConfig,
+    mut oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    if streams(inp_fname, "v").await?.is_empty() {
+        return Ok(());
+    }
+    let frames = tempfile::tempdir()?;
+    let output = crate::audit::output(
+        Command::new("ffmpeg")
+            .arg("-hide_banner")
+            .arg("-nostdin")
+            // only decode keyframes, much faster than decoding the whole video
+            .args(["-skip_frame", "nokey"])
+            .arg("-i")
+            .arg(inp_fname)
+            .args(["-map", "0:v:0", "-vf"])
+            .arg(format!(
+                "select='isnan(prev_selected_t)+gte(t-prev_selected_t,{interval})',showinfo"
+            ))
+            .args(["-fps_mode", "vfr"])
+            .arg(frames.path().join("%06d.png"))
+            .stdin(Stdio::null()),
+    )
+    .await
+    .map_err(|e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed."))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("ffmpeg failed extracting frames: {:?}\n{}", output.status, stderr);
+    }
+    let times = frame_times(&stderr);
+    let mut paths = std::fs::read_dir(frames.path())?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    let mut previous = String::new();
+    for (i, frame) in paths.iter().enumerate() {
+        let seconds = times
+            .get(i)
+            .copied()
+            .unwrap_or((i as u64 * interval as u64) as f64);
+        let time = frame_time(seconds);
+        let text = ocr(frame, config)
+            .await
+            .with_context(|| format!("OCR of the frame at {time}"))?;
+        let text = text.trim();
+        if text != previous {
+            for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+                async_writeln!(oup, "{line_prefix}[{time}] {line}")?;
+            }
+        }
+        previous = text.to_string();
+    }
+    Ok(())
 }
 
 #[async_trait]
@@ -64,129 +387,96 @@ impl WritingFileAdapter for FFmpegAdapter {
         _detection_reason: &FileMatcher,
         mut oup: Pin<Box<dyn AsyncWrite + Send>>,
     ) -> Result<()> {
-        let AdaptInfo {
-            is_real_file,
-            filepath_hint,
-            line_prefix,
-            mut inp,
-            ..
-        } = ai;
-
-        // If it's a stream (e.g., inside an archive), we need to buffer it to a temporary file
-        // because we run multiple passes of ffprobe and ffmpeg over the data.
-        let temp_dir;
-        let temp_file_path;
-        let inp_fname = if is_real_file {
-            filepath_hint.clone()
-        } else {
-            temp_dir = tempfile::tempdir()?;
-            let t_path = temp_dir.path().join(filepath_hint.file_name().unwrap_or_else(|| std::ffi::OsStr::new("vid.tmp")));
-            let mut f = tokio::fs::File::create(&t_path).await?;
-            tokio::io::copy(&mut inp, &mut f).await?;
-            temp_file_path = t_path;
-            temp_file_path.clone()
-        };
-
-        let spawn_fail = |e| map_exe_error(e, "ffprobe", "Make sure you have ffmpeg installed.");
-        let subtitle_streams = {
-            let probe = Command::new("ffprobe")
-                .args(vec![
-                    "-v",
-                    "error", // show all errors
-                    "-select_streams",
-                    "s", // show only subtitle streams
-                    "-of",
-                    "json", // use json as output format
-                    "-show_entries",
-                    "stream=index", // show index of subtitle streams
-                ])
-                .arg("-i")
-                .arg(&inp_fname)
-                .output()
-                .await
-                .map_err(spawn_fail)?;
-            if !probe.status.success() {
-                return Err(format_err!(
-                    "ffprobe failed: {:?}\n{}",
-                    probe.status,
-                    String::from_utf8_lossy(&probe.stderr)
-                ));
-            }
-            let p: FFprobeOutput = serde_json::from_slice(&probe.stdout)?;
-            p.streams
-        };
-        {
-            // extract file metadata (especially chapter names in a greppable format)
-            let mut probe = Command::new("ffprobe")
-                .args(vec![
-                    "-v",
-                    "error",
-                    "-show_format",
-                    "-show_streams",
-                    "-of",
-                    "flat",
-                    // "-show_data",
-                    "-show_error",
-                    "-show_programs",
-                    "-show_chapters",
-                    // "-count_frames",
-                    //"-count_packets",
-                ])
-                .arg("-i")
-                .arg(&inp_fname)
-                .stdout(Stdio::piped())
-                .spawn()?;
-            let mut lines = BufReader::new(probe.stdout.as_mut().context("ffprobe stdout not piped")?).lines();
-            while let Some(line) = lines.next_line().await? {
-                let line = line.replace("\\r\\n", "\n").replace("\\n", "\n"); // just unescape newlines
-                async_writeln!(oup, "{line_prefix}metadata: {line}")?;
-            }
-            let exit = probe.wait().await?;
-            if !exit.success() {
-                return Err(format_err!("ffprobe failed: {:?}", exit));
-            }
-        }
-        if !subtitle_streams.is_empty() {
-            let time_re = Regex::new(r".*\d.*-->.*\d.*").context("invalid subtitle time regex")?;
-            for probe_stream in subtitle_streams.iter() {
-                // extract subtitles
-                let mut cmd = Command::new("ffmpeg");
-                cmd.arg("-hide_banner")
-                    .arg("-loglevel")
-                    .arg("panic")
-                    .arg("-i")
-                    .arg(&inp_fname)
-                    .arg("-map")
-                    .arg(format!("0:{}", probe_stream.index)) // 0 for first input
-                    .arg("-f")
-                    .arg("webvtt")
-                    .arg("-");
-                let mut cmd = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().map_err(spawn_fail)?;
-                let stdo = cmd.stdout.as_mut().context("ffmpeg stdout not piped")?;
-                let mut time: String = "".to_owned();
-                // rewrite subtitle times so they are shown as a prefix in every line
-                let mut lines = BufReader::new(stdo).lines();
-                while let Some(line) = lines.next_line().await? {
-                    // 09:55.195 --> 09:56.730
-                    if time_re.is_match(&line) {
-                        time = line.to_owned();
-                    } else if line.is_empty() {
-                        async_writeln!(oup)?;
-                    } else {
-                        async_writeln!(oup, "{line_prefix}{time}: {line}")?;
-                    }
-                }
-                let exit = cmd.wait().await?;
-                if !exit.success() {
-                    let mut stderr_str = String::new();
-                    if let Some(mut stderr) = cmd.stderr.take() {
-                        use tokio::io::AsyncReadExt as _;
-                        let _ = stderr.read_to_string(&mut stderr_str).await;
-                    }
-                    return Err(format_err!("ffmpeg failed: {:?}\n{}", exit, stderr_str));
-                }
-            }
+        // we run multiple passes of ffprobe and ffmpeg over the data, so this adapter has seekable_input
+        // and files in archives are already buffered to a temporary file by preproc
+        write_metadata_and_subtitles(&ai.filepath_hint, &ai.line_prefix, &ai.config, &mut oup)
+            .await?;
+        if let Some(interval) = ai.config.video_ocr_interval {
+            write_frame_text(
+                &ai.filepath_hint,
+                interval.max(1),
+                &ai.line_prefix,
+                &ai.config,
+                &mut oup,
+            )
+            .await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn metadata() -> Result<()> {
+        let probe: FFprobeOutput = serde_json::from_str(
+            r#"{
+                "streams": [
+                    {"index": 0, "codec_type": "video", "codec_name": "h264"},
+                    {"index": 1, "codec_type": "audio", "codec_name": "vorbis", "tags": {"ARTIST": "Someone"}},
+                    {"index": 2, "codec_type": "subtitle", "codec_name": "subrip", "tags": {"language": "ger"}}
+                ],
+                "chapters": [
+                    {"start_time": "0.000000", "end_time": "754.5", "tags": {"title": "Opening"}},
+                    {"start_time": "754.500000", "end_time": "3725.04"}
+                ],
+                "format": {"format_long_name": "Matroska / WebM", "duration": "3725.040000", "tags": {"title": "A film", "comment": "two\nlines"}}
+            }"#,
+        )?;
+        assert_eq!(
+            metadata_lines(&probe),
+            vec![
+                "metadata: comment: two",
+                "metadata: comment: lines",
+                "metadata: title: A film",
+                "stream 1: ARTIST: Someone",
+                "stream 2: language: ger",
+                "metadata: format: Matroska / WebM",
+                "metadata: duration: 01:02:05.040",
+                "stream 0: video h264",
+                "stream 1: audio vorbis",
+                "stream 2: subtitle subrip",
+                "chapter 00:00:00.000 --> 00:12:34.500: Opening",
+                "chapter 00:12:34.500 --> 01:02:05.040: ",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn frames() {
+        let stderr = "Input #0, matroska,webm, from 'lecture.mkv':
+[Parsed_showinfo_1 @ 0x55d0c8a0] config in time_base: 1/1000, frame_rate: 30/1
+[Parsed_showinfo_1 @ 0x55d0c8a0] n:   0 pts:      0 pts_time:0       duration:     33 fmt:yuv420p
+[Parsed_showinfo_1 @ 0x55d0c8a0] n:   1 pts:  10010 pts_time:10.01   duration:     33 fmt:yuv420p
+[Parsed_showinfo_1 @ 0x55d0c8a0] n:   2 pts:3725040 pts_time:3725.04 duration:     33 fmt:yuv420p";
+        let times = frame_times(stderr);
+        assert_eq!(times, vec![0.0, 10.01, 3725.04]);
+        assert_eq!(
+            times.into_iter().map(frame_time).collect::<Vec<_>>(),
+            vec!["00:00:00", "00:00:10", "01:02:05"]
+        );
+    }
+
+    #[test]
+    fn languages() {
+        let stream = |language: Option<&str>| FFprobeStream {
+            index: 0,
+            codec_type: None,
+            codec_name: None,
+            tags: language
+                .map(|l| ("language".to_string(), l.to_string()))
+                .into_iter()
+                .collect(),
+        };
+        let languages = vec!["eng".to_string(), "und".to_string()];
+        assert!(selected(&stream(Some("ger")), None));
+        assert!(selected(&stream(Some("ENG")), Some(&languages)));
+        assert!(!selected(&stream(Some("ger")), Some(&languages)));
+        assert!(selected(&stream(None), Some(&languages)));
+        assert!(selected(&stream(Some("und")), Some(&languages)));
+    }
+}
@@ -0,0 +1,421 @@
+//! Bank statement exports: OFX (and Quicken's .qfx, both the SGML and the XML variant), QIF and
+//! SWIFT MT940. Every transaction becomes one `date amount payee memo` line with the date as
+//! YYYY-MM-DD and the amount with a decimal point, whatever the format wrote.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["ofx", "qfx", "qif", "mt940", "sta"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "finance".to_owned(),
+        version: 1,
+        description: "Converts bank statements (OFX/QFX, QIF, SWIFT MT940) into one `date amount payee memo` line per transaction".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-ofx".to_owned()),
+            FileMatcher::MimeType("application/vnd.intu.qfx".to_owned()),
+            FileMatcher::MimeType("application/qif".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FinanceAdapter;
+
+impl FinanceAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for FinanceAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Default)]
+struct Transaction {
+    date: String,
+    amount: String,
+    payee: String,
+    memo: String,
+}
+
+impl Transaction {
+    fn is_empty(&self) -> bool {
+        self.date.is_empty() && self.amount.is_empty() && self.payee.is_empty()
+    }
+
+    fn line(&self) -> String {
+        [&self.date, &self.amount, &self.payee, &self.memo]
+            .iter()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// `-1.234,5`, `1,234.50` and `1000,` as `-1234.50`, `1234.50` and `1000.00`. The last of `.`
+/// and `,` is the decimal separator unless it is followed by three digits
+fn amount(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | '-'))
+        .collect();
+    let negative = text.starts_with('-');
+    let text = text.trim_matches('-');
+    let (int, fraction) = match text.rfind(['.', ',']) {
+        Some(i) if text.len() - i - 1 != 3 || text[..i].contains(['.', ',']) => {
+            (&text[..i], &text[i + 1..])
+        }
+        _ => (text, ""),
+    };
+    let int: String = int.chars().filter(char::is_ascii_digit).collect();
+    let int = if int.is_empty() { "0" } else { &int };
+    format!("{}{int}.{fraction:0<2}", if negative { "-" } else { "" })
+}
+
+fn iso_date(year: u32, month: u32, day: u32) -> String {
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// two digit years as in Quicken: up to 69 is 20xx
+fn full_year(year: u32, digits: usize) -> u32 {
+    match (digits, year) {
+        (4, y) => y,
+        (_, y) if y < 70 => 2000 + y,
+        (_, y) => 1900 + y,
+    }
+}
+
+/// `20190102120000.000[-5:EST]`
+fn ofx_date(text: &str) -> String {
+    let digits = |r: std::ops::Range<usize>| text.get(r).and_then(|s| s.parse().ok());
+    match (digits(0..4), digits(4..6), digits(6..8)) {
+        (Some(y), Some(m), Some(d)) => iso_date(y, m, d),
+        _ => text.to_string(),
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// OFX 1 is SGML where elements with a value have no end tag, OFX 2 is XML. Reading the value
+/// up to the next tag works for both
+fn ofx(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut transaction: Option<Transaction> = None;
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = rest[start + 1..start + len].trim().to_ascii_uppercase();
+        rest = &rest[start + len + 1..];
+        let value = unescape(rest[..rest.find('<').unwrap_or(rest.len())].trim());
+        match (tag.as_str(), &mut transaction) {
+            ("STMTTRN", _) => transaction = Some(Transaction::default()),
+            ("/STMTTRN", Some(t)) => {
+                lines.push(t.line());
+                transaction = None;
+            }
+            // the account of the statement, not the other side of a transfer
+            ("ACCTID", None) => lines.push(format!("account: {value}")),
+            ("DTPOSTED", Some(t)) => t.date = ofx_date(&value),
+            ("TRNAMT", Some(t)) => t.amount = amount(&value),
+            ("NAME", Some(t)) if t.payee.is_empty() => t.payee = value,
+            ("MEMO", Some(t)) => t.memo = value,
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// `12/31/2019`, `1/ 2'05`, `2019-12-31` or `31.12.2019`
+fn qif_date(text: &str) -> String {
+    let parts: Vec<&str> = text
+        .split(['/', '\'', '-', '.'])
+        .map(|p| p.trim())
+        .collect();
+    let numbers: Vec<u32> = parts.iter().filter_map(|p| p.parse().ok()).collect();
+    let [a, b, c] = numbers[..] else {
+        return text.to_string();
+    };
+    if parts[0].len() == 4 {
+        iso_date(a, b, c)
+    } else if text.contains('.') {
+        iso_date(full_year(c, parts[2].len()), b, a)
+    } else {
+        iso_date(full_year(c, parts[2].len()), a, b)
+    }
+}
+
+fn qif(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut transaction = Transaction::default();
+    let mut in_account = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        let Some(code) = line.chars().next() else {
+            continue;
+        };
+        let value = line[code.len_utf8()..].trim();
+        match code {
+            '!' => in_account = value.eq_ignore_ascii_case("Account"),
+            'N' if in_account => lines.push(format!("account: {value}")),
+            '^' if in_account => in_account = false,
+            _ if in_account => {}
+            'D' => transaction.date = qif_date(value),
+            'T' | 'U' => transaction.amount = amount(value),
+            'P' => transaction.payee = value.to_string(),
+            'M' => transaction.memo = value.to_string(),
+            '^' => {
+                if !transaction.is_empty() {
+                    lines.push(transaction.line());
+                }
+                transaction = Transaction::default();
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// the fields of the messages, with their continuation lines
+fn mt940_fields(text: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut fields: Vec<(&str, Vec<&str>)> = vec![];
+    let mut in_field = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        // the tag is 2 digits and an optional letter
+        let tag = line
+            .strip_prefix(':')
+            .and_then(|rest| rest.split_once(':'))
+            .filter(|(tag, _)| (2..=3).contains(&tag.len()));
+        if let Some((tag, value)) = tag {
+            fields.push((tag, vec![value]));
+            in_field = true;
+        } else if line.starts_with(['-', '{', '}']) {
+            // end of a message or the headers of the next one
+            in_field = false;
+        } else if in_field && let Some((_, lines)) = fields.last_mut() {
+            lines.push(line);
+        }
+    }
+    fields
+}
+
+/// `YYMMDD[MMDD](C|D|RC|RD)[funds code]amount`, the rest is the reference
+fn mt940_statement_line(value: &str) -> Transaction {
+    let date = match (
+        value.get(0..2).and_then(|s| s.parse().ok()),
+        value.get(2..4).and_then(|s| s.parse().ok()),
+        value.get(4..6).and_then(|s| s.parse().ok()),
+    ) {
+        (Some(y), Some(m), Some(d)) => iso_date(full_year(y, 2), m, d),
+        _ => String::new(),
+    };
+    let mut rest = value.get(6..).unwrap_or_default();
+    if rest
+        .as_bytes()
+        .get(..4)
+        .is_some_and(|b| b.iter().all(u8::is_ascii_digit))
+    {
+        rest = &rest[4..];
+    }
+    let (debit, rest) = if let Some(rest) = rest.strip_prefix("RC") {
+        (true, rest)
+    } else if let Some(rest) = rest.strip_prefix("RD") {
+        (false, rest)
+    } else if let Some(rest) = rest.strip_prefix('D') {
+        (true, rest)
+    } else {
+        (false, rest.strip_prefix('C').unwrap_or(rest))
+    };
+    let rest = rest
+        .strip_prefix(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(rest);
+    let len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ',')
+        .unwrap_or(rest.len());
+    Transaction {
+        date,
+        amount: amount(&format!("{}{}", if debit { "-" } else { "" }, &rest[..len])),
+        ..Default::default()
+    }
+}
+
+/// the information to the account owner: either free text or `?nn` subfields, with the payee in
+/// 32 and 33 and the purpose in 20 to 29
+fn mt940_details(lines: &[&str], transaction: &mut Transaction) {
+    let joined = lines.concat();
+    if !joined.contains('?') {
+        transaction.memo = lines.join(" ");
+        return;
+    }
+    let mut memo = String::new();
+    for field in joined.split('?').skip(1) {
+        let (code, value) = field.split_at(field.len().min(2));
+        match code.parse::<u32>() {
+            Result::Ok(20..=29 | 60..=63) => memo.push_str(value),
+            Result::Ok(32 | 33) => transaction.payee.push_str(value),
+            _ => {}
+        }
+    }
+    transaction.memo = memo;
+}
+
+fn mt940(text: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut transaction: Option<Transaction> = None;
+    for (tag, value) in mt940_fields(text) {
+        match tag {
+            "61" => {
+                lines.extend(transaction.take().map(|t| t.line()));
+                transaction = Some(mt940_statement_line(value[0]));
+            }
+            "86" => {
+                if let Some(t) = &mut transaction {
+                    mt940_details(&value, t);
+                }
+            }
+            tag => {
+                lines.extend(transaction.take().map(|t| t.line()));
+                if tag == "25" {
+                    lines.push(format!("account: {}", value.join(" ")));
+                }
+            }
+        }
+    }
+    lines.extend(transaction.map(|t| t.line()));
+    lines
+}
+
+fn convert(data: &[u8]) -> Result<Vec<String>> {
+    // OFX 1 and QIF files are usually in the codepage of the bank's system
+    let text = match std::str::from_utf8(data) {
+        Result::Ok(text) => text.into(),
+        Err(_) => {
+            encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(data)
+                .0
+        }
+    };
+    let start = text.trim_start_matches('\u{feff}').trim_start();
+    if start.starts_with("OFXHEADER") || text.contains("<OFX>") {
+        Ok(ofx(&text))
+    } else if start.starts_with("!Type:") || start.starts_with("!Account") {
+        Ok(qif(&text))
+    } else if text.contains(":61:") || text.contains(":20:") {
+        Ok(mt940(&text))
+    } else {
+        Err(format_err!("not an OFX, QIF or MT940 file"))
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for FinanceAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        for line in convert(&data)? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ofx_sgml() -> Result<()> {
+        let file = "OFXHEADER:100\nDATA:OFXSGML\nCHARSET:1252\n\n<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>\
+            <BANKACCTFROM><BANKID>123<ACCTID>987654</BANKACCTFROM><BANKTRANLIST>\n\
+            <STMTTRN>\n<TRNTYPE>DEBIT\n<DTPOSTED>20190102120000.000[-5:EST]\n<TRNAMT>-12.5\n\
+            <NAME>COFFEE &amp; CO\n<MEMO>card 1234\n</STMTTRN>\n\
+            <STMTTRN><TRNTYPE>XFER<DTPOSTED>20190103<TRNAMT>1,250.00<PAYEE><NAME>ACME</PAYEE>\
+            <BANKACCTTO><ACCTID>555</BANKACCTTO></STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>";
+        assert_eq!(
+            convert(file.as_bytes())?,
+            vec![
+                "account: 987654",
+                "2019-01-02 -12.50 COFFEE & CO card 1234",
+                "2019-01-03 1250.00 ACME",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn qif_and_mt940() -> Result<()> {
+        let file = "!Account\nNChecking\nTBank\n^\n!Type:Bank\nD12/31'19\nT-1,234.56\nPLandlord\n\
+            MRent\n^\nD31.01.2020\nU100\nPEmployer\n^\n";
+        assert_eq!(
+            convert(file.as_bytes())?,
+            vec![
+                "account: Checking",
+                "2019-12-31 -1234.56 Landlord Rent",
+                "2020-01-31 100.00 Employer",
+            ]
+        );
+
+        let file = "{1:F01BANKDEFFXXXX0000000000}{2:I940BANKDEFFXXXXN}{4:\n:20:STARTUMSE\n\
+            :25:10020030/1234567\n:28C:00001/001\n:60F:C190101EUR1000,00\n\
+            :61:1901020102DR12,5NTRFNONREF//8327000090031789\n\
+            :86:166?00SEPA-UEBERWEISUNG?20EREF+INVOICE 2019-\n?2142?32MUSTERMANN\n?33GMBH\n\
+            :61:190103C1000,NTRFNONREF\n:86:SALARY JANUARY\nACME CORP\n\
+            :62F:C190103EUR1987,50\n-}";
+        assert_eq!(
+            convert(file.as_bytes())?,
+            vec![
+                "account: 10020030/1234567",
+                "2019-01-02 -12.50 MUSTERMANNGMBH EREF+INVOICE 2019-42",
+                "2019-01-03 1000.00 SALARY JANUARY ACME CORP",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mt940_non_ascii_after_date() -> Result<()> {
+        let file = "{4:\n:25:1234567\n:61:190104’ACME\n:86:REFUND\n-}";
+        assert_eq!(
+            convert(file.as_bytes())?,
+            vec!["account: 1234567", "2019-01-04 0.00 REFUND"]
+        );
+        Ok(())
+    }
+}
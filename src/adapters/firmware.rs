@@ -0,0 +1,108 @@
+//! Firmware images: Intel HEX and S-record files, UEFI firmware volumes (in flash images and capsules)
+//! and flattened device trees including U-Boot FIT images.
+mod fdt;
+mod hexfile;
+mod uefi;
+
+use super::executable::ascii_strings;
+use super::*;
+use anyhow::*;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &[
+    "hex", "ihex", "ihx", "srec", "s19", "s28", "s37", "mot", "fd", "rom", "cap", "dtb", "itb",
+    "fit",
+];
+
+/// shortest printable run reported as a string
+const MIN_STRING_LEN: usize = 4;
+
+/// the listing of an image and the (path, data) of the images embedded in it
+type Decoded = (String, Vec<(String, Vec<u8>)>);
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "firmware".to_owned(),
+        version: 1,
+        description: "Decodes Intel HEX and S-record files to their memory regions with the strings in each, lists the modules (GUIDs, names, versions, strings) of UEFI firmware volumes and capsules, and dumps device trees, extracting the images of U-Boot FIT files".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        // .hex and .rom are also used for unrelated files
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FirmwareAdapter;
+
+impl FirmwareAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for FirmwareAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// printable runs with their offset in `data`
+fn strings_at(data: &[u8]) -> impl Iterator<Item = (usize, &str)> {
+    ascii_strings(data, MIN_STRING_LEN)
+        .map(move |s| (s.as_ptr() as usize - data.as_ptr() as usize, s))
+}
+
+/// a text description of the image and the images embedded in it
+fn decode(data: &[u8]) -> Result<Decoded> {
+    if data.starts_with(fdt::MAGIC) {
+        return fdt::decode(data);
+    }
+    let text = || std::str::from_utf8(data).context("not a text file");
+    if data.starts_with(b":") {
+        return Ok((hexfile::listing(&hexfile::parse_ihex(text()?)?), vec![]));
+    }
+    if data.starts_with(b"S") && data.get(1).is_some_and(u8::is_ascii_digit) {
+        return Ok((hexfile::listing(&hexfile::parse_srec(text()?)?), vec![]));
+    }
+    let listing = uefi::listing(data).context("not a recognized firmware image")?;
+    Ok((listing, vec![]))
+}
+
+#[async_trait]
+impl FileAdapter for FirmwareAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let s = stream! {
+            let mut data = Vec::new();
+            inp.read_to_end(&mut data).await?;
+            let (listing, images) = tokio::task::spawn_blocking(move || decode(&data)).await??;
+            // the description is the content of the file itself, keep its line prefix
+            yield Ok(AdaptInfo {
+                line_prefix: container.line_prefix.clone(),
+                ..container.member(
+                    container.filepath_hint.join("listing.txt"),
+                    Box::pin(Cursor::new(listing.into_bytes())),
+                )
+            });
+            for (path, data) in images {
+                yield Ok(container.member(path, Box::pin(Cursor::new(data))));
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
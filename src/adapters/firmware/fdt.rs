@@ -0,0 +1,267 @@
+//! Flattened device trees (.dtb) and U-Boot FIT images, which are device trees with the kernel,
+//! ramdisk and device tree blobs in the `data` properties of the nodes below `/images`.
+use anyhow::*;
+use std::fmt::Write;
+
+pub const MAGIC: &[u8] = &[0xd0, 0x0d, 0xfe, 0xed];
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+/// values longer than this are summarized instead of written as cells
+const MAX_CELLS: usize = 64;
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn c_string(data: &[u8]) -> &[u8] {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    &data[..end]
+}
+
+struct Node<'a> {
+    path: String,
+    props: Vec<(String, &'a [u8])>,
+}
+
+impl<'a> Node<'a> {
+    fn prop(&self, name: &str) -> Option<&'a [u8]> {
+        self.props.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+    }
+
+    fn prop_str(&self, name: &str) -> Option<String> {
+        self.prop(name)
+            .map(|v| String::from_utf8_lossy(c_string(v)).into_owned())
+    }
+}
+
+fn parse(data: &[u8]) -> Result<Vec<Node<'_>>> {
+    let header = |i: usize| be32(data, i * 4).context("truncated device tree header");
+    let structure = header(2)? as usize;
+    let strings = header(3)? as usize;
+    let mut nodes: Vec<Node> = vec![];
+    // indices into `nodes` of the open nodes
+    let mut open: Vec<usize> = vec![];
+    let mut pos = structure;
+    loop {
+        let token = be32(data, pos).context("truncated device tree structure")?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_string(data.get(pos..).unwrap_or_default());
+                pos = (pos + name.len() + 1 + 3) & !3;
+                let name = String::from_utf8_lossy(name);
+                let path = match open.last() {
+                    Some(&parent) => {
+                        format!("{}/{name}", nodes[parent].path.trim_end_matches('/'))
+                    }
+                    None => "/".to_string(),
+                };
+                open.push(nodes.len());
+                nodes.push(Node {
+                    path,
+                    props: vec![],
+                });
+            }
+            FDT_END_NODE => {
+                open.pop();
+            }
+            FDT_PROP => {
+                let len = be32(data, pos).context("truncated property")? as usize;
+                let name_offset = be32(data, pos + 4).context("truncated property")? as usize;
+                let value = data
+                    .get(pos + 8..pos + 8 + len)
+                    .context("truncated property value")?;
+                pos = (pos + 8 + len + 3) & !3;
+                let name = c_string(data.get(strings + name_offset..).unwrap_or_default());
+                let node = *open.last().context("property outside of a node")?;
+                nodes[node]
+                    .props
+                    .push((String::from_utf8_lossy(name).into_owned(), value));
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            t => return Err(format_err!("invalid device tree token {t}")),
+        }
+    }
+    Ok(nodes)
+}
+
+/// a property value the way dtc writes it: strings, cells or a size for large binary data
+fn render(value: &[u8]) -> String {
+    let is_strings = value.last() == Some(&0)
+        && value[..value.len() - 1]
+            .split(|b| *b == 0)
+            .all(|s| !s.is_empty() && s.iter().all(|b| b.is_ascii_graphic() || *b == b' '));
+    if is_strings {
+        value[..value.len() - 1]
+            .split(|b| *b == 0)
+            .map(|s| format!("\"{}\"", String::from_utf8_lossy(s)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    } else if value.len().is_multiple_of(4) && value.len() <= MAX_CELLS {
+        let cells: Vec<String> = value
+            .chunks_exact(4)
+            .map(|c| format!("0x{:x}", u32::from_be_bytes(c.try_into().unwrap())))
+            .collect();
+        format!("<{}>", cells.join(" "))
+    } else {
+        format!("[{} bytes]", value.len())
+    }
+}
+
+/// file extension for an image by its compression, so the decompress adapter picks it up
+fn image_extension(compression: Option<&str>) -> &'static str {
+    match compression {
+        Some("gzip") => ".gz",
+        Some("bzip2") => ".bz2",
+        Some("lzma") => ".lzma",
+        Some("zstd") => ".zst",
+        Some("lz4") => ".lz4",
+        _ => "",
+    }
+}
+
+/// the properties of all nodes, one per line, and the data of the FIT images
+pub fn decode(data: &[u8]) -> Result<super::Decoded> {
+    let nodes = parse(data)?;
+    let mut listing = String::new();
+    for node in &nodes {
+        writeln!(listing, "{}", node.path).unwrap();
+        for (name, value) in &node.props {
+            if value.is_empty() {
+                writeln!(listing, "{}: {name}", node.path).unwrap();
+            } else {
+                writeln!(listing, "{}: {name} = {}", node.path, render(value)).unwrap();
+            }
+        }
+    }
+    // external data is stored after the tree, aligned to 4 bytes
+    let total = be32(data, 4).unwrap_or(0) as usize;
+    let external = (total + 3) & !3;
+    let mut images = vec![];
+    for node in &nodes {
+        let Some(name) = node.path.strip_prefix("/images/") else {
+            continue;
+        };
+        if name.contains('/') {
+            continue;
+        }
+        let cell = |prop: &str| node.prop(prop).and_then(|v| be32(v, 0)).map(|v| v as usize);
+        let image = match (node.prop("data"), cell("data-size")) {
+            (Some(d), _) => Some(d),
+            (None, Some(size)) => {
+                let start = cell("data-position").or_else(|| Some(external + cell("data-offset")?));
+                start.and_then(|s| data.get(s..s + size))
+            }
+            _ => None,
+        };
+        if let Some(image) = image {
+            let compression = node.prop_str("compression");
+            let path = format!("images/{name}{}", image_extension(compression.as_deref()));
+            images.push((path, image.to_vec()));
+        }
+    }
+    Ok((listing, images))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// a FIT image with one external and one embedded image
+    fn fit() -> Vec<u8> {
+        let mut strings = vec![];
+        let mut structure = vec![];
+        let mut name_offset = |name: &str| {
+            let at = strings.len() as u32;
+            strings.extend(name.as_bytes());
+            strings.push(0);
+            at
+        };
+        let token = |s: &mut Vec<u8>, t: u32| s.extend(t.to_be_bytes());
+        let begin = |s: &mut Vec<u8>, name: &str| {
+            s.extend(FDT_BEGIN_NODE.to_be_bytes());
+            s.extend(name.as_bytes());
+            s.push(0);
+            s.resize((s.len() + 3) & !3, 0);
+        };
+        let mut props = vec![];
+        for (name, value) in [
+            ("description", b"kernel\0".to_vec()),
+            ("compression", b"gzip\0".to_vec()),
+            ("data-size", 4u32.to_be_bytes().to_vec()),
+            ("data-offset", 0u32.to_be_bytes().to_vec()),
+            ("data", b"{\"a\"}".to_vec()),
+        ] {
+            props.push((name_offset(name), value));
+        }
+        begin(&mut structure, "");
+        begin(&mut structure, "images");
+        for (node, props) in [("kernel", &props[..4]), ("script", &props[4..])] {
+            begin(&mut structure, node);
+            for (offset, value) in props {
+                token(&mut structure, FDT_PROP);
+                structure.extend((value.len() as u32).to_be_bytes());
+                structure.extend(offset.to_be_bytes());
+                structure.extend(value);
+                structure.resize((structure.len() + 3) & !3, 0);
+            }
+            token(&mut structure, FDT_END_NODE);
+        }
+        token(&mut structure, FDT_END_NODE);
+        token(&mut structure, FDT_END_NODE);
+        token(&mut structure, FDT_END);
+
+        let header_len = 40;
+        let total = header_len + structure.len() + strings.len();
+        let mut out = MAGIC.to_vec();
+        for v in [
+            total,
+            header_len,
+            header_len + structure.len(),
+            header_len,
+            17,
+            16,
+            0,
+            strings.len(),
+            structure.len(),
+        ] {
+            out.extend((v as u32).to_be_bytes());
+        }
+        out.extend(structure);
+        out.extend(strings);
+        out.resize((out.len() + 3) & !3, 0);
+        out.extend(b"\x1f\x8b\x08\x00");
+        out
+    }
+
+    #[test]
+    fn fit_images() -> Result<()> {
+        let (listing, images) = decode(&fit())?;
+        assert_eq!(
+            listing,
+            "/\n\
+             /images\n\
+             /images/kernel\n\
+             /images/kernel: description = \"kernel\"\n\
+             /images/kernel: compression = \"gzip\"\n\
+             /images/kernel: data-size = <0x4>\n\
+             /images/kernel: data-offset = <0x0>\n\
+             /images/script\n\
+             /images/script: data = [5 bytes]\n"
+        );
+        assert_eq!(
+            images,
+            vec![
+                ("images/kernel.gz".to_string(), b"\x1f\x8b\x08\x00".to_vec()),
+                ("images/script".to_string(), b"{\"a\"}".to_vec()),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Intel HEX and Motorola S-record files: text encodings of memory contents used by flash tools.
+use super::strings_at;
+use anyhow::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// contiguous memory decoded from the records
+#[derive(Debug, PartialEq)]
+pub struct Region {
+    pub start: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct HexFile {
+    /// S0 header text
+    pub header: Option<String>,
+    /// start address
+    pub entry: Option<u64>,
+    pub regions: Vec<Region>,
+}
+
+fn hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn be(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// collects data records, merging the ones that continue the previous one
+#[derive(Default)]
+struct Memory(BTreeMap<u64, Vec<u8>>);
+
+impl Memory {
+    fn add(&mut self, address: u64, data: &[u8]) {
+        if let Some((start, region)) = self.0.range_mut(..=address).next_back()
+            && start + region.len() as u64 == address
+        {
+            region.extend(data);
+            return;
+        }
+        self.0.insert(address, data.to_vec());
+    }
+
+    fn regions(self) -> Vec<Region> {
+        let mut out: Vec<Region> = vec![];
+        for (start, data) in self.0 {
+            match out.last_mut() {
+                Some(last) if last.start + last.data.len() as u64 == start => {
+                    last.data.extend(data)
+                }
+                _ => out.push(Region { start, data }),
+            }
+        }
+        out
+    }
+}
+
+/// records of an Intel HEX file: `:LLAAAATT<data>CC`
+pub fn parse_ihex(text: &str) -> Result<HexFile> {
+    let mut file = HexFile::default();
+    let mut memory = Memory::default();
+    let mut base = 0u64;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = line
+            .strip_prefix(':')
+            .and_then(hex_bytes)
+            .filter(|r| r.len() >= 5 && r.len() == r[0] as usize + 5)
+            .with_context(|| format!("invalid intel hex record on line {}", i + 1))?;
+        if record.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0 {
+            return Err(format_err!("checksum mismatch on line {}", i + 1));
+        }
+        let address = be(&record[1..3]);
+        let data = &record[4..record.len() - 1];
+        match record[3] {
+            0 => memory.add(base + address, data),
+            1 => break,
+            // extended segment address: bits 4-19
+            2 => base = be(data) << 4,
+            // start segment address: CS:IP
+            3 if data.len() == 4 => file.entry = Some((be(&data[..2]) << 4) + be(&data[2..])),
+            // extended linear address: bits 16-31
+            4 => base = be(data) << 16,
+            5 => file.entry = Some(be(data)),
+            _ => {}
+        }
+    }
+    file.regions = memory.regions();
+    Ok(file)
+}
+
+/// records of an S-record file: `S<type><count><address><data><checksum>`
+pub fn parse_srec(text: &str) -> Result<HexFile> {
+    let mut file = HexFile::default();
+    let mut memory = Memory::default();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || format_err!("invalid s-record on line {}", i + 1);
+        let kind = line
+            .strip_prefix('S')
+            .and_then(|l| l.chars().next())
+            .ok_or_else(invalid)?;
+        let record = line
+            .get(2..)
+            .and_then(hex_bytes)
+            .filter(|r| !r.is_empty() && r.len() == r[0] as usize + 1)
+            .ok_or_else(invalid)?;
+        if record.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0xff {
+            return Err(format_err!("checksum mismatch on line {}", i + 1));
+        }
+        let address_len = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(invalid()),
+        };
+        let (address, data) = record[1..record.len() - 1]
+            .split_at_checked(address_len)
+            .ok_or_else(invalid)?;
+        let address = be(address);
+        match kind {
+            '0' => file.header = Some(String::from_utf8_lossy(data).into_owned()),
+            '1' | '2' | '3' => memory.add(address, data),
+            '7' | '8' | '9' => file.entry = Some(address),
+            _ => {}
+        }
+    }
+    file.regions = memory.regions();
+    Ok(file)
+}
+
+/// the regions and the strings in them by address
+pub fn listing(file: &HexFile) -> String {
+    let mut out = String::new();
+    if let Some(header) = &file.header {
+        writeln!(out, "header: {header}").unwrap();
+    }
+    if let Some(entry) = file.entry {
+        writeln!(out, "entry: 0x{entry:08x}").unwrap();
+    }
+    for region in &file.regions {
+        let end = region.start + region.data.len() as u64;
+        writeln!(
+            out,
+            "region 0x{:08x}-0x{:08x} ({} bytes)",
+            region.start,
+            end.saturating_sub(1),
+            region.data.len()
+        )
+        .unwrap();
+        for (offset, s) in strings_at(&region.data) {
+            writeln!(out, "0x{:08x}: {s}", region.start + offset as u64).unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ihex_and_srec() -> Result<()> {
+        let ihex = ":020000040800F2\n\
+                    :0A0000004669726D77617265203168\n\
+                    :04000A00207631002B\n\
+                    :0400000508000101ED\n\
+                    :00000001FF\n";
+        let file = parse_ihex(ihex)?;
+        assert_eq!(
+            listing(&file),
+            "entry: 0x08000101\n\
+             region 0x08000000-0x0800000d (14 bytes)\n\
+             0x08000000: Firmware 1 v1\n"
+        );
+
+        let srec = "S00600004844521B\n\
+                    S1130000285F245F2212226A000424290008237C2A\n\
+                    S9030000FC\n";
+        let file = parse_srec(srec)?;
+        assert_eq!(file.header.as_deref(), Some("HDR"));
+        assert_eq!(file.entry, Some(0));
+        assert_eq!(file.regions.len(), 1);
+        assert_eq!(file.regions[0].data.len(), 16);
+
+        assert!(parse_ihex(":0A0000004669726D77617265203169\n").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! UEFI firmware volumes, found anywhere in flash images and capsules: the modules (FFS files) with
+//! their GUIDs, types, names and versions, and the strings in their code and data sections.
+use super::MIN_STRING_LEN;
+use crate::adapters::executable::{ascii_strings, utf16_strings};
+use crate::adapters::le::{u32_at, u64_at, uint};
+use std::fmt::Write;
+
+const SIGNATURE: &[u8] = b"_FVH";
+/// offset of the signature in the volume header
+const SIGNATURE_OFFSET: usize = 40;
+const MIN_HEADER_LEN: usize = 56;
+/// volumes and encapsulation sections nested deeper than this are skipped
+const MAX_DEPTH: usize = 8;
+/// the file has a 64 bit size after the normal header
+const FFS_ATTRIB_LARGE_FILE: u8 = 0x01;
+const FFS_TYPE_PAD: u8 = 0xf0;
+/// EE4E5898-3914-4259-9D6E-DC7BD79403CF: lzma compressed sections
+const LZMA_GUID: [u8; 16] = [
+    0x98, 0x58, 0x4e, 0xee, 0x14, 0x39, 0x59, 0x42, 0x9d, 0x6e, 0xdc, 0x7b, 0xd7, 0x94, 0x03, 0xcf,
+];
+/// the data of a GUID defined section can only be read by its tool
+const GUIDED_PROCESSING_REQUIRED: u16 = 0x01;
+
+fn guid(b: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        uint(&b[0..4]),
+        uint(&b[4..6]),
+        uint(&b[6..8]),
+        b[8],
+        b[9],
+        b[10..16]
+            .iter()
+            .map(|x| format!("{x:02X}"))
+            .collect::<String>()
+    )
+}
+
+fn file_type(t: u8) -> String {
+    let name = match t {
+        0x01 => "RAW",
+        0x02 => "FREEFORM",
+        0x03 => "SECURITY_CORE",
+        0x04 => "PEI_CORE",
+        0x05 => "DXE_CORE",
+        0x06 => "PEIM",
+        0x07 => "DRIVER",
+        0x08 => "COMBINED_PEIM_DRIVER",
+        0x09 => "APPLICATION",
+        0x0a => "MM",
+        0x0b => "FIRMWARE_VOLUME_IMAGE",
+        0x0c => "COMBINED_MM_DXE",
+        0x0d => "MM_CORE",
+        0x0e => "MM_STANDALONE",
+        0x0f => "MM_CORE_STANDALONE",
+        t => return format!("TYPE_{t:02X}"),
+    };
+    name.to_string()
+}
+
+fn ucs2(b: &[u8]) -> String {
+    let units: Vec<u16> = b
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// what the sections of a module contain
+#[derive(Default)]
+struct Module<'a> {
+    name: Option<String>,
+    version: Option<String>,
+    strings: Vec<String>,
+    /// firmware volume image sections
+    volumes: Vec<std::borrow::Cow<'a, [u8]>>,
+    notes: Vec<&'static str>,
+}
+
+fn sections<'a>(data: &'a [u8], module: &mut Module<'a>, depth: usize) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let mut pos = 0;
+    while pos + 4 <= data.len() {
+        let mut size = uint(&data[pos..pos + 3]) as usize;
+        let kind = data[pos + 3];
+        let mut header = 4;
+        if size == 0xff_ffff {
+            size = u32_at(data, pos + 4).unwrap_or(0) as usize;
+            header = 8;
+        }
+        if size < header || pos.checked_add(size).is_none_or(|end| end > data.len()) {
+            break;
+        }
+        let section = &data[pos..pos + size];
+        let body = &section[header..];
+        match kind {
+            // compression: uncompressed length and compression type, 0 for none
+            0x01 => match body.get(4) {
+                Some(0) => sections(&body[5..], module, depth + 1),
+                _ => module.notes.push("compressed section not decoded"),
+            },
+            // GUID defined: guid, data offset (from the section start) and attributes
+            0x02 if body.len() >= 20 => {
+                let offset = (uint(&body[16..18]) as usize).min(section.len());
+                let attributes = uint(&body[18..20]) as u16;
+                let inner = &section[offset..];
+                if body[..16] == LZMA_GUID {
+                    let mut out = vec![];
+                    match lzma_rs::lzma_decompress(&mut std::io::Cursor::new(inner), &mut out) {
+                        Result::Ok(()) => {
+                            // the decompressed sections are owned, so nested volumes are kept as copies
+                            let mut inner_module = Module::default();
+                            sections(&out, &mut inner_module, depth + 1);
+                            module.name = module.name.take().or(inner_module.name);
+                            module.version = module.version.take().or(inner_module.version);
+                            module.strings.extend(inner_module.strings);
+                            module.notes.extend(inner_module.notes);
+                            module.volumes.extend(
+                                inner_module
+                                    .volumes
+                                    .into_iter()
+                                    .map(|v| std::borrow::Cow::Owned(v.into_owned())),
+                            );
+                        }
+                        Err(_) => module.notes.push("invalid lzma section"),
+                    }
+                } else if attributes & GUIDED_PROCESSING_REQUIRED == 0 {
+                    sections(inner, module, depth + 1);
+                } else {
+                    module.notes.push("guid defined section not decoded");
+                }
+            }
+            // pe32, pic, te, raw, freeform subtype guid
+            0x10 | 0x11 | 0x12 | 0x18 | 0x19 => {
+                module
+                    .strings
+                    .extend(ascii_strings(body, MIN_STRING_LEN).map(|s| s.to_string()));
+                module.strings.extend(utf16_strings(body, MIN_STRING_LEN));
+            }
+            // version: build number and string
+            0x14 => module.version = Some(ucs2(body.get(2..).unwrap_or_default())),
+            // user interface: the module name
+            0x15 => module.name = Some(ucs2(body)),
+            0x17 => module.volumes.push(std::borrow::Cow::Borrowed(body)),
+            _ => {}
+        }
+        pos = (pos + size + 3) & !3;
+    }
+}
+
+/// the length of the volume starting at `data`, if its header is valid
+fn volume_len(data: &[u8]) -> Option<usize> {
+    if data.get(SIGNATURE_OFFSET..SIGNATURE_OFFSET + 4)? != SIGNATURE {
+        return None;
+    }
+    let len = uint(data.get(32..40)?) as usize;
+    let header_len = uint(data.get(48..50)?) as usize;
+    (header_len >= MIN_HEADER_LEN && header_len <= len && len <= data.len()).then_some(len)
+}
+
+fn volume(data: &[u8], out: &mut String, depth: usize) {
+    writeln!(out, "volume {} ({} bytes)", guid(&data[16..32]), data.len()).unwrap();
+    let mut pos = uint(&data[48..50]) as usize;
+    let ext_header = uint(&data[52..54]) as usize;
+    if ext_header != 0 {
+        // the extended header: the volume name and its size
+        pos = ext_header + u32_at(data, ext_header + 16).unwrap_or(0) as usize;
+    }
+    loop {
+        pos = (pos + 7) & !7;
+        let Some(header) = data.get(pos..pos + 24) else {
+            break;
+        };
+        if header.iter().all(|b| *b == 0xff) {
+            // free space
+            break;
+        }
+        let (mut size, mut header_len) = (uint(&header[20..23]) as usize, 24);
+        if header[19] & FFS_ATTRIB_LARGE_FILE != 0 {
+            size = u64_at(data, pos + 24).unwrap_or(0) as usize;
+            header_len = 32;
+        }
+        // a large file's size is a u64 of its own
+        if size < header_len || pos.checked_add(size).is_none_or(|end| end > data.len()) {
+            break;
+        }
+        if header[18] != FFS_TYPE_PAD {
+            let mut module = Module::default();
+            sections(&data[pos + header_len..pos + size], &mut module, depth);
+            let id = guid(&header[..16]);
+            let mut line = format!("module {id} {}", file_type(header[18]));
+            for part in [&module.name, &module.version].into_iter().flatten() {
+                line.push(' ');
+                line.push_str(part);
+            }
+            for note in &module.notes {
+                line.push_str(&format!(" ({note})"));
+            }
+            writeln!(out, "{line}").unwrap();
+            let label = module.name.as_deref().unwrap_or(&id);
+            for s in &module.strings {
+                writeln!(out, "{label}: {s}").unwrap();
+            }
+            if depth < MAX_DEPTH {
+                for nested in &module.volumes {
+                    if let Some(len) = volume_len(nested) {
+                        volume(&nested[..len], out, depth + 1);
+                    }
+                }
+            }
+        }
+        pos += size;
+    }
+}
+
+/// the modules of all firmware volumes in `data`, `None` if there are none
+pub fn listing(data: &[u8]) -> Option<String> {
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut found = false;
+    while let Some(i) = memchr::memmem::find(&data[pos..], SIGNATURE) {
+        let signature = pos + i;
+        pos = signature + SIGNATURE.len();
+        let Some(start) = signature.checked_sub(SIGNATURE_OFFSET) else {
+            continue;
+        };
+        if let Some(len) = volume_len(&data[start..]) {
+            volume(&data[start..start + len], &mut out, 0);
+            found = true;
+            pos = start + len;
+        }
+    }
+    found.then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn section(kind: u8, body: &[u8]) -> Vec<u8> {
+        let size = body.len() + 4;
+        let mut s = size.to_le_bytes()[..3].to_vec();
+        s.push(kind);
+        s.extend(body);
+        s.resize((s.len() + 3) & !3, 0);
+        s
+    }
+
+    fn ucs2z(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    /// a volume with the GUID 11111111-… holding `file`
+    fn firmware_volume(file: Vec<u8>) -> Vec<u8> {
+        let mut volume = vec![0u8; 16];
+        volume.extend([0x11; 16]);
+        let len = 72 + file.len() + 8;
+        volume.extend((len as u64).to_le_bytes());
+        volume.extend(SIGNATURE);
+        volume.extend([0; 4]);
+        volume.extend(72u16.to_le_bytes());
+        volume.resize(72, 0);
+        volume.extend(file);
+        volume.resize(len, 0xff);
+        volume
+    }
+
+    #[test]
+    fn capsule_with_volume() {
+        let mut sections = section(0x15, &ucs2z("Shell"));
+        sections.extend(section(0x10, b"MZ\0\0Shell> help\0"));
+        let mut file = (0..16).collect::<Vec<u8>>();
+        file.extend([0, 0, 0x09, 0]);
+        file.extend((24 + sections.len()).to_le_bytes()[..3].iter());
+        file.push(0xf8);
+        file.extend(sections);
+
+        let mut capsule = vec![0xaa; 100];
+        capsule.extend(firmware_volume(file));
+        assert_eq!(
+            listing(&capsule).unwrap(),
+            "volume 11111111-1111-1111-1111-111111111111 (140 bytes)\n\
+             module 03020100-0504-0706-0809-0A0B0C0D0E0F APPLICATION Shell\n\
+             Shell: Shell> help\n"
+        );
+        assert_eq!(listing(b"no firmware here"), None);
+    }
+
+    #[test]
+    fn large_file_size_overflow() {
+        let mut file = vec![0; 16];
+        file.extend([0, 0, 0x07, FFS_ATTRIB_LARGE_FILE, 0, 0, 0, 0xf8]);
+        // 72 + this is past u64::MAX
+        file.extend((u64::MAX - 20).to_le_bytes());
+        assert_eq!(
+            listing(&firmware_volume(file)).unwrap(),
+            "volume 11111111-1111-1111-1111-111111111111 (112 bytes)\n"
+        );
+    }
+}
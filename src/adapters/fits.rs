@@ -0,0 +1,329 @@
+//! FITS astronomy files: the header cards of the primary HDU and of every extension, as
+//! `KEYWORD = value / comment` lines prefixed with the HDU they are in. Long strings continued
+//! over CONTINUE cards are joined. The data of the HDUs is skipped without being read into memory.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader};
+
+static EXTENSIONS: &[&str] = &["fits", "fit", "fts", "fz"];
+
+const MIME: &str = "image/fits";
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+/// a header longer than this is not a FITS header
+const MAX_HEADER_BLOCKS: usize = 10_000;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "fits".to_owned(),
+        version: 1,
+        description: "Lists the header cards of all HDUs of FITS astronomy files".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(MIME.to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FitsAdapter;
+
+impl FitsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for FitsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// FITS files start with the SIMPLE card of the primary header
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    buf.starts_with(b"SIMPLE  = ").then_some(MIME)
+}
+
+/// a header card split into its keyword, value and comment
+#[derive(Debug, PartialEq)]
+struct Card {
+    keyword: String,
+    /// strings without their quotes
+    value: Option<String>,
+    comment: String,
+    /// a string value that ends with `&` is continued in the next CONTINUE card
+    continued: bool,
+}
+
+/// a quoted string at the start of `s` with `''` for quotes, and the rest after it
+fn quoted(s: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = s[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\'' {
+            if s[i + 2..].starts_with('\'') {
+                value.push('\'');
+                chars.next();
+                continue;
+            }
+            return (value, &s[i + 2..]);
+        }
+        value.push(c);
+    }
+    (value, "")
+}
+
+fn parse_card(card: &[u8]) -> Card {
+    let card = String::from_utf8_lossy(card);
+    let keyword = card.get(..8).unwrap_or(&card).trim_end().to_string();
+    let rest = card.get(8..).unwrap_or_default();
+    let value_part = match rest.strip_prefix("= ") {
+        Some(v) => Some(v),
+        // CONTINUE cards have a value without the `= `
+        None if keyword == "CONTINUE" => Some(rest),
+        None => None,
+    };
+    let Some(value_part) = value_part.map(str::trim_start) else {
+        // COMMENT, HISTORY and keywords without a value
+        return Card {
+            keyword,
+            value: None,
+            comment: rest.trim().to_string(),
+            continued: false,
+        };
+    };
+    let (value, comment, continued) = if value_part.starts_with('\'') {
+        let (value, rest) = quoted(value_part);
+        let value = value.trim_end().to_string();
+        let continued = value.ends_with('&');
+        let comment = rest.split_once('/').map(|(_, c)| c).unwrap_or_default();
+        let value = value.trim_end_matches('&').to_string();
+        (value, comment, continued)
+    } else {
+        let (value, comment) = value_part.split_once('/').unwrap_or((value_part, ""));
+        (value.trim().to_string(), comment, false)
+    };
+    Card {
+        keyword,
+        value: Some(value),
+        comment: comment.trim().to_string(),
+        continued,
+    }
+}
+
+/// the lines of the cards of one header, with continued strings joined
+fn header_lines(cards: &[Card]) -> Vec<String> {
+    let mut lines = vec![];
+    let mut cards = cards.iter().peekable();
+    while let Some(card) = cards.next() {
+        if card.keyword.is_empty() && card.comment.is_empty() {
+            continue;
+        }
+        let Some(value) = &card.value else {
+            lines.push(
+                format!("{} {}", card.keyword, card.comment)
+                    .trim()
+                    .to_string(),
+            );
+            continue;
+        };
+        let mut value = value.clone();
+        let mut comment = card.comment.clone();
+        let mut continued = card.continued;
+        while continued && let Some(next) = cards.next_if(|c| c.keyword == "CONTINUE") {
+            value.push_str(next.value.as_deref().unwrap_or_default());
+            if !next.comment.is_empty() {
+                comment = next.comment.clone();
+            }
+            continued = next.continued;
+        }
+        lines.push(if comment.is_empty() {
+            format!("{} = {value}", card.keyword)
+        } else {
+            format!("{} = {value} / {comment}", card.keyword)
+        });
+    }
+    lines
+}
+
+fn int(cards: &[Card], keyword: &str) -> Option<i64> {
+    cards
+        .iter()
+        .find(|c| c.keyword == keyword)?
+        .value
+        .as_deref()?
+        .parse()
+        .ok()
+}
+
+/// the size of the data after a header, padded to whole blocks
+fn data_len(cards: &[Card]) -> u64 {
+    let bitpix = int(cards, "BITPIX").unwrap_or(8).unsigned_abs();
+    let naxis = int(cards, "NAXIS").unwrap_or(0);
+    if naxis == 0 {
+        return 0;
+    }
+    // random groups have NAXIS1 = 0 and the sizes of the group in the other axes
+    let random_groups = int(cards, "NAXIS1") == Some(0);
+    let pixels: u64 = (1..=naxis)
+        .filter(|i| !(random_groups && *i == 1))
+        .map(|i| int(cards, &format!("NAXIS{i}")).unwrap_or(0).max(0) as u64)
+        .product();
+    let pcount = int(cards, "PCOUNT").unwrap_or(0).max(0) as u64;
+    let gcount = int(cards, "GCOUNT").unwrap_or(1).max(0) as u64;
+    let bits = bitpix * gcount * (pcount + pixels);
+    (bits / 8).div_ceil(BLOCK as u64) * BLOCK as u64
+}
+
+/// the cards of the next header, None at the end of the file
+async fn read_header(inp: &mut (impl AsyncRead + Unpin)) -> Result<Option<Vec<Card>>> {
+    let mut cards = vec![];
+    let mut block = vec![0; BLOCK];
+    for i in 0..MAX_HEADER_BLOCKS {
+        match inp.read_exact(&mut block).await {
+            Result::Ok(_) => {}
+            // some files have garbage or a short block after the last HDU, a header that is cut
+            // off is listed as far as it goes
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Ok((!cards.is_empty()).then_some(cards));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if i == 0 && !(block.starts_with(b"SIMPLE  =") || block.starts_with(b"XTENSION=")) {
+            return Ok(None);
+        }
+        for card in block.chunks(CARD) {
+            let card = parse_card(card);
+            if card.keyword == "END" {
+                return Ok(Some(cards));
+            }
+            cards.push(card);
+        }
+    }
+    bail!("FITS header without END")
+}
+
+#[async_trait]
+impl WritingFileAdapter for FitsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut inp = BufReader::new(inp);
+        let mut hdu = 0;
+        while let Some(cards) = read_header(&mut inp).await? {
+            let name = cards
+                .iter()
+                .find(|c| c.keyword == "EXTNAME")
+                .and_then(|c| c.value.clone());
+            let hdu_prefix = match name {
+                Some(name) => format!("hdu {hdu} {name}: "),
+                None => format!("hdu {hdu}: "),
+            };
+            for line in header_lines(&cards) {
+                async_writeln!(oup, "{line_prefix}{hdu_prefix}{line}")?;
+            }
+            let len = data_len(&cards);
+            let skipped =
+                tokio::io::copy(&mut (&mut inp).take(len), &mut tokio::io::sink()).await?;
+            if skipped < len {
+                break;
+            }
+            hdu += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    fn header(cards: &[&str]) -> Vec<u8> {
+        let mut out: Vec<u8> = cards
+            .iter()
+            .chain(&["END"])
+            .flat_map(|c| format!("{c:80}").into_bytes())
+            .collect();
+        out.resize(out.len().div_ceil(BLOCK) * BLOCK, b' ');
+        out
+    }
+
+    #[tokio::test]
+    async fn primary_and_extension() -> Result<()> {
+        let primary = header(&[
+            "SIMPLE  =                    T / conforms to FITS standard",
+            "BITPIX  =                   16",
+            "NAXIS   =                    2",
+            "NAXIS1  =                   10",
+            "NAXIS2  =                   10",
+            "OBJECT  = 'M31     '           / Andromeda galaxy",
+            "OBSERVER= 'O''Brien'",
+            "NOTES   = 'a long string that goes &'",
+            "CONTINUE  'on and on'          / continued",
+            "HISTORY reduced with the pipeline",
+            "",
+        ]);
+        let table = header(&[
+            "XTENSION= 'BINTABLE'           / binary table extension",
+            "BITPIX  =                    8",
+            "NAXIS   =                    2",
+            "NAXIS1  =                   12",
+            "NAXIS2  =                  300",
+            "PCOUNT  =                    0",
+            "GCOUNT  =                    1",
+            "EXTNAME = 'EVENTS  '",
+            "EXPTIME =               1200.5 / [s]",
+        ]);
+        let file = [
+            primary,
+            vec![0; BLOCK],
+            table,
+            vec![0; 12 * 300],
+            vec![0; 2 * BLOCK - 12 * 300],
+        ]
+        .concat();
+        assert_eq!(sniff_mime(&file), Some(MIME));
+        let (a, d) = simple_adapt_info(Path::new("m31.fits"), Box::pin(std::io::Cursor::new(file)));
+        let out = adapted_to_vec(FitsAdapter.adapt(a, &d).await?).await?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "PREFIX:hdu 0: SIMPLE = T / conforms to FITS standard
+PREFIX:hdu 0: BITPIX = 16
+PREFIX:hdu 0: NAXIS = 2
+PREFIX:hdu 0: NAXIS1 = 10
+PREFIX:hdu 0: NAXIS2 = 10
+PREFIX:hdu 0: OBJECT = M31 / Andromeda galaxy
+PREFIX:hdu 0: OBSERVER = O'Brien
+PREFIX:hdu 0: NOTES = a long string that goes on and on / continued
+PREFIX:hdu 0: HISTORY reduced with the pipeline
+PREFIX:hdu 1 EVENTS: XTENSION = BINTABLE / binary table extension
+PREFIX:hdu 1 EVENTS: BITPIX = 8
+PREFIX:hdu 1 EVENTS: NAXIS = 2
+PREFIX:hdu 1 EVENTS: NAXIS1 = 12
+PREFIX:hdu 1 EVENTS: NAXIS2 = 300
+PREFIX:hdu 1 EVENTS: PCOUNT = 0
+PREFIX:hdu 1 EVENTS: GCOUNT = 1
+PREFIX:hdu 1 EVENTS: EXTNAME = EVENTS
+PREFIX:hdu 1 EVENTS: EXPTIME = 1200.5 / [s]
+"
+        );
+        Ok(())
+    }
+}
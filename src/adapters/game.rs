@@ -0,0 +1,230 @@
+//! Game engine archives: Valve VPK, Unreal .pak (and .locres localization tables) and Unity asset
+//! bundles and serialized files. All members are listed, text assets are extracted.
+mod unity;
+mod unreal;
+mod vpk;
+
+use super::*;
+use crate::vfs::{MemoryFs, adapt_vfs};
+use anyhow::*;
+use lazy_static::lazy_static;
+use std::fmt::Write;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use tokio_stream::StreamExt;
+
+static EXTENSIONS: &[&str] = &["vpk", "pak", "locres", "assets", "unity3d", "bundle"];
+
+/// members with these extensions are extracted, the others (textures, meshes, audio) only listed
+static TEXT_EXTENSIONS: &[&str] = &[
+    "json", "txt", "ini", "cfg", "csv", "tsv", "xml", "yaml", "yml", "lua", "vdf", "res", "vmt",
+    "nut", "po", "locres", "html", "md",
+];
+/// text members larger than this are only listed
+const MAX_TEXT_SIZE: u64 = 64 << 20;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "game".to_owned(),
+        version: 1,
+        description: "Lists the members of Valve VPK, Unreal .pak and Unity asset bundles and extracts their text assets (JSON, configs, scripts, Unity TextAssets); Unreal .locres localization tables become namespace/key = text lines".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        // .pak and .bundle are used by many unrelated formats
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GameAdapter;
+
+impl GameAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for GameAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a member of an archive. `data` is only read for text members
+struct Member {
+    path: String,
+    size: u64,
+    /// why the member could not be read, shown in the listing
+    note: Option<String>,
+    data: Option<Vec<u8>>,
+}
+
+impl Member {
+    fn listed(path: String, size: u64) -> Self {
+        Member {
+            path,
+            size,
+            note: None,
+            data: None,
+        }
+    }
+}
+
+fn is_text(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| TEXT_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// little or big endian fields from a byte slice
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Fields {
+            data,
+            pos: 0,
+            big_endian: false,
+        }
+    }
+
+    fn big_endian(data: &'a [u8]) -> Self {
+        Fields {
+            big_endian: true,
+            ..Fields::new(data)
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .with_context(|| format!("truncated at offset {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        let bytes = self.take(len)?;
+        let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+        Ok(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.uint(4)? as u32)
+    }
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.uint(4)? as u32 as i32)
+    }
+    fn u64(&mut self) -> Result<u64> {
+        self.uint(8)
+    }
+
+    /// a nul terminated string
+    fn cstring(&mut self) -> Result<String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = memchr::memchr(0, rest).context("unterminated string")?;
+        self.pos += len + 1;
+        Ok(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+
+    /// skip to the next multiple of `n`
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.next_multiple_of(n);
+    }
+}
+
+fn read_at<R: Read + Seek>(r: &mut R, offset: u64, len: u64) -> Result<Vec<u8>> {
+    r.seek(std::io::SeekFrom::Start(offset))?;
+    let mut buf = vec![];
+    r.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        return Err(format_err!("truncated member at offset {offset}"));
+    }
+    Ok(buf)
+}
+
+/// the listing of all members and the text members
+fn decode<R: Read + Seek>(r: &mut R, path: &Path) -> Result<(String, Vec<Member>)> {
+    let mut magic = [0; 16];
+    let len = r.read(&mut magic)?;
+    r.rewind()?;
+    let magic = &magic[..len];
+    let members = if magic.starts_with(&vpk::MAGIC.to_le_bytes()) {
+        vpk::members(r, path, is_text)?
+    } else if magic.starts_with(unity::BUNDLE_SIGNATURE) {
+        unity::bundle_members(r)?
+    } else if magic.starts_with(&unreal::LOCRES_MAGIC)
+        || path.extension() == Some("locres".as_ref())
+    {
+        let mut data = vec![];
+        r.read_to_end(&mut data)?;
+        return Ok((unreal::locres(&data)?, vec![]));
+    } else if let Some(members) = unreal::pak_members(r, is_text)? {
+        members
+    } else {
+        unity::serialized_members(r, "").context("not a recognized game archive")?
+    };
+    let mut listing = String::new();
+    for member in &members {
+        write!(listing, "{} ({} bytes)", member.path, member.size).unwrap();
+        if let Some(note) = &member.note {
+            write!(listing, " ({note})").unwrap();
+        }
+        listing.push('\n');
+    }
+    Ok((listing, members))
+}
+
+#[async_trait]
+impl FileAdapter for GameAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (_inp, container) = ai.into_parts();
+        let path = container.filepath_hint.clone();
+        let (listing, members) = tokio::task::spawn_blocking(move || {
+            let mut file = std::fs::File::open(&path)
+                .with_context(|| format!("opening {}", path.display()))?;
+            decode(&mut file, &path)
+        })
+        .await??;
+        // the listing describes the archive itself, keep its line prefix
+        let listing = AdaptInfo {
+            line_prefix: container.line_prefix.clone(),
+            ..container.member(
+                container.filepath_hint.join("listing.txt"),
+                Box::pin(Cursor::new(listing.into_bytes())),
+            )
+        };
+        let files = members.into_iter().filter_map(|m| Some((m.path, m.data?)));
+        let members = adapt_vfs(Arc::new(MemoryFs::new(files)), container).await?;
+        Ok(Box::pin(tokio_stream::once(Ok(listing)).chain(members)))
+    }
+}
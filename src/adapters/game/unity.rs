@@ -0,0 +1,351 @@
+//! Unity asset bundles (UnityFS, Unity 5.3 and later) and the serialized files (.assets) in them.
+//! Objects are only listed by name, except for TextAssets whose content is extracted: reading
+//! other objects needs their type trees.
+use super::{Fields, MAX_TEXT_SIZE, Member, read_at};
+use anyhow::*;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+pub const BUNDLE_SIGNATURE: &[u8] = b"UnityFS\0";
+/// the blocks info is at the end of the bundle instead of after the header
+const BLOCKS_INFO_AT_END: u32 = 0x80;
+const BLOCKS_INFO_PADDING: u32 = 0x200;
+const COMPRESSION_MASK: u32 = 0x3f;
+/// directory node flag of serialized files, the other nodes are resources of them
+const NODE_SERIALIZED: u32 = 0x04;
+/// bundles decompressing to more than this are rejected
+const MAX_DECOMPRESSED: u64 = 1 << 30;
+/// the oldest serialized file version supported, from Unity 5.0
+const MIN_VERSION: u32 = 14;
+const CLASS_TEXT_ASSET: i32 = 49;
+const CLASS_MONO_BEHAVIOUR: i32 = 114;
+
+/// classes whose data starts with their name
+fn named_class(id: i32) -> Option<&'static str> {
+    Some(match id {
+        21 => "Material",
+        28 => "Texture2D",
+        43 => "Mesh",
+        48 => "Shader",
+        49 => "TextAsset",
+        74 => "AnimationClip",
+        83 => "AudioClip",
+        89 => "Cubemap",
+        90 => "Avatar",
+        91 => "AnimatorController",
+        128 => "Font",
+        213 => "Sprite",
+        329 => "VideoClip",
+        _ => return None,
+    })
+}
+
+fn decompress(method: u32, data: &[u8], size: usize) -> Result<Vec<u8>> {
+    match method {
+        0 => Ok(data.to_vec()),
+        1 => {
+            let options = lzma_rs::decompress::Options {
+                unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(size as u64)),
+                memlimit: Some(MAX_DECOMPRESSED as usize),
+                allow_incomplete: false,
+            };
+            let mut out = vec![];
+            lzma_rs::lzma_decompress_with_options(&mut Cursor::new(data), &mut out, &options)
+                .map_err(|e| format_err!("lzma: {e:?}"))?;
+            Ok(out)
+        }
+        2 | 3 => lz4_flex::block::decompress(data, size).context("lz4"),
+        m => Err(format_err!("unsupported compression method {m}")),
+    }
+}
+
+/// the files of a bundle and the objects of the serialized files among them
+pub fn bundle_members<R: Read + Seek>(r: &mut R) -> Result<Vec<Member>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let head = read_at(r, 0, len.min(1024))?;
+    let mut h = Fields::big_endian(&head);
+    h.take(BUNDLE_SIGNATURE.len())?;
+    let format = h.u32()?;
+    let _unity_version = h.cstring()?;
+    let _revision = h.cstring()?;
+    h.u64()?;
+    let info_len = h.u32()? as u64;
+    let info_size = h.u32()? as usize;
+    let flags = h.u32()?;
+    if format >= 7 {
+        h.align(16);
+    }
+    let header_len = h.pos as u64;
+    let (info_offset, mut data_offset) = if flags & BLOCKS_INFO_AT_END != 0 {
+        (len.saturating_sub(info_len), header_len)
+    } else {
+        (header_len, header_len + info_len)
+    };
+    if flags & BLOCKS_INFO_PADDING != 0 {
+        data_offset = data_offset.next_multiple_of(16);
+    }
+    let info = read_at(r, info_offset, info_len).context("reading the blocks info")?;
+    let info = decompress(flags & COMPRESSION_MASK, &info, info_size)
+        .context("decompressing the blocks info")?;
+    let mut f = Fields::big_endian(&info);
+    // hash of the uncompressed data
+    f.take(16)?;
+    let mut blocks = vec![];
+    for _ in 0..f.u32()? {
+        let (size, compressed, flags) = (f.u32()?, f.u32()? as u64, f.u16()?);
+        blocks.push((size as usize, compressed, flags as u32));
+    }
+    if blocks.iter().map(|b| b.0 as u64).sum::<u64>() > MAX_DECOMPRESSED {
+        return Err(format_err!("bundle too large"));
+    }
+    let mut data = vec![];
+    r.seek(SeekFrom::Start(data_offset))?;
+    for (size, compressed, flags) in blocks {
+        let mut block = vec![];
+        r.by_ref().take(compressed).read_to_end(&mut block)?;
+        data.extend(decompress(flags & COMPRESSION_MASK, &block, size)?);
+    }
+
+    let mut members = vec![];
+    for _ in 0..f.u32()? {
+        let (offset, size, flags) = (f.u64()? as usize, f.u64()?, f.u32()?);
+        let path = f.cstring()?;
+        let node = offset
+            .checked_add(size as usize)
+            .and_then(|end| data.get(offset..end))
+            .context("node outside of the bundle data")?;
+        let mut member = Member::listed(path, size);
+        let objects = if flags & NODE_SERIALIZED != 0 {
+            let prefix = format!("{}/", member.path);
+            serialized_members(&mut Cursor::new(node), &prefix)
+                .map_err(|e| member.note = Some(e.to_string()))
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        members.push(member);
+        members.extend(objects);
+    }
+    Ok(members)
+}
+
+/// the named objects of a serialized file, with the content of the TextAssets
+pub fn serialized_members<R: Read + Seek>(r: &mut R, prefix: &str) -> Result<Vec<Member>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let head = read_at(r, 0, len.min(48))?;
+    let mut h = Fields::big_endian(&head);
+    let mut metadata_size = h.u32()? as u64;
+    let mut file_size = h.u32()? as u64;
+    let version = h.u32()?;
+    let mut data_offset = h.u32()? as u64;
+    let big_endian = h.u8()? != 0;
+    h.take(3)?;
+    if version >= 22 {
+        metadata_size = h.u32()? as u64;
+        file_size = h.u64()?;
+        data_offset = h.u64()?;
+        h.u64()?;
+    }
+    if !(MIN_VERSION..100).contains(&version) || file_size != len || data_offset > file_size {
+        return Err(format_err!("not a unity serialized file"));
+    }
+    // the header is kept so that alignment is relative to the start of the file
+    let metadata = read_at(r, 0, h.pos as u64 + metadata_size)?;
+    let mut f = Fields {
+        data: &metadata,
+        pos: h.pos,
+        big_endian,
+    };
+    let _unity_version = f.cstring()?;
+    let _platform = f.u32()?;
+    let type_trees = f.u8()? != 0;
+    let mut classes = vec![];
+    for _ in 0..f.u32()? {
+        let class = f.i32()?;
+        if version >= 16 {
+            // stripped
+            f.u8()?;
+        }
+        if version >= 17 {
+            // script type index
+            f.u16()?;
+        }
+        if (version < 16 && class < 0) || (version >= 16 && class == CLASS_MONO_BEHAVIOUR) {
+            // script id
+            f.take(16)?;
+        }
+        // type hash
+        f.take(16)?;
+        if type_trees {
+            let nodes = f.u32()? as usize;
+            let strings = f.u32()? as usize;
+            let node_len = if version >= 19 { 32 } else { 24 };
+            f.take(nodes * node_len + strings)?;
+            if version >= 21 {
+                let dependencies = f.u32()? as usize;
+                f.take(dependencies * 4)?;
+            }
+        }
+        classes.push(class);
+    }
+
+    let mut members = vec![];
+    for _ in 0..f.u32()? {
+        f.align(4);
+        // path id
+        f.u64()?;
+        let start = if version >= 22 {
+            f.u64()?
+        } else {
+            f.u32()? as u64
+        };
+        let size = f.u32()? as u64;
+        let type_id = f.i32()?;
+        let class = if version >= 16 {
+            *classes
+                .get(type_id as usize)
+                .context("invalid object type")?
+        } else {
+            f.u16()? as i32
+        };
+        if version < 17 {
+            // script type index
+            f.u16()?;
+        }
+        if version == 15 || version == 16 {
+            // stripped
+            f.u8()?;
+        }
+        let Some(class_name) = named_class(class) else {
+            continue;
+        };
+        let object = read_at(r, data_offset + start, size.min(MAX_TEXT_SIZE))?;
+        let mut o = Fields {
+            data: &object,
+            pos: 0,
+            big_endian,
+        };
+        let name_len = o.u32()? as usize;
+        let name = String::from_utf8_lossy(o.take(name_len)?).into_owned();
+        if class == CLASS_TEXT_ASSET {
+            o.align(4);
+            let script_len = o.u32()? as usize;
+            let mut member = Member::listed(format!("{prefix}{name}.txt"), script_len as u64);
+            member.data = Some(o.take(script_len)?.to_vec());
+            members.push(member);
+        } else {
+            let mut member = Member::listed(format!("{prefix}{name}"), size);
+            member.note = Some(class_name.to_string());
+            members.push(member);
+        }
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn aligned_string(out: &mut Vec<u8>, s: &[u8]) {
+        out.extend((s.len() as u32).to_le_bytes());
+        out.extend(s);
+        out.resize(out.len().next_multiple_of(4), 0);
+    }
+
+    /// a version 21 serialized file with a TextAsset and a Texture2D
+    fn serialized() -> Vec<u8> {
+        let mut metadata = b"2021.3.1f1\0".to_vec();
+        metadata.extend(19u32.to_le_bytes());
+        metadata.push(0);
+        metadata.extend(2u32.to_le_bytes());
+        for class in [CLASS_TEXT_ASSET, 28] {
+            metadata.extend(class.to_le_bytes());
+            metadata.extend([0, 0xff, 0xff]);
+            metadata.extend([0; 16]);
+        }
+        let mut objects = vec![];
+        aligned_string(&mut objects, b"dialog");
+        aligned_string(&mut objects, b"{\"hello\": \"world\"}");
+        let texture = objects.len();
+        aligned_string(&mut objects, b"hero");
+        objects.extend([0; 12]);
+
+        metadata.extend(2u32.to_le_bytes());
+        let mut object_table = vec![];
+        for (i, (start, size)) in [(0, texture), (texture, objects.len() - texture)]
+            .into_iter()
+            .enumerate()
+        {
+            object_table.push((i as u32, start as u32, size as u32));
+        }
+        let header_len = 20;
+        for (type_id, start, size) in object_table {
+            metadata.resize(
+                (header_len + metadata.len()).next_multiple_of(4) - header_len,
+                0,
+            );
+            metadata.extend((type_id as u64 + 1).to_le_bytes());
+            metadata.extend(start.to_le_bytes());
+            metadata.extend(size.to_le_bytes());
+            metadata.extend(type_id.to_le_bytes());
+        }
+        let data_offset = (header_len + metadata.len()).next_multiple_of(16);
+        let file_size = data_offset + objects.len();
+        let mut file = vec![];
+        for v in [metadata.len(), file_size, 21, data_offset] {
+            file.extend((v as u32).to_be_bytes());
+        }
+        file.extend([0; 4]);
+        file.extend(metadata);
+        file.resize(data_offset, 0);
+        file.extend(objects);
+        file
+    }
+
+    #[test]
+    fn bundle_with_text_asset() -> Result<()> {
+        let node = serialized();
+        let compressed = lz4_flex::block::compress(&node);
+        let mut info = vec![0; 16];
+        info.extend(1u32.to_be_bytes());
+        info.extend((node.len() as u32).to_be_bytes());
+        info.extend((compressed.len() as u32).to_be_bytes());
+        info.extend(2u16.to_be_bytes());
+        info.extend(1u32.to_be_bytes());
+        info.extend(0u64.to_be_bytes());
+        info.extend((node.len() as u64).to_be_bytes());
+        info.extend(NODE_SERIALIZED.to_be_bytes());
+        info.extend(b"CAB-1234\0");
+
+        let mut bundle = BUNDLE_SIGNATURE.to_vec();
+        bundle.extend(8u32.to_be_bytes());
+        bundle.extend(b"5.x.x\0");
+        bundle.extend(b"2021.3.1f1\0");
+        bundle.extend(0u64.to_be_bytes());
+        bundle.extend((info.len() as u32).to_be_bytes());
+        bundle.extend((info.len() as u32).to_be_bytes());
+        bundle.extend(0u32.to_be_bytes());
+        bundle.resize(bundle.len().next_multiple_of(16), 0);
+        bundle.extend(info);
+        bundle.extend(compressed);
+
+        let members = bundle_members(&mut Cursor::new(bundle))?;
+        let summary: Vec<_> = members
+            .iter()
+            .map(|m| (m.path.as_str(), m.note.as_deref(), m.data.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("CAB-1234", None, None),
+                (
+                    "CAB-1234/dialog.txt",
+                    None,
+                    Some(&b"{\"hello\": \"world\"}"[..])
+                ),
+                ("CAB-1234/hero", Some("Texture2D"), None),
+            ]
+        );
+        Ok(())
+    }
+}
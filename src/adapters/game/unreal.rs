@@ -0,0 +1,534 @@
+//! Unreal Engine pak files (versions 1 to 11, as written by UnrealPak for UE 4.0 to 5.x) and the
+//! .locres localization tables found in them.
+use super::{Fields, MAX_TEXT_SIZE, Member, read_at};
+use anyhow::*;
+use std::fmt::Write;
+use std::io::{Read, Seek, SeekFrom};
+
+const PAK_MAGIC: u32 = 0x5a6f_12e1;
+/// the footer is at most this long (encryption key guid, flags and five compression method names)
+const MAX_FOOTER: u64 = 16 + 1 + 44 + 1 + 5 * 32;
+/// versions that changed the format: compression blocks, block offsets relative to the entry, named
+/// compression methods and the path hash index
+const PAK_VERSION_COMPRESSION_BLOCKS: u32 = 3;
+const PAK_VERSION_RELATIVE_OFFSETS: u32 = 5;
+const PAK_VERSION_METHOD_NAMES: u32 = 8;
+const PAK_VERSION_PATH_HASH_INDEX: u32 = 10;
+/// compression flag of versions before 8
+const COMPRESS_ZLIB: u32 = 0x01;
+const ENCRYPTED_BLOCK_ALIGN: u64 = 16;
+
+pub const LOCRES_MAGIC: [u8; 16] = [
+    0x0e, 0x14, 0x74, 0x75, 0x67, 0x4a, 0x03, 0xfc, 0x4a, 0x15, 0x90, 0x9d, 0xc3, 0x37, 0x7f, 0x1b,
+];
+
+impl Fields<'_> {
+    /// an FString: the length including the nul, negative for utf-16
+    fn fstring(&mut self) -> Result<String> {
+        let len = self.i32()?;
+        if len >= 0 {
+            let bytes = self.take(len as usize)?;
+            let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+            Ok(bytes.iter().map(|b| *b as char).collect())
+        } else {
+            let bytes = self.take(len.unsigned_abs() as usize * 2)?;
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            let units = units.strip_suffix(&[0]).unwrap_or(&units);
+            Ok(String::from_utf16_lossy(units))
+        }
+    }
+}
+
+struct Footer {
+    version: u32,
+    index_offset: u64,
+    index_size: u64,
+    encrypted_index: bool,
+    /// compression methods of version 8 and later, entries refer to them starting at 1
+    methods: Vec<String>,
+}
+
+fn footer<R: Read + Seek>(r: &mut R) -> Result<Option<Footer>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let start = len.saturating_sub(MAX_FOOTER);
+    let tail = read_at(r, start, len - start)?;
+    // the magic is followed by the version, index offset, size and hash, then the method names
+    let magic = PAK_MAGIC.to_le_bytes();
+    for at in memchr::memmem::rfind_iter(&tail, &magic) {
+        let mut f = Fields::new(&tail[at + 4..]);
+        let (Result::Ok(version), Result::Ok(index_offset), Result::Ok(index_size)) =
+            (f.u32(), f.u64(), f.u64())
+        else {
+            continue;
+        };
+        if !(1..=11).contains(&version)
+            || index_offset.saturating_add(index_size) > start + at as u64
+        {
+            continue;
+        }
+        f.pos += 20;
+        if version == 9 {
+            // frozen index flag
+            f.pos += 1;
+        }
+        let mut methods = vec![];
+        while let Result::Ok(name) = f.take(32) {
+            let name = name.split(|b| *b == 0).next().unwrap_or_default();
+            methods.push(String::from_utf8_lossy(name).into_owned());
+        }
+        let encrypted_index = version >= 4 && at > 0 && tail[at - 1] != 0;
+        return Ok(Some(Footer {
+            version,
+            index_offset,
+            index_size,
+            encrypted_index,
+            methods,
+        }));
+    }
+    Ok(None)
+}
+
+/// length of a serialized entry, which is stored again in front of the data
+fn serialized_len(version: u32, compressed: bool, block_count: u64) -> u64 {
+    let mut len = 8 * 3 + 4 + 20;
+    if version == 1 {
+        // timestamp
+        len += 8;
+    }
+    if version >= PAK_VERSION_COMPRESSION_BLOCKS {
+        if compressed {
+            len += 4 + 16 * block_count;
+        }
+        // encrypted flag and block size
+        len += 1 + 4;
+    }
+    len
+}
+
+#[derive(Clone)]
+struct Entry {
+    offset: u64,
+    size: u64,
+    uncompressed_size: u64,
+    /// 0 for stored
+    compression: u32,
+    /// start and end of the compressed blocks, relative to the offset
+    blocks: Vec<(u64, u64)>,
+    encrypted: bool,
+}
+
+impl Entry {
+    /// an entry of the index of versions before 10
+    fn parse(f: &mut Fields, version: u32) -> Result<Self> {
+        let offset = f.u64()?;
+        let size = f.u64()?;
+        let uncompressed_size = f.u64()?;
+        let compression = f.u32()?;
+        if version == 1 {
+            f.u64()?;
+        }
+        f.take(20)?;
+        let mut blocks = vec![];
+        let mut encrypted = false;
+        if version >= PAK_VERSION_COMPRESSION_BLOCKS {
+            if compression != 0 {
+                for _ in 0..f.u32()? {
+                    let (start, end) = (f.u64()?, f.u64()?);
+                    blocks.push(if version >= PAK_VERSION_RELATIVE_OFFSETS {
+                        (start, end)
+                    } else {
+                        (start.saturating_sub(offset), end.saturating_sub(offset))
+                    });
+                }
+            }
+            encrypted = f.u8()? != 0;
+            f.u32()?;
+        }
+        if compression != 0 && blocks.is_empty() {
+            let header = serialized_len(version, true, 0);
+            blocks.push((header, header + size));
+        }
+        Ok(Entry {
+            offset,
+            size,
+            uncompressed_size,
+            compression,
+            blocks,
+            encrypted,
+        })
+    }
+
+    /// a bit packed entry of version 10 and later
+    fn decode(f: &mut Fields, version: u32) -> Result<Self> {
+        let bits = f.u32()?;
+        if bits & 0x3f == 0x3f {
+            // block size
+            f.u32()?;
+        }
+        let block_count = ((bits >> 6) & 0xffff) as u64;
+        let encrypted = bits & (1 << 22) != 0;
+        let compression = (bits >> 23) & 0x3f;
+        // the bit is set if the value fits into 32 bits
+        let mut var = |bit: u32| -> Result<u64> {
+            if bits & (1 << bit) != 0 {
+                Ok(f.u32()? as u64)
+            } else {
+                f.u64()
+            }
+        };
+        let offset = var(31)?;
+        let uncompressed_size = var(30)?;
+        let size = if compression != 0 {
+            var(29)?
+        } else {
+            uncompressed_size
+        };
+        let mut start = serialized_len(version, compression != 0, block_count);
+        let mut blocks = vec![];
+        if block_count == 1 && !encrypted {
+            blocks.push((start, start + size));
+        } else {
+            for _ in 0..block_count {
+                let len = f.u32()? as u64;
+                blocks.push((start, start + len));
+                start += if encrypted {
+                    len.next_multiple_of(ENCRYPTED_BLOCK_ALIGN)
+                } else {
+                    len
+                };
+            }
+        }
+        Ok(Entry {
+            offset,
+            size,
+            uncompressed_size,
+            compression,
+            blocks,
+            encrypted,
+        })
+    }
+
+    fn method<'a>(&self, footer: &'a Footer) -> Option<&'a str> {
+        if self.compression == 0 {
+            None
+        } else if footer.version < PAK_VERSION_METHOD_NAMES {
+            Some(if self.compression & COMPRESS_ZLIB != 0 {
+                "Zlib"
+            } else {
+                "unknown"
+            })
+        } else {
+            let method = footer.methods.get(self.compression as usize - 1);
+            Some(method.map_or("unknown", |m| m.as_str()))
+        }
+    }
+
+    fn read<R: Read + Seek>(&self, r: &mut R, footer: &Footer) -> Result<Vec<u8>> {
+        if self.encrypted {
+            return Err(format_err!("encrypted"));
+        }
+        let Some(method) = self.method(footer) else {
+            let header = serialized_len(footer.version, false, 0);
+            return read_at(r, self.offset + header, self.size);
+        };
+        let mut out = vec![];
+        for (start, end) in &self.blocks {
+            let block = read_at(r, self.offset + start, end.saturating_sub(*start))?;
+            let mut decoder: Box<dyn Read> = match method.to_ascii_lowercase().as_str() {
+                "zlib" => Box::new(flate2::read::ZlibDecoder::new(&block[..])),
+                "gzip" => Box::new(flate2::read::GzDecoder::new(&block[..])),
+                _ => return Err(format_err!("{method} compressed")),
+            };
+            decoder.read_to_end(&mut out)?;
+        }
+        if out.len() as u64 != self.uncompressed_size {
+            return Err(format_err!(
+                "decompressed {} bytes instead of {}",
+                out.len(),
+                self.uncompressed_size
+            ));
+        }
+        Ok(out)
+    }
+}
+
+/// the file names and entries of the index
+fn index<R: Read + Seek>(r: &mut R, footer: &Footer) -> Result<Vec<(String, Entry)>> {
+    if footer.encrypted_index {
+        return Err(format_err!("the pak index is encrypted"));
+    }
+    let index =
+        read_at(r, footer.index_offset, footer.index_size).context("reading the pak index")?;
+    let mut f = Fields::new(&index);
+    let mount = f.fstring()?;
+    let mount = mount.trim_start_matches("../");
+    let count = f.u32()?;
+    let mut out = vec![];
+    if footer.version < PAK_VERSION_PATH_HASH_INDEX {
+        for _ in 0..count {
+            let name = f.fstring()?;
+            out.push((
+                format!("{mount}{name}"),
+                Entry::parse(&mut f, footer.version)?,
+            ));
+        }
+        return Ok(out);
+    }
+    // path hash seed
+    f.u64()?;
+    if f.u32()? != 0 {
+        // the path hash index, only useful for lookups
+        f.take(8 + 8 + 20)?;
+    }
+    if f.u32()? == 0 {
+        return Err(format_err!("the pak has no directory index"));
+    }
+    let (dir_offset, dir_size) = (f.u64()?, f.u64()?);
+    f.take(20)?;
+    let encoded_len = f.u32()? as usize;
+    let encoded = f.take(encoded_len)?;
+    let mut unencoded = vec![];
+    for _ in 0..f.u32()? {
+        unencoded.push(Entry::parse(&mut f, footer.version)?);
+    }
+    let dirs = read_at(r, dir_offset, dir_size).context("reading the pak directory index")?;
+    let mut d = Fields::new(&dirs);
+    for _ in 0..d.u32()? {
+        let dir = d.fstring()?;
+        let dir = dir.trim_start_matches('/');
+        for _ in 0..d.u32()? {
+            let name = d.fstring()?;
+            let at = d.i32()?;
+            let entry = if at >= 0 {
+                let mut e =
+                    Fields::new(encoded.get(at as usize..).context("invalid entry offset")?);
+                Entry::decode(&mut e, footer.version)?
+            } else {
+                let i = (-(at as i64) - 1) as usize;
+                unencoded.get(i).context("invalid entry index")?.clone()
+            };
+            out.push((format!("{mount}{dir}{name}"), entry));
+        }
+    }
+    Ok(out)
+}
+
+/// the members of an unreal pak, `None` if there is no pak footer
+pub fn pak_members<R: Read + Seek>(
+    r: &mut R,
+    wanted: fn(&str) -> bool,
+) -> Result<Option<Vec<Member>>> {
+    let Some(footer) = footer(r)? else {
+        return Ok(None);
+    };
+    let mut members = vec![];
+    for (path, entry) in index(r, &footer)? {
+        let mut member = Member::listed(path, entry.uncompressed_size);
+        if wanted(&member.path) && member.size <= MAX_TEXT_SIZE {
+            match entry.read(r, &footer) {
+                Result::Ok(data) => member.data = Some(data),
+                Err(e) => member.note = Some(e.to_string()),
+            }
+        }
+        members.push(member);
+    }
+    Ok(Some(members))
+}
+
+/// the entries of a localization table as `namespace/key = text` lines
+pub fn locres(data: &[u8]) -> Result<String> {
+    let mut f = Fields::new(data);
+    let version = if data.starts_with(&LOCRES_MAGIC) {
+        f.pos = LOCRES_MAGIC.len();
+        f.u8()?
+    } else {
+        0
+    };
+    let mut strings = vec![];
+    if version >= 1 {
+        let mut s = Fields::new(data);
+        s.pos = f.u64()? as usize;
+        for _ in 0..s.u32()? {
+            strings.push(s.fstring()?);
+            if version >= 2 {
+                // reference count
+                s.u32()?;
+            }
+        }
+    }
+    if version >= 2 {
+        // entry count
+        f.u32()?;
+    }
+    let mut out = String::new();
+    for _ in 0..f.u32()? {
+        if version >= 2 {
+            f.u32()?;
+        }
+        let namespace = f.fstring()?;
+        for _ in 0..f.u32()? {
+            if version >= 2 {
+                f.u32()?;
+            }
+            let key = f.fstring()?;
+            // source string hash
+            f.u32()?;
+            let text = if version >= 1 {
+                let i = f.i32()?;
+                strings.get(i as usize).cloned().unwrap_or_default()
+            } else {
+                f.fstring()?
+            };
+            let text = text.replace('\r', "").replace('\n', "\\n");
+            writeln!(out, "{namespace}/{key} = {text}").unwrap();
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn fstring(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as i32 + 1).to_le_bytes().to_vec();
+        out.extend(s.as_bytes());
+        out.push(0);
+        out
+    }
+
+    /// a version 11 pak with a stored and a zlib compressed file
+    fn pak() -> Vec<u8> {
+        use std::io::Write;
+        let stored = b"{\"id\": 1}";
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zlib.write_all(b"[/Script/Engine]\nName=Test\n").unwrap();
+        let compressed = zlib.finish().unwrap();
+
+        let mut file = vec![0u8; serialized_len(11, false, 0) as usize];
+        file.extend(stored);
+        let second = file.len() as u64;
+        file.extend(vec![0u8; serialized_len(11, true, 1) as usize]);
+        file.extend(&compressed);
+
+        // bits: compression method 1, one block, offset and sizes fit into 32 bits
+        let mut encoded = (7u32 << 29).to_le_bytes().to_vec();
+        encoded.extend(0u32.to_le_bytes());
+        encoded.extend((stored.len() as u32).to_le_bytes());
+        let bits = (7u32 << 29) | (1 << 23) | (1 << 6);
+        encoded.extend(bits.to_le_bytes());
+        encoded.extend((second as u32).to_le_bytes());
+        encoded.extend(27u32.to_le_bytes());
+        encoded.extend((compressed.len() as u32).to_le_bytes());
+
+        let dir_offset = file.len() as u64;
+        let mut dirs = 1u32.to_le_bytes().to_vec();
+        dirs.extend(fstring("/"));
+        dirs.extend(2u32.to_le_bytes());
+        dirs.extend(fstring("data.json"));
+        dirs.extend(0i32.to_le_bytes());
+        dirs.extend(fstring("Config/Game.ini"));
+        dirs.extend(12i32.to_le_bytes());
+        file.extend(&dirs);
+
+        let index_offset = file.len() as u64;
+        let mut index = fstring("../../../Mod/");
+        index.extend(2u32.to_le_bytes());
+        index.extend(0u64.to_le_bytes());
+        index.extend(0u32.to_le_bytes());
+        index.extend(1u32.to_le_bytes());
+        index.extend(dir_offset.to_le_bytes());
+        index.extend((dirs.len() as u64).to_le_bytes());
+        index.extend([0; 20]);
+        index.extend((encoded.len() as u32).to_le_bytes());
+        index.extend(encoded);
+        index.extend(0u32.to_le_bytes());
+        file.extend(&index);
+
+        file.extend([0; 17]);
+        file.extend(PAK_MAGIC.to_le_bytes());
+        file.extend(11u32.to_le_bytes());
+        file.extend(index_offset.to_le_bytes());
+        file.extend((index.len() as u64).to_le_bytes());
+        file.extend([0; 20]);
+        for method in ["Zlib", "", "", "", ""] {
+            let mut name = method.as_bytes().to_vec();
+            name.resize(32, 0);
+            file.extend(name);
+        }
+        file
+    }
+
+    #[test]
+    fn pak_v11() -> Result<()> {
+        let members = pak_members(&mut Cursor::new(pak()), |_| true)?.unwrap();
+        let summary: Vec<_> = members
+            .iter()
+            .map(|m| {
+                (
+                    m.path.as_str(),
+                    m.size,
+                    m.data.as_deref(),
+                    m.note.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Mod/data.json", 9, Some(&b"{\"id\": 1}"[..]), None),
+                (
+                    "Mod/Config/Game.ini",
+                    27,
+                    Some(&b"[/Script/Engine]\nName=Test\n"[..]),
+                    None
+                ),
+            ]
+        );
+        assert!(pak_members(&mut Cursor::new(b"not a pak"), |_| true)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn locres_v2() -> Result<()> {
+        let mut table = LOCRES_MAGIC.to_vec();
+        table.push(2);
+        let strings_offset = table.len() + 8;
+        table.extend(0u64.to_le_bytes());
+        table.extend(2u32.to_le_bytes());
+        table.extend(1u32.to_le_bytes());
+        table.extend(0u32.to_le_bytes());
+        table.extend(fstring("Menu"));
+        table.extend(2u32.to_le_bytes());
+        for (key, index) in [("Start", 1i32), ("Quit", 0)] {
+            table.extend(0u32.to_le_bytes());
+            table.extend(fstring(key));
+            table.extend(0u32.to_le_bytes());
+            table.extend(index.to_le_bytes());
+        }
+        let offset = table.len() as u64;
+        table[strings_offset - 8..strings_offset].copy_from_slice(&offset.to_le_bytes());
+        table.extend(2u32.to_le_bytes());
+        table.extend(fstring("Quit game"));
+        table.extend(1u32.to_le_bytes());
+        // utf-16
+        table.extend((-7i32).to_le_bytes());
+        table.extend(
+            "Spiel\nx\0"
+                .encode_utf16()
+                .take(7)
+                .flat_map(u16::to_le_bytes),
+        );
+        table.extend(1u32.to_le_bytes());
+        assert_eq!(
+            locres(&table)?,
+            "Menu/Start = Spiel\\nx\nMenu/Quit = Quit game\n"
+        );
+        Ok(())
+    }
+}
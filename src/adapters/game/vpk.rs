@@ -0,0 +1,156 @@
+//! Valve pak files (Source engine). A `_dir.vpk` holds the directory tree and usually only small
+//! files, the rest is stored in the numbered archives next to it (`pak01_000.vpk`, ...).
+use super::{Fields, MAX_TEXT_SIZE, Member, read_at};
+use anyhow::*;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+
+pub const MAGIC: u32 = 0x55aa_1234;
+/// archive index of data stored in the directory file after the tree
+const DIR_ARCHIVE: u16 = 0x7fff;
+const TERMINATOR: u16 = 0xffff;
+
+/// where the data of an entry outside of the directory file is
+fn archive_path(dir_file: &Path, index: u16) -> Option<PathBuf> {
+    let name = dir_file.file_name()?.to_str()?;
+    let prefix = name.strip_suffix("_dir.vpk")?;
+    Some(dir_file.with_file_name(format!("{prefix}_{index:03}.vpk")))
+}
+
+fn path_of(dir: &str, name: &str, ext: &str) -> String {
+    let mut path = match dir.trim() {
+        "" => name.to_string(),
+        dir => format!("{dir}/{name}"),
+    };
+    if !ext.trim().is_empty() {
+        path.push('.');
+        path.push_str(ext);
+    }
+    path
+}
+
+pub fn members<R: Read + Seek>(
+    r: &mut R,
+    path: &Path,
+    wanted: fn(&str) -> bool,
+) -> Result<Vec<Member>> {
+    let mut header = [0; 12];
+    r.read_exact(&mut header)?;
+    let mut h = Fields::new(&header);
+    h.u32()?;
+    let version = h.u32()?;
+    let tree_size = h.u32()? as u64;
+    let header_len = match version {
+        1 => 12,
+        2 => 28,
+        v => return Err(format_err!("unsupported vpk version {v}")),
+    };
+    let tree = read_at(r, header_len, tree_size).context("reading the vpk directory tree")?;
+    let data_start = header_len + tree_size;
+    let mut t = Fields::new(&tree);
+    let mut members = vec![];
+    loop {
+        let ext = t.cstring()?;
+        if ext.is_empty() {
+            break;
+        }
+        loop {
+            let dir = t.cstring()?;
+            if dir.is_empty() {
+                break;
+            }
+            loop {
+                let name = t.cstring()?;
+                if name.is_empty() {
+                    break;
+                }
+                let _crc = t.u32()?;
+                let preload_len = t.u16()? as usize;
+                let archive = t.u16()?;
+                let offset = t.u32()? as u64;
+                let len = t.u32()? as u64;
+                if t.u16()? != TERMINATOR {
+                    return Err(format_err!("invalid vpk directory entry for {name}"));
+                }
+                let preload = t.take(preload_len)?;
+                let mut member =
+                    Member::listed(path_of(&dir, &name, &ext), preload_len as u64 + len);
+                if wanted(&member.path) && member.size <= MAX_TEXT_SIZE {
+                    let rest = if len == 0 {
+                        Ok(vec![])
+                    } else if archive == DIR_ARCHIVE {
+                        read_at(r, data_start + offset, len)
+                    } else {
+                        archive_path(path, archive)
+                            .context("not a _dir.vpk")
+                            .and_then(|p| Ok(std::fs::File::open(p)?))
+                            .and_then(|mut f| read_at(&mut f, offset, len))
+                    };
+                    match rest {
+                        Result::Ok(rest) => member.data = Some([preload, &rest].concat()),
+                        Err(e) => member.note = Some(format!("archive {archive}: {e}")),
+                    }
+                }
+                members.push(member);
+            }
+        }
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[test]
+    fn dir_and_archive() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir_file = dir.path().join("pak01_dir.vpk");
+        std::fs::write(dir.path().join("pak01_000.vpk"), b"xx\"lang\" {}")?;
+
+        let entry = |dir: &str, name: &str, preload: &[u8], archive: u16, offset: u32, len: u32| {
+            let mut e = format!("{dir}\0{name}\0").into_bytes();
+            e.extend(0u32.to_le_bytes());
+            e.extend((preload.len() as u16).to_le_bytes());
+            e.extend(archive.to_le_bytes());
+            e.extend(offset.to_le_bytes());
+            e.extend(len.to_le_bytes());
+            e.extend(TERMINATOR.to_le_bytes());
+            e.extend(preload);
+            e
+        };
+        let tree = [
+            &b"txt\0"[..],
+            &entry("resource", "english", b"// ", 0, 2, 9),
+            b"\0",
+            &entry(" ", "readme", b"", DIR_ARCHIVE, 0, 5),
+            b"\0\0vtf\0",
+            &entry("materials/x", "wall", b"", 0, 0, 100),
+            b"\0\0\0",
+        ]
+        .concat();
+
+        let mut file = MAGIC.to_le_bytes().to_vec();
+        file.extend(1u32.to_le_bytes());
+        file.extend((tree.len() as u32).to_le_bytes());
+        file.extend(tree);
+        file.extend(b"hello");
+
+        let members = members(&mut Cursor::new(file), &dir_file, super::super::is_text)?;
+        let summary: Vec<_> = members
+            .iter()
+            .map(|m| (m.path.as_str(), m.size, m.data.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("resource/english.txt", 12, Some(&b"// \"lang\" {}"[..])),
+                ("readme.txt", 5, Some(&b"hello"[..])),
+                ("materials/x/wall.vtf", 100, None),
+            ]
+        );
+        Ok(())
+    }
+}
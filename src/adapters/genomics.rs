@@ -0,0 +1,446 @@
+//! Sequence alignment files: BAM is converted to SAM text like `samtools view -h`, of CRAM only the
+//! SAM header is shown, since its reads can only be decoded against the reference sequence.
+//! VCF and FASTA files compressed with bgzip are read by the decompress adapter.
+use super::{writing::WritingFileAdapter, *};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["bam", "cram"];
+
+const BAM_MAGIC: &[u8] = b"BAM\x01";
+const CRAM_MAGIC: &[u8] = b"CRAM";
+/// records larger than this are taken to be corruption rather than very long reads
+const MAX_RECORD_LEN: usize = 64 << 20;
+const CIGAR_OPS: &[u8] = b"MIDNSHP=X";
+const BASES: &[u8] = b"=ACMGRSVTWYHKDBN";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "genomics".to_owned(),
+        version: 1,
+        description: "Converts BAM alignments to SAM text (header, read names, positions, sequences and tags) and extracts the SAM header of CRAM files".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GenomicsAdapter;
+
+impl GenomicsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for GenomicsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// little endian fields of a decompressed BAM record
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .context("truncated record")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+    fn cstring(&mut self) -> Result<&'a [u8]> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = memchr::memchr(0, rest).context("unterminated string")?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+/// a number of an aux tag or array, by its type code
+fn aux_number(f: &mut Fields, kind: u8) -> Result<String> {
+    Ok(match kind {
+        b'c' => (f.u8()? as i8).to_string(),
+        b'C' => f.u8()?.to_string(),
+        b's' => (f.u16()? as i16).to_string(),
+        b'S' => f.u16()?.to_string(),
+        b'i' => f.i32()?.to_string(),
+        b'I' => f.u32()?.to_string(),
+        b'f' => f32::from_bits(f.u32()?).to_string(),
+        k => return Err(format_err!("invalid tag type {}", k as char)),
+    })
+}
+
+/// one alignment record as a SAM line, without the newline
+fn sam_line(record: &[u8], refs: &[String]) -> Result<String> {
+    let mut f = Fields {
+        data: record,
+        pos: 0,
+    };
+    let ref_name = |id: i32| {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| refs.get(id))
+            .map_or("*", |n| n.as_str())
+    };
+    let ref_id = f.i32()?;
+    let pos = f.i32()?;
+    let name_len = f.u8()? as usize;
+    let mapq = f.u8()?;
+    let _bin = f.u16()?;
+    let cigar_ops = f.u16()? as usize;
+    let flag = f.u16()?;
+    let seq_len = f.u32()? as usize;
+    let next_ref_id = f.i32()?;
+    let next_pos = f.i32()?;
+    let template_len = f.i32()?;
+    let name = f.take(name_len)?;
+    let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name));
+
+    let mut cigar = String::new();
+    for _ in 0..cigar_ops {
+        let op = f.u32()?;
+        let code = *CIGAR_OPS
+            .get((op & 0xf) as usize)
+            .context("invalid cigar op")?;
+        cigar.push_str(&format!("{}{}", op >> 4, code as char));
+    }
+    let seq: String = f
+        .take(seq_len.div_ceil(2))?
+        .iter()
+        .flat_map(|b| [BASES[(b >> 4) as usize], BASES[(b & 0xf) as usize]])
+        .take(seq_len)
+        .map(char::from)
+        .collect();
+    let qual = f.take(seq_len)?;
+    let qual: String = if qual.first().is_none_or(|q| *q == 0xff) {
+        "*".to_string()
+    } else {
+        qual.iter()
+            .map(|q| char::from(q.saturating_add(33)))
+            .collect()
+    };
+    let or_star = |s: String| if s.is_empty() { "*".to_string() } else { s };
+    let next_ref = match next_ref_id {
+        -1 => "*",
+        id if id == ref_id => "=",
+        id => ref_name(id),
+    };
+    let mut line = format!(
+        "{name}\t{flag}\t{}\t{}\t{mapq}\t{}\t{next_ref}\t{}\t{template_len}\t{}\t{qual}",
+        ref_name(ref_id),
+        pos + 1,
+        or_star(cigar),
+        next_pos + 1,
+        or_star(seq),
+    );
+
+    while f.pos < record.len() {
+        let tag = String::from_utf8_lossy(f.take(2)?).into_owned();
+        let kind = f.u8()?;
+        let value = match kind {
+            b'A' => format!("A:{}", f.u8()? as char),
+            b'Z' | b'H' => format!("{}:{}", kind as char, String::from_utf8_lossy(f.cstring()?)),
+            b'B' => {
+                let element = f.u8()?;
+                let mut value = format!("B:{}", element as char);
+                for _ in 0..f.u32()? {
+                    value.push(',');
+                    value.push_str(&aux_number(&mut f, element)?);
+                }
+                value
+            }
+            b'f' => format!("f:{}", aux_number(&mut f, kind)?),
+            _ => format!("i:{}", aux_number(&mut f, kind)?),
+        };
+        line.push_str(&format!("\t{tag}:{value}"));
+    }
+    Ok(line)
+}
+
+/// BAM is a series of gzip members (BGZF blocks), so it can be read as one gzip stream
+fn dump_bam(inp: impl Read, line_prefix: &str, oup: &mut impl Write) -> Result<()> {
+    let mut r = BufReader::new(flate2::read::MultiGzDecoder::new(inp));
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != BAM_MAGIC {
+        return Err(format_err!("not a bam file"));
+    }
+    let text_len = read_u32(&mut r)?;
+    let mut text = vec![];
+    (&mut r).take(text_len as u64).read_to_end(&mut text)?;
+    let mut refs = vec![];
+    for _ in 0..read_u32(&mut r)? {
+        let mut name = vec![0; read_u32(&mut r)? as usize];
+        r.read_exact(&mut name)?;
+        let len = read_u32(&mut r)?;
+        let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(&name)).into_owned();
+        refs.push((name, len));
+    }
+    let text = String::from_utf8_lossy(text.split(|b| *b == 0).next().unwrap_or_default());
+    if text.is_empty() {
+        // like samtools, describe the references when the header text is missing
+        for (name, len) in &refs {
+            writeln!(oup, "{line_prefix}@SQ\tSN:{name}\tLN:{len}")?;
+        }
+    }
+    for line in text.lines() {
+        writeln!(oup, "{line_prefix}{line}")?;
+    }
+    let refs: Vec<String> = refs.into_iter().map(|(name, _)| name).collect();
+    let mut record = vec![];
+    loop {
+        let len = match read_u32(&mut r) {
+            Result::Ok(len) => len as usize,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof) =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if len > MAX_RECORD_LEN {
+            return Err(format_err!("invalid record length {len}"));
+        }
+        record.resize(len, 0);
+        r.read_exact(&mut record)?;
+        writeln!(oup, "{line_prefix}{}", sam_line(&record, &refs)?)?;
+    }
+    Ok(())
+}
+
+/// CRAM's variable length integers: the number of leading one bits of the first byte is the
+/// number of bytes that follow. `max_len` is 5 for itf8 (with only 4 bits of the last byte
+/// used) and 9 for ltf8
+fn read_varint(r: &mut dyn Read, max_len: u32) -> Result<u64> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    let extra = b[0].leading_ones().min(max_len - 1);
+    // the longest form has no terminating zero bit
+    let flag_bits = if extra == max_len - 1 {
+        extra
+    } else {
+        extra + 1
+    };
+    let mut value = (b[0] as u64) & (0xff >> flag_bits);
+    for i in 0..extra {
+        r.read_exact(&mut b)?;
+        value = if max_len == 5 && i == 3 {
+            (value << 4) | (b[0] & 0x0f) as u64
+        } else {
+            (value << 8) | b[0] as u64
+        };
+    }
+    Ok(value)
+}
+
+/// the SAM header of a CRAM file, in the first block of the first container
+fn cram_header(mut r: impl Read) -> Result<String> {
+    let mut definition = [0; 26];
+    r.read_exact(&mut definition)?;
+    if !definition.starts_with(CRAM_MAGIC) {
+        return Err(format_err!("not a cram file"));
+    }
+    let major = definition[4];
+    if !(2..=3).contains(&major) {
+        return Err(format_err!("unsupported cram version {major}"));
+    }
+    let itf8 = |r: &mut dyn Read| read_varint(r, 5);
+    let ltf8 = |r: &mut dyn Read| read_varint(r, 9);
+    read_u32(&mut r)?;
+    for _ in 0..4 {
+        itf8(&mut r)?;
+    }
+    ltf8(&mut r)?;
+    ltf8(&mut r)?;
+    itf8(&mut r)?;
+    for _ in 0..itf8(&mut r)? {
+        itf8(&mut r)?;
+    }
+    if major >= 3 {
+        read_u32(&mut r)?;
+    }
+
+    let mut method = [0; 2];
+    r.read_exact(&mut method)?;
+    itf8(&mut r)?;
+    let size = itf8(&mut r)?;
+    itf8(&mut r)?;
+    let mut data = vec![];
+    (&mut r).take(size).read_to_end(&mut data)?;
+    let mut raw = vec![];
+    match method[0] {
+        0 => raw = data,
+        1 => {
+            flate2::read::MultiGzDecoder::new(data.as_slice()).read_to_end(&mut raw)?;
+        }
+        2 => {
+            bzip2::read::BzDecoder::new(data.as_slice()).read_to_end(&mut raw)?;
+        }
+        3 => lzma_rs::xz_decompress(&mut data.as_slice(), &mut raw)
+            .map_err(|e| format_err!("lzma: {e:?}"))?,
+        m => return Err(format_err!("unsupported cram header compression {m}")),
+    }
+    let len = u32::from_le_bytes(raw.get(..4).context("empty header")?.try_into()?) as usize;
+    let text = raw.get(4..4 + len).context("truncated header")?;
+    Ok(String::from_utf8_lossy(text.split(|b| *b == 0).next().unwrap_or_default()).into_owned())
+}
+
+fn synchronous_dump_alignments(
+    mut inp: impl Read,
+    line_prefix: &str,
+    oup: impl Write,
+) -> Result<()> {
+    let mut oup = BufWriter::new(oup);
+    let mut magic = [0; 4];
+    inp.read_exact(&mut magic)?;
+    let inp = (&magic[..]).chain(inp);
+    if magic == CRAM_MAGIC {
+        for line in cram_header(inp)?.lines() {
+            writeln!(oup, "{line_prefix}{line}")?;
+        }
+    } else {
+        dump_bam(inp, line_prefix, &mut oup)?;
+    }
+    oup.flush()?;
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for GenomicsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let inp_sync = SyncIoBridge::new(inp);
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump_alignments(inp_sync, &line_prefix, oup_sync)
+        })
+        .await?
+        .context("in synchronous genomics task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+    use pretty_assertions::assert_eq;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(vec![], Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn bam() -> Result<()> {
+        let text = b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n";
+        let mut header = BAM_MAGIC.to_vec();
+        header.extend((text.len() as u32).to_le_bytes());
+        header.extend(text);
+        header.extend(1u32.to_le_bytes());
+        header.extend(5u32.to_le_bytes());
+        header.extend(b"chr1\0");
+        header.extend(1000u32.to_le_bytes());
+
+        let mut record = vec![];
+        record.extend(0i32.to_le_bytes());
+        record.extend(99i32.to_le_bytes());
+        record.extend([6, 60]);
+        record.extend(0u16.to_le_bytes());
+        record.extend(1u16.to_le_bytes());
+        record.extend(0x63u16.to_le_bytes());
+        record.extend(5u32.to_le_bytes());
+        record.extend(0i32.to_le_bytes());
+        record.extend(199i32.to_le_bytes());
+        record.extend(105i32.to_le_bytes());
+        record.extend(b"read1\0");
+        record.extend((5u32 << 4).to_le_bytes());
+        record.extend([0x12, 0x48, 0xf0]);
+        record.extend([30, 30, 40, 40, 20]);
+        record.extend(b"NMC\x01");
+        record.extend(b"RGZgroup1\0");
+        record.extend(b"XBBs\x02\0\0\0");
+        record.extend((-1i16).to_le_bytes());
+        record.extend(7i16.to_le_bytes());
+        let mut records = (record.len() as u32).to_le_bytes().to_vec();
+        records.extend(record);
+
+        // two bgzf blocks and the empty end of file block
+        let bam = [gzip(&header), gzip(&records), gzip(b"")].concat();
+        let mut out = vec![];
+        synchronous_dump_alignments(bam.as_slice(), "PREFIX:", &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "PREFIX:@HD\tVN:1.6\n\
+             PREFIX:@SQ\tSN:chr1\tLN:1000\n\
+             PREFIX:read1\t99\tchr1\t100\t60\t5M\t=\t200\t105\tACGTN\t??II5\tNM:i:1\tRG:Z:group1\tXB:B:s,-1,7\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cram() -> Result<()> {
+        let text = b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n";
+        let mut block = (text.len() as u32).to_le_bytes().to_vec();
+        block.extend(text);
+        let block = gzip(&block);
+
+        let mut cram = b"CRAM\x03\x00".to_vec();
+        cram.extend([0; 20]);
+        cram.extend(100u32.to_le_bytes());
+        // reference, start, span, records, counter, bases, blocks, no landmarks
+        cram.extend([0, 0, 0, 0, 0, 0, 1, 0]);
+        cram.extend([0; 4]);
+        cram.extend([1, 0, 0]);
+        // a two byte itf8 for the size
+        cram.extend([0x80, block.len() as u8]);
+        cram.push(0);
+        cram.extend(block);
+
+        let mut out = vec![];
+        synchronous_dump_alignments(cram.as_slice(), "", &mut out)?;
+        assert_eq!(String::from_utf8(out)?, String::from_utf8(text.to_vec())?);
+        Ok(())
+    }
+}
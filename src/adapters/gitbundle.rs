@@ -0,0 +1,201 @@
+//! Git bundles (`git bundle create`): the commits of the bundle with their patches, as `git log -p`
+//! shows them, with the lines of the diffs prefixed with the paths of the files they change like
+//! the patch adapter does. Runs git in a temporary repository. Incremental bundles only work if
+//! their prerequisite commits are in the bundle, git refuses them otherwise.
+use super::{
+    custom::map_exe_error,
+    patch::write_diffs,
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWrite;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["bundle"];
+
+const MIME: &str = "application/x-git-bundle";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "gitbundle".to_owned(),
+        version: 1,
+        description: "Uses git to list the commits of git bundles with their patches".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..AdapterCapabilities::runs(&["git"])
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(MIME.to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GitBundleAdapter;
+
+impl GitBundleAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for GitBundleAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// bundles start with their version line
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    (buf.starts_with(b"# v2 git bundle\n") || buf.starts_with(b"# v3 git bundle\n")).then_some(MIME)
+}
+
+/// git in `repo`, without the user's configuration changing the output
+fn git(repo: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C")
+        .arg(repo)
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "-c",
+            "log.showSignature=false",
+        ])
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .stdin(Stdio::null());
+    cmd
+}
+
+async fn run(cmd: &mut Command) -> Result<Vec<u8>> {
+    let output = crate::audit::output(cmd)
+        .await
+        .map_err(|e| map_exe_error(e, "git", "Make sure you have git installed."))?;
+    if !output.status.success() {
+        bail!(
+            "git failed: {:?}\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[async_trait]
+impl WritingFileAdapter for GitBundleAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let bundle = std::path::absolute(&ai.filepath_hint)?;
+        let repo = tempfile::tempdir()?;
+        run(git(repo.path()).args(["init", "--quiet", "--bare"])).await?;
+        // the objects of the bundle without changing any refs, prints the refs of the bundle
+        let heads = run(git(repo.path()).args(["bundle", "unbundle"]).arg(&bundle)).await?;
+        let heads = String::from_utf8_lossy(&heads);
+        let mut commits = vec![];
+        for (commit, name) in heads.lines().filter_map(|l| l.split_once(' ')) {
+            async_writeln!(oup, "{}ref {name} {commit}", ai.line_prefix)?;
+            commits.push(commit.to_string());
+        }
+        if commits.is_empty() {
+            return Ok(());
+        }
+        let (mut log, spawned) = crate::audit::spawn(
+            git(repo.path())
+                .args([
+                    "log",
+                    "--patch",
+                    "--no-color",
+                    "--no-ext-diff",
+                    "--no-textconv",
+                ])
+                .args(["--format=medium", "--date=iso-strict"])
+                .args(&commits)
+                .arg("--")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .map_err(|e| map_exe_error(e, "git", "Make sure you have git installed."))?;
+        let stdout = log.stdout.take().context("git stdout not piped")?;
+        write_diffs(stdout, &ai.line_prefix, &mut oup).await?;
+        let output = log.wait_with_output().await?;
+        spawned.exited(&output.status);
+        if !output.status.success() {
+            bail!(
+                "git log failed: {:?}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn git_ok(repo: &Path, args: &[&str]) -> Result<()> {
+        let status = std::process::Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args([
+                "-c",
+                "user.name=Jane Doe",
+                "-c",
+                "user.email=jane@example.com",
+                "-c",
+                "commit.gpgsign=false",
+            ])
+            .args(args)
+            .status()?;
+        ensure!(status.success(), "git {args:?} failed");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bundle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = dir.path().join("repo");
+        std::fs::create_dir(&repo)?;
+        git_ok(&repo, &["init", "--quiet", "--initial-branch=main"])?;
+        std::fs::write(repo.join("budget.txt"), "total: 10\n")?;
+        git_ok(&repo, &["add", "budget.txt"])?;
+        git_ok(&repo, &["commit", "--quiet", "-m", "add the budget"])?;
+        std::fs::write(repo.join("budget.txt"), "total: 12\n")?;
+        git_ok(&repo, &["commit", "--quiet", "-am", "raise the budget"])?;
+        let bundle = dir.path().join("repo.bundle");
+        git_ok(
+            &repo,
+            &[
+                "bundle",
+                "create",
+                "--quiet",
+                bundle.to_str().unwrap(),
+                "main",
+            ],
+        )?;
+        assert_eq!(sniff_mime(&std::fs::read(&bundle)?), Some(MIME));
+
+        let (a, d) = simple_fs_adapt_info(&bundle).await?;
+        let out = adapted_to_vec(GitBundleAdapter.adapt(a, &d).await?).await?;
+        let out = String::from_utf8(out)?;
+        assert!(out.starts_with("PREFIX:ref refs/heads/main "));
+        assert!(out.contains("PREFIX:    raise the budget\n"));
+        assert!(out.contains("PREFIX:budget.txt: -total: 10\nPREFIX:budget.txt: +total: 12\n"));
+        assert!(out.contains("PREFIX:budget.txt: new file mode 100644\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,463 @@
+//! GPS tracks and places in GPX, KML and GeoJSON files: the names, descriptions and other text of
+//! waypoints, routes, tracks and placemarks, and the number of points of tracks with the times they
+//! start and end. The coordinates themselves are left out. KMZ files are zips with a KML inside,
+//! they go through the zip adapter.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    zim::html_text,
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use quick_xml::events::{BytesStart, Event};
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["gpx", "kml", "geojson"];
+
+const GPX_MIME: &str = "application/gpx+xml";
+const KML_MIME: &str = "application/vnd.google-earth.kml+xml";
+const GEOJSON_MIME: &str = "application/geo+json";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "gps".to_owned(),
+        version: 1,
+        description: "Lists the names, descriptions and track metadata of the waypoints, tracks and places in GPX, KML and GeoJSON files".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            [GPX_MIME, KML_MIME, GEOJSON_MIME]
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GpsAdapter;
+
+impl GpsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for GpsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// GPX and KML are XML without a magic number, found by their root element. Only used with
+/// --rga-accurate.
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    match xml_root(buf)?.0 {
+        b"gpx" => Some(GPX_MIME),
+        b"kml" => Some(KML_MIME),
+        _ => None,
+    }
+}
+
+/// a waypoint, route, track or placemark, with the text in it
+#[derive(Default)]
+struct Feature {
+    kind: &'static str,
+    /// the depth of its element
+    depth: usize,
+    name: String,
+    fields: Vec<(String, String)>,
+    points: u64,
+    /// the times of the first and the last point
+    start: Option<String>,
+    end: Option<String>,
+}
+
+impl Feature {
+    fn lines(&self) -> Vec<String> {
+        let prefix = match self.name.as_str() {
+            "" => self.kind.to_string(),
+            name => format!("{} {name}", self.kind),
+        };
+        let mut lines: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("{prefix}: {key} = {value}"))
+            .collect();
+        if self.points > 1 {
+            lines.push(match (&self.start, &self.end) {
+                (Some(start), Some(end)) => {
+                    format!("{prefix}: {} points from {start} to {end}", self.points)
+                }
+                _ => format!("{prefix}: {} points", self.points),
+            });
+        }
+        if lines.is_empty() && !self.name.is_empty() {
+            lines.push(prefix);
+        }
+        lines
+    }
+}
+
+fn feature_kind(element: &str) -> Option<&'static str> {
+    Some(match element {
+        "metadata" => "metadata",
+        "wpt" => "waypoint",
+        "rte" => "route",
+        "trk" => "track",
+        "Document" => "document",
+        "Folder" => "folder",
+        "Placemark" => "placemark",
+        _ => return None,
+    })
+}
+
+/// the elements of GPX and KML with text worth searching
+fn is_text(element: &str) -> bool {
+    matches!(
+        element,
+        "name"
+            | "desc"
+            | "cmt"
+            | "type"
+            | "src"
+            | "sym"
+            | "keywords"
+            | "time"
+            | "text"
+            | "description"
+            | "address"
+            | "Snippet"
+            | "phoneNumber"
+            | "value"
+            | "SimpleData"
+            | "when"
+            | "begin"
+            | "end"
+    )
+}
+
+fn name_attribute(e: &BytesStart) -> Option<String> {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| a.key.as_ref() == b"name")
+        .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+}
+
+/// the features of a GPX or KML file in document order
+fn convert_xml(xml: &str) -> Result<Vec<Feature>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().check_end_names = false;
+    let mut features: Vec<Feature> = vec![];
+    // the features the current element is in, as indices into `features`
+    let mut open: Vec<usize> = vec![];
+    // local names, the name attributes of KML's Data and SimpleData, and the text of the elements
+    let mut stack: Vec<(String, Option<String>, String)> = vec![];
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                if let Some(kind) = feature_kind(&name) {
+                    open.push(features.len());
+                    features.push(Feature {
+                        kind,
+                        depth: stack.len(),
+                        ..Default::default()
+                    });
+                }
+                stack.push((name, name_attribute(&e), String::new()));
+            }
+            Event::Text(t) => {
+                if let Some((.., text)) = stack.last_mut() {
+                    match t.unescape() {
+                        Result::Ok(t) => text.push_str(&t),
+                        Err(_) => text.push_str(&String::from_utf8_lossy(&t)),
+                    }
+                }
+            }
+            Event::CData(t) => {
+                if let Some((.., text)) = stack.last_mut() {
+                    text.push_str(&String::from_utf8_lossy(&t));
+                }
+            }
+            Event::End(_) => {
+                let Some((element, name_attribute, text)) = stack.pop() else {
+                    continue;
+                };
+                let Some(&index) = open.last() else {
+                    continue;
+                };
+                let feature = &mut features[index];
+                if feature.depth == stack.len() {
+                    open.pop();
+                    continue;
+                }
+                // the path of the element below the feature
+                let path = &stack[feature.depth + 1..];
+                if matches!(element.as_str(), "trkpt" | "rtept") {
+                    feature.points += 1;
+                    continue;
+                }
+                let text = if element == "description" && text.contains('<') {
+                    html_text(&text).replace('\n', " ")
+                } else {
+                    text.split_whitespace().collect::<Vec<_>>().join(" ")
+                };
+                if text.is_empty() || !is_text(&element) {
+                    continue;
+                }
+                // the times of the points of KML's gx:Track
+                if element == "when" && path.last().is_some_and(|(e, ..)| e == "Track") {
+                    feature.points += 1;
+                    feature.start.get_or_insert_with(|| text.clone());
+                    feature.end = Some(text);
+                    continue;
+                }
+                // the name and time of a point, the rest of it is coordinates and sensor data
+                if let Some(point) = path
+                    .iter()
+                    .position(|(e, ..)| matches!(e.as_str(), "trkpt" | "rtept"))
+                {
+                    match element.as_str() {
+                        "time" if point + 1 == path.len() => {
+                            feature.start.get_or_insert_with(|| text.clone());
+                            feature.end = Some(text);
+                        }
+                        "name" => feature.fields.push(("point".to_string(), text)),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if element == "name" && path.is_empty() {
+                    feature.name = text;
+                    continue;
+                }
+                // the values of KML's extended data are named by their Data element
+                let data_name = match element.as_str() {
+                    "SimpleData" => name_attribute,
+                    "value" => path.last().and_then(|(_, name, _)| name.clone()),
+                    _ => None,
+                };
+                let key = match data_name {
+                    Some(name) => name,
+                    None => path
+                        .iter()
+                        .map(|(e, ..)| e.as_str())
+                        .chain([element.as_str()])
+                        .collect::<Vec<_>>()
+                        .join("."),
+                };
+                feature.fields.push((key, text));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(features)
+}
+
+fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// the number of positions in the coordinates of a geometry
+fn positions(coordinates: &serde_json::Value) -> u64 {
+    match coordinates {
+        serde_json::Value::Array(a) if a.first().is_some_and(|c| c.is_number()) => 1,
+        serde_json::Value::Array(a) => a.iter().map(positions).sum(),
+        _ => 0,
+    }
+}
+
+fn geojson_feature(feature: &serde_json::Value) -> Feature {
+    let properties = feature.get("properties").and_then(|p| p.as_object());
+    let name = properties
+        .and_then(|p| ["name", "title"].iter().find_map(|k| p.get(*k)))
+        .or_else(|| feature.get("id"))
+        .map(json_text)
+        .unwrap_or_default();
+    let mut fields: Vec<(String, String)> = properties
+        .into_iter()
+        .flatten()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| (k.clone(), json_text(v)))
+        .filter(|(_, v)| *v != name)
+        .collect();
+    let geometry = feature.get("geometry");
+    if let Some(ty) = geometry.and_then(|g| g.get("type")) {
+        fields.push(("geometry".to_string(), json_text(ty)));
+    }
+    Feature {
+        kind: "feature",
+        name,
+        fields,
+        points: geometry
+            .and_then(|g| g.get("coordinates"))
+            .map(positions)
+            .unwrap_or(0),
+        ..Default::default()
+    }
+}
+
+fn convert_geojson(json: &[u8]) -> Result<Vec<Feature>> {
+    let root: serde_json::Value = serde_json::from_slice(json).context("invalid GeoJSON")?;
+    Ok(match root.get("type").and_then(|t| t.as_str()) {
+        Some("FeatureCollection") => root
+            .get("features")
+            .and_then(|f| f.as_array())
+            .into_iter()
+            .flatten()
+            .map(geojson_feature)
+            .collect(),
+        _ => vec![geojson_feature(&root)],
+    })
+}
+
+#[async_trait]
+impl WritingFileAdapter for GpsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+        let features = if data.trim_ascii_start().starts_with(b"{") {
+            convert_geojson(data)?
+        } else {
+            convert_xml(&String::from_utf8_lossy(data))?
+        };
+        for feature in features {
+            for line in feature.lines() {
+                async_writeln!(oup, "{line_prefix}{line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn lines(features: Vec<Feature>) -> Vec<String> {
+        features.iter().flat_map(Feature::lines).collect()
+    }
+
+    #[test]
+    fn gpx() -> Result<()> {
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <gpx version="1.1" creator="Garmin Connect" xmlns="http://www.topografix.com/GPX/1/1">
+              <metadata>
+                <name>Alps 2024</name>
+                <author><name>Jane Doe</name></author>
+                <time>2024-07-01T05:00:00Z</time>
+              </metadata>
+              <wpt lat="46.55" lon="7.96">
+                <ele>4158</ele>
+                <name>Jungfrau</name>
+                <desc>Summit &amp; viewpoint</desc>
+                <sym>Summit</sym>
+              </wpt>
+              <trk>
+                <name>Morning ride</name>
+                <type>cycling</type>
+                <trkseg>
+                  <trkpt lat="46.1" lon="7.1"><ele>500</ele><time>2024-07-01T06:00:00Z</time></trkpt>
+                  <trkpt lat="46.2" lon="7.2"><ele>520</ele><time>2024-07-01T06:00:05Z</time>
+                    <name>Bridge</name></trkpt>
+                  <trkpt lat="46.3" lon="7.3"><time>2024-07-01T07:30:00Z</time></trkpt>
+                </trkseg>
+              </trk>
+            </gpx>"#;
+        assert_eq!(sniff_mime(gpx.as_bytes()), Some(GPX_MIME));
+        assert_eq!(
+            lines(convert_xml(gpx)?),
+            [
+                "metadata Alps 2024: author.name = Jane Doe",
+                "metadata Alps 2024: time = 2024-07-01T05:00:00Z",
+                "waypoint Jungfrau: desc = Summit & viewpoint",
+                "waypoint Jungfrau: sym = Summit",
+                "track Morning ride: type = cycling",
+                "track Morning ride: point = Bridge",
+                "track Morning ride: 3 points from 2024-07-01T06:00:00Z to 2024-07-01T07:30:00Z",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn kml() -> Result<()> {
+        let kml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <kml xmlns="http://www.opengis.net/kml/2.2">
+              <Document>
+                <name>Hikes</name>
+                <Folder>
+                  <name>Huts</name>
+                  <Placemark>
+                    <name>Hörnli hut</name>
+                    <description><![CDATA[<p>Open <b>July</b> to September</p>]]></description>
+                    <ExtendedData><Data name="beds"><value>130</value></Data></ExtendedData>
+                    <Point><coordinates>7.68,45.98,3260</coordinates></Point>
+                  </Placemark>
+                  <Placemark>
+                    <name>Approach</name>
+                    <gx:Track>
+                      <when>2024-08-02T04:00:00Z</when><gx:coord>7.69 45.99 2580</gx:coord>
+                      <when>2024-08-02T06:10:00Z</when><gx:coord>7.68 45.98 3260</gx:coord>
+                    </gx:Track>
+                  </Placemark>
+                </Folder>
+              </Document>
+            </kml>"#;
+        assert_eq!(sniff_mime(kml.as_bytes()), Some(KML_MIME));
+        assert_eq!(
+            lines(convert_xml(kml)?),
+            [
+                "document Hikes",
+                "folder Huts",
+                "placemark Hörnli hut: description = Open July to September",
+                "placemark Hörnli hut: beds = 130",
+                "placemark Approach: 2 points from 2024-08-02T04:00:00Z to 2024-08-02T06:10:00Z",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn geojson() -> Result<()> {
+        let geojson = br#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"name": "Lake loop", "surface": "gravel", "km": 12.5},
+             "geometry": {"type": "LineString", "coordinates": [[8.5, 47.3], [8.6, 47.3], [8.6, 47.4]]}},
+            {"type": "Feature", "id": "spring-1", "properties": null,
+             "geometry": {"type": "Point", "coordinates": [8.55, 47.35]}}
+        ]}"#;
+        assert_eq!(
+            lines(convert_geojson(geojson)?),
+            [
+                "feature Lake loop: surface = gravel",
+                "feature Lake loop: km = 12.5",
+                "feature Lake loop: geometry = LineString",
+                "feature Lake loop: 3 points",
+                "feature spring-1: geometry = Point",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,461 @@
+use super::*;
+use super::{custom::map_exe_error, writing::async_writeln};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::path::Path;
+use tokio::io::AsyncWrite;
+use tokio::process::Command;
+use writing::WritingFileAdapter;
+
+static HDF5_EXTENSIONS: &[&str] = &["h5", "hdf5", "he5", "hdf"];
+static NETCDF_EXTENSIONS: &[&str] = &["nc", "nc4", "cdf"];
+
+/// string datasets / variables with more elements than this are only listed, not printed
+const MAX_STRING_ELEMENTS: u64 = 1000;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "hdf5".to_owned(),
+        version: 1,
+        description:
+            "Uses h5dump / ncdump to list the groups, datasets and attributes of HDF5 and NetCDF files, including the content of small string datasets"
+                .to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..AdapterCapabilities::runs(&["h5dump", "ncdump"])
+        },
+        fast_matchers: HDF5_EXTENSIONS
+            .iter()
+            .chain(NETCDF_EXTENSIONS)
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct Hdf5Adapter;
+
+impl Hdf5Adapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for Hdf5Adapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Group,
+    Dataset,
+    Attribute,
+}
+
+/// a group, dataset or attribute from h5dump output
+#[derive(Debug, PartialEq)]
+struct Object {
+    kind: Kind,
+    /// `/group/dataset`, attributes are `/group/dataset@name`
+    path: String,
+    dtype: String,
+    shape: String,
+    values: Vec<String>,
+}
+
+impl Object {
+    fn is_string(&self) -> bool {
+        self.dtype.starts_with("H5T_STRING")
+    }
+    /// number of elements of a `SIMPLE { ( 2, 3 ) / ( 2, 3 ) }` or `SCALAR` dataspace
+    fn element_count(&self) -> Option<u64> {
+        let shape = self.shape.trim();
+        if shape == "SCALAR" {
+            return Some(1);
+        }
+        let dims = shape.split_once('(')?.1.split_once(')')?.0;
+        dims.split(',')
+            .map(|d| d.trim().parse::<u64>().ok())
+            .product()
+    }
+}
+
+fn quoted_name(header: &str) -> Option<&str> {
+    let start = header.find('"')? + 1;
+    let len = header[start..].find('"')?;
+    Some(&header[start..start + len])
+}
+
+fn child_path(parent: &str, name: &str) -> String {
+    if name.starts_with('/') {
+        // h5dump -d prints the full path
+        name.to_string()
+    } else {
+        format!("{}/{}", parent.trim_end_matches('/'), name)
+    }
+}
+
+enum Frame {
+    Object(usize),
+    Data(usize),
+    Other,
+}
+
+/// parse the (DDL) output of h5dump into a flat list of objects in document order
+fn parse_h5dump(out: &str) -> Vec<Object> {
+    let mut objects: Vec<Object> = vec![];
+    let mut stack: Vec<Frame> = vec![];
+    let current = |stack: &[Frame]| {
+        stack.iter().rev().find_map(|f| match f {
+            Frame::Object(i) => Some(*i),
+            _ => None,
+        })
+    };
+    for line in out.lines() {
+        let line = line.trim();
+        if let Some(Frame::Data(i)) = stack.last() {
+            let i = *i;
+            if line.starts_with('}') {
+                stack.pop();
+                continue;
+            }
+            // strip the `(0): ` / `(1,0): ` element index
+            let value = match line.strip_prefix('(').and_then(|l| l.split_once("): ")) {
+                Some((_, v)) => v,
+                None => line,
+            };
+            let value = value.trim_end_matches(',').trim();
+            if !value.is_empty() {
+                objects[i].values.push(value.to_string());
+            }
+            continue;
+        }
+        if line.starts_with('}') {
+            stack.pop();
+            continue;
+        }
+        let parent = current(&stack);
+        let parent_path = parent.map(|i| objects[i].path.clone()).unwrap_or_default();
+        if let Some(rest) = line.strip_prefix("DATASPACE") {
+            if let Some(i) = parent {
+                objects[i].shape = rest.trim().to_string();
+            }
+            if !line.ends_with('{') {
+                continue;
+            }
+        }
+        let Some(header) = line.strip_suffix('{').map(str::trim) else {
+            if let (Some(rest), Some(i)) = (line.strip_prefix("DATATYPE"), parent) {
+                objects[i].dtype = rest.trim().to_string();
+            }
+            continue;
+        };
+        let kind = if header.starts_with("GROUP ") {
+            Some(Kind::Group)
+        } else if header.starts_with("DATASET ") {
+            Some(Kind::Dataset)
+        } else if header.starts_with("ATTRIBUTE ") {
+            Some(Kind::Attribute)
+        } else {
+            None
+        };
+        match (kind, quoted_name(header)) {
+            (Some(kind), Some(name)) => {
+                let path = match kind {
+                    Kind::Group if name == "/" => "/".to_string(),
+                    Kind::Attribute => format!("{}@{}", parent_path, name),
+                    _ => child_path(&parent_path, name),
+                };
+                objects.push(Object {
+                    kind,
+                    path,
+                    dtype: String::new(),
+                    shape: String::new(),
+                    values: vec![],
+                });
+                stack.push(Frame::Object(objects.len() - 1));
+            }
+            _ => {
+                if let (Some(rest), Some(i)) = (header.strip_prefix("DATATYPE"), parent) {
+                    objects[i].dtype = rest.trim().to_string();
+                }
+                match parent {
+                    Some(i) if header == "DATA" => stack.push(Frame::Data(i)),
+                    _ => stack.push(Frame::Other),
+                }
+            }
+        }
+    }
+    objects
+}
+
+/// the `dimensions:` and string variables of `ncdump -h` output. Returns the names of string variables
+/// that are small enough to print
+fn small_netcdf_strings(header: &str) -> Vec<String> {
+    let mut dims: HashMap<&str, u64> = HashMap::new();
+    let mut out = vec![];
+    let mut section = "";
+    for line in header.lines() {
+        let line = line.trim();
+        if line.ends_with(':') && !line.contains(' ') {
+            section = line;
+            continue;
+        }
+        match section {
+            "dimensions:" => {
+                // `time = 10 ;` or `time = UNLIMITED ; // (4 currently)`
+                if let Some((name, size)) = line.split_once(" = ") {
+                    let size = size
+                        .split_once('(')
+                        .map(|(_, s)| s)
+                        .unwrap_or(size)
+                        .trim_start();
+                    let digits: String = size.chars().take_while(char::is_ascii_digit).collect();
+                    if let std::result::Result::Ok(n) = digits.parse() {
+                        dims.insert(name.trim(), n);
+                    }
+                }
+            }
+            "variables:" => {
+                // `char title(len) ;`, attributes are indented further and contain `:`
+                let Some(decl) = line
+                    .strip_prefix("char ")
+                    .or_else(|| line.strip_prefix("string "))
+                else {
+                    continue;
+                };
+                let decl = decl.trim_end_matches(';').trim();
+                let (name, count) = match decl.split_once('(') {
+                    Some((name, d)) => (
+                        name,
+                        d.trim_end_matches(')')
+                            .split(',')
+                            .map(|d| dims.get(d.trim()).copied())
+                            .product::<Option<u64>>(),
+                    ),
+                    None => (decl, Some(1)),
+                };
+                if count.is_some_and(|c| c <= MAX_STRING_ELEMENTS) {
+                    out.push(name.trim().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// the lines of the `data:` section of ncdump output
+fn netcdf_data(out: &str) -> impl Iterator<Item = &str> {
+    out.lines()
+        .skip_while(|l| l.trim() != "data:")
+        .skip(1)
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && *l != "}")
+}
+
+async fn run(exe: &str, args: &[&str], file: &Path) -> Result<String> {
+    let out = crate::audit::output(Command::new(exe).args(args).arg(file))
+        .await
+        .map_err(|e| {
+            map_exe_error(
+                e,
+                exe,
+                "Make sure you have the HDF5 tools (h5dump) and NetCDF tools (ncdump) installed.",
+            )
+        })?;
+    if !out.status.success() {
+        return Err(format_err!(
+            "{} failed: {:?}\n{}",
+            exe,
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+async fn dump_hdf5(
+    file: &Path,
+    line_prefix: &str,
+    mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    // header and attribute values, but no dataset values
+    let objects = parse_h5dump(&run("h5dump", &["-A"], file).await?);
+    let small_strings: Vec<&str> = objects
+        .iter()
+        .filter(|o| o.kind == Kind::Dataset && o.is_string())
+        .filter(|o| o.element_count().is_some_and(|c| c <= MAX_STRING_ELEMENTS))
+        .map(|o| o.path.as_str())
+        .collect();
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    if !small_strings.is_empty() {
+        let args: Vec<&str> = small_strings.iter().flat_map(|p| ["-d", *p]).collect();
+        for o in parse_h5dump(&run("h5dump", &args, file).await?) {
+            if o.kind == Kind::Dataset {
+                values.insert(o.path, o.values);
+            }
+        }
+    }
+    for o in &objects {
+        match o.kind {
+            Kind::Group => async_writeln!(oup, "{line_prefix}group {}", o.path)?,
+            Kind::Dataset => {
+                async_writeln!(
+                    oup,
+                    "{line_prefix}dataset {}: {} {}",
+                    o.path,
+                    o.dtype,
+                    o.shape
+                )?;
+                if let Some(v) = values.get(&o.path).filter(|v| !v.is_empty()) {
+                    async_writeln!(oup, "{line_prefix}{}: {}", o.path, v.join(", "))?;
+                }
+            }
+            Kind::Attribute => {
+                async_writeln!(oup, "{line_prefix}{}: {}", o.path, o.values.join(", "))?
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn dump_netcdf(
+    file: &Path,
+    line_prefix: &str,
+    mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let header = run("ncdump", &["-h"], file).await?;
+    for line in header.lines() {
+        async_writeln!(oup, "{line_prefix}{}", line.trim())?;
+    }
+    let strings = small_netcdf_strings(&header);
+    if !strings.is_empty() {
+        let data = run("ncdump", &["-v", &strings.join(",")], file).await?;
+        for line in netcdf_data(&data) {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for Hdf5Adapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            ..
+        } = ai;
+        let ext = filepath_hint
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_ascii_lowercase();
+        let is_netcdf = NETCDF_EXTENSIONS.contains(&ext.as_str());
+        // both tools need random access (seekable_input), so this is always a real file
+        if is_netcdf {
+            dump_netcdf(&filepath_hint, &line_prefix, oup).await
+        } else {
+            dump_hdf5(&filepath_hint, &line_prefix, oup).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const H5DUMP: &str = r#"HDF5 "run.h5" {
+GROUP "/" {
+   ATTRIBUTE "title" {
+      DATATYPE  H5T_STRING {
+         STRSIZE 12;
+         STRPAD H5T_STR_NULLTERM;
+         CSET H5T_CSET_ASCII;
+         CTYPE H5T_C_S1;
+      }
+      DATASPACE  SCALAR
+      DATA {
+      (0): "experiment 1"
+      }
+   }
+   GROUP "run1" {
+      DATASET "names" {
+         DATATYPE  H5T_STRING {
+            STRSIZE H5T_VARIABLE;
+            STRPAD H5T_STR_NULLTERM;
+            CSET H5T_CSET_UTF8;
+            CTYPE H5T_C_S1;
+         }
+         DATASPACE  SIMPLE { ( 2 ) / ( 2 ) }
+      }
+      DATASET "temps" {
+         DATATYPE  H5T_IEEE_F64LE
+         DATASPACE  SIMPLE { ( 100, 3 ) / ( 100, 3 ) }
+         ATTRIBUTE "units" {
+            DATATYPE  H5T_STRING {
+               STRSIZE 1;
+            }
+            DATASPACE  SCALAR
+            DATA {
+            (0): "K"
+            }
+         }
+      }
+   }
+}
+}
+"#;
+
+    #[test]
+    fn h5dump_structure() {
+        let objects = parse_h5dump(H5DUMP);
+        let summary: Vec<_> = objects
+            .iter()
+            .map(|o| (o.kind, o.path.as_str(), o.values.join(", ")))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Kind::Group, "/", "".to_string()),
+                (Kind::Attribute, "/@title", "\"experiment 1\"".to_string()),
+                (Kind::Group, "/run1", "".to_string()),
+                (Kind::Dataset, "/run1/names", "".to_string()),
+                (Kind::Dataset, "/run1/temps", "".to_string()),
+                (Kind::Attribute, "/run1/temps@units", "\"K\"".to_string()),
+            ]
+        );
+        assert!(objects[3].is_string());
+        assert_eq!(objects[3].element_count(), Some(2));
+        assert_eq!(objects[4].dtype, "H5T_IEEE_F64LE");
+        assert_eq!(objects[4].element_count(), Some(300));
+
+        let data = parse_h5dump(
+            "HDF5 \"run.h5\" {\nDATASET \"/run1/names\" {\n   DATATYPE  H5T_STRING {\n   }\n   DATASPACE  SIMPLE { ( 2 ) / ( 2 ) }\n   DATA {\n   (0): \"alice\", \"bob\"\n   }\n}\n}\n",
+        );
+        assert_eq!(data[0].path, "/run1/names");
+        assert_eq!(data[0].values, vec!["\"alice\", \"bob\""]);
+    }
+
+    #[test]
+    fn ncdump_strings() {
+        let header = "netcdf run {\ndimensions:\n\ttime = UNLIMITED ; // (4 currently)\n\tlen = 16 ;\n\tbig = 100000 ;\nvariables:\n\tdouble temp(time) ;\n\t\ttemp:units = \"K\" ;\n\tchar station(time, len) ;\n\tchar blob(big) ;\n\tstring comment ;\n\n// global attributes:\n\t\t:title = \"experiment\" ;\n}\n";
+        assert_eq!(small_netcdf_strings(header), vec!["station", "comment"]);
+        let data = "netcdf run {\ndimensions:\n\tlen = 16 ;\ndata:\n\n station =\n  \"north\",\n  \"south\" ;\n}\n";
+        assert_eq!(
+            netcdf_data(data).collect::<Vec<_>>(),
+            vec!["station =", "\"north\",", "\"south\" ;"]
+        );
+    }
+}
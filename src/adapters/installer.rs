@@ -0,0 +1,154 @@
+//! Installer executables (NSIS, Inno Setup) and compiled AutoHotkey scripts:
+//! the embedded files and scripts are yielded as members, so urls and commands in a downloaded
+//! installer can be searched without unpacking it first.
+mod inno;
+mod nsis;
+
+use super::*;
+use crate::vfs::{MemoryFs, adapt_vfs};
+use anyhow::*;
+use lazy_static::lazy_static;
+use object::LittleEndian as LE;
+use object::pe::{ImageNtHeaders32, ImageNtHeaders64, RT_RCDATA};
+use object::read::pe::{ImageNtHeaders, PeFile, ResourceDirectoryEntryData, ResourceNameOrId};
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["exe"];
+
+/// decompressed data beyond this is dropped so a crafted installer can't exhaust memory
+const MAX_DECOMPRESSED: u64 = 1 << 30;
+/// names of the RCDATA resource holding the script of a compiled AutoHotkey exe
+const AHK_RESOURCES: &[&str] = &[">AUTOHOTKEY SCRIPT<", ">AHK WITH ICON<"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "installer".to_owned(),
+        version: 1,
+        description: "Extracts the files and script strings of NSIS installers, the setup script strings of Inno Setup installers and the script of compiled AutoHotkey programs".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.microsoft.portable-executable".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        // reads every exe completely, most of which aren't installers
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct InstallerAdapter;
+
+impl InstallerAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for InstallerAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a file or script found in an installer
+#[derive(Debug)]
+struct Member {
+    path: String,
+    data: Vec<u8>,
+}
+
+/// decode a raw lzma stream: the 5 property bytes followed by the data, without the size. Installers
+/// write it without an end marker, so whatever could be decoded is returned
+fn lzma(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(None),
+        memlimit: Some(MAX_DECOMPRESSED as usize),
+        allow_incomplete: true,
+    };
+    let mut stream = lzma_rs::decompress::Stream::new_with_options(&options, vec![]);
+    // a failing write leaves the output decoded so far in the stream
+    let _ = stream.write_all(data);
+    stream.finish().map_err(|e| format_err!("lzma: {e:?}"))
+}
+
+fn pe_resource<Pe: ImageNtHeaders>(data: &[u8], names: &[&str]) -> Option<Vec<u8>> {
+    let pe = PeFile::<Pe>::parse(data).ok()?;
+    let sections = pe.section_table();
+    let resources = pe
+        .data_directories()
+        .resource_directory(data, &sections)
+        .ok()??;
+    let by_type = resources.root().ok()?;
+    let rcdata = by_type
+        .entries
+        .iter()
+        .find(|e| matches!(e.name_or_id(), ResourceNameOrId::Id(RT_RCDATA)))?
+        .data(resources)
+        .ok()?
+        .table()?;
+    for entry in rcdata.entries {
+        let ResourceNameOrId::Name(name) = entry.name_or_id() else {
+            continue;
+        };
+        if !names.contains(&name.to_string_lossy(resources).ok()?.as_str()) {
+            continue;
+        }
+        // the first language
+        let languages = entry.data(resources).ok()?.table()?;
+        let ResourceDirectoryEntryData::Data(res) =
+            languages.entries.first()?.data(resources).ok()?
+        else {
+            continue;
+        };
+        let rva = res.offset_to_data.get(LE);
+        let size = res.size.get(LE) as usize;
+        return Some(sections.pe_data_at(data, rva)?.get(..size)?.to_vec());
+    }
+    None
+}
+
+/// the script of a compiled AutoHotkey (v1.1+ and v2) exe, stored as plain text resource
+fn autohotkey_script(data: &[u8]) -> Option<Vec<u8>> {
+    pe_resource::<ImageNtHeaders32>(data, AHK_RESOURCES)
+        .or_else(|| pe_resource::<ImageNtHeaders64>(data, AHK_RESOURCES))
+}
+
+/// the members of whichever installer format `data` is, empty for other executables
+fn extract(data: &[u8]) -> Result<Vec<Member>> {
+    if let Some(pos) = nsis::find(data) {
+        return nsis::extract(data, pos);
+    }
+    if let Some(pos) = inno::find(data) {
+        return inno::extract(data, pos);
+    }
+    Ok(autohotkey_script(data)
+        .map(|script| Member {
+            path: "script.ahk".to_string(),
+            data: script,
+        })
+        .into_iter()
+        .collect())
+}
+
+#[async_trait]
+impl FileAdapter for InstallerAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let members = tokio::task::spawn_blocking(move || extract(&data)).await??;
+        let files = members.into_iter().map(|m| (m.path, m.data));
+        adapt_vfs(Arc::new(MemoryFs::new(files)), container).await
+    }
+}
@@ -0,0 +1,118 @@
+//! Inno Setup installers: the strings of the compressed setup header, which holds the compiled
+//! script (file names and destinations, registry values, `[Run]` command lines, urls and messages).
+//!
+//! The files themselves are in separately compressed (and possibly encrypted) chunks and aren't extracted.
+use super::{Member, lzma};
+use crate::adapters::executable::{ascii_strings, utf16_strings};
+use crate::adapters::le::u32_at;
+use anyhow::*;
+use std::io::Read;
+
+const SIGNATURE: &[u8] = b"Inno Setup Setup Data (";
+/// the signature is padded to this length, the header block follows
+const ID_LEN: usize = 64;
+/// the stored header is split into chunks that each start with a crc32
+const CHUNK_LEN: usize = 4096;
+const MIN_STRING_LEN: usize = 4;
+
+/// offset of the setup data signature
+pub fn find(data: &[u8]) -> Option<usize> {
+    memchr::memmem::find(data, SIGNATURE)
+}
+
+/// the version from a signature like `Inno Setup Setup Data (5.5.7) (u)`
+fn version(id: &[u8]) -> Option<(u32, u32, u32)> {
+    let id = std::str::from_utf8(&id[SIGNATURE.len()..]).ok()?;
+    let mut parts = id.split(')').next()?.split('.').map(|p| p.parse().ok());
+    Some((parts.next()??, parts.next()??, parts.next()??))
+}
+
+/// the decompressed setup header of the installer whose signature is at `pos`
+fn header(data: &[u8], pos: usize) -> Result<Vec<u8>> {
+    let id = data
+        .get(pos..pos + ID_LEN)
+        .context("truncated inno setup id")?;
+    let version = version(id).context("unknown inno setup version")?;
+    if version < (4, 0, 9) {
+        return Err(format_err!(
+            "inno setup {}.{}.{} is too old to be read",
+            version.0,
+            version.1,
+            version.2
+        ));
+    }
+    let block = &data[pos + ID_LEN..];
+    // crc32, stored size and a compressed flag
+    let stored_size = u32_at(block, 4).context("truncated inno setup header")? as usize;
+    let compressed = block.get(8).context("truncated inno setup header")? != &0;
+    let stored = block
+        .get(9..9 + stored_size)
+        .context("truncated inno setup header")?;
+    let mut joined = Vec::with_capacity(stored_size);
+    for chunk in stored.chunks(CHUNK_LEN + 4) {
+        joined.extend(chunk.get(4..).unwrap_or_default());
+    }
+    if !compressed {
+        return Ok(joined);
+    }
+    if version >= (4, 1, 6) {
+        lzma(&joined)
+    } else {
+        let mut out = vec![];
+        flate2::read::ZlibDecoder::new(joined.as_slice()).read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// the strings of the setup header of the installer whose signature is at `pos`
+pub fn extract(data: &[u8], pos: usize) -> Result<Vec<Member>> {
+    let header = header(data, pos)?;
+    // unicode builds store most strings as utf-16, the license texts stay ansi in both
+    let mut strings: Vec<String> = ascii_strings(&header, MIN_STRING_LEN)
+        .map(|s| s.to_string())
+        .collect();
+    strings.extend(utf16_strings(&header, MIN_STRING_LEN));
+    Ok(vec![Member {
+        path: "[inno setup header].txt".to_string(),
+        data: (strings.join("\n") + "\n").into_bytes(),
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lzma_header() -> Result<()> {
+        // length prefixed strings, the second one utf-16
+        let mut content = b"\x0e\0\0\0{app}\\tool.exe\x1c\0\0\0".to_vec();
+        for c in "https://ex.com".encode_utf16() {
+            content.extend(c.to_le_bytes());
+        }
+        let mut compressed = vec![];
+        lzma_rs::lzma_compress(&mut content.as_slice(), &mut compressed)?;
+        // inno setup keeps the properties but not the size
+        compressed.drain(5..13);
+
+        let mut data = b"MZ padding".to_vec();
+        let pos = data.len();
+        let mut id = b"Inno Setup Setup Data (5.5.7) (u)".to_vec();
+        id.resize(ID_LEN, 0);
+        data.extend(id);
+        data.extend(0u32.to_le_bytes());
+        data.extend((compressed.len() as u32 + 4).to_le_bytes());
+        data.push(1);
+        data.extend(0u32.to_le_bytes());
+        data.extend(compressed);
+
+        assert_eq!(find(&data), Some(pos));
+        let members = extract(&data, pos)?;
+        assert_eq!(members.len(), 1);
+        assert_eq!(
+            String::from_utf8_lossy(&members[0].data),
+            "{app}\\tool.exe\nhttps://ex.com\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,521 @@
+//! Nullsoft (NSIS) installers: the install script's strings and the files it extracts.
+//!
+//! The exe stub is followed by a "first header" at a 512 byte aligned offset. After it comes either one
+//! solid compressed stream (`u32` header length, header, then `u32` length prefixed files) or separately
+//! compressed blocks, each with a `u32` length whose high bit marks compressed data.
+use super::{MAX_DECOMPRESSED, Member, lzma};
+use crate::adapters::le::{u16_at, u32_at};
+use anyhow::*;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+const SIGNATURE: &[u8] = b"\xef\xbe\xad\xdeNullsoftInst";
+/// flags, signature, header length, total length
+const FIRST_HEADER_LEN: usize = 28;
+/// header blocks: pages, sections, entries, strings, language tables, colors, fonts, data
+const BLOCKS: usize = 8;
+const BLOCK_ENTRIES: usize = 2;
+const BLOCK_STRINGS: usize = 3;
+/// an entry is an opcode and six parameters
+const ENTRY_LEN: usize = 28;
+const EW_EXTRACTFILE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    Lzma { x86_filter: bool },
+    Bzip2,
+    Deflate,
+}
+
+/// offset of the first header. Only 512 byte boundaries are checked, like the NSIS stub does
+pub fn find(data: &[u8]) -> Option<usize> {
+    (0..data.len())
+        .step_by(512)
+        .find(|&pos| data.get(pos + 4..pos + 4 + SIGNATURE.len()) == Some(SIGNATURE))
+}
+
+/// guess the compression from the first bytes of a stream, as makensis doesn't record it
+fn detect(sig: &[u8]) -> Method {
+    // lzma properties byte 0x5d and a dictionary size below 4GB, optionally after the filter flag
+    let is_lzma = |s: &[u8]| s.len() >= 5 && s[0] == 0x5d && s[4] < 0x80;
+    if is_lzma(sig) {
+        Method::Lzma { x86_filter: false }
+    } else if sig.first().is_some_and(|b| *b <= 1) && is_lzma(&sig[1..]) {
+        Method::Lzma { x86_filter: true }
+    } else if sig.first() == Some(&0x31) {
+        // a bzip2 block without the stream header
+        Method::Bzip2
+    } else {
+        Method::Deflate
+    }
+}
+
+/// decompress as much of `data` as possible. Streams are often cut off or lack an end marker, so
+/// errors after some output are ignored
+fn decompress(method: Method, data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    match method {
+        Method::Lzma { x86_filter } => {
+            let data = if x86_filter { &data[1..] } else { data };
+            out = lzma(data)?;
+            if x86_filter {
+                bcj_x86_decode(&mut out);
+            }
+        }
+        Method::Bzip2 => {
+            let framed = [b"BZh9".as_slice(), data].concat();
+            let r = bzip2::read::BzDecoder::new(framed.as_slice())
+                .take(MAX_DECOMPRESSED)
+                .read_to_end(&mut out);
+            r.or_else(|e| {
+                if out.is_empty() {
+                    Err(e)
+                } else {
+                    Result::Ok(0)
+                }
+            })?;
+        }
+        Method::Deflate => {
+            let r = flate2::read::DeflateDecoder::new(data)
+                .take(MAX_DECOMPRESSED)
+                .read_to_end(&mut out);
+            r.or_else(|e| {
+                if out.is_empty() {
+                    Err(e)
+                } else {
+                    Result::Ok(0)
+                }
+            })?;
+        }
+    }
+    out.truncate(MAX_DECOMPRESSED as usize);
+    Ok(out)
+}
+
+/// undo the x86 branch conversion (BCJ) applied before lzma compression, so the strings in
+/// extracted executables are readable again. A port of `x86_Convert` from the LZMA SDK
+fn bcj_x86_decode(data: &mut [u8]) {
+    const ALLOWED: [bool; 8] = [true, true, true, false, true, false, false, false];
+    const BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+    let test = |b: u8| b == 0 || b == 0xff;
+    if data.len() < 5 {
+        return;
+    }
+    let limit = data.len() - 4;
+    let mut prev_mask = 0u32;
+    let mut prev_pos = usize::MAX;
+    let mut pos = 0;
+    loop {
+        while pos < limit && data[pos] & 0xfe != 0xe8 {
+            pos += 1;
+        }
+        if pos >= limit {
+            break;
+        }
+        let distance = pos.wrapping_sub(prev_pos);
+        if distance > 3 {
+            prev_mask = 0;
+        } else {
+            prev_mask = (prev_mask << (distance - 1)) & 7;
+            if prev_mask != 0 {
+                let b = data[pos + 4 - BIT_NUMBER[prev_mask as usize] as usize];
+                if !ALLOWED[prev_mask as usize] || test(b) {
+                    prev_pos = pos;
+                    prev_mask = ((prev_mask << 1) & 7) | 1;
+                    pos += 1;
+                    continue;
+                }
+            }
+        }
+        prev_pos = pos;
+        if test(data[pos + 4]) {
+            let mut src = u32::from_le_bytes(data[pos + 1..pos + 5].try_into().unwrap());
+            let ip = (pos as u32).wrapping_add(5);
+            let mut dest;
+            loop {
+                dest = src.wrapping_sub(ip);
+                if prev_mask == 0 {
+                    break;
+                }
+                let index = BIT_NUMBER[prev_mask as usize] * 8;
+                if !test((dest >> (24 - index)) as u8) {
+                    break;
+                }
+                src = dest ^ ((1 << (32 - index)) - 1);
+            }
+            data[pos + 1..pos + 4].copy_from_slice(&dest.to_le_bytes()[..3]);
+            data[pos + 4] = if (dest >> 24) & 1 != 0 { 0xff } else { 0 };
+            pos += 5;
+        } else {
+            prev_mask = ((prev_mask << 1) & 7) | 1;
+            pos += 1;
+        }
+    }
+}
+
+/// name of the built-in variable `n` ($0-$9, $R0-$R9, then the predefined ones)
+fn variable(n: u16) -> String {
+    const PREDEFINED: &[&str] = &[
+        "CMDLINE",
+        "INSTDIR",
+        "OUTDIR",
+        "EXEDIR",
+        "LANGUAGE",
+        "TEMP",
+        "PLUGINSDIR",
+        "EXEPATH",
+        "EXEFILE",
+        "HWNDPARENT",
+        "_CLICK",
+        "_OUTDIR",
+    ];
+    match n {
+        0..=9 => format!("${n}"),
+        10..=19 => format!("$R{}", n - 10),
+        _ => match PREDEFINED.get(n as usize - 20) {
+            Some(name) => format!("${name}"),
+            None => format!("$_{n}_"),
+        },
+    }
+}
+
+/// the string table with the escape codes for variables, shell folders and language strings rendered
+/// in script syntax. Offsets are in characters, like the references in the entries
+struct Strings<'a> {
+    data: &'a [u8],
+    unicode: bool,
+}
+
+impl Strings<'_> {
+    fn new(data: &[u8]) -> Strings<'_> {
+        // the first string is always empty, so a unicode table starts with a 16 bit zero
+        let unicode = data.len() >= 2 && data[0] == 0 && data[1] == 0;
+        Strings { data, unicode }
+    }
+
+    fn special(&self, code: u32, value: u16) -> String {
+        let (lang, shell, var) = if self.unicode {
+            (1, 2, 3)
+        } else {
+            (255, 254, 253)
+        };
+        match code {
+            c if c == var => variable(value),
+            c if c == shell => format!("$SHELLFOLDER_{:#x}", value),
+            c if c == lang => format!("$(LSTR_{value})"),
+            _ => String::new(),
+        }
+    }
+
+    /// the string starting at character `offset` and the offset after its terminator
+    fn get(&self, offset: usize) -> (String, usize) {
+        let mut out = String::new();
+        let mut pos = offset;
+        if self.unicode {
+            let unit = |p: usize| u16_at(self.data, p * 2);
+            let mut units = vec![];
+            while let Some(c) = unit(pos) {
+                pos += 1;
+                match c {
+                    0 => break,
+                    1..=3 => {
+                        out.push_str(&String::from_utf16_lossy(&units));
+                        units.clear();
+                        let value = unit(pos).unwrap_or(0) & 0x7fff;
+                        pos += 1;
+                        out.push_str(&self.special(c as u32, value));
+                    }
+                    4 => {
+                        // skip code: the next character is literal
+                        units.extend(unit(pos));
+                        pos += 1;
+                    }
+                    _ => units.push(c),
+                }
+            }
+            out.push_str(&String::from_utf16_lossy(&units));
+        } else {
+            let mut bytes = vec![];
+            while let Some(&c) = self.data.get(pos) {
+                pos += 1;
+                match c {
+                    0 => break,
+                    253..=255 => {
+                        out.push_str(&String::from_utf8_lossy(&bytes));
+                        bytes.clear();
+                        let b = self.data.get(pos..pos + 2).unwrap_or(&[0, 0]);
+                        let value = (b[0] as u16 & 0x7f) | ((b[1] as u16 & 0x7f) << 7);
+                        pos += 2;
+                        out.push_str(&self.special(c as u32, value));
+                    }
+                    252 => {
+                        bytes.extend(self.data.get(pos));
+                        pos += 1;
+                    }
+                    _ => bytes.push(c),
+                }
+            }
+            out.push_str(&String::from_utf8_lossy(&bytes));
+        }
+        (out, pos)
+    }
+
+    fn all(&self) -> Vec<String> {
+        let len = if self.unicode {
+            self.data.len() / 2
+        } else {
+            self.data.len()
+        };
+        let mut out = vec![];
+        let mut pos = 0;
+        while pos < len {
+            let (s, next) = self.get(pos);
+            if !s.is_empty() {
+                out.push(s);
+            }
+            pos = next;
+        }
+        out
+    }
+}
+
+/// where the file data referenced by the script lives
+enum Files<'a> {
+    /// blocks that are each compressed (or stored) on their own
+    Blocks { data: &'a [u8], method: Method },
+    /// the rest of the solid stream after the header
+    Solid(Vec<u8>),
+}
+
+impl Files<'_> {
+    fn read(&self, offset: usize) -> Option<Vec<u8>> {
+        match self {
+            Files::Blocks { data, method } => {
+                let len = u32_at(data, offset)?;
+                let start = offset + 4;
+                let block = data.get(start..start + (len & 0x7fff_ffff) as usize)?;
+                if len & 0x8000_0000 != 0 {
+                    decompress(*method, block).ok()
+                } else {
+                    Some(block.to_vec())
+                }
+            }
+            Files::Solid(stream) => {
+                let len = u32_at(stream, offset)? as usize;
+                let start = offset + 4;
+                // the last file may be cut off when decompression stopped early
+                stream
+                    .get(start..(start + len).min(stream.len()))
+                    .map(|d| d.to_vec())
+            }
+        }
+    }
+}
+
+/// the header and the file data of the installer whose first header is at `pos`
+fn split(data: &[u8], pos: usize) -> Result<(Vec<u8>, Files<'_>)> {
+    let header_len = u32_at(data, pos + 20).context("truncated nsis first header")? as usize;
+    let body = data
+        .get(pos + FIRST_HEADER_LEN..)
+        .context("truncated nsis first header")?;
+    let first = u32_at(body, 0).context("truncated nsis data")?;
+    let compressed = first & 0x8000_0000 != 0;
+    let first_len = (first & 0x7fff_ffff) as usize;
+    if compressed || first_len == header_len {
+        let block = body
+            .get(4..4 + first_len)
+            .context("truncated nsis header")?;
+        let method = detect(block);
+        let header = if compressed {
+            decompress(method, block)?
+        } else {
+            block.to_vec()
+        };
+        let rest = &body[4 + first_len..];
+        // with a stored header the files may still be compressed, look at the first block
+        let method = if compressed {
+            method
+        } else {
+            detect(rest.get(4..).unwrap_or_default())
+        };
+        Ok((header, Files::Blocks { data: rest, method }))
+    } else {
+        let stream = decompress(detect(body), body)?;
+        let header_len = u32_at(&stream, 0).context("truncated nsis solid stream")? as usize;
+        let header = stream
+            .get(4..4 + header_len)
+            .context("truncated nsis header")?
+            .to_vec();
+        let rest = stream[4 + header_len..].to_vec();
+        Ok((header, Files::Solid(rest)))
+    }
+}
+
+/// a member path from a script file name like `$INSTDIR\bin\tool.exe`
+fn member_path(name: &str) -> String {
+    name.replace('\\', "/").trim_start_matches('/').to_string()
+}
+
+/// the script strings and the extracted files of the installer whose first header is at `pos`
+pub fn extract(data: &[u8], pos: usize) -> Result<Vec<Member>> {
+    let (header, files) = split(data, pos)?;
+    let block = |i: usize| -> Option<(usize, usize)> {
+        let at = 4 + i * 8;
+        Some((
+            u32_at(&header, at)? as usize,
+            u32_at(&header, at + 4)? as usize,
+        ))
+    };
+    let (strings_start, _) = block(BLOCK_STRINGS).context("truncated nsis header")?;
+    // the string table runs until the next block
+    let strings_end = (0..BLOCKS)
+        .filter_map(block)
+        .map(|(offset, _)| offset)
+        .filter(|&o| o > strings_start)
+        .min()
+        .unwrap_or(header.len())
+        .min(header.len());
+    let strings = Strings::new(header.get(strings_start..strings_end).unwrap_or_default());
+
+    let mut members = vec![Member {
+        path: "[nsis strings].txt".to_string(),
+        data: (strings.all().join("\n") + "\n").into_bytes(),
+    }];
+
+    let (entries_start, entry_count) = block(BLOCK_ENTRIES).context("truncated nsis header")?;
+    // the same data offset is referenced once per `File` command that installs it
+    let mut extracted = BTreeMap::new();
+    for i in 0..entry_count {
+        let at = entries_start + i * ENTRY_LEN;
+        let Some(entry) = header.get(at..at + ENTRY_LEN) else {
+            break;
+        };
+        let param = |n: usize| u32_at(entry, 4 + n * 4).unwrap_or(0) as usize;
+        if u32_at(entry, 0) != Some(EW_EXTRACTFILE) {
+            continue;
+        }
+        let (name, _) = strings.get(param(1));
+        extracted.entry(param(2)).or_insert(name);
+    }
+    for (offset, name) in extracted {
+        let Some(data) = files.read(offset) else {
+            continue;
+        };
+        let path = match member_path(&name) {
+            p if p.is_empty() => format!("file{offset}"),
+            p => p,
+        };
+        members.push(Member { path, data });
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    fn push_u32(v: &mut Vec<u8>, n: u32) {
+        v.extend(n.to_le_bytes());
+    }
+
+    /// a minimal installer: one `File` entry and a string table with a variable
+    fn installer(compress: bool) -> Vec<u8> {
+        let strings = b"\0\xfd\x15\x00\\setup.cfg\0https://example.com/update\0".to_vec();
+        let mut header = vec![];
+        push_u32(&mut header, 0);
+        let blocks_end = 4 + BLOCKS as u32 * 8;
+        for i in 0..BLOCKS {
+            match i {
+                BLOCK_ENTRIES => {
+                    push_u32(&mut header, blocks_end);
+                    push_u32(&mut header, 1);
+                }
+                BLOCK_STRINGS => {
+                    push_u32(&mut header, blocks_end + ENTRY_LEN as u32);
+                    push_u32(&mut header, 0);
+                }
+                _ => {
+                    push_u32(&mut header, 0);
+                    push_u32(&mut header, 0);
+                }
+            }
+        }
+        for param in [EW_EXTRACTFILE, 0, 1, 0, 0, 0, 0] {
+            push_u32(&mut header, param);
+        }
+        header.extend(strings);
+
+        let file = b"server=https://example.com\n";
+        let mut out = vec![b'M', b'Z'];
+        out.resize(512, 0);
+        push_u32(&mut out, 0);
+        out.extend(SIGNATURE);
+        push_u32(&mut out, header.len() as u32);
+        push_u32(&mut out, 0);
+        if compress {
+            let deflate = |d: &[u8]| {
+                let mut e = flate2::write::DeflateEncoder::new(vec![], Default::default());
+                e.write_all(d).unwrap();
+                e.finish().unwrap()
+            };
+            for block in [header.as_slice(), file] {
+                let block = deflate(block);
+                push_u32(&mut out, block.len() as u32 | 0x8000_0000);
+                out.extend(block);
+            }
+        } else {
+            for block in [header.as_slice(), file] {
+                push_u32(&mut out, block.len() as u32);
+                out.extend(block);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn blocks() -> Result<()> {
+        for compress in [false, true] {
+            let data = installer(compress);
+            let pos = find(&data).context("no header")?;
+            assert_eq!(pos, 512);
+            let members = extract(&data, pos)?;
+            let members = members
+                .iter()
+                .map(|m| {
+                    (
+                        m.path.as_str(),
+                        String::from_utf8_lossy(&m.data).into_owned(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                members,
+                vec![
+                    (
+                        "[nsis strings].txt",
+                        "$INSTDIR\\setup.cfg\nhttps://example.com/update\n".to_string()
+                    ),
+                    (
+                        "$INSTDIR/setup.cfg",
+                        "server=https://example.com\n".to_string()
+                    ),
+                ]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lzma_without_size() -> Result<()> {
+        let text = b"nsis solid stream ".repeat(50);
+        let mut compressed = vec![];
+        lzma_rs::lzma_compress(&mut text.as_slice(), &mut compressed)?;
+        // nsis stores the properties without the 64 bit size
+        compressed.drain(5..13);
+        assert_eq!(detect(&compressed), Method::Lzma { x86_filter: false });
+        assert_eq!(decompress(detect(&compressed), &compressed)?, text);
+        Ok(())
+    }
+}
@@ -0,0 +1,559 @@
+//! ISO 9660 and UDF disc images, read in place as a [`Vfs`] instead of loop-mounting them.
+mod udf;
+
+use super::*;
+use crate::vfs::{Vfs, VfsEntry, adapt_vfs};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["iso", "udf"];
+
+const SECTOR: u64 = 2048;
+/// volume descriptors start after the system area
+const FIRST_DESCRIPTOR: u64 = 16;
+/// directories nested deeper than this are skipped (directory loops in broken images)
+const MAX_DEPTH: usize = 64;
+/// directories are only read up to this size, their extents can be unrecorded and read as zeros, so their
+/// size isn't bounded by the image
+const MAX_DIRECTORY_LEN: u64 = 16 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "iso".to_owned(),
+        version: 1,
+        description: "Lists the files of ISO 9660 (with Joliet or Rock Ridge names) and UDF disc images and recurses into them".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-iso9660-image".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IsoAdapter;
+
+impl IsoAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for IsoAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a run of bytes of a file in the image. Unrecorded (sparse) extents have no offset and read as zeros
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Extent {
+    pub(super) offset: Option<u64>,
+    pub(super) len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ImageFile {
+    pub(super) path: String,
+    pub(super) size: u64,
+    pub(super) mtime_unix: Option<i64>,
+    pub(super) extents: Vec<Extent>,
+}
+
+/// `len` bytes at `offset`. Offsets and lengths come from the image, so they are checked against its length
+/// before anything is allocated
+pub(super) fn read_at<R: Read + Seek>(r: &mut R, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let image_len = r.seek(SeekFrom::End(0))?;
+    if offset
+        .checked_add(len as u64)
+        .is_none_or(|end| end > image_len)
+    {
+        bail!("invalid image, {len} bytes at {offset} are past its end");
+    }
+    r.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)
+        .with_context(|| format!("reading {len} bytes at offset {offset}"))?;
+    Ok(buf)
+}
+
+/// the first `size` bytes of the extents (at most [`MAX_DIRECTORY_LEN`]), for reading directories
+fn read_extents<R: Read + Seek>(r: &mut R, extents: &[Extent], size: u64) -> Result<Vec<u8>> {
+    let size = size.min(MAX_DIRECTORY_LEN);
+    let mut out = vec![];
+    for extent in extents {
+        let len = extent.len.min(size - out.len() as u64) as usize;
+        match extent.offset {
+            Some(offset) => out.extend(read_at(r, offset, len)?),
+            None => out.resize(out.len() + len, 0),
+        }
+        if out.len() as u64 >= size {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// seconds since the epoch of a civil date and time, `None` for unset (all zero) dates
+fn unix_time(year: i64, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<i64> {
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    // days_from_civil from http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    Some(days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64)
+}
+
+/// which of the directory trees of an ISO 9660 image names are taken from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Names {
+    /// 8.3 upper case names
+    Primary,
+    /// utf-16 names in the supplementary tree
+    Joliet,
+    /// posix names in the system use area of the primary tree
+    RockRidge,
+}
+
+struct Record<'a> {
+    extent: u32,
+    size: u32,
+    /// the 7 byte recording date
+    date: &'a [u8],
+    flags: u8,
+    id: &'a [u8],
+    system_use: &'a [u8],
+}
+
+const FLAG_DIRECTORY: u8 = 2;
+/// more records for the same file follow (files over 4GB)
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+impl Record<'_> {
+    fn parse(d: &[u8]) -> Option<Record<'_>> {
+        let len = *d.first()? as usize;
+        let d = d.get(..len)?;
+        let id_len = *d.get(32)? as usize;
+        let id = d.get(33..33 + id_len)?;
+        // the id is padded to an even length
+        let system_use = d.get(33 + id_len + (1 - id_len % 2)..).unwrap_or_default();
+        Some(Record {
+            extent: u32::from_le_bytes(d[2..6].try_into().ok()?),
+            size: u32::from_le_bytes(d[10..14].try_into().ok()?),
+            date: &d[18..25],
+            flags: d[25],
+            id,
+            system_use,
+        })
+    }
+
+    fn is_special(&self) -> bool {
+        // "." and ".."
+        self.id == [0] || self.id == [1]
+    }
+
+    fn mtime_unix(&self) -> Option<i64> {
+        let d = self.date;
+        let local = unix_time(1900 + d[0] as i64, d[1], d[2], d[3], d[4], d[5])?;
+        // offset from gmt in 15 minute intervals
+        Some(local - d[6] as i8 as i64 * 15 * 60)
+    }
+
+    /// the system use entry `sig` (Rock Ridge / SUSP)
+    fn system_use_entries<'a>(&'a self, sig: &'a [u8; 2]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let mut rest = self.system_use;
+        std::iter::from_fn(move || {
+            loop {
+                if rest.len() < 4 || rest[2] < 4 {
+                    return None;
+                }
+                let (entry, next) = rest.split_at((rest[2] as usize).min(rest.len()));
+                rest = next;
+                if &entry[..2] == sig {
+                    return Some(&entry[4..]);
+                }
+            }
+        })
+    }
+
+    fn name(&self, names: Names) -> String {
+        let name = match names {
+            Names::RockRidge => {
+                // NM entries: a flags byte and a piece of the name
+                let name: Vec<u8> = self
+                    .system_use_entries(b"NM")
+                    .flat_map(|nm| nm.get(1..).unwrap_or_default())
+                    .copied()
+                    .collect();
+                if !name.is_empty() {
+                    return String::from_utf8_lossy(&name).into_owned();
+                }
+                String::from_utf8_lossy(self.id).into_owned()
+            }
+            Names::Joliet => {
+                let units: Vec<u16> = self
+                    .id
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            Names::Primary => String::from_utf8_lossy(self.id).into_owned(),
+        };
+        // strip the version (`;1`) and the dot of names without extension
+        let name = match name.rsplit_once(';') {
+            Some((name, version)) if version.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => &name,
+        };
+        name.strip_suffix('.').unwrap_or(name).to_string()
+    }
+}
+
+/// the records of a directory
+fn directory_records(data: &[u8]) -> Vec<Record<'_>> {
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        if data[pos] == 0 {
+            // records don't cross sector boundaries, the rest of the sector is padding
+            pos = (pos as u64 / SECTOR + 1) as usize * SECTOR as usize;
+            continue;
+        }
+        let Some(record) = Record::parse(&data[pos..]) else {
+            break;
+        };
+        pos += data[pos] as usize;
+        out.push(record);
+    }
+    out
+}
+
+fn walk_iso9660<R: Read + Seek>(
+    r: &mut R,
+    extent: u32,
+    size: u32,
+    dir: &str,
+    names: Names,
+    visited: &mut HashSet<u32>,
+    files: &mut Vec<ImageFile>,
+) -> Result<()> {
+    if dir.matches('/').count() >= MAX_DEPTH || !visited.insert(extent) {
+        return Ok(());
+    }
+    let data = read_at(r, extent as u64 * SECTOR, size as usize)?;
+    let mut continues = false;
+    for record in directory_records(&data) {
+        if record.is_special() {
+            continue;
+        }
+        let extent = Extent {
+            offset: Some(record.extent as u64 * SECTOR),
+            len: record.size as u64,
+        };
+        let previous_continues =
+            std::mem::replace(&mut continues, record.flags & FLAG_MULTI_EXTENT != 0);
+        if let (true, Some(file)) = (previous_continues, files.last_mut()) {
+            file.size += extent.len;
+            file.extents.push(extent);
+            continue;
+        }
+        let name = record.name(names);
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{dir}/{name}")
+        };
+        if record.flags & FLAG_DIRECTORY != 0 {
+            walk_iso9660(r, record.extent, record.size, &path, names, visited, files)?;
+        } else {
+            files.push(ImageFile {
+                path,
+                size: extent.len,
+                mtime_unix: record.mtime_unix(),
+                extents: vec![extent],
+            });
+        }
+    }
+    Ok(())
+}
+
+/// the volume descriptors of ISO 9660 and UDF come after 32 KiB of system area, so this needs a
+/// large enough sniff window. Only used with --rga-accurate.
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    let start = (FIRST_DESCRIPTOR * SECTOR) as usize + 1;
+    let id = buf.get(start..start + 5)?;
+    matches!(id, b"CD001" | b"BEA01").then_some("application/x-iso9660-image")
+}
+
+/// the files of the ISO 9660 file system, preferring Rock Ridge over Joliet over the primary names
+fn read_iso9660<R: Read + Seek>(r: &mut R) -> Result<Vec<ImageFile>> {
+    let mut primary = None;
+    let mut joliet = None;
+    for sector in FIRST_DESCRIPTOR.. {
+        let d = read_at(r, sector * SECTOR, SECTOR as usize)?;
+        if &d[1..6] != b"CD001" {
+            return Err(format_err!("not an ISO 9660 image"));
+        }
+        match d[0] {
+            1 => primary = Some(d),
+            // the escape sequences of UCS-2 level 1-3
+            2 if matches!(&d[88..91], b"%/@" | b"%/C" | b"%/E") => joliet = Some(d),
+            255 => break,
+            _ => {}
+        }
+    }
+    let primary = primary.context("no primary volume descriptor")?;
+    let root = |d: &[u8]| -> Result<(u32, u32)> {
+        let record = Record::parse(&d[156..190]).context("invalid root directory record")?;
+        Ok((record.extent, record.size))
+    };
+    // the "." record of the root starts with the SUSP indicator when Rock Ridge is used
+    let (extent, size) = root(&primary)?;
+    let root_data = read_at(r, extent as u64 * SECTOR, SECTOR as usize)?;
+    let rock_ridge = Record::parse(&root_data).is_some_and(|dot| dot.system_use.starts_with(b"SP"));
+    let (names, (extent, size)) = match (rock_ridge, &joliet) {
+        (true, _) => (Names::RockRidge, (extent, size)),
+        (false, Some(joliet)) => (Names::Joliet, root(joliet)?),
+        (false, None) => (Names::Primary, (extent, size)),
+    };
+    let mut files = vec![];
+    walk_iso9660(r, extent, size, "", names, &mut HashSet::new(), &mut files)?;
+    Ok(files)
+}
+
+/// the files of the image. UDF is preferred, as the ISO 9660 tree of UDF bridge images (DVDs, Windows
+/// installation media) often only holds a readme
+fn read_image<R: Read + Seek>(r: &mut R) -> Result<Vec<ImageFile>> {
+    match udf::read(r)? {
+        Some(files) => Ok(files),
+        None => read_iso9660(r),
+    }
+}
+
+/// the files of a disc image on the local file system
+pub struct IsoFs {
+    path: PathBuf,
+    files: tokio::sync::OnceCell<(Vec<ImageFile>, HashMap<String, usize>)>,
+}
+
+impl IsoFs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            files: Default::default(),
+        }
+    }
+
+    async fn files(&self) -> Result<&(Vec<ImageFile>, HashMap<String, usize>)> {
+        self.files
+            .get_or_try_init(|| async {
+                let path = self.path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let file = std::fs::File::open(&path)
+                        .with_context(|| format!("opening {}", path.display()))?;
+                    let files = read_image(&mut std::io::BufReader::new(file))?;
+                    let index = files
+                        .iter()
+                        .enumerate()
+                        .map(|(i, f)| (f.path.clone(), i))
+                        .collect();
+                    Ok((files, index))
+                })
+                .await?
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Vfs for IsoFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let (files, _) = self.files().await?;
+        Ok(files
+            .iter()
+            .map(|f| VfsEntry {
+                path: f.path.clone().into(),
+                size: f.size,
+                mtime_unix: f.mtime_unix,
+                version: f.mtime_unix.map(|m| m.to_string()).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let (files, index) = self.files().await?;
+        let file = index
+            .get(entry.path.to_str().unwrap_or_default())
+            .map(|i| &files[*i])
+            .with_context(|| format!("{} not found in {}", entry.path.display(), self.path.display()))?;
+        let mut image = tokio::fs::File::open(&self.path).await?;
+        let mut skip = offset;
+        let mut remaining = file.size.saturating_sub(offset);
+        for extent in &file.extents {
+            if skip >= extent.len {
+                skip -= extent.len;
+                continue;
+            }
+            let len = (extent.len - skip).min(remaining);
+            match extent.offset {
+                Some(start) => {
+                    image.seek(SeekFrom::Start(start + skip)).await?;
+                    tokio::io::copy(&mut (&mut image).take(len), oup).await?;
+                }
+                None => {
+                    tokio::io::copy(&mut tokio::io::repeat(0).take(len), oup).await?;
+                }
+            }
+            skip = 0;
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl FileAdapter for IsoAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (_inp, container) = ai.into_parts();
+        let vfs = Arc::new(IsoFs::new(&container.filepath_hint));
+        adapt_vfs(vfs, container).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn record(extent: u32, size: u32, flags: u8, id: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut r = vec![0u8; 33];
+        r[2..6].copy_from_slice(&extent.to_le_bytes());
+        r[10..14].copy_from_slice(&size.to_le_bytes());
+        // 2024-03-01 10:20:30 at gmt+1
+        r[18..25].copy_from_slice(&[124, 3, 1, 10, 20, 30, 4]);
+        r[25] = flags;
+        r[32] = id.len() as u8;
+        r.extend(id);
+        if id.len().is_multiple_of(2) {
+            r.push(0);
+        }
+        r.extend(system_use);
+        r[0] = r.len() as u8;
+        r
+    }
+
+    fn sector(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut s = records.concat();
+        s.resize(SECTOR as usize, 0);
+        s
+    }
+
+    /// a Rock Ridge image with `docs/Read Me.txt` and `top.txt`
+    fn image() -> Vec<u8> {
+        let sp = b"SP\x07\x01\xbe\xef\x00";
+        let nm = |name: &str| [b"NM", &[5 + name.len() as u8, 1, 0][..], name.as_bytes()].concat();
+        let mut image = vec![0u8; 16 * SECTOR as usize];
+        let mut pvd = vec![1u8];
+        pvd.extend(b"CD001\x01");
+        pvd.resize(156, 0);
+        pvd.extend(record(18, SECTOR as u32, FLAG_DIRECTORY, &[0], &[]));
+        pvd.resize(SECTOR as usize, 0);
+        image.extend(pvd);
+        image.extend(sector(&[[&[255u8][..], b"CD001\x01"].concat()]));
+        image.extend(sector(&[
+            record(18, SECTOR as u32, FLAG_DIRECTORY, &[0], sp),
+            record(18, SECTOR as u32, FLAG_DIRECTORY, &[1], &[]),
+            record(19, SECTOR as u32, FLAG_DIRECTORY, b"DOCS", &nm("docs")),
+            record(20, 5, 0, b"TOP.TXT;1", &nm("top.txt")),
+        ]));
+        image.extend(sector(&[
+            record(19, SECTOR as u32, FLAG_DIRECTORY, &[0], &[]),
+            record(18, SECTOR as u32, FLAG_DIRECTORY, &[1], &[]),
+            record(21, 7, 0, b"READ_ME.TXT;1", &nm("Read Me.txt")),
+        ]));
+        image.extend(sector(&[b"top\n\n".to_vec()]));
+        image.extend(sector(&[b"readme\n".to_vec()]));
+        image
+    }
+
+    #[test]
+    fn rock_ridge() -> Result<()> {
+        assert_eq!(sniff_mime(&image()), Some("application/x-iso9660-image"));
+        assert_eq!(sniff_mime(&image()[..8192]), None);
+        let files = read_image(&mut Cursor::new(image()))?;
+        let mtime = unix_time(2024, 3, 1, 9, 20, 30);
+        assert_eq!(
+            files,
+            vec![
+                ImageFile {
+                    path: "docs/Read Me.txt".to_string(),
+                    size: 7,
+                    mtime_unix: mtime,
+                    extents: vec![Extent {
+                        offset: Some(21 * SECTOR),
+                        len: 7
+                    }],
+                },
+                ImageFile {
+                    path: "top.txt".to_string(),
+                    size: 5,
+                    mtime_unix: mtime,
+                    extents: vec![Extent {
+                        offset: Some(20 * SECTOR),
+                        len: 5
+                    }],
+                },
+            ]
+        );
+        assert_eq!(mtime, Some(1709284830));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn iso_fs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("disc.iso");
+        std::fs::write(&path, image())?;
+        let vfs = IsoFs::new(&path);
+        let entries = vfs.list().await?;
+        let mut buf = vec![];
+        vfs.read_range(&entries[0], 2, &mut buf).await?;
+        assert_eq!(buf, b"adme\n");
+        Ok(())
+    }
+}
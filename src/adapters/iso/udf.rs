@@ -0,0 +1,399 @@
+//! UDF (ECMA-167) file systems, as used on DVDs, Blu-rays and Windows installation images.
+//!
+//! Only a single physical (type 1) partition is supported, which covers images written by the common
+//! mastering tools. Tag checksums are not verified.
+use super::{Extent, ImageFile, MAX_DEPTH, SECTOR, read_at, read_extents, unix_time};
+use crate::adapters::le::{u16_at, u32_at, u64_at};
+use anyhow::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+
+/// the anchor volume descriptor pointer is always at this sector
+const ANCHOR_SECTOR: u64 = 256;
+
+const TAG_ANCHOR: u16 = 2;
+const TAG_PARTITION: u16 = 5;
+const TAG_LOGICAL_VOLUME: u16 = 6;
+const TAG_TERMINATING: u16 = 8;
+const TAG_FILE_SET: u16 = 256;
+const TAG_FILE_ID: u16 = 257;
+const TAG_FILE_ENTRY: u16 = 261;
+const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+
+const FILE_TYPE_DIRECTORY: u8 = 4;
+/// file identifier characteristics
+const FID_DIRECTORY: u8 = 2;
+const FID_DELETED: u8 = 4;
+const FID_PARENT: u8 = 8;
+
+const TRUNCATED: &str = "udf: truncated descriptor";
+
+/// the partition files are addressed in
+struct Volume {
+    block_size: u64,
+    /// byte offset of the partition in the image
+    start: u64,
+}
+
+impl Volume {
+    fn block(&self, lb: u32) -> u64 {
+        self.start + lb as u64 * self.block_size
+    }
+
+    fn read_block<R: Read + Seek>(&self, r: &mut R, lb: u32) -> Result<Vec<u8>> {
+        read_at(r, self.block(lb), self.block_size as usize)
+    }
+}
+
+/// a file or directory (file entry)
+struct Node {
+    is_dir: bool,
+    size: u64,
+    mtime_unix: Option<i64>,
+    extents: Vec<Extent>,
+}
+
+/// a 12 byte timestamp: type and time zone, year, month, day, hour, minute, second, ...
+fn timestamp(d: &[u8]) -> Option<i64> {
+    let local = unix_time(u16_at(d, 2)? as i16 as i64, d[4], d[5], d[6], d[7], d[8])?;
+    let type_and_zone = u16_at(d, 0)?;
+    // 12 bit signed offset in minutes, -2047 if unspecified
+    let zone = ((type_and_zone << 4) as i16 >> 4) as i64;
+    if type_and_zone >> 12 == 1 && zone != -2047 {
+        Some(local - zone * 60)
+    } else {
+        Some(local)
+    }
+}
+
+/// an OSTA compressed unicode string: a compression id (8 or 16 bit characters) and the characters
+fn decode_name(d: &[u8]) -> String {
+    match d.split_first() {
+        Some((16, rest)) => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        Some((_, rest)) => rest.iter().map(|&b| b as char).collect(),
+        None => String::new(),
+    }
+}
+
+fn read_node<R: Read + Seek>(r: &mut R, vol: &Volume, lb: u32) -> Result<Node> {
+    let d = vol.read_block(r, lb)?;
+    // offsets of the modification time and the extended attribute length
+    let (mtime_at, ea_len_at) = match u16_at(&d, 0).context(TRUNCATED)? {
+        TAG_FILE_ENTRY => (84, 168),
+        TAG_EXTENDED_FILE_ENTRY => (92, 208),
+        tag => {
+            return Err(format_err!(
+                "udf: expected a file entry at block {lb}, got tag {tag}"
+            ));
+        }
+    };
+    // the ICB tag: file type at 11, flags with the allocation descriptor type at 18
+    let file_type = *d.get(16 + 11).context(TRUNCATED)?;
+    let ad_type = u16_at(&d, 16 + 18).context(TRUNCATED)? & 7;
+    let size = u64_at(&d, 56).context(TRUNCATED)?;
+    let ads_start = ea_len_at + 8 + u32_at(&d, ea_len_at).context(TRUNCATED)? as usize;
+    let ads_len = u32_at(&d, ea_len_at + 4).context(TRUNCATED)? as usize;
+    let ads = ads_start
+        .checked_add(ads_len)
+        .and_then(|end| d.get(ads_start..end))
+        .context("udf: allocation descriptors don't fit the file entry")?;
+    let short_or_long = |a: &[u8]| Some((u32_at(a, 0)?, u32_at(a, 4)?));
+    let raw: Vec<(u32, u32)> = match ad_type {
+        // short: length and block, long: length, block, partition and implementation use
+        0 => ads.chunks_exact(8).filter_map(short_or_long).collect(),
+        1 => ads.chunks_exact(16).filter_map(short_or_long).collect(),
+        // the data is embedded in the file entry
+        3 => vec![],
+        t => {
+            return Err(format_err!(
+                "udf: unsupported allocation descriptor type {t}"
+            ));
+        }
+    };
+    let mut extents = vec![];
+    if ad_type == 3 {
+        extents.push(Extent {
+            offset: Some(vol.block(lb) + ads_start as u64),
+            len: ads_len as u64,
+        });
+    }
+    for (len, block) in raw {
+        // the top two bits are the extent type
+        let (kind, len) = (len >> 30, (len & 0x3fff_ffff) as u64);
+        match kind {
+            _ if len == 0 => break,
+            0 => extents.push(Extent {
+                offset: Some(vol.block(block)),
+                len,
+            }),
+            1 | 2 => extents.push(Extent { offset: None, len }),
+            // continuation of the descriptors in another block, only needed for very fragmented files
+            _ => break,
+        }
+    }
+    Ok(Node {
+        is_dir: file_type == FILE_TYPE_DIRECTORY,
+        size,
+        mtime_unix: d.get(mtime_at..mtime_at + 12).and_then(timestamp),
+        extents,
+    })
+}
+
+fn walk<R: Read + Seek>(
+    r: &mut R,
+    vol: &Volume,
+    dir_lb: u32,
+    dir: &str,
+    visited: &mut HashSet<u32>,
+    files: &mut Vec<ImageFile>,
+) -> Result<()> {
+    if dir.matches('/').count() >= MAX_DEPTH || !visited.insert(dir_lb) {
+        return Ok(());
+    }
+    let node = read_node(r, vol, dir_lb)?;
+    let data = read_extents(r, &node.extents, node.size)?;
+    let mut pos = 0;
+    while pos + 38 <= data.len() {
+        let fid = &data[pos..];
+        if u16_at(fid, 0) != Some(TAG_FILE_ID) {
+            break;
+        }
+        let characteristics = fid[18];
+        let name_len = fid[19] as usize;
+        // the icb is a long allocation descriptor, the file entry is in its block
+        let icb = u32_at(fid, 24).context(TRUNCATED)?;
+        let name_at = 38 + u16_at(fid, 36).context(TRUNCATED)? as usize;
+        let name = decode_name(fid.get(name_at..name_at + name_len).unwrap_or_default());
+        // padded to a multiple of 4
+        pos += (name_at + name_len + 3) & !3;
+        if characteristics & (FID_DELETED | FID_PARENT) != 0 {
+            continue;
+        }
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{dir}/{name}")
+        };
+        if characteristics & FID_DIRECTORY != 0 {
+            walk(r, vol, icb, &path, visited, files)?;
+            continue;
+        }
+        let node = read_node(r, vol, icb)?;
+        if !node.is_dir {
+            files.push(ImageFile {
+                path,
+                size: node.size,
+                mtime_unix: node.mtime_unix,
+                extents: node.extents,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// the files of the UDF file system, `None` if the image has none
+pub(super) fn read<R: Read + Seek>(r: &mut R) -> Result<Option<Vec<ImageFile>>> {
+    let Result::Ok(anchor) = read_at(r, ANCHOR_SECTOR * SECTOR, SECTOR as usize) else {
+        return Ok(None);
+    };
+    if u16_at(&anchor, 0) != Some(TAG_ANCHOR) {
+        return Ok(None);
+    }
+    // the main volume descriptor sequence
+    let len = u32_at(&anchor, 16).context(TRUNCATED)? as u64;
+    let location = u32_at(&anchor, 20).context(TRUNCATED)? as u64;
+    let mut partitions = HashMap::new();
+    let mut logical_volume = None;
+    for sector in location..location + (len / SECTOR).min(256) {
+        let d = read_at(r, sector * SECTOR, SECTOR as usize)?;
+        match u16_at(&d, 0) {
+            Some(TAG_PARTITION) => {
+                partitions.insert(
+                    u16_at(&d, 22).context(TRUNCATED)?,
+                    u32_at(&d, 188).context(TRUNCATED)?,
+                );
+            }
+            Some(TAG_LOGICAL_VOLUME) => logical_volume = Some(d),
+            Some(TAG_TERMINATING) => break,
+            _ => {}
+        }
+    }
+    let lvd = logical_volume.context("udf: no logical volume descriptor")?;
+    let block_size = u32_at(&lvd, 212).context(TRUNCATED)? as u64;
+    // the first partition map: type, length, volume sequence number, partition number
+    let map = lvd.get(440..446).context(TRUNCATED)?;
+    if map[0] != 1 {
+        return Err(format_err!(
+            "udf: partition map type {} (virtual, sparable or metadata partitions) is not supported",
+            map[0]
+        ));
+    }
+    let start = partitions
+        .get(&u16_at(map, 4).context(TRUNCATED)?)
+        .context("udf: no descriptor for the partition")?;
+    let vol = Volume {
+        block_size,
+        start: *start as u64 * block_size,
+    };
+    // the logical volume contents use holds the location of the file set descriptor
+    let file_set = vol.read_block(r, u32_at(&lvd, 252).context(TRUNCATED)?)?;
+    if u16_at(&file_set, 0) != Some(TAG_FILE_SET) {
+        return Err(format_err!("udf: no file set descriptor"));
+    }
+    let mut files = vec![];
+    walk(
+        r,
+        &vol,
+        u32_at(&file_set, 404).context(TRUNCATED)?,
+        "",
+        &mut HashSet::new(),
+        &mut files,
+    )?;
+    Ok(Some(files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    const PARTITION: u32 = 260;
+
+    fn block(tag: u16, fields: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut b = vec![0u8; SECTOR as usize];
+        b[0..2].copy_from_slice(&tag.to_le_bytes());
+        for (at, bytes) in fields {
+            b[*at..at + bytes.len()].copy_from_slice(bytes);
+        }
+        b
+    }
+
+    fn file_entry(file_type: u8, size: u64, ads: &[u8]) -> Vec<u8> {
+        // 2024-03-01 10:20:30 utc
+        let mut mtime = vec![0x00, 0x10];
+        mtime.extend(2024u16.to_le_bytes());
+        mtime.extend([3, 1, 10, 20, 30]);
+        block(
+            TAG_FILE_ENTRY,
+            &[
+                (16 + 11, &[file_type]),
+                (56, &size.to_le_bytes()),
+                (84, &mtime),
+                (172, &(ads.len() as u32).to_le_bytes()),
+                (176, ads),
+            ],
+        )
+    }
+
+    fn short_ad(len: u32, lb: u32) -> Vec<u8> {
+        [len.to_le_bytes(), lb.to_le_bytes()].concat()
+    }
+
+    fn fid(characteristics: u8, icb: u32, name: &str) -> Vec<u8> {
+        let mut f = vec![0u8; 38];
+        f[0..2].copy_from_slice(&TAG_FILE_ID.to_le_bytes());
+        f[18] = characteristics;
+        f[24..28].copy_from_slice(&icb.to_le_bytes());
+        if !name.is_empty() {
+            f[19] = name.len() as u8 + 1;
+            f.push(8);
+            f.extend(name.as_bytes());
+        }
+        f.resize((f.len() + 3) & !3, 0);
+        f
+    }
+
+    /// anchor, volume descriptors and a partition with `hello.txt` in the root
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; ANCHOR_SECTOR as usize * SECTOR as usize];
+        let vds = [
+            (16, &(3 * SECTOR as u32).to_le_bytes()[..]),
+            (20, &257u32.to_le_bytes()),
+        ];
+        image.extend(block(TAG_ANCHOR, &vds));
+        image.extend(block(
+            TAG_PARTITION,
+            &[(22, &0u16.to_le_bytes()), (188, &PARTITION.to_le_bytes())],
+        ));
+        image.extend(block(
+            TAG_LOGICAL_VOLUME,
+            &[
+                (212, &(SECTOR as u32).to_le_bytes()),
+                (252, &0u32.to_le_bytes()),
+                (440, &[1, 6, 1, 0, 0, 0]),
+            ],
+        ));
+        image.extend(block(TAG_TERMINATING, &[]));
+        // partition blocks: file set, root entry, root directory, file entry, file data
+        image.extend(block(TAG_FILE_SET, &[(404, &1u32.to_le_bytes())]));
+        let dir = [fid(FID_PARENT, 1, ""), fid(0, 3, "hello.txt")].concat();
+        image.extend(file_entry(
+            FILE_TYPE_DIRECTORY,
+            dir.len() as u64,
+            &short_ad(dir.len() as u32, 2),
+        ));
+        let mut dir_block = dir;
+        dir_block.resize(SECTOR as usize, 0);
+        image.extend(dir_block);
+        image.extend(file_entry(5, 6, &short_ad(6, 4)));
+        let mut data = b"hello\n".to_vec();
+        data.resize(SECTOR as usize, 0);
+        image.extend(data);
+        image
+    }
+
+    #[test]
+    fn root_file() -> Result<()> {
+        let files = read(&mut Cursor::new(image()))?.context("no udf")?;
+        assert_eq!(
+            files,
+            vec![ImageFile {
+                path: "hello.txt".to_string(),
+                size: 6,
+                mtime_unix: Some(1709288430),
+                extents: vec![Extent {
+                    offset: Some((PARTITION as u64 + 4) * SECTOR),
+                    len: 6
+                }],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn crafted_images() -> Result<()> {
+        // a block size too small for a file entry
+        let vol = Volume {
+            block_size: 16,
+            start: 0,
+        };
+        let entry = file_entry(5, 6, &short_ad(6, 4));
+        let err = read_node(&mut Cursor::new(entry), &vol, 0)
+            .err()
+            .context("no error")?;
+        assert!(format!("{err:#}").contains("truncated"), "{err:#}");
+        let lvd = (ANCHOR_SECTOR as usize + 2) * SECTOR as usize;
+
+        // a root directory of 4 GiB in unrecorded extents
+        let mut sparse = image();
+        let root = (PARTITION as usize + 1) * SECTOR as usize;
+        let ads = [(1 << 30 | 0x3fff_f800u32).to_le_bytes(), 0u32.to_le_bytes()].concat();
+        let entry = file_entry(FILE_TYPE_DIRECTORY, 1 << 32, &ads.repeat(4));
+        sparse[root..root + SECTOR as usize].copy_from_slice(&entry);
+        assert_eq!(read(&mut Cursor::new(sparse))?, Some(vec![]));
+
+        // a file set descriptor past the end of the image
+        let mut past_end = image();
+        past_end[lvd + 252..lvd + 256].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = read(&mut Cursor::new(past_end)).unwrap_err();
+        assert!(format!("{err:#}").contains("past its end"), "{err:#}");
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! Little-endian integer readers for the adapters that parse binary headers by hand, and big-endian
+//! ones for the few formats that use network byte order.
+//!
+//! They return `None` past the end of the data, so a truncated file is reported instead of
+//! panicking.
+
+fn bytes<const N: usize>(data: &[u8], at: usize) -> Option<[u8; N]> {
+    data.get(at..at.checked_add(N)?)?.try_into().ok()
+}
+
+pub fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    bytes(data, at).map(u16::from_le_bytes)
+}
+
+pub fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    bytes(data, at).map(u32::from_le_bytes)
+}
+
+pub fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    bytes(data, at).map(u64::from_le_bytes)
+}
+
+pub fn u16_be_at(data: &[u8], at: usize) -> Option<u16> {
+    bytes(data, at).map(u16::from_be_bytes)
+}
+
+pub fn u32_be_at(data: &[u8], at: usize) -> Option<u32> {
+    bytes(data, at).map(u32::from_be_bytes)
+}
+
+pub fn u64_be_at(data: &[u8], at: usize) -> Option<u64> {
+    bytes(data, at).map(u64::from_be_bytes)
+}
+
+/// for formats that record their byte order, e.g. pcap captures
+pub fn u16_ordered_at(data: &[u8], at: usize, little_endian: bool) -> Option<u16> {
+    if little_endian {
+        u16_at(data, at)
+    } else {
+        u16_be_at(data, at)
+    }
+}
+
+pub fn u32_ordered_at(data: &[u8], at: usize, little_endian: bool) -> Option<u32> {
+    if little_endian {
+        u32_at(data, at)
+    } else {
+        u32_be_at(data, at)
+    }
+}
+
+pub fn u64_ordered_at(data: &[u8], at: usize, little_endian: bool) -> Option<u64> {
+    if little_endian {
+        u64_at(data, at)
+    } else {
+        u64_be_at(data, at)
+    }
+}
+
+/// an unsigned integer of up to eight bytes, e.g. the 24 bit sizes of UEFI sections
+pub fn uint(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn reads() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        assert_eq!(u16_at(&data, 1), Some(0x0302));
+        assert_eq!(u32_at(&data, 0), Some(0x04030201));
+        assert_eq!(u64_at(&data, 1), Some(0x0908070605040302));
+        assert_eq!(uint(&data[..3]), 0x030201);
+        assert_eq!(u32_at(&data, 6), None);
+        assert_eq!(u16_at(&data, usize::MAX), None);
+        assert_eq!(u16_be_at(&data, 1), Some(0x0203));
+        assert_eq!(u32_be_at(&data, 0), Some(0x01020304));
+        assert_eq!(u64_be_at(&data, 1), Some(0x0203040506070809));
+        assert_eq!(u64_be_at(&data, 2), None);
+        assert_eq!(u32_ordered_at(&data, 0, true), u32_at(&data, 0));
+        assert_eq!(u16_ordered_at(&data, 0, false), u16_be_at(&data, 0));
+    }
+}
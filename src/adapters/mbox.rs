@@ -18,7 +18,10 @@ lazy_static! {
         description:
             "Reads mailbox/mail files and runs extractors on the contents and attachments."
                 .to_owned(),
-        recurses: true,
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -55,15 +58,7 @@ impl FileAdapter for MboxAdapter {
         ai: AdaptInfo,
         _detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let AdaptInfo {
-            filepath_hint,
-            mut inp,
-            line_prefix,
-            archive_recursion_depth,
-            config,
-            postprocess,
-            ..
-        } = ai;
+        let (mut inp, container) = ai.into_parts();
 
         let mut content = Vec::new();
         let s = stream! {
@@ -80,7 +75,7 @@ impl FileAdapter for MboxAdapter {
                 todos.push_back(mail);
 
                 while let Some(mail) = todos.pop_front() {
-                let mut path = filepath_hint.clone();
+                let mut path = container.filepath_hint.clone();
                 let filename = mail.get_content_disposition().params.get("filename").cloned();
                 match &*mail.ctype.mimetype {
                     x if x.starts_with("multipart/") => {
@@ -98,19 +93,15 @@ impl FileAdapter for MboxAdapter {
                     }
                 }
 
-                let mut config = config.clone();
+                let mut config = container.config.clone();
                 config.accurate = true;
 
                 let raw_body = match mail.get_body_raw() { Ok(b) => b, Err(_) => continue };
+                // the parts of a mail keep its line prefix
                 let ai2: AdaptInfo = AdaptInfo {
-                    filepath_hint: path,
-                    is_real_file: false,
-                    file_mtime_unix_ms: None,
-                    archive_recursion_depth: archive_recursion_depth + 1,
-                    inp: Box::pin(Cursor::new(raw_body)),
-                    line_prefix: line_prefix.to_string(),
+                    line_prefix: container.line_prefix.clone(),
                     config,
-                    postprocess,
+                    ..container.member(path, Box::pin(Cursor::new(raw_body)))
                 };
                 ais.push(ai2);
                 }
@@ -0,0 +1,185 @@
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use mailparse::{MailHeaderMap, ParsedMail};
+use tokio::io::AsyncReadExt;
+
+use std::io::Cursor;
+
+static EXTENSIONS: &[&str] = &["mht", "mhtml"];
+static MIME_TYPES: &[&str] = &["application/x-mimearchive", "multipart/related"];
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "mhtml".to_owned(),
+        version: 1,
+        description: "Decodes the HTML and text parts of MIME-encapsulated saved web pages (.mht) and runs them through the HTML extractor. Images, styles and scripts are skipped.".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIME_TYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        disabled_by_default: false,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+#[derive(Default)]
+pub struct MhtmlAdapter;
+
+impl MhtmlAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for MhtmlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// file extension for the parts that contain readable text
+fn text_extension(mimetype: &str) -> Option<&'static str> {
+    match mimetype {
+        "text/html" | "application/xhtml+xml" => Some("html"),
+        "text/plain" => Some("txt"),
+        _ => None,
+    }
+}
+
+/// name of a part: the last segment of its Content-Location (frames and iframes are saved with their url),
+/// with the extension the text extractors match on
+fn part_name(part: &ParsedMail, index: usize, extension: &str) -> String {
+    let location = part.headers.get_first_value("Content-Location");
+    let stem = location
+        .as_deref()
+        .map(|l| l.split(['?', '#']).next().unwrap_or_default())
+        .and_then(|l| l.trim_end_matches('/').rsplit('/').next())
+        .filter(|s| !s.is_empty() && !s.contains(':'))
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("part{index}"));
+    if stem.ends_with(&format!(".{extension}")) {
+        stem
+    } else {
+        format!("{stem}.{extension}")
+    }
+}
+
+/// the decoded (transfer encoding and charset) text parts of a saved page in document order
+fn text_parts(page: &ParsedMail) -> Vec<(String, String)> {
+    let mut out = vec![];
+    let mut todo = vec![page];
+    while let Some(part) = todo.pop() {
+        if part.ctype.mimetype.starts_with("multipart/") {
+            todo.extend(part.subparts.iter().rev());
+            continue;
+        }
+        let Some(extension) = text_extension(&part.ctype.mimetype) else {
+            continue;
+        };
+        let Ok(body) = part.get_body() else { continue };
+        out.push((part_name(part, out.len(), extension), body));
+    }
+    out
+}
+
+#[async_trait]
+impl FileAdapter for MhtmlAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+            ..
+        } = ai;
+
+        let s = stream! {
+            let mut content = Vec::new();
+            inp.read_to_end(&mut content).await?;
+            let page = mailparse::parse_mail(&content)?;
+            for (name, body) in text_parts(&page) {
+                yield Ok(AdaptInfo {
+                    filepath_hint: filepath_hint.join(name),
+                    is_real_file: false,
+                    file_mtime_unix_ms: None,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(Cursor::new(body.into_bytes())),
+                    line_prefix: line_prefix.to_string(),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    static PAGE: &[u8] = b"From: <Saved by Blink>\r
+Subject: Example\r
+MIME-Version: 1.0\r
+Content-Type: multipart/related; type=\"text/html\"; boundary=\"----b\"\r
+\r
+------b\r
+Content-Type: text/html; charset=iso-8859-1\r
+Content-Transfer-Encoding: quoted-printable\r
+Content-Location: https://example.com/docs/\r
+\r
+<html><body><p>Caf=E9 men=\r
+u</p><iframe src=3D\"frame.html\"></iframe></body></html>\r
+------b\r
+Content-Type: image/png\r
+Content-Transfer-Encoding: base64\r
+Content-Location: https://example.com/logo.png\r
+\r
+iVBORw0KGgo=\r
+------b\r
+Content-Type: text/html\r
+Content-Transfer-Encoding: base64\r
+Content-Location: https://example.com/frame.html?x=1\r
+\r
+PHA+ZnJhbWUgdGV4dDwvcD4=\r
+------b--\r
+";
+
+    #[test]
+    fn parts() -> Result<()> {
+        let page = mailparse::parse_mail(PAGE)?;
+        let parts = text_parts(&page)
+            .into_iter()
+            .map(|(name, body)| (name, body.trim_end().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![
+                (
+                    "docs.html".to_string(),
+                    "<html><body><p>Café menu</p><iframe src=\"frame.html\"></iframe></body></html>"
+                        .to_string()
+                ),
+                ("frame.html".to_string(), "<p>frame text</p>".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
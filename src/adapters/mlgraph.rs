@@ -0,0 +1,98 @@
+//! Model graphs in protobuf: ONNX models and TensorFlow graphs and SavedModels. The nodes are
+//! listed with their names, ops and inputs, so model repositories can be searched for operators
+//! and tensor names. The files are read as a stream, the weights in them are skipped.
+mod onnx;
+mod proto;
+mod tensorflow;
+
+use super::{writing::WritingFileAdapter, *};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::{BufReader, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["onnx", "pb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "mlgraph".to_owned(),
+        version: 1,
+        description: "Lists the nodes, ops, initializers and signatures of ONNX models and TensorFlow graphs and SavedModels".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MlGraphAdapter;
+
+impl MlGraphAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for MlGraphAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the output of a graph, with the line prefix in front of every line
+struct Lines<'a> {
+    oup: &'a mut dyn Write,
+    prefix: &'a str,
+}
+
+impl Lines<'_> {
+    fn line(&mut self, text: &str) -> Result<()> {
+        for line in text.lines() {
+            writeln!(self.oup, "{}{line}", self.prefix)?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for MlGraphAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            line_prefix,
+            filepath_hint,
+            ..
+        } = ai;
+        let is_onnx = filepath_hint
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("onnx"));
+        let inp = SyncIoBridge::new(inp);
+        let mut oup = std::io::BufWriter::new(SyncIoBridge::new(oup));
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut reader = proto::Reader::new(BufReader::new(inp));
+            let mut lines = Lines {
+                oup: &mut oup,
+                prefix: &line_prefix,
+            };
+            if is_onnx {
+                onnx::dump(&mut reader, &mut lines)?;
+            } else {
+                tensorflow::dump(&mut reader, &mut lines)?;
+            }
+            oup.flush()?;
+            Ok(())
+        })
+        .await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+//! ONNX models (`ModelProto`): the producer, opsets and metadata of the model, and the inputs,
+//! outputs, nodes and initializers of its graph, of the graphs in the attributes of nodes (the
+//! branches of `If`, the bodies of `Loop`) and of its functions.
+use super::Lines;
+use super::proto::{Reader, Value};
+use anyhow::*;
+use std::io::Read;
+
+/// the names of `TensorProto.DataType`
+const DATA_TYPES: &[&str] = &[
+    "undefined",
+    "float",
+    "uint8",
+    "int8",
+    "uint16",
+    "int16",
+    "int32",
+    "int64",
+    "string",
+    "bool",
+    "float16",
+    "double",
+    "uint32",
+    "uint64",
+    "complex64",
+    "complex128",
+    "bfloat16",
+    "float8e4m3fn",
+    "float8e4m3fnuz",
+    "float8e5m2",
+    "float8e5m2fnuz",
+    "uint4",
+    "int4",
+];
+/// the values of string tensors past this are left out
+const MAX_STRINGS: usize = 64;
+
+fn data_type(t: i64) -> String {
+    usize::try_from(t)
+        .ok()
+        .and_then(|t| DATA_TYPES.get(t))
+        .map(|t| t.to_string())
+        .unwrap_or_else(|| format!("type {t}"))
+}
+
+fn shape(dims: &[String]) -> String {
+    format!("[{}]", dims.join(", "))
+}
+
+/// the element type and shape of a `TypeProto`, for tensors
+fn type_proto(r: &mut Reader<impl Read>, len: u64) -> Result<String> {
+    let end = Some(r.end(len));
+    let mut out = String::new();
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            // tensor_type, sparse_tensor_type
+            (1 | 8, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let (mut elem, mut dims) = (0, vec![]);
+                while let Some((field, value)) = r.field(end)? {
+                    match (field, value) {
+                        (1, Value::Varint(t)) => elem = t as i64,
+                        (2, Value::Len(len)) => {
+                            let end = Some(r.end(len));
+                            while let Some((field, value)) = r.field(end)? {
+                                match (field, value) {
+                                    (1, Value::Len(len)) => dims.push(dimension(r, len)?),
+                                    (_, value) => r.skip_value(value)?,
+                                }
+                            }
+                        }
+                        (_, value) => r.skip_value(value)?,
+                    }
+                }
+                out = format!("{} {}", data_type(elem), shape(&dims));
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(out)
+}
+
+/// a dimension of a shape, its size or its name
+fn dimension(r: &mut Reader<impl Read>, len: u64) -> Result<String> {
+    let end = Some(r.end(len));
+    let mut out = "?".to_string();
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, Value::Varint(v)) => out = (v as i64).to_string(),
+            (2, value @ Value::Len(_)) => out = r.string(value)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(out)
+}
+
+/// an input, output or intermediate value of a graph
+fn value_info(r: &mut Reader<impl Read>, len: u64, kind: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let (mut name, mut ty, mut doc) = (String::new(), String::new(), String::new());
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => name = r.string(value)?,
+            (2, Value::Len(len)) => ty = type_proto(r, len)?,
+            (3, value) => doc = r.string(value)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    out.line(format!("{kind} {name}: {ty}").trim_end())?;
+    if !doc.is_empty() {
+        out.line(&format!("{kind} {name}: {doc}"))?;
+    }
+    Ok(())
+}
+
+/// a `TensorProto` of the initializers, without its data
+fn tensor(r: &mut Reader<impl Read>, len: u64, kind: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let (mut name, mut ty, mut dims, mut strings, mut location) =
+        (String::new(), 0, vec![], vec![], None);
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => dims.extend(r.ints(value)?.iter().map(i64::to_string)),
+            (2, Value::Varint(t)) => ty = t as i64,
+            (6, value) if strings.len() < MAX_STRINGS => strings.push(r.string(value)?),
+            (8, value) => name = r.string(value)?,
+            // external_data, the location of the weights in another file
+            (13, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let (mut key, mut val) = (String::new(), String::new());
+                while let Some((field, value)) = r.field(end)? {
+                    match field {
+                        1 => key = r.string(value)?,
+                        2 => val = r.string(value)?,
+                        _ => r.skip_value(value)?,
+                    }
+                }
+                if key == "location" {
+                    location = Some(val);
+                }
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    let mut line = format!("{kind} {name}: {} {}", data_type(ty), shape(&dims));
+    if let Some(location) = location {
+        line.push_str(&format!(" in {location}"));
+    }
+    out.line(&line)?;
+    if !strings.is_empty() {
+        out.line(&format!("{kind} {name}: {}", strings.join(", ")))?;
+    }
+    Ok(())
+}
+
+/// an attribute of a node: strings are listed, graphs are read with the node and attribute
+/// names in front of their lines
+fn attribute(r: &mut Reader<impl Read>, len: u64, node: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let mut name = String::new();
+    let mut strings = vec![];
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => name = r.string(value)?,
+            // s, strings
+            (4 | 9, value) if strings.len() < MAX_STRINGS => strings.push(r.string(value)?),
+            // g, graphs
+            (6 | 11, Value::Len(len)) => graph(r, len, &format!("{node}.{name} "), out)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    if !strings.is_empty() {
+        out.line(&format!("node {node}: {name} = {}", strings.join(", ")))?;
+    }
+    Ok(())
+}
+
+/// a node, `node name: op(inputs) -> outputs`, with its string attributes
+fn node(r: &mut Reader<impl Read>, len: u64, scope: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let (mut inputs, mut outputs) = (vec![], vec![]);
+    let (mut name, mut op, mut domain, mut doc) =
+        (String::new(), String::new(), String::new(), String::new());
+    // the attributes come before the domain, their lines are written after the node's
+    let mut attributes = vec![];
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => inputs.push(r.string(value)?),
+            (2, value) => outputs.push(r.string(value)?),
+            (3, value) => name = r.string(value)?,
+            (4, value) => op = r.string(value)?,
+            (5, Value::Len(len)) => {
+                let mut buf = vec![];
+                let mut lines = Lines {
+                    oup: &mut buf,
+                    prefix: "",
+                };
+                let node_name = if name.is_empty() { &op } else { &name };
+                attribute(r, len, &format!("{scope}{node_name}"), &mut lines)?;
+                attributes.push(buf);
+            }
+            (6, value) => doc = r.string(value)?,
+            (7, value) => domain = r.string(value)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    let op = if domain.is_empty() {
+        op
+    } else {
+        format!("{domain}.{op}")
+    };
+    let node_name = if name.is_empty() { &op } else { &name };
+    out.line(&format!(
+        "{scope}node {node_name}: {op}({}) -> {}",
+        inputs.join(", "),
+        outputs.join(", ")
+    ))?;
+    for buf in attributes {
+        out.line(&String::from_utf8_lossy(&buf))?;
+    }
+    if !doc.is_empty() {
+        out.line(&format!("{scope}node {node_name}: {doc}"))?;
+    }
+    Ok(())
+}
+
+/// a graph, with `scope` in front of its lines if it is nested in a node
+fn graph(r: &mut Reader<impl Read>, len: u64, scope: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, Value::Len(len)) => node(r, len, scope, out)?,
+            (2, value) => {
+                let name = r.string(value)?;
+                out.line(&format!("{scope}graph: {name}"))?;
+            }
+            (5, Value::Len(len)) => tensor(r, len, &format!("{scope}initializer"), out)?,
+            (10, value) => {
+                let doc = r.string(value)?;
+                out.line(&format!("{scope}doc: {doc}"))?;
+            }
+            (11, Value::Len(len)) => value_info(r, len, &format!("{scope}input"), out)?,
+            (12, Value::Len(len)) => value_info(r, len, &format!("{scope}output"), out)?,
+            (13, Value::Len(len)) => value_info(r, len, &format!("{scope}value"), out)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+/// a function of the model, its nodes have the function name in front
+fn function(r: &mut Reader<impl Read>, len: u64, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let mut name = String::new();
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => {
+                name = r.string(value)?;
+                out.line(&format!("function: {name}"))?;
+            }
+            (7, Value::Len(len)) => node(r, len, &format!("{name} "), out)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+/// the lines of a `ModelProto`
+pub fn dump(r: &mut Reader<impl Read>, out: &mut Lines) -> Result<()> {
+    while let Some((field, value)) = r.field(None)? {
+        match (field, value) {
+            (1, Value::Varint(v)) => out.line(&format!("ir_version: {v}"))?,
+            (2, value) => {
+                let v = r.string(value)?;
+                out.line(&format!("producer_name: {v}"))?;
+            }
+            (3, value) => {
+                let v = r.string(value)?;
+                out.line(&format!("producer_version: {v}"))?;
+            }
+            (4, value) => {
+                let v = r.string(value)?;
+                out.line(&format!("domain: {v}"))?;
+            }
+            (5, Value::Varint(v)) => out.line(&format!("model_version: {v}"))?,
+            (6, value) => {
+                let v = r.string(value)?;
+                out.line(&format!("doc: {v}"))?;
+            }
+            (7, Value::Len(len)) => graph(r, len, "", out)?,
+            (8, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let (mut domain, mut version) = ("ai.onnx".to_string(), 0);
+                while let Some((field, value)) = r.field(end)? {
+                    match (field, value) {
+                        (1, value) => {
+                            let d = r.string(value)?;
+                            if !d.is_empty() {
+                                domain = d;
+                            }
+                        }
+                        (2, Value::Varint(v)) => version = v,
+                        (_, value) => r.skip_value(value)?,
+                    }
+                }
+                out.line(&format!("opset: {domain} {version}"))?;
+            }
+            (14, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let (mut key, mut val) = (String::new(), String::new());
+                while let Some((field, value)) = r.field(end)? {
+                    match field {
+                        1 => key = r.string(value)?,
+                        2 => val = r.string(value)?,
+                        _ => r.skip_value(value)?,
+                    }
+                }
+                out.line(&format!("metadata {key}: {val}"))?;
+            }
+            (25, Value::Len(len)) => function(r, len, out)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::proto::encode::*;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn model() -> Result<()> {
+        let dim = |d: u64| len(1, int(1, d));
+        let input = [
+            len(1, "pixels"),
+            len(
+                2,
+                len(
+                    1,
+                    [
+                        int(1, 1),
+                        len(2, [len(1, len(2, "batch")), dim(3)].concat()),
+                    ]
+                    .concat(),
+                ),
+            ),
+        ]
+        .concat();
+        let node = [
+            len(1, "pixels"),
+            len(1, "conv.weight"),
+            len(2, "features"),
+            len(3, "/conv/Conv"),
+            len(4, "Conv"),
+            len(
+                5,
+                [len(1, "auto_pad"), len(4, "SAME_UPPER"), int(20, 3)].concat(),
+            ),
+            len(5, [len(1, "group"), int(3, 1), int(20, 2)].concat()),
+        ]
+        .concat();
+        let weight = [
+            len(1, [varint(64), varint(3)].concat()),
+            int(2, 1),
+            len(8, "conv.weight"),
+            len(9, [0; 64 * 3 * 4]),
+        ]
+        .concat();
+        let graph = [
+            len(1, node),
+            len(2, "main_graph"),
+            len(5, weight),
+            len(11, input),
+        ]
+        .concat();
+        let model = [
+            int(1, 8),
+            len(2, "pytorch"),
+            len(3, "2.1.0"),
+            len(7, graph),
+            len(8, [len(1, ""), int(2, 17)].concat()),
+            len(14, [len(1, "author"), len(2, "Jane Doe")].concat()),
+        ]
+        .concat();
+        let mut buf = vec![];
+        let mut out = Lines {
+            oup: &mut buf,
+            prefix: "P:",
+        };
+        dump(&mut Reader::new(&model[..]), &mut out)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "P:ir_version: 8
+P:producer_name: pytorch
+P:producer_version: 2.1.0
+P:node /conv/Conv: Conv(pixels, conv.weight) -> features
+P:node /conv/Conv: auto_pad = SAME_UPPER
+P:graph: main_graph
+P:initializer conv.weight: float [64, 3]
+P:input pixels: float [batch, 3]
+P:opset: ai.onnx 17
+P:metadata author: Jane Doe
+"
+        );
+        Ok(())
+    }
+}
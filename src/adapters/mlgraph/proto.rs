@@ -0,0 +1,173 @@
+//! The protobuf wire format, read front to back from a stream so that large fields (the weights
+//! stored in a model) are skipped without being held in memory. There is no schema here, the
+//! code that reads a message knows what its fields are.
+use anyhow::*;
+use std::io::Read;
+
+/// longer strings are cut off
+const MAX_STRING: u64 = 1 << 20;
+
+pub enum Value {
+    Varint(u64),
+    /// fixed size numbers are skipped, nothing read here is one
+    Fixed64,
+    Fixed32,
+    /// strings, bytes, messages and packed repeated fields, by their length
+    Len(u64),
+}
+
+pub struct Reader<R> {
+    inner: R,
+    pos: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Reader { inner, pos: 0 }
+    }
+
+    fn byte(&mut self) -> Result<Option<u8>> {
+        let mut b = [0];
+        match self.inner.read(&mut b)? {
+            0 => Ok(None),
+            _ => {
+                self.pos += 1;
+                Ok(Some(b[0]))
+            }
+        }
+    }
+
+    fn exact<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut b = [0; N];
+        self.inner
+            .read_exact(&mut b)
+            .context("unexpected end of protobuf")?;
+        self.pos += N as u64;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?.context("unexpected end of protobuf")?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        bail!("protobuf varint too long")
+    }
+
+    /// the end of a message of `len` bytes that starts here
+    pub fn end(&self, len: u64) -> u64 {
+        self.pos + len
+    }
+
+    /// the next field of the message that ends at `end`, or of the outermost message that ends
+    /// with the stream
+    pub fn field(&mut self, end: Option<u64>) -> Result<Option<(u64, Value)>> {
+        if end.is_some_and(|end| self.pos >= end) {
+            return Ok(None);
+        }
+        let key = match self.byte()? {
+            Some(b) if b & 0x80 == 0 => b as u64,
+            Some(b) => {
+                let rest = self.varint()?;
+                (b & 0x7f) as u64 | rest << 7
+            }
+            None if end.is_none() => return Ok(None),
+            None => bail!("unexpected end of protobuf"),
+        };
+        let value = match key & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.exact::<8>()?;
+                Value::Fixed64
+            }
+            2 => Value::Len(self.varint()?),
+            5 => {
+                self.exact::<4>()?;
+                Value::Fixed32
+            }
+            wire => bail!("unsupported protobuf wire type {wire}"),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    pub fn skip(&mut self, len: u64) -> Result<()> {
+        let skipped = std::io::copy(&mut (&mut self.inner).take(len), &mut std::io::sink())?;
+        self.pos += skipped;
+        ensure!(skipped == len, "unexpected end of protobuf");
+        Ok(())
+    }
+
+    /// skips a field that is not read
+    pub fn skip_value(&mut self, value: Value) -> Result<()> {
+        match value {
+            Value::Len(len) => self.skip(len),
+            _ => Ok(()),
+        }
+    }
+
+    /// a string or bytes field as text
+    pub fn string(&mut self, value: Value) -> Result<String> {
+        let Value::Len(len) = value else {
+            return Ok(String::new());
+        };
+        let mut buf = vec![];
+        (&mut self.inner)
+            .take(len.min(MAX_STRING))
+            .read_to_end(&mut buf)?;
+        self.pos += buf.len() as u64;
+        self.skip(len - buf.len() as u64)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+
+    /// the numbers of a repeated integer field, packed or not
+    pub fn ints(&mut self, value: Value) -> Result<Vec<i64>> {
+        Ok(match value {
+            Value::Varint(v) => vec![v as i64],
+            Value::Len(len) => {
+                let end = self.end(len);
+                let mut out = vec![];
+                while self.pos < end {
+                    out.push(self.varint()? as i64);
+                }
+                out
+            }
+            _ => vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod encode {
+    //! protobuf messages for tests
+
+    pub fn varint(mut v: u64) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(b);
+                return out;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    pub fn int(field: u64, v: u64) -> Vec<u8> {
+        [varint(field << 3), varint(v)].concat()
+    }
+
+    pub fn len(field: u64, data: impl AsRef<[u8]>) -> Vec<u8> {
+        let data = data.as_ref();
+        [
+            varint(field << 3 | 2),
+            varint(data.len() as u64),
+            data.to_vec(),
+        ]
+        .concat()
+    }
+}
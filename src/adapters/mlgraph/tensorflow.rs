@@ -0,0 +1,317 @@
+//! TensorFlow graphs: frozen `GraphDef`s and the `saved_model.pb` of SavedModel directories, with
+//! the tags, versions, signatures and assets of their meta graphs. Nodes are listed with their op
+//! and inputs, and with their string attributes and the values of string constants (vocabularies,
+//! file names of savers).
+use super::Lines;
+use super::proto::{Reader, Value};
+use anyhow::*;
+use std::io::Read;
+
+/// the values of string tensors past this are left out
+const MAX_STRINGS: usize = 64;
+
+/// the strings of an `AttrValue`, from a string, a list of strings, a string tensor or a function
+fn attr_strings(r: &mut Reader<impl Read>, len: u64, strings: &mut Vec<String>) -> Result<()> {
+    let end = Some(r.end(len));
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            // s
+            (2, value) if strings.len() < MAX_STRINGS => strings.push(r.string(value)?),
+            // list (its s), tensor (its string_val), func (its name)
+            (1 | 8 | 10, Value::Len(len)) => {
+                let wanted = match field {
+                    1 => 2,
+                    8 => 8,
+                    _ => 1,
+                };
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    match value {
+                        value @ Value::Len(_) if field == wanted && strings.len() < MAX_STRINGS => {
+                            strings.push(r.string(value)?)
+                        }
+                        value => r.skip_value(value)?,
+                    }
+                }
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+/// a `NodeDef`, `node name: op(inputs)`, with its string attributes
+fn node(r: &mut Reader<impl Read>, len: u64, scope: &str, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let (mut name, mut op, mut inputs, mut attrs) = (String::new(), String::new(), vec![], vec![]);
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => name = r.string(value)?,
+            (2, value) => op = r.string(value)?,
+            (3, value) => inputs.push(r.string(value)?),
+            // attr, a map entry of the name and the value
+            (5, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let (mut key, mut strings) = (String::new(), vec![]);
+                while let Some((field, value)) = r.field(end)? {
+                    match (field, value) {
+                        (1, value) => key = r.string(value)?,
+                        (2, Value::Len(len)) => attr_strings(r, len, &mut strings)?,
+                        (_, value) => r.skip_value(value)?,
+                    }
+                }
+                if !strings.is_empty() {
+                    attrs.push(format!("{key} = {}", strings.join(", ")));
+                }
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    // not a node of a graph
+    if op.is_empty() {
+        return Ok(());
+    }
+    out.line(&format!("{scope}node {name}: {op}({})", inputs.join(", ")))?;
+    for attr in attrs {
+        out.line(&format!("{scope}node {name}: {attr}"))?;
+    }
+    Ok(())
+}
+
+/// a `FunctionDef` of the graph's library, its nodes have the function name in front
+fn function(r: &mut Reader<impl Read>, len: u64, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    let mut name = String::new();
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            // signature, an OpDef
+            (1, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    match field {
+                        1 => name = r.string(value)?,
+                        _ => r.skip_value(value)?,
+                    }
+                }
+                out.line(&format!("function: {name}"))?;
+            }
+            (3, Value::Len(len)) => node(r, len, &format!("{name} "), out)?,
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+/// a field of a `GraphDef`
+fn graph_field(r: &mut Reader<impl Read>, field: u64, value: Value, out: &mut Lines) -> Result<()> {
+    match (field, value) {
+        (1, Value::Len(len)) => node(r, len, "", out),
+        // library
+        (2, Value::Len(len)) => {
+            let end = Some(r.end(len));
+            while let Some((field, value)) = r.field(end)? {
+                match (field, value) {
+                    (1, Value::Len(len)) => function(r, len, out)?,
+                    (_, value) => r.skip_value(value)?,
+                }
+            }
+            Ok(())
+        }
+        (_, value) => r.skip_value(value),
+    }
+}
+
+/// the inputs or outputs of a signature, map entries of names and `TensorInfo`s
+fn signature_tensor(
+    r: &mut Reader<impl Read>,
+    len: u64,
+    signature: &str,
+    kind: &str,
+    out: &mut Lines,
+) -> Result<()> {
+    let end = Some(r.end(len));
+    let (mut key, mut tensor) = (String::new(), String::new());
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            (1, value) => key = r.string(value)?,
+            (2, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    match field {
+                        1 => tensor = r.string(value)?,
+                        _ => r.skip_value(value)?,
+                    }
+                }
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    out.line(&format!("signature {signature}: {kind} {key} = {tensor}"))
+}
+
+/// a `MetaGraphDef` of a SavedModel
+fn meta_graph(r: &mut Reader<impl Read>, len: u64, out: &mut Lines) -> Result<()> {
+    let end = Some(r.end(len));
+    while let Some((field, value)) = r.field(end)? {
+        match (field, value) {
+            // meta_info_def
+            (1, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    let name = match field {
+                        4 => "tag",
+                        5 => "tensorflow_version",
+                        6 => "tensorflow_git_version",
+                        _ => {
+                            r.skip_value(value)?;
+                            continue;
+                        }
+                    };
+                    let v = r.string(value)?;
+                    out.line(&format!("{name}: {v}"))?;
+                }
+            }
+            (2, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    graph_field(r, field, value, out)?;
+                }
+            }
+            // signature_def, map entries of the name and the SignatureDef
+            (5, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                let mut name = String::new();
+                while let Some((field, value)) = r.field(end)? {
+                    match (field, value) {
+                        (1, value) => name = r.string(value)?,
+                        (2, Value::Len(len)) => {
+                            let end = Some(r.end(len));
+                            while let Some((field, value)) = r.field(end)? {
+                                match (field, value) {
+                                    (1, Value::Len(len)) => {
+                                        signature_tensor(r, len, &name, "input", out)?
+                                    }
+                                    (2, Value::Len(len)) => {
+                                        signature_tensor(r, len, &name, "output", out)?
+                                    }
+                                    (3, value) => {
+                                        let method = r.string(value)?;
+                                        out.line(&format!("signature {name}: method {method}"))?;
+                                    }
+                                    (_, value) => r.skip_value(value)?,
+                                }
+                            }
+                        }
+                        (_, value) => r.skip_value(value)?,
+                    }
+                }
+            }
+            // asset_file_def
+            (6, Value::Len(len)) => {
+                let end = Some(r.end(len));
+                while let Some((field, value)) = r.field(end)? {
+                    match field {
+                        2 => {
+                            let file = r.string(value)?;
+                            out.line(&format!("asset: {file}"))?;
+                        }
+                        _ => r.skip_value(value)?,
+                    }
+                }
+            }
+            (_, value) => r.skip_value(value)?,
+        }
+    }
+    Ok(())
+}
+
+/// the lines of a `SavedModel` or a `GraphDef`. The first field of a SavedModel is its schema
+/// version, a number, and the first of a graph is a node
+pub fn dump(r: &mut Reader<impl Read>, out: &mut Lines) -> Result<()> {
+    let mut saved_model = None;
+    while let Some((field, value)) = r.field(None)? {
+        let saved_model =
+            *saved_model.get_or_insert(field == 1 && matches!(value, Value::Varint(_)));
+        match (saved_model, field, value) {
+            (true, 2, Value::Len(len)) => meta_graph(r, len, out)?,
+            (true, _, value) => r.skip_value(value)?,
+            (false, field, value) => graph_field(r, field, value, out)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::proto::encode::*;
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn saved_model() -> Result<()> {
+        let vocab = [
+            len(1, "vocab"),
+            len(2, "Const"),
+            len(
+                5,
+                [
+                    len(1, "value"),
+                    len(
+                        2,
+                        len(8, [int(1, 7), len(8, "[UNK]"), len(8, "hello")].concat()),
+                    ),
+                ]
+                .concat(),
+            ),
+            len(5, [len(1, "dtype"), len(2, int(6, 7))].concat()),
+        ]
+        .concat();
+        let lookup = [
+            len(1, "lookup"),
+            len(2, "LookupTableFindV2"),
+            len(3, "table"),
+            len(3, "vocab"),
+        ]
+        .concat();
+        let signature = [
+            len(1, "serving_default"),
+            len(
+                2,
+                [
+                    len(
+                        1,
+                        [len(1, "text"), len(2, len(1, "serving_default_text:0"))].concat(),
+                    ),
+                    len(3, "tensorflow/serving/predict"),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        let meta_graph = [
+            len(1, [len(4, "serve"), len(5, "2.15.0")].concat()),
+            len(2, [len(1, vocab), len(1, lookup)].concat()),
+            len(5, signature),
+        ]
+        .concat();
+        let saved_model = [int(1, 1), len(2, meta_graph)].concat();
+        let mut buf = vec![];
+        let mut out = Lines {
+            oup: &mut buf,
+            prefix: "P:",
+        };
+        dump(&mut Reader::new(&saved_model[..]), &mut out)?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "P:tag: serve
+P:tensorflow_version: 2.15.0
+P:node vocab: Const()
+P:node vocab: value = [UNK], hello
+P:node lookup: LookupTableFindV2(table, vocab)
+P:signature serving_default: input text = serving_default_text:0
+P:signature serving_default: method tensorflow/serving/predict
+"
+        );
+        Ok(())
+    }
+}
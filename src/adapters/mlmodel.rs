@@ -0,0 +1,273 @@
+//! Machine learning model files: PyTorch checkpoints (zip archives of a pickle and the tensor
+//! data, or pickles one after the other in the old format), joblib and plain pickles of
+//! scikit-learn models, Keras v3 archives and numpy .npz archives. Pickles are listed as
+//! `path: value` lines with tensors and arrays as their type and shape, config JSON is pretty
+//! printed and TorchScript code is kept as it is. The tensor data itself is never read. pandas
+//! HDFStore files are HDF5 files, the hdf5 adapter lists them.
+mod pickle;
+
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    zip::members,
+    *,
+};
+use crate::print_bytes;
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Read;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &[
+    "pt", "pth", "ckpt", "joblib", "pkl", "pickle", "keras", "npz",
+];
+
+/// larger members of archives are only listed
+const MAX_MEMBER: u64 = 64 << 20;
+/// the most of a pickle that is read
+const MAX_PICKLE: u64 = 1 << 30;
+/// the most of an .npy file that is read for its header
+const NPY_HEADER: u64 = 4096;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "mlmodel".to_owned(),
+        version: 1,
+        description: "Lists the keys, hyperparameters, tensor shapes and configs of PyTorch, joblib/pickle, Keras and numpy .npz model files".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MlModelAdapter;
+
+impl MlModelAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for MlModelAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Pickle,
+    Json,
+    Npy,
+    Text,
+    /// the data of a torch tensor, `archive/data/0`
+    Storage,
+    Other,
+}
+
+fn kind(name: &str) -> Kind {
+    let (dir, file) = name.rsplit_once('/').unwrap_or(("", name));
+    let ext = file
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "pkl" | "pickle" | "joblib" => Kind::Pickle,
+        "json" => Kind::Json,
+        "npy" => Kind::Npy,
+        "py" | "txt" | "yaml" | "yml" | "cfg" => Kind::Text,
+        "" if (dir == "data" || dir.ends_with("/data"))
+            && file.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            Kind::Storage
+        }
+        // `version`, `byteorder`, `.data/serialization_id`
+        "" => Kind::Text,
+        _ => Kind::Other,
+    }
+}
+
+/// the type and shape in the header of an .npy file, like `ndarray <f4 (3, 4)`
+fn npy_header(data: &[u8]) -> Option<String> {
+    let rest = data.strip_prefix(b"\x93NUMPY")?;
+    let (&major, rest) = rest.split_first()?;
+    let (len, header) = match major {
+        1 => (
+            u16::from_le_bytes(rest.get(1..3)?.try_into().ok()?) as usize,
+            rest.get(3..)?,
+        ),
+        _ => (
+            u32::from_le_bytes(rest.get(1..5)?.try_into().ok()?) as usize,
+            rest.get(5..)?,
+        ),
+    };
+    let header = String::from_utf8_lossy(&header[..len.min(header.len())]);
+    let descr = header.split_once("'descr': '")?.1.split_once('\'')?.0;
+    let shape = header.split_once("'shape': ")?.1;
+    let shape = &shape[..=shape.find(')')?];
+    Some(format!("ndarray {descr} {}", shape.replace(",)", ")")))
+}
+
+/// the lines of a member of an archive
+fn member_lines(kind: &Kind, data: &[u8]) -> Result<Vec<String>> {
+    Ok(match kind {
+        Kind::Pickle => pickle::convert(data)?,
+        Kind::Json => match serde_json::from_slice::<serde_json::Value>(data) {
+            Result::Ok(json) => serde_json::to_string_pretty(&json)?
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            Err(_) => String::from_utf8_lossy(data)
+                .lines()
+                .map(str::to_string)
+                .collect(),
+        },
+        Kind::Npy => npy_header(data).into_iter().collect(),
+        Kind::Text => match std::str::from_utf8(data) {
+            Result::Ok(text) if !text.contains('\0') => text.lines().map(str::to_string).collect(),
+            _ => vec![],
+        },
+        Kind::Storage | Kind::Other => vec![],
+    })
+}
+
+/// pickles that joblib compressed with zlib or gzip are decompressed
+fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    if data.starts_with(b"\x1f\x8b") {
+        flate2::read::GzDecoder::new(&data[..])
+            .take(MAX_PICKLE)
+            .read_to_end(&mut out)?;
+    } else if data.len() >= 2
+        && data[0] & 0x0f == 8
+        && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+    {
+        flate2::read::ZlibDecoder::new(&data[..])
+            .take(MAX_PICKLE)
+            .read_to_end(&mut out)?;
+    } else {
+        return Ok(data);
+    }
+    Ok(out)
+}
+
+async fn write_archive(
+    path: &Path,
+    line_prefix: &str,
+    mut oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let owned = path.to_owned();
+    let members = tokio::task::spawn_blocking(move || members(&owned)).await??;
+    for member in members {
+        let name = member.name();
+        let kind = kind(name);
+        let limit = match kind {
+            Kind::Npy => NPY_HEADER,
+            Kind::Pickle | Kind::Json | Kind::Text if member.size() <= MAX_MEMBER => MAX_MEMBER,
+            Kind::Storage => continue,
+            _ => {
+                let size = print_bytes(member.size() as f64);
+                async_writeln!(oup, "{line_prefix}{name} ({size})")?;
+                continue;
+            }
+        };
+        let data = member.read(path, limit).await?;
+        for line in member_lines(&kind, &data).with_context(|| format!("reading {name}"))? {
+            async_writeln!(oup, "{line_prefix}{name}: {line}")?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for MlModelAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            ..
+        } = ai;
+        // seekable_input, so this is always a real file
+        let mut file = tokio::fs::File::open(&filepath_hint).await?;
+        let mut magic = [0; 4];
+        let n = file.read(&mut magic).await?;
+        if magic[..n].starts_with(b"PK") {
+            return write_archive(&filepath_hint, &line_prefix, &mut oup).await;
+        }
+        let mut data = magic[..n].to_vec();
+        file.take(MAX_PICKLE).read_to_end(&mut data).await?;
+        let lines =
+            tokio::task::spawn_blocking(move || pickle::convert(&decompress(data)?)).await??;
+        for line in lines {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn archive_members() -> Result<()> {
+        assert_eq!(kind("model/data.pkl"), Kind::Pickle);
+        assert_eq!(kind("model/data/12"), Kind::Storage);
+        assert_eq!(kind("model/version"), Kind::Text);
+        assert_eq!(kind("model.weights.h5"), Kind::Other);
+        let npy = [
+            &b"\x93NUMPY\x01\x00\x46\x00"[..],
+            b"{'descr': '<f4', 'fortran_order': False, 'shape': (10,), }",
+            &[b' '; 11],
+            b"\n",
+        ]
+        .concat();
+        assert_eq!(member_lines(&Kind::Npy, &npy)?, ["ndarray <f4 (10)"]);
+        assert_eq!(
+            member_lines(
+                &Kind::Json,
+                br#"{"class_name":"Dense","config":{"units":64}}"#
+            )?,
+            [
+                "{",
+                r#"  "class_name": "Dense","#,
+                r#"  "config": {"#,
+                r#"    "units": 64"#,
+                "  }",
+                "}",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compressed_pickle() -> Result<()> {
+        let mut z = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        std::io::Write::write_all(
+            &mut z,
+            b"\x80\x04}\x94\x8c\x05alpha\x94G?\xb9\x99\x99\x99\x99\x99\x9as.",
+        )?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ridge.joblib");
+        std::fs::write(&path, z.finish()?)?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let out = adapted_to_vec(MlModelAdapter.adapt(a, &d).await?).await?;
+        assert_eq!(String::from_utf8(out)?, "PREFIX:alpha: 0.1\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,795 @@
+//! Python pickles, read without running them: classes and functions are kept as their names and
+//! objects as the class with the arguments and state they are built from. Enough to list the keys,
+//! hyperparameters and tensor shapes of pickled models. The array data that joblib writes into
+//! the middle of its pickles is skipped.
+use anyhow::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// the most lines of one file
+const MAX_LINES: usize = 100_000;
+/// elements of sequences past this are left out
+const MAX_ITEMS: usize = 64;
+const MAX_DEPTH: usize = 64;
+const PROTO: u8 = 0x80;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// bytes, and integers too large for an i64
+    Bytes,
+    /// a class or function, `module.name`
+    Global(String),
+    Tuple(Rc<Vec<Value>>),
+    /// lists and sets
+    List(Rc<RefCell<Vec<Value>>>),
+    Dict(Rc<RefCell<Vec<(Value, Value)>>>),
+    Object(Rc<RefCell<Object>>),
+    /// a reference to data outside of the pickle, like the storages of torch tensors
+    Persistent(Rc<Value>),
+    Mark,
+}
+
+#[derive(Debug, Default)]
+pub struct Object {
+    class: String,
+    args: Vec<Value>,
+    /// set like on a dict, for subclasses like OrderedDict
+    items: Vec<(Value, Value)>,
+    /// appended like to a list
+    list: Vec<Value>,
+    state: Option<Value>,
+}
+
+impl Value {
+    fn object(class: String, args: Vec<Value>) -> Value {
+        Value::Object(Rc::new(RefCell::new(Object {
+            class,
+            args,
+            ..Default::default()
+        })))
+    }
+
+    /// the name of a class or function, or of the class of an object
+    fn name(&self) -> String {
+        match self {
+            Value::Global(name) => name.clone(),
+            Value::Object(o) => o.borrow().class.clone(),
+            Value::Str(s) => s.clone(),
+            _ => "?".to_string(),
+        }
+    }
+
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        }
+    }
+
+    /// the value of a string key of a dict
+    fn get(&self, key: &str) -> Option<Value> {
+        let Value::Dict(items) = self else {
+            return None;
+        };
+        items
+            .borrow()
+            .iter()
+            .find(|(k, _)| matches!(k, Value::Str(k) if k == key))
+            .map(|(_, v)| v.clone())
+    }
+}
+
+fn elements(v: &Value) -> Vec<Value> {
+    match v {
+        Value::Tuple(items) => items.to_vec(),
+        Value::List(items) => items.borrow().clone(),
+        _ => vec![],
+    }
+}
+
+struct Unpickler<'a> {
+    data: &'a [u8],
+    pos: usize,
+    stack: Vec<Value>,
+    memo: HashMap<u64, Value>,
+}
+
+impl<'a> Unpickler<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Unpickler {
+            data,
+            pos,
+            stack: vec![],
+            memo: HashMap::new(),
+        }
+    }
+
+    fn take(&mut self, n: u64) -> Result<&'a [u8]> {
+        let end = usize::try_from(n)
+            .ok()
+            .and_then(|n| self.pos.checked_add(n))
+            .filter(|end| *end <= self.data.len())
+            .context("unexpected end of pickle")?;
+        let b = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(b)
+    }
+
+    fn uint(&mut self, n: u64) -> Result<u64> {
+        let mut b = [0; 8];
+        b[..n as usize].copy_from_slice(self.take(n)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn line(&mut self) -> Result<String> {
+        let rest = &self.data[self.pos..];
+        let len = memchr::memchr(b'\n', rest).context("unexpected end of pickle")?;
+        let line = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Ok(line)
+    }
+
+    fn string(&mut self, len: u64) -> Result<Value> {
+        Ok(Value::Str(
+            String::from_utf8_lossy(self.take(len)?).into_owned(),
+        ))
+    }
+
+    fn push(&mut self, v: Value) {
+        self.stack.push(v);
+    }
+
+    fn pop(&mut self) -> Result<Value> {
+        self.stack.pop().context("pickle stack underflow")
+    }
+
+    fn top(&mut self) -> Result<&mut Value> {
+        self.stack.last_mut().context("pickle stack underflow")
+    }
+
+    /// the values after the topmost mark
+    fn pop_mark(&mut self) -> Result<Vec<Value>> {
+        let mark = self
+            .stack
+            .iter()
+            .rposition(|v| matches!(v, Value::Mark))
+            .context("pickle mark not found")?;
+        let items = self.stack.split_off(mark + 1);
+        self.stack.pop();
+        Ok(items)
+    }
+
+    fn append(&mut self, items: Vec<Value>) -> Result<()> {
+        match self.top()? {
+            Value::List(list) => list.borrow_mut().extend(items),
+            Value::Object(o) => o.borrow_mut().list.extend(items),
+            _ => bail!("append to a value that is not a list"),
+        }
+        Ok(())
+    }
+
+    fn set_items(&mut self, items: Vec<Value>) -> Result<()> {
+        let mut pairs = vec![];
+        let mut items = items.into_iter();
+        while let (Some(k), Some(v)) = (items.next(), items.next()) {
+            pairs.push((k, v));
+        }
+        match self.top()? {
+            Value::Dict(dict) => dict.borrow_mut().extend(pairs),
+            Value::Object(o) => o.borrow_mut().items.extend(pairs),
+            _ => bail!("setitem on a value that is not a dict"),
+        }
+        Ok(())
+    }
+
+    fn memoize(&mut self, key: u64) -> Result<()> {
+        let v = self.top()?.clone();
+        self.memo.insert(key, v);
+        Ok(())
+    }
+
+    fn get(&mut self, key: u64) -> Result<()> {
+        let v = self
+            .memo
+            .get(&key)
+            .context("pickle memo key not found")?
+            .clone();
+        self.push(v);
+        Ok(())
+    }
+
+    fn long(&mut self, len: u64) -> Result<Value> {
+        let b = self.take(len)?;
+        if b.len() > 8 {
+            return Ok(Value::Bytes);
+        }
+        let negative = b.last().is_some_and(|b| b & 0x80 != 0);
+        let mut full = [if negative { 0xff } else { 0 }; 8];
+        full[..b.len()].copy_from_slice(b);
+        Ok(Value::Int(i64::from_le_bytes(full)))
+    }
+
+    /// joblib writes the data of numpy arrays right after the wrapper that describes them
+    fn skip_array(&mut self, state: &Value) -> Result<()> {
+        let count: i64 = state
+            .get("shape")
+            .map(|s| elements(&s).iter().map(|d| d.int().unwrap_or(0)).product())
+            .unwrap_or(1);
+        let dtype = state.get("dtype").context("array without dtype")?;
+        let Value::Object(dtype) = dtype else {
+            bail!("array dtype is not a numpy dtype");
+        };
+        let dtype = dtype.borrow();
+        let code = dtype.args.first().map(Value::name).unwrap_or_default();
+        if code.starts_with('O') {
+            // arrays of objects are pickles of their own
+            let mut inner = Unpickler::new(self.data, self.pos);
+            inner.load()?;
+            self.pos = inner.pos;
+            return Ok(());
+        }
+        // the item size is in the state of the dtype for strings, and in its code otherwise
+        let size = dtype
+            .state
+            .as_ref()
+            .and_then(|s| elements(s).get(5).and_then(Value::int))
+            .filter(|s| *s > 0)
+            .or_else(|| {
+                code.trim_start_matches(|c: char| !c.is_ascii_digit())
+                    .parse()
+                    .ok()
+            })
+            .context("unknown array item size")?;
+        if state
+            .get("numpy_array_alignment_bytes")
+            .is_some_and(|a| a.int().is_some())
+        {
+            let padding = self.uint(1)?;
+            self.take(padding)?;
+        }
+        self.take(count.max(0) as u64 * size as u64)?;
+        Ok(())
+    }
+
+    /// the value of the pickle at the current position
+    fn load(&mut self) -> Result<Value> {
+        loop {
+            let op = self.uint(1)? as u8;
+            match op {
+                PROTO => {
+                    self.take(1)?;
+                }
+                // FRAME
+                0x95 => {
+                    self.take(8)?;
+                }
+                b'.' => return self.pop(),
+                b'(' => self.push(Value::Mark),
+                b'0' => {
+                    self.pop()?;
+                }
+                b'1' => {
+                    self.pop_mark()?;
+                }
+                b'2' => {
+                    let v = self.top()?.clone();
+                    self.push(v);
+                }
+                b'N' => self.push(Value::None),
+                0x88 => self.push(Value::Bool(true)),
+                0x89 => self.push(Value::Bool(false)),
+                b'I' => {
+                    let line = self.line()?;
+                    self.push(match line.as_str() {
+                        "00" => Value::Bool(false),
+                        "01" => Value::Bool(true),
+                        n => Value::Int(n.parse().context("bad pickle int")?),
+                    });
+                }
+                b'L' => {
+                    let line = self.line()?;
+                    let n = line.trim_end_matches('L');
+                    self.push(n.parse().map(Value::Int).unwrap_or(Value::Bytes));
+                }
+                b'J' => {
+                    let n = self.uint(4)? as u32 as i32;
+                    self.push(Value::Int(n as i64));
+                }
+                b'K' => {
+                    let n = self.uint(1)?;
+                    self.push(Value::Int(n as i64));
+                }
+                b'M' => {
+                    let n = self.uint(2)?;
+                    self.push(Value::Int(n as i64));
+                }
+                0x8a => {
+                    let len = self.uint(1)?;
+                    let v = self.long(len)?;
+                    self.push(v);
+                }
+                0x8b => {
+                    let len = self.uint(4)?;
+                    let v = self.long(len)?;
+                    self.push(v);
+                }
+                b'F' => {
+                    let line = self.line()?;
+                    self.push(Value::Float(
+                        line.trim().parse().context("bad pickle float")?,
+                    ));
+                }
+                b'G' => {
+                    let b = self.take(8)?;
+                    self.push(Value::Float(f64::from_be_bytes(b.try_into()?)));
+                }
+                b'S' => {
+                    let line = self.line()?;
+                    let s = line.trim_matches(|c| c == '\'' || c == '"');
+                    self.push(Value::Str(s.to_string()));
+                }
+                b'V' => {
+                    let line = self.line()?;
+                    self.push(Value::Str(line));
+                }
+                b'T' | b'X' => {
+                    let len = self.uint(4)?;
+                    let v = self.string(len)?;
+                    self.push(v);
+                }
+                b'U' | 0x8c => {
+                    let len = self.uint(1)?;
+                    let v = self.string(len)?;
+                    self.push(v);
+                }
+                0x8d => {
+                    let len = self.uint(8)?;
+                    let v = self.string(len)?;
+                    self.push(v);
+                }
+                b'B' | b'C' | 0x8e | 0x96 => {
+                    let len = match op {
+                        b'B' => self.uint(4)?,
+                        b'C' => self.uint(1)?,
+                        _ => self.uint(8)?,
+                    };
+                    self.take(len)?;
+                    self.push(Value::Bytes);
+                }
+                // NEXT_BUFFER, the buffers are passed out of band
+                0x97 => self.push(Value::Bytes),
+                // READONLY_BUFFER
+                0x98 => {}
+                b']' => self.push(Value::List(Default::default())),
+                b'l' => {
+                    let items = self.pop_mark()?;
+                    self.push(Value::List(Rc::new(RefCell::new(items))));
+                }
+                b'a' => {
+                    let v = self.pop()?;
+                    self.append(vec![v])?;
+                }
+                b'e' => {
+                    let items = self.pop_mark()?;
+                    self.append(items)?;
+                }
+                b')' => self.push(Value::Tuple(Default::default())),
+                b't' => {
+                    let items = self.pop_mark()?;
+                    self.push(Value::Tuple(Rc::new(items)));
+                }
+                0x85..=0x87 => {
+                    let n = (op - 0x84) as usize;
+                    let at = self
+                        .stack
+                        .len()
+                        .checked_sub(n)
+                        .context("pickle stack underflow")?;
+                    let items = self.stack.split_off(at);
+                    self.push(Value::Tuple(Rc::new(items)));
+                }
+                b'}' => self.push(Value::Dict(Default::default())),
+                b'd' => {
+                    let items = self.pop_mark()?;
+                    self.push(Value::Dict(Default::default()));
+                    self.set_items(items)?;
+                }
+                b's' => {
+                    let v = self.pop()?;
+                    let k = self.pop()?;
+                    self.set_items(vec![k, v])?;
+                }
+                b'u' => {
+                    let items = self.pop_mark()?;
+                    self.set_items(items)?;
+                }
+                // EMPTY_SET
+                0x8f => self.push(Value::List(Default::default())),
+                // ADDITEMS
+                0x90 => {
+                    let items = self.pop_mark()?;
+                    self.append(items)?;
+                }
+                // FROZENSET
+                0x91 => {
+                    let items = self.pop_mark()?;
+                    self.push(Value::List(Rc::new(RefCell::new(items))));
+                }
+                b'c' => {
+                    let module = self.line()?;
+                    let name = self.line()?;
+                    self.push(Value::Global(format!("{module}.{name}")));
+                }
+                // STACK_GLOBAL
+                0x93 => {
+                    let name = self.pop()?.name();
+                    let module = self.pop()?.name();
+                    self.push(Value::Global(format!("{module}.{name}")));
+                }
+                // EXT1, EXT2, EXT4: classes registered with copyreg by code
+                0x82..=0x84 => {
+                    let code = self.uint(match op {
+                        0x82 => 1,
+                        0x83 => 2,
+                        _ => 4,
+                    })?;
+                    self.push(Value::Global(format!("extension {code}")));
+                }
+                b'R' | 0x81 => {
+                    let mut args = elements(&self.pop()?);
+                    let mut class = self.pop()?.name();
+                    // how protocols 0 and 1 create objects
+                    if matches!(
+                        class.as_str(),
+                        "copy_reg._reconstructor" | "copyreg._reconstructor"
+                    ) && !args.is_empty()
+                    {
+                        class = args[0].name();
+                        args.clear();
+                    }
+                    self.push(Value::object(class, args));
+                }
+                // NEWOBJ_EX
+                0x92 => {
+                    self.pop()?;
+                    let args = elements(&self.pop()?);
+                    let class = self.pop()?.name();
+                    self.push(Value::object(class, args));
+                }
+                b'i' => {
+                    let module = self.line()?;
+                    let name = self.line()?;
+                    let args = self.pop_mark()?;
+                    self.push(Value::object(format!("{module}.{name}"), args));
+                }
+                b'o' => {
+                    let mut items = self.pop_mark()?.into_iter();
+                    let class = items.next().context("pickle obj without class")?.name();
+                    self.push(Value::object(class, items.collect()));
+                }
+                b'b' => {
+                    let state = self.pop()?;
+                    let is_array = match self.top()? {
+                        Value::Object(o) => {
+                            let mut o = o.borrow_mut();
+                            o.state = Some(state.clone());
+                            o.class == "joblib.numpy_pickle.NumpyArrayWrapper"
+                        }
+                        Value::Dict(dict) => {
+                            if let Value::Dict(items) = &state {
+                                dict.borrow_mut().extend(items.borrow().iter().cloned());
+                            }
+                            false
+                        }
+                        _ => bail!("pickle build on a value that is not an object"),
+                    };
+                    if is_array {
+                        self.skip_array(&state)?;
+                    }
+                }
+                b'P' => {
+                    let id = self.line()?;
+                    self.push(Value::Persistent(Rc::new(Value::Str(id))));
+                }
+                b'Q' => {
+                    let id = self.pop()?;
+                    self.push(Value::Persistent(Rc::new(id)));
+                }
+                b'g' => {
+                    let key = self.line()?.parse().context("bad pickle memo key")?;
+                    self.get(key)?;
+                }
+                b'h' => {
+                    let key = self.uint(1)?;
+                    self.get(key)?;
+                }
+                b'j' => {
+                    let key = self.uint(4)?;
+                    self.get(key)?;
+                }
+                b'p' => {
+                    let key = self.line()?.parse().context("bad pickle memo key")?;
+                    self.memoize(key)?;
+                }
+                b'q' => {
+                    let key = self.uint(1)?;
+                    self.memoize(key)?;
+                }
+                b'r' => {
+                    let key = self.uint(4)?;
+                    self.memoize(key)?;
+                }
+                // MEMOIZE
+                0x94 => {
+                    let key = self.memo.len() as u64;
+                    self.memoize(key)?;
+                }
+                op => bail!("unknown pickle opcode {op:#04x} at {}", self.pos - 1),
+            }
+        }
+    }
+}
+
+/// a value that fits on one line
+fn inline(v: &Value) -> Option<String> {
+    Some(match v {
+        Value::None => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Float(f) => format!("{f:?}"),
+        Value::Str(s) => s.replace('\n', "\\n"),
+        Value::Global(name) => name.clone(),
+        Value::Tuple(items) => sequence(items, '(', ')')?,
+        Value::List(items) => sequence(&items.borrow(), '[', ']')?,
+        _ => return None,
+    })
+}
+
+/// a sequence of values that fit on one line
+fn sequence(items: &[Value], open: char, close: char) -> Option<String> {
+    let mut shown = items
+        .iter()
+        .take(MAX_ITEMS)
+        .map(|v| match v {
+            Value::Tuple(_) | Value::List(_) => None,
+            v => inline(v),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if items.len() > MAX_ITEMS {
+        shown.push("…".to_string());
+    }
+    Some(format!("{open}{}{close}", shown.join(", ")))
+}
+
+/// the type of the elements of a numpy array from its dtype
+fn dtype(v: &Value) -> String {
+    match v {
+        Value::Object(o) => o.borrow().args.first().map(Value::name).unwrap_or_default(),
+        v => v.name(),
+    }
+}
+
+/// tensors and arrays as their type and shape
+fn array(o: &Object) -> Option<String> {
+    let class = o.class.as_str();
+    if class.starts_with("torch._utils._rebuild_tensor") {
+        // the storage is ('storage', torch.FloatStorage, key, location, size)
+        let storage = match o.args.first()? {
+            Value::Persistent(id) => elements(id).get(1).map(Value::name),
+            _ => None,
+        }
+        .unwrap_or_default();
+        let dtype = storage
+            .trim_start_matches("torch.")
+            .trim_end_matches("Storage");
+        return Some(format!("tensor {dtype} {}", inline(o.args.get(2)?)?));
+    }
+    if class.ends_with("multiarray._reconstruct") {
+        // the state is (version, shape, dtype, is_fortran, data)
+        let state = elements(o.state.as_ref()?);
+        return Some(format!(
+            "ndarray {} {}",
+            dtype(state.get(2)?),
+            inline(state.get(1)?)?
+        ));
+    }
+    if class == "joblib.numpy_pickle.NumpyArrayWrapper" {
+        let state = o.state.as_ref()?;
+        return Some(format!(
+            "ndarray {} {}",
+            dtype(&state.get("dtype")?),
+            inline(&state.get("shape")?)?
+        ));
+    }
+    None
+}
+
+fn child(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn push(out: &mut Vec<String>, path: &str, text: String) {
+    out.push(if path.is_empty() {
+        text
+    } else {
+        format!("{path}: {text}")
+    });
+}
+
+fn render_items(items: &[(Value, Value)], path: &str, depth: usize, out: &mut Vec<String>) {
+    for (k, v) in items {
+        let key = inline(k).unwrap_or_else(|| "?".to_string());
+        render(v, &child(path, &key), depth + 1, out);
+    }
+}
+
+fn render_sequence(items: &[Value], path: &str, depth: usize, out: &mut Vec<String>) {
+    for (i, v) in items.iter().enumerate().take(MAX_ITEMS) {
+        render(v, &child(path, &i.to_string()), depth + 1, out);
+    }
+}
+
+fn render(v: &Value, path: &str, depth: usize, out: &mut Vec<String>) {
+    if depth > MAX_DEPTH || out.len() >= MAX_LINES {
+        return;
+    }
+    if let Some(text) = inline(v) {
+        push(out, path, text);
+        return;
+    }
+    match v {
+        Value::Tuple(items) => render_sequence(items, path, depth, out),
+        Value::List(items) => render_sequence(&items.borrow(), path, depth, out),
+        Value::Dict(items) => render_items(&items.borrow(), path, depth, out),
+        Value::Object(o) => {
+            let o = o.borrow();
+            if let Some(text) = array(&o) {
+                push(out, path, text);
+                return;
+            }
+            if o.class.starts_with("torch._utils._rebuild_parameter") {
+                if let Some(tensor) = o.args.first() {
+                    render(tensor, path, depth + 1, out);
+                }
+                return;
+            }
+            match sequence(&o.args, '(', ')') {
+                Some(args) if !o.args.is_empty() => push(out, path, format!("{}{args}", o.class)),
+                // the class of dicts is noise above their keys
+                _ if o.class == "collections.OrderedDict" => {}
+                _ => {
+                    push(out, path, o.class.clone());
+                    render_sequence(&o.args, path, depth, out);
+                }
+            }
+            render_items(&o.items, path, depth, out);
+            render_sequence(&o.list, path, depth, out);
+            match &o.state {
+                Some(Value::Dict(items)) => render_items(&items.borrow(), path, depth, out),
+                // (state, slot state)
+                Some(Value::Tuple(parts))
+                    if parts
+                        .iter()
+                        .all(|p| matches!(p, Value::Dict(_) | Value::None)) =>
+                {
+                    for part in parts.iter() {
+                        if let Value::Dict(items) = part {
+                            render_items(&items.borrow(), path, depth, out);
+                        }
+                    }
+                }
+                Some(state) => render(state, &child(path, "state"), depth + 1, out),
+                None => {}
+            }
+        }
+        Value::Bytes | Value::Persistent(_) | Value::Mark => {}
+        _ => {}
+    }
+}
+
+/// the values of the pickles at the start of `data` as `path: value` lines, with the keys and
+/// indices of the containers a value is in joined by dots. Pickles that follow each other (like in
+/// old torch files) are read until one can not be read.
+pub fn convert(data: &[u8]) -> Result<Vec<String>> {
+    let mut out = vec![];
+    let mut pos = 0;
+    loop {
+        let mut unpickler = Unpickler::new(data, pos);
+        match unpickler.load() {
+            Result::Ok(v) => render(&v, "", 0, &mut out),
+            Err(e) if pos == 0 => return Err(e),
+            Err(_) => break,
+        }
+        pos = unpickler.pos;
+        if data.get(pos) != Some(&PROTO) {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn torch_state_dict() -> Result<()> {
+        // torch.save({"epoch": 3, "model": model.state_dict(), "optimizer": {"lr": 0.001}})
+        let p = b"\x80\x02}q\x00(X\x05\x00\x00\x00epochq\x01K\x03X\x05\x00\x00\x00modelq\x02ccollections\nOrderedDict\nq\x03)Rq\x04(X\x09\x00\x00\x00fc.weightq\x05ctorch._utils\n_rebuild_tensor_v2\nq\x06((X\x07\x00\x00\x00storageq\x07ctorch\nFloatStorage\nq\x08X\x01\x00\x00\x000q\x09X\x03\x00\x00\x00cpuq\nK\x01tq\x0bQK\x00K\x02K\x04\x86q\x0cK\x01K\x01\x86q\x0d\x89h\x03)Rq\x0etq\x0fRq\x10X\x07\x00\x00\x00fc.biasq\x11h\x06((h\x07h\x08X\x01\x00\x00\x001q\x12h\nK\x01tq\x13QK\x00K\x02\x85q\x14K\x01\x85q\x15\x89h\x03)Rq\x16tq\x17Rq\x18uX\x09\x00\x00\x00optimizerq\x19}q\x1aX\x02\x00\x00\x00lrq\x1bG?PbM\xd2\xf1\xa9\xfcsu.";
+        assert_eq!(
+            convert(p)?,
+            [
+                "epoch: 3",
+                "model.fc.weight: tensor Float (2, 4)",
+                "model.fc.bias: tensor Float (2)",
+                "optimizer.lr: 0.001",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sklearn_objects() -> Result<()> {
+        // Pipeline([("scaler", StandardScaler())]) with protocol 0 and 4
+        let p0 = b"ccopy_reg\n_reconstructor\np0\n(csklearn.pipeline\nPipeline\np1\nc__builtin__\nobject\np2\nNtp3\nRp4\n(dp5\nVsteps\np6\n(lp7\n(Vscaler\np8\ng0\n(csklearn.preprocessing._data\nStandardScaler\np9\ng2\nNtp10\nRp11\n(dp12\nVwith_mean\np13\nI01\nsbtp14\nasVmemory\np15\nNsb.";
+        let p4 = b"\x80\x04\x95\x8e\x00\x00\x00\x00\x00\x00\x00\x8c\x10sklearn.pipeline\x94\x8c\x08Pipeline\x94\x93\x94)\x81\x94}\x94(\x8c\x05steps\x94]\x94\x8c\x06scaler\x94\x8c\x1bsklearn.preprocessing._data\x94\x8c\x0eStandardScaler\x94\x93\x94)\x81\x94}\x94\x8c\x09with_mean\x94\x88sb\x86\x94a\x8c\x06memory\x94Nub.";
+        let lines = [
+            "sklearn.pipeline.Pipeline",
+            "steps.0.0: scaler",
+            "steps.0.1: sklearn.preprocessing._data.StandardScaler",
+            "steps.0.1.with_mean: True",
+            "memory: None",
+        ];
+        assert_eq!(convert(p0)?, lines);
+        assert_eq!(convert(p4)?, lines);
+        Ok(())
+    }
+
+    #[test]
+    fn joblib_arrays() -> Result<()> {
+        // joblib.dump of a LinearRegression, the array data follows the wrapper of each array
+        let p = [
+            &b"\x80\x02csklearn.linear_model._base\nLinearRegression\nq\x00)\x81q\x01}q\x02(X\
+              \x0d\x00\x00\x00fit_interceptq\x03\x88X\x05\x00\x00\x00coef_q\x04cjoblib.numpy_p\
+              ickle\nNumpyArrayWrapper\nq\x05)\x81q\x06}q\x07(X\x08\x00\x00\x00subclassq\x08cn\
+              umpy\nndarray\nq\x09X\x05\x00\x00\x00shapeq\nK\x03\x85q\x0bX\x05\x00\x00\x00orde\
+              rq\x0cX\x01\x00\x00\x00Cq\x0dX\x05\x00\x00\x00dtypeq\x0ecnumpy\ndtype\nq\x0fX\
+              \x02\x00\x00\x00f8q\x10\x89\x88\x87q\x11Rq\x12(K\x03X\x01\x00\x00\x00<q\x13NNNJ\
+              \xff\xff\xff\xffJ\xff\xff\xff\xffK\x00tq\x14bX\n\x00\x00\x00allow_mmapq\x15\x89X\
+              \x1b\x00\x00\x00numpy_array_alignment_bytesq\x16K\x10ub"[..],
+            b"\x03\xff\xff\xff",
+            &[1; 3 * 8],
+            b"X\x11\x00\x00\x00feature_names_in_q\x17h\x05)\x81q\x18}q\x19(h\x08h\x09h\nK\x02\
+              \x85q\x1ah\x0ch\x0dh\x0eh\x0fX\x03\x00\x00\x00U10q\x1b\x89\x88\x87q\x1cRq\x1d(K\
+              \x03h\x13NNNK(K\x04K\x08tq\x1ebh\x15\x89h\x16K\x10ub",
+            b"\x03\xff\xff\xff",
+            &[1; 2 * 40],
+            b"X\n\x00\x00\x00intercept_q\x1fG?\xe0\x00\x00\x00\x00\x00\x00ub.",
+        ]
+        .concat();
+        assert_eq!(
+            convert(&p)?,
+            [
+                "sklearn.linear_model._base.LinearRegression",
+                "fit_intercept: True",
+                "coef_: ndarray f8 (3)",
+                "feature_names_in_: ndarray U10 (2)",
+                "intercept_: 0.5",
+            ]
+        );
+        Ok(())
+    }
+}
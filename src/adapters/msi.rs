@@ -0,0 +1,650 @@
+//! Windows Installer packages: the tables that say what an installer does to a system (properties,
+//! registry values, custom actions, files) as text, the installed files from the embedded cabinets
+//! under their install paths, and the other streams (`Binary` table entries like custom action
+//! scripts and dlls) as members.
+//!
+//! A package is a compound file. Its tables are streams stored column by column, with strings
+//! referring to a shared string pool.
+use super::*;
+use crate::adapters::le::{u16_at, u32_at};
+use crate::vfs::{MemoryFs, adapt_vfs};
+use anyhow::*;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["msi", "msm", "msp"];
+
+const ALPHABET: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz._";
+/// prefix of the table streams
+const TABLE_MARKER: u32 = 0x4840;
+/// the tables written as text
+const TABLES: &[&str] = &["Property", "Registry", "CustomAction", "File"];
+
+/// column type bits. Strings also have the non-binary bit 0x0400, streams don't
+const TYPE_VALID: u16 = 0x0100;
+const TYPE_STRING: u16 = 0x0800;
+const TYPE_NULLABLE: u16 = 0x1000;
+/// the string pool codepage has this bit set if string references are 3 bytes long
+const LONG_STRING_REFS: u32 = 0x8000_0000;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "msi".to_owned(),
+        version: 2,
+        description: "Writes the Property, Registry, CustomAction and File tables of Windows Installer packages as text, extracts the installed files from the embedded cabinets under their install paths and recurses into them and the binary streams".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-msi".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MsiAdapter;
+
+impl MsiAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for MsiAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// decode a stream name. Msi packs two name characters from [`ALPHABET`] into one code point
+/// in 0x3800..0x4800, a single one into 0x4800..0x4840, and tables start with 0x4840 (shown as `!`)
+fn decode_name(name: &str) -> String {
+    let mut out = String::new();
+    for c in name.chars() {
+        let c = c as u32;
+        match c {
+            0x3800..0x4800 => {
+                let x = (c - 0x3800) as usize;
+                out.push(ALPHABET[x & 0x3f] as char);
+                out.push(ALPHABET[x >> 6] as char);
+            }
+            0x4800..TABLE_MARKER => out.push(ALPHABET[(c - 0x4800) as usize] as char),
+            TABLE_MARKER => out.push('!'),
+            _ => out.extend(char::from_u32(c)),
+        }
+    }
+    out
+}
+
+/// the long part of a `short|long` file name
+fn long_name(name: &str) -> &str {
+    name.rsplit('|').next().unwrap_or(name)
+}
+
+/// the encoding of an ansi codepage number, windows-1252 if unknown
+fn codepage_encoding(codepage: u32) -> &'static encoding_rs::Encoding {
+    let label = match codepage {
+        65001 => "utf-8".to_string(),
+        932 => "shift_jis".to_string(),
+        936 => "gbk".to_string(),
+        949 => "euc-kr".to_string(),
+        950 => "big5".to_string(),
+        cp => format!("windows-{cp}"),
+    };
+    encoding_rs::Encoding::for_label(label.as_bytes()).unwrap_or(encoding_rs::WINDOWS_1252)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Int(i32),
+    Str(String),
+    Stream,
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => std::fmt::Result::Ok(()),
+            Value::Int(i) => write!(f, "{i}"),
+            // multi-line values (inline scripts) stay on the row's line
+            Value::Str(s) => write!(f, "{}", s.replace("\r\n", "\\n").replace('\n', "\\n")),
+            Value::Stream => write!(f, "[stream]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Int2,
+    Int4,
+    Str,
+    Stream,
+}
+
+struct Column {
+    name: String,
+    kind: ColumnType,
+}
+
+impl ColumnType {
+    fn from_bits(bits: u16) -> ColumnType {
+        if bits & !TYPE_NULLABLE == TYPE_STRING | TYPE_VALID {
+            ColumnType::Stream
+        } else if bits & TYPE_STRING != 0 {
+            ColumnType::Str
+        } else if bits & 0xff == 4 {
+            ColumnType::Int4
+        } else {
+            ColumnType::Int2
+        }
+    }
+}
+
+/// a package opened from memory, with its streams by decoded name
+struct Package<'a> {
+    file: cfb::CompoundFile<Cursor<&'a [u8]>>,
+    streams: Vec<(String, PathBuf)>,
+    strings: Vec<String>,
+    /// size of a string reference in a table
+    string_ref: usize,
+}
+
+impl<'a> Package<'a> {
+    fn open(data: &'a [u8]) -> Result<Package<'a>> {
+        let file = cfb::CompoundFile::open(Cursor::new(data))?;
+        let streams = file
+            .walk()
+            .filter(|e| e.is_stream())
+            .map(|e| (decode_name(e.name()), e.path().to_path_buf()))
+            .collect();
+        let mut package = Package {
+            file,
+            streams,
+            strings: vec![],
+            string_ref: 2,
+        };
+        package.read_string_pool()?;
+        Ok(package)
+    }
+
+    fn stream(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some((_, path)) = self.streams.iter().find(|(n, _)| n == name) else {
+            return Ok(None);
+        };
+        let mut data = vec![];
+        self.file.open_stream(path)?.read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// `!_StringPool` has the codepage and a length and reference count per string, the characters
+    /// are concatenated in `!_StringData`
+    fn read_string_pool(&mut self) -> Result<()> {
+        let (Some(pool), Some(data)) = (self.stream("!_StringPool")?, self.stream("!_StringData")?)
+        else {
+            return Ok(());
+        };
+        let codepage = u32_at(&pool, 0).context("empty string pool")?;
+        if codepage & LONG_STRING_REFS != 0 {
+            self.string_ref = 3;
+        }
+        let encoding = codepage_encoding(codepage & !LONG_STRING_REFS);
+        let mut pos = 4;
+        let mut start = 0usize;
+        while let (Some(len), Some(refs)) = (u16_at(&pool, pos), u16_at(&pool, pos + 2)) {
+            pos += 4;
+            let mut len = len as usize;
+            if len == 0 && refs != 0 {
+                // strings over 64k: the high bits are in the reference count, the real one follows
+                len = ((refs as usize) << 16) | u16_at(&pool, pos).unwrap_or(0) as usize;
+                pos += 4;
+            }
+            let bytes = data.get(start..start + len).unwrap_or_default();
+            self.strings
+                .push(encoding.decode_without_bom_handling(bytes).0.into_owned());
+            start += len;
+        }
+        Ok(())
+    }
+
+    /// the string for a reference, which is 1-based with 0 for null
+    fn string(&self, index: u32) -> Value {
+        match index.checked_sub(1) {
+            Some(i) => Value::Str(self.strings.get(i as usize).cloned().unwrap_or_default()),
+            None => Value::Null,
+        }
+    }
+
+    fn width(&self, kind: ColumnType) -> usize {
+        match kind {
+            ColumnType::Int2 | ColumnType::Stream => 2,
+            ColumnType::Int4 => 4,
+            ColumnType::Str => self.string_ref,
+        }
+    }
+
+    /// the rows of the table stream `!{name}`, which stores the columns one after the other
+    fn rows(&mut self, name: &str, columns: &[ColumnType]) -> Result<Vec<Vec<Value>>> {
+        let Some(data) = self.stream(&format!("!{name}"))? else {
+            return Ok(vec![]);
+        };
+        let row_size: usize = columns.iter().map(|c| self.width(*c)).sum();
+        if row_size == 0 {
+            return Ok(vec![]);
+        }
+        let count = data.len() / row_size;
+        let mut rows = vec![Vec::with_capacity(columns.len()); count];
+        let mut offset = 0;
+        for &kind in columns {
+            let width = self.width(kind);
+            for row in rows.iter_mut() {
+                let mut raw = [0u8; 4];
+                raw[..width].copy_from_slice(&data[offset..offset + width]);
+                let raw = u32::from_le_bytes(raw);
+                offset += width;
+                row.push(match kind {
+                    _ if raw == 0 && kind != ColumnType::Str => Value::Null,
+                    // integers are stored with the sign bit flipped
+                    ColumnType::Int2 => Value::Int((raw ^ 0x8000) as u16 as i16 as i32),
+                    ColumnType::Int4 => Value::Int((raw ^ 0x8000_0000) as i32),
+                    ColumnType::Str => self.string(raw),
+                    ColumnType::Stream => Value::Stream,
+                });
+            }
+        }
+        Ok(rows)
+    }
+
+    /// the columns of each table, from the `_Columns` system table
+    fn columns(&mut self) -> Result<HashMap<String, Vec<Column>>> {
+        use ColumnType::*;
+        let mut tables: HashMap<String, Vec<(i32, Column)>> = HashMap::new();
+        for row in self.rows("_Columns", &[Str, Int2, Str, Int2])? {
+            let [
+                Value::Str(table),
+                Value::Int(number),
+                Value::Str(name),
+                Value::Int(bits),
+            ] = row.as_slice()
+            else {
+                continue;
+            };
+            let kind = ColumnType::from_bits(*bits as u16);
+            let column = Column {
+                name: name.clone(),
+                kind,
+            };
+            tables
+                .entry(table.clone())
+                .or_default()
+                .push((*number, column));
+        }
+        Ok(tables
+            .into_iter()
+            .map(|(table, mut columns)| {
+                columns.sort_by_key(|(n, _)| *n);
+                (table, columns.into_iter().map(|(_, c)| c).collect())
+            })
+            .collect())
+    }
+
+    /// the string values of `table` by column name
+    fn records(
+        &mut self,
+        columns: &HashMap<String, Vec<Column>>,
+        table: &str,
+    ) -> Result<Vec<HashMap<String, String>>> {
+        let Some(columns) = columns.get(table) else {
+            return Ok(vec![]);
+        };
+        let kinds: Vec<ColumnType> = columns.iter().map(|c| c.kind).collect();
+        Ok(self
+            .rows(table, &kinds)?
+            .into_iter()
+            .map(|row| {
+                columns
+                    .iter()
+                    .zip(row)
+                    .filter_map(|(c, v)| match v {
+                        Value::Str(s) => Some((c.name.clone(), s)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// the install path of each file by its File table key, which is its name in the cabinets
+    fn file_paths(
+        &mut self,
+        columns: &HashMap<String, Vec<Column>>,
+    ) -> Result<HashMap<String, String>> {
+        let mut dirs = HashMap::new();
+        for row in self.records(columns, "Directory")? {
+            let (Some(dir), Some(default_dir)) = (row.get("Directory"), row.get("DefaultDir"))
+            else {
+                continue;
+            };
+            // `target[:source]`, both as `short|long` or just one name
+            let target = default_dir.split(':').next().unwrap_or_default();
+            let name = long_name(target).to_string();
+            dirs.insert(dir.clone(), (row.get("Directory_Parent").cloned(), name));
+        }
+        let dir_path = |dir: &str| {
+            let mut parts = vec![];
+            let mut current = dir.to_string();
+            // bounded, in case of loops
+            for _ in 0..=dirs.len() {
+                let Some((parent, name)) = dirs.get(&current) else {
+                    break;
+                };
+                let parent = parent
+                    .as_ref()
+                    .filter(|p| **p != current && dirs.contains_key(*p));
+                let Some(parent) = parent else {
+                    // the root, TARGETDIR
+                    break;
+                };
+                let parent_is_root = dirs.get(parent).is_some_and(|d| d.0.is_none());
+                match name.as_str() {
+                    // folders like ProgramFilesFolder are set when installing
+                    "." if parent_is_root => parts.push(current.clone()),
+                    "." => {}
+                    name => parts.push(name.to_string()),
+                }
+                current = parent.clone();
+            }
+            parts.reverse();
+            parts.join("/")
+        };
+        let components: HashMap<String, String> = self
+            .records(columns, "Component")?
+            .into_iter()
+            .filter_map(|mut row| Some((row.remove("Component")?, row.remove("Directory_")?)))
+            .collect();
+        let mut paths = HashMap::new();
+        for row in self.records(columns, "File")? {
+            let (Some(key), Some(name)) = (row.get("File"), row.get("FileName")) else {
+                continue;
+            };
+            let dir = row
+                .get("Component_")
+                .and_then(|c| components.get(c))
+                .map(|d| dir_path(d))
+                .unwrap_or_default();
+            let path = match dir.as_str() {
+                "" => long_name(name).to_string(),
+                dir => format!("{dir}/{}", long_name(name)),
+            };
+            paths.insert(key.clone(), path);
+        }
+        Ok(paths)
+    }
+
+    /// `table` as one line per row: `Table row=N: Column=value, ...`
+    fn table_text(&mut self, table: &str, columns: &[Column]) -> Result<String> {
+        let kinds: Vec<ColumnType> = columns.iter().map(|c| c.kind).collect();
+        let mut out = String::new();
+        for (i, row) in self.rows(table, &kinds)?.iter().enumerate() {
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(row)
+                .filter(|(_, v)| **v != Value::Null)
+                .map(|(c, v)| format!("{}={v}", c.name))
+                .collect();
+            out.push_str(&format!("{table} row={}: {}\n", i + 1, fields.join(", ")));
+        }
+        Ok(out)
+    }
+}
+
+/// the tables as text members and the embedded streams
+fn extract(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut package = Package::open(data)?;
+    let mut members = vec![];
+    let columns = package.columns()?;
+    for table in TABLES {
+        let Some(columns) = columns.get(*table) else {
+            continue;
+        };
+        let text = package.table_text(table, columns)?;
+        members.push((format!("tables/{table}.txt"), text.into_bytes()));
+    }
+    let paths = package.file_paths(&columns)?;
+    let streams = package.streams.clone();
+    for (name, _) in streams {
+        // tables and the \u{5}SummaryInformation property set
+        if name.starts_with('!') || name.starts_with(|c: char| c.is_control()) {
+            continue;
+        }
+        let Some(data) = package.stream(&name)? else {
+            continue;
+        };
+        if data.starts_with(b"MSCF") {
+            match cab::extract(&data) {
+                Result::Ok(files) => {
+                    for (key, content) in files {
+                        let path = paths.get(&key).unwrap_or(&key);
+                        members.push((format!("files/{path}"), content));
+                    }
+                    continue;
+                }
+                // the cab adapter will report it again
+                Err(e) => warn!("could not extract the cabinet {name}: {e:#}"),
+            }
+        }
+        members.push((name, data));
+    }
+    Ok(members)
+}
+
+#[async_trait]
+impl FileAdapter for MsiAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (mut inp, container) = ai.into_parts();
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let members = tokio::task::spawn_blocking(move || extract(&data)).await??;
+        adapt_vfs(Arc::new(MemoryFs::new(members)), container).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    fn encode_name(name: &str, table: bool) -> String {
+        let index = |c: u8| ALPHABET.iter().position(|a| *a == c).unwrap() as u32;
+        let mut out: String = table
+            .then(|| char::from_u32(TABLE_MARKER).unwrap())
+            .into_iter()
+            .collect();
+        for pair in name.as_bytes().chunks(2) {
+            let c = match pair {
+                [a, b] => 0x3800 + index(*a) + (index(*b) << 6),
+                [a] => 0x4800 + index(*a),
+                _ => unreachable!(),
+            };
+            out.push(char::from_u32(c).unwrap());
+        }
+        out
+    }
+
+    /// a cabinet with one stored file
+    fn cabinet(name: &str, content: &[u8]) -> Vec<u8> {
+        let file_entry = [
+            &(content.len() as u32).to_le_bytes()[..],
+            &[0; 4],
+            &[0; 2],
+            &[0; 6],
+            name.as_bytes(),
+            &[0],
+        ]
+        .concat();
+        let mut out = b"MSCF".to_vec();
+        out.extend([0; 12]);
+        out.extend(44u32.to_le_bytes());
+        out.extend([0; 4]);
+        out.extend([3, 1, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        out.extend((44 + file_entry.len() as u32).to_le_bytes());
+        out.extend([1, 0, 0, 0]);
+        out.extend(file_entry);
+        out.extend([0; 4]);
+        out.extend((content.len() as u16).to_le_bytes());
+        out.extend((content.len() as u16).to_le_bytes());
+        out.extend(content);
+        out
+    }
+
+    /// a package with a Property table, a file in a cabinet, a binary stream and the summary
+    /// information
+    fn package() -> Result<Vec<u8>> {
+        // s72: valid, non-binary, string, 72 characters
+        const KEY: i16 = 0x0d48 | 0x2000;
+        const NULLABLE: i16 = 0x0d48 | TYPE_NULLABLE as i16;
+        type Table = (
+            &'static str,
+            &'static [(&'static str, i16)],
+            &'static [&'static [&'static str]],
+        );
+        let tables: &[Table] = &[
+            (
+                "Property",
+                &[("Property", KEY), ("Value", NULLABLE)],
+                &[
+                    &["ProductName", "Example"],
+                    &["UpdateUrl", "https://example.com"],
+                ],
+            ),
+            (
+                "Directory",
+                &[
+                    ("Directory", KEY),
+                    ("Directory_Parent", NULLABLE),
+                    ("DefaultDir", NULLABLE),
+                ],
+                &[
+                    &["TARGETDIR", "", "SourceDir"],
+                    &["ProgramFilesFolder", "TARGETDIR", "."],
+                    &["INSTALLDIR", "ProgramFilesFolder", "EXAMPLE|Example App"],
+                ],
+            ),
+            (
+                "Component",
+                &[("Component", KEY), ("Directory_", NULLABLE)],
+                &[&["Main", "INSTALLDIR"]],
+            ),
+            (
+                "File",
+                &[
+                    ("File", KEY),
+                    ("Component_", NULLABLE),
+                    ("FileName", NULLABLE),
+                ],
+                &[&["filConfig", "Main", "CONFIG~1.INI|config.ini"]],
+            ),
+        ];
+        let mut strings: Vec<&str> = vec![];
+        let mut string_ref = |s: &'static str| -> [u8; 2] {
+            if s.is_empty() {
+                return [0, 0];
+            }
+            let i = strings.iter().position(|x| *x == s).unwrap_or_else(|| {
+                strings.push(s);
+                strings.len() - 1
+            });
+            (i as u16 + 1).to_le_bytes()
+        };
+        let int2 = |i: i16| (i as u16 ^ 0x8000).to_le_bytes();
+        // both _Columns and the tables store one column after the other
+        let mut columns: [Vec<u8>; 4] = Default::default();
+        let mut streams = vec![];
+        for (table, table_columns, rows) in tables {
+            for (i, (name, kind)) in table_columns.iter().enumerate() {
+                columns[0].extend(string_ref(table));
+                columns[1].extend(int2(i as i16 + 1));
+                columns[2].extend(string_ref(name));
+                columns[3].extend(int2(*kind));
+            }
+            let mut data = vec![];
+            for i in 0..table_columns.len() {
+                for row in *rows {
+                    data.extend(string_ref(row[i]));
+                }
+            }
+            streams.push((encode_name(table, true), data));
+        }
+        streams.push((encode_name("_Columns", true), columns.concat()));
+        let mut pool = 1252u32.to_le_bytes().to_vec();
+        for s in &strings {
+            pool.extend((s.len() as u16).to_le_bytes());
+            pool.extend(1u16.to_le_bytes());
+        }
+        streams.push((encode_name("_StringPool", true), pool));
+        streams.push((
+            encode_name("_StringData", true),
+            strings.concat().into_bytes(),
+        ));
+        streams.push(("\u{5}SummaryInformation".to_string(), b"summary".to_vec()));
+        streams.push((
+            encode_name("Binary.setup.vbs", false),
+            b"WScript.Echo 1".to_vec(),
+        ));
+        streams.push((
+            encode_name("cab1.cab", false),
+            cabinet("filConfig", b"[app]\nport=8080\n"),
+        ));
+
+        let mut file = cfb::CompoundFile::create(Cursor::new(vec![]))?;
+        for (name, content) in streams {
+            file.create_stream(format!("/{name}"))?
+                .write_all(&content)?;
+        }
+        file.flush()?;
+        Ok(file.into_inner().into_inner())
+    }
+
+    #[test]
+    fn tables_and_streams() -> Result<()> {
+        let members = extract(&package()?)?;
+        let members: Vec<(&str, String)> = members
+            .iter()
+            .map(|(p, d)| (p.as_str(), String::from_utf8_lossy(d).into_owned()))
+            .collect();
+        assert_eq!(
+            members,
+            vec![
+                (
+                    "tables/Property.txt",
+                    "Property row=1: Property=ProductName, Value=Example\n\
+                     Property row=2: Property=UpdateUrl, Value=https://example.com\n"
+                        .to_string()
+                ),
+                (
+                    "tables/File.txt",
+                    "File row=1: File=filConfig, Component_=Main, FileName=CONFIG~1.INI|config.ini\n"
+                        .to_string()
+                ),
+                (
+                    "files/ProgramFilesFolder/Example App/config.ini",
+                    "[app]\nport=8080\n".to_string()
+                ),
+                ("Binary.setup.vbs", "WScript.Echo 1".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
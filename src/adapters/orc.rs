@@ -0,0 +1,138 @@
+use super::*;
+use super::{custom::map_exe_error, writing::async_writeln};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::process::Stdio;
+use tokio::io::AsyncWrite;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command;
+use writing::WritingFileAdapter;
+
+static EXTENSIONS: &[&str] = &["orc"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "orc".to_owned(),
+        version: 1,
+        description:
+            "Uses orc-metadata and orc-contents (from the Apache ORC tools) to print the schema and one JSON line per row"
+                .to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            ..AdapterCapabilities::runs(&["orc-metadata", "orc-contents"])
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OrcAdapter;
+
+impl OrcAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for OrcAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn spawn_fail(exe: &str) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| {
+        map_exe_error(
+            e,
+            exe,
+            "Make sure you have the Apache ORC tools (orc-metadata, orc-contents) installed.",
+        )
+    }
+}
+
+/// the `type` field of the `orc-metadata` json output, e.g. `struct<id:bigint,name:string>`
+fn schema_from_metadata(metadata: &[u8]) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_slice(metadata).ok()?;
+    Some(v.get("type")?.as_str()?.to_string())
+}
+
+#[async_trait]
+impl WritingFileAdapter for OrcAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            ..
+        } = ai;
+        // the orc footer is at the end of the file (seekable_input), so this is always a real file
+        let inp_fname = filepath_hint;
+
+        let metadata = crate::audit::output(Command::new("orc-metadata").arg(&inp_fname))
+            .await
+            .map_err(spawn_fail("orc-metadata"))?;
+        if !metadata.status.success() {
+            return Err(format_err!(
+                "orc-metadata failed: {:?}\n{}",
+                metadata.status,
+                String::from_utf8_lossy(&metadata.stderr)
+            ));
+        }
+        match schema_from_metadata(&metadata.stdout) {
+            Some(schema) => async_writeln!(oup, "{line_prefix}schema: {schema}")?,
+            None => async_writeln!(oup, "{line_prefix}[rga: could not parse orc-metadata output]")?,
+        }
+
+        let (mut contents, spawned) = crate::audit::spawn(
+            Command::new("orc-contents")
+                .arg(&inp_fname)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .map_err(spawn_fail("orc-contents"))?;
+        let mut lines = BufReader::new(
+            contents
+                .stdout
+                .as_mut()
+                .context("orc-contents stdout not piped")?,
+        )
+        .lines();
+        while let Some(line) = lines.next_line().await? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        let status = contents.wait().await?;
+        spawned.exited(&status);
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut e) = contents.stderr.take() {
+                let _ = e.read_to_string(&mut stderr).await;
+            }
+            return Err(format_err!("orc-contents failed: {:?}\n{}", status, stderr));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn metadata_schema() {
+        let out = br#"{ "name": "x.orc", "type": "struct<id:bigint,name:string>", "rows": 2 }"#;
+        assert_eq!(
+            schema_from_metadata(out).as_deref(),
+            Some("struct<id:bigint,name:string>")
+        );
+        assert_eq!(schema_from_metadata(b"garbage"), None);
+    }
+}
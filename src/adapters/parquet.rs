@@ -0,0 +1,232 @@
+use super::{writing::WritingFileAdapter, *};
+use ::parquet::arrow::ProjectionMask;
+use ::parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
+use ::parquet::file::reader::ChunkReader;
+use anyhow::Result;
+use arrow_array::{RecordBatch, RecordBatchReader};
+use arrow_cast::display::{ArrayFormatter, FormatOptions};
+use async_trait::async_trait;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["parquet", "parq"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "parquet".to_owned(),
+        version: 1,
+        description:
+            "Uses the arrow/parquet crates to render each row group of a parquet file as tab separated text"
+                .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ParquetAdapter;
+
+impl ParquetAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ParquetAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// keep one row per line, otherwise the column alignment is lost
+fn escape_cell(s: &str) -> String {
+    s.replace('\\', r"\\")
+        .replace('\t', r"\t")
+        .replace('\n', r"\n")
+        .replace('\r', r"\r")
+}
+
+fn write_batch(
+    batch: &RecordBatch,
+    options: &FormatOptions,
+    line_prefix: &str,
+    s: &mut impl Write,
+) -> Result<()> {
+    let formatters = batch
+        .columns()
+        .iter()
+        .map(|c| ArrayFormatter::try_new(c.as_ref(), options))
+        .collect::<Result<Vec<_>, _>>()?;
+    for row in 0..batch.num_rows() {
+        let row_str = formatters
+            .iter()
+            .map(|f| escape_cell(&f.value(row).to_string()))
+            .collect::<Vec<_>>()
+            .join("\t");
+        writeln!(s, "{line_prefix}{row_str}")?;
+    }
+    Ok(())
+}
+
+fn synchronous_dump_parquet<R: ChunkReader + 'static>(
+    open: impl Fn() -> Result<R>,
+    config: &RgaConfig,
+    line_prefix: &str,
+    mut s: impl Write,
+) -> Result<()> {
+    let metadata = ArrowReaderMetadata::load(&open()?, Default::default())?;
+    let mask = match &config.parquet_columns {
+        Some(cols) => ProjectionMask::columns(
+            metadata.metadata().file_metadata().schema_descr(),
+            cols.iter().map(|c| c.as_str()),
+        ),
+        None => ProjectionMask::all(),
+    };
+    let mut remaining = config.parquet_max_rows;
+    let options = FormatOptions::default().with_null("NULL");
+    let num_row_groups = metadata.metadata().num_row_groups();
+    debug!("parquet file has {} row groups", num_row_groups);
+    for i in 0..num_row_groups {
+        if remaining == Some(0) {
+            writeln!(s, "{line_prefix}[rga: row limit reached]")?;
+            break;
+        }
+        // batches can span row groups, so read each one separately to be able to label it
+        let mut builder =
+            ParquetRecordBatchReaderBuilder::new_with_metadata(open()?, metadata.clone())
+                .with_projection(mask.clone())
+                .with_row_groups(vec![i]);
+        if let Some(n) = remaining {
+            builder = builder.with_limit(n);
+        }
+        let reader = builder.build()?;
+        let header = reader
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().as_str())
+            .collect::<Vec<_>>()
+            .join("\t");
+        writeln!(s, "{line_prefix}row group {i}: {header}")?;
+        for batch in reader {
+            let batch = batch?;
+            if let Some(n) = remaining.as_mut() {
+                *n = n.saturating_sub(batch.num_rows());
+            }
+            write_batch(&batch, &options, line_prefix, &mut s)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for ParquetAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            is_real_file,
+            filepath_hint,
+            line_prefix,
+            config,
+            mut inp,
+            ..
+        } = ai;
+        let oup_sync = SyncIoBridge::new(oup);
+        if is_real_file {
+            // the footer is at the end of the file, so read it directly instead of from the stream
+            let file = std::fs::File::open(&filepath_hint)
+                .with_context(|| format!("opening {}", filepath_hint.display()))?;
+            tokio::task::spawn_blocking(move || {
+                synchronous_dump_parquet(|| Ok(file.try_clone()?), &config, &line_prefix, oup_sync)
+            })
+            .await?
+            .context("in synchronous parquet task")?;
+        } else {
+            // parquet is in an archive, needs random access so read it to memory
+            let mut buf = Vec::new();
+            inp.read_to_end(&mut buf).await?;
+            let buf = Bytes::from(buf);
+            tokio::task::spawn_blocking(move || {
+                synchronous_dump_parquet(|| Ok(buf.clone()), &config, &line_prefix, oup_sync)
+            })
+            .await?
+            .context("in synchronous parquet task")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use ::parquet::arrow::ArrowWriter;
+    use ::parquet::file::properties::WriterProperties;
+    use arrow_array::{ArrayRef, Int64Array, StringArray};
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+
+    fn example_parquet() -> Result<Vec<u8>> {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("id", Arc::new(Int64Array::from(vec![1, 2, 3])) as ArrayRef),
+            (
+                "name",
+                Arc::new(StringArray::from(vec![Some("foo"), None, Some("a\tb")])) as ArrayRef,
+            ),
+        ])?;
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(2)
+            .build();
+        let mut buf = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), Some(props))?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(buf)
+    }
+
+    async fn adapt_with(config: RgaConfig) -> Result<String> {
+        let adapter: Box<dyn FileAdapter> = Box::<ParquetAdapter>::default();
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("data.parquet"),
+            Box::pin(std::io::Cursor::new(example_parquet()?)),
+        );
+        a.config = config;
+        let res = adapter.adapt(a, &d).await?;
+        Ok(String::from_utf8(adapted_to_vec(res).await?)?)
+    }
+
+    #[tokio::test]
+    async fn row_groups() -> Result<()> {
+        assert_eq!(
+            adapt_with(RgaConfig::default()).await?,
+            "PREFIX:row group 0: id\tname\nPREFIX:1\tfoo\nPREFIX:2\tNULL\nPREFIX:row group 1: id\tname\nPREFIX:3\ta\\tb\n",
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn limit_and_projection() -> Result<()> {
+        let config = RgaConfig {
+            parquet_max_rows: Some(1),
+            parquet_columns: Some(vec!["name".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            adapt_with(config).await?,
+            "PREFIX:row group 0: name\nPREFIX:foo\nPREFIX:[rga: row limit reached]\n",
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! Patches and diffs (`git format-patch` series, `git diff`, `diff -u`): every line of a diff is
+//! prefixed with the path of the file it changes, so a search for a path finds the changes to it
+//! and matches in the changes show the file. Commit messages and mail headers are kept as they
+//! are, the base85 data of binary patches is left out.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+
+static EXTENSIONS: &[&str] = &["patch", "diff"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "patch".to_owned(),
+        version: 1,
+        description:
+            "Prefixes the lines of patches and diffs with the path of the file they change"
+                .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("text/x-diff".to_owned()),
+            FileMatcher::MimeType("text/x-patch".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PatchAdapter;
+
+impl PatchAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for PatchAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the lines of the header of a git diff, between `diff --git` and the first hunk
+const GIT_HEADERS: &[&str] = &[
+    "index ",
+    "old mode ",
+    "new mode ",
+    "deleted file mode ",
+    "new file mode ",
+    "similarity index ",
+    "dissimilarity index ",
+    "rename from ",
+    "rename to ",
+    "copy from ",
+    "copy to ",
+    "Binary files ",
+];
+
+/// the path in a `---`/`+++` line, None for /dev/null
+fn file_path(s: &str) -> Option<String> {
+    // diff -u adds the modification time after a tab
+    let s = s.split('\t').next().unwrap_or(s).trim_end();
+    let s = s.trim_matches('"');
+    if s == "/dev/null" {
+        return None;
+    }
+    Some(
+        s.strip_prefix("a/")
+            .or_else(|| s.strip_prefix("b/"))
+            .unwrap_or(s)
+            .to_string(),
+    )
+}
+
+/// the new path of a `diff --git a/<old> b/<new>` line
+fn git_path(s: &str) -> String {
+    if let Some((_, new)) = s.rsplit_once(" \"b/") {
+        return new.trim_end_matches('"').to_string();
+    }
+    match s.rsplit_once(" b/") {
+        Some((_, new)) => new.to_string(),
+        None => s.split_whitespace().last().unwrap_or(s).to_string(),
+    }
+}
+
+/// the number of lines of the old and new file in a `@@ -l,s +l,s @@` hunk header
+fn hunk_lengths(header: &str) -> Option<(u64, u64)> {
+    let mut ranges = header.strip_prefix("@@ ")?.split_whitespace();
+    let length = |range: &str| -> Option<u64> {
+        match range.split_once(',') {
+            Some((_, length)) => length.parse().ok(),
+            None => Some(1),
+        }
+    };
+    let old = length(ranges.next()?.strip_prefix('-')?)?;
+    let new = length(ranges.next()?.strip_prefix('+')?)?;
+    Some((old, new))
+}
+
+/// a line of the base85 data of a binary patch
+fn is_binary_data(line: &str) -> bool {
+    line.is_empty()
+        || line.starts_with("literal ")
+        || line.starts_with("delta ")
+        || (line.len() >= 6
+            && line.starts_with(|c: char| c.is_ascii_alphabetic())
+            && !line.contains(' '))
+}
+
+/// follows the diffs in a text to prefix their lines with the path of the file they change
+#[derive(Default)]
+pub struct DiffPaths {
+    /// the file of the current diff, None outside of diffs
+    path: Option<String>,
+    /// lines of the old and new file left in the current hunk
+    hunk: (u64, u64),
+    /// between `diff --git` and the first hunk
+    git_header: bool,
+    binary: bool,
+}
+
+impl DiffPaths {
+    fn prefixed(&self, line_prefix: &str, line: &str) -> String {
+        match &self.path {
+            Some(path) => format!("{line_prefix}{path}: {line}"),
+            None => format!("{line_prefix}{line}"),
+        }
+    }
+
+    /// the output of a line, None if it is left out
+    pub fn line(&mut self, line_prefix: &str, line: &str) -> Option<String> {
+        if self.hunk != (0, 0) {
+            let (old, new) = &mut self.hunk;
+            match line.chars().next() {
+                // some mail clients strip the space of empty context lines
+                Some(' ') | None => {
+                    *old = old.saturating_sub(1);
+                    *new = new.saturating_sub(1);
+                }
+                Some('-') => *old = old.saturating_sub(1),
+                Some('+') => *new = new.saturating_sub(1),
+                Some('\\') => {}
+                _ => {
+                    self.hunk = (0, 0);
+                    return self.line(line_prefix, line);
+                }
+            }
+            return Some(self.prefixed(line_prefix, line));
+        }
+        if self.binary {
+            if is_binary_data(line) {
+                return None;
+            }
+            self.binary = false;
+        }
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            self.path = Some(git_path(rest));
+            self.git_header = true;
+        } else if let Some(rest) = line.strip_prefix("+++ ") {
+            if let Some(path) = file_path(rest) {
+                self.path = Some(path);
+            }
+        } else if let Some(rest) = line.strip_prefix("--- ") {
+            // the start of a diff without the git header, or the old file of one with it
+            if !self.git_header {
+                self.path = file_path(rest);
+            }
+        } else if let Some(rest) = line.strip_prefix("Index: ") {
+            self.path = Some(rest.trim().to_string());
+        } else if self.path.is_some() && line.starts_with("@@ ") {
+            self.hunk = hunk_lengths(line).unwrap_or((0, 0));
+            self.git_header = false;
+        } else if self.path.is_some() && line == "GIT binary patch" {
+            self.binary = true;
+            self.git_header = false;
+        } else if !(self.path.is_some()
+            && (line.starts_with("\\ ") || GIT_HEADERS.iter().any(|h| line.starts_with(h))))
+        {
+            self.path = None;
+            self.git_header = false;
+        }
+        Some(self.prefixed(line_prefix, line))
+    }
+}
+
+/// writes the lines of `inp` prefixed with the paths of the files their diffs change
+pub async fn write_diffs(
+    inp: impl AsyncRead + Unpin,
+    line_prefix: &str,
+    mut oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let mut inp = BufReader::new(inp);
+    let mut paths = DiffPaths::default();
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if inp.read_until(b'\n', &mut buf).await? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some(line) = paths.line(line_prefix, line) {
+            async_writeln!(oup, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for PatchAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        write_diffs(ai.inp, &ai.line_prefix, &mut oup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn format_patch() {
+        let patch = "From 1c0ffee Mon Sep 17 00:00:00 2001
+From: Jane Doe <jane@example.com>
+Subject: [PATCH] mm: fix the budget check
+
+The check was off by one:
+ if (budget > max)
+
+---
+ mm/page_alloc.c | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/mm/page_alloc.c b/mm/page_alloc.c
+index 1111111..2222222 100644
+--- a/mm/page_alloc.c
++++ b/mm/page_alloc.c
+@@ -10,3 +10,3 @@ static int check(void)
+ {
+-\tif (budget > max)
++\tif (budget >= max)
+
+diff --git a/logo.png b/docs/logo.png
+similarity index 90%
+rename from logo.png
+rename to docs/logo.png
+GIT binary patch
+literal 12
+TcmZ?wbhEHbWMp7u0D>h8
+
+literal 0
+HcmV?d00001
+
+--
+2.43.0
+";
+        let mut paths = DiffPaths::default();
+        let lines: Vec<_> = patch.lines().filter_map(|l| paths.line("", l)).collect();
+        assert_eq!(
+            lines,
+            [
+                "From 1c0ffee Mon Sep 17 00:00:00 2001",
+                "From: Jane Doe <jane@example.com>",
+                "Subject: [PATCH] mm: fix the budget check",
+                "",
+                "The check was off by one:",
+                " if (budget > max)",
+                "",
+                "---",
+                " mm/page_alloc.c | 2 +-",
+                " 1 file changed, 1 insertion(+), 1 deletion(-)",
+                "",
+                "mm/page_alloc.c: diff --git a/mm/page_alloc.c b/mm/page_alloc.c",
+                "mm/page_alloc.c: index 1111111..2222222 100644",
+                "mm/page_alloc.c: --- a/mm/page_alloc.c",
+                "mm/page_alloc.c: +++ b/mm/page_alloc.c",
+                "mm/page_alloc.c: @@ -10,3 +10,3 @@ static int check(void)",
+                "mm/page_alloc.c:  {",
+                "mm/page_alloc.c: -\tif (budget > max)",
+                "mm/page_alloc.c: +\tif (budget >= max)",
+                "mm/page_alloc.c: ",
+                "docs/logo.png: diff --git a/logo.png b/docs/logo.png",
+                "docs/logo.png: similarity index 90%",
+                "docs/logo.png: rename from logo.png",
+                "docs/logo.png: rename to docs/logo.png",
+                "docs/logo.png: GIT binary patch",
+                "--",
+                "2.43.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn unified_diff() {
+        let diff = "diff -ur old/src/main.c new/src/main.c
+--- old/src/main.c\t2024-01-01 10:00:00
++++ new/src/main.c\t2024-01-02 10:00:00
+@@ -1 +1,2 @@
+-int main() {}
++int main() {
++}
+Only in new: README
+";
+        let mut paths = DiffPaths::default();
+        let lines: Vec<_> = diff
+            .lines()
+            .filter_map(|l| paths.line("x.diff: ", l))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "x.diff: diff -ur old/src/main.c new/src/main.c",
+                "x.diff: old/src/main.c: --- old/src/main.c\t2024-01-01 10:00:00",
+                "x.diff: new/src/main.c: +++ new/src/main.c\t2024-01-02 10:00:00",
+                "x.diff: new/src/main.c: @@ -1 +1,2 @@",
+                "x.diff: new/src/main.c: -int main() {}",
+                "x.diff: new/src/main.c: +int main() {",
+                "x.diff: new/src/main.c: +}",
+                "x.diff: Only in new: README",
+            ]
+        );
+    }
+}
@@ -0,0 +1,784 @@
+use super::{writing::WritingFileAdapter, *};
+use crate::adapters::le::{u16_be_at, u16_ordered_at, u32_at, u32_be_at, u32_ordered_at};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["pcap", "pcapng", "cap"];
+
+/// payload lines longer than this are cut, so binary streams without newlines don't pile up
+const MAX_LINE_LEN: usize = 4096;
+/// shorter printable runs are too likely to be noise in binary payloads
+const MIN_TEXT_LEN: usize = 4;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pcap".to_owned(),
+        version: 1,
+        description: "Parses pcap and pcapng network captures into one summary line per packet, DNS names, and the text in reassembled TCP streams and UDP payloads".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.tcpdump.pcap".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PcapAdapter;
+
+impl PcapAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for PcapAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a captured frame, in capture order
+struct Frame {
+    /// unix time in nanoseconds
+    time_ns: u64,
+    linktype: u32,
+    data: Vec<u8>,
+}
+
+/// read exactly `buf.len()` bytes. false on a clean end of file
+fn read_or_eof(r: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match r.read_exact(buf) {
+        std::result::Result::Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// reads frames from a classic pcap or a pcapng file
+enum CaptureReader<R: Read> {
+    Pcap {
+        r: R,
+        le: bool,
+        /// nanoseconds per unit of the sub-second timestamp field
+        frac_ns: u64,
+        linktype: u32,
+    },
+    PcapNg {
+        r: R,
+        le: bool,
+        /// (linktype, nanoseconds per timestamp unit) of each interface of the current section
+        interfaces: Vec<(u32, u64)>,
+    },
+}
+
+const PCAPNG_SHB: u32 = 0x0A0D0D0A;
+
+impl<R: Read> CaptureReader<R> {
+    fn new(mut r: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic).context("reading capture magic")?;
+        if u32::from_le_bytes(magic) == PCAPNG_SHB {
+            let mut reader = Self::PcapNg {
+                r,
+                le: true,
+                interfaces: vec![],
+            };
+            reader.section_header()?;
+            return Ok(reader);
+        }
+        let (le, frac_ns) = match magic {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (true, 1000),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (false, 1000),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (true, 1),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (false, 1),
+            _ => return Err(format_err!("not a pcap or pcapng file")),
+        };
+        let mut header = [0u8; 20];
+        r.read_exact(&mut header)?;
+        let linktype = u32_ordered_at(&header, 16, le).unwrap_or(1) & 0x0fff_ffff;
+        Ok(Self::Pcap {
+            r,
+            le,
+            frac_ns,
+            linktype,
+        })
+    }
+
+    /// the rest of a section header block, after its block type
+    fn section_header(&mut self) -> Result<()> {
+        let Self::PcapNg { r, le, interfaces } = self else {
+            return Ok(());
+        };
+        let mut head = [0u8; 8];
+        r.read_exact(&mut head)?;
+        *le = match head[4..8] {
+            [0x4d, 0x3c, 0x2b, 0x1a] => true,
+            [0x1a, 0x2b, 0x3c, 0x4d] => false,
+            _ => return Err(format_err!("invalid pcapng byte order magic")),
+        };
+        let len = u32_ordered_at(&head, 0, *le).unwrap_or(0) as usize;
+        let mut rest = vec![0u8; len.checked_sub(12).context("invalid pcapng block length")?];
+        r.read_exact(&mut rest)?;
+        interfaces.clear();
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>> {
+        match self {
+            Self::Pcap {
+                r,
+                le,
+                frac_ns,
+                linktype,
+            } => {
+                let mut head = [0u8; 16];
+                if !read_or_eof(r, &mut head)? {
+                    return Ok(None);
+                }
+                let secs = u32_ordered_at(&head, 0, *le).unwrap_or(0) as u64;
+                let frac = u32_ordered_at(&head, 4, *le).unwrap_or(0) as u64;
+                let incl_len = u32_ordered_at(&head, 8, *le).unwrap_or(0) as usize;
+                let mut data = vec![0u8; incl_len];
+                r.read_exact(&mut data).context("truncated packet record")?;
+                Ok(Some(Frame {
+                    time_ns: secs * 1_000_000_000 + frac * *frac_ns,
+                    linktype: *linktype,
+                    data,
+                }))
+            }
+            Self::PcapNg { .. } => self.next_ng_frame(),
+        }
+    }
+
+    fn next_ng_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            let Self::PcapNg { r, le, interfaces } = self else {
+                unreachable!()
+            };
+            let mut head = [0u8; 8];
+            if !read_or_eof(r, &mut head)? {
+                return Ok(None);
+            }
+            if u32::from_le_bytes(head[0..4].try_into()?) == PCAPNG_SHB {
+                // next section, possibly with another byte order. re-read its length after the magic
+                let mut rest = [0u8; 4];
+                r.read_exact(&mut rest)?;
+                let le_now = match rest {
+                    [0x4d, 0x3c, 0x2b, 0x1a] => true,
+                    [0x1a, 0x2b, 0x3c, 0x4d] => false,
+                    _ => return Err(format_err!("invalid pcapng byte order magic")),
+                };
+                *le = le_now;
+                let len = u32_ordered_at(&head, 4, le_now).unwrap_or(0) as usize;
+                let mut body =
+                    vec![0u8; len.checked_sub(12).context("invalid pcapng block length")?];
+                r.read_exact(&mut body)?;
+                interfaces.clear();
+                continue;
+            }
+            let block_type = u32_ordered_at(&head, 0, *le).unwrap_or(0);
+            let len = u32_ordered_at(&head, 4, *le).unwrap_or(0) as usize;
+            let mut body = vec![0u8; len.checked_sub(8).context("invalid pcapng block length")?];
+            r.read_exact(&mut body).context("truncated pcapng block")?;
+            match block_type {
+                // interface description
+                1 => {
+                    let linktype = u16_ordered_at(&body, 0, *le).unwrap_or(1) as u32;
+                    interfaces.push((linktype, if_tsresol(&body, *le)));
+                }
+                // enhanced packet
+                6 => {
+                    let interface = u32_ordered_at(&body, 0, *le).unwrap_or(0) as usize;
+                    let ts = ((u32_ordered_at(&body, 4, *le).unwrap_or(0) as u64) << 32)
+                        | u32_ordered_at(&body, 8, *le).unwrap_or(0) as u64;
+                    let cap_len = u32_ordered_at(&body, 12, *le).unwrap_or(0) as usize;
+                    let (linktype, unit_ns) =
+                        interfaces.get(interface).copied().unwrap_or((1, 1000));
+                    let data = body
+                        .get(20..20 + cap_len)
+                        .context("truncated enhanced packet block")?;
+                    return Ok(Some(Frame {
+                        time_ns: ts.saturating_mul(unit_ns),
+                        linktype,
+                        data: data.to_vec(),
+                    }));
+                }
+                // simple packet, no timestamp
+                3 => {
+                    let (linktype, _) = interfaces.first().copied().unwrap_or((1, 1000));
+                    let orig_len = u32_ordered_at(&body, 0, *le).unwrap_or(0) as usize;
+                    let end = (4 + orig_len).min(body.len().saturating_sub(4));
+                    return Ok(Some(Frame {
+                        time_ns: 0,
+                        linktype,
+                        data: body.get(4..end).unwrap_or_default().to_vec(),
+                    }));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// nanoseconds per timestamp unit from the `if_tsresol` option of an interface description block
+fn if_tsresol(body: &[u8], le: bool) -> u64 {
+    let mut i = 8;
+    while let (Some(code), Some(len)) =
+        (u16_ordered_at(body, i, le), u16_ordered_at(body, i + 2, le))
+    {
+        if code == 0 {
+            break;
+        }
+        if code == 9 && len == 1 {
+            let v = body.get(i + 4).copied().unwrap_or(6);
+            let exp = (v & 0x7f) as u32;
+            return if v & 0x80 == 0 {
+                // 10^-exp seconds
+                10u64.checked_pow(9u32.saturating_sub(exp)).unwrap_or(1)
+            } else {
+                // 2^-exp seconds
+                (1_000_000_000u64 >> exp.min(63)).max(1)
+            };
+        }
+        i += 4 + (len as usize).div_ceil(4) * 4;
+    }
+    1000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Proto {
+    Tcp,
+    Udp,
+    Icmp,
+    Other(u8),
+}
+
+/// the parts of a packet we print
+#[derive(Debug)]
+struct Packet<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    proto: Proto,
+    src_port: u16,
+    dst_port: u16,
+    tcp_seq: u32,
+    tcp_flags: u8,
+    payload: &'a [u8],
+}
+
+/// strip the link layer, returns (ethertype, network layer)
+fn link_payload(linktype: u32, data: &[u8]) -> Option<(u16, &[u8])> {
+    let (mut ethertype, mut rest) = match linktype {
+        // ethernet
+        1 => (u16_be_at(data, 12)?, data.get(14..)?),
+        // raw ip
+        12 | 101 | 228 | 229 => {
+            let version = data.first()? >> 4;
+            (if version == 6 { 0x86dd } else { 0x0800 }, data)
+        }
+        // BSD loopback: address family in host byte order
+        0 | 108 => {
+            let family = u32_at(data, 0)?.min(u32_be_at(data, 0)?);
+            let ethertype = if matches!(family, 24 | 28 | 30) {
+                0x86dd
+            } else {
+                0x0800
+            };
+            (ethertype, data.get(4..)?)
+        }
+        // linux cooked capture v1 and v2
+        113 => (u16_be_at(data, 14)?, data.get(16..)?),
+        276 => (u16_be_at(data, 0)?, data.get(20..)?),
+        _ => return None,
+    };
+    // 802.1Q / QinQ vlan tags
+    while ethertype == 0x8100 || ethertype == 0x88a8 {
+        ethertype = u16_be_at(rest, 2)?;
+        rest = rest.get(4..)?;
+    }
+    Some((ethertype, rest))
+}
+
+fn parse_packet(linktype: u32, data: &[u8]) -> Option<Packet<'_>> {
+    let (ethertype, ip) = link_payload(linktype, data)?;
+    let (src, dst, proto, transport) = match ethertype {
+        0x0800 => {
+            let ihl = ((ip.first()? & 0x0f) as usize) * 4;
+            let total = (u16_be_at(ip, 2)? as usize).clamp(ihl, ip.len());
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                *ip.get(9)?,
+                ip.get(ihl..total)?,
+            )
+        }
+        0x86dd => {
+            let len = u16_be_at(ip, 4)? as usize;
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                *ip.get(6)?,
+                ip.get(40..(40 + len).min(ip.len()))?,
+            )
+        }
+        _ => return None,
+    };
+    let mut packet = Packet {
+        src,
+        dst,
+        proto: Proto::Other(proto),
+        src_port: 0,
+        dst_port: 0,
+        tcp_seq: 0,
+        tcp_flags: 0,
+        payload: &[],
+    };
+    match proto {
+        6 => {
+            let offset = ((transport.get(12)? >> 4) as usize) * 4;
+            packet.proto = Proto::Tcp;
+            packet.src_port = u16_be_at(transport, 0)?;
+            packet.dst_port = u16_be_at(transport, 2)?;
+            packet.tcp_seq = u32_be_at(transport, 4)?;
+            packet.tcp_flags = *transport.get(13)?;
+            packet.payload = transport.get(offset..).unwrap_or_default();
+        }
+        17 => {
+            packet.proto = Proto::Udp;
+            packet.src_port = u16_be_at(transport, 0)?;
+            packet.dst_port = u16_be_at(transport, 2)?;
+            packet.payload = transport.get(8..).unwrap_or_default();
+        }
+        1 | 58 => {
+            packet.proto = Proto::Icmp;
+            packet.payload = transport.get(8..).unwrap_or_default();
+        }
+        _ => packet.payload = transport,
+    }
+    Some(packet)
+}
+
+/// a (possibly compressed) domain name at `pos` in a dns message. Returns the name and the position after it
+fn dns_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            return Some((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            end.get_or_insert(pos + 2);
+            pos = (u16_be_at(msg, pos)? & 0x3fff) as usize;
+            continue;
+        }
+        labels.push(String::from_utf8_lossy(msg.get(pos + 1..pos + 1 + len)?).into_owned());
+        pos += 1 + len;
+    }
+    None
+}
+
+fn dns_type(t: u16) -> String {
+    match t {
+        1 => "A".to_string(),
+        2 => "NS".to_string(),
+        5 => "CNAME".to_string(),
+        6 => "SOA".to_string(),
+        12 => "PTR".to_string(),
+        15 => "MX".to_string(),
+        16 => "TXT".to_string(),
+        28 => "AAAA".to_string(),
+        33 => "SRV".to_string(),
+        65 => "HTTPS".to_string(),
+        t => format!("TYPE{t}"),
+    }
+}
+
+/// `query name TYPE` and `answer name TYPE value` lines of a dns message
+fn dns_lines(msg: &[u8]) -> Option<Vec<String>> {
+    let questions = u16_be_at(msg, 4)?;
+    let answers =
+        u16_be_at(msg, 6)? as usize + u16_be_at(msg, 8)? as usize + u16_be_at(msg, 10)? as usize;
+    let mut out = vec![];
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (name, next) = dns_name(msg, pos)?;
+        out.push(format!(
+            "dns query {} {}",
+            name,
+            dns_type(u16_be_at(msg, next)?)
+        ));
+        pos = next + 4;
+    }
+    for _ in 0..answers.min(256) {
+        let (name, next) = dns_name(msg, pos)?;
+        let typ = u16_be_at(msg, next)?;
+        let rdlen = u16_be_at(msg, next + 8)? as usize;
+        let rdata_pos = next + 10;
+        let rdata = msg.get(rdata_pos..rdata_pos + rdlen)?;
+        let value = match typ {
+            // OPT pseudo record of EDNS
+            41 => None,
+            1 => <[u8; 4]>::try_from(rdata)
+                .ok()
+                .map(|a| Ipv4Addr::from(a).to_string()),
+            28 => <[u8; 16]>::try_from(rdata)
+                .ok()
+                .map(|a| Ipv6Addr::from(a).to_string()),
+            2 | 5 | 12 => dns_name(msg, rdata_pos).map(|(n, _)| n),
+            15 => dns_name(msg, rdata_pos + 2).map(|(n, _)| n),
+            16 => Some(String::from_utf8_lossy(rdata.get(1..).unwrap_or_default()).into_owned()),
+            _ => Some(String::new()),
+        };
+        if let Some(value) = value {
+            out.push(
+                format!("dns answer {} {} {}", name, dns_type(typ), value)
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+        pos = rdata_pos + rdlen;
+    }
+    Some(out)
+}
+
+fn is_dns_port(p: u16) -> bool {
+    matches!(p, 53 | 5353 | 5355)
+}
+
+/// the text of a payload line, if it is mostly printable
+fn text_line(line: &[u8]) -> Option<String> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let printable = line
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || **b == b' ' || **b == b'\t')
+        .count();
+    if printable < MIN_TEXT_LEN || printable * 10 < line.len() * 9 {
+        return None;
+    }
+    Some(
+        line.iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect(),
+    )
+}
+
+/// one direction of a tcp connection
+#[derive(Default)]
+struct TcpStream {
+    next_seq: Option<u32>,
+    /// bytes of the current, unfinished line
+    line: Vec<u8>,
+    /// frame in which the current line started
+    line_frame: u64,
+}
+
+impl TcpStream {
+    /// append a segment and return the completed lines, with the frame they started in
+    fn push(&mut self, frame: u64, seq: u32, syn: bool, data: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        if syn {
+            self.next_seq = Some(seq.wrapping_add(1));
+        }
+        let next = *self.next_seq.get_or_insert(seq);
+        // drop the part that was already seen (retransmissions)
+        let already = next.wrapping_sub(seq) as i32;
+        let data = if already > 0 {
+            data.get(already as usize..).unwrap_or_default()
+        } else {
+            data
+        };
+        if data.is_empty() {
+            return vec![];
+        }
+        self.next_seq = Some(
+            seq.wrapping_add(already.max(0) as u32)
+                .wrapping_add(data.len() as u32),
+        );
+        let mut out = vec![];
+        for b in data {
+            if self.line.is_empty() {
+                self.line_frame = frame;
+            }
+            if *b == b'\n' || self.line.len() >= MAX_LINE_LEN {
+                out.push((self.line_frame, std::mem::take(&mut self.line)));
+                if *b == b'\n' {
+                    continue;
+                }
+                self.line_frame = frame;
+            }
+            self.line.push(*b);
+        }
+        out
+    }
+}
+
+type FlowKey = (IpAddr, u16, IpAddr, u16);
+
+fn synchronous_dump_pcap(inp: impl Read, line_prefix: &str, mut s: impl Write) -> Result<()> {
+    let mut reader = CaptureReader::new(BufReader::new(inp))?;
+    let mut streams: HashMap<FlowKey, TcpStream> = HashMap::new();
+    let mut frame_no: u64 = 0;
+    let write_text = |s: &mut dyn Write, frame: u64, line: &[u8]| -> Result<()> {
+        if let Some(text) = text_line(line) {
+            writeln!(s, "{line_prefix}frame {frame}: {text}")?;
+        }
+        Ok(())
+    };
+    while let Some(frame) = reader.next_frame()? {
+        frame_no += 1;
+        let time = format!(
+            "{}.{:06}",
+            frame.time_ns / 1_000_000_000,
+            frame.time_ns % 1_000_000_000 / 1000
+        );
+        let Some(p) = parse_packet(frame.linktype, &frame.data) else {
+            writeln!(
+                s,
+                "{line_prefix}frame {frame_no}: t={time} linktype={} len={}",
+                frame.linktype,
+                frame.data.len()
+            )?;
+            continue;
+        };
+        let endpoint = |ip: IpAddr, port: u16| match (ip, port) {
+            (ip, 0) => ip.to_string(),
+            (IpAddr::V6(ip), port) => format!("[{ip}]:{port}"),
+            (ip, port) => format!("{ip}:{port}"),
+        };
+        let proto = match p.proto {
+            Proto::Tcp => "TCP".to_string(),
+            Proto::Udp => "UDP".to_string(),
+            Proto::Icmp => "ICMP".to_string(),
+            Proto::Other(n) => format!("proto={n}"),
+        };
+        let src_port = if p.proto == Proto::Icmp {
+            0
+        } else {
+            p.src_port
+        };
+        let dst_port = if p.proto == Proto::Icmp {
+            0
+        } else {
+            p.dst_port
+        };
+        writeln!(
+            s,
+            "{line_prefix}frame {frame_no}: t={time} {} -> {} {proto} len={}",
+            endpoint(p.src, src_port),
+            endpoint(p.dst, dst_port),
+            p.payload.len()
+        )?;
+        match p.proto {
+            Proto::Udp if is_dns_port(p.src_port) || is_dns_port(p.dst_port) => {
+                for line in dns_lines(p.payload).unwrap_or_default() {
+                    writeln!(s, "{line_prefix}frame {frame_no}: {line}")?;
+                }
+            }
+            Proto::Tcp => {
+                let key = (p.src, p.src_port, p.dst, p.dst_port);
+                let stream = streams.entry(key).or_default();
+                let syn = p.tcp_flags & 0x02 != 0;
+                for (frame, line) in stream.push(frame_no, p.tcp_seq, syn, p.payload) {
+                    write_text(&mut s, frame, &line)?;
+                }
+                // FIN or RST: flush the last unterminated line
+                if p.tcp_flags & 0x05 != 0
+                    && let Some(stream) = streams.remove(&key)
+                {
+                    write_text(&mut s, stream.line_frame, &stream.line)?;
+                }
+            }
+            _ => {
+                for line in p.payload.split(|b| *b == b'\n') {
+                    write_text(&mut s, frame_no, line)?;
+                }
+            }
+        }
+    }
+    let mut rest: Vec<TcpStream> = streams
+        .into_values()
+        .filter(|s| !s.line.is_empty())
+        .collect();
+    rest.sort_by_key(|s| s.line_frame);
+    for stream in rest {
+        write_text(&mut s, stream.line_frame, &stream.line)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for PcapAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let inp_sync = SyncIoBridge::new(inp);
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump_pcap(inp_sync, &line_prefix, oup_sync)
+        })
+        .await?
+        .context("in synchronous pcap task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn ipv4_frame(proto: u8, src_port: u16, dst_port: u16, transport_rest: &[u8]) -> Vec<u8> {
+        let mut transport = vec![];
+        transport.extend(src_port.to_be_bytes());
+        transport.extend(dst_port.to_be_bytes());
+        transport.extend(transport_rest);
+        let mut f = vec![0u8; 12];
+        f.extend([0x08, 0x00]);
+        let total = (20 + transport.len()) as u16;
+        f.extend([
+            0x45,
+            0,
+            total.to_be_bytes()[0],
+            total.to_be_bytes()[1],
+            0,
+            0,
+            0,
+            0,
+            64,
+            proto,
+            0,
+            0,
+        ]);
+        f.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        f.extend(transport);
+        f
+    }
+
+    fn tcp(seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut t = seq.to_be_bytes().to_vec();
+        t.extend([0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        t.extend(payload);
+        ipv4_frame(6, 40000, 80, &t)
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        out.extend([0u8; 8]);
+        out.extend(65535u32.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        for (i, f) in frames.iter().enumerate() {
+            out.extend((1_700_000_000 + i as u32).to_le_bytes());
+            out.extend(500u32.to_le_bytes());
+            out.extend((f.len() as u32).to_le_bytes());
+            out.extend((f.len() as u32).to_le_bytes());
+            out.extend(f);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn http_and_dns() -> Result<()> {
+        // query for example.com A and an answer with a compressed name
+        let mut dns = vec![0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        dns.extend(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        dns.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        let mut udp = vec![0, 0, 0, 0];
+        udp.extend(dns);
+        let frames = vec![
+            ipv4_frame(17, 53, 5000, &udp),
+            tcp(99, 0x02, b""),
+            tcp(100, 0x18, b"GET /secret HTTP/1.1\r\nHo"),
+            // retransmission of the first segment
+            tcp(100, 0x18, b"GET /secret HTTP/1.1\r\nHo"),
+            tcp(124, 0x19, b"st: example.com\r\n\r\n\x00\x01\x02"),
+        ];
+        let adapter: Box<dyn FileAdapter> = Box::<PcapAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("capture.pcap"),
+            Box::pin(std::io::Cursor::new(pcap(&frames))),
+        );
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:frame 1: t=1700000000.000500 10.0.0.1:53 -> 10.0.0.2:5000 UDP len=45
+PREFIX:frame 1: dns query example.com A
+PREFIX:frame 1: dns answer example.com A 93.184.216.34
+PREFIX:frame 2: t=1700000001.000500 10.0.0.1:40000 -> 10.0.0.2:80 TCP len=0
+PREFIX:frame 3: t=1700000002.000500 10.0.0.1:40000 -> 10.0.0.2:80 TCP len=24
+PREFIX:frame 3: GET /secret HTTP/1.1
+PREFIX:frame 4: t=1700000003.000500 10.0.0.1:40000 -> 10.0.0.2:80 TCP len=24
+PREFIX:frame 5: t=1700000004.000500 10.0.0.1:40000 -> 10.0.0.2:80 TCP len=22
+PREFIX:frame 3: Host: example.com
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pcapng_interfaces() -> Result<()> {
+        let frame = ipv4_frame(17, 1000, 2000, b"\0\0\0\0hello world");
+        let mut f = vec![];
+        // section header
+        f.extend(PCAPNG_SHB.to_le_bytes());
+        f.extend(28u32.to_le_bytes());
+        f.extend(0x1A2B3C4Du32.to_le_bytes());
+        f.extend([1, 0, 0, 0]);
+        f.extend(u64::MAX.to_le_bytes());
+        f.extend(28u32.to_le_bytes());
+        // interface with nanosecond resolution
+        f.extend(1u32.to_le_bytes());
+        f.extend(32u32.to_le_bytes());
+        f.extend([1, 0, 0, 0]);
+        f.extend(65535u32.to_le_bytes());
+        f.extend([9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        f.extend(32u32.to_le_bytes());
+        // enhanced packet
+        let padded = frame.len().div_ceil(4) * 4;
+        let len = (32 + padded) as u32;
+        f.extend(6u32.to_le_bytes());
+        f.extend(len.to_le_bytes());
+        f.extend(0u32.to_le_bytes());
+        let ts: u64 = 1_700_000_000_123_456_789;
+        f.extend(((ts >> 32) as u32).to_le_bytes());
+        f.extend((ts as u32).to_le_bytes());
+        f.extend((frame.len() as u32).to_le_bytes());
+        f.extend((frame.len() as u32).to_le_bytes());
+        f.extend(&frame);
+        f.resize(f.len() + padded - frame.len(), 0);
+        f.extend(len.to_le_bytes());
+
+        let mut out = vec![];
+        synchronous_dump_pcap(&f[..], "", &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "frame 1: t=1700000000.123456 10.0.0.1:1000 -> 10.0.0.2:2000 UDP len=11\nframe 1: hello world\n"
+        );
+        Ok(())
+    }
+}
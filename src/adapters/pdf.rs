@@ -0,0 +1,153 @@
+//! A minimal PDF text extractor in Rust (with `--features pdf-fallback`), for systems without
+//! pdftotext. It reads the text of unencrypted PDFs with standard fonts, ToUnicode maps and
+//! compressed object streams, but without pdftotext's layout analysis: lines come in the order
+//! they are drawn, columns and tables are not put back together.
+//!
+//! If pdftotext is not in PATH it takes the place of the poppler adapter (which is then disabled by
+//! default), otherwise it is only used with `--rga-adapters=+pdf`.
+mod content;
+mod objects;
+
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::*;
+use async_trait::async_trait;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["pdf"];
+
+pub struct PdfAdapter {
+    meta: AdapterMeta,
+}
+
+impl PdfAdapter {
+    pub fn new(disabled_by_default: bool) -> Self {
+        Self {
+            meta: AdapterMeta {
+                name: "pdf".to_owned(),
+                version: 1,
+                description:
+                    "Extracts the text of PDF files without pdftotext. Less accurate than poppler"
+                        .to_owned(),
+                capabilities: AdapterCapabilities {
+                    produces_pages: true,
+                    produces_subfiles: true,
+                    ..Default::default()
+                },
+                fast_matchers: EXTENSIONS
+                    .iter()
+                    .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+                    .collect(),
+                slow_matchers: Some(vec![FileMatcher::MimeType("application/pdf".to_owned())]),
+                keep_fast_matchers_if_accurate: true,
+                disabled_by_default,
+            },
+        }
+    }
+}
+
+impl GetMetadata for PdfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &self.meta
+    }
+}
+
+/// whether the poppler adapter can run, then this adapter is not needed
+pub fn pdftotext_installed() -> bool {
+    crate::bundled::find("pdftotext").is_some()
+}
+
+/// the text of the pages, each followed by a form feed like pdftotext's
+fn extract(data: &[u8]) -> Result<String> {
+    let doc = objects::Document::parse(data)?;
+    let mut interpreter = content::Interpreter::new(&doc);
+    let mut text = String::new();
+    for (page, resources) in doc.pages() {
+        text.push_str(&interpreter.page_text(page, resources));
+        text.push('\x0c');
+    }
+    Ok(text)
+}
+
+#[async_trait]
+impl FileAdapter for PdfAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        // cross references point anywhere in the file, it has to be read completely
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        let text = tokio::task::spawn_blocking(move || extract(&data))
+            .await?
+            .with_context(|| format!("reading {}", filepath_hint.display()))?;
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!(
+                "{}.txt.asciipagebreaks",
+                filepath_hint.to_string_lossy()
+            )),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            file_mtime_unix_ms: None,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use tokio::fs::File;
+
+    #[tokio::test]
+    async fn short_pdf() -> Result<()> {
+        let adapter = PdfAdapter::new(false);
+        let filepath = test_data_dir().join("short.pdf");
+        let (a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+        let r = loop_adapt(&adapter, d, a, get_all_adapters(None).0).await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:Page 1: hello world
+PREFIX:Page 1: this is just a test.
+PREFIX:Page 1: 1
+PREFIX:Page 1: 
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn uncompressed() -> Result<()> {
+        let content = b"BT /F1 12 Tf 72 720 Td (Caf\\351 au) Tj ( lait) Tj 0 -14 Td [(se)-20(cond)-400(line)] TJ ET";
+        let pdf = format!(
+            "%PDF-1.4\n1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj\n\
+             2 0 obj << /Type /Pages /Kids [3 0 R] /Count 1 /Resources << /Font << /F1 5 0 R >> >> >> endobj\n\
+             3 0 obj << /Type /Page /Parent 2 0 R /Contents 4 0 R >> endobj\n\
+             4 0 obj << /Length {} >>\nstream\n{}\nendstream endobj\n\
+             5 0 obj << /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >> endobj\n\
+             trailer << /Root 1 0 R >>\n%%EOF\n",
+            content.len(),
+            String::from_utf8_lossy(content)
+        );
+        assert_eq!(extract(pdf.as_bytes())?, "Café au lait\nsecond line\n\x0c");
+        Ok(())
+    }
+}
@@ -0,0 +1,720 @@
+//! The text of a page: runs its content stream, decodes the strings shown with the fonts' ToUnicode
+//! maps or encodings and puts them into lines by their position. There is no layout analysis like
+//! in pdftotext, text comes in the order it is drawn.
+use super::objects::{Dict, Document, Object, Parser};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// form XObjects drawing form XObjects, deeper ones are skipped
+const MAX_FORM_DEPTH: usize = 4;
+
+type Matrix = [f64; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn matrix(operands: &[Object]) -> Option<Matrix> {
+    let numbers: Vec<f64> = operands.iter().filter_map(Object::number).collect();
+    numbers.try_into().ok()
+}
+
+/// where a code of a ToUnicode map goes
+#[derive(Debug)]
+enum Target {
+    /// the first code of a range maps to this, the following ones to the next characters
+    Start(Vec<u16>),
+    Each(Vec<String>),
+}
+
+#[derive(Debug, Default)]
+pub struct CMap {
+    code_len: Option<usize>,
+    chars: HashMap<u32, String>,
+    ranges: Vec<(u32, u32, Target)>,
+}
+
+fn code(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |c, &b| c << 8 | b as u32)
+}
+
+fn utf16(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks(2)
+        .map(|pair| (pair[0] as u16) << 8 | *pair.get(1).unwrap_or(&0) as u16)
+        .collect()
+}
+
+impl CMap {
+    pub fn parse(data: &[u8]) -> Self {
+        let mut cmap = Self::default();
+        let mut parser = Parser::new(data);
+        let mut operands = vec![];
+        let mut in_section = false;
+        while let Some(object) = parser.next() {
+            let Object::Keyword(keyword) = object else {
+                operands.push(object);
+                continue;
+            };
+            match keyword.as_slice() {
+                b"begincodespacerange" | b"beginbfchar" | b"beginbfrange" => in_section = true,
+                b"endcodespacerange" => {
+                    if let Some(Object::String(low)) = operands.first() {
+                        cmap.code_len = Some(low.len().clamp(1, 4));
+                    }
+                    in_section = false;
+                }
+                b"endbfchar" => {
+                    for pair in operands.chunks(2) {
+                        if let [Object::String(source), Object::String(target)] = pair {
+                            cmap.chars
+                                .insert(code(source), String::from_utf16_lossy(&utf16(target)));
+                        }
+                    }
+                    in_section = false;
+                }
+                b"endbfrange" => {
+                    for triple in operands.chunks(3) {
+                        let [Object::String(low), Object::String(high), target] = triple else {
+                            continue;
+                        };
+                        let target = match target {
+                            Object::String(s) => Target::Start(utf16(s)),
+                            Object::Array(strings) => Target::Each(
+                                strings
+                                    .iter()
+                                    .map(|s| match s {
+                                        Object::String(s) => String::from_utf16_lossy(&utf16(s)),
+                                        _ => String::new(),
+                                    })
+                                    .collect(),
+                            ),
+                            _ => continue,
+                        };
+                        cmap.ranges.push((code(low), code(high), target));
+                    }
+                    in_section = false;
+                }
+                // operators of the PostScript around the map
+                _ if in_section => continue,
+                _ => {}
+            }
+            operands.clear();
+        }
+        cmap
+    }
+
+    pub fn get(&self, c: u32) -> Option<String> {
+        if let Some(s) = self.chars.get(&c) {
+            return Some(s.clone());
+        }
+        self.ranges.iter().find_map(|(low, high, target)| {
+            if c < *low || c > *high {
+                return None;
+            }
+            let offset = c - low;
+            match target {
+                Target::Start(units) => {
+                    let mut units = units.clone();
+                    let last = units.last_mut()?;
+                    *last = last.wrapping_add(offset as u16);
+                    Some(String::from_utf16_lossy(&units))
+                }
+                Target::Each(strings) => strings.get(offset as usize).cloned(),
+            }
+        })
+    }
+}
+
+/// windows-1252 in 0x80..0xa0, where it differs from latin1
+static WIN_ANSI_HIGH: [char; 32] = [
+    '€', '\u{fffd}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{fffd}', 'Ž',
+    '\u{fffd}', '\u{fffd}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{fffd}',
+    'ž', 'Ÿ',
+];
+
+/// the text of a glyph name of an /Encoding's /Differences. Only the common ones
+fn glyph_text(name: &[u8]) -> Option<String> {
+    let name = std::str::from_utf8(name).ok()?;
+    // `a.sc`, `one.oldstyle`
+    let name = name.split('.').next()?;
+    if name.len() == 1 {
+        return Some(name.to_string());
+    }
+    if let Some(hex) = name.strip_prefix("uni").or_else(|| name.strip_prefix('u'))
+        && hex.len() >= 4
+        && let std::result::Result::Ok(units) = (0..hex.len() / 4)
+            .map(|i| u16::from_str_radix(&hex[i * 4..i * 4 + 4], 16))
+            .collect::<std::result::Result<Vec<u16>, _>>()
+    {
+        return Some(String::from_utf16_lossy(&units));
+    }
+    let text = match name {
+        "space" | "nbspace" => " ",
+        "exclam" => "!",
+        "quotedbl" => "\"",
+        "numbersign" => "#",
+        "dollar" => "$",
+        "percent" => "%",
+        "ampersand" => "&",
+        "quoteright" | "quotesingle" => "'",
+        "parenleft" => "(",
+        "parenright" => ")",
+        "asterisk" => "*",
+        "plus" => "+",
+        "comma" => ",",
+        "hyphen" | "minus" | "sfthyphen" => "-",
+        "period" => ".",
+        "slash" => "/",
+        "zero" => "0",
+        "one" => "1",
+        "two" => "2",
+        "three" => "3",
+        "four" => "4",
+        "five" => "5",
+        "six" => "6",
+        "seven" => "7",
+        "eight" => "8",
+        "nine" => "9",
+        "colon" => ":",
+        "semicolon" => ";",
+        "less" => "<",
+        "equal" => "=",
+        "greater" => ">",
+        "question" => "?",
+        "at" => "@",
+        "bracketleft" => "[",
+        "backslash" => "\\",
+        "bracketright" => "]",
+        "asciicircum" => "^",
+        "underscore" => "_",
+        "quoteleft" | "grave" => "`",
+        "braceleft" => "{",
+        "bar" => "|",
+        "braceright" => "}",
+        "asciitilde" => "~",
+        "ff" => "ff",
+        "fi" => "fi",
+        "fl" => "fl",
+        "ffi" => "ffi",
+        "ffl" => "ffl",
+        "endash" => "–",
+        "emdash" => "—",
+        "quotedblleft" => "“",
+        "quotedblright" => "”",
+        "quotesinglbase" => "‚",
+        "quotedblbase" => "„",
+        "bullet" => "•",
+        "ellipsis" => "…",
+        "dagger" => "†",
+        "daggerdbl" => "‡",
+        "section" => "§",
+        "paragraph" => "¶",
+        "copyright" => "©",
+        "registered" => "®",
+        "trademark" => "™",
+        "degree" => "°",
+        "germandbls" => "ß",
+        "dotlessi" => "ı",
+        "ae" => "æ",
+        "AE" => "Æ",
+        "oe" => "œ",
+        "OE" => "Œ",
+        "oslash" => "ø",
+        "Oslash" => "Ø",
+        "Euro" => "€",
+        _ => return accented(name),
+    };
+    Some(text.to_string())
+}
+
+/// `eacute`, `Udieresis`: the letter followed by the combining accent
+fn accented(name: &str) -> Option<String> {
+    let mut chars = name.chars();
+    let letter = chars.next().filter(char::is_ascii_alphabetic)?;
+    let accent = match chars.as_str() {
+        "grave" => '\u{300}',
+        "acute" => '\u{301}',
+        "circumflex" => '\u{302}',
+        "tilde" => '\u{303}',
+        "macron" => '\u{304}',
+        "breve" => '\u{306}',
+        "dotaccent" => '\u{307}',
+        "dieresis" => '\u{308}',
+        "ring" => '\u{30a}',
+        "hungarumlaut" => '\u{30b}',
+        "caron" => '\u{30c}',
+        "cedilla" => '\u{327}',
+        "ogonek" => '\u{328}',
+        _ => return None,
+    };
+    Some([letter, accent].iter().collect())
+}
+
+/// how the codes of strings shown with a font become text, and how wide their glyphs are
+#[derive(Debug)]
+pub struct Font {
+    code_len: usize,
+    to_unicode: Option<CMap>,
+    /// the /Differences of a simple font
+    differences: HashMap<u32, String>,
+    /// in thousandths of the font size
+    widths: HashMap<u32, f64>,
+    default_width: f64,
+}
+
+impl Font {
+    pub fn load(doc: &Document, dict: &Dict) -> Self {
+        let composite = doc.get(dict, b"Subtype").name() == Some(b"Type0".as_slice());
+        let to_unicode = match doc.get(dict, b"ToUnicode") {
+            Object::Stream(d, data) => doc.decode(d, data).map(|data| CMap::parse(&data)),
+            _ => None,
+        };
+        let mut differences = HashMap::new();
+        if let Some(encoding) = doc.get(dict, b"Encoding").dict()
+            && let Some(entries) = doc.get(encoding, b"Differences").array()
+        {
+            let mut next = 0;
+            for entry in entries {
+                match doc.resolve(entry) {
+                    Object::Number(n) => next = *n as u32,
+                    Object::Name(name) => {
+                        if let Some(text) = glyph_text(name) {
+                            differences.insert(next, text);
+                        }
+                        next += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut widths = HashMap::new();
+        let default_width;
+        if composite {
+            let descendant = doc
+                .get(dict, b"DescendantFonts")
+                .array()
+                .and_then(|a| a.first())
+                .and_then(|d| doc.resolve(d).dict());
+            default_width = descendant
+                .and_then(|d| doc.get(d, b"DW").number())
+                .unwrap_or(1000.0);
+            let w = descendant
+                .and_then(|d| doc.get(d, b"W").array())
+                .unwrap_or(&[]);
+            // `c [w1 w2 ...]` or `c_first c_last w`
+            let mut i = 0;
+            while i + 1 < w.len() {
+                let first = doc.resolve(&w[i]).number().unwrap_or(0.0) as u32;
+                match doc.resolve(&w[i + 1]) {
+                    Object::Array(list) => {
+                        for (offset, width) in list.iter().enumerate() {
+                            if let Some(width) = doc.resolve(width).number() {
+                                widths.insert(first + offset as u32, width);
+                            }
+                        }
+                        i += 2;
+                    }
+                    Object::Number(last) => {
+                        let width = w.get(i + 2).and_then(|x| doc.resolve(x).number());
+                        if let Some(width) = width {
+                            for c in first..=(*last as u32).min(first + 0xffff) {
+                                widths.insert(c, width);
+                            }
+                        }
+                        i += 3;
+                    }
+                    _ => break,
+                }
+            }
+        } else {
+            let first = doc.get(dict, b"FirstChar").number().unwrap_or(0.0) as u32;
+            for (offset, width) in doc
+                .get(dict, b"Widths")
+                .array()
+                .unwrap_or(&[])
+                .iter()
+                .enumerate()
+            {
+                if let Some(width) = doc.resolve(width).number() {
+                    widths.insert(first + offset as u32, width);
+                }
+            }
+            // without widths, about the average width of latin glyphs
+            default_width = 500.0;
+        }
+        let code_len = to_unicode
+            .as_ref()
+            .and_then(|c| c.code_len)
+            .unwrap_or(if composite { 2 } else { 1 });
+        Self {
+            code_len,
+            to_unicode,
+            differences,
+            widths,
+            default_width,
+        }
+    }
+
+    /// the glyphs of a shown string: their text, width and whether they are the single-byte space
+    /// that word spacing applies to
+    fn glyphs(&self, bytes: &[u8]) -> Vec<(String, f64, bool)> {
+        bytes
+            .chunks(self.code_len)
+            .map(|chunk| {
+                let c = code(chunk);
+                let text = self
+                    .to_unicode
+                    .as_ref()
+                    .and_then(|m| m.get(c))
+                    .or_else(|| self.differences.get(&c).cloned())
+                    .unwrap_or_else(|| {
+                        if self.code_len > 1 {
+                            // Identity-H without ToUnicode: the codes are glyph ids, there is no text
+                            String::new()
+                        } else if (0x80..0xa0).contains(&c) {
+                            WIN_ANSI_HIGH[c as usize - 0x80].to_string()
+                        } else {
+                            char::from(c as u8).to_string()
+                        }
+                    });
+                let width = self.widths.get(&c).copied().unwrap_or(self.default_width);
+                (text, width, self.code_len == 1 && c == 32)
+            })
+            .collect()
+    }
+}
+
+/// text with its lines from the positions of the strings
+#[derive(Default)]
+struct Lines {
+    text: String,
+    /// baseline, where the last string ended, its font size. In device space
+    last: Option<(f64, f64, f64)>,
+}
+
+impl Lines {
+    fn push(&mut self, x: f64, y: f64, end_x: f64, size: f64, text: &str) {
+        // some ToUnicode maps give the gaps between words as tabs
+        let text: String = expand_ligatures(text)
+            .chars()
+            .filter_map(|c| match c {
+                '\t' => Some(' '),
+                c if c.is_control() => None,
+                c => Some(c),
+            })
+            .collect();
+        if let Some((last_y, last_end, last_size)) = self.last {
+            let size = size.max(last_size).max(1.0);
+            if (y - last_y).abs() > size * 0.5 {
+                if !self.text.ends_with('\n') {
+                    self.text.push('\n');
+                }
+            } else if x - last_end > size * 0.15 && !self.text.ends_with([' ', '\n']) {
+                self.text.push(' ');
+            }
+        }
+        self.text.push_str(&text);
+        self.last = Some((y, end_x, size));
+    }
+}
+
+/// ligatures are written out so searches for "find" match "ﬁnd"
+fn expand_ligatures(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\u{fb00}' => out.push_str("ff"),
+            '\u{fb01}' => out.push_str("fi"),
+            '\u{fb02}' => out.push_str("fl"),
+            '\u{fb03}' => out.push_str("ffi"),
+            '\u{fb04}' => out.push_str("ffl"),
+            '\u{fb05}' | '\u{fb06}' => out.push_str("st"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+struct TextState {
+    font: Option<Rc<Font>>,
+    size: f64,
+    char_spacing: f64,
+    word_spacing: f64,
+    horizontal_scale: f64,
+    leading: f64,
+    matrix: Matrix,
+    line_matrix: Matrix,
+}
+
+impl Default for TextState {
+    fn default() -> Self {
+        Self {
+            font: None,
+            size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scale: 1.0,
+            leading: 0.0,
+            matrix: IDENTITY,
+            line_matrix: IDENTITY,
+        }
+    }
+}
+
+pub struct Interpreter<'a> {
+    doc: &'a Document,
+    /// by the object number of the font dictionary
+    fonts: HashMap<u32, Rc<Font>>,
+    lines: Lines,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(doc: &'a Document) -> Self {
+        Self {
+            doc,
+            fonts: HashMap::new(),
+            lines: Lines::default(),
+        }
+    }
+
+    /// the text of a page, its lines ending with a newline
+    pub fn page_text(&mut self, page: &Dict, resources: Option<&Dict>) -> String {
+        let mut content = vec![];
+        let streams = match page
+            .get(b"Contents".as_slice())
+            .map(|c| self.doc.resolve(c))
+        {
+            Some(Object::Array(parts)) => parts.iter().map(|p| self.doc.resolve(p)).collect(),
+            Some(stream) => vec![stream],
+            None => vec![],
+        };
+        for stream in streams {
+            if let Object::Stream(dict, data) = stream
+                && let Some(data) = self.doc.decode(dict, data)
+            {
+                content.extend(data);
+                content.push(b'\n');
+            }
+        }
+        self.lines = Lines::default();
+        self.run(&content, resources, IDENTITY, 0);
+        let mut text = std::mem::take(&mut self.lines.text);
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
+    fn font(&mut self, resources: Option<&Dict>, name: &[u8]) -> Option<Rc<Font>> {
+        let fonts = self.doc.get(resources?, b"Font").dict()?;
+        let entry = fonts.get(name)?;
+        if let Object::Ref(id) = entry
+            && let Some(font) = self.fonts.get(id)
+        {
+            return Some(font.clone());
+        }
+        let font = Rc::new(Font::load(self.doc, self.doc.resolve(entry).dict()?));
+        if let Object::Ref(id) = entry {
+            self.fonts.insert(*id, font.clone());
+        }
+        Some(font)
+    }
+
+    fn show(&mut self, state: &mut TextState, ctm: &Matrix, bytes: &[u8]) {
+        let Some(font) = state.font.clone() else {
+            return;
+        };
+        let device = multiply(&state.matrix, ctm);
+        let (x, y) = (device[4], device[5]);
+        let size = state.size * device[2].hypot(device[3]);
+        let mut text = String::new();
+        for (glyph, width, is_space) in font.glyphs(bytes) {
+            let advance = (width / 1000.0 * state.size
+                + state.char_spacing
+                + if is_space { state.word_spacing } else { 0.0 })
+                * state.horizontal_scale;
+            translate(&mut state.matrix, advance, 0.0);
+            text.push_str(&glyph);
+        }
+        let end = multiply(&state.matrix, ctm);
+        self.lines.push(x, y, end[4], size, &text);
+    }
+
+    fn run(&mut self, content: &[u8], resources: Option<&Dict>, ctm: Matrix, depth: usize) {
+        let mut parser = Parser::new(content);
+        let mut operands: Vec<Object> = vec![];
+        let mut state = TextState::default();
+        let mut ctm = ctm;
+        let mut saved = vec![];
+        while let Some(object) = parser.next() {
+            let Object::Keyword(operator) = object else {
+                operands.push(object);
+                continue;
+            };
+            let number = |i: usize| operands.get(i).and_then(Object::number).unwrap_or(0.0);
+            match operator.as_slice() {
+                b"q" => saved.push(ctm),
+                b"Q" => ctm = saved.pop().unwrap_or(ctm),
+                b"cm" => {
+                    if let Some(m) = matrix(&operands) {
+                        ctm = multiply(&m, &ctm);
+                    }
+                }
+                b"BT" => {
+                    state.matrix = IDENTITY;
+                    state.line_matrix = IDENTITY;
+                }
+                b"Tf" => {
+                    state.font = operands
+                        .first()
+                        .and_then(Object::name)
+                        .and_then(|name| self.font(resources, name));
+                    state.size = number(1);
+                }
+                b"Tc" => state.char_spacing = number(0),
+                b"Tw" => state.word_spacing = number(0),
+                b"Tz" => state.horizontal_scale = number(0) / 100.0,
+                b"TL" => state.leading = number(0),
+                b"Td" | b"TD" => {
+                    if operator == b"TD" {
+                        state.leading = -number(1);
+                    }
+                    translate(&mut state.line_matrix, number(0), number(1));
+                    state.matrix = state.line_matrix;
+                }
+                b"Tm" => {
+                    if let Some(m) = matrix(&operands) {
+                        state.line_matrix = m;
+                        state.matrix = m;
+                    }
+                }
+                b"T*" => next_line(&mut state),
+                b"Tj" | b"'" | b"\"" => {
+                    if operator == b"\"" {
+                        state.word_spacing = number(0);
+                        state.char_spacing = number(1);
+                    }
+                    if operator != b"Tj" {
+                        next_line(&mut state);
+                    }
+                    if let Some(Object::String(s)) = operands.last() {
+                        let s = s.clone();
+                        self.show(&mut state, &ctm, &s);
+                    }
+                }
+                b"TJ" => {
+                    if let Some(Object::Array(items)) = operands.last() {
+                        for item in items.clone() {
+                            match item {
+                                Object::String(s) => self.show(&mut state, &ctm, &s),
+                                Object::Number(n) => translate(
+                                    &mut state.matrix,
+                                    -n / 1000.0 * state.size * state.horizontal_scale,
+                                    0.0,
+                                ),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                b"Do" if depth < MAX_FORM_DEPTH => {
+                    let form = operands.first().and_then(Object::name).and_then(|name| {
+                        let xobjects = self.doc.get(resources?, b"XObject").dict()?;
+                        match self.doc.resolve(xobjects.get(name)?) {
+                            Object::Stream(dict, data)
+                                if self.doc.get(dict, b"Subtype").name()
+                                    == Some(b"Form".as_slice()) =>
+                            {
+                                Some((dict, data))
+                            }
+                            _ => None,
+                        }
+                    });
+                    if let Some((dict, data)) = form
+                        && let Some(data) = self.doc.decode(dict, data)
+                    {
+                        let form_matrix = self.doc.get(dict, b"Matrix").array().and_then(matrix);
+                        let form_ctm = multiply(&form_matrix.unwrap_or(IDENTITY), &ctm);
+                        let form_resources = self.doc.get(dict, b"Resources").dict().or(resources);
+                        self.run(&data, form_resources, form_ctm, depth + 1);
+                    }
+                }
+                b"BI" => skip_inline_image(&mut parser),
+                _ => {}
+            }
+            operands.clear();
+        }
+    }
+}
+
+fn translate(m: &mut Matrix, tx: f64, ty: f64) {
+    *m = multiply(&[1.0, 0.0, 0.0, 1.0, tx, ty], m);
+}
+
+fn next_line(state: &mut TextState) {
+    translate(&mut state.line_matrix, 0.0, -state.leading);
+    state.matrix = state.line_matrix;
+}
+
+/// the data of an inline image (`BI <dict> ID <data> EI`) is not made of tokens
+fn skip_inline_image(parser: &mut Parser) {
+    let data = parser.data;
+    let is_space = |b: Option<&u8>| b.is_none_or(|b| b.is_ascii_whitespace());
+    let mut i = parser.pos;
+    while i + 2 <= data.len() {
+        if &data[i..i + 2] == b"ID"
+            && is_space(data.get(i + 2))
+            && (i == 0 || is_space(data.get(i - 1)))
+        {
+            break;
+        }
+        i += 1;
+    }
+    i += 3;
+    while i + 2 <= data.len() {
+        if &data[i..i + 2] == b"EI"
+            && is_space(data.get(i + 2))
+            && is_space(data.get(i.wrapping_sub(1)))
+        {
+            parser.pos = i + 2;
+            return;
+        }
+        i += 1;
+    }
+    parser.pos = data.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn to_unicode() {
+        let cmap = CMap::parse(
+            b"/CIDInit /ProcSet findresource begin 12 dict begin begincmap\n\
+              1 begincodespacerange <0000> <FFFF> endcodespacerange\n\
+              2 beginbfchar <0003> <0020> <0011> <00660069> endbfchar\n\
+              2 beginbfrange <0024> <0026> <0041> <0030> <0031> [<00E9> <00FC>] endbfrange\n\
+              endcmap CMapName currentdict /CMap defineresource pop end end",
+        );
+        assert_eq!(cmap.code_len, Some(2));
+        assert_eq!(cmap.get(0x03).as_deref(), Some(" "));
+        assert_eq!(cmap.get(0x11).as_deref(), Some("fi"));
+        assert_eq!(cmap.get(0x25).as_deref(), Some("B"));
+        assert_eq!(cmap.get(0x31).as_deref(), Some("ü"));
+        assert_eq!(cmap.get(0x27), None);
+        assert_eq!(glyph_text(b"eacute").as_deref(), Some("e\u{301}"));
+        assert_eq!(glyph_text(b"uni00410042").as_deref(), Some("AB"));
+    }
+}
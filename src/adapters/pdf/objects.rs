@@ -0,0 +1,654 @@
+//! The objects of a PDF file. They are found by scanning the file for `N G obj` instead of reading
+//! the cross-reference table, so files with a broken or missing table can still be read. Later
+//! definitions of an object (incremental updates) replace earlier ones, objects in object streams
+//! are only used if they are not defined directly.
+use anyhow::*;
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+pub type Dict = HashMap<Vec<u8>, Object>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Name(Vec<u8>),
+    Array(Vec<Object>),
+    Dict(Dict),
+    Ref(u32),
+    /// the dictionary and the data as it is in the file, see [`Document::decode`]
+    Stream(Dict, Vec<u8>),
+    /// an operator of a content stream or another bare word
+    Keyword(Vec<u8>),
+}
+
+static NULL: Object = Object::Null;
+
+impl Object {
+    pub fn dict(&self) -> Option<&Dict> {
+        match self {
+            Object::Dict(d) | Object::Stream(d, _) => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Object::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&[u8]> {
+        match self {
+            Object::Name(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn array(&self) -> Option<&[Object]> {
+        match self {
+            Object::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, 0 | b'\t' | b'\n' | 0x0c | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    matches!(
+        b,
+        b'(' | b')' | b'<' | b'>' | b'[' | b']' | b'{' | b'}' | b'/' | b'%'
+    )
+}
+
+fn is_regular(b: u8) -> bool {
+    !is_whitespace(b) && !is_delimiter(b)
+}
+
+/// reads objects and operators one after another
+pub struct Parser<'a> {
+    pub data: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn skip_whitespace(&mut self) {
+        while let Some(&b) = self.data.get(self.pos) {
+            if is_whitespace(b) {
+                self.pos += 1;
+            } else if b == b'%' {
+                while self
+                    .data
+                    .get(self.pos)
+                    .is_some_and(|&b| b != b'\r' && b != b'\n')
+                {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn regular_run(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(is_regular) {
+            self.pos += 1;
+        }
+        &self.data[start..self.pos]
+    }
+
+    /// the next object or operator, None at the end. Stray delimiters are returned as keywords
+    pub fn next(&mut self) -> Option<Object> {
+        self.skip_whitespace();
+        let b = self.peek()?;
+        Some(match b {
+            b'/' => {
+                self.pos += 1;
+                Object::Name(decode_name(self.regular_run()))
+            }
+            b'(' => Object::String(self.literal_string()),
+            b'<' if self.data.get(self.pos + 1) == Some(&b'<') => {
+                self.pos += 2;
+                Object::Dict(self.dict_entries())
+            }
+            b'<' => Object::String(self.hex_string()),
+            b'[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        None => break,
+                        Some(b']') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        _ => items.extend(self.next()),
+                    }
+                }
+                Object::Array(items)
+            }
+            b'+' | b'-' | b'.' | b'0'..=b'9' => self.number_or_ref(),
+            _ if is_delimiter(b) => {
+                self.pos += 1;
+                Object::Keyword(vec![b])
+            }
+            _ => match self.regular_run() {
+                b"true" => Object::Bool(true),
+                b"false" => Object::Bool(false),
+                b"null" => Object::Null,
+                word => Object::Keyword(word.to_vec()),
+            },
+        })
+    }
+
+    fn dict_entries(&mut self) -> Dict {
+        let mut dict = Dict::new();
+        loop {
+            self.skip_whitespace();
+            if self.data[self.pos..].starts_with(b">>") {
+                self.pos += 2;
+                break;
+            }
+            match self.next() {
+                None => break,
+                Some(Object::Name(key)) => {
+                    if let Some(value) = self.next() {
+                        dict.insert(key, value);
+                    }
+                }
+                // a stray `>` or junk, dropped
+                Some(_) => {}
+            }
+        }
+        dict
+    }
+
+    fn number_or_ref(&mut self) -> Object {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|b| matches!(b, b'+' | b'-' | b'.' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.data[start..self.pos]).unwrap_or("0");
+        let number = text.parse::<f64>().unwrap_or(0.0);
+        if text.bytes().all(|b| b.is_ascii_digit()) {
+            // `12 0 R`
+            let after_number = self.pos;
+            self.skip_whitespace();
+            let generation_start = self.pos;
+            while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+                self.pos += 1;
+            }
+            if self.pos > generation_start {
+                self.skip_whitespace();
+                if self.peek() == Some(b'R')
+                    && !self.data.get(self.pos + 1).is_some_and(|&b| is_regular(b))
+                {
+                    self.pos += 1;
+                    return Object::Ref(number as u32);
+                }
+            }
+            self.pos = after_number;
+        }
+        Object::Number(number)
+    }
+
+    fn literal_string(&mut self) -> Vec<u8> {
+        self.pos += 1;
+        let mut out = vec![];
+        let mut depth = 1;
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'(' => {
+                    depth += 1;
+                    out.push(b);
+                }
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    out.push(b);
+                }
+                b'\\' => {
+                    let Some(escaped) = self.peek() else { break };
+                    self.pos += 1;
+                    match escaped {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'0'..=b'7' => {
+                            let mut value = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(d @ b'0'..=b'7') => {
+                                        value = value * 8 + (d - b'0') as u32;
+                                        self.pos += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            out.push(value as u8);
+                        }
+                        // a line continuation
+                        b'\r' => {
+                            if self.peek() == Some(b'\n') {
+                                self.pos += 1;
+                            }
+                        }
+                        b'\n' => {}
+                        other => out.push(other),
+                    }
+                }
+                b'\r' => {
+                    if self.peek() == Some(b'\n') {
+                        self.pos += 1;
+                    }
+                    out.push(b'\n');
+                }
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+
+    fn hex_string(&mut self) -> Vec<u8> {
+        self.pos += 1;
+        let mut digits = vec![];
+        while let Some(b) = self.peek() {
+            self.pos += 1;
+            match b {
+                b'>' => break,
+                _ if b.is_ascii_hexdigit() => digits.push(hex_value(b)),
+                _ => {}
+            }
+        }
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect()
+    }
+}
+
+fn hex_value(b: u8) -> u8 {
+    match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10,
+    }
+}
+
+/// `#20` in names is a space
+fn decode_name(raw: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'#'
+            && i + 2 < raw.len() + 1
+            && raw.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && raw.get(i + 2).is_some_and(u8::is_ascii_hexdigit)
+        {
+            out.push(hex_value(raw[i + 1]) << 4 | hex_value(raw[i + 2]));
+            i += 3;
+        } else {
+            out.push(raw[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+lazy_static! {
+    static ref OBJECT_START: Regex =
+        Regex::new(r"(\d{1,10})[\x00\t\n\x0c\r ]+\d{1,5}[\x00\t\n\x0c\r ]+obj\b").unwrap();
+    static ref TRAILER: Regex = Regex::new(r"trailer[\x00\t\n\x0c\r ]*<<").unwrap();
+}
+
+pub struct Document {
+    objects: HashMap<u32, Object>,
+    /// the dictionaries of the trailers and cross-reference streams, the last one first
+    trailers: Vec<Dict>,
+}
+
+impl Document {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(b"%PDF") && find(&data[..data.len().min(1024)], b"%PDF", 0).is_none() {
+            bail!("not a PDF file");
+        }
+        let mut objects = HashMap::new();
+        let mut trailers = vec![];
+        let mut pos = 0;
+        while let Some(m) = OBJECT_START.captures_at(data, pos) {
+            let whole = m.get(0).expect("group 0");
+            let id: u32 = std::str::from_utf8(&m[1])?.parse().unwrap_or(0);
+            let mut parser = Parser {
+                data,
+                pos: whole.end(),
+            };
+            let Some(object) = parser.next() else { break };
+            parser.skip_whitespace();
+            let object = match object {
+                Object::Dict(dict) if data[parser.pos..].starts_with(b"stream") => {
+                    let (stream, end) = stream_data(data, parser.pos + b"stream".len(), &dict);
+                    parser.pos = end;
+                    Object::Stream(dict, stream)
+                }
+                object => object,
+            };
+            if object.dict().and_then(|d| d.get(b"Type".as_slice()))
+                == Some(&Object::Name(b"XRef".to_vec()))
+            {
+                trailers.push(object.dict().expect("checked").clone());
+            }
+            objects.insert(id, object);
+            pos = parser.pos.max(whole.end());
+        }
+        for m in TRAILER.find_iter(data) {
+            let mut parser = Parser {
+                data,
+                pos: m.end() - 2,
+            };
+            if let Some(Object::Dict(dict)) = parser.next() {
+                trailers.push(dict);
+            }
+        }
+        trailers.reverse();
+        if trailers
+            .iter()
+            .any(|t| t.contains_key(b"Encrypt".as_slice()))
+        {
+            bail!("encrypted PDFs are not supported without pdftotext");
+        }
+        let mut document = Self { objects, trailers };
+        document.read_object_streams();
+        Ok(document)
+    }
+
+    fn read_object_streams(&mut self) {
+        let streams: Vec<(Dict, Vec<u8>)> = self
+            .objects
+            .values()
+            .filter_map(|o| match o {
+                Object::Stream(dict, data)
+                    if dict.get(b"Type".as_slice()) == Some(&Object::Name(b"ObjStm".to_vec())) =>
+                {
+                    Some((dict.clone(), data.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        for (dict, data) in streams {
+            let Some(data) = self.decode(&dict, &data) else {
+                continue;
+            };
+            let count = self.number(&dict, b"N").unwrap_or(0.0) as usize;
+            let first = self.number(&dict, b"First").unwrap_or(0.0) as usize;
+            let mut header = Parser::new(&data);
+            let mut offsets = vec![];
+            for _ in 0..count {
+                match (header.next(), header.next()) {
+                    (Some(Object::Number(id)), Some(Object::Number(offset))) => {
+                        offsets.push((id as u32, offset as usize));
+                    }
+                    _ => break,
+                }
+            }
+            for (id, offset) in offsets {
+                let mut parser = Parser {
+                    data: &data,
+                    pos: first + offset,
+                };
+                if parser.pos < data.len()
+                    && let Some(object) = parser.next()
+                {
+                    self.objects.entry(id).or_insert(object);
+                }
+            }
+        }
+    }
+
+    /// `object`, or the object it refers to
+    pub fn resolve<'a>(&'a self, object: &'a Object) -> &'a Object {
+        let mut object = object;
+        // references to references are allowed, loops are not
+        for _ in 0..32 {
+            match object {
+                Object::Ref(id) => object = self.objects.get(id).unwrap_or(&NULL),
+                _ => return object,
+            }
+        }
+        &NULL
+    }
+
+    /// the value of `key` in `dict`, resolved
+    pub fn get<'a>(&'a self, dict: &'a Dict, key: &[u8]) -> &'a Object {
+        dict.get(key).map_or(&NULL, |o| self.resolve(o))
+    }
+
+    fn number(&self, dict: &Dict, key: &[u8]) -> Option<f64> {
+        self.get(dict, key).number()
+    }
+
+    /// the data of a stream with its filters undone, None for filters that are not supported (images)
+    pub fn decode(&self, dict: &Dict, data: &[u8]) -> Option<Vec<u8>> {
+        let filters = match self.get(dict, b"Filter") {
+            Object::Name(name) => vec![name.clone()],
+            Object::Array(names) => names
+                .iter()
+                .filter_map(|n| self.resolve(n).name().map(<[u8]>::to_vec))
+                .collect(),
+            _ => vec![],
+        };
+        let mut data = data.to_vec();
+        for filter in filters {
+            data = match filter.as_slice() {
+                b"FlateDecode" | b"Fl" => {
+                    let mut out = vec![];
+                    // the text of a damaged stream up to the damage is still worth having
+                    let result = flate2::read::ZlibDecoder::new(&data[..]).read_to_end(&mut out);
+                    if result.is_err() && out.is_empty() {
+                        return None;
+                    }
+                    out
+                }
+                b"ASCIIHexDecode" | b"AHx" => {
+                    Parser::new(&[b"<".as_slice(), &data].concat()).hex_string()
+                }
+                b"ASCII85Decode" | b"A85" => ascii85(&data),
+                _ => return None,
+            };
+        }
+        Some(data)
+    }
+
+    /// the page dictionaries in order, with the resources they use
+    pub fn pages(&self) -> Vec<(&Dict, Option<&Dict>)> {
+        let mut pages = vec![];
+        let root = self
+            .trailers
+            .iter()
+            .find_map(|t| t.get(b"Root".as_slice()))
+            .and_then(|root| self.resolve(root).dict());
+        if let Some(root) = root {
+            let mut seen = HashSet::new();
+            self.walk_pages(
+                root.get(b"Pages".as_slice()).unwrap_or(&NULL),
+                None,
+                &mut pages,
+                &mut seen,
+            );
+        }
+        if pages.is_empty() {
+            // no usable page tree, the pages in the order of their numbers
+            let mut ids: Vec<&u32> = self.objects.keys().collect();
+            ids.sort();
+            for id in ids {
+                if let Some(dict) = self.objects[id].dict()
+                    && dict.get(b"Type".as_slice()) == Some(&Object::Name(b"Page".to_vec()))
+                {
+                    let resources = self.get(dict, b"Resources").dict();
+                    pages.push((dict, resources));
+                }
+            }
+        }
+        pages
+    }
+
+    fn walk_pages<'a>(
+        &'a self,
+        node: &'a Object,
+        resources: Option<&'a Dict>,
+        pages: &mut Vec<(&'a Dict, Option<&'a Dict>)>,
+        seen: &mut HashSet<u32>,
+    ) {
+        if let Object::Ref(id) = node
+            && !seen.insert(*id)
+        {
+            return;
+        }
+        let Some(dict) = self.resolve(node).dict() else {
+            return;
+        };
+        let resources = self.get(dict, b"Resources").dict().or(resources);
+        match dict.get(b"Kids".as_slice()).map(|k| self.resolve(k)) {
+            Some(Object::Array(kids)) => {
+                for kid in kids {
+                    self.walk_pages(kid, resources, pages, seen);
+                }
+            }
+            _ => pages.push((dict, resources)),
+        }
+    }
+}
+
+/// the data of a stream starting after the `stream` keyword, and where it ends
+fn stream_data(data: &[u8], mut start: usize, dict: &Dict) -> (Vec<u8>, usize) {
+    if data[start..].starts_with(b"\r\n") {
+        start += 2;
+    } else if data[start..].starts_with(b"\n") || data[start..].starts_with(b"\r") {
+        start += 1;
+    }
+    // /Length can be a reference to an object that is not read yet, then the end is searched
+    if let Some(Object::Number(length)) = dict.get(b"Length".as_slice()) {
+        let end = start + *length as usize;
+        if end <= data.len() {
+            let mut after = Parser { data, pos: end };
+            after.skip_whitespace();
+            if data[after.pos..].starts_with(b"endstream") {
+                return (data[start..end].to_vec(), after.pos + b"endstream".len());
+            }
+        }
+    }
+    match find(data, b"endstream", start) {
+        Some(end) => {
+            let mut content_end = end;
+            if data[..content_end].ends_with(b"\r\n") {
+                content_end -= 2;
+            } else if data[..content_end].ends_with(b"\n") || data[..content_end].ends_with(b"\r") {
+                content_end -= 1;
+            }
+            (
+                data[start..content_end.max(start)].to_vec(),
+                end + b"endstream".len(),
+            )
+        }
+        None => (data[start..].to_vec(), data.len()),
+    }
+}
+
+fn ascii85(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut group = vec![];
+    for &b in data {
+        match b {
+            b'~' => break,
+            b'z' if group.is_empty() => out.extend([0; 4]),
+            b'!'..=b'u' => {
+                group.push(b - b'!');
+                if group.len() == 5 {
+                    let value = group
+                        .iter()
+                        .fold(0u32, |v, &d| v.wrapping_mul(85).wrapping_add(d as u32));
+                    out.extend(value.to_be_bytes());
+                    group.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+    if group.len() > 1 {
+        let len = group.len();
+        group.resize(5, 84);
+        let value = group
+            .iter()
+            .fold(0u32, |v, &d| v.wrapping_mul(85).wrapping_add(d as u32));
+        out.extend(&value.to_be_bytes()[..len - 1]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn objects() {
+        let mut parser = Parser::new(
+            b"<< /Type /Page /Kids [3 0 R 4 0 R] /Title (a \\(b\\) c\\101) /Id <48 65 6c6>\n\
+              /Name#20x 12.5 /Neg -3 >> %comment\n 7 false",
+        );
+        let Some(Object::Dict(dict)) = parser.next() else {
+            panic!("not a dict");
+        };
+        assert_eq!(
+            dict[b"Kids".as_slice()],
+            Object::Array(vec![Object::Ref(3), Object::Ref(4)])
+        );
+        assert_eq!(
+            dict[b"Title".as_slice()],
+            Object::String(b"a (b) cA".to_vec())
+        );
+        assert_eq!(dict[b"Id".as_slice()], Object::String(b"Hel`".to_vec()));
+        assert_eq!(dict[b"Name x".as_slice()], Object::Number(12.5));
+        assert_eq!(dict[b"Neg".as_slice()], Object::Number(-3.0));
+        assert_eq!(parser.next(), Some(Object::Number(7.0)));
+        assert_eq!(parser.next(), Some(Object::Bool(false)));
+        assert_eq!(parser.next(), None);
+        assert_eq!(ascii85(b"87cURD]i,\"Ebo80~>"), b"Hello World!");
+    }
+}
@@ -0,0 +1,409 @@
+//! Adapters loaded from dynamic libraries at startup.
+//!
+//! Every `.so` / `.dylib` / `.dll` in `--rga-plugins-dir` must export a C function
+//!
+//! ```c
+//! const struct RgaPluginDescriptor *rga_plugin_v1(void);
+//! ```
+//!
+//! returning a descriptor that lives as long as the library is loaded (libraries are never unloaded).
+//! The structs below are the whole ABI. They only use C types so plugins can be written in any
+//! language that can produce a C shared library. Incompatible changes bump [`RGA_PLUGIN_ABI_VERSION`]
+//! and the name of the entry point.
+//!
+//! `adapt` is called on a blocking thread, once per file. It reads the file through `input` and writes
+//! plain text to `output`. Line prefixes, postprocessing and caching are handled by rga as for custom adapters.
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::{join_handle_to_stream, to_io_err};
+use anyhow::Result;
+use async_trait::async_trait;
+use log::debug;
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::SyncIoBridge;
+
+pub const RGA_PLUGIN_ABI_VERSION: u32 = 1;
+const ENTRY_POINT: &[u8] = b"rga_plugin_v1";
+/// size of the buffer passed to `adapt` for an error message
+const ERROR_LEN: usize = 1024;
+
+/// the file to adapt. `read` returns the number of bytes read, 0 at the end of the file and -1 on errors
+#[repr(C)]
+pub struct RgaPluginReader {
+    pub ctx: *mut c_void,
+    pub read: unsafe extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize,
+}
+
+/// the adapted text. `write` writes all `len` bytes and returns 0, or non-zero on errors
+/// (e.g. because rg stopped reading). The plugin should then return as soon as possible
+#[repr(C)]
+pub struct RgaPluginWriter {
+    pub ctx: *mut c_void,
+    pub write: unsafe extern "C" fn(ctx: *mut c_void, buf: *const u8, len: usize) -> i32,
+}
+
+#[repr(C)]
+pub struct RgaPluginDescriptor {
+    /// must be [`RGA_PLUGIN_ABI_VERSION`]
+    pub abi_version: u32,
+    /// adapter name as used in `--rga-adapters`
+    pub name: *const c_char,
+    /// bump when the output changes, to invalidate cached results
+    pub version: i32,
+    pub description: *const c_char,
+    /// NULL-terminated list of file extensions without the dot
+    pub extensions: *const *const c_char,
+    /// NULL-terminated list of mime types. May be NULL
+    pub mimetypes: *const *const c_char,
+    /// non-zero to only enable the adapter with `--rga-adapters=+name`
+    pub disabled_by_default: u8,
+    /// adapt the file at (virtual) path `path`. Returns 0 on success, otherwise writes a
+    /// NUL-terminated message of at most `error_len` bytes to `error`
+    pub adapt: unsafe extern "C" fn(
+        path: *const c_char,
+        input: *const RgaPluginReader,
+        output: *const RgaPluginWriter,
+        error: *mut c_char,
+        error_len: usize,
+    ) -> i32,
+}
+
+// descriptors are required to be immutable and `adapt` to be callable from any thread
+unsafe impl Sync for RgaPluginDescriptor {}
+
+type EntryPoint = unsafe extern "C" fn() -> *const RgaPluginDescriptor;
+
+struct ReadCtx<'a> {
+    inner: &'a mut dyn Read,
+    error: Option<std::io::Error>,
+}
+struct WriteCtx<'a> {
+    inner: &'a mut dyn Write,
+    error: Option<std::io::Error>,
+}
+
+unsafe extern "C" fn read_callback(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize {
+    let ctx = unsafe { &mut *(ctx as *mut ReadCtx) };
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+    loop {
+        match ctx.inner.read(buf) {
+            std::result::Result::Ok(n) => return n as isize,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                ctx.error = Some(e);
+                return -1;
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn write_callback(ctx: *mut c_void, buf: *const u8, len: usize) -> i32 {
+    let ctx = unsafe { &mut *(ctx as *mut WriteCtx) };
+    let buf = unsafe { std::slice::from_raw_parts(buf, len) };
+    match ctx.inner.write_all(buf) {
+        std::result::Result::Ok(()) => 0,
+        Err(e) => {
+            ctx.error = Some(e);
+            1
+        }
+    }
+}
+
+/// # Safety
+/// `p` must be NULL or point to a NUL-terminated string
+unsafe fn c_str(p: *const c_char) -> Option<String> {
+    (!p.is_null()).then(|| unsafe { CStr::from_ptr(p) }.to_string_lossy().into_owned())
+}
+
+/// # Safety
+/// `p` must be NULL or point to a NULL-terminated array of NUL-terminated strings
+unsafe fn c_str_list(mut p: *const *const c_char) -> Vec<String> {
+    let mut out = vec![];
+    while !p.is_null() && !unsafe { *p }.is_null() {
+        out.extend(unsafe { c_str(*p) });
+        p = unsafe { p.add(1) };
+    }
+    out
+}
+
+pub struct PluginAdapter {
+    plugin: &'static RgaPluginDescriptor,
+    meta: AdapterMeta,
+}
+
+impl PluginAdapter {
+    /// # Safety
+    /// all pointers in `plugin` must be valid as documented on [`RgaPluginDescriptor`]
+    pub unsafe fn from_descriptor(plugin: &'static RgaPluginDescriptor) -> Result<Self> {
+        if plugin.abi_version != RGA_PLUGIN_ABI_VERSION {
+            return Err(format_err!(
+                "unsupported plugin ABI version {} (expected {})",
+                plugin.abi_version,
+                RGA_PLUGIN_ABI_VERSION
+            ));
+        }
+        let name = unsafe { c_str(plugin.name) }.context("plugin has no name")?;
+        let mimetypes = unsafe { c_str_list(plugin.mimetypes) };
+        let meta = AdapterMeta {
+            name,
+            version: plugin.version,
+            description: unsafe { c_str(plugin.description) }.unwrap_or_default(),
+            capabilities: AdapterCapabilities::default(),
+            fast_matchers: unsafe { c_str_list(plugin.extensions) }
+                .into_iter()
+                .map(FastFileMatcher::FileExtension)
+                .collect(),
+            slow_matchers: (!mimetypes.is_empty())
+                .then(|| mimetypes.into_iter().map(FileMatcher::MimeType).collect()),
+            keep_fast_matchers_if_accurate: true,
+            disabled_by_default: plugin.disabled_by_default != 0,
+        };
+        Ok(Self { plugin, meta })
+    }
+}
+
+fn run(
+    plugin: &RgaPluginDescriptor,
+    name: &str,
+    path: &Path,
+    inp: &mut dyn Read,
+    oup: &mut dyn Write,
+) -> Result<()> {
+    let path = CString::new(path.to_string_lossy().replace('\0', ""))?;
+    let mut read_ctx = ReadCtx {
+        inner: inp,
+        error: None,
+    };
+    let mut write_ctx = WriteCtx {
+        inner: oup,
+        error: None,
+    };
+    let reader = RgaPluginReader {
+        ctx: &mut read_ctx as *mut ReadCtx as *mut c_void,
+        read: read_callback,
+    };
+    let writer = RgaPluginWriter {
+        ctx: &mut write_ctx as *mut WriteCtx as *mut c_void,
+        write: write_callback,
+    };
+    let mut error = vec![0u8; ERROR_LEN];
+    let status = unsafe {
+        (plugin.adapt)(
+            path.as_ptr(),
+            &reader,
+            &writer,
+            error.as_mut_ptr() as *mut c_char,
+            ERROR_LEN,
+        )
+    };
+    // io errors of our side are more useful than whatever the plugin made of them
+    if let Some(e) = write_ctx.error.take().or(read_ctx.error.take()) {
+        return Err(e.into());
+    }
+    if status != 0 {
+        let msg = CStr::from_bytes_until_nul(&error)
+            .map(|m| m.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        return Err(format_err!(
+            "plugin {} failed with status {}: {}",
+            name,
+            status,
+            msg
+        ));
+    }
+    Ok(())
+}
+
+impl GetMetadata for PluginAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &self.meta
+    }
+}
+
+#[async_trait]
+impl FileAdapter for PluginAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let plugin = self.plugin;
+        let name = self.meta.name.clone();
+        let (w, r) = tokio::io::duplex(128 * 1024);
+        let path = filepath_hint.clone();
+        let mut inp_sync = SyncIoBridge::new(inp);
+        let mut oup_sync = SyncIoBridge::new(w);
+        let joiner = tokio::task::spawn_blocking(move || {
+            run(plugin, &name, &path, &mut inp_sync, &mut oup_sync)
+                .with_context(|| format!("adapting {}", path.display()))
+                .map_err(to_io_err)?;
+            oup_sync.shutdown()
+        });
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(r.chain(join_handle_to_stream(joiner))),
+            line_prefix,
+            is_real_file: false,
+            file_mtime_unix_ms: None,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+/// plugins already loaded in this process, by directory
+type LoadedPlugins = HashMap<PathBuf, Vec<Arc<dyn FileAdapter>>>;
+static LOADED: Lazy<Mutex<LoadedPlugins>> = Lazy::new(Default::default);
+
+fn load_plugin(path: &Path) -> Result<PluginAdapter> {
+    // libraries stay loaded for the lifetime of the process, the descriptor points into them
+    let lib: &'static libloading::Library = Box::leak(Box::new(unsafe {
+        libloading::Library::new(path).context("loading library")?
+    }));
+    let plugin = unsafe {
+        let entry: libloading::Symbol<EntryPoint> = lib
+            .get(ENTRY_POINT)
+            .with_context(|| format!("missing {}", String::from_utf8_lossy(ENTRY_POINT)))?;
+        entry().as_ref().context("plugin returned no descriptor")?
+    };
+    unsafe { PluginAdapter::from_descriptor(plugin) }
+}
+
+/// load every plugin library in `dir`, sorted by file name
+pub fn load_plugins(dir: &Path) -> Result<Vec<Arc<dyn FileAdapter>>> {
+    let mut loaded = LOADED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(adapters) = loaded.get(dir) {
+        return Ok(adapters.clone());
+    }
+    let mut paths = std::fs::read_dir(dir)
+        .with_context(|| format!("reading plugins dir {}", dir.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|p| {
+        p.extension()
+            .is_some_and(|e| e == std::env::consts::DLL_EXTENSION)
+    });
+    paths.sort();
+    let mut adapters: Vec<Arc<dyn FileAdapter>> = vec![];
+    for path in paths {
+        let adapter =
+            load_plugin(&path).with_context(|| format!("loading plugin {}", path.display()))?;
+        debug!(
+            "loaded plugin {} from {}",
+            adapter.meta.name,
+            path.display()
+        );
+        adapters.push(Arc::new(adapter));
+    }
+    loaded.insert(dir.to_path_buf(), adapters.clone());
+    Ok(adapters)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// uppercases its input, fails on files containing "fail"
+    unsafe extern "C" fn upper_adapt(
+        _path: *const c_char,
+        input: *const RgaPluginReader,
+        output: *const RgaPluginWriter,
+        error: *mut c_char,
+        error_len: usize,
+    ) -> i32 {
+        let (input, output) = unsafe { (&*input, &*output) };
+        let mut content = vec![];
+        let mut buf = [0u8; 3];
+        loop {
+            let n = unsafe { (input.read)(input.ctx, buf.as_mut_ptr(), buf.len()) };
+            if n < 0 {
+                return 1;
+            }
+            if n == 0 {
+                break;
+            }
+            content.extend_from_slice(&buf[..n as usize]);
+        }
+        if content.windows(4).any(|w| w == b"fail") {
+            let msg = b"no thanks\0";
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    msg.as_ptr(),
+                    error as *mut u8,
+                    error_len.min(msg.len()),
+                )
+            };
+            return 2;
+        }
+        content.make_ascii_uppercase();
+        unsafe { (output.write)(output.ctx, content.as_ptr(), content.len()) }
+    }
+
+    struct Ptrs([*const c_char; 2]);
+    unsafe impl Sync for Ptrs {}
+    static EXTENSIONS: Ptrs = Ptrs([c"upper".as_ptr(), std::ptr::null()]);
+    static UPPER: RgaPluginDescriptor = RgaPluginDescriptor {
+        abi_version: RGA_PLUGIN_ABI_VERSION,
+        name: c"upper".as_ptr(),
+        version: 1,
+        description: c"Uppercases text".as_ptr(),
+        extensions: &EXTENSIONS.0 as *const _ as *const *const c_char,
+        mimetypes: std::ptr::null(),
+        disabled_by_default: 0,
+        adapt: upper_adapt,
+    };
+
+    #[tokio::test]
+    async fn descriptor() -> Result<()> {
+        let adapter = unsafe { PluginAdapter::from_descriptor(&UPPER) }?;
+        assert_eq!(adapter.metadata().name, "upper");
+        assert!(matches!(
+            &adapter.metadata().fast_matchers[..],
+            [FastFileMatcher::FileExtension(ext)] if ext == "upper"
+        ));
+        assert!(adapter.metadata().slow_matchers.is_none());
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("x.upper"),
+            Box::pin(std::io::Cursor::new(b"hello\nworld\n".to_vec())),
+        );
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(adapted_to_vec(res).await?, b"HELLO\nWORLD\n");
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("x.upper"),
+            Box::pin(std::io::Cursor::new(b"please fail".to_vec())),
+        );
+        let err = adapted_to_vec(adapter.adapt(a, &d).await?)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("failed with status 2: no thanks"));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("readme.txt"), "not a plugin")?;
+        assert!(load_plugins(dir.path())?.is_empty());
+        assert!(load_plugins(&dir.path().join("missing")).is_err());
+        Ok(())
+    }
+}
@@ -10,23 +10,180 @@ use encoding_rs::Encoding;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 use tokio_util::io::SyncIoBridge;
 
+use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
 use std::path::PathBuf;
 use std::pin::Pin;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use regex::bytes::RegexSet;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
 use tokio_util::io::ReaderStream;
 use tokio_util::io::StreamReader;
 
 use crate::adapted_iter::AdaptedFilesIterBox;
 use crate::adapted_iter::one_file;
+use crate::config::{PostFilter, PostprocOptions};
 use crate::matching::FastFileMatcher;
 
-use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
+use super::{AdaptInfo, AdapterCapabilities, AdapterMeta, FileAdapter, GetMetadata};
 
 fn add_newline(ar: impl AsyncRead + Send) -> impl AsyncRead + Send {
     ar.chain(Cursor::new(b"\n"))
 }
 
+/// the marker of binary files with `postproc.markers` "tokens" or "both"
+pub const BINARY_TOKEN: &str = "[rga:binary]";
+/// the start of `[rga:page=N]`, the page token with `postproc.markers` "tokens" or "both"
+const PAGE_TOKEN: &str = "[rga:page=";
+
+/// (language, page prefix, binary marker) for `postproc.locale`
+const TRANSLATIONS: &[(&str, &str, &str)] = &[
+    ("de", "Seite ", "[rga: Binärdaten]"),
+    ("es", "Página ", "[rga: datos binarios]"),
+    ("fr", "Page ", "[rga: données binaires]"),
+    ("it", "Pagina ", "[rga: dati binari]"),
+    ("nl", "Pagina ", "[rga: binaire gegevens]"),
+    ("pl", "Strona ", "[rga: dane binarne]"),
+    ("pt", "Página ", "[rga: dados binários]"),
+    ("ru", "Страница ", "[rga: двоичные данные]"),
+    ("sv", "Sida ", "[rga: binära data]"),
+];
+
+/// the page prefix and binary marker in the language of `postproc.locale`, English if there is
+/// no translation
+fn translation(options: &PostprocOptions) -> (&'static str, &'static str) {
+    options
+        .locale
+        .as_deref()
+        .and_then(|lang| TRANSLATIONS.iter().find(|(l, ..)| *l == lang))
+        .map(|(_, page, binary)| (*page, *binary))
+        .unwrap_or(("Page ", "[rga: binary data]"))
+}
+
+/// How pages and binary files are marked, see `postproc.markers`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Markers {
+    /// the page prefix and the binary marker
+    #[default]
+    Text,
+    /// `[rga:page=N]` and `[rga:binary]`
+    Tokens,
+    /// the token and the text
+    Both,
+}
+
+impl Markers {
+    pub fn new(options: &PostprocOptions) -> Self {
+        match options.markers.as_deref() {
+            Some("tokens") => Markers::Tokens,
+            Some("both") => Markers::Both,
+            _ => Markers::Text,
+        }
+    }
+}
+
+fn binary_marker(options: &PostprocOptions) -> String {
+    let text = options
+        .binary_marker
+        .as_deref()
+        .unwrap_or(translation(options).1);
+    match Markers::new(options) {
+        Markers::Text => text.to_string(),
+        Markers::Tokens => BINARY_TOKEN.to_string(),
+        Markers::Both => format!("{BINARY_TOKEN} {text}"),
+    }
+}
+
+pub fn prefix_separator(options: &PostprocOptions) -> &str {
+    options.prefix_separator.as_deref().unwrap_or(": ")
+}
+
+/// a file name or title for a line prefix, with the characters that would end a line or rg's record
+/// escaped
+pub fn prefix_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if !s.contains(['\n', '\r', '\0']) {
+        return s.into();
+    }
+    s.replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\0', "\\0")
+        .into()
+}
+
+/// How [`postproc_pagebreaks`] numbers pages.
+#[derive(Debug, Clone)]
+pub struct PageFormat {
+    pub prefix: String,
+    /// zero-pad page numbers to this many digits
+    pub number_width: usize,
+    pub first_page: i32,
+    pub include_empty: bool,
+    /// after the page number
+    pub separator: String,
+    pub markers: Markers,
+}
+
+impl Default for PageFormat {
+    fn default() -> Self {
+        Self {
+            prefix: "Page ".to_string(),
+            number_width: 0,
+            first_page: 1,
+            include_empty: true,
+            separator: ": ".to_string(),
+            markers: Markers::Text,
+        }
+    }
+}
+
+impl PageFormat {
+    pub fn new(options: &PostprocOptions) -> Self {
+        let default = Self::default();
+        Self {
+            prefix: options
+                .page_prefix
+                .clone()
+                .unwrap_or_else(|| translation(options).0.to_string()),
+            number_width: options.page_number_width.unwrap_or(default.number_width),
+            first_page: options.first_page.unwrap_or(default.first_page),
+            include_empty: options.page_include_empty.unwrap_or(default.include_empty),
+            separator: prefix_separator(options).to_string(),
+            markers: Markers::new(options),
+        }
+    }
+
+    pub fn line_prefix(&self, page: i32) -> String {
+        let text = format!("{}{:0width$}", self.prefix, page, width = self.number_width);
+        match self.markers {
+            Markers::Text => format!("{text}{}", self.separator),
+            Markers::Tokens => format!("{PAGE_TOKEN}{page}]{}", self.separator),
+            Markers::Both => format!("{PAGE_TOKEN}{page}] {text}{}", self.separator),
+        }
+    }
+
+    /// the page number of a line that starts with a [`Self::line_prefix`], and the rest of the line
+    pub fn strip_page<'a>(&self, line: &'a str) -> Option<(u32, &'a str)> {
+        fn number<'a>(s: &'a str, prefix: &str) -> Option<(u32, &'a str)> {
+            let after = s.strip_prefix(prefix)?;
+            let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            Some((after[..digits].parse().ok()?, &after[digits..]))
+        }
+        let (page, rest) = match self.markers {
+            // without a prefix, any number at the start of a line would look like a page
+            Markers::Text if self.prefix.is_empty() => return None,
+            Markers::Text => number(line, &self.prefix)?,
+            Markers::Tokens => {
+                let (page, rest) = number(line, PAGE_TOKEN)?;
+                (page, rest.strip_prefix(']')?)
+            }
+            Markers::Both => {
+                let (page, rest) = number(line, PAGE_TOKEN)?;
+                (page, number(rest.strip_prefix("] ")?, &self.prefix)?.1)
+            }
+        };
+        Some((page, rest.strip_prefix(self.separator.as_str())?))
+    }
+}
+
 pub struct PostprocPrefix {}
 impl GetMetadata for PostprocPrefix {
     fn metadata(&self) -> &super::AdapterMeta {
@@ -35,7 +192,7 @@ impl GetMetadata for PostprocPrefix {
                 name: "postprocprefix".to_owned(),
                 version: 1,
                 description: "Adds the line prefix to each line (e.g. the filename within a zip)".to_owned(),
-                recurses: false,
+                capabilities: AdapterCapabilities::default(),
                 fast_matchers: vec![],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
@@ -52,7 +209,7 @@ impl FileAdapter for PostprocPrefix {
         a: super::AdaptInfo,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let marker = a.config.postproc_binary_marker.clone().unwrap_or("[rga: binary data]".to_string());
+        let marker = binary_marker(&a.config.postproc.options);
         let read = add_newline(postproc_prefix(
             &a.line_prefix,
             postproc_encoding(&a.line_prefix, a.inp, &marker).await?,
@@ -169,7 +326,7 @@ impl GetMetadata for PostprocPageBreaks {
                 name: "postprocpagebreaks".to_owned(),
                 version: 1,
                 description: "Adds the page number to each line for an input file that specifies page breaks as ascii page break character.\nMainly to be used internally by the poppler adapter.".to_owned(),
-                recurses: false,
+                capabilities: AdapterCapabilities::default(),
                 fast_matchers: vec![FastFileMatcher::FileExtension("asciipagebreaks".to_string())],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
@@ -186,10 +343,14 @@ impl FileAdapter for PostprocPageBreaks {
         a: super::AdaptInfo,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let marker = a.config.postproc_binary_marker.clone().unwrap_or("[rga: binary data]".to_string());
-        let prefix = a.config.postproc_page_prefix.clone().unwrap_or("Page ".to_string());
-        let include_empty = a.config.postproc_page_include_empty.unwrap_or(true);
-        let read = postproc_pagebreaks(postproc_encoding(&a.line_prefix, a.inp, &marker).await?, prefix, include_empty);
+        let options = &a.config.postproc.options;
+        let read = postproc_pagebreaks(
+            postproc_repeated_lines(
+                postproc_encoding(&a.line_prefix, a.inp, &binary_marker(options)).await?,
+                RepeatedLines::new(options),
+            ),
+            PageFormat::new(options),
+        );
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
             inp: Box::pin(read),
@@ -205,51 +366,237 @@ impl FileAdapter for PostprocPageBreaks {
         Ok(one_file(ai))
     }
 }
-/// Adds the prefix "Page N: " to each line,
-/// where N starts at one and is incremented for each ASCII Form Feed character in the input stream.
+/// the tag of lines with `postproc.repeated_lines` "tag"
+pub const REPEATED_TOKEN: &str = "[rga:repeated] ";
+/// how many lines at the top and the bottom of a page can be running headers and footers
+const EDGE_LINES: usize = 3;
+/// documents with fewer pages with text are not looked at, two pages can have the same line by chance
+const MIN_PAGES_FOR_REPEATED: usize = 3;
+
+/// What to do with lines that are at the same place on most pages, see `postproc.repeated_lines`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatedLines {
+    Keep,
+    Remove,
+    Tag,
+}
+
+impl RepeatedLines {
+    pub fn new(options: &PostprocOptions) -> Self {
+        match options.repeated_lines.as_deref() {
+            Some("remove") => RepeatedLines::Remove,
+            Some("tag") => RepeatedLines::Tag,
+            _ => RepeatedLines::Keep,
+        }
+    }
+}
+
+/// a line without its surrounding whitespace and with its numbers replaced, so "Page 3 of 12"
+/// on one page is the same as "Page 4 of 12" on the next
+fn normalized(line: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    for &b in line.trim_ascii() {
+        if b.is_ascii_digit() {
+            if out.last() != Some(&b'#') {
+                out.push(b'#');
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// the places of the text lines of a page that can be headers or footers: counted from the top
+/// (positive) and from the bottom (negative)
+fn edge_lines(lines: &[&[u8]]) -> Vec<(usize, i32)> {
+    let text: Vec<usize> = (0..lines.len())
+        .filter(|&i| !lines[i].trim_ascii().is_empty())
+        .collect();
+    let mut edges: Vec<(usize, i32)> = vec![];
+    for (n, &i) in text.iter().take(EDGE_LINES).enumerate() {
+        edges.push((i, n as i32 + 1));
+    }
+    for (n, &i) in text.iter().rev().take(EDGE_LINES).enumerate() {
+        edges.push((i, -(n as i32) - 1));
+    }
+    edges
+}
+
+/// Removes or tags the lines that are at the same place on more than half of the pages (separated
+/// by form feeds) of a document. The whole document is read first.
+pub fn postproc_repeated_lines<T: AsyncRead + Send + 'static>(
+    input: T,
+    mode: RepeatedLines,
+) -> Pin<Box<dyn AsyncRead + Send>> {
+    if mode == RepeatedLines::Keep {
+        return Box::pin(input);
+    }
+    let output_stream = stream! {
+        let mut text = vec![];
+        if let Err(e) = std::pin::pin!(input).read_to_end(&mut text).await {
+            yield Err(e);
+            return;
+        }
+        yield std::io::Result::Ok(Bytes::from(remove_repeated_lines(&text, mode)));
+    };
+    Box::pin(StreamReader::new(output_stream))
+}
+
+fn remove_repeated_lines(text: &[u8], mode: RepeatedLines) -> Vec<u8> {
+    let pages: Vec<Vec<&[u8]>> = text
+        .split(|&b| b == b'\x0c')
+        .map(|page| page.split(|&b| b == b'\n').collect())
+        .collect();
+    let with_text = pages
+        .iter()
+        .filter(|lines| lines.iter().any(|l| !l.trim_ascii().is_empty()))
+        .count();
+    if with_text < MIN_PAGES_FOR_REPEATED {
+        return text.to_vec();
+    }
+    // the number of pages each line is on, at each place
+    let mut counts: HashMap<(i32, Vec<u8>), usize> = HashMap::new();
+    for lines in &pages {
+        let mut seen = HashSet::new();
+        for (i, place) in edge_lines(lines) {
+            let key = (place, normalized(lines[i]));
+            if seen.insert(key.clone()) {
+                *counts.entry(key).or_default() += 1;
+            }
+        }
+    }
+    let repeated = |line: &[u8], place: i32| {
+        counts
+            .get(&(place, normalized(line)))
+            .is_some_and(|&n| n * 2 > with_text)
+    };
+    let mut out = Vec::with_capacity(text.len());
+    for (p, lines) in pages.iter().enumerate() {
+        if p > 0 {
+            out.push(b'\x0c');
+        }
+        let edges = edge_lines(lines);
+        let mut first = true;
+        for (i, line) in lines.iter().enumerate() {
+            let is_repeated = edges
+                .iter()
+                .any(|&(j, place)| j == i && repeated(line, place));
+            if is_repeated && mode == RepeatedLines::Remove {
+                continue;
+            }
+            if !first {
+                out.push(b'\n');
+            }
+            first = false;
+            if is_repeated {
+                out.extend_from_slice(REPEATED_TOKEN.as_bytes());
+            }
+            out.extend_from_slice(line);
+        }
+    }
+    out
+}
+
+/// Adds the prefix "Page N: " (see [`PageFormat`]) to each line,
+/// where N starts at the first page number and is incremented for each ASCII Form Feed character in the input stream.
 /// ASCII form feeds are the page delimiters output by `pdftotext`.
-pub fn postproc_pagebreaks<T: AsyncRead + Send + 'static>(input: T, prefix: String, _include_empty: bool) -> std::pin::Pin<Box<dyn AsyncRead + Send>> {
-    let regex_linefeed = regex::bytes::Regex::new(r"\x0c").unwrap();
+pub fn postproc_pagebreaks<T: AsyncRead + Send + 'static>(input: T, format: PageFormat) -> std::pin::Pin<Box<dyn AsyncRead + Send>> {
     let regex_newline = regex::bytes::Regex::new("\n").unwrap();
     let regex_crlf = regex::bytes::Regex::new("\r\n").unwrap();
-    let mut page_count: i32 = 1;
-    let mut page_prefix: String = format!("\n{prefix}{page_count}: ");
 
+    let first_page = format.first_page;
+    let include_empty = format.include_empty;
+    // a page is written once it is complete, so empty pages can be left out
+    let render_page = move |page: i32, content: &[u8], first: bool| {
+        let line_prefix = format.line_prefix(page);
+        let content = regex_crlf.replace_all(content, &b"\n"[..]);
+        let mut out = if first { vec![] } else { b"\n".to_vec() };
+        out.extend_from_slice(line_prefix.as_bytes());
+        out.extend_from_slice(&regex_newline.replace_all(&content, format!("\n{line_prefix}").as_bytes()));
+        Bytes::from(out)
+    };
     let input_stream = ReaderStream::new(input);
     let output_stream = stream! {
-        yield std::io::Result::Ok(Bytes::copy_from_slice(format!("{prefix}{page_count}: ").as_bytes()));
-        // store Page X: line prefixes in pending and only write it to the output when there is more text to be written
-        // this is needed since pdftotext outputs a \x0c at the end of the last page
-        let mut pending: Option<Bytes> = None;
-
+        let mut page = first_page;
+        let mut content: Vec<u8> = vec![];
+        let mut first = true;
         for await read_chunk in input_stream {
             let read_chunk = read_chunk?;
-            let read_chunk = regex_crlf.replace_all(&read_chunk, &b"\n"[..]);
-            let page_chunks = regex_linefeed.split(&read_chunk);
-            for (chunk_idx, page_chunk) in page_chunks.enumerate() {
-                if chunk_idx != 0 {
-                    page_count += 1;
-                    page_prefix = format!("\n{prefix}{page_count}: ");
-                    if let Some(p) = pending.take() {
-                        yield Ok(p);
-                    }
-                    pending = Some(Bytes::copy_from_slice(page_prefix.as_bytes()));
+            let mut parts = read_chunk.split(|b| *b == b'\x0c');
+            content.extend_from_slice(parts.next().unwrap_or_default());
+            for part in parts {
+                if include_empty || !content.trim_ascii().is_empty() {
+                    yield std::io::Result::Ok(render_page(page, &content, first));
+                    first = false;
                 }
-                // Only flush and emit when there is actual content in the chunk.
-                // This avoids emitting a trailing empty page produced by a final form feed.
-                if !page_chunk.is_empty() {
-                    if let Some(p) = pending.take() {
-                        yield Ok(p);
-                    }
-                    yield Ok(Bytes::copy_from_slice(&regex_newline.replace_all(page_chunk, page_prefix.as_bytes())));
-                }
-
+                page += 1;
+                content.clear();
+                content.extend_from_slice(part);
             }
         }
+        // pdftotext ends the last page with a form feed, the empty "page" after it is not a page
+        let trailing = content.is_empty() && page != first_page;
+        if !trailing && (include_empty || !content.trim_ascii().is_empty()) {
+            yield Ok(render_page(page, &content, first));
+        }
+    };
+    Box::pin(StreamReader::new(output_stream))
+}
+
+/// the compiled regexes of a [`PostFilter`]
+#[derive(Clone, Debug)]
+pub struct LineFilter {
+    include: Option<RegexSet>,
+    exclude: RegexSet,
+}
+
+impl LineFilter {
+    pub fn new(filter: &PostFilter) -> Result<Self> {
+        Ok(Self {
+            include: (!filter.include.is_empty())
+                .then(|| RegexSet::new(&filter.include))
+                .transpose()?,
+            exclude: RegexSet::new(&filter.exclude)?,
+        })
+    }
 
-        
-        // Drop any pending page prefix at EOF to avoid a trailing empty page.
-        let _ = pending.take();
+    /// whether to keep `line`, which is matched without its line ending and page breaks
+    fn keeps(&self, line: &[u8]) -> bool {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line: Vec<u8> = line.iter().copied().filter(|&b| b != b'\x0c').collect();
+        self.include.as_ref().is_none_or(|include| include.is_match(&line))
+            && !self.exclude.is_match(&line)
+    }
+}
+
+/// Drops the lines `filter` does not keep. The form feeds (page breaks) in them are kept and
+/// written before the next line, so the page numbers stay the same.
+pub fn postproc_filter<T: AsyncRead + Send + 'static>(input: T, filter: LineFilter) -> Pin<Box<dyn AsyncRead + Send>> {
+    let output_stream = stream! {
+        let mut input = std::pin::pin!(BufReader::new(input));
+        let mut page_breaks = vec![];
+        loop {
+            let mut line = vec![];
+            match input.read_until(b'\n', &mut line).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+            if filter.keeps(&line) {
+                page_breaks.append(&mut line);
+                yield std::io::Result::Ok(Bytes::from(std::mem::take(&mut page_breaks)));
+            } else {
+                page_breaks.extend(line.iter().filter(|&&b| b == b'\x0c'));
+            }
+        }
+        if !page_breaks.is_empty() {
+            yield Ok(Bytes::from(page_breaks));
+        }
     };
     Box::pin(StreamReader::new(output_stream))
 }
@@ -273,7 +620,7 @@ mod tests {
         let mock: Mock = Builder::new()
             .read(b"Hello\nWorld\x0cFoo Bar\n\x0cTest\x0c")
             .build();
-        let res = postproc_pagebreaks(mock, "Page ".to_string(), true).read_to_end(&mut output).await;
+        let res = postproc_pagebreaks(mock, PageFormat::default()).read_to_end(&mut output).await;
         println!("{}", String::from_utf8_lossy(&output));
         assert!(res.is_ok());
         assert_eq!(
@@ -291,7 +638,7 @@ mod tests {
             .read(b"Foo Bar\n")
             .read(b"\x0cTest\x0c")
             .build();
-        let res = postproc_pagebreaks(mock, "Page ".to_string(), true).read_to_end(&mut output).await;
+        let res = postproc_pagebreaks(mock, PageFormat::default()).read_to_end(&mut output).await;
         println!("{}", String::from_utf8_lossy(&output));
         assert!(res.is_ok());
         assert_eq!(
@@ -300,6 +647,98 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_page_format() -> Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new().read(b"Hello\x0c \n\x0cWor").read(b"ld\nFoo\x0c").build();
+        let format = PageFormat {
+            prefix: "p".to_string(),
+            number_width: 3,
+            first_page: 0,
+            include_empty: false,
+            separator: ": ".to_string(),
+            markers: Markers::Text,
+        };
+        postproc_pagebreaks(mock, format).read_to_end(&mut output).await?;
+        assert_eq!(String::from_utf8(output)?, "p000: Hello\np002: World\np002: Foo");
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_lines() -> Result<()> {
+        let text = "ACME Annual Report\nRevenue grew\n\n1 of 3\n\x0c\
+            ACME Annual Report\nCosts fell\nACME Annual Report is audited\n2 of 3\n\x0c\
+            ACME Annual Report\n\nOutlook\n3 of 3\n\x0c";
+        assert_eq!(
+            String::from_utf8(remove_repeated_lines(text.as_bytes(), RepeatedLines::Remove))?,
+            "Revenue grew\n\n\x0cCosts fell\nACME Annual Report is audited\n\x0c\nOutlook\n\x0c"
+        );
+        let tagged = String::from_utf8(remove_repeated_lines(text.as_bytes(), RepeatedLines::Tag))?;
+        assert!(tagged.starts_with("[rga:repeated] ACME Annual Report\nRevenue grew\n\n[rga:repeated] 1 of 3\n"));
+        // too few pages to tell
+        let two_pages = "Header\nOne\n\x0cHeader\nTwo\n";
+        assert_eq!(
+            remove_repeated_lines(two_pages.as_bytes(), RepeatedLines::Remove),
+            two_pages.as_bytes()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_post_filter() -> Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new()
+            .read(b"ACME Confidential\r\nHello\n\x0cACME Conf")
+            .read(b"idential\nWorld\n\x0cACME Confidential")
+            .build();
+        let filter = LineFilter::new(&PostFilter {
+            include: vec![],
+            exclude: vec!["^ACME Confidential$".to_string()],
+        })?;
+        let filtered = postproc_filter(mock, filter);
+        postproc_pagebreaks(filtered, PageFormat::default()).read_to_end(&mut output).await?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "Page 1: Hello\nPage 1: \nPage 2: World\nPage 2: "
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn adapter_overrides() -> Result<()> {
+        let config: crate::config::RgaConfig = serde_json::from_value(serde_json::json!({
+            "postproc": {"page_prefix": "p", "adapters": {"poppler": {"page_number_width": 2}}}
+        }))?;
+        assert!(config.postproc.for_adapter("zip").is_none());
+        assert_eq!(PageFormat::new(&config.postproc.options).line_prefix(3), "p3: ");
+        let poppler = config.postproc.for_adapter("poppler").unwrap();
+        assert_eq!(PageFormat::new(&poppler.options).line_prefix(3), "p03: ");
+        Ok(())
+    }
+
+    #[test]
+    fn markers() -> Result<()> {
+        let options = |markers: &str| PostprocOptions {
+            markers: Some(markers.to_string()),
+            locale: Some("de".to_string()),
+            ..Default::default()
+        };
+        let prefixes: Vec<_> = ["text", "tokens", "both"]
+            .iter()
+            .map(|m| PageFormat::new(&options(m)).line_prefix(3))
+            .collect();
+        assert_eq!(prefixes, ["Seite 3: ", "[rga:page=3]: ", "[rga:page=3] Seite 3: "]);
+        for m in ["text", "tokens", "both"] {
+            let format = PageFormat::new(&options(m));
+            let line = format!("{}hello", format.line_prefix(12));
+            assert_eq!(format.strip_page(&line), Some((12, "hello")), "{m}");
+            assert_eq!(format.strip_page("Page 12: hello"), None, "{m}");
+        }
+        assert_eq!(binary_marker(&options("both")), "[rga:binary] [rga: Binärdaten]");
+        assert_eq!(binary_marker(&PostprocOptions::default()), "[rga: binary data]");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pdf_twoblank() -> Result<()> {
         let adapter = poppler_adapter();
@@ -354,7 +793,7 @@ PREFIX:Page 3:
         let inp = Box::pin(Cursor::new(a));
         let inp = postproc_encoding("", inp, "[rga: binary data]").await?;
         if pagebreaks {
-            postproc_pagebreaks(inp, "Page ".to_string(), true).read_to_end(&mut oup).await?;
+            postproc_pagebreaks(inp, PageFormat::default()).read_to_end(&mut oup).await?;
         } else {
             let x = postproc_prefix(line_prefix, inp);
             pin!(x);
@@ -0,0 +1,517 @@
+//! Photoshop documents (.psd, and .psb for large documents): the layers from top to bottom as in
+//! the layers panel, with the names of the groups they are in, and the text of text layers. Only
+//! the layer records at the start of the file are read, the image data is skipped.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["psd", "psb"];
+
+const SIGNATURE: &[u8] = b"8BPS";
+/// the additional layer information blocks that have a 64 bit length in psb files
+const LONG_BLOCKS: &[&[u8; 4]] = &[
+    b"LMsk", b"Lr16", b"Lr32", b"Layr", b"Mt16", b"Mt32", b"Mtrn", b"Alph", b"FMsk", b"lnk2",
+    b"FEid", b"FXid", b"PxSD",
+];
+/// section divider types of group layers: open and closed folders, and the hidden end of a group
+const SECTION_OPEN: u32 = 1;
+const SECTION_CLOSED: u32 = 2;
+const SECTION_END: u32 = 3;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "psd".to_owned(),
+        version: 1,
+        description: "Lists the layers of Photoshop documents with the text of their text layers"
+            .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "image/vnd.adobe.photoshop".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PsdAdapter;
+
+impl PsdAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for PsdAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// big endian values from a buffer
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(n <= self.data.len(), "truncated layer record");
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn skip(&mut self, n: u64) -> Result<()> {
+        self.take(usize::try_from(n)?).map(|_| ())
+    }
+
+    /// a length prefixed UTF-16 string
+    fn unicode(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let units: Vec<u16> = self
+            .take(len.checked_mul(2).context("string too long")?)?
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units)
+            .trim_end_matches('\0')
+            .to_string())
+    }
+
+    /// a descriptor key or class id: a length prefixed string, or four bytes if the length is 0
+    fn id(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(if len == 0 { 4 } else { len })
+    }
+}
+
+/// skips a value of an action descriptor, false for types that are not understood
+fn skip_value(c: &mut Cursor, typ: &[u8]) -> Result<bool> {
+    match typ {
+        b"TEXT" => {
+            c.unicode()?;
+        }
+        b"enum" => {
+            c.id()?;
+            c.id()?;
+        }
+        b"long" => c.skip(4)?,
+        b"comp" | b"doub" => c.skip(8)?,
+        b"bool" => c.skip(1)?,
+        b"UntF" => c.skip(12)?,
+        b"UnFl" => {
+            c.skip(4)?;
+            let n = c.u32()?;
+            c.skip(u64::from(n) * 8)?;
+        }
+        b"type" | b"GlbC" => {
+            c.unicode()?;
+            c.id()?;
+        }
+        b"alis" | b"tdta" => {
+            let n = c.u32()?;
+            c.skip(n.into())?;
+        }
+        b"Objc" | b"GlbO" => return skip_descriptor(c),
+        b"VlLs" => {
+            for _ in 0..c.u32()? {
+                let typ = c.take(4)?;
+                if !skip_value(c, typ)? {
+                    return Ok(false);
+                }
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn skip_descriptor(c: &mut Cursor) -> Result<bool> {
+    c.unicode()?;
+    c.id()?;
+    for _ in 0..c.u32()? {
+        c.id()?;
+        let typ = c.take(4)?;
+        if !skip_value(c, typ)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// the `Txt ` item of an action descriptor, as in the text data of a type tool object
+fn descriptor_text(c: &mut Cursor) -> Result<Option<String>> {
+    c.unicode()?;
+    c.id()?;
+    for _ in 0..c.u32()? {
+        let key = c.id()?;
+        let typ = c.take(4)?;
+        if key == b"Txt " && typ == b"TEXT" {
+            return Ok(Some(c.unicode()?));
+        }
+        if !skip_value(c, typ)? {
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// the text of a type tool object (`TySh`)
+fn type_tool_text(data: &[u8]) -> Result<Option<String>> {
+    let mut c = Cursor { data };
+    // version, transform, text version and descriptor version
+    c.skip(2 + 6 * 8 + 2 + 4)?;
+    descriptor_text(&mut c)
+}
+
+#[derive(Default)]
+struct Layer {
+    name: String,
+    text: Option<String>,
+    section: u32,
+}
+
+/// the additional layer information blocks: signature, key and data
+fn tagged_blocks<'a>(
+    c: &mut Cursor<'a>,
+    psb: bool,
+    mut f: impl FnMut(&[u8; 4], &'a [u8]) -> Result<()>,
+) -> Result<()> {
+    while c.data.len() >= 12 {
+        // the data of some writers is padded to 4 bytes without the length saying so
+        if !matches!(&c.data[..4], b"8BIM" | b"8B64") {
+            match (1..4).find(|&i| matches!(c.data.get(i..i + 4), Some(b"8BIM" | b"8B64"))) {
+                Some(padding) => c.skip(padding as u64)?,
+                None => break,
+            }
+        }
+        c.skip(4)?;
+        let key: [u8; 4] = c.take(4)?.try_into()?;
+        let len = if psb && LONG_BLOCKS.contains(&&key) {
+            c.u64()?
+        } else {
+            c.u32()?.into()
+        };
+        let data = c.take(usize::try_from(len)?)?;
+        f(&key, data)?;
+    }
+    Ok(())
+}
+
+/// the name, text and group type of a layer from the extra data of its record
+fn layer(extra: &[u8], psb: bool) -> Result<Layer> {
+    let mut c = Cursor { data: extra };
+    // layer mask and blending ranges
+    let mask = c.u32()?;
+    c.skip(mask.into())?;
+    let ranges = c.u32()?;
+    c.skip(ranges.into())?;
+    // pascal string padded to a multiple of 4 bytes
+    let len = c.u8()? as usize;
+    let mut layer = Layer {
+        name: String::from_utf8_lossy(c.take(len)?).into_owned(),
+        ..Default::default()
+    };
+    c.skip(((4 - (len + 1) % 4) % 4) as u64)?;
+    tagged_blocks(&mut c, psb, |key, data| {
+        match key {
+            b"luni" => layer.name = Cursor { data }.unicode()?,
+            b"TySh" => layer.text = type_tool_text(data).unwrap_or(None),
+            b"lsct" | b"lsdk" => layer.section = Cursor { data }.u32()?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+    Ok(layer)
+}
+
+/// reads the document from its start, counting the bytes read
+struct Reader<R> {
+    inp: R,
+    psb: bool,
+    pos: u64,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    async fn bytes(&mut self, n: u64) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        (&mut self.inp).take(n).read_to_end(&mut buf).await?;
+        ensure!(buf.len() as u64 == n, "truncated photoshop document");
+        self.pos += n;
+        Ok(buf)
+    }
+
+    async fn u16(&mut self) -> Result<u16> {
+        self.pos += 2;
+        Ok(self.inp.read_u16().await?)
+    }
+
+    async fn u32(&mut self) -> Result<u32> {
+        self.pos += 4;
+        Ok(self.inp.read_u32().await?)
+    }
+
+    /// a length that is 64 bit in psb files
+    async fn length(&mut self) -> Result<u64> {
+        if self.psb {
+            self.pos += 8;
+            Ok(self.inp.read_u64().await?)
+        } else {
+            Ok(self.u32().await?.into())
+        }
+    }
+
+    async fn skip(&mut self, n: u64) -> Result<()> {
+        let skipped = tokio::io::copy(&mut (&mut self.inp).take(n), &mut tokio::io::sink()).await?;
+        ensure!(skipped == n, "truncated photoshop document");
+        self.pos += n;
+        Ok(())
+    }
+
+    /// the layer records of the layer info section of `len` bytes, skipping the image data after them
+    async fn layer_info(&mut self, len: u64) -> Result<Vec<Layer>> {
+        let end = self.pos + len;
+        let mut layers = vec![];
+        if len > 0 {
+            // negative if the first alpha channel is the transparency of the merged image
+            let count = (self.u16().await? as i16).unsigned_abs();
+            for _ in 0..count {
+                // bounds
+                self.skip(16).await?;
+                let channels = self.u16().await?;
+                // channel ids and the length of their data
+                self.skip(u64::from(channels) * if self.psb { 10 } else { 6 })
+                    .await?;
+                // blend mode signature and key, opacity, clipping, flags and filler
+                self.skip(12).await?;
+                let extra = self.u32().await?;
+                let extra = self.bytes(extra.into()).await?;
+                layers.push(layer(&extra, self.psb)?);
+            }
+        }
+        self.skip(end.saturating_sub(self.pos)).await?;
+        Ok(layers)
+    }
+}
+
+/// the lines of the layers, from top to bottom, after reading the document from its start
+async fn layers(inp: impl AsyncRead + Unpin) -> Result<Vec<String>> {
+    let mut r = Reader {
+        inp,
+        psb: false,
+        pos: 0,
+    };
+    let header = r.bytes(26).await?;
+    ensure!(header.starts_with(SIGNATURE), "not a photoshop document");
+    r.psb = header[4..6] == [0, 2];
+    // color mode data and image resources
+    let color_mode = r.u32().await?;
+    r.skip(color_mode.into()).await?;
+    let resources = r.u32().await?;
+    r.skip(resources.into()).await?;
+
+    let len = r.length().await?;
+    let end = r.pos + len;
+    let mut layers = vec![];
+    if len > 0 {
+        let info = r.length().await?;
+        layers = r.layer_info(info).await?;
+        let global_mask = r.u32().await?;
+        r.skip(global_mask.into()).await?;
+        // 16 and 32 bit documents have their layers in a block after the (empty) layer info
+        while layers.is_empty() && r.pos + 12 <= end {
+            let signature = r.bytes(8).await?;
+            let key: [u8; 4] = signature[4..].try_into()?;
+            let len = if r.psb && LONG_BLOCKS.contains(&&key) {
+                r.length().await?
+            } else {
+                r.u32().await?.into()
+            };
+            if matches!(&key, b"Lr16" | b"Lr32" | b"Layr") {
+                layers = r.layer_info(len).await?;
+            } else {
+                r.skip(len).await?;
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    let mut groups: Vec<String> = vec![];
+    for layer in layers.into_iter().rev() {
+        if layer.section == SECTION_END {
+            groups.pop();
+            continue;
+        }
+        let path = groups
+            .iter()
+            .chain([&layer.name])
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("/");
+        lines.push(format!("layer: {path}"));
+        for line in layer
+            .text
+            .iter()
+            .flat_map(|t| t.split(['\r', '\n', '\u{3}']))
+        {
+            let line = line.trim();
+            if !line.is_empty() {
+                lines.push(format!("text: {line}"));
+            }
+        }
+        if matches!(layer.section, SECTION_OPEN | SECTION_CLOSED) {
+            groups.push(layer.name);
+        }
+    }
+    Ok(lines)
+}
+
+#[async_trait]
+impl WritingFileAdapter for PsdAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        for line in layers(inp).await? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn unicode(s: &str) -> Vec<u8> {
+        let units: Vec<u16> = s.encode_utf16().chain([0]).collect();
+        [
+            (units.len() as u32).to_be_bytes().to_vec(),
+            units.iter().flat_map(|u| u.to_be_bytes()).collect(),
+        ]
+        .concat()
+    }
+
+    fn block(key: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        [b"8BIM", key, &(data.len() as u32).to_be_bytes()[..], data].concat()
+    }
+
+    fn record(name: &str, blocks: &[Vec<u8>]) -> Vec<u8> {
+        let mut pascal = vec![name.len() as u8];
+        pascal.extend(name.as_bytes());
+        pascal.resize(pascal.len().div_ceil(4) * 4, 0);
+        let extra = [&[0; 8][..], &pascal, &blocks.concat()].concat();
+        [
+            &[0; 16][..],
+            // one channel with 2 bytes of data
+            &1u16.to_be_bytes(),
+            &[0, 0, 0, 0, 0, 2],
+            b"8BIMnorm",
+            &[255, 0, 0, 0],
+            &(extra.len() as u32).to_be_bytes(),
+            &extra,
+        ]
+        .concat()
+    }
+
+    fn type_tool(text: &str) -> Vec<u8> {
+        [
+            &[0, 1][..],
+            &[0; 48],
+            &[0, 50, 0, 0, 0, 16],
+            &unicode(""),
+            &[0, 0, 0, 0],
+            b"TxLr",
+            &2u32.to_be_bytes(),
+            &[0, 0, 0, 0],
+            b"Txt TEXT",
+            &unicode(text),
+            &[0, 0, 0, 0],
+            b"Ornt",
+            b"enum",
+            &[0, 0, 0, 0],
+            b"Ornt",
+            &[0, 0, 0, 0],
+            b"Hrzn",
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn text_layers_in_groups() -> Result<()> {
+        // bottom to top, a group's end marker before its layers
+        let records = [
+            record("Background", &[]),
+            record(
+                "</Layer group>",
+                &[block(b"lsct", &SECTION_END.to_be_bytes())],
+            ),
+            record(
+                "Summer sale",
+                &[
+                    block(b"luni", &unicode("Summer sale – 50%")),
+                    block(b"TySh", &type_tool("Summer sale\r50% off\u{3}everything")),
+                ],
+            ),
+            record("Hero", &[block(b"lsct", &SECTION_OPEN.to_be_bytes())]),
+        ];
+        let info = [
+            &(records.len() as u16).to_be_bytes()[..],
+            &records.concat(),
+            // channel data of the 4 layers
+            &[0; 8],
+        ]
+        .concat();
+        let layer_and_mask =
+            [&(info.len() as u32).to_be_bytes()[..], &info, &[0, 0, 0, 0]].concat();
+        let psd = [
+            SIGNATURE,
+            &[
+                0, 1, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 1, 0, 8, 0, 3,
+            ],
+            &[0, 0, 0, 0, 0, 0, 0, 0],
+            &(layer_and_mask.len() as u32).to_be_bytes(),
+            &layer_and_mask,
+            // merged image
+            &[0, 0, 1, 2, 3],
+        ]
+        .concat();
+        assert_eq!(
+            layers(&psd[..]).await?,
+            [
+                "layer: Hero",
+                "layer: Hero/Summer sale – 50%",
+                "text: Summer sale",
+                "text: 50% off",
+                "text: everything",
+                "layer: Background",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use super::custom::map_exe_error;
+use super::*;
+use crate::vfs::{Vfs, VfsEntry, adapt_vfs};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["rar"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rar".to_owned(),
+        version: 1,
+        description: "Uses unrar to list the files in rar archives and recurses into them".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..AdapterCapabilities::runs(&["unrar"])
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.rar".to_owned()),
+            FileMatcher::MimeType("application/x-rar-compressed".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RarAdapter;
+
+impl RarAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for RarAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the regular files of a rar archive on the local file system, read through the unrar binary
+pub struct RarFs {
+    path: PathBuf,
+}
+
+impl RarFs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn unrar(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new("unrar");
+        // -p-: never ask for a password, rga has no terminal to ask on
+        cmd.args(args)
+            .args(["-p-", "-y", "--"])
+            .arg(&self.path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        cmd
+    }
+}
+
+fn spawn_fail(e: std::io::Error) -> Error {
+    map_exe_error(e, "unrar", "Install unrar to search rar archives.")
+}
+
+/// parse the technical listing (`unrar lt`): blocks of `Key: value` lines starting with `Name:`
+fn parse_listing(out: &str) -> Vec<VfsEntry> {
+    let mut entries = vec![];
+    // the entry being parsed and whether it is a regular file
+    let mut current: Option<(VfsEntry, bool)> = None;
+    let finish = |current: Option<(VfsEntry, bool)>, entries: &mut Vec<VfsEntry>| {
+        if let Some((mut entry, true)) = current {
+            entry.version = format!("{} {}", entry.version, entry.size);
+            entries.push(entry);
+        }
+    };
+    for line in out.lines() {
+        let Some((key, value)) = line.trim().split_once(": ") else {
+            continue;
+        };
+        match (key, current.as_mut()) {
+            ("Name", _) => {
+                finish(current.take(), &mut entries);
+                let entry = VfsEntry {
+                    path: value.into(),
+                    size: 0,
+                    mtime_unix: None,
+                    version: String::new(),
+                };
+                current = Some((entry, false));
+            }
+            ("Type", Some((_, is_file))) => *is_file = value == "File",
+            ("Size", Some((entry, _))) => entry.size = value.parse().unwrap_or(0),
+            ("mtime", Some((entry, _))) => entry.version = value.to_string(),
+            _ => {}
+        }
+    }
+    finish(current, &mut entries);
+    entries
+}
+
+#[async_trait]
+impl Vfs for RarFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let out = crate::audit::output(&mut self.unrar(&["lt"]))
+            .await
+            .map_err(spawn_fail)?;
+        if !out.status.success() {
+            return Err(format_err!(
+                "unrar lt failed: {:?}\n{}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr)
+            ));
+        }
+        Ok(parse_listing(&String::from_utf8_lossy(&out.stdout)))
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        _offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        // -inul: no banner or progress in the printed file
+        let (mut child, spawned) =
+            crate::audit::spawn(self.unrar(&["p", "-inul"]).arg(&entry.path))
+                .map_err(spawn_fail)?;
+        let mut stdout = child.stdout.take().context("unrar stdout not piped")?;
+        tokio::io::copy(&mut stdout, oup).await?;
+        let status = child.wait().await?;
+        spawned.exited(&status);
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut e) = child.stderr.take() {
+                let _ = e.read_to_string(&mut stderr).await;
+            }
+            return Err(format_err!(
+                "unrar p {} failed: {:?}\n{}",
+                entry.path.display(),
+                status,
+                stderr
+            ));
+        }
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl FileAdapter for RarAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (_inp, container) = ai.into_parts();
+        let vfs = Arc::new(RarFs::new(&container.filepath_hint));
+        adapt_vfs(vfs, container).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn listing() {
+        let out = "
+UNRAR 6.21 freeware      Copyright (c) 1993-2023 Alexander Roshal
+
+Archive: docs.rar
+Details: RAR 5
+
+        Name: docs/report.pdf
+        Type: File
+        Size: 48213
+ Packed size: 40110
+       mtime: 2024-03-01 10:20:30,000000000
+  Attributes: -rw-r--r--
+
+        Name: docs
+        Type: Directory
+       mtime: 2024-03-01 10:20:30,000000000
+
+        Name: notes.txt
+        Type: File
+        Size: 12
+       mtime: 2024-03-02 08:00:00,000000000
+";
+        assert_eq!(
+            parse_listing(out),
+            vec![
+                VfsEntry {
+                    path: "docs/report.pdf".into(),
+                    size: 48213,
+                    mtime_unix: None,
+                    version: "2024-03-01 10:20:30,000000000 48213".to_string(),
+                },
+                VfsEntry {
+                    path: "notes.txt".into(),
+                    size: 12,
+                    mtime_unix: None,
+                    version: "2024-03-02 08:00:00,000000000 12".to_string(),
+                },
+            ]
+        );
+    }
+}
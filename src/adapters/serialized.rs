@@ -0,0 +1,267 @@
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde_json::{Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["msgpack", "mpk", "cbor", "bson"];
+static MIME_TYPES: &[&str] = &[
+    "application/msgpack",
+    "application/cbor",
+    "application/bson",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "serialized".to_owned(),
+        version: 1,
+        description:
+            "Pretty-prints MessagePack, CBOR and BSON data (including concatenated dumps) as JSON"
+                .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIME_TYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SerializedAdapter;
+
+impl SerializedAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for SerializedAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    MsgPack,
+    Cbor,
+    Bson,
+}
+
+impl Format {
+    fn from_matcher(m: &FileMatcher) -> Option<Self> {
+        let name = match m {
+            FileMatcher::MimeType(mime) => mime.strip_prefix("application/")?,
+            FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) => ext.as_str(),
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "msgpack" | "mpk" => Some(Self::MsgPack),
+            "cbor" => Some(Self::Cbor),
+            "bson" => Some(Self::Bson),
+            _ => None,
+        }
+    }
+}
+
+/// none of the formats has a real magic number, so these are heuristics on the start of the data.
+/// only used with --rga-accurate, if `infer` did not recognize the file.
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    // self-described CBOR tag 55799
+    if buf.starts_with(&[0xd9, 0xd9, 0xf7]) {
+        return Some("application/cbor");
+    }
+    // bson: little endian document length, then an element of a known type with a nul-terminated key
+    if buf.len() >= 7 {
+        let len = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let known_type = matches!(buf[4], 0x01..=0x13 | 0x7f | 0xff);
+        let key_end = buf[5..].iter().take(256).position(|b| *b == 0);
+        let terminated = buf.get(len.wrapping_sub(1)).is_none_or(|b| *b == 0);
+        if (7..16 * 1024 * 1024).contains(&len) && known_type && key_end.is_some() && terminated {
+            return Some("application/bson");
+        }
+    }
+    // msgpack: a map (fixmap, map16, map32) whose first key is a string
+    let key = match buf.first()? {
+        0x81..=0x8f => buf.get(1),
+        0xde => buf.get(3),
+        0xdf => buf.get(5),
+        _ => None,
+    };
+    if matches!(key, Some(0xa1..=0xbf | 0xd9)) {
+        return Some("application/msgpack");
+    }
+    None
+}
+
+fn lossy(b: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(b).into_owned())
+}
+
+/// JSON object keys have to be strings
+fn key_string(v: Value) -> String {
+    match v {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+fn msgpack_to_json(v: rmpv::Value) -> Value {
+    use rmpv::Value as M;
+    match v {
+        M::Nil => Value::Null,
+        M::Boolean(b) => Value::Bool(b),
+        M::Integer(i) => i
+            .as_i64()
+            .map(Value::from)
+            .or_else(|| i.as_u64().map(Value::from))
+            .unwrap_or(Value::Null),
+        M::F32(f) => Value::from(f as f64),
+        M::F64(f) => Value::from(f),
+        M::String(s) => lossy(s.as_bytes()),
+        M::Binary(b) => lossy(&b),
+        M::Array(a) => Value::Array(a.into_iter().map(msgpack_to_json).collect()),
+        M::Map(m) => Value::Object(
+            m.into_iter()
+                .map(|(k, v)| (key_string(msgpack_to_json(k)), msgpack_to_json(v)))
+                .collect::<Map<_, _>>(),
+        ),
+        M::Ext(typ, data) => serde_json::json!({ "ext": typ, "data": lossy(&data) }),
+    }
+}
+
+fn cbor_to_json(v: ciborium::Value) -> Value {
+    use ciborium::Value as C;
+    match v {
+        C::Null => Value::Null,
+        C::Bool(b) => Value::Bool(b),
+        C::Integer(i) => {
+            let i = i128::from(i);
+            i64::try_from(i)
+                .map(Value::from)
+                .or_else(|_| u64::try_from(i).map(Value::from))
+                .unwrap_or_else(|_| Value::String(i.to_string()))
+        }
+        C::Float(f) => Value::from(f),
+        C::Text(s) => Value::String(s),
+        C::Bytes(b) => lossy(&b),
+        C::Array(a) => Value::Array(a.into_iter().map(cbor_to_json).collect()),
+        C::Map(m) => Value::Object(
+            m.into_iter()
+                .map(|(k, v)| (key_string(cbor_to_json(k)), cbor_to_json(v)))
+                .collect::<Map<_, _>>(),
+        ),
+        // the self-describe tag carries no information
+        C::Tag(55799, inner) => cbor_to_json(*inner),
+        C::Tag(tag, inner) => serde_json::json!({ "tag": tag, "value": cbor_to_json(*inner) }),
+        _ => Value::Null,
+    }
+}
+
+fn write_value(v: &Value, line_prefix: &str, s: &mut impl Write) -> Result<()> {
+    for line in serde_json::to_string_pretty(v)?.lines() {
+        writeln!(s, "{line_prefix}{line}")?;
+    }
+    Ok(())
+}
+
+/// dump every value in the (possibly concatenated) input
+fn synchronous_dump(
+    format: Format,
+    inp: impl Read,
+    line_prefix: &str,
+    mut s: impl Write,
+) -> Result<()> {
+    let mut r = BufReader::new(inp);
+    while !r.fill_buf()?.is_empty() {
+        let value = match format {
+            Format::MsgPack => msgpack_to_json(rmpv::decode::read_value(&mut r)?),
+            Format::Cbor => cbor_to_json(
+                ciborium::de::from_reader(&mut r).map_err(|e| format_err!("invalid cbor: {e}"))?,
+            ),
+            Format::Bson => {
+                bson::Bson::Document(bson::Document::from_reader(&mut r)?).into_relaxed_extjson()
+            }
+        };
+        write_value(&value, line_prefix, &mut s)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for SerializedAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let format = Format::from_matcher(detection_reason)
+            .with_context(|| format!("unknown serialization format for {detection_reason:?}"))?;
+        let inp_sync = SyncIoBridge::new(inp);
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump(format, inp_sync, &line_prefix, oup_sync)
+        })
+        .await?
+        .with_context(|| format!("in synchronous {format:?} task"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    async fn adapt(fname: &str, data: Vec<u8>) -> Result<String> {
+        let adapter: Box<dyn FileAdapter> = Box::<SerializedAdapter>::default();
+        let (a, d) = simple_adapt_info(&PathBuf::from(fname), Box::pin(std::io::Cursor::new(data)));
+        let res = adapter.adapt(a, &d).await?;
+        Ok(String::from_utf8(adapted_to_vec(res).await?)?)
+    }
+
+    #[tokio::test]
+    async fn concatenated_msgpack() -> Result<()> {
+        // {"a": 1} followed by ["x", true]
+        let data = vec![0x81, 0xa1, b'a', 0x01, 0x92, 0xa1, b'x', 0xc3];
+        assert_eq!(sniff_mime(&data), Some("application/msgpack"));
+        assert_eq!(
+            adapt("dump.msgpack", data).await?,
+            "PREFIX:{\nPREFIX:  \"a\": 1\nPREFIX:}\nPREFIX:[\nPREFIX:  \"x\",\nPREFIX:  true\nPREFIX:]\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bson_and_cbor() -> Result<()> {
+        let mut data = vec![];
+        bson::doc! { "n": 3, "user": "alice" }.to_writer(&mut data)?;
+        assert_eq!(sniff_mime(&data), Some("application/bson"));
+        assert_eq!(
+            adapt("users.bson", data).await?,
+            "PREFIX:{\nPREFIX:  \"n\": 3,\nPREFIX:  \"user\": \"alice\"\nPREFIX:}\n"
+        );
+
+        let mut data = vec![0xd9, 0xd9, 0xf7];
+        ciborium::ser::into_writer(&serde_json::json!({ "k": [1, "v"] }), &mut data)?;
+        assert_eq!(sniff_mime(&data), Some("application/cbor"));
+        assert_eq!(
+            adapt("x.cbor", data).await?,
+            "PREFIX:{\nPREFIX:  \"k\": [\nPREFIX:    1,\nPREFIX:    \"v\"\nPREFIX:  ]\nPREFIX:}\n"
+        );
+        Ok(())
+    }
+}
@@ -5,6 +5,7 @@ use lazy_static::lazy_static;
 use log::*;
 use rusqlite::types::ValueRef;
 use rusqlite::*;
+use std::path::Path;
 use std::{convert::TryInto, io::Write};
 use tokio::io::AsyncWrite;
 
@@ -15,11 +16,16 @@ static EXTENSIONS: &[&str] = &["db", "db3", "sqlite", "sqlite3"];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "sqlite".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Uses sqlite bindings to convert sqlite databases into a simple plain text format"
+            "Uses sqlite bindings to print every value of a sqlite database as `table.column row=N: value`, including uncheckpointed WAL content"
                 .to_owned(),
-        recurses: false, // set to true if we decide to make sqlite blobs searchable (gz blob in db is kinda common I think)
+        // set produces_subfiles if we decide to make sqlite blobs searchable (gz blob in db is kinda common I think)
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            sidecar_suffixes: vec!["-wal".to_owned()],
+            ..Default::default()
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -46,13 +52,12 @@ impl GetMetadata for SqliteAdapter {
     }
 }
 
-fn format_blob(b: ValueRef) -> String {
-    use ValueRef;
+fn format_value(b: ValueRef) -> String {
     match b {
         ValueRef::Null => "NULL".to_owned(),
         ValueRef::Integer(i) => format!("{}", i),
         ValueRef::Real(i) => format!("{}", i),
-        ValueRef::Text(i) => format!("'{}'", String::from_utf8_lossy(i).replace('\'', "''")),
+        ValueRef::Text(i) => String::from_utf8_lossy(i).into_owned(),
         ValueRef::Blob(b) => format!(
             "[blob {}B]",
             size_format::SizeFormatterSI::new(
@@ -63,53 +68,116 @@ fn format_blob(b: ValueRef) -> String {
     }
 }
 
+/// `--rga-sqlite-include` / `--rga-sqlite-exclude` entries, each either `table` or `table.column`
+struct TableFilter<'a> {
+    include: Option<&'a [String]>,
+    exclude: &'a [String],
+}
+
+impl TableFilter<'_> {
+    fn table(&self, table: &str) -> bool {
+        let prefix = format!("{table}.");
+        let included = self
+            .include
+            .is_none_or(|inc| inc.iter().any(|e| e == table || e.starts_with(&prefix)));
+        included && !self.exclude.iter().any(|e| e == table)
+    }
+    fn column(&self, table: &str, column: &str) -> bool {
+        let full = format!("{table}.{column}");
+        let prefix = format!("{table}.");
+        let included = self.include.is_none_or(|inc| {
+            // a table without any columns listed means all columns
+            inc.iter().any(|e| e == &full)
+                || (inc.iter().any(|e| e == table) && !inc.iter().any(|e| e.starts_with(&prefix)))
+        });
+        included && !self.exclude.iter().any(|e| e == &full)
+    }
+}
+
+/// the `-wal` file next to a database. Its content is not in the main file until the next checkpoint
+fn wal_path(db: &Path) -> PathBuf {
+    let mut p = db.as_os_str().to_owned();
+    p.push("-wal");
+    PathBuf::from(p)
+}
+
+/// open the database read only. If it has a WAL file, both are copied to a temporary directory first,
+/// since replaying the WAL needs write access and must not touch the original
+fn open_db(path: &Path) -> Result<(Connection, Option<tempfile::TempDir>)> {
+    let wal = wal_path(path);
+    if !wal.exists() {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("opening sqlite connection to {}", path.display()))?;
+        return Ok((conn, None));
+    }
+    debug!("{} has a WAL file, reading a copy", path.display());
+    let dir = tempfile::tempdir()?;
+    let copy = dir.path().join("db.sqlite3");
+    std::fs::copy(path, &copy).with_context(|| format!("copying {}", path.display()))?;
+    std::fs::copy(&wal, wal_path(&copy)).with_context(|| format!("copying {}", wal.display()))?;
+    let conn = Connection::open(&copy)
+        .with_context(|| format!("opening sqlite connection to copy of {}", path.display()))?;
+    Ok((conn, Some(dir)))
+}
+
 fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
     let AdaptInfo {
         is_real_file,
         filepath_hint,
         line_prefix,
+        config,
         ..
     } = ai;
     if !is_real_file {
-        // db is in an archive
-        // todo: read to memory and then use that blob if size < max
+        // only happens if called directly, preproc writes dbs in archives to a temporary file (seekable_input)
         writeln!(s, "{line_prefix}[rga: skipping sqlite in archive]",)?;
         return Ok(());
     }
-    let inp_fname = filepath_hint;
-    let conn = Connection::open_with_flags(&inp_fname, OpenFlags::SQLITE_OPEN_READ_ONLY)
-        .with_context(|| format!("opening sqlite connection to {}", inp_fname.display()))?;
+    let filter = TableFilter {
+        include: config.sqlite_include.as_deref(),
+        exclude: config.sqlite_exclude.as_deref().unwrap_or_default(),
+    };
+    let (conn, _tempdir) = open_db(&filepath_hint)?;
     let tables: Vec<String> = conn
         .prepare("select name from sqlite_master where type='table'")
         .context("while preparing query")?
         .query_map([], |r| r.get::<_, String>(0))
         .context("while executing query")?
         .filter_map(|e| e.ok())
+        .filter(|t| filter.table(t))
         .collect();
-    debug!("db has {} tables", tables.len());
+    debug!("dumping {} tables", tables.len());
     for table in tables {
-        // can't use query param at that position
-        let mut sel = conn.prepare(&format!(
-            "select * from {}",
-            rusqlite::vtab::escape_double_quote(&table)
-        ))?;
-        let col_names: Vec<String> = sel
+        let quoted = rusqlite::vtab::escape_double_quote(&table);
+        // can't use query param at that position. Tables created WITHOUT ROWID are numbered by position instead
+        let (mut sel, has_rowid) =
+            match conn.prepare(&format!("select _rowid_, * from \"{quoted}\"")) {
+                std::result::Result::Ok(sel) => (sel, true),
+                Err(_) => (conn.prepare(&format!("select * from \"{quoted}\""))?, false),
+            };
+        let offset = usize::from(has_rowid);
+        let columns: Vec<(usize, String)> = sel
             .column_names()
             .into_iter()
-            .map(|e| e.to_owned())
+            .enumerate()
+            .skip(offset)
+            .filter(|(_, c)| filter.column(&table, c))
+            .map(|(i, c)| (i, c.to_owned()))
             .collect();
-        let mut z = sel.query([])?;
-        // writeln!(oup, "{}: {}", table, cols.join(", "))?;
-
-        // kind of shitty (lossy) output. maybe output real csv or something?
-        while let Some(row) = z.next()? {
-            let row_str = col_names
-                .iter()
-                .enumerate()
-                .map(|(i, e)| Ok(format!("{}={}", e, format_blob(row.get_ref(i)?))))
-                .collect::<Result<Vec<String>>>()?
-                .join(", ");
-            writeln!(s, "{line_prefix}{table}: {row_str}",)?;
+        if columns.is_empty() {
+            continue;
+        }
+        let mut rows = sel.query([])?;
+        let mut n: i64 = 0;
+        while let Some(row) = rows.next()? {
+            n += 1;
+            let rowid = if has_rowid { row.get::<_, i64>(0)? } else { n };
+            for (i, column) in &columns {
+                let value = format_value(row.get_ref(*i)?);
+                for line in value.lines().chain(value.is_empty().then_some("")) {
+                    writeln!(s, "{line_prefix}{table}.{column} row={rowid}: {line}")?;
+                }
+            }
         }
     }
     Ok(())
@@ -151,9 +219,66 @@ mod test {
 
         assert_eq!(
             String::from_utf8(buf)?,
-            "PREFIX:tbl: greeting='hello', from='sqlite database!'\nPREFIX:tbl2: x=123, y=456.789\n",
+            "PREFIX:tbl.greeting row=1: hello
+PREFIX:tbl.from row=1: sqlite database!
+PREFIX:tbl2.x row=1: 123
+PREFIX:tbl2.y row=1: 456.789
+",
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_archive() -> Result<()> {
+        // not a real file, so preproc has to write it to a temporary file first (seekable_input)
+        let adapter = SqliteAdapter::new();
+        let fname = test_data_dir().join("hello.sqlite3");
+        let data = tokio::fs::read(&fname).await?;
+        let (a, d) = simple_adapt_info(&fname, Box::pin(std::io::Cursor::new(data)));
+        let res = crate::preproc::loop_adapt(&adapter, d, a, get_all_adapters(None).0).await?;
+
+        let out = String::from_utf8(adapted_to_vec(res).await?)?;
+
+        assert!(out.contains("tbl.greeting row=1: hello\n"));
+        assert!(!out.contains("skipping sqlite in archive"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wal_and_filter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("app.db");
+        let conn = Connection::open(&fname)?;
+        conn.pragma_update(None, "journal_mode", "wal")?;
+        conn.pragma_update(None, "wal_autocheckpoint", 0)?;
+        conn.execute_batch(
+            "create table users(name text, avatar blob);
+             create table log(msg text primary key) without rowid;
+             insert into users values ('alice', x'00ff'), ('bob', null);
+             insert into log values ('started');
+             delete from users where name = 'alice';",
+        )?;
+        // conn stays open, so the rows are only in the WAL
+        assert!(wal_path(&fname).exists());
+
+        let adapter: Box<dyn FileAdapter> = Box::<SqliteAdapter>::default();
+        let (mut a, d) = simple_fs_adapt_info(&fname).await?;
+        a.config.sqlite_exclude = Some(vec!["users.avatar".to_string()]);
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:users.name row=2: bob\nPREFIX:log.msg row=1: started\n"
         );
 
+        let filter = TableFilter {
+            include: Some(&["users".to_string(), "log.msg".to_string()]),
+            exclude: &[],
+        };
+        assert!(filter.table("log") && filter.column("log", "msg"));
+        assert!(filter.column("users", "avatar"));
+        assert!(!filter.table("other") && !filter.column("log", "other"));
+        drop(conn);
         Ok(())
     }
 }
@@ -0,0 +1,249 @@
+//! SVG images: the text of `<text>` elements (with their `<tspan>`s joined into one line) and of
+//! Inkscape's flowed text, the `<title>` and `<desc>` labels and the Dublin Core metadata, without
+//! the markup around them. Styles, scripts and path data are left out.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use quick_xml::events::{BytesStart, Event};
+use std::io::Read;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["svg", "svgz"];
+
+const MIME: &str = "image/svg+xml";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "svg".to_owned(),
+        version: 1,
+        description: "Extracts the text, titles, descriptions and metadata of SVG images"
+            .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(MIME.to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SvgAdapter;
+
+impl SvgAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for SvgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// SVG is XML without a magic number, its root element is `svg`. Only used with --rga-accurate.
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    let (local, _) = xml_root(buf)?;
+    (local == b"svg").then_some(MIME)
+}
+
+/// elements whose text becomes one line
+fn is_line(name: &str) -> bool {
+    matches!(name, "text" | "flowPara")
+}
+
+/// a tspan that is positioned on its own starts a new line of the text in the image
+fn is_positioned(e: &BytesStart) -> bool {
+    e.attributes()
+        .with_checks(false)
+        .flatten()
+        .any(|a| matches!(a.key.as_ref(), b"x" | b"y" | b"dy" | b"sodipodi:role"))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// the lines of an image in document order
+fn convert(xml: &str) -> Result<Vec<String>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().check_end_names = false;
+    let mut lines = vec![];
+    // local names, and the qualified names to find the Dublin Core element of metadata
+    let mut stack: Vec<(String, String)> = vec![];
+    // the element the current line or label is collected for, by depth
+    let mut current: Option<(usize, String, String)> = None;
+    let mut skipped = 0;
+    loop {
+        let event = reader.read_event()?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+                let qname = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                if let Some((_, _, text)) = &mut current
+                    && name == "tspan"
+                    && is_positioned(e)
+                {
+                    text.push(' ');
+                }
+                if matches!(event, Event::Empty(_)) {
+                    continue;
+                }
+                if matches!(name.as_str(), "style" | "script") {
+                    skipped += 1;
+                }
+                if current.is_none()
+                    && (is_line(&name) || matches!(name.as_str(), "title" | "desc"))
+                {
+                    current = Some((stack.len(), name.clone(), String::new()));
+                }
+                stack.push((name, qname));
+            }
+            Event::Text(t) => {
+                if skipped > 0 {
+                    continue;
+                }
+                let t = t
+                    .unescape()
+                    .map(|t| t.into_owned())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(t).into_owned());
+                if let Some((_, _, text)) = &mut current {
+                    text.push_str(&t);
+                } else if stack.iter().any(|(name, _)| name == "metadata") {
+                    let element = stack.iter().rev().find_map(|(_, q)| q.strip_prefix("dc:"));
+                    let value = collapse_whitespace(&t);
+                    if let Some(element) = element
+                        && !value.is_empty()
+                    {
+                        lines.push(format!("{element}: {value}"));
+                    }
+                }
+            }
+            Event::CData(t) => {
+                if let Some((_, _, text)) = &mut current
+                    && skipped == 0
+                {
+                    text.push_str(&String::from_utf8_lossy(t));
+                }
+            }
+            Event::End(_) => {
+                let Some((name, _)) = stack.pop() else {
+                    continue;
+                };
+                if matches!(name.as_str(), "style" | "script") {
+                    skipped -= 1;
+                }
+                if current
+                    .as_ref()
+                    .is_some_and(|(depth, ..)| *depth == stack.len())
+                {
+                    let (_, name, text) = current.take().expect("checked above");
+                    let text = collapse_whitespace(&text);
+                    if text.is_empty() {
+                        continue;
+                    }
+                    lines.push(if is_line(&name) {
+                        text
+                    } else {
+                        format!("{name}: {text}")
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(lines)
+}
+
+#[async_trait]
+impl WritingFileAdapter for SvgAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        if data.starts_with(b"\x1f\x8b") {
+            // svgz
+            let mut xml = vec![];
+            flate2::read::GzDecoder::new(&data[..])
+                .read_to_end(&mut xml)
+                .context("decompressing svgz")?;
+            data = xml;
+        }
+        let xml = String::from_utf8_lossy(&data);
+        for line in convert(xml.trim_start_matches('\u{feff}'))? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use std::path::Path;
+
+    const SVG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:dc="http://purl.org/dc/elements/1.1/"
+             xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" width="200" height="100">
+          <title>Quarterly revenue</title>
+          <desc>Bar chart of the revenue &amp; costs per quarter</desc>
+          <metadata><rdf:RDF><rdf:Description>
+            <dc:creator><rdf:Seq><rdf:li>Jane Doe</rdf:li></rdf:Seq></dc:creator>
+          </rdf:Description></rdf:RDF></metadata>
+          <style>.label { font: 12px sans-serif }</style>
+          <g><title>Q1 bar</title><rect width="10" height="40"/></g>
+          <text x="10" y="20">Re<tspan font-weight="bold">venue</tspan> 2023</text>
+          <text x="10" y="40"><tspan x="10" y="40">First line</tspan><tspan x="10" y="55">second
+            line</tspan></text>
+          <path d="M 0 0 L 10 10"/>
+        </svg>"#;
+
+    #[test]
+    fn text_and_labels() -> Result<()> {
+        assert_eq!(sniff_mime(SVG.as_bytes()), Some(MIME));
+        assert_eq!(
+            convert(SVG)?,
+            [
+                "title: Quarterly revenue",
+                "desc: Bar chart of the revenue & costs per quarter",
+                "creator: Jane Doe",
+                "title: Q1 bar",
+                "Revenue 2023",
+                "First line second line",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn svgz() -> Result<()> {
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gz.write_all(SVG.as_bytes())?;
+        let (a, d) = simple_adapt_info(
+            Path::new("chart.svgz"),
+            Box::pin(std::io::Cursor::new(gz.finish()?)),
+        );
+        let output = SvgAdapter.adapt(a, &d).await?;
+        let oup = adapted_to_vec(output).await?;
+        assert!(String::from_utf8(oup)?.contains("PREFIX:Revenue 2023\n"));
+        Ok(())
+    }
+}
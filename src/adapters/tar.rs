@@ -1,6 +1,8 @@
+mod salvage;
+
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
-    adapters::AdapterMeta,
+    adapters::{AdapterCapabilities, AdapterMeta},
     matching::{FastFileMatcher, FileMatcher},
     print_bytes,
 };
@@ -14,6 +16,8 @@ use std::path::PathBuf;
 use tokio_stream::StreamExt;
 
 use super::{AdaptInfo, FileAdapter, GetMetadata};
+use crate::vfs::{Vfs, VfsEntry};
+use tokio::io::AsyncWrite;
 
 static EXTENSIONS: &[&str] = &["tar"];
 
@@ -22,7 +26,10 @@ lazy_static! {
         name: "tar".to_owned(),
         version: 1,
         description: "Reads a tar file as a stream and recurses down into its contents".to_owned(),
-        recurses: true,
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -53,15 +60,10 @@ impl FileAdapter for TarAdapter {
         ai: AdaptInfo,
         _detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let AdaptInfo {
-            filepath_hint,
-            inp,
-            line_prefix,
-            archive_recursion_depth,
-            config,
-            postprocess,
-            ..
-        } = ai;
+        let (inp, container) = ai.into_parts();
+        if container.config.salvage {
+            return Ok(salvage::members(inp, container));
+        }
         let mut archive = ::tokio_tar::Archive::new(inp);
 
         let mut entries = archive.entries()?;
@@ -72,21 +74,11 @@ impl FileAdapter for TarAdapter {
                     let path = PathBuf::from(file.path()?.to_owned());
                     debug!(
                         "{}|{}: {}",
-                        filepath_hint.display(),
+                        container.filepath_hint.display(),
                         path.display(),
                         print_bytes(file.header().size().unwrap_or(0) as f64),
                     );
-                    let line_prefix = &format!("{}{}: ", line_prefix, path.display());
-                    let ai2: AdaptInfo = AdaptInfo {
-                        filepath_hint: path,
-                        is_real_file: false,
-                        file_mtime_unix_ms: None,
-                        archive_recursion_depth: archive_recursion_depth + 1,
-                        inp: Box::pin(file),
-                        line_prefix: line_prefix.to_string(),
-                        config: config.clone(),
-                        postprocess,
-                    };
+                    let ai2 = container.member(path, Box::pin(file));
                     yield Ok(ai2);
                 }
             }
@@ -96,6 +88,71 @@ impl FileAdapter for TarAdapter {
     }
 }
 
+/// the regular files of a tar file on the local file system. Reading a member scans the archive up to it,
+/// so the adapter streams through the archive instead
+pub struct TarFs {
+    path: PathBuf,
+}
+
+impl TarFs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    async fn archive(&self) -> Result<::tokio_tar::Archive<tokio::io::BufReader<tokio::fs::File>>> {
+        let file = tokio::fs::File::open(&self.path)
+            .await
+            .with_context(|| format!("opening {}", self.path.display()))?;
+        Ok(::tokio_tar::Archive::new(tokio::io::BufReader::new(file)))
+    }
+}
+
+#[async_trait]
+impl Vfs for TarFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let mut archive = self.archive().await?;
+        let mut entries = archive.entries()?;
+        let mut out = vec![];
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let header = entry.header();
+            if header.entry_type() != tokio_tar::EntryType::Regular {
+                continue;
+            }
+            let mtime = header.mtime().ok().map(|m| m as i64);
+            out.push(VfsEntry {
+                path: entry.path()?.into_owned(),
+                size: header.size()?,
+                mtime_unix: mtime,
+                version: mtime.map(|m| m.to_string()).unwrap_or_default(),
+            });
+        }
+        Ok(out)
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        _offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let mut archive = self.archive().await?;
+        let mut entries = archive.entries()?;
+        while let Some(file) = entries.next().await {
+            let mut file = file?;
+            if file.path()? == entry.path {
+                tokio::io::copy(&mut file, oup).await?;
+                return Ok(());
+            }
+        }
+        Err(format_err!("{} not found in {}", entry.path.display(), self.path.display()))
+    }
+
+    fn supports_ranges(&self) -> bool {
+        false
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,4 +187,50 @@ PREFIX:dir/file-a.pdf: Page 1:
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn salvage() -> Result<()> {
+        let mut builder = tokio_tar::Builder::new(vec![]);
+        for (name, data) in [
+            ("a.txt", "first"),
+            ("b.txt", "second"),
+            ("c.txt", "third member"),
+        ] {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data.as_bytes()).await?;
+        }
+        let mut tar = builder.into_inner().await?;
+        // the header of b.txt is damaged and c.txt is cut off
+        tar[1024] = b'x';
+        tar.truncate(5 * 512 + 5);
+
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("damaged.tar"),
+            Box::pin(std::io::Cursor::new(tar)),
+        );
+        a.config.salvage = true;
+        let r = loop_adapt(&TarAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:a.txt: first\nPREFIX:c.txt: third\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tar_fs() -> Result<()> {
+        let vfs = TarFs::new(test_data_dir().join("hello.tar"));
+        let entries = vfs.list().await?;
+        assert_eq!(
+            entries.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(),
+            vec!["dir/file-b.pdf", "dir/file-a.pdf"]
+        );
+        let mut buf = vec![];
+        vfs.read_range(&entries[1], 0, &mut buf).await?;
+        assert_eq!(buf.len() as u64, entries[1].size);
+        assert!(buf.starts_with(b"%PDF"));
+        Ok(())
+    }
 }
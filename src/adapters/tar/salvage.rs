@@ -0,0 +1,186 @@
+//! Reading a damaged tar file block by block for `--rga-salvage`. Headers are always at block
+//! boundaries and carry a checksum, so a damaged header is skipped by trying the following blocks
+//! until one is a valid header again. The data of a truncated member ends where the input does.
+use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapters::{ContainerInfo, ReadBox};
+use anyhow::*;
+use async_stream::stream;
+use log::*;
+use tokio::io::AsyncReadExt;
+
+const BLOCK: usize = 512;
+/// the pipe a member is passed through
+const PIPE_LEN: usize = 64 * 1024;
+/// longer long names and pax headers are taken for damage
+const MAX_EXTENDED_HEADER: u64 = 1 << 20;
+
+/// the value of a numeric header field: octal digits, or big endian after a set high bit (GNU)
+fn number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            value = value.checked_mul(256)? | b as u64;
+        }
+        return Some(value);
+    }
+    let digits = std::str::from_utf8(field).ok()?;
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// whether the checksum of `block` matches, with the checksum field counted as spaces
+fn is_header(block: &[u8; BLOCK]) -> bool {
+    let Some(expected) = number(&block[148..156]) else {
+        return false;
+    };
+    let sum: u64 = block
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+        .sum();
+    sum == expected
+}
+
+/// a NUL terminated header field
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// the path of a header, with the ustar prefix
+fn header_path(block: &[u8; BLOCK]) -> String {
+    let name = text(&block[..100]);
+    if &block[257..262] == b"ustar" {
+        let prefix = text(&block[345..500]);
+        if !prefix.is_empty() {
+            return format!("{prefix}/{name}");
+        }
+    }
+    name
+}
+
+/// the `path` record of a pax extended header
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines().find_map(|record| {
+        let (_, record) = record.split_once(' ')?;
+        record.strip_prefix("path=").map(str::to_owned)
+    })
+}
+
+/// reads up to `buf.len()` bytes, fewer only at the end of the input
+async fn read_full(inp: &mut ReadBox, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = inp.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// the regular files of the tar stream `inp`, skipping damaged headers
+pub fn members(mut inp: ReadBox, container: ContainerInfo) -> AdaptedFilesIterBox {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<(String, ReadBox)>(1);
+    let archive = format!(
+        "{}{}",
+        container.line_prefix,
+        container.filepath_hint.display()
+    );
+    let reader = tokio::spawn(async move {
+        let mut block = [0; BLOCK];
+        // the path from a GNU long name or pax header, for the next member
+        let mut long_path = None;
+        // the offset of the first damaged block since the last header
+        let mut damaged_at = None;
+        let mut offset = 0u64;
+        loop {
+            let n = read_full(&mut inp, &mut block).await?;
+            if n < BLOCK {
+                if n > 0 {
+                    warn!("{archive}: truncated in a header, salvaged the members before");
+                }
+                break;
+            }
+            offset += BLOCK as u64;
+            if block.iter().all(|&b| b == 0) {
+                // the end of the archive, or padding
+                continue;
+            }
+            if !is_header(&block) {
+                damaged_at.get_or_insert(offset - BLOCK as u64);
+                continue;
+            }
+            if let Some(at) = damaged_at.take() {
+                warn!(
+                    "{archive}: damaged from offset {at} to {}, skipped to the next header",
+                    offset - BLOCK as u64
+                );
+            }
+            let size = number(&block[124..136]).unwrap_or(0);
+            let padded = size.div_ceil(BLOCK as u64) * BLOCK as u64;
+            let kind = block[156];
+            let path = long_path.take().unwrap_or_else(|| header_path(&block));
+            match kind {
+                // GNU long name, pax extended header
+                b'L' | b'x' if size <= MAX_EXTENDED_HEADER => {
+                    let mut data = vec![0; padded as usize];
+                    let n = read_full(&mut inp, &mut data).await?;
+                    offset += n as u64;
+                    let data = &data[..(size as usize).min(n)];
+                    long_path = if kind == b'L' {
+                        Some(text(data))
+                    } else {
+                        pax_path(data)
+                    };
+                }
+                b'0' | b'\0' | b'7' => {
+                    let (mut pipe, member) = tokio::io::duplex(PIPE_LEN);
+                    if tx.send((path.clone(), Box::pin(member))).await.is_err() {
+                        break;
+                    }
+                    let mut data = (&mut inp).take(size);
+                    // if the reader goes away, the rest of the member is skipped
+                    let _ = tokio::io::copy(&mut data, &mut pipe).await;
+                    drop(pipe);
+                    tokio::io::copy(&mut data, &mut tokio::io::sink()).await?;
+                    if data.limit() > 0 {
+                        let salvaged = size - data.limit();
+                        warn!(
+                            "{archive}: {path} is truncated, salvaged {salvaged} of {size} bytes"
+                        );
+                        break;
+                    }
+                    let padding = padded - size;
+                    tokio::io::copy(&mut (&mut inp).take(padding), &mut tokio::io::sink()).await?;
+                    offset += padded;
+                }
+                _ => {
+                    let skipped =
+                        tokio::io::copy(&mut (&mut inp).take(padded), &mut tokio::io::sink())
+                            .await?;
+                    offset += skipped;
+                }
+            }
+        }
+        if let Some(at) = damaged_at {
+            warn!("{archive}: damaged from offset {at} to the end");
+        }
+        Ok(())
+    });
+    Box::pin(stream! {
+        while let Some((path, member)) = rx.recv().await {
+            yield Ok(container.member(path, member));
+        }
+        match reader.await {
+            Result::Ok(Result::Ok(())) => {}
+            Result::Ok(Err(e)) => yield Err(e),
+            Err(e) => yield Err(e.into()),
+        }
+    })
+}
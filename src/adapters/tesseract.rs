@@ -0,0 +1,206 @@
+//! OCR of images with tesseract. Its TSV output has the confidence of each word, the lines are
+//! put together from the words and get the mean confidence of their words, see [`confidence`].
+//! Paragraphs are separated by an empty line like in tesseract's plain text output.
+use super::{
+    confidence,
+    custom::map_exe_error,
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "tiff", "bmp", "gif"];
+static MIMETYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "image/tiff",
+    "image/bmp",
+    "image/gif",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "tesseract".to_owned(),
+        version: 2,
+        description: "Uses tesseract to extract text from images".to_owned(),
+        capabilities: AdapterCapabilities::runs(&["tesseract"]),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIMETYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct TesseractAdapter;
+
+impl TesseractAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for TesseractAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a line of recognized text
+#[derive(Debug, PartialEq)]
+struct OcrLine {
+    text: String,
+    /// mean of the words
+    confidence: f32,
+    /// page, block and paragraph
+    paragraph: (u32, u32, u32),
+}
+
+/// the lines of tesseract's TSV output, whose rows are `level page_num block_num par_num line_num
+/// word_num left top width height conf text` with the words at level 5
+fn tsv_lines(tsv: &str) -> Vec<OcrLine> {
+    let mut lines: Vec<OcrLine> = vec![];
+    let mut current = None;
+    let mut confidences = vec![];
+    for row in tsv.lines().skip(1) {
+        let cols: Vec<&str> = row.splitn(12, '\t').collect();
+        let [level, page, block, par, line, _, _, _, _, _, conf, text] = cols[..] else {
+            continue;
+        };
+        let number = |s: &str| s.parse::<u32>().unwrap_or(0);
+        let conf = conf.parse::<f32>().unwrap_or(-1.0);
+        let text = text.trim();
+        if level != "5" || text.is_empty() || conf < 0.0 {
+            continue;
+        }
+        let key = (number(page), number(block), number(par), number(line));
+        if current != Some(key) {
+            if let Some(last) = lines.last_mut() {
+                last.confidence = confidences.iter().sum::<f32>() / confidences.len() as f32;
+            }
+            confidences.clear();
+            current = Some(key);
+            lines.push(OcrLine {
+                text: String::new(),
+                confidence: 0.0,
+                paragraph: (key.0, key.1, key.2),
+            });
+        }
+        let last = lines.last_mut().expect("pushed above");
+        if !last.text.is_empty() {
+            last.text.push(' ');
+        }
+        last.text += text;
+        confidences.push(conf);
+    }
+    if let Some(last) = lines.last_mut() {
+        last.confidence = confidences.iter().sum::<f32>() / confidences.len() as f32;
+    }
+    lines
+}
+
+#[async_trait]
+impl WritingFileAdapter for TesseractAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let (mut child, spawned) = crate::audit::spawn(
+            Command::new("tesseract")
+                .args(["stdin", "stdout", "tsv"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+        .map_err(|e| map_exe_error(e, "tesseract", "Make sure you have tesseract installed."))?;
+        let mut stdin = child.stdin.take().context("stdin not piped")?;
+        let writer = tokio::spawn(async move { tokio::io::copy(&mut inp, &mut stdin).await });
+        let mut tsv = String::new();
+        child
+            .stdout
+            .take()
+            .context("stdout not piped")?
+            .read_to_string(&mut tsv)
+            .await?;
+        let output = child.wait_with_output().await?;
+        spawned.exited(&output.status);
+        if !output.status.success() {
+            bail!(
+                "tesseract failed: {:?}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        writer.await??;
+        let mut last_paragraph = None;
+        for line in tsv_lines(&tsv) {
+            let Some(text) = confidence::line(&config, Some(line.confidence), &line.text) else {
+                continue;
+            };
+            if last_paragraph.is_some_and(|p| p != line.paragraph) {
+                async_writeln!(oup, "{line_prefix}")?;
+            }
+            last_paragraph = Some(line.paragraph);
+            async_writeln!(oup, "{line_prefix}{text}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lines_from_words() {
+        let tsv = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext
+1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t
+5\t1\t1\t1\t1\t1\t10\t10\t50\t20\t96.5\tInvoice
+5\t1\t1\t1\t1\t2\t70\t10\t40\t20\t91.5\t2024
+5\t1\t1\t1\t2\t1\t10\t40\t50\t20\t95\tDue
+5\t1\t2\t1\t1\t1\t10\t300\t50\t20\t12\t~:%;
+5\t1\t2\t1\t1\t2\t70\t300\t50\t20\t18\t.-
+";
+        assert_eq!(
+            tsv_lines(tsv),
+            [
+                OcrLine {
+                    text: "Invoice 2024".to_string(),
+                    confidence: 94.0,
+                    paragraph: (1, 1, 1)
+                },
+                OcrLine {
+                    text: "Due".to_string(),
+                    confidence: 95.0,
+                    paragraph: (1, 1, 1)
+                },
+                OcrLine {
+                    text: "~:%; .-".to_string(),
+                    confidence: 15.0,
+                    paragraph: (1, 2, 1)
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,459 @@
+//! Adapters compiled to WebAssembly and run in an embedded wasmtime runtime (with `--features wasm`).
+//!
+//! A module is a WASI (preview 1) command: it reads the file from stdin and writes text to stdout.
+//! It gets no preopened directories, no environment and no sockets, so all it can touch is the input
+//! stream, and its linear memory is capped. That makes modules safe to share like custom adapter configs.
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::Result;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Clone)]
+pub struct WasmAdapterConfig {
+    /// The unique identifier and name of this adapter.
+    ///
+    /// Must only include a-z, 0-9, _.
+    pub name: String,
+
+    /// The description of this adapter shown in help.
+    pub description: String,
+
+    /// If true, the adapter will be disabled by default.
+    pub disabled_by_default: Option<bool>,
+
+    /// Version identifier used to key cache entries.
+    ///
+    /// Change this if the module changes.
+    pub version: i32,
+
+    /// The file extensions this adapter supports, for example `["epub", "mobi"]`.
+    pub extensions: Vec<String>,
+
+    /// If not null and `--rga-accurate` is enabled, mimetype matching is used instead of file name matching.
+    pub mimetypes: Option<Vec<String>>,
+
+    /// Path to the WASI module (`.wasm`, or `.wat` text).
+    pub module: String,
+
+    /// The arguments passed to the module (after `argv[0]`, which is the adapter name).
+    /// Placeholders are the same as for custom adapters, e.g. `$input_virtual_path`.
+    pub args: Vec<String>,
+
+    /// Maximum size of the module's linear memory in MiB. Defaults to 1024.
+    pub max_memory_mb: Option<u64>,
+
+    /// Maximum fuel (about one unit per wasm instruction) the module may use for one file, so a module
+    /// stuck in a loop fails instead of hanging the search. Defaults to 100 billion.
+    pub max_fuel: Option<u64>,
+}
+
+impl WasmAdapterConfig {
+    pub fn to_adapter(&self) -> WasmAdapter {
+        WasmAdapter {
+            config: self.clone(),
+            meta: AdapterMeta {
+                name: self.name.clone(),
+                version: self.version,
+                description: format!(
+                    "{}\nRuns: {} {}",
+                    self.description,
+                    self.module,
+                    self.args.join(" ")
+                ),
+                capabilities: AdapterCapabilities::default(),
+                fast_matchers: self
+                    .extensions
+                    .iter()
+                    .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+                    .collect(),
+                slow_matchers: self.mimetypes.as_ref().map(|mimetypes| {
+                    mimetypes
+                        .iter()
+                        .map(|s| FileMatcher::MimeType(s.to_string()))
+                        .collect()
+                }),
+                keep_fast_matchers_if_accurate: true,
+                disabled_by_default: self.disabled_by_default.unwrap_or(false),
+            },
+        }
+    }
+}
+
+pub struct WasmAdapter {
+    config: WasmAdapterConfig,
+    meta: AdapterMeta,
+}
+
+impl GetMetadata for WasmAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &self.meta
+    }
+}
+
+#[async_trait]
+impl FileAdapter for WasmAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let args = self
+            .config
+            .args
+            .iter()
+            .map(|arg| super::custom::arg_replacer(arg, &filepath_hint, &config))
+            .collect::<Result<Vec<_>>>()?;
+        let output = runtime::run(&self.config, args, inp, &config)
+            .await
+            .with_context(|| format!("running wasm adapter {}", self.config.name))?;
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: output,
+            line_prefix,
+            is_real_file: false,
+            file_mtime_unix_ms: None,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+mod runtime {
+    use super::*;
+
+    pub async fn run(
+        adapter: &WasmAdapterConfig,
+        _args: Vec<String>,
+        _inp: ReadBox,
+        _config: &RgaConfig,
+    ) -> Result<ReadBox> {
+        Err(format_err!(
+            "{} is a wasm adapter, but rga was built without the wasm feature",
+            adapter.name
+        ))
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod runtime {
+    use super::*;
+    use crate::{join_handle_to_stream, to_io_err};
+    use log::debug;
+    use log::warn;
+    use once_cell::sync::Lazy;
+    use std::io::Write;
+    use std::path::Path;
+    use tokio::io::AsyncReadExt;
+    use wasmtime::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+    use wasmtime_wasi::pipe::{AsyncReadStream, AsyncWriteStream};
+    use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+    use wasmtime_wasi::{AsyncStdinStream, AsyncStdoutStream, I32Exit, WasiCtxBuilder};
+
+    /// default cap for `max_memory_mb`
+    const DEFAULT_MAX_MEMORY_MB: u64 = 1024;
+    /// default cap for `max_fuel`
+    const DEFAULT_MAX_FUEL: u64 = 100_000_000_000;
+    /// bytes a module may have in flight to stdout before its writes block
+    const WRITE_BUDGET: usize = 64 * 1024;
+    /// file in the config directory with the key that authenticates the compiled modules in the cache
+    const MODULE_KEY_FILE: &str = "wasm-cache.key";
+
+    static ENGINE: Lazy<Engine> = Lazy::new(|| {
+        let mut config = wasmtime::Config::new();
+        config.async_support(true);
+        config.consume_fuel(true);
+        Engine::new(&config).expect("default wasmtime config is valid")
+    });
+
+    /// the key for the compiled modules, created on first use. None if it can't be kept in the config directory
+    static MODULE_KEY: Lazy<Option<[u8; 32]>> = Lazy::new(|| {
+        let path = crate::project_dirs().ok()?.config_dir().join(MODULE_KEY_FILE);
+        match module_key(&path) {
+            std::result::Result::Ok(key) => Some(key),
+            Err(e) => {
+                debug!("not caching compiled wasm modules, no key in {}: {:#}", path.display(), e);
+                None
+            }
+        }
+    });
+
+    fn module_key(path: &Path) -> Result<[u8; 32]> {
+        let read = |path: &Path| -> Result<[u8; 32]> {
+            std::fs::read(path)?
+                .try_into()
+                .map_err(|_| format_err!("wrong key length"))
+        };
+        if path.exists() {
+            return read(path);
+        }
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key)?;
+        std::fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(path) {
+            std::result::Result::Ok(mut file) => {
+                file.write_all(&key)?;
+                Ok(key)
+            }
+            // another rga-preproc was faster
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => read(path),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    struct State {
+        wasi: WasiP1Ctx,
+        limits: StoreLimits,
+    }
+
+    /// compile the module, or load it from `<cache path>/wasm/` where compiled modules are kept
+    /// since rga-preproc runs once per file.
+    ///
+    /// Compiled modules are native code, so they are named by the blake3 of the module and only loaded if
+    /// their MAC (keyed with `key`, which is not kept in the cache directory) is right
+    fn load_module(path: &Path, config: &RgaConfig, key: Option<&[u8; 32]>) -> Result<Module> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(&bytes);
+        let dir = Path::new(&config.cache.path.0).join("wasm");
+        let compiled = dir.join(format!("{}.cwasm", hasher.finalize().to_hex()));
+        let key = key.filter(|_| !config.cache.disabled);
+        if let (Some(key), std::result::Result::Ok(stored)) = (key, std::fs::read(&compiled)) {
+            match stored.split_at_checked(blake3::OUT_LEN) {
+                Some((mac, artifact)) if blake3::keyed_hash(key, artifact) == *mac => {
+                    // safety: the artifact was written by `serialize` below, the MAC shows it wasn't changed
+                    // since. Engine mismatches are detected by wasmtime
+                    match unsafe { Module::deserialize(&ENGINE, artifact) } {
+                        std::result::Result::Ok(module) => return Ok(module),
+                        Err(e) => debug!("recompiling {}: {:#}", path.display(), e),
+                    }
+                }
+                _ => warn!("ignoring {}, it was not written by this rga", compiled.display()),
+            }
+        }
+        let module = Module::new(&ENGINE, &bytes)?;
+        if let Some(key) = key {
+            let artifact = module.serialize()?;
+            let written = std::fs::create_dir_all(&dir).and_then(|_| {
+                let mut tmp = tempfile::NamedTempFile::new_in(&dir)?;
+                tmp.write_all(blake3::keyed_hash(key, &artifact).as_bytes())?;
+                tmp.write_all(&artifact)?;
+                tmp.persist(&compiled)?;
+                std::result::Result::Ok(())
+            });
+            if let Err(e) = written {
+                debug!(
+                    "could not store compiled module {}: {}",
+                    compiled.display(),
+                    e
+                );
+            }
+        }
+        Ok(module)
+    }
+
+    pub async fn run(
+        adapter: &WasmAdapterConfig,
+        args: Vec<String>,
+        inp: ReadBox,
+        config: &RgaConfig,
+    ) -> Result<ReadBox> {
+        let module_path = PathBuf::from(&adapter.module);
+        let module = {
+            let config = config.clone();
+            tokio::task::spawn_blocking(move || {
+                load_module(&module_path, &config, MODULE_KEY.as_ref())
+            })
+            .await??
+        };
+        let (w, r) = tokio::io::duplex(128 * 1024);
+        let wasi = WasiCtxBuilder::new()
+            .arg(&adapter.name)
+            .args(&args)
+            .stdin(AsyncStdinStream::new(AsyncReadStream::new(inp)))
+            .stdout(AsyncStdoutStream::new(AsyncWriteStream::new(
+                WRITE_BUDGET,
+                w,
+            )))
+            .build_p1();
+        let max_memory =
+            adapter.max_memory_mb.unwrap_or(DEFAULT_MAX_MEMORY_MB) as usize * 1024 * 1024;
+        let limits = StoreLimitsBuilder::new().memory_size(max_memory).build();
+        let max_fuel = adapter.max_fuel.unwrap_or(DEFAULT_MAX_FUEL);
+        let name = adapter.name.clone();
+        let guest = async move {
+            let mut store = Store::new(&ENGINE, State { wasi, limits });
+            store.limiter(|s| &mut s.limits);
+            store.set_fuel(max_fuel).map_err(to_io_err)?;
+            let mut linker: Linker<State> = Linker::new(&ENGINE);
+            preview1::add_to_linker_async(&mut linker, |s| &mut s.wasi).map_err(to_io_err)?;
+            let result = async {
+                let instance = linker.instantiate_async(&mut store, &module).await?;
+                let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+                start.call_async(&mut store, ()).await
+            }
+            .await;
+            match result {
+                std::result::Result::Ok(()) => Ok(()),
+                Err(e) => match e.downcast_ref::<I32Exit>() {
+                    Some(I32Exit(0)) => Ok(()),
+                    Some(I32Exit(code)) => {
+                        Err(to_io_err(format_err!("{name} exited with code {code}")))
+                    }
+                    None if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                        Err(to_io_err(format_err!(
+                            "{name} used up its max_fuel of {max_fuel}, it may be stuck in a loop"
+                        )))
+                    }
+                    None => Err(to_io_err(e)),
+                },
+            }
+        };
+        // the guest runs on a thread of its own, so a module that computes for long doesn't block the
+        // workers of the runtime that stream its input and output
+        let joiner = tokio::task::spawn_blocking(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(guest)
+        });
+        Ok(Box::pin(r.chain(join_handle_to_stream(joiner))))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use crate::test_utils::*;
+        use pretty_assertions::assert_eq;
+
+        /// copies stdin to stdout, uppercasing ascii letters. Exits with 3 if it reads a '!'
+        const UPPER_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (func (export "_start")
+    (local $n i32) (local $i i32) (local $c i32)
+    (loop $read
+      ;; iovec at 0: buffer at 64, 1024 bytes. nread at 16
+      (i32.store (i32.const 0) (i32.const 64))
+      (i32.store (i32.const 4) (i32.const 1024))
+      (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+      (local.set $n (i32.load (i32.const 16)))
+      (if (i32.eqz (local.get $n)) (then (return)))
+      (local.set $i (i32.const 0))
+      (loop $upper
+        (local.set $c (i32.load8_u (i32.add (i32.const 64) (local.get $i))))
+        (if (i32.eq (local.get $c) (i32.const 33)) (then (call $proc_exit (i32.const 3))))
+        (if (i32.and (i32.ge_u (local.get $c) (i32.const 97)) (i32.le_u (local.get $c) (i32.const 122)))
+          (then (i32.store8 (i32.add (i32.const 64) (local.get $i)) (i32.sub (local.get $c) (i32.const 32)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br_if $upper (i32.lt_u (local.get $i) (local.get $n))))
+      (i32.store (i32.const 4) (local.get $n))
+      (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 16)))
+      (br $read)))
+)"#;
+
+        #[tokio::test]
+        async fn upper() -> Result<()> {
+            let dir = tempfile::tempdir()?;
+            let module = dir.path().join("upper.wat");
+            std::fs::write(&module, UPPER_WAT)?;
+            let adapter = WasmAdapterConfig {
+                name: "upper".to_string(),
+                extensions: vec!["up".to_string()],
+                module: module.to_string_lossy().into_owned(),
+                ..Default::default()
+            }
+            .to_adapter();
+
+            let (mut a, d) = simple_adapt_info(
+                &PathBuf::from("x.up"),
+                Box::pin(std::io::Cursor::new(b"hello wasm\n".to_vec())),
+            );
+            a.config.cache.path =
+                crate::config::CachePath(dir.path().to_string_lossy().into_owned());
+            let res = adapter.adapt(a, &d).await?;
+            assert_eq!(
+                String::from_utf8(adapted_to_vec(res).await?)?,
+                "HELLO WASM\n"
+            );
+            // compiled once, then loaded from the cache
+            assert_eq!(std::fs::read_dir(dir.path().join("wasm"))?.count(), 1);
+
+            let (mut a, d) = simple_adapt_info(
+                &PathBuf::from("x.up"),
+                Box::pin(std::io::Cursor::new(b"no!".to_vec())),
+            );
+            a.config.cache.path =
+                crate::config::CachePath(dir.path().to_string_lossy().into_owned());
+            let err = adapted_to_vec(adapter.adapt(a, &d).await?)
+                .await
+                .unwrap_err();
+            assert!(format!("{err:?}").contains("upper exited with code 3"));
+            Ok(())
+        }
+
+        #[test]
+        fn planted_module() -> Result<()> {
+            let dir = tempfile::tempdir()?;
+            let module = dir.path().join("upper.wat");
+            std::fs::write(&module, UPPER_WAT)?;
+            let mut config = RgaConfig::default();
+            config.cache.path = crate::config::CachePath(dir.path().to_string_lossy().into_owned());
+            let key = [7; 32];
+            load_module(&module, &config, Some(&key))?;
+            let compiled = std::fs::read_dir(dir.path().join("wasm"))?.next().unwrap()?.path();
+            // a file that someone else put there is compiled again instead of being loaded
+            std::fs::write(&compiled, b"not the real thing")?;
+            load_module(&module, &config, Some(&key))?;
+            let stored = std::fs::read(&compiled)?;
+            let (mac, artifact) = stored.split_at(blake3::OUT_LEN);
+            assert_eq!(blake3::keyed_hash(&key, artifact), *mac);
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn endless_loop() -> Result<()> {
+            let dir = tempfile::tempdir()?;
+            let module = dir.path().join("loop.wat");
+            std::fs::write(&module, r#"(module (func (export "_start") (loop $l (br $l))))"#)?;
+            let adapter = WasmAdapterConfig {
+                name: "spin".to_string(),
+                extensions: vec!["spin".to_string()],
+                module: module.to_string_lossy().into_owned(),
+                max_fuel: Some(1_000_000),
+                ..Default::default()
+            }
+            .to_adapter();
+            let (mut a, d) = simple_adapt_info(
+                &PathBuf::from("x.spin"),
+                Box::pin(std::io::Cursor::new(b"".to_vec())),
+            );
+            a.config.cache.disabled = true;
+            let err = adapted_to_vec(adapter.adapt(a, &d).await?)
+                .await
+                .unwrap_err();
+            assert!(format!("{err:?}").contains("spin used up its max_fuel of 1000000"));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Model weights in GGUF and safetensors files: the metadata of the model (architecture, context
+//! length, tokenizer settings, ...) as `key: value` lines and the names, types and shapes of its
+//! tensors. Only the header at the start of the file is read, the weights after it are not.
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, BufReader};
+
+static EXTENSIONS: &[&str] = &["gguf", "safetensors"];
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+/// longer strings are cut off
+const MAX_STRING: u64 = 64 * 1024;
+/// the elements of arrays past this (the tokens of a vocabulary) are left out
+const MAX_ARRAY: u64 = 64;
+/// the largest safetensors header, as in the reference implementation
+const MAX_SAFETENSORS_HEADER: u64 = 100_000_000;
+
+/// the names of `ggml_type`, by their number
+const GGML_TYPES: &[&str] = &[
+    "F32", "F16", "Q4_0", "Q4_1", "", "", "Q5_0", "Q5_1", "Q8_0", "Q8_1", "Q2_K", "Q3_K", "Q4_K",
+    "Q5_K", "Q6_K", "Q8_K", "IQ2_XXS", "IQ2_XS", "IQ3_XXS", "IQ1_S", "IQ4_NL", "IQ3_S", "IQ2_S",
+    "IQ4_XS", "I8", "I16", "I32", "I64", "F64", "IQ1_M", "BF16", "", "", "", "TQ1_0", "TQ2_0",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "weights".to_owned(),
+        version: 1,
+        description:
+            "Lists the metadata and the tensor names of GGUF and safetensors model weights"
+                .to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct WeightsAdapter;
+
+impl WeightsAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for WeightsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+type Input = BufReader<ReadBox>;
+
+async fn skip(inp: &mut Input, len: u64) -> Result<()> {
+    let skipped = tokio::io::copy(&mut inp.take(len), &mut tokio::io::sink()).await?;
+    ensure!(skipped == len, "unexpected end of GGUF header");
+    Ok(())
+}
+
+/// a GGUF string, a u64 length and the bytes
+async fn gguf_string(inp: &mut Input) -> Result<String> {
+    let len = inp.read_u64_le().await?;
+    let mut buf = vec![];
+    (&mut *inp)
+        .take(len.min(MAX_STRING))
+        .read_to_end(&mut buf)
+        .await?;
+    skip(inp, len - buf.len() as u64).await?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// a metadata value of the given `gguf_type`
+async fn gguf_value(inp: &mut Input, ty: u32) -> Result<String> {
+    Ok(match ty {
+        0 => inp.read_u8().await?.to_string(),
+        1 => inp.read_i8().await?.to_string(),
+        2 => inp.read_u16_le().await?.to_string(),
+        3 => inp.read_i16_le().await?.to_string(),
+        4 => inp.read_u32_le().await?.to_string(),
+        5 => inp.read_i32_le().await?.to_string(),
+        6 => inp.read_f32_le().await?.to_string(),
+        7 => (inp.read_u8().await? != 0).to_string(),
+        8 => gguf_string(inp).await?,
+        9 => {
+            let ty = inp.read_u32_le().await?;
+            ensure!(ty != 9, "nested GGUF arrays are not supported");
+            let len = inp.read_u64_le().await?;
+            let mut values = vec![];
+            for i in 0..len {
+                let value = Box::pin(gguf_value(inp, ty)).await?;
+                if i < MAX_ARRAY {
+                    values.push(value);
+                }
+            }
+            if len > MAX_ARRAY {
+                values.push(format!("... ({len} items)"));
+            }
+            format!("[{}]", values.join(", "))
+        }
+        10 => inp.read_u64_le().await?.to_string(),
+        11 => inp.read_i64_le().await?.to_string(),
+        12 => inp.read_f64_le().await?.to_string(),
+        ty => bail!("unknown GGUF value type {ty}"),
+    })
+}
+
+fn ggml_type(ty: u32) -> String {
+    GGML_TYPES
+        .get(ty as usize)
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .unwrap_or_else(|| format!("type {ty}"))
+}
+
+async fn gguf(
+    inp: &mut Input,
+    line_prefix: &str,
+    mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let version = inp.read_u32_le().await?;
+    async_writeln!(oup, "{line_prefix}version: {version}")?;
+    // version 1 has 32 bit counts and lengths, it is not supported by llama.cpp anymore either
+    ensure!(version >= 2, "GGUF version {version} is not supported");
+    let tensors = inp.read_u64_le().await?;
+    let kvs = inp.read_u64_le().await?;
+    for _ in 0..kvs {
+        let key = gguf_string(inp).await?;
+        let ty = inp.read_u32_le().await?;
+        let value = gguf_value(inp, ty).await?;
+        async_writeln!(oup, "{line_prefix}{key}: {value}")?;
+    }
+    for _ in 0..tensors {
+        let name = gguf_string(inp).await?;
+        let dims = inp.read_u32_le().await?;
+        let mut shape = vec![];
+        for _ in 0..dims {
+            shape.push(inp.read_u64_le().await?.to_string());
+        }
+        let ty = ggml_type(inp.read_u32_le().await?);
+        let _offset = inp.read_u64_le().await?;
+        async_writeln!(
+            oup,
+            "{line_prefix}tensor {name}: {ty} [{}]",
+            shape.join(", ")
+        )?;
+    }
+    Ok(())
+}
+
+fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+async fn safetensors(
+    inp: &mut Input,
+    len: u64,
+    line_prefix: &str,
+    mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    ensure!(
+        len <= MAX_SAFETENSORS_HEADER,
+        "safetensors header too large: {len} bytes"
+    );
+    let mut header = vec![];
+    (&mut *inp).take(len).read_to_end(&mut header).await?;
+    let header: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&header).context("invalid safetensors header")?;
+    if let Some(serde_json::Value::Object(metadata)) = header.get("__metadata__") {
+        for (key, value) in metadata {
+            async_writeln!(oup, "{line_prefix}metadata {key}: {}", json_text(value))?;
+        }
+    }
+    for (name, info) in header.iter().filter(|(name, _)| *name != "__metadata__") {
+        let dtype = info.get("dtype").map(json_text).unwrap_or_default();
+        let shape = info
+            .get("shape")
+            .and_then(|s| s.as_array())
+            .map(|s| s.iter().map(json_text).collect::<Vec<_>>().join(", "))
+            .unwrap_or_default();
+        async_writeln!(oup, "{line_prefix}tensor {name}: {dtype} [{shape}]")?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for WeightsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut inp = BufReader::new(inp);
+        // a GGUF file starts with its magic, a safetensors file with the length of its header
+        let mut start = [0; 8];
+        inp.read_exact(&mut start[..4]).await?;
+        if &start[..4] == GGUF_MAGIC {
+            return gguf(&mut inp, &line_prefix, oup).await;
+        }
+        inp.read_exact(&mut start[4..]).await?;
+        safetensors(&mut inp, u64::from_le_bytes(start), &line_prefix, oup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    fn string(s: &str) -> Vec<u8> {
+        [&(s.len() as u64).to_le_bytes()[..], s.as_bytes()].concat()
+    }
+
+    async fn adapt(name: &str, file: Vec<u8>) -> Result<String> {
+        let (a, d) = simple_adapt_info(Path::new(name), Box::pin(std::io::Cursor::new(file)));
+        let out = adapted_to_vec(WeightsAdapter.adapt(a, &d).await?).await?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[tokio::test]
+    async fn gguf() -> Result<()> {
+        let tokens: Vec<u8> = (0..70).flat_map(|i| string(&format!("t{i}"))).collect();
+        let file = [
+            &GGUF_MAGIC[..],
+            &3u32.to_le_bytes(),
+            &1u64.to_le_bytes(),
+            &4u64.to_le_bytes(),
+            &string("general.architecture"),
+            &8u32.to_le_bytes(),
+            &string("llama"),
+            &string("llama.context_length"),
+            &4u32.to_le_bytes(),
+            &4096u32.to_le_bytes(),
+            &string("tokenizer.ggml.add_bos_token"),
+            &7u32.to_le_bytes(),
+            &[1],
+            &string("tokenizer.ggml.tokens"),
+            &9u32.to_le_bytes(),
+            &8u32.to_le_bytes(),
+            &70u64.to_le_bytes(),
+            &tokens,
+            &string("blk.0.attn_q.weight"),
+            &2u32.to_le_bytes(),
+            &4096u64.to_le_bytes(),
+            &4096u64.to_le_bytes(),
+            &12u32.to_le_bytes(),
+            &0u64.to_le_bytes(),
+            &[0; 32],
+        ]
+        .concat();
+        let tokens = (0..64)
+            .map(|i| format!("t{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(
+            adapt("model.gguf", file).await?,
+            format!(
+                "PREFIX:version: 3
+PREFIX:general.architecture: llama
+PREFIX:llama.context_length: 4096
+PREFIX:tokenizer.ggml.add_bos_token: true
+PREFIX:tokenizer.ggml.tokens: [{tokens}, ... (70 items)]
+PREFIX:tensor blk.0.attn_q.weight: Q4_K [4096, 4096]
+"
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn safetensors() -> Result<()> {
+        let header = br#"{"__metadata__":{"format":"pt"},"lm_head.weight":{"dtype":"BF16","shape":[32000,4096],"data_offsets":[0,16]},"model.norm.weight":{"dtype":"F32","shape":[4096],"data_offsets":[16,32]}}"#;
+        let file = [&(header.len() as u64).to_le_bytes()[..], header, &[0; 32]].concat();
+        assert_eq!(
+            adapt("model.safetensors", file).await?,
+            "PREFIX:metadata format: pt
+PREFIX:tensor lm_head.weight: BF16 [32000, 4096]
+PREFIX:tensor model.norm.weight: F32 [4096]
+"
+        );
+        Ok(())
+    }
+}
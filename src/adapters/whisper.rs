@@ -0,0 +1,297 @@
+//! Speech to text for audio and video files with whisper.cpp (`whisper-cli`) or another whisper
+//! binary. Writes what the ffmpeg adapter writes, and if the file has no subtitles its audio is
+//! transcribed into lines prefixed with their time, like subtitles. The segments are read from
+//! whisper.cpp's full JSON output, which has the probability of each token for
+//! `--rga-min-confidence`, or else from what the binary prints.
+use super::ffmpeg::{self, write_metadata_and_subtitles};
+use super::*;
+use super::{confidence, custom::map_exe_error, writing::async_writeln};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWrite;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use writing::WritingFileAdapter;
+
+const DEFAULT_BINARY: &str = "whisper-cli";
+/// arguments of whisper.cpp: print only the transcript, and write it with the token
+/// probabilities to `$output.json`
+const DEFAULT_ARGS: &[&str] = &[
+    "-m", "$model", "-l", "$language", "-f", "$input", "-np", "-ojf", "-of", "$output",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "whisper".to_owned(),
+        version: 2,
+        description: "Like ffmpeg, and transcribes the speech in audio and video files without subtitles using whisper.cpp. Slow, needs --rga-whisper-model".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            slow: true,
+            ..AdapterCapabilities::runs(&["ffmpeg", "ffprobe", DEFAULT_BINARY])
+        },
+        fast_matchers: ffmpeg::EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: true
+    };
+    /// `[00:00:01.000 --> 00:00:04.500]  text` of whisper.cpp and openai-whisper
+    static ref SEGMENT: Regex = Regex::new(r"^\s*\[\s*([^\]]*?)\s*-->\s*([^\]]*?)\s*\]\s*(.*)$").unwrap();
+}
+
+#[derive(Default, Clone)]
+pub struct WhisperAdapter;
+
+impl WhisperAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for WhisperAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the arguments of the whisper binary with the placeholders replaced
+fn whisper_args(config: &RgaConfig, input: &Path, output: &Path) -> Result<Vec<String>> {
+    let args = match &config.whisper_args {
+        Some(args) => args.clone(),
+        None => DEFAULT_ARGS.iter().map(|a| a.to_string()).collect(),
+    };
+    let language = config.whisper_language.as_deref().unwrap_or("auto");
+    args.into_iter()
+        .map(|arg| {
+            if arg.contains("$model") && config.whisper_model.is_none() {
+                bail!("the whisper adapter needs a model, set --rga-whisper-model");
+            }
+            Ok(arg
+                .replace("$input", &input.to_string_lossy())
+                .replace("$output", &output.to_string_lossy())
+                .replace("$model", config.whisper_model.as_deref().unwrap_or(""))
+                .replace("$language", language))
+        })
+        .collect()
+}
+
+/// a line of the transcript as it is written, None if there is no text
+fn transcript_line(line_prefix: &str, line: &str) -> Option<String> {
+    match SEGMENT.captures(line) {
+        Some(c) => {
+            let text = c[3].trim();
+            (!text.is_empty()).then(|| format!("{line_prefix}{} --> {}: {text}", &c[1], &c[2]))
+        }
+        None => {
+            let text = line.trim();
+            (!text.is_empty()).then(|| format!("{line_prefix}{text}"))
+        }
+    }
+}
+
+/// the lines of whisper.cpp's full JSON output (`-ojf`), with the mean probability of the text
+/// tokens of each segment as its confidence. None if it is not in that format
+fn json_lines(line_prefix: &str, json: &str, config: &RgaConfig) -> Option<Vec<String>> {
+    let json: serde_json::Value = serde_json::from_str(json).ok()?;
+    let mut lines = vec![];
+    for segment in json.get("transcription")?.as_array()? {
+        let text = segment.get("text")?.as_str()?.trim();
+        if text.is_empty() {
+            continue;
+        }
+        // whisper.cpp writes `00:00:01,000` here and `00:00:01.000` when printing
+        let time = |key: &str| -> Option<String> {
+            Some(segment.get("timestamps")?.get(key)?.as_str()?.replace(',', "."))
+        };
+        // special tokens like `[_BEG_]` are not part of the text
+        let probabilities: Vec<f64> = segment
+            .get("tokens")
+            .and_then(|t| t.as_array())
+            .into_iter()
+            .flatten()
+            .filter(|t| t.get("text").and_then(|t| t.as_str()).is_some_and(|t| !t.starts_with("[_")))
+            .filter_map(|t| t.get("p")?.as_f64())
+            .collect();
+        let confidence = (!probabilities.is_empty())
+            .then(|| (probabilities.iter().sum::<f64>() / probabilities.len() as f64 * 100.0) as f32);
+        if let Some(text) = confidence::line(config, confidence, text) {
+            lines.push(format!("{line_prefix}{} --> {}: {text}", time("from")?, time("to")?));
+        }
+    }
+    Some(lines)
+}
+
+async fn transcribe(
+    inp_fname: &Path,
+    line_prefix: &str,
+    config: &RgaConfig,
+    mut oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    // whisper.cpp only reads 16kHz wav files
+    let wav = dir.path().join("audio.wav");
+    let convert = crate::audit::output(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-i"])
+            .arg(inp_fname)
+            .args([
+                "-map",
+                "0:a:0",
+                "-vn",
+                "-ac",
+                "1",
+                "-ar",
+                "16000",
+                "-c:a",
+                "pcm_s16le",
+            ])
+            .arg(&wav),
+    )
+    .await
+    .map_err(|e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed."))?;
+    if !convert.status.success() {
+        bail!(
+            "ffmpeg failed: {:?}\n{}",
+            convert.status,
+            String::from_utf8_lossy(&convert.stderr)
+        );
+    }
+
+    let binary = config.whisper_binary.as_deref().unwrap_or(DEFAULT_BINARY);
+    // whisper logs a lot to stderr, it is only read if it fails
+    let stderr_path = dir.path().join("stderr");
+    let output = dir.path().join("transcript");
+    let (mut child, spawned) = crate::audit::spawn(
+        Command::new(binary)
+            .args(whisper_args(config, &wav, &output)?)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(std::fs::File::create(&stderr_path)?),
+    )
+    .map_err(|e| {
+        map_exe_error(
+            e,
+            binary,
+            "Install whisper.cpp or set --rga-whisper-binary.",
+        )
+    })?;
+    let mut lines =
+        BufReader::new(child.stdout.take().context("whisper stdout not piped")?).lines();
+    let mut printed = vec![];
+    while let Some(line) = lines.next_line().await? {
+        printed.extend(transcript_line(line_prefix, &line));
+    }
+    let exit = child.wait().await?;
+    spawned.exited(&exit);
+    if !exit.success() {
+        let stderr = std::fs::read_to_string(&stderr_path).unwrap_or_default();
+        bail!("{binary} failed: {exit:?}\n{stderr}");
+    }
+    let json = std::fs::read_to_string(output.with_extension("json")).unwrap_or_default();
+    let lines = match json_lines(line_prefix, &json, config) {
+        Some(lines) => lines,
+        None => printed,
+    };
+    for line in lines {
+        async_writeln!(oup, "{line}")?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for WhisperAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let subtitle_streams =
+            write_metadata_and_subtitles(&ai.filepath_hint, &ai.line_prefix, &ai.config, &mut oup)
+                .await?;
+        if subtitle_streams > 0 {
+            debug!(
+                "{} has subtitles, not transcribing it",
+                ai.filepath_hint.display()
+            );
+            return Ok(());
+        }
+        if ffmpeg::streams(&ai.filepath_hint, "a").await?.is_empty() {
+            return Ok(());
+        }
+        transcribe(&ai.filepath_hint, &ai.line_prefix, &ai.config, &mut oup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn transcript() {
+        let lines = [
+            "[00:00:00.000 --> 00:00:03.500]   Let's start with the budget approval.",
+            "[00:00:03.500 --> 00:00:04.000]  ",
+            "[00:07.120 --> 00:09.000] Any objections?",
+            "no timestamps here",
+        ];
+        assert_eq!(
+            lines
+                .iter()
+                .filter_map(|l| transcript_line("meeting.mp3: ", l))
+                .collect::<Vec<_>>(),
+            [
+                "meeting.mp3: 00:00:00.000 --> 00:00:03.500: Let's start with the budget approval.",
+                "meeting.mp3: 00:07.120 --> 00:09.000: Any objections?",
+                "meeting.mp3: no timestamps here",
+            ]
+        );
+    }
+
+    #[test]
+    fn confidence_from_json() {
+        let json = r#"{"transcription": [
+            {"timestamps": {"from": "00:00:00,000", "to": "00:00:03,500"}, "text": " Budget approved.",
+             "tokens": [{"text": "[_BEG_]", "p": 0.1}, {"text": " Budget", "p": 0.9}, {"text": " approved.", "p": 0.8}]},
+            {"timestamps": {"from": "00:00:03,500", "to": "00:00:05,000"}, "text": " mhm grr",
+             "tokens": [{"text": " mhm", "p": 0.2}, {"text": " grr", "p": 0.3}]}
+        ]}"#;
+        let mut config = RgaConfig::default();
+        let all = json_lines("m.mp3: ", json, &config).unwrap();
+        assert_eq!(
+            all,
+            [
+                "m.mp3: 00:00:00.000 --> 00:00:03.500: Budget approved.",
+                "m.mp3: 00:00:03.500 --> 00:00:05.000: mhm grr"
+            ]
+        );
+        config.min_confidence = Some(50);
+        config.confidence_markers = true;
+        assert_eq!(
+            json_lines("m.mp3: ", json, &config).unwrap(),
+            ["m.mp3: 00:00:00.000 --> 00:00:03.500: [confidence 85] Budget approved."]
+        );
+        assert_eq!(json_lines("m.mp3: ", "", &config), None);
+    }
+
+    #[test]
+    fn args() -> Result<()> {
+        let mut config = RgaConfig {
+            whisper_language: Some("de".to_string()),
+            ..Default::default()
+        };
+        assert!(whisper_args(&config, Path::new("/tmp/a.wav"), Path::new("/tmp/t")).is_err());
+        config.whisper_model = Some("ggml-base.bin".to_string());
+        assert_eq!(
+            whisper_args(&config, Path::new("/tmp/a.wav"), Path::new("/tmp/t"))?,
+            ["-m", "ggml-base.bin", "-l", "de", "-f", "/tmp/a.wav", "-np", "-ojf", "-of", "/tmp/t"]
+        );
+        Ok(())
+    }
+}
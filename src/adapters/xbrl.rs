@@ -0,0 +1,475 @@
+//! XBRL financial filings: instance documents and inline XBRL, the XHTML reports with the facts
+//! tagged in the text. Every fact becomes a line with its concept, value, unit, period and
+//! dimensions, so `us-gaap:Revenues` or a number finds the fact no matter how it is displayed.
+//! Inline XBRL is usually `.htm`, which pandoc claims, so it is only recognized by its content
+//! with --rga-accurate (and --rga-adapters=+xbrl for .htm files).
+use super::{
+    writing::{WritingFileAdapter, async_writeln},
+    *,
+};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::HashMap;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["xbrl"];
+
+const INSTANCE_MIME: &str = "application/xbrl+xml";
+const INLINE_MIME: &str = "application/vnd.xbrl.inline+xhtml";
+const INLINE_NAMESPACE: &[u8] = b"http://www.xbrl.org/2013/inlineXBRL";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "xbrl".to_owned(),
+        version: 1,
+        description: "Lists the facts of XBRL instances and inline XBRL reports with their concept, value, unit, period and dimensions".to_owned(),
+        capabilities: AdapterCapabilities::default(),
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType(INSTANCE_MIME.to_owned()),
+            FileMatcher::MimeType(INLINE_MIME.to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct XbrlAdapter;
+
+impl XbrlAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for XbrlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// XBRL is XML without a magic number: the root element of an instance is `xbrl`, inline XBRL
+/// is XHTML that declares the inline namespace. Only used with --rga-accurate.
+pub fn sniff_mime(buf: &[u8]) -> Option<&'static str> {
+    let (local, root) = xml_root(buf)?;
+    if local == b"xbrl" {
+        Some(INSTANCE_MIME)
+    } else if local == b"html" && memchr::memmem::find(root, INLINE_NAMESPACE).is_some() {
+        Some(INLINE_MIME)
+    } else {
+        None
+    }
+}
+
+#[derive(Default)]
+struct Context {
+    entity: String,
+    period: String,
+    /// dimension=member
+    dimensions: Vec<String>,
+}
+
+struct Fact {
+    concept: String,
+    context: String,
+    unit: Option<String>,
+    /// text nested in the element, ix:exclude left out
+    text: String,
+    /// the ix:nonFraction attributes needed to get the value from the displayed text
+    number: Option<NumberFormat>,
+    nil: bool,
+    /// element depth, to find the end of the fact
+    depth: usize,
+}
+
+#[derive(Default)]
+struct NumberFormat {
+    format: String,
+    scale: i32,
+    negative: bool,
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<Option<String>> {
+    // the names in this format are used unprefixed, except xsi:nil
+    Ok(e.attributes()
+        .with_checks(false)
+        .flatten()
+        .find(|a| {
+            a.key.as_ref() == name.as_bytes() || a.key.local_name().as_ref() == name.as_bytes()
+        })
+        .map(|a| a.unescape_value().map(|v| v.into_owned()))
+        .transpose()?)
+}
+
+/// the name without the namespace prefix, as measures like `iso4217:USD` are shown
+fn local(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// elements that separate words, so text in table cells or paragraphs does not run together
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div" | "br" | "td" | "th" | "tr" | "li" | "table" | "h1" | "h2" | "h3" | "h4"
+    )
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// moves the decimal point of `digits` (with an optional `.`) by `scale` places to the right
+fn scale_decimal(digits: &str, scale: i32) -> String {
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let all = format!("{int}{frac}");
+    let point = int.len() as i64 + scale as i64;
+    let mut out = if point <= 0 {
+        format!("0.{}{all}", "0".repeat(-point as usize))
+    } else if point as usize >= all.len() {
+        format!("{all}{}", "0".repeat(point as usize - all.len()))
+    } else {
+        format!("{}.{}", &all[..point as usize], &all[point as usize..])
+    };
+    if out.contains('.') {
+        out.truncate(out.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    let trimmed = out.trim_start_matches('0');
+    if trimmed.is_empty() || trimmed.starts_with('.') {
+        format!("0{trimmed}")
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// the value of an ix:nonFraction from its displayed text, None if the format is not understood
+fn number_value(text: &str, number: &NumberFormat) -> Option<String> {
+    let format = local(&number.format).to_ascii_lowercase();
+    let value = if format.contains("zerodash") || format.contains("fixed-zero") {
+        "0".to_string()
+    } else {
+        let decimal = if format.contains("comma-decimal") || format.contains("numcommadecimal") {
+            ','
+        } else {
+            '.'
+        };
+        let digits: String = text
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == decimal)
+            .map(|c| if c == decimal { '.' } else { c })
+            .collect();
+        if !digits.chars().any(|c| c.is_ascii_digit()) || digits.matches('.').count() > 1 {
+            return None;
+        }
+        scale_decimal(&digits, number.scale)
+    };
+    Some(if number.negative && value != "0" {
+        format!("-{value}")
+    } else {
+        value
+    })
+}
+
+/// the lines of a filing: the entities, then one per fact in document order
+fn convert(xml: &str) -> Result<Vec<String>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    // inline XBRL is XHTML, but not always strictly
+    reader.config_mut().check_end_names = false;
+    let mut contexts: HashMap<String, Context> = HashMap::new();
+    let mut units: HashMap<String, String> = HashMap::new();
+    let mut facts = vec![];
+    let mut open_facts: Vec<Fact> = vec![];
+    let mut stack: Vec<String> = vec![];
+    let mut text = String::new();
+    let mut context: Option<(String, Context)> = None;
+    let mut unit: Option<(String, Vec<String>, Vec<String>)> = None;
+    let mut start_date = String::new();
+    let mut dimension = String::new();
+    let mut excluded = 0;
+    loop {
+        let event = reader.read_event()?;
+        let (e, is_empty) = match &event {
+            Event::Start(e) => (Some(e), false),
+            Event::Empty(e) => (Some(e), true),
+            _ => (None, false),
+        };
+        if let Some(e) = e {
+            let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+            text.clear();
+            if is_block(&name) {
+                for fact in &mut open_facts {
+                    fact.text.push(' ');
+                }
+            }
+            match name.as_str() {
+                "context" => {
+                    context = Some((attribute(e, "id")?.unwrap_or_default(), Context::default()))
+                }
+                "unit" if context.is_none() => {
+                    unit = Some((attribute(e, "id")?.unwrap_or_default(), vec![], vec![]))
+                }
+                "explicitMember" | "typedMember" => {
+                    dimension = attribute(e, "dimension")?.unwrap_or_default()
+                }
+                "forever" => {
+                    if let Some((_, c)) = &mut context {
+                        c.period = "forever".to_string();
+                    }
+                }
+                "exclude" => excluded += 1,
+                _ => {}
+            }
+            if let Some(context_ref) = attribute(e, "contextRef")? {
+                let qname = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let number = (name == "nonFraction")
+                    .then(|| -> Result<NumberFormat> {
+                        Ok(NumberFormat {
+                            format: attribute(e, "format")?.unwrap_or_default(),
+                            scale: attribute(e, "scale")?
+                                .and_then(|s| s.parse().ok())
+                                .unwrap_or(0),
+                            negative: attribute(e, "sign")?.as_deref() == Some("-"),
+                        })
+                    })
+                    .transpose()?;
+                let fact = Fact {
+                    concept: attribute(e, "name")?.unwrap_or(qname),
+                    context: context_ref,
+                    unit: attribute(e, "unitRef")?,
+                    text: String::new(),
+                    number,
+                    nil: attribute(e, "nil")?.as_deref() == Some("true"),
+                    depth: stack.len(),
+                };
+                if is_empty {
+                    facts.push(fact);
+                } else {
+                    open_facts.push(fact);
+                }
+            }
+            if !is_empty {
+                stack.push(name);
+            }
+            continue;
+        }
+        match event {
+            Event::Text(t) => {
+                let t = t
+                    .unescape_with(|entity| (entity == "nbsp").then_some("\u{a0}"))
+                    .map(|t| t.into_owned())
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&t).into_owned());
+                text.push_str(&t);
+                if excluded == 0 {
+                    for fact in &mut open_facts {
+                        fact.text.push_str(&t);
+                    }
+                }
+            }
+            Event::CData(t) => {
+                let t = String::from_utf8_lossy(&t);
+                text.push_str(&t);
+                for fact in &mut open_facts {
+                    fact.text.push_str(&t);
+                }
+            }
+            Event::End(_) => {
+                let Some(name) = stack.pop() else {
+                    continue;
+                };
+                let value = text.trim().to_string();
+                if let Some((id, c)) = &mut context {
+                    match name.as_str() {
+                        "identifier" => c.entity = value.clone(),
+                        "instant" => c.period = value.clone(),
+                        "startDate" => start_date = value.clone(),
+                        "endDate" => c.period = format!("{start_date}..{value}"),
+                        "explicitMember" | "typedMember" => {
+                            c.dimensions.push(format!("{dimension}={value}"))
+                        }
+                        "context" => {
+                            let (id, c) = (std::mem::take(id), std::mem::take(c));
+                            contexts.insert(id, c);
+                            context = None;
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some((id, numerator, denominator)) = &mut unit {
+                    match name.as_str() {
+                        "measure" if stack.iter().any(|n| n == "unitDenominator") => {
+                            denominator.push(local(&value).to_string())
+                        }
+                        "measure" => numerator.push(local(&value).to_string()),
+                        "unit" => {
+                            let mut measures = numerator.join("*");
+                            if !denominator.is_empty() {
+                                measures = format!("{measures}/{}", denominator.join("*"));
+                            }
+                            units.insert(std::mem::take(id), measures);
+                            unit = None;
+                        }
+                        _ => {}
+                    }
+                }
+                if name == "exclude" {
+                    excluded -= 1;
+                }
+                if is_block(&name) {
+                    for fact in &mut open_facts {
+                        fact.text.push(' ');
+                    }
+                }
+                if open_facts.last().is_some_and(|f| f.depth == stack.len()) {
+                    facts.extend(open_facts.pop());
+                }
+                text.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut lines = vec![];
+    let mut entities: Vec<&str> = contexts.values().map(|c| c.entity.as_str()).collect();
+    entities.sort();
+    entities.dedup();
+    for entity in entities.into_iter().filter(|e| !e.is_empty()) {
+        lines.push(format!("entity: {entity}"));
+    }
+    for fact in facts {
+        let shown = collapse_whitespace(&fact.text);
+        let (value, shown) = match &fact.number {
+            _ if fact.nil => ("nil".to_string(), None),
+            Some(number) => match number_value(&shown, number) {
+                Some(value) if value != shown => (value, Some(shown)),
+                _ => (shown, None),
+            },
+            None => (shown, None),
+        };
+        let unit = fact
+            .unit
+            .map(|u| format!(" {}", units.get(&u).unwrap_or(&u)))
+            .unwrap_or_default();
+        let shown = shown
+            .map(|s| format!(" (shown as {s})"))
+            .unwrap_or_default();
+        let context = match contexts.get(&fact.context) {
+            Some(c) => [c.period.clone()]
+                .into_iter()
+                .chain(c.dimensions.iter().cloned())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => fact.context,
+        };
+        lines.push(format!(
+            "{} = {value}{unit}{shown} [{context}]",
+            fact.concept
+        ));
+    }
+    Ok(lines)
+}
+
+#[async_trait]
+impl WritingFileAdapter for XbrlAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = vec![];
+        inp.read_to_end(&mut data).await?;
+        let xml = String::from_utf8_lossy(&data);
+        for line in convert(xml.trim_start_matches('\u{feff}'))? {
+            async_writeln!(oup, "{line_prefix}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const CONTEXTS: &str = r#"
+        <xbrli:context id="FY23">
+          <xbrli:entity><xbrli:identifier scheme="http://www.sec.gov/CIK">0000320193</xbrli:identifier></xbrli:entity>
+          <xbrli:period><xbrli:startDate>2022-09-25</xbrli:startDate><xbrli:endDate>2023-09-30</xbrli:endDate></xbrli:period>
+        </xbrli:context>
+        <xbrli:context id="FY23_americas">
+          <xbrli:entity>
+            <xbrli:identifier scheme="http://www.sec.gov/CIK">0000320193</xbrli:identifier>
+            <xbrli:segment><xbrldi:explicitMember dimension="us-gaap:StatementBusinessSegmentsAxis">aapl:AmericasSegmentMember</xbrldi:explicitMember></xbrli:segment>
+          </xbrli:entity>
+          <xbrli:period><xbrli:instant>2023-09-30</xbrli:instant></xbrli:period>
+        </xbrli:context>
+        <xbrli:unit id="usd"><xbrli:measure>iso4217:USD</xbrli:measure></xbrli:unit>
+        <xbrli:unit id="usdPerShare"><xbrli:divide>
+          <xbrli:unitNumerator><xbrli:measure>iso4217:USD</xbrli:measure></xbrli:unitNumerator>
+          <xbrli:unitDenominator><xbrli:measure>xbrli:shares</xbrli:measure></xbrli:unitDenominator>
+        </xbrli:divide></xbrli:unit>"#;
+
+    #[test]
+    fn instance() -> Result<()> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <xbrli:xbrl xmlns:xbrli="http://www.xbrl.org/2003/instance">
+              <us-gaap:Revenues contextRef="FY23" unitRef="usd" decimals="-6">383285000000</us-gaap:Revenues>
+              <us-gaap:EarningsPerShareBasic contextRef="FY23" unitRef="usdPerShare" decimals="2">6.16</us-gaap:EarningsPerShareBasic>
+              <us-gaap:Revenues contextRef="FY23_americas" unitRef="usd" decimals="-6">162560000000</us-gaap:Revenues>
+              <dei:AmendmentFlag contextRef="FY23">false</dei:AmendmentFlag>
+              {CONTEXTS}
+            </xbrli:xbrl>"#
+        );
+        assert_eq!(sniff_mime(xml.as_bytes()), Some(INSTANCE_MIME));
+        assert_eq!(
+            convert(&xml)?,
+            [
+                "entity: 0000320193",
+                "us-gaap:Revenues = 383285000000 USD [2022-09-25..2023-09-30]",
+                "us-gaap:EarningsPerShareBasic = 6.16 USD/shares [2022-09-25..2023-09-30]",
+                "us-gaap:Revenues = 162560000000 USD [2023-09-30, us-gaap:StatementBusinessSegmentsAxis=aapl:AmericasSegmentMember]",
+                "dei:AmendmentFlag = false [2022-09-25..2023-09-30]",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn inline() -> Result<()> {
+        let html = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <html xmlns="http://www.w3.org/1999/xhtml" xmlns:ix="http://www.xbrl.org/2013/inlineXBRL">
+            <body>
+              <div style="display:none"><ix:header><ix:resources>{CONTEXTS}</ix:resources></ix:header></div>
+              <p>Form <ix:nonNumeric name="dei:DocumentType" contextRef="FY23">10-K</ix:nonNumeric></p>
+              <table><tr><td>Net sales</td><td>$&nbsp;<ix:nonFraction name="us-gaap:Revenues" contextRef="FY23" unitRef="usd" scale="6" decimals="-6" format="ixt:num-dot-decimal">383,285</ix:nonFraction></td></tr>
+              <tr><td>Loss</td><td>(<ix:nonFraction name="us-gaap:OtherNonoperatingIncomeExpense" contextRef="FY23" unitRef="usd" scale="6" sign="-" format="ixt:num-dot-decimal">565.5</ix:nonFraction>)</td></tr>
+              <tr><td>Other</td><td><ix:nonFraction name="us-gaap:OtherAssets" contextRef="FY23_americas" unitRef="usd" scale="6" format="ixt:fixed-zero">—</ix:nonFraction></td></tr></table>
+              <ix:nonNumeric name="us-gaap:SegmentReportingDisclosureTextBlock" contextRef="FY23"><p>The Company reports</p><p>five segments<ix:exclude> (page 3)</ix:exclude>.</p></ix:nonNumeric>
+            </body></html>"#
+        );
+        assert_eq!(sniff_mime(html.as_bytes()), Some(INLINE_MIME));
+        assert_eq!(
+            convert(&html)?[1..],
+            [
+                "dei:DocumentType = 10-K [2022-09-25..2023-09-30]",
+                "us-gaap:Revenues = 383285000000 USD (shown as 383,285) [2022-09-25..2023-09-30]",
+                "us-gaap:OtherNonoperatingIncomeExpense = -565500000 USD (shown as 565.5) [2022-09-25..2023-09-30]",
+                "us-gaap:OtherAssets = 0 USD (shown as —) [2023-09-30, us-gaap:StatementBusinessSegmentsAxis=aapl:AmericasSegmentMember]",
+                "us-gaap:SegmentReportingDisclosureTextBlock = The Company reports five segments. [2022-09-25..2023-09-30]",
+            ]
+        );
+        Ok(())
+    }
+}
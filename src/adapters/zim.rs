@@ -0,0 +1,743 @@
+//! ZIM archives, the offline dumps of Wikipedia, Wiktionary, StackExchange and others read by
+//! Kiwix. Every article is a member named by its title, with an extension from its mime type so
+//! that it is recursed into, and its lines are prefixed with the title. Html articles are turned
+//! into plain text here. Images, styles, scripts, metadata and redirects are left out.
+use super::*;
+use crate::adapters::le::{u16_at, u32_at, u64_at};
+use crate::vfs::{Vfs, VfsEntry, adapt_vfs};
+use anyhow::*;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+static EXTENSIONS: &[&str] = &["zim"];
+
+const MAGIC: u32 = 72173914;
+const HEADER_LEN: usize = 80;
+/// mime type numbers of entries without content
+const REDIRECT: u16 = 0xffff;
+const LINK_TARGET: u16 = 0xfffe;
+const DELETED: u16 = 0xfffd;
+/// the article namespaces of the old (`A`) and new (`C`) layout
+const ARTICLE_NAMESPACES: &[u8] = b"AC";
+/// elements whose content is not text of the article
+const INVISIBLE_ELEMENTS: &[&str] = &["head", "script", "style", "template", "noscript", "svg"];
+/// elements that start a new line
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "caption",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+/// hyphenation hint, dropped so that hyphenated words can be found
+const SOFT_HYPHEN: char = '\u{ad}';
+/// content of these types is not searchable text
+const SKIPPED_MIME_PREFIXES: &[&str] = &[
+    "image/",
+    "video/",
+    "audio/",
+    "font/",
+    "text/css",
+    "text/javascript",
+    "application/javascript",
+    "application/octet-stream+xapian",
+];
+const EXTENSION_BY_MIME: &[(&str, &str)] = &[
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    ("text/markdown", "md"),
+    ("application/pdf", "pdf"),
+    ("application/epub+zip", "epub"),
+    ("application/json", "json"),
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "zim".to_owned(),
+        version: 2,
+        description: "Lists the articles of ZIM archives (Kiwix offline Wikipedia and other wikis) under their titles, strips the html of articles and recurses into the rest".to_owned(),
+        capabilities: AdapterCapabilities {
+            seekable_input: true,
+            produces_subfiles: true,
+            ..Default::default()
+        },
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-zim".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ZimAdapter;
+
+impl ZimAdapter {
+    pub fn new() -> Self {
+        Self
+    }
+}
+impl GetMetadata for ZimAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Article {
+    path: String,
+    title: String,
+    /// turned into text when read
+    html: bool,
+    cluster: u32,
+    blob: u32,
+}
+
+/// `count` pointers at `offset`, checked against the length of the file first
+fn read_u64s<R: Read + Seek>(
+    r: &mut R,
+    offset: u64,
+    count: usize,
+    file_len: u64,
+) -> Result<Vec<u64>> {
+    if offset
+        .checked_add(count as u64 * 8)
+        .is_none_or(|end| end > file_len)
+    {
+        bail!("invalid ZIM file, {count} pointers at {offset} are past its end");
+    }
+    r.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; count * 8];
+    r.read_exact(&mut buf)
+        .context("truncated ZIM pointer list")?;
+    buf.chunks_exact(8)
+        .map(|c| u64_at(c, 0).context("truncated ZIM pointer list"))
+        .collect()
+}
+
+fn read_cstr<R: BufRead>(r: &mut R) -> Result<String> {
+    let mut buf = vec![];
+    r.read_until(0, &mut buf)?;
+    if buf.pop() != Some(0) {
+        bail!("truncated ZIM directory entry");
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// the member name of an article: its title with an extension for its type
+fn article_path(title: &str, mime: &str) -> String {
+    let extension = EXTENSION_BY_MIME
+        .iter()
+        .find(|(m, _)| mime.split(';').next().unwrap_or("").trim() == *m)
+        .map(|(_, e)| *e);
+    match extension {
+        Some(e) if !title.to_lowercase().ends_with(&format!(".{e}")) => format!("{title}.{e}"),
+        _ => title.to_string(),
+    }
+}
+
+/// the character an html entity (without `&` and `;`) stands for
+fn entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => number.parse(),
+        };
+        return code.ok().and_then(char::from_u32);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "shy" => SOFT_HYPHEN,
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "laquo" => '«',
+        "raquo" => '»',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "middot" => '·',
+        "times" => '×',
+        "deg" => '°',
+        "copy" => '©',
+        _ => return None,
+    })
+}
+
+/// the text of an html page, a line for each block element. Whitespace is collapsed as in a browser
+pub(crate) fn html_text(html: &str) -> String {
+    let mut lines: Vec<String> = vec![String::new()];
+    // inside an invisible element until its end tag
+    let mut invisible: Option<String> = None;
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+        if let Some(tag) = rest.strip_prefix('<') {
+            let end = tag.find('>').unwrap_or(tag.len());
+            let (closing, name) = match tag[..end].strip_prefix('/') {
+                Some(name) => (true, name),
+                None => (false, &tag[..end]),
+            };
+            let name = name
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("")
+                .to_lowercase();
+            rest = tag.get(end + 1..).unwrap_or("");
+            match &invisible {
+                Some(element) if closing && *element == name => invisible = None,
+                Some(_) => {}
+                None if !closing && INVISIBLE_ELEMENTS.contains(&name.as_str()) => {
+                    invisible = (!tag[..end].ends_with('/')).then_some(name)
+                }
+                None if BLOCK_ELEMENTS.contains(&name.as_str()) => lines.push(String::new()),
+                None => {}
+            }
+            continue;
+        }
+        let end = rest.find('<').unwrap_or(rest.len());
+        if invisible.is_none() {
+            let line = lines.last_mut().unwrap();
+            let mut text = &rest[..end];
+            while let Some(c) = text.chars().next() {
+                text = &text[c.len_utf8()..];
+                let c = match c {
+                    '&' => match text.find(';').filter(|&e| e <= 10) {
+                        Some(e) if entity(&text[..e]).is_some() => {
+                            let c = entity(&text[..e]).unwrap();
+                            text = &text[e + 1..];
+                            c
+                        }
+                        _ => '&',
+                    },
+                    c => c,
+                };
+                if c.is_whitespace() {
+                    if !line.is_empty() && !line.ends_with(' ') {
+                        line.push(' ');
+                    }
+                } else if c != SOFT_HYPHEN {
+                    line.push(c);
+                }
+            }
+        }
+        rest = &rest[end..];
+    }
+    lines
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Archive {
+    /// ordered by cluster, so each cluster is decompressed once
+    articles: Vec<Article>,
+    index: HashMap<String, usize>,
+    /// start of every cluster and the end of the last
+    clusters: Vec<u64>,
+}
+
+fn read_archive<R: BufRead + Seek>(r: &mut R) -> Result<Archive> {
+    let mut header = [0; HEADER_LEN];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header).context("truncated ZIM header")?;
+    let header_u32 = |at| u32_at(&header, at).context("truncated ZIM header");
+    let header_u64 = |at| u64_at(&header, at).context("truncated ZIM header");
+    if header_u32(0)? != MAGIC {
+        bail!("not a ZIM file");
+    }
+    let (entry_count, cluster_count) = (header_u32(24)? as usize, header_u32(28)? as usize);
+    let (url_ptr_pos, cluster_ptr_pos) = (header_u64(32)?, header_u64(48)?);
+    let (mime_list_pos, checksum_pos) = (header_u64(56)?, header_u64(72)?);
+    let file_len = r.seek(SeekFrom::End(0))?;
+    if checksum_pos > file_len {
+        bail!("invalid ZIM file, its checksum at {checksum_pos} is past its end");
+    }
+
+    r.seek(SeekFrom::Start(mime_list_pos))?;
+    let mut mimes = vec![];
+    loop {
+        let mime = read_cstr(r)?;
+        if mime.is_empty() {
+            break;
+        }
+        mimes.push(mime);
+    }
+    let mut clusters = read_u64s(r, cluster_ptr_pos, cluster_count, file_len)?;
+    let end = match checksum_pos {
+        0 => file_len,
+        pos => pos,
+    };
+    if let Some(start) = clusters.iter().find(|start| **start > end) {
+        bail!("invalid ZIM file, a cluster at {start} is past its end");
+    }
+    clusters.push(end);
+
+    let mut articles = vec![];
+    let mut used = HashSet::new();
+    let mut pos = None;
+    for pointer in read_u64s(r, url_ptr_pos, entry_count, file_len)? {
+        // directory entries are usually stored in order, avoid dropping the read buffer
+        if pos != Some(pointer) {
+            r.seek(SeekFrom::Start(pointer))?;
+        }
+        let mut fixed = [0; 16];
+        r.read_exact(&mut fixed[..8])
+            .context("truncated ZIM directory entry")?;
+        let mime = u16_at(&fixed, 0).context("truncated ZIM directory entry")?;
+        let (parameter_len, namespace) = (fixed[2] as u64, fixed[3]);
+        let len = match mime {
+            REDIRECT => 12,
+            LINK_TARGET | DELETED => 8,
+            _ => 16,
+        };
+        r.read_exact(&mut fixed[8..len])
+            .context("truncated ZIM directory entry")?;
+        let url = read_cstr(r)?;
+        let title = read_cstr(r)?;
+        if parameter_len > 0 {
+            r.seek(SeekFrom::Current(parameter_len as i64))?;
+        }
+        pos =
+            Some(pointer + len as u64 + url.len() as u64 + title.len() as u64 + 2 + parameter_len);
+        if len != 16 || !ARTICLE_NAMESPACES.contains(&namespace) {
+            continue;
+        }
+        let Some(mime) = mimes.get(mime as usize) else {
+            continue;
+        };
+        if SKIPPED_MIME_PREFIXES.iter().any(|p| mime.starts_with(p)) {
+            continue;
+        }
+        let title = if title.is_empty() { url.clone() } else { title };
+        let html = mime.starts_with("text/html");
+        let mime = if html { "text/plain" } else { mime };
+        // titles are not unique, urls are
+        let path = [article_path(&title, mime), article_path(&url, mime)]
+            .into_iter()
+            .find(|p| !used.contains(p))
+            .unwrap_or_else(|| format!("{}/{url}", namespace as char));
+        used.insert(path.clone());
+        articles.push(Article {
+            path,
+            title,
+            html,
+            cluster: u32_at(&fixed, 8).context("truncated ZIM directory entry")?,
+            blob: u32_at(&fixed, 12).context("truncated ZIM directory entry")?,
+        });
+    }
+    articles.sort_by_key(|a| (a.cluster, a.blob));
+    let index = articles
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (a.path.clone(), i))
+        .collect();
+    Ok(Archive {
+        articles,
+        index,
+        clusters,
+    })
+}
+
+/// the blobs of a cluster
+fn decompress_cluster(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let (info, compressed) = data.split_first().context("empty ZIM cluster")?;
+    let mut out = vec![];
+    match info & 0x0f {
+        0 | 1 => out.extend_from_slice(compressed),
+        2 => {
+            flate2::read::ZlibDecoder::new(compressed).read_to_end(&mut out)?;
+        }
+        3 => {
+            bzip2::read::BzDecoder::new(compressed).read_to_end(&mut out)?;
+        }
+        4 => lzma_rs::xz_decompress(&mut std::io::Cursor::new(compressed), &mut out)
+            .map_err(|e| format_err!("{e:?}"))?,
+        5 => {
+            zstd::stream::read::Decoder::new(compressed)?.read_to_end(&mut out)?;
+        }
+        c => bail!("unknown ZIM cluster compression {c}"),
+    }
+    // extended clusters have 64 bit blob offsets
+    let width = if info & 0x10 != 0 { 8 } else { 4 };
+    let offset = |i: usize| -> Result<usize> {
+        let at = i * width;
+        let offset = if width == 8 {
+            u64_at(&out, at)
+        } else {
+            u32_at(&out, at).map(u64::from)
+        };
+        Ok(offset.context("truncated ZIM cluster")? as usize)
+    };
+    let count = offset(0)? / width;
+    let mut blobs = Vec::with_capacity(count.saturating_sub(1));
+    for i in 1..count {
+        let (start, end) = (offset(i - 1)?, offset(i)?);
+        blobs.push(
+            out.get(start..end)
+                .context("invalid ZIM blob offset")?
+                .to_vec(),
+        );
+    }
+    Ok(blobs)
+}
+
+/// the decompressed blobs of a cluster
+type Blobs = Arc<Vec<Vec<u8>>>;
+
+/// the articles of a ZIM file on the local file system
+pub struct ZimFs {
+    path: PathBuf,
+    archive: tokio::sync::OnceCell<Archive>,
+    /// the last decompressed cluster
+    cluster: Mutex<Option<(u32, Blobs)>>,
+}
+
+impl ZimFs {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            archive: Default::default(),
+            cluster: Default::default(),
+        }
+    }
+
+    async fn archive(&self) -> Result<&Archive> {
+        self.archive
+            .get_or_try_init(|| async {
+                let path = self.path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let file = std::fs::File::open(&path)
+                        .with_context(|| format!("opening {}", path.display()))?;
+                    read_archive(&mut BufReader::new(file))
+                })
+                .await?
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Vfs for ZimFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let archive = self.archive().await?;
+        Ok(archive
+            .articles
+            .iter()
+            .map(|a| VfsEntry {
+                path: a.path.clone().into(),
+                // not known without decompressing the cluster
+                size: 0,
+                mtime_unix: None,
+                version: String::new(),
+            })
+            .collect())
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let archive = self.archive().await?;
+        let article = archive
+            .index
+            .get(entry.path.to_str().unwrap_or_default())
+            .map(|i| &archive.articles[*i])
+            .with_context(|| {
+                format!(
+                    "{} not found in {}",
+                    entry.path.display(),
+                    self.path.display()
+                )
+            })?;
+        let cached = self.cluster.lock().unwrap().clone();
+        let blobs = match cached {
+            Some((cluster, blobs)) if cluster == article.cluster => blobs,
+            _ => {
+                let i = article.cluster as usize;
+                let (start, end) = match archive.clusters.get(i..i + 2) {
+                    Some([start, end]) if start <= end => (*start, *end),
+                    _ => bail!("invalid ZIM cluster {i}"),
+                };
+                let path = self.path.clone();
+                let blobs = tokio::task::spawn_blocking(move || {
+                    let mut file = std::fs::File::open(&path)?;
+                    file.seek(SeekFrom::Start(start))?;
+                    // the file may have changed since its header was read
+                    let mut data = vec![];
+                    file.take(end - start).read_to_end(&mut data)?;
+                    if data.len() as u64 != end - start {
+                        bail!("truncated ZIM cluster");
+                    }
+                    decompress_cluster(&data)
+                })
+                .await??;
+                let blobs = Arc::new(blobs);
+                *self.cluster.lock().unwrap() = Some((article.cluster, blobs.clone()));
+                blobs
+            }
+        };
+        let blob = blobs
+            .get(article.blob as usize)
+            .with_context(|| format!("{}: invalid ZIM blob number", entry.path.display()))?;
+        let text;
+        let blob = if article.html {
+            text = html_text(&String::from_utf8_lossy(blob));
+            text.as_bytes()
+        } else {
+            blob
+        };
+        oup.write_all(blob.get(offset as usize..).unwrap_or_default())
+            .await?;
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl FileAdapter for ZimAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let (_inp, container) = ai.into_parts();
+        let vfs = Arc::new(ZimFs::new(&container.filepath_hint));
+        let titles: HashMap<String, String> = vfs
+            .archive()
+            .await
+            .with_context(|| format!("reading {}", container.filepath_hint.display()))?
+            .articles
+            .iter()
+            .map(|a| (a.path.clone(), a.title.clone()))
+            .collect();
+        let line_prefix = container.line_prefix.clone();
+        let separator = postproc::prefix_separator(&container.config.postproc.options).to_string();
+        let members = adapt_vfs(vfs, container).await?;
+        Ok(Box::pin(members.map(move |member| {
+            let mut member = member?;
+            if let Some(title) = titles.get(&*member.filepath_hint.to_string_lossy()) {
+                member.line_prefix =
+                    format!("{line_prefix}{}{separator}", postproc::prefix_field(title));
+            }
+            Ok(member)
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    fn cluster(compression: u8, blobs: &[&[u8]]) -> Vec<u8> {
+        let mut offset = 4 * (blobs.len() + 1);
+        let mut data = vec![];
+        for blob in blobs {
+            data.extend((offset as u32).to_le_bytes());
+            offset += blob.len();
+        }
+        data.extend((offset as u32).to_le_bytes());
+        data.extend(blobs.concat());
+        if compression == 5 {
+            data = zstd::encode_all(&data[..], 3).unwrap();
+        }
+        [vec![compression], data].concat()
+    }
+
+    /// (namespace, url, title, mime, cluster, blob), with mime u16::MAX for redirects
+    fn zim(entries: &[(u8, &str, &str, u16, u32, u32)], clusters: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = vec![];
+        let mime_list = HEADER_LEN as u64;
+        for mime in ["text/html", "text/plain", "image/png", ""] {
+            body.extend(mime.as_bytes());
+            body.push(0);
+        }
+        let mut pointers = vec![];
+        for (namespace, url, title, mime, cluster, blob) in entries {
+            pointers.push(HEADER_LEN as u64 + body.len() as u64);
+            body.extend(mime.to_le_bytes());
+            body.extend([0, *namespace]);
+            body.extend(0u32.to_le_bytes());
+            body.extend(cluster.to_le_bytes());
+            if *mime != REDIRECT {
+                body.extend(blob.to_le_bytes());
+            }
+            for s in [url, title] {
+                body.extend(s.as_bytes());
+                body.push(0);
+            }
+        }
+        let url_ptr_pos = HEADER_LEN as u64 + body.len() as u64;
+        body.extend(pointers.iter().flat_map(|p| p.to_le_bytes()));
+        let mut cluster_pos = HEADER_LEN as u64 + body.len() as u64 + 8 * clusters.len() as u64;
+        let cluster_ptr_pos = HEADER_LEN as u64 + body.len() as u64;
+        for c in clusters {
+            body.extend(cluster_pos.to_le_bytes());
+            cluster_pos += c.len() as u64;
+        }
+        body.extend(clusters.concat());
+
+        let mut header = vec![0; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&6u16.to_le_bytes());
+        header[24..28].copy_from_slice(&(entries.len() as u32).to_le_bytes());
+        header[28..32].copy_from_slice(&(clusters.len() as u32).to_le_bytes());
+        for (at, v) in [(32, url_ptr_pos), (48, cluster_ptr_pos), (56, mime_list)] {
+            header[at..at + 8].copy_from_slice(&v.to_le_bytes());
+        }
+        header[72..80].copy_from_slice(&cluster_pos.to_le_bytes());
+        [header, body].concat()
+    }
+
+    #[test]
+    fn html() {
+        assert_eq!(
+            html_text(
+                "<html><head><title>Aardvark</title><style>p { color: red }</style></head>\
+                 <body><h1>Aard&shy;vark</h1><!-- <p>hidden</p> -->\n<p>The <b>aardvark</b>  is\n\
+                 a mammal &amp; digs<br/>burrows&#8230;</p><script>var p = '<p>';</script>\
+                 <ul><li>Africa &lt;south&gt;</li><li>&#x41;&unknown; &</li></ul></body></html>"
+            ),
+            "Aardvark\nThe aardvark is a mammal & digs\nburrows…\nAfrica <south>\nA&unknown; &"
+        );
+    }
+
+    #[tokio::test]
+    async fn articles() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wiki.zim");
+        std::fs::write(
+            &path,
+            zim(
+                &[
+                    (b'C', "Aardvark", "Aardvark", 0, 0, 0),
+                    (b'C', "Notes", "Field notes", 1, 0, 1),
+                    (b'C', "Erdferkel", "", REDIRECT, 0, 0),
+                    (b'C', "_res/logo.png", "", 2, 1, 0),
+                    (b'M', "Title", "", 1, 1, 1),
+                ],
+                &[
+                    cluster(
+                        5,
+                        &[
+                            b"<p>Aardvarks <i>dig</i>.</p><p>At night.</p>",
+                            b"an aardvark was seen",
+                        ],
+                    ),
+                    cluster(1, &[b"\x89PNG", b"Wikipedia"]),
+                ],
+            ),
+        )?;
+        let vfs = ZimFs::new(&path);
+        let paths: Vec<_> = vfs.list().await?.into_iter().map(|e| e.path).collect();
+        assert_eq!(
+            paths,
+            [
+                std::path::Path::new("Aardvark.txt"),
+                std::path::Path::new("Field notes.txt")
+            ]
+        );
+
+        let mut text = vec![];
+        vfs.read_range(&vfs.list().await?[0], 0, &mut text).await?;
+        assert_eq!(text, b"Aardvarks dig.\nAt night.");
+
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let r = loop_adapt(&ZimAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:Aardvark: Aardvarks dig.\nPREFIX:Aardvark: At night.\nPREFIX:Field notes: an aardvark was seen\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn crafted_header() {
+        let file = zim(
+            &[(b'C', "Aardvark", "Aardvark", 0, 0, 0)],
+            &[cluster(1, &[b"aardvark"])],
+        );
+        let read = |at: usize, value: &[u8]| {
+            let mut file = file.clone();
+            file[at..at + value.len()].copy_from_slice(value);
+            read_archive(&mut std::io::Cursor::new(file))
+                .err()
+                .map(|e| e.to_string())
+        };
+        assert_eq!(read(0, &[]), None);
+        assert_eq!(
+            read(24, &u32::MAX.to_le_bytes()).as_deref(),
+            Some(
+                format!(
+                    "invalid ZIM file, {} pointers at {} are past its end",
+                    u32::MAX,
+                    u64_at(&file, 32).unwrap()
+                )
+                .as_str()
+            )
+        );
+        assert!(read(28, &u32::MAX.to_le_bytes()).is_some());
+        assert!(read(48, &u64::MAX.to_le_bytes()).is_some());
+        assert!(read(72, &u64::MAX.to_le_bytes()).is_some());
+        // a cluster pointer past the end of the file
+        let cluster_ptr_pos = u64_at(&file, 48).unwrap() as usize;
+        assert!(read(cluster_ptr_pos, &u64::MAX.to_le_bytes()).is_some());
+    }
+}
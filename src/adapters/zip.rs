@@ -1,21 +1,40 @@
+mod checked;
+mod directory;
+mod encrypted;
+mod local;
+
 use super::*;
-use crate::print_bytes;
+use crate::{preproc::spool_to_temp_file, print_bytes, salvage::{Inflate, SalvageRead}, verify};
 use anyhow::*;
+use async_compression::tokio::bufread;
 use async_stream::stream;
 use lazy_static::lazy_static;
 use log::*;
-use tokio::io::AsyncReadExt;
+use std::path::Path;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 // TODO: allow users to configure file extensions instead of hard coding the list
 // https://github.com/phiresky/ripgrep-all/pull/208#issuecomment-2173241243
-static EXTENSIONS: &[&str] = &["zip", "jar", "xpi", "kra", "snagx"];
+static EXTENSIONS: &[&str] = &["zip", "jar", "xpi", "kra", "snagx", "kmz"];
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const METHOD_BZIP2: u16 = 12;
+const METHOD_LZMA: u16 = 14;
+const METHOD_ZSTD: u16 = 93;
+const METHOD_XZ: u16 = 95;
+/// the pipe a streamed member is passed through
+const PIPE_LEN: usize = 64 * 1024;
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "zip".to_owned(),
-        version: 1,
-        description: "Reads a zip file as a stream and recurses down into its contents".to_owned(),
-        recurses: true,
+        version: 3,
+        description: "Reads a zip file (including ZIP64) member by member without buffering and recurses down into its contents, decrypting members with --rga-archive-password".to_owned(),
+        capabilities: AdapterCapabilities {
+            produces_subfiles: true,
+            ..Default::default()
+        },
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -39,28 +58,276 @@ impl GetMetadata for ZipAdapter {
     }
 }
 
-fn make_zip_adapt_info(
-    filename: String,
-    buf: Vec<u8>,
+fn is_supported(method: u16) -> bool {
+    matches!(
+        method,
+        METHOD_STORED | METHOD_DEFLATE | METHOD_BZIP2 | METHOD_LZMA | METHOD_ZSTD | METHOD_XZ
+    )
+}
+
+/// the decompressed data of a member, from its compressed data. With `--rga-salvage`, deflated
+/// data is inflated so that a damaged member keeps the data before the damage
+async fn decompressing<'a, R: AsyncBufRead + Send + Unpin + 'a>(
+    method: u16,
+    uncompressed_size: Option<u64>,
+    mut data: R,
+    salvage: bool,
+) -> Result<Pin<Box<dyn AsyncRead + Send + 'a>>> {
+    Ok(match method {
+        METHOD_STORED => Box::pin(data),
+        METHOD_DEFLATE if salvage => Box::pin(Inflate::new(data)),
+        METHOD_DEFLATE => Box::pin(bufread::DeflateDecoder::new(data)),
+        METHOD_BZIP2 => Box::pin(bufread::BzDecoder::new(data)),
+        METHOD_ZSTD => Box::pin(bufread::ZstdDecoder::new(data)),
+        METHOD_XZ => Box::pin(bufread::XzDecoder::new(data)),
+        METHOD_LZMA => {
+            // a version and the length of the properties come first, the .lzma header that the
+            // decoder expects is the properties and the uncompressed size
+            let mut header = [0; 4];
+            data.read_exact(&mut header).await?;
+            let mut properties = vec![0; u16::from_le_bytes([header[2], header[3]]) as usize];
+            data.read_exact(&mut properties).await?;
+            properties.extend(uncompressed_size.unwrap_or(u64::MAX).to_le_bytes());
+            let data = std::io::Cursor::new(properties).chain(data);
+            Box::pin(bufread::LzmaDecoder::new(data))
+        }
+        _ => bail!("unsupported compression method {method}"),
+    })
+}
+
+/// the content of a member of a zip file on disk
+async fn open_member(path: &Path, entry: &directory::Entry, salvage: bool) -> Result<ReadBox> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(entry.local_header_offset))
+        .await?;
+    let mut header = [0; directory::LOCAL_HEADER_LEN as usize];
+    file.read_exact(&mut header).await?;
+    let offset = entry.local_header_offset + directory::local_header_len(&header)?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    let data = tokio::io::BufReader::new(file).take(entry.compressed_size);
+    decompressing(entry.method, Some(entry.uncompressed_size), data, salvage).await
+}
+
+/// copy a member to the pipe it is read from. If the reader goes away the rest is skipped.
+/// Returns the crc of the member if `verify` is set
+async fn copy_member<R: AsyncBufRead + Send + Unpin>(
+    entry: &local::LocalEntry,
+    data: R,
+    pipe: &mut tokio::io::DuplexStream,
+    verify: bool,
+    salvage: bool,
+) -> Result<Option<u32>> {
+    let mut content = decompressing(entry.method, entry.uncompressed_size, data, salvage).await?;
+    let mut buf = vec![0; PIPE_LEN];
+    let mut reader_gone = false;
+    let mut crc = !0;
+    loop {
+        let n = content.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        if verify {
+            crc = checked::update(crc, &buf[..n]);
+        }
+        if !reader_gone && pipe.write_all(&buf[..n]).await.is_err() {
+            reader_gone = true;
+        }
+    }
+    Ok(verify.then_some(!crc))
+}
+
+/// what the task reading a zip stream passes on
+enum Streamed {
+    Member(String, ReadBox),
+    /// the crc of the member read last does not match
+    Mismatch(String, verify::Mismatch),
+}
+
+/// pass the member `entry` of the zip stream `inp` on through `tx`, or skip it. Returns false if
+/// the receiver went away
+async fn stream_member<R: AsyncBufRead + Send + Unpin>(
+    entry: local::LocalEntry,
+    inp: &mut R,
+    tx: &tokio::sync::mpsc::Sender<Streamed>,
     line_prefix: &str,
-    archive_recursion_depth: i32,
-    postprocess: bool,
-    config: &RgaConfig,
-) -> AdaptInfo {
-    let new_line_prefix = format!("{}{}: ", line_prefix, filename);
-    let s = async_stream::stream! { yield std::io::Result::Ok(bytes::Bytes::from(buf)); };
-    AdaptInfo {
-        filepath_hint: PathBuf::from(filename),
-        is_real_file: false,
-        file_mtime_unix_ms: None,
-        inp: Box::pin(tokio_util::io::StreamReader::new(s)),
-        line_prefix: new_line_prefix,
-        archive_recursion_depth: archive_recursion_depth + 1,
-        postprocess,
-        config: config.clone(),
+    verify: bool,
+    salvage: bool,
+) -> Result<bool> {
+    let mut crc = None;
+    let skip = if entry.is_dir() {
+        Some("directory")
+    } else if entry.is_encrypted() {
+        Some("encrypted file, --rga-archive-password reads it from a spooled copy")
+    } else if !is_supported(entry.method) {
+        Some("unsupported compression method")
+    } else {
+        None
+    };
+    if let Some(reason) = skip {
+        if reason != "directory" {
+            warn!("{line_prefix}{}: skipping {reason}", entry.name);
+        }
+        let len = entry
+            .compressed_size
+            .context("can not find its end without its size")?;
+        tokio::io::copy(&mut (&mut *inp).take(len), &mut tokio::io::sink()).await?;
+    } else {
+        debug!(
+            "{line_prefix}{}: {} ({} packed)",
+            entry.name,
+            print_bytes(entry.uncompressed_size.unwrap_or(0) as f64),
+            print_bytes(entry.compressed_size.unwrap_or(0) as f64)
+        );
+        let (mut pipe, member) = tokio::io::duplex(PIPE_LEN);
+        let member = Streamed::Member(entry.name.clone(), Box::pin(member));
+        if tx.send(member).await.is_err() {
+            return Ok(false);
+        }
+        match entry.compressed_size {
+            Some(len) => {
+                let mut data = (&mut *inp).take(len);
+                crc = copy_member(&entry, &mut data, &mut pipe, verify, salvage).await?;
+                tokio::io::copy(&mut data, &mut tokio::io::sink()).await?;
+                ensure!(
+                    data.limit() == 0,
+                    "truncated, {} bytes of its data are missing",
+                    data.limit()
+                );
+            }
+            // only compressed data knows where it ends
+            None if entry.method != METHOD_STORED => {
+                crc = copy_member(&entry, &mut *inp, &mut pipe, verify, salvage).await?
+            }
+            None => bail!("stored without its size, can not be read as a stream"),
+        }
+    }
+    let expected = if entry.has_data_descriptor() {
+        local::skip_data_descriptor(inp, entry.zip64).await?
+    } else {
+        entry.crc32
+    };
+    if let Some(mismatch) = crc.and_then(|crc| checked::mismatch(expected, crc)) {
+        warn!("{line_prefix}{}: {mismatch}", entry.name);
+        if tx.send(Streamed::Mismatch(entry.name, mismatch)).await.is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// the members of a zip stream, front to back. A task reads the archive and passes one member
+/// at a time through a pipe, so nothing but the pipe buffer is held in memory. With
+/// `--rga-salvage`, damaged members and headers are skipped by looking for the next local header
+fn stream_members(inp: ReadBox, container: ContainerInfo) -> AdaptedFilesIterBox {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Streamed>(1);
+    let line_prefix = container.line_prefix.clone();
+    let archive = format!("{line_prefix}{}", container.filepath_hint.display());
+    let (verify, salvage) = (container.config.verify, container.config.salvage);
+    let reader = tokio::spawn(async move {
+        trace!("begin zip");
+        let mut inp = tokio::io::BufReader::new(inp);
+        let mut next = local::next_entry(&mut inp).await;
+        // whether `next` is from looking for a header, where None is the end of the input
+        let mut searched = false;
+        loop {
+            let entry = match next {
+                Result::Ok(Some(entry)) => entry,
+                Result::Ok(None) if !salvage || searched => break,
+                Err(e) if !salvage => return Err(e),
+                // the central directory, garbage or a damaged header
+                other => {
+                    if let Err(e) = other {
+                        warn!("{archive}: {e:#}, looking for the next member");
+                    }
+                    next = local::find_entry(&mut inp).await;
+                    searched = true;
+                    continue;
+                }
+            };
+            searched = false;
+            trace!("zip next entry {}", entry.name);
+            let name = entry.name.clone();
+            match stream_member(entry, &mut inp, &tx, &line_prefix, verify, salvage).await {
+                Result::Ok(true) => next = local::next_entry(&mut inp).await,
+                Result::Ok(false) => break,
+                Err(e) if salvage => {
+                    warn!(
+                        "{line_prefix}{name}: {e:#}, salvaged the data before, looking for the next member"
+                    );
+                    next = local::find_entry(&mut inp).await;
+                    searched = true;
+                }
+                Err(e) => return Err(e.context(name)),
+            }
+        }
+        trace!("zip over");
+        Ok(())
+    });
+    Box::pin(stream! {
+        while let Some(streamed) = rx.recv().await {
+            yield Ok(match streamed {
+                Streamed::Member(name, member) => container.member(name, member),
+                Streamed::Mismatch(name, mismatch) => verify::mismatch_member(&container, &name, &mismatch),
+            });
+        }
+        match reader.await {
+            Result::Ok(Result::Ok(())) => {}
+            Result::Ok(Err(e)) => yield Err(e),
+            Err(e) => yield Err(e.into()),
+        }
+    })
+}
+
+/// the names of the files in the zip file at `path`, in central directory order
+pub fn file_names(path: &Path) -> Result<Vec<String>> {
+    Ok(directory::entries(path)?
+        .into_iter()
+        .filter(|e| !e.is_dir())
+        .map(|e| e.name)
+        .collect())
+}
+
+/// a file in a zip file on disk
+pub struct Member(directory::Entry);
+
+impl Member {
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn size(&self) -> u64 {
+        self.0.uncompressed_size
+    }
+
+    /// the first `limit` bytes of the file in the zip file at `path`
+    pub async fn read(&self, path: &Path, limit: u64) -> Result<Vec<u8>> {
+        let entry = &self.0;
+        ensure!(!entry.is_encrypted(), "{} is encrypted", entry.name);
+        ensure!(
+            is_supported(entry.method),
+            "{}: unsupported compression method {}",
+            entry.name,
+            entry.method
+        );
+        let mut data = vec![];
+        open_member(path, entry, false)
+            .await?
+            .take(limit)
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
     }
 }
 
+/// the files in the zip file at `path`, in central directory order
+pub fn members(path: &Path) -> Result<Vec<Member>> {
+    Ok(directory::entries(path)?
+        .into_iter()
+        .filter(|e| !e.is_dir())
+        .map(Member)
+        .collect())
+}
+
 #[async_trait]
 impl FileAdapter for ZipAdapter {
     async fn adapt(
@@ -68,99 +335,116 @@ impl FileAdapter for ZipAdapter {
         ai: AdaptInfo,
         _detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let AdaptInfo {
-            inp,
+        // encrypted members are read from the central directory, which needs the whole file
+        let (ai, spooled) = if !ai.is_real_file && ai.config.has_archive_passwords() {
+            let (ai, dir) = spool_to_temp_file(ai).await?;
+            (ai, Some(dir))
+        } else {
+            (ai, None)
+        };
+        let is_real_file = ai.is_real_file;
+        let (inp, container) = ai.into_parts();
+        if !is_real_file {
+            return Ok(stream_members(inp, container));
+        }
+        let ContainerInfo {
             filepath_hint,
-            archive_recursion_depth,
-            postprocess,
             line_prefix,
             config,
-            is_real_file,
             ..
-        } = ai;
-        if is_real_file {
-            use async_zip::read::fs::ZipFileReader;
-
-            let zip = ZipFileReader::new(&filepath_hint).await?;
-            let s = stream! {
-                for i in 0..zip.file().entries().len() {
-                    let file = zip.get_entry(i)?;
-                    let reader = zip.entry(i).await?;
-                    if file.filename().ends_with('/') {
+        } = container.clone();
+        let path = filepath_hint.clone();
+        let entries = tokio::task::spawn_blocking(move || directory::entries(&path))
+            .await?
+            .with_context(|| format!("reading the central directory of {}", filepath_hint.display()));
+        let entries = match entries {
+            Result::Ok(entries) => entries,
+            // e.g. an interrupted download, the members before the end are still there
+            Err(e) if config.salvage => {
+                warn!("{line_prefix}{e:#}, reading the headers of the members instead");
+                return Ok(stream_members(inp, container));
+            }
+            Err(e) => return Err(e),
+        };
+        let passwords = if entries.iter().any(|e| e.is_encrypted()) {
+            config.archive_passwords()?
+        } else {
+            vec![]
+        };
+        let s = stream! {
+            // the temporary copy is deleted once all members are read
+            let _spooled = spooled;
+            for entry in entries {
+                if entry.is_dir() {
+                    continue;
+                }
+                debug!(
+                    "{}{}|{}: {} ({} packed)",
+                    line_prefix,
+                    filepath_hint.display(),
+                    entry.name,
+                    print_bytes(entry.uncompressed_size as f64),
+                    print_bytes(entry.compressed_size as f64)
+                );
+                if !is_supported(entry.method) {
+                    warn!("{line_prefix}{}: skipping unsupported compression method {}", entry.name, entry.method);
+                    continue;
+                }
+                let member = if !entry.is_encrypted() {
+                    open_member(&filepath_hint, &entry, config.salvage).await
+                } else {
+                    let (path, to_try, e) = (filepath_hint.clone(), passwords.clone(), entry.clone());
+                    let decryption = match tokio::task::spawn_blocking(move || encrypted::open(&path, &e, &to_try)).await? {
+                        Result::Ok(decryption) => decryption,
+                        // like members that can't be read, the rest of the archive still is
+                        Err(e) => {
+                            warn!("{line_prefix}{}: can't decrypt it, {e:#}, skipping it", entry.name);
+                            continue;
+                        }
+                    };
+                    let Some(decryption) = decryption else {
+                        let reason = if passwords.is_empty() {
+                            "no --rga-archive-password given"
+                        } else {
+                            "none of the archive passwords match"
+                        };
+                        warn!("{line_prefix}{}: skipping encrypted file, {reason}", entry.name);
                         continue;
+                    };
+                    let name = format!("{line_prefix}{}", entry.name);
+                    encrypted::open_member(&filepath_hint, &entry, decryption, name, config.salvage).await
+                };
+                let member: ReadBox = match member.with_context(|| format!("opening {}", entry.name)) {
+                    Result::Ok(member) if config.salvage => {
+                        let name = format!("{line_prefix}{}", entry.name);
+                        Box::pin(SalvageRead::new(member, name))
                     }
-                    debug!(
-                        "{}{}|{}: {} ({} packed)",
-                        line_prefix,
-                        filepath_hint.display(),
-                        file.filename(),
-                        print_bytes(file.uncompressed_size() as f64),
-                        print_bytes(file.compressed_size() as f64)
-                    );
-                    tokio::pin!(reader);
-                    let mut buf = Vec::new();
-                    if file.uncompressed_size() < 10_000_000 {
-                        buf.reserve(file.uncompressed_size() as usize);
-                    }
-                    reader.read_to_end(&mut buf).await?;
-                    yield Ok(make_zip_adapt_info(
-                        file.filename().to_string(),
-                        buf,
-                        &line_prefix,
-                        archive_recursion_depth,
-                        postprocess,
-                        &config,
-                    ));
-                }
-            };
-
-            Ok(Box::pin(s))
-        } else {
-            use async_zip::read::stream::ZipFileReader;
-            let mut zip = ZipFileReader::new(inp);
-
-            let s = stream! {
-                trace!("begin zip");
-                while let Some(mut entry) = zip.next_entry().await? {
-                    trace!("zip next entry");
-                    let file = entry.entry();
-                    let filename = file.filename().to_string();
-                    let uncompressed = file.uncompressed_size();
-                    let compressed = file.compressed_size();
-                    if filename.ends_with('/') {
-                        zip = entry.skip().await?;
+                    Result::Ok(member) => member,
+                    Err(e) if config.salvage => {
+                        warn!("{line_prefix}{e:#}, skipping it");
                         continue;
                     }
-                    debug!(
-                        "{}{}|{}: {} ({} packed)",
-                        line_prefix,
-                        filepath_hint.display(),
-                        filename,
-                        print_bytes(uncompressed as f64),
-                        print_bytes(compressed as f64)
-                    );
-                    let reader = entry.reader();
-                    tokio::pin!(reader);
-                    let mut buf = Vec::new();
-                    if uncompressed < 10_000_000 {
-                        buf.reserve(uncompressed as usize);
+                    Err(e) => Err(e)?,
+                };
+                // the crc of encrypted members is always checked, a wrong ZipCrypto password can get past
+                // the check byte. AE-2 leaves out the crc, the authentication code replaces it
+                let has_crc = !matches!(entry.aes, Some((_, 2)));
+                if !(config.verify || entry.is_encrypted() && has_crc) {
+                    yield Ok(container.member(entry.name, member));
+                    continue;
+                }
+                // the member is read to the end before the next one is asked for
+                let (member, crc) = checked::CrcReader::new(member);
+                yield Ok(container.member(entry.name.clone(), Box::pin(member)));
+                if let Some(mismatch) = crc.mismatch(entry.crc32).filter(|_| has_crc) {
+                    warn!("{line_prefix}{}: {mismatch}", entry.name);
+                    if config.verify {
+                        yield Ok(verify::mismatch_member(&container, &entry.name, &mismatch));
                     }
-                    reader.read_to_end(&mut buf).await?;
-                    yield Ok(make_zip_adapt_info(
-                        filename,
-                        buf,
-                        &line_prefix,
-                        archive_recursion_depth,
-                        postprocess,
-                        &config,
-                    ));
-                    zip = entry.done().await.context("going to next file in zip but entry was not read fully")?;
                 }
-                trace!("zip over");
-            };
-
-            Ok(Box::pin(s))
-        }
+            }
+        };
+        Ok(Box::pin(s))
     }
 }
 
@@ -197,7 +481,16 @@ mod test {
     async fn only_seek_zip_fs() -> Result<()> {
         let zip = test_data_dir().join("only-seek-zip.zip");
         let (a, d) = simple_fs_adapt_info(&zip).await?;
-        let _v = adapted_to_vec(loop_adapt(&ZipAdapter::new(), d, a, crate::adapters::get_all_adapters(None).0).await?).await?;
+        let _v = adapted_to_vec(
+            loop_adapt(
+                &ZipAdapter::new(),
+                d,
+                a,
+                crate::adapters::get_all_adapters(None).0,
+            )
+            .await?,
+        )
+        .await?;
         Ok(())
     }
 
@@ -210,7 +503,10 @@ mod test {
             &PathBuf::from("outer.zip"),
             Box::pin(std::io::Cursor::new(zipfile)),
         );
-        let buf = adapted_to_vec(loop_adapt(&adapter, d, a, crate::adapters::get_all_adapters(None).0).await?).await?;
+        let buf = adapted_to_vec(
+            loop_adapt(&adapter, d, a, crate::adapters::get_all_adapters(None).0).await?,
+        )
+        .await?;
 
         assert_eq!(
             String::from_utf8(buf)?,
@@ -219,4 +515,168 @@ mod test {
 
         Ok(())
     }
+
+    /// a ZIP64 archive as streaming writers produce it: a stored member with its sizes in the
+    /// ZIP64 extra field and a deflated one with the sizes in a data descriptor
+    fn zip64() -> Vec<u8> {
+        use super::directory::*;
+        use std::io::Write;
+
+        let mut deflated = flate2::write::DeflateEncoder::new(vec![], Default::default());
+        deflated.write_all(b"second member, deflated").unwrap();
+        let members = [
+            ("a.txt", METHOD_STORED, b"first member".to_vec(), 12u64, false),
+            ("b.txt", METHOD_DEFLATE, deflated.finish().unwrap(), 23, true),
+        ];
+        let mut out = vec![];
+        let mut central = vec![];
+        for (name, method, data, len, descriptor) in members {
+            let offset = out.len() as u64;
+            let flags = if descriptor { FLAG_DATA_DESCRIPTOR } else { 0 };
+            let (csize, usize): (u64, u64) = if descriptor { (0, 0) } else { (data.len() as u64, len) };
+            out.extend(LOCAL_HEADER.to_le_bytes());
+            for v in [45, flags, method, 0, 0] {
+                out.extend(v.to_le_bytes());
+            }
+            for v in [0, u32::MAX, u32::MAX] {
+                out.extend(v.to_le_bytes());
+            }
+            for v in [name.len() as u16, 20] {
+                out.extend(v.to_le_bytes());
+            }
+            out.extend(name.as_bytes());
+            for v in [ZIP64_EXTRA, 16] {
+                out.extend(v.to_le_bytes());
+            }
+            out.extend(usize.to_le_bytes());
+            out.extend(csize.to_le_bytes());
+            out.extend(&data);
+            if descriptor {
+                out.extend(0x08074b50u32.to_le_bytes());
+                out.extend(0u32.to_le_bytes());
+                out.extend((data.len() as u64).to_le_bytes());
+                out.extend(len.to_le_bytes());
+            }
+
+            central.extend(CENTRAL_HEADER.to_le_bytes());
+            for v in [45, 45, flags, method, 0, 0] {
+                central.extend(v.to_le_bytes());
+            }
+            for v in [0, u32::MAX, u32::MAX] {
+                central.extend(v.to_le_bytes());
+            }
+            for v in [name.len() as u16, 28, 0, 0, 0] {
+                central.extend(v.to_le_bytes());
+            }
+            central.extend([0; 4]);
+            central.extend(u32::MAX.to_le_bytes());
+            central.extend(name.as_bytes());
+            for v in [ZIP64_EXTRA, 24] {
+                central.extend(v.to_le_bytes());
+            }
+            for v in [len, data.len() as u64, offset] {
+                central.extend(v.to_le_bytes());
+            }
+        }
+        let (cd_offset, cd_len) = (out.len() as u64, central.len() as u64);
+        out.extend(central);
+        let eocd64 = out.len() as u64;
+        out.extend(ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend(44u64.to_le_bytes());
+        out.extend([45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for v in [2, 2, cd_len, cd_offset] {
+            out.extend(v.to_le_bytes());
+        }
+        out.extend(ZIP64_LOCATOR.to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(eocd64.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        out.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend([0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        out.extend([0xff; 8]);
+        out.extend([0, 0]);
+        out
+    }
+
+    #[tokio::test]
+    async fn zip64_stream_and_fs() -> Result<()> {
+        let zip = zip64();
+        let expected = "PREFIX:a.txt: first member\nPREFIX:b.txt: second member, deflated\n";
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("big.zip"),
+            Box::pin(std::io::Cursor::new(zip.clone())),
+        );
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(String::from_utf8(adapted_to_vec(r).await?)?, expected);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("big.zip");
+        std::fs::write(&path, zip)?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(String::from_utf8(adapted_to_vec(r).await?)?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_crc() -> Result<()> {
+        // the test archive has a crc of 0 everywhere
+        let zip = zip64();
+        let expected = "PREFIX:a.txt: first member
+PREFIX:a.txt: [rga:verify] mismatch algorithm=crc32 expected=00000000 actual=8a30f0a2 source=zip
+PREFIX:b.txt: second member, deflated
+PREFIX:b.txt: [rga:verify] mismatch algorithm=crc32 expected=00000000 actual=25bfe7ee source=zip
+";
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("big.zip"),
+            Box::pin(std::io::Cursor::new(zip.clone())),
+        );
+        a.config.verify = true;
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(String::from_utf8(adapted_to_vec(r).await?)?, expected);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("big.zip");
+        std::fs::write(&path, zip)?;
+        let (mut a, d) = simple_fs_adapt_info(&path).await?;
+        a.config.verify = true;
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(String::from_utf8(adapted_to_vec(r).await?)?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn salvage() -> Result<()> {
+        let zip = zip64();
+        let b = zip.windows(5).position(|w| w == b"b.txt").unwrap() - 30;
+        // garbage between the members is skipped
+        let mut damaged = zip[..b].to_vec();
+        damaged.extend([0xab; 100]);
+        damaged.extend(&zip[b..]);
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("carved.zip"),
+            Box::pin(std::io::Cursor::new(damaged)),
+        );
+        a.config.salvage = true;
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:a.txt: first member\nPREFIX:b.txt: second member, deflated\n"
+        );
+
+        // a download that stopped in the first member has no central directory
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("partial.zip");
+        std::fs::write(&path, &zip[..30 + 5 + 20 + 5])?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        assert!(ZipAdapter.adapt(a, &d).await.is_err());
+        let (mut a, d) = simple_fs_adapt_info(&path).await?;
+        a.config.salvage = true;
+        let r = loop_adapt(&ZipAdapter, d, a, crate::adapters::get_all_adapters(None).0).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:a.txt: first\n"
+        );
+        Ok(())
+    }
 }
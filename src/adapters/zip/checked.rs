@@ -0,0 +1,72 @@
+//! The CRC32 of members as they are read, for `--rga-verify`.
+use super::encrypted::crc32_update;
+use crate::verify::{Algorithm, Mismatch};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// the crc of a member, once it was read to the end
+#[derive(Clone, Default)]
+pub struct Crc(Arc<Mutex<Option<u32>>>);
+
+impl Crc {
+    /// the mismatch with the crc stored in the archive. None if it matches or the member was not
+    /// read to the end
+    pub fn mismatch(&self, expected: u32) -> Option<Mismatch> {
+        let actual = (*self.0.lock().unwrap())?;
+        mismatch(expected, actual)
+    }
+}
+
+pub fn mismatch(expected: u32, actual: u32) -> Option<Mismatch> {
+    (expected != actual).then(|| Mismatch {
+        algorithm: Algorithm::Crc32,
+        expected: format!("{expected:08x}"),
+        actual: format!("{actual:08x}"),
+        source: "zip".to_string(),
+    })
+}
+
+/// computes the crc of the data of a member on the way through
+pub struct CrcReader<R> {
+    inner: R,
+    crc: u32,
+    done: Crc,
+}
+
+impl<R> CrcReader<R> {
+    pub fn new(inner: R) -> (Self, Crc) {
+        let done = Crc::default();
+        let reader = CrcReader {
+            inner,
+            crc: !0,
+            done: done.clone(),
+        };
+        (reader, done)
+    }
+}
+
+/// the crc of `data` continued from `crc`, which starts at `!0`. The result is `!crc`
+pub fn update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, b| crc32_update(crc, *b))
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CrcReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let (before, room) = (buf.filled().len(), buf.remaining());
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[before..];
+        if read.is_empty() && room > 0 {
+            *this.done.0.lock().unwrap() = Some(!this.crc);
+        } else {
+            this.crc = update(this.crc, read);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
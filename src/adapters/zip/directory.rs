@@ -0,0 +1,226 @@
+//! The central directory at the end of a zip file. Sizes and offsets that do not fit in 32 bits
+//! are in the ZIP64 end of central directory record and the ZIP64 extra field of each entry.
+use crate::adapters::le::{u16_at, u32_at, u64_at};
+use anyhow::*;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const LOCAL_HEADER: u32 = 0x04034b50;
+pub const CENTRAL_HEADER: u32 = 0x02014b50;
+pub const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+pub const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+pub const ZIP64_LOCATOR: u32 = 0x07064b50;
+pub const ZIP64_EXTRA: u16 = 0x0001;
+pub const AES_EXTRA: u16 = 0x9901;
+/// compression method of entries encrypted with WinZip AES, the real one is in the extra field
+pub const METHOD_AES: u16 = 99;
+pub const FLAG_ENCRYPTED: u16 = 1;
+pub const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+pub const LOCAL_HEADER_LEN: u64 = 30;
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub flags: u16,
+    pub method: u16,
+    pub mtime: u16,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub local_header_offset: u64,
+    /// (strength 1-3, vendor version) of WinZip AES entries
+    pub aes: Option<(u8, u16)>,
+}
+
+impl Entry {
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// `len` bytes at `offset`. Offsets and sizes come from the archive, so they are checked against the
+/// length of the file before anything is allocated
+pub fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let file_len = file.metadata()?.len();
+    if offset
+        .checked_add(len as u64)
+        .is_none_or(|end| end > file_len)
+    {
+        bail!("invalid zip file, {len} bytes at {offset} are past its end");
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; len];
+    file.read_exact(&mut buf)
+        .context("unexpected end of zip file")?;
+    Ok(buf)
+}
+
+/// (offset, size, entry count) of the central directory
+fn central_directory(file: &mut File) -> Result<(u64, u64, u64)> {
+    let len = file.metadata()?.len();
+    // the end record is 22 bytes plus a comment of at most 64K
+    let tail_start = len.saturating_sub(22 + 0xffff);
+    let tail = read_at(file, tail_start, (len - tail_start) as usize)?;
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(&tail, i) == Some(END_OF_CENTRAL_DIRECTORY))
+        .context("no end of central directory record")?;
+    let truncated = "truncated end of central directory record";
+    let (count, size, offset) = (
+        u16_at(&tail, eocd + 10).context(truncated)? as u64,
+        u32_at(&tail, eocd + 12).context(truncated)? as u64,
+        u32_at(&tail, eocd + 16).context(truncated)? as u64,
+    );
+    if eocd >= 20 && u32_at(&tail, eocd - 20) == Some(ZIP64_LOCATOR) {
+        let record_offset = u64_at(&tail, eocd - 20 + 8).context(truncated)?;
+        let record = read_at(file, record_offset, 56)?;
+        if u32_at(&record, 0) != Some(ZIP64_END_OF_CENTRAL_DIRECTORY) {
+            bail!("invalid zip64 end of central directory record");
+        }
+        return Ok((
+            u64_at(&record, 48).context(truncated)?,
+            u64_at(&record, 40).context(truncated)?,
+            u64_at(&record, 32).context(truncated)?,
+        ));
+    }
+    Ok((offset, size, count))
+}
+
+/// the values of a ZIP64 extra field. Only the ones that did not fit are there, in this order
+pub fn zip64_values(
+    data: &[u8],
+    uncompressed_size: &mut u64,
+    compressed_size: &mut u64,
+    local_header_offset: Option<&mut u64>,
+) -> Result<()> {
+    let mut values = data.chunks_exact(8).filter_map(|v| u64_at(v, 0));
+    let fields = [
+        Some(uncompressed_size),
+        Some(compressed_size),
+        local_header_offset,
+    ];
+    for field in fields.into_iter().flatten() {
+        if *field == u32::MAX as u64 {
+            *field = values.next().context("invalid zip64 extra field")?;
+        }
+    }
+    Ok(())
+}
+
+/// the entries of the zip file, in central directory order
+pub fn entries(path: &Path) -> Result<Vec<Entry>> {
+    let mut file = File::open(path)?;
+    let (offset, size, count) = central_directory(&mut file)?;
+    let cd = read_at(&mut file, offset, size as usize)?;
+    let mut entries = Vec::with_capacity(count.min(1 << 20) as usize);
+    let mut at = 0;
+    for _ in 0..count {
+        if cd.len() < at + 46 || u32_at(&cd, at) != Some(CENTRAL_HEADER) {
+            bail!("invalid central directory header");
+        }
+        let header = &cd[at..];
+        let field = |at| u16_at(header, at).context("truncated central directory");
+        let (name_len, extra_len, comment_len) = (
+            field(28)? as usize,
+            field(30)? as usize,
+            field(32)? as usize,
+        );
+        let name = header
+            .get(46..46 + name_len)
+            .context("truncated central directory")?;
+        let extra = header
+            .get(46 + name_len..46 + name_len + extra_len)
+            .context("truncated central directory")?;
+        at += 46 + name_len + extra_len + comment_len;
+        let long_field = |at| u32_at(header, at).context("truncated central directory");
+        let mut entry = Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: field(8)?,
+            method: field(10)?,
+            mtime: field(12)?,
+            crc32: long_field(16)?,
+            compressed_size: long_field(20)? as u64,
+            uncompressed_size: long_field(24)? as u64,
+            local_header_offset: long_field(42)? as u64,
+            aes: None,
+        };
+        let mut pos = 0;
+        while let (Some(id), Some(len)) = (u16_at(extra, pos), u16_at(extra, pos + 2)) {
+            let data = extra
+                .get(pos + 4..pos + 4 + len as usize)
+                .context("truncated zip extra field")?;
+            pos += 4 + len as usize;
+            match id {
+                ZIP64_EXTRA => zip64_values(
+                    data,
+                    &mut entry.uncompressed_size,
+                    &mut entry.compressed_size,
+                    Some(&mut entry.local_header_offset),
+                )?,
+                AES_EXTRA => {
+                    let Some((vendor_version, method)) = u16_at(data, 0).zip(u16_at(data, 5))
+                    else {
+                        continue;
+                    };
+                    entry.aes = Some((data[4], vendor_version));
+                    entry.method = method;
+                }
+                _ => {}
+            }
+        }
+        if entry.method == METHOD_AES {
+            bail!("AES encrypted zip entry without its extra field");
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// the length of a local header with its name and extra field, from its fixed size part
+pub fn local_header_len(header: &[u8]) -> Result<u64> {
+    if u32_at(header, 0) != Some(LOCAL_HEADER) {
+        bail!("invalid local file header");
+    }
+    let (name_len, extra_len) = u16_at(header, 26)
+        .zip(u16_at(header, 28))
+        .context("truncated local file header")?;
+    Ok(LOCAL_HEADER_LEN + name_len as u64 + extra_len as u64)
+}
+
+/// the offset of the (compressed) data of `entry`, after its local header
+pub fn data_offset(file: &mut File, entry: &Entry) -> Result<u64> {
+    let header = read_at(file, entry.local_header_offset, LOCAL_HEADER_LEN as usize)?;
+    Ok(entry.local_header_offset + local_header_len(&header)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn huge_central_directory() -> Result<()> {
+        // a ZIP64 record that puts a central directory of 2^60 bytes at the start of a 98 byte file
+        let mut zip = ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes().to_vec();
+        zip.extend([0; 28]);
+        for v in [1u64, 1 << 60, 0] {
+            zip.extend(v.to_le_bytes());
+        }
+        zip.extend(ZIP64_LOCATOR.to_le_bytes());
+        zip.extend([0; 4]);
+        zip.extend(0u64.to_le_bytes());
+        zip.extend(1u32.to_le_bytes());
+        zip.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        zip.extend([0; 18]);
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&zip)?;
+        let err = entries(file.path()).unwrap_err();
+        assert!(format!("{err}").contains("past its end"), "{err}");
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+//! Encrypted zip members. They are decrypted with traditional PKWARE encryption ("ZipCrypto") or
+//! WinZip AES as they are read, and then decompressed like the other members.
+use super::decompressing;
+use super::directory::{Entry, FLAG_DATA_DESCRIPTOR, data_offset, read_at};
+use crate::adapters::ReadBox;
+use aes::{Aes128, Aes192, Aes256};
+use anyhow::*;
+use ctr::Ctr128LE;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use log::warn;
+use sha1::Sha1;
+use std::fs::File;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, ReadBuf};
+
+const AES_ROUNDS: u32 = 1000;
+const AES_AUTH_LEN: usize = 10;
+
+fn crc_table() -> &'static [u32; 256] {
+    static TABLE: OnceLock<[u32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0; 256];
+        for (n, value) in table.iter_mut().enumerate() {
+            *value = (0..8).fold(n as u32, |c, _| {
+                if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                }
+            });
+        }
+        table
+    })
+}
+
+pub(super) fn crc32_update(crc: u32, byte: u8) -> u32 {
+    crc_table()[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+}
+
+/// the key state of traditional PKWARE encryption
+struct ZipCrypto([u32; 3]);
+
+impl ZipCrypto {
+    fn new(password: &[u8]) -> Self {
+        let mut keys = ZipCrypto([0x12345678, 0x23456789, 0x34567890]);
+        for b in password {
+            keys.update(*b);
+        }
+        keys
+    }
+
+    fn update(&mut self, plain: u8) {
+        let [k0, k1, k2] = &mut self.0;
+        *k0 = crc32_update(*k0, plain);
+        *k1 = k1
+            .wrapping_add(*k0 & 0xff)
+            .wrapping_mul(134775813)
+            .wrapping_add(1);
+        *k2 = crc32_update(*k2, (*k1 >> 24) as u8);
+    }
+
+    fn stream_byte(&self) -> u8 {
+        let t = (self.0[2] | 2) & 0xffff;
+        (t.wrapping_mul(t ^ 1) >> 8) as u8
+    }
+
+    fn decrypt(&mut self, data: &mut [u8]) {
+        for b in data {
+            *b ^= self.stream_byte();
+            self.update(*b);
+        }
+    }
+}
+
+/// AES in counter mode with the little endian counter of WinZip, starting at 1. `key` is 16, 24 or 32 bytes
+fn aes_ctr(key: &[u8]) -> Box<dyn StreamCipher + Send> {
+    let iv = 1u128.to_le_bytes();
+    match key.len() {
+        16 => Box::new(Ctr128LE::<Aes128>::new(key.into(), &iv.into())),
+        24 => Box::new(Ctr128LE::<Aes192>::new(key.into(), &iv.into())),
+        _ => Box::new(Ctr128LE::<Aes256>::new(key.into(), &iv.into())),
+    }
+}
+
+fn hmac_sha1(key: &[u8]) -> Hmac<Sha1> {
+    // any key length is fine for HMAC
+    Hmac::new_from_slice(key).unwrap()
+}
+
+/// the state of the decryption of a member
+enum Cipher {
+    ZipCrypto(ZipCrypto),
+    /// the HMAC is over the ciphertext, `auth` is its start, stored after the data
+    Aes {
+        ctr: Box<dyn StreamCipher + Send>,
+        mac: Option<Hmac<Sha1>>,
+        auth: Vec<u8>,
+    },
+}
+
+/// the decryption of a member with a password that fits, and where its encrypted data is
+pub struct Decryption {
+    cipher: Cipher,
+    offset: u64,
+    len: u64,
+}
+
+/// sets up the decryption of the entry with the first password that fits, None if none does.
+///
+/// Only the header is read: the password verifier of AES, the check byte of ZipCrypto. A wrong ZipCrypto
+/// password passes the check byte one time in 256, then the member does not decompress or its crc does not match
+pub fn open(path: &Path, entry: &Entry, passwords: &[String]) -> Result<Option<Decryption>> {
+    let mut file = File::open(path)?;
+    let offset = data_offset(&mut file, entry)?;
+    match entry.aes {
+        Some((strength @ 1..=3, _)) => {
+            let key_len = 8 + 8 * strength as usize;
+            let header_len = key_len / 2 + 2;
+            let Some(len) = entry
+                .compressed_size
+                .checked_sub((header_len + AES_AUTH_LEN) as u64)
+            else {
+                bail!("encrypted data too short");
+            };
+            let header = read_at(&mut file, offset, header_len)?;
+            let auth = read_at(&mut file, offset + header_len as u64 + len, AES_AUTH_LEN)?;
+            let (salt, verifier) = header.split_at(key_len / 2);
+            for password in passwords {
+                let mut keys = vec![0; 2 * key_len + 2];
+                pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, AES_ROUNDS, &mut keys);
+                if keys[2 * key_len..] == *verifier {
+                    let cipher = Cipher::Aes {
+                        ctr: aes_ctr(&keys[..key_len]),
+                        mac: Some(hmac_sha1(&keys[key_len..2 * key_len])),
+                        auth,
+                    };
+                    let offset = offset + header_len as u64;
+                    return Ok(Some(Decryption { cipher, offset, len }));
+                }
+            }
+        }
+        Some((strength, _)) => bail!("invalid AES strength {strength}, it is 1, 2 or 3"),
+        None => {
+            let Some(len) = entry.compressed_size.checked_sub(12) else {
+                bail!("encrypted data too short");
+            };
+            let header = read_at(&mut file, offset, 12)?;
+            // the last header byte is a check byte, the top of the crc or of the time
+            let check = if entry.flags & FLAG_DATA_DESCRIPTOR != 0 {
+                (entry.mtime >> 8) as u8
+            } else {
+                (entry.crc32 >> 24) as u8
+            };
+            for password in passwords {
+                let mut keys = ZipCrypto::new(password.as_bytes());
+                let mut header = header.clone();
+                keys.decrypt(&mut header);
+                if header[11] == check {
+                    let cipher = Cipher::ZipCrypto(keys);
+                    return Ok(Some(Decryption { cipher, offset: offset + 12, len }));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// decrypts the data of a member as it is read
+struct Decrypting<R> {
+    inner: R,
+    cipher: Cipher,
+    name: String,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Decrypting<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let (before, room) = (buf.filled().len(), buf.remaining());
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &mut buf.filled_mut()[before..];
+        match &mut this.cipher {
+            Cipher::ZipCrypto(keys) => keys.decrypt(read),
+            Cipher::Aes { ctr, mac, auth } => {
+                if read.is_empty() && room > 0 {
+                    let matches = mac.take().is_none_or(|m| m.verify_truncated_left(auth).is_ok());
+                    if !matches {
+                        warn!("{}: the AES authentication code does not match, the member is damaged", this.name);
+                    }
+                } else {
+                    if let Some(mac) = mac {
+                        mac.update(read);
+                    }
+                    ctr.apply_keystream(read);
+                }
+            }
+        }
+        Poll::Ready(std::io::Result::Ok(()))
+    }
+}
+
+/// the content of an encrypted member, decrypted and decompressed as it is read. `name` is for messages
+pub async fn open_member(
+    path: &Path,
+    entry: &Entry,
+    decryption: Decryption,
+    name: String,
+    salvage: bool,
+) -> Result<ReadBox> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(decryption.offset)).await?;
+    let data = Decrypting {
+        inner: file.take(decryption.len),
+        cipher: decryption.cipher,
+        name,
+    };
+    let data = tokio::io::BufReader::new(data);
+    decompressing(entry.method, Some(entry.uncompressed_size), data, salvage).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::directory::*;
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    fn crc32(data: &[u8]) -> u32 {
+        !super::super::checked::update(!0, data)
+    }
+
+    struct TestEntry {
+        name: &'static str,
+        flags: u16,
+        method: u16,
+        crc32: u32,
+        data: Vec<u8>,
+        extra: Vec<u8>,
+    }
+
+    fn zipcrypto(name: &'static str, content: &[u8], password: &str) -> TestEntry {
+        let crc = crc32(content);
+        let mut data = vec![0x5a; 11];
+        data.push((crc >> 24) as u8);
+        data.extend(content);
+        let mut keys = ZipCrypto::new(password.as_bytes());
+        for b in &mut data {
+            let plain = *b;
+            *b ^= keys.stream_byte();
+            keys.update(plain);
+        }
+        TestEntry {
+            name,
+            flags: FLAG_ENCRYPTED,
+            method: 0,
+            crc32: crc,
+            data,
+            extra: vec![],
+        }
+    }
+
+    fn aes(name: &'static str, content: &[u8], password: &str) -> TestEntry {
+        let mut compressed = flate2::write::DeflateEncoder::new(vec![], Default::default());
+        compressed.write_all(content).unwrap();
+        let mut ciphertext = compressed.finish().unwrap();
+        let salt = [7u8; 16];
+        let mut keys = [0; 66];
+        pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), &salt, AES_ROUNDS, &mut keys);
+        aes_ctr(&keys[..32]).apply_keystream(&mut ciphertext);
+        let mut mac = hmac_sha1(&keys[32..64]);
+        mac.update(&ciphertext);
+        let auth = mac.finalize().into_bytes();
+        let data = [&salt, &keys[64..], &ciphertext[..], &auth[..AES_AUTH_LEN]].concat();
+        let mut extra = vec![];
+        for v in [AES_EXTRA, 7, 2] {
+            extra.extend(v.to_le_bytes());
+        }
+        extra.extend(b"AE\x03");
+        extra.extend(8u16.to_le_bytes());
+        TestEntry {
+            name,
+            flags: FLAG_ENCRYPTED,
+            method: METHOD_AES,
+            crc32: 0,
+            data,
+            extra,
+        }
+    }
+
+    fn archive(entries: &[TestEntry]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut central = vec![];
+        for e in entries {
+            let offset = out.len() as u32;
+            let common = |out: &mut Vec<u8>| {
+                for v in [e.flags, e.method, 0, 0] {
+                    out.extend(v.to_le_bytes());
+                }
+                for v in [e.crc32, e.data.len() as u32, 0] {
+                    out.extend(v.to_le_bytes());
+                }
+                for v in [e.name.len() as u16, e.extra.len() as u16] {
+                    out.extend(v.to_le_bytes());
+                }
+            };
+            out.extend(LOCAL_HEADER.to_le_bytes());
+            out.extend(20u16.to_le_bytes());
+            common(&mut out);
+            out.extend(e.name.as_bytes());
+            out.extend(&e.extra);
+            out.extend(&e.data);
+
+            central.extend(CENTRAL_HEADER.to_le_bytes());
+            central.extend([20, 0, 20, 0]);
+            common(&mut central);
+            // comment length, disk, attributes
+            central.extend([0; 10]);
+            central.extend(offset.to_le_bytes());
+            central.extend(e.name.as_bytes());
+            central.extend(&e.extra);
+        }
+        let cd_offset = out.len() as u32;
+        out.extend(&central);
+        out.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        out.extend([0; 4]);
+        for v in [entries.len() as u16; 2] {
+            out.extend(v.to_le_bytes());
+        }
+        out.extend((central.len() as u32).to_le_bytes());
+        out.extend(cd_offset.to_le_bytes());
+        out.extend([0; 2]);
+        out
+    }
+
+    async fn content(path: &Path, entry: &Entry, passwords: &[String]) -> Result<Option<Vec<u8>>> {
+        let Some(decryption) = open(path, entry, passwords)? else {
+            return Ok(None);
+        };
+        let mut member = open_member(path, entry, decryption, entry.name.clone(), false).await?;
+        let mut content = vec![];
+        member.read_to_end(&mut content).await?;
+        Ok(Some(content))
+    }
+
+    #[tokio::test]
+    async fn zipcrypto_and_aes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("secret.zip");
+        let plain = TestEntry {
+            name: "plain.txt",
+            flags: 0,
+            method: 0,
+            crc32: crc32(b"not encrypted"),
+            data: b"not encrypted".to_vec(),
+            extra: vec![],
+        };
+        std::fs::write(
+            &path,
+            archive(&[
+                plain,
+                zipcrypto("zipcrypto.txt", b"the first secret", "hunter2"),
+                aes("aes.txt", b"the second secret", "correct horse"),
+            ]),
+        )?;
+        let entries: std::collections::HashMap<String, Entry> = entries(&path)?
+            .into_iter()
+            .filter(|e| e.is_encrypted())
+            .map(|e| (e.name.clone(), e))
+            .collect();
+        let mut names: Vec<_> = entries.keys().collect();
+        names.sort();
+        assert_eq!(names, ["aes.txt", "zipcrypto.txt"]);
+
+        let passwords = [
+            "wrong".to_string(),
+            "hunter2".into(),
+            "correct horse".into(),
+        ];
+        let zipcrypto = content(&path, &entries["zipcrypto.txt"], &passwords).await?;
+        assert_eq!(zipcrypto.unwrap(), b"the first secret");
+        let aes = content(&path, &entries["aes.txt"], &passwords).await?;
+        assert_eq!(aes.unwrap(), b"the second secret");
+        assert!(open(&path, &entries["aes.txt"], &passwords[..2])?.is_none());
+        for strength in [0, 4] {
+            let entry = Entry {
+                aes: Some((strength, 2)),
+                ..entries["aes.txt"].clone()
+            };
+            assert!(open(&path, &entry, &passwords).is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn undecryptable_member() -> Result<()> {
+        use crate::preproc::loop_adapt;
+        use crate::test_utils::{adapted_to_vec, simple_fs_adapt_info};
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("damaged.zip");
+        let plain = |name, content: &[u8]| TestEntry {
+            name,
+            flags: 0,
+            method: 0,
+            crc32: crc32(content),
+            data: content.to_vec(),
+            extra: vec![],
+        };
+        // an AES strength that doesn't exist
+        let mut damaged = aes("aes.txt", b"the secret", "correct horse");
+        damaged.extra[8] = 4;
+        std::fs::write(
+            &path,
+            archive(&[plain("a.txt", b"before"), damaged, plain("b.txt", b"after")]),
+        )?;
+        let (a, d) = simple_fs_adapt_info(&path).await?;
+        let all = crate::adapters::get_all_adapters(None).0;
+        let r = loop_adapt(&super::super::ZipAdapter, d, a, all).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:a.txt: before\nPREFIX:b.txt: after\n"
+        );
+        Ok(())
+    }
+}
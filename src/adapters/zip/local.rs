@@ -0,0 +1,129 @@
+//! Reading a zip file front to back from its local headers, for archives that are not files on
+//! disk. Entries written with a data descriptor may have no sizes in their local header, their
+//! data then ends where the decompressor says it does.
+use super::directory::*;
+use crate::adapters::le::{u16_at, u32_at};
+use anyhow::*;
+use tokio::io::{AsyncBufRead, AsyncReadExt};
+
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+
+#[derive(Clone, Debug)]
+pub struct LocalEntry {
+    pub name: String,
+    pub flags: u16,
+    pub method: u16,
+    /// 0 if it is only in the data descriptor
+    pub crc32: u32,
+    /// None if it is only in the data descriptor
+    pub compressed_size: Option<u64>,
+    pub uncompressed_size: Option<u64>,
+    /// whether the sizes in the data descriptor are 64 bit
+    pub zip64: bool,
+}
+
+impl LocalEntry {
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn has_data_descriptor(&self) -> bool {
+        self.flags & FLAG_DATA_DESCRIPTOR != 0
+    }
+}
+
+/// the next local header, None at the central directory or the end of the input
+pub async fn next_entry<R: AsyncBufRead + Unpin>(r: &mut R) -> Result<Option<LocalEntry>> {
+    let mut signature = [0; 4];
+    if r.read(&mut signature[..1]).await? == 0 {
+        return Ok(None);
+    }
+    r.read_exact(&mut signature[1..]).await?;
+    // split archives start with the signature of a data descriptor
+    if u32::from_le_bytes(signature) == DATA_DESCRIPTOR {
+        r.read_exact(&mut signature).await?;
+    }
+    if u32::from_le_bytes(signature) != LOCAL_HEADER {
+        return Ok(None);
+    }
+    read_entry(r).await.map(Some)
+}
+
+/// the next local header anywhere after the position of `r`, for damaged archives. None at the end
+/// of the input
+pub async fn find_entry<R: AsyncBufRead + Unpin>(r: &mut R) -> Result<Option<LocalEntry>> {
+    let mut window = 0u32;
+    let mut byte = [0];
+    loop {
+        if r.read(&mut byte).await? == 0 {
+            return Ok(None);
+        }
+        window = (window >> 8) | (byte[0] as u32) << 24;
+        if window == LOCAL_HEADER {
+            return read_entry(r).await.map(Some);
+        }
+    }
+}
+
+/// the local header whose signature was just read
+async fn read_entry<R: AsyncBufRead + Unpin>(r: &mut R) -> Result<LocalEntry> {
+    let mut header = [0; LOCAL_HEADER_LEN as usize];
+    header[..4].copy_from_slice(&LOCAL_HEADER.to_le_bytes());
+    r.read_exact(&mut header[4..])
+        .await
+        .context("truncated local file header")?;
+    // the header has a fixed size, so its fields are always there
+    let field = |at| u16_at(&header, at).unwrap_or_default();
+    let long_field = |at| u32_at(&header, at).unwrap_or_default();
+    let (name_len, extra_len) = (field(26) as usize, field(28) as usize);
+    let mut name = vec![0; name_len];
+    r.read_exact(&mut name).await?;
+    let mut extra = vec![0; extra_len];
+    r.read_exact(&mut extra).await?;
+
+    let flags = field(6);
+    let mut compressed_size = long_field(18) as u64;
+    let mut uncompressed_size = long_field(22) as u64;
+    let mut zip64 = false;
+    let mut pos = 0;
+    while let (Some(id), Some(len)) = (u16_at(&extra, pos), u16_at(&extra, pos + 2)) {
+        let data = extra
+            .get(pos + 4..pos + 4 + len as usize)
+            .context("truncated zip extra field")?;
+        pos += 4 + len as usize;
+        if id == ZIP64_EXTRA {
+            zip64 = true;
+            zip64_values(data, &mut uncompressed_size, &mut compressed_size, None)?;
+        }
+    }
+    // writers that stream leave the sizes at zero and put them in the data descriptor
+    let known = flags & FLAG_DATA_DESCRIPTOR == 0 || compressed_size != 0;
+    Ok(LocalEntry {
+        name: String::from_utf8_lossy(&name).into_owned(),
+        flags,
+        method: field(8),
+        crc32: long_field(14),
+        compressed_size: known.then_some(compressed_size),
+        uncompressed_size: known.then_some(uncompressed_size),
+        zip64,
+    })
+}
+
+/// skip the data descriptor after the data of an entry, returns the crc in it
+pub async fn skip_data_descriptor<R: AsyncBufRead + Unpin>(r: &mut R, zip64: bool) -> Result<u32> {
+    let mut word = [0; 4];
+    r.read_exact(&mut word).await?;
+    // the signature is optional, otherwise this was the crc
+    if u32::from_le_bytes(word) == DATA_DESCRIPTOR {
+        r.read_exact(&mut word).await?;
+    }
+    let mut sizes = vec![0; if zip64 { 16 } else { 8 }];
+    r.read_exact(&mut sizes)
+        .await
+        .context("truncated data descriptor")?;
+    Ok(u32::from_le_bytes(word))
+}
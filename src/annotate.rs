@@ -0,0 +1,447 @@
+//! `--rga-matches-manifest` and `--rga-matches-sidecars`: every match of a search recorded as a JSON
+//! line with its coordinates, the path inside archives and the page, for reviewing the matches
+//! later or diffing them between runs. The matches are collected from a run of rg with `--json`,
+//! whose events are also printed as the text rg would have printed, see [`TextPrinter`].
+use crate::adapters::{confidence, postproc::PageFormat};
+use crate::config::RgaConfig;
+use crate::redact::Redactor;
+use anyhow::{Context, Result};
+use base64::Engine;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// the matches of `file` are written to `file` + this. rg never searches these files
+pub const SIDECAR_SUFFIX: &str = ".rga-matches.jsonl";
+
+lazy_static! {
+    /// a member of an archive as rga prefixes its lines, `dir/name.ext: `
+    static ref MEMBER: Regex = Regex::new(r"^([^\s:][^:\n]*?(?:\.[A-Za-z0-9_-]{1,10}|/))(?:: |:$)").unwrap();
+    /// a member path before a separator other than `: `
+    static ref MEMBER_PATH: Regex = Regex::new(r"^[^\s][^\n]*?(?:\.[A-Za-z0-9_-]{1,10}|/)$").unwrap();
+}
+
+/// the member prefix at the start of `rest`: its path and its length with the separator
+fn member<'a>(rest: &'a str, separator: &str) -> Option<(&'a str, usize)> {
+    if separator == ": " {
+        let m = MEMBER.captures(rest)?;
+        return Some((m.get(1)?.as_str(), m[0].len()));
+    }
+    let end = rest.find(separator)?;
+    MEMBER_PATH
+        .is_match(&rest[..end])
+        .then(|| (&rest[..end], end + separator.len()))
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Submatch {
+    pub text: String,
+    /// byte offsets in `text` of the match
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct Match {
+    pub path: String,
+    /// the path of the member in archives (nested archives joined by `/`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    /// of the line in the extracted text
+    pub line_number: Option<u64>,
+    /// the line without its member and page prefixes and its confidence marker
+    pub text: String,
+    pub submatches: Vec<Submatch>,
+    /// of OCR and speech to text lines, see [`crate::adapters::confidence`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+}
+
+/// the text of an rg json string (`{"text": ...}`, or `{"bytes": ...}` if it is not UTF-8)
+fn rg_text(v: &Value) -> Option<String> {
+    v.get("text").and_then(Value::as_str).map(str::to_string)
+}
+
+/// (inner path, page, length of the prefixes) of an extracted line
+fn coordinates(line: &str, format: &PageFormat) -> (Option<String>, Option<u32>, usize) {
+    let mut members = vec![];
+    let mut page = None;
+    let mut rest = line;
+    loop {
+        if let Some((number, text)) = format.strip_page(rest) {
+            page = Some(number);
+            rest = text;
+            continue;
+        }
+        // pages are the innermost coordinate
+        if page.is_some() {
+            break;
+        }
+        let Some((path, len)) = member(rest, &format.separator) else {
+            break;
+        };
+        members.push(path.trim_end_matches('/').to_string());
+        rest = &rest[len..];
+    }
+    let inner_path = (!members.is_empty()).then(|| members.join("/"));
+    (inner_path, page, line.len() - rest.len())
+}
+
+/// the bytes of an rg json string, also if they are not UTF-8
+fn rg_bytes(v: &Value) -> Option<Vec<u8>> {
+    match v.get("text").and_then(Value::as_str) {
+        Some(text) => Some(text.as_bytes().to_vec()),
+        None => base64::engine::general_purpose::STANDARD
+            .decode(v.get("bytes")?.as_str()?)
+            .ok(),
+    }
+}
+
+/// the match of an rg `--json` event, None for other events
+pub fn parse_event(event: &str, format: &PageFormat) -> Option<Match> {
+    match_of(&serde_json::from_str(event).ok()?, format)
+}
+
+fn match_of(event: &Value, format: &PageFormat) -> Option<Match> {
+    if event.get("type")?.as_str()? != "match" {
+        return None;
+    }
+    let data = event.get("data")?;
+    let line = rg_text(data.get("lines")?)?;
+    let line = line.trim_end_matches(['\n', '\r']);
+    let (inner_path, page, prefix) = coordinates(line, format);
+    let mut text = line[prefix..].to_string();
+    let marker = confidence::find_marker(&text);
+    if let Some((_, start, end)) = marker {
+        text.replace_range(start..end, "");
+    }
+    // offsets in `text` once the marker is taken out
+    let offset = |at: usize| {
+        let at = at.saturating_sub(prefix);
+        match marker {
+            Some((_, start, end)) if at >= end => at - (end - start),
+            Some((_, start, _)) if at > start => start,
+            _ => at,
+        }
+    };
+    let submatches = data
+        .get("submatches")?
+        .as_array()?
+        .iter()
+        .filter_map(|s| {
+            let (start, end) = (
+                s.get("start")?.as_u64()? as usize,
+                s.get("end")?.as_u64()? as usize,
+            );
+            Some(Submatch {
+                text: rg_text(s.get("match")?)?,
+                start: offset(start),
+                end: offset(end),
+            })
+        })
+        .collect();
+    Some(Match {
+        path: rg_text(data.get("path")?)?,
+        inner_path,
+        page,
+        line_number: data.get("line_number").and_then(Value::as_u64),
+        text,
+        submatches,
+        confidence: marker.map(|(confidence, _, _)| confidence),
+    })
+}
+
+/// run `rg` (already given `--json`) and collect its matches, ordered by file and line
+pub fn collect(rg: Command, config: &RgaConfig) -> Result<Vec<Match>> {
+    let (matches, status) = collect_with(rg, config, |_| Ok(()))?;
+    // 1 is no matches
+    if !status.success() && status.code() != Some(1) {
+        anyhow::bail!("rg failed collecting the matches: {status}");
+    }
+    Ok(matches)
+}
+
+/// like [`collect`], but every event is also given to `on_event` as it arrives (e.g. to print it), and
+/// rg's exit status is returned instead of checked. rg is stopped if `on_event` fails
+pub fn collect_with(
+    mut rg: Command,
+    config: &RgaConfig,
+    mut on_event: impl FnMut(&Value) -> std::io::Result<()>,
+) -> Result<(Vec<Match>, ExitStatus)> {
+    let format = PageFormat::new(&config.postproc.options);
+    let mut child = rg.stdout(Stdio::piped()).spawn().context("running rg")?;
+    let stdout = child.stdout.take().context("rg stdout not piped")?;
+    let mut matches = vec![];
+    for event in BufReader::new(stdout).lines() {
+        let Ok(event) = serde_json::from_str::<Value>(&event?) else {
+            continue;
+        };
+        if let Err(e) = on_event(&event) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e.into());
+        }
+        if let Some(m) = match_of(&event, &format) {
+            matches.push(m);
+        }
+    }
+    let status = child.wait()?;
+    matches.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+    Ok((matches, status))
+}
+
+/// how rg lays out its text output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextLayout {
+    pub with_filename: bool,
+    pub line_number: bool,
+    /// the path on a line of its own before the lines of each file
+    pub heading: bool,
+    /// `--` between lines that are not adjacent, rg prints it when showing context
+    pub context_separator: bool,
+}
+
+impl TextLayout {
+    /// the layout rg uses for the arguments `args`, `tty` if its output is a terminal
+    pub fn from_args(args: &[OsString], tty: bool) -> Self {
+        let (_, roots) = crate::roots::split_roots(args);
+        let mut layout = TextLayout {
+            // rg leaves out the file name if it searches a single file
+            with_filename: roots.len() != 1 || Path::new(&roots[0]).is_dir(),
+            line_number: tty,
+            heading: tty,
+            context_separator: false,
+        };
+        for arg in args.iter().map(|a| a.to_string_lossy()) {
+            if arg == "--" {
+                break;
+            }
+            if let Some(long) = arg.strip_prefix("--") {
+                match long.split('=').next().unwrap_or_default() {
+                    "line-number" => layout.line_number = true,
+                    "no-line-number" => layout.line_number = false,
+                    "heading" => layout.heading = true,
+                    "no-heading" => layout.heading = false,
+                    "with-filename" => layout.with_filename = true,
+                    "no-filename" => layout.with_filename = false,
+                    "context" | "after-context" | "before-context" => layout.context_separator = true,
+                    _ => {}
+                }
+            } else if let Some(short) = arg.strip_prefix('-') {
+                for flag in short.chars() {
+                    match flag {
+                        'n' => layout.line_number = true,
+                        'N' => layout.line_number = false,
+                        'H' => layout.with_filename = true,
+                        'I' => layout.with_filename = false,
+                        'A' | 'B' | 'C' => layout.context_separator = true,
+                        _ => {}
+                    }
+                    // the rest is the value of the flag
+                    if "ABCEMTdefgjmrt".contains(flag) {
+                        break;
+                    }
+                }
+            }
+        }
+        layout
+    }
+}
+
+/// Prints the match and context events of rg's `--json` output as rg prints them without `--json`, so a
+/// single run of rg gives both the matches and the usual output. The output is not colored.
+pub struct TextPrinter<W: Write> {
+    out: W,
+    layout: TextLayout,
+    redactor: Option<Redactor>,
+    /// the file and line number of the last printed line
+    last: Option<(Vec<u8>, Option<u64>)>,
+}
+
+impl<W: Write> TextPrinter<W> {
+    /// `redact` to redact secrets like --rga-redact-secrets
+    pub fn new(out: W, layout: TextLayout, redact: bool) -> Self {
+        Self {
+            out,
+            layout,
+            redactor: redact.then(Redactor::new),
+            last: None,
+        }
+    }
+
+    pub fn print(&mut self, event: &Value) -> std::io::Result<()> {
+        let separator = match event.get("type").and_then(Value::as_str) {
+            Some("match") => b':',
+            Some("context") => b'-',
+            _ => return Ok(()),
+        };
+        let data = &event["data"];
+        let (Some(path), Some(mut text)) = (rg_bytes(&data["path"]), rg_bytes(&data["lines"])) else {
+            return Ok(());
+        };
+        let number = data.get("line_number").and_then(Value::as_u64);
+        // only written for the matches, see RgaConfig::confidence_markers
+        if let Ok(line) = std::str::from_utf8(&text)
+            && let Some((_, start, end)) = confidence::find_marker(line)
+        {
+            text.drain(start..end);
+        }
+        let same_file = self.last.as_ref().is_some_and(|(last, _)| *last == path);
+        if self.layout.heading && !same_file {
+            if self.last.is_some() {
+                self.out.write_all(b"\n")?;
+            }
+            self.out.write_all(&path)?;
+            self.out.write_all(b"\n")?;
+        } else if self.layout.context_separator
+            && let Some((_, last_number)) = &self.last
+            && !(same_file && number.is_some() && *last_number == number.map(|n| n - 1))
+        {
+            self.out.write_all(b"--\n")?;
+        }
+        let mut line = vec![];
+        if self.layout.with_filename && !self.layout.heading {
+            line.extend_from_slice(&path);
+            line.push(separator);
+        }
+        if let Some(number) = number.filter(|_| self.layout.line_number) {
+            line.extend_from_slice(number.to_string().as_bytes());
+            line.push(separator);
+        }
+        line.extend_from_slice(&text);
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        let line = match &mut self.redactor {
+            Some(redactor) => redactor.redact(&line).unwrap_or(line),
+            None => line,
+        };
+        self.out.write_all(&line)?;
+        self.last = Some((path, number));
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn write_lines<'a>(path: &str, matches: impl Iterator<Item = &'a Match>) -> Result<()> {
+    let mut out = std::io::BufWriter::new(
+        std::fs::File::create(path).with_context(|| format!("creating {path}"))?,
+    );
+    for m in matches {
+        serde_json::to_writer(&mut out, m)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn write_manifest(path: &str, matches: &[Match]) -> Result<()> {
+    write_lines(path, matches.iter())
+}
+
+/// write the matches of each file next to it. Sidecars of files without matches are left alone
+pub fn write_sidecars(matches: &[Match]) -> Result<()> {
+    let mut by_file: BTreeMap<&str, Vec<&Match>> = BTreeMap::new();
+    for m in matches {
+        by_file.entry(&m.path).or_default().push(m);
+    }
+    for (file, matches) in by_file {
+        write_lines(&format!("{file}{SIDECAR_SUFFIX}"), matches.into_iter())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn events() {
+        let event = r#"{"type":"match","data":{"path":{"text":"docs/a.zip"},"lines":{"text":"reports/q1.pdf: Page 3: revenue: up\n"},"line_number":12,"absolute_offset":0,"submatches":[{"match":{"text":"revenue"},"start":24,"end":31}]}}"#;
+        assert_eq!(
+            parse_event(event, &PageFormat::default()),
+            Some(Match {
+                path: "docs/a.zip".to_string(),
+                inner_path: Some("reports/q1.pdf".to_string()),
+                page: Some(3),
+                line_number: Some(12),
+                text: "revenue: up".to_string(),
+                submatches: vec![Submatch {
+                    text: "revenue".to_string(),
+                    start: 0,
+                    end: 7
+                }],
+                confidence: None,
+            })
+        );
+        let ocr = r#"{"type":"match","data":{"path":{"text":"docs/a.zip"},"lines":{"text":"scan.png: [confidence 91] Invoice 2024\n"},"line_number":1,"absolute_offset":0,"submatches":[{"match":{"text":"Invoice"},"start":26,"end":33}]}}"#;
+        let ocr = parse_event(ocr, &PageFormat::default()).unwrap();
+        assert_eq!(
+            (ocr.text.as_str(), ocr.confidence, (ocr.submatches[0].start, ocr.submatches[0].end)),
+            ("Invoice 2024", Some(91), (0, 7))
+        );
+        assert_eq!(parse_event(r#"{"type":"begin","data":{}}"#, &PageFormat::default()), None);
+        assert_eq!(
+            coordinates("outer.tar: inner/b.txt: Note: e.g. this", &PageFormat::default()),
+            (Some("outer.tar/inner/b.txt".to_string()), None, 24)
+        );
+        assert_eq!(coordinates("Page 2: x", &PageFormat::default()), (None, Some(2), 8));
+        let tabs = PageFormat {
+            separator: "\t".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            coordinates("outer.tar\tinner/b.txt\tPage 2\tNote: e.g. this", &tabs),
+            (Some("outer.tar/inner/b.txt".to_string()), Some(2), 29)
+        );
+    }
+
+    #[test]
+    fn printed_as_text() -> Result<()> {
+        let events = [
+            r#"{"type":"begin","data":{"path":{"text":"a.zip"}}}"#,
+            r#"{"type":"match","data":{"path":{"text":"a.zip"},"lines":{"text":"b.png: [confidence 91] Invoice\n"},"line_number":1,"submatches":[]}}"#,
+            r#"{"type":"context","data":{"path":{"text":"a.zip"},"lines":{"text":"b.png: total\n"},"line_number":2,"submatches":[]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"a.zip"},"lines":{"text":"c.txt: Invoice\n"},"line_number":9,"submatches":[]}}"#,
+            r#"{"type":"match","data":{"path":{"text":"d.pdf"},"lines":{"bytes":"UGFnZSAxOiBJbnZvaWNlIP8K"},"line_number":4,"submatches":[]}}"#,
+        ];
+        let print = |layout: TextLayout| -> Result<String> {
+            let mut out = vec![];
+            let mut printer = TextPrinter::new(&mut out, layout, false);
+            for event in events {
+                printer.print(&serde_json::from_str(event)?)?;
+            }
+            printer.finish()?;
+            Ok(String::from_utf8_lossy(&out).into_owned())
+        };
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let piped = TextLayout::from_args(&args(&["-C1", "Invoice"]), false);
+        assert_eq!(
+            print(piped)?,
+            "a.zip:b.png: Invoice\na.zip-b.png: total\n--\na.zip:c.txt: Invoice\n--\nd.pdf:Page 1: Invoice \u{fffd}\n"
+        );
+        let terminal = TextLayout::from_args(&args(&["Invoice"]), true);
+        assert_eq!(
+            print(terminal)?,
+            "a.zip\n1:b.png: Invoice\n2-b.png: total\n9:c.txt: Invoice\n\nd.pdf\n4:Page 1: Invoice \u{fffd}\n"
+        );
+        let single_file = TextLayout::from_args(&args(&["-nN", "Invoice", "Cargo.toml"]), false);
+        assert_eq!(
+            single_file,
+            TextLayout { with_filename: false, line_number: false, heading: false, context_separator: false }
+        );
+        Ok(())
+    }
+}
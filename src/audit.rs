@@ -0,0 +1,214 @@
+//! The audit log (`--rga-audit-log`): a JSON line for every external command rga runs over the searched
+//! files, with its arguments, duration and exit status, appended to `audit.jsonl` in the cache directory.
+//!
+//! rg starts many rga-preproc processes at once, each appends its lines with single writes.
+
+use crate::config::RgaConfig;
+use log::*;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub static AUDIT_LOG_FILE: &str = "audit.jsonl";
+
+struct Log {
+    path: PathBuf,
+    /// the file rga-preproc was started for
+    file: Option<PathBuf>,
+    /// passwords from the config, which adapters may pass as arguments
+    secrets: Vec<String>,
+}
+
+static LOG: OnceCell<Option<Log>> = OnceCell::new();
+
+impl Log {
+    fn new(config: &RgaConfig, file: Option<&Path>) -> Option<Self> {
+        config.audit_log.then(|| Log {
+            path: Path::new(&config.cache.path.0).join(AUDIT_LOG_FILE),
+            file: file.map(Path::to_path_buf),
+            secrets: config
+                .password
+                .iter()
+                .chain(config.document_passwords.values())
+                .chain(&config.archive_password)
+                .filter(|s| !s.is_empty())
+                .cloned()
+                .collect(),
+        })
+    }
+
+    fn redact(&self, s: &std::ffi::OsStr) -> String {
+        let s = s.to_string_lossy().into_owned();
+        self.secrets
+            .iter()
+            .fold(s, |s, secret| s.replace(secret.as_str(), "<redacted>"))
+    }
+}
+
+/// enables the log for this process if `audit_log` is set. `file` is the file rga-preproc runs on
+pub fn init(config: &RgaConfig, file: Option<&Path>) {
+    let _ = LOG.set(Log::new(config, file));
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time_unix_ms: u128,
+    pid: u32,
+    file: Option<&'a Path>,
+    program: String,
+    args: Vec<String>,
+    duration_ms: u128,
+    exit_code: Option<i32>,
+    /// why there is no exit code: it could not be started, was killed or was not waited for
+    error: Option<String>,
+}
+
+/// a started command, written to the log when dropped
+pub struct Spawned {
+    command: Option<Command>,
+    exit: Result<i32, String>,
+}
+
+struct Command {
+    log: &'static Log,
+    time: SystemTime,
+    started: Instant,
+    program: String,
+    args: Vec<String>,
+}
+
+impl Spawned {
+    /// records `cmd`, call before spawning it
+    pub fn start(cmd: &std::process::Command) -> Self {
+        Self::start_in(LOG.get().and_then(Option::as_ref), cmd)
+    }
+
+    fn start_in(log: Option<&'static Log>, cmd: &std::process::Command) -> Self {
+        Self {
+            command: log.map(|log| Command {
+                log,
+                time: SystemTime::now(),
+                started: Instant::now(),
+                program: log.redact(cmd.get_program()),
+                args: cmd.get_args().map(|a| log.redact(a)).collect(),
+            }),
+            exit: Err("not waited for".to_string()),
+        }
+    }
+
+    pub fn exited(mut self, status: &ExitStatus) {
+        self.exit = status.code().ok_or_else(|| status.to_string());
+    }
+
+    pub fn failed(mut self, error: &std::io::Error) {
+        self.exit = Err(error.to_string());
+    }
+
+    /// records the result of `Command::output`
+    pub fn finish(self, output: &std::io::Result<Output>) {
+        match output {
+            Ok(output) => self.exited(&output.status),
+            Err(e) => self.failed(e),
+        }
+    }
+}
+
+impl Drop for Spawned {
+    fn drop(&mut self) {
+        let Some(Command { log, time, started, program, args }) = self.command.take() else {
+            return;
+        };
+        let entry = Entry {
+            time_unix_ms: time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            pid: std::process::id(),
+            file: log.file.as_deref(),
+            program,
+            args,
+            duration_ms: started.elapsed().as_millis(),
+            exit_code: self.exit.as_ref().ok().copied(),
+            error: self.exit.as_ref().err().cloned(),
+        };
+        if let Err(e) = append(&log.path, &entry) {
+            warn!("could not write the audit log {}: {e:#}", log.path.display());
+        }
+    }
+}
+
+fn append(path: &Path, entry: &Entry) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&line)?;
+    Ok(())
+}
+
+/// `cmd.output()`, recorded in the audit log
+pub async fn output(cmd: &mut tokio::process::Command) -> std::io::Result<Output> {
+    let spawned = Spawned::start(cmd.as_std());
+    let output = cmd.output().await;
+    spawned.finish(&output);
+    output
+}
+
+/// `cmd.spawn()`, the record is finished with the exit status of the child
+pub fn spawn(cmd: &mut tokio::process::Command) -> std::io::Result<(tokio::process::Child, Spawned)> {
+    let spawned = Spawned::start(cmd.as_std());
+    match cmd.spawn() {
+        Ok(child) => Ok((child, spawned)),
+        Err(e) => {
+            spawned.failed(&e);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn commands() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = RgaConfig {
+            audit_log: true,
+            password: Some("hunter2".to_string()),
+            ..Default::default()
+        };
+        let mut log = Log::new(&config, Some(Path::new("report.pdf"))).unwrap();
+        log.path = dir.path().join(AUDIT_LOG_FILE);
+        let log: &'static Log = Box::leak(Box::new(log));
+        let run = |cmd: &mut std::process::Command| {
+            let spawned = Spawned::start_in(Some(log), cmd);
+            spawned.finish(&cmd.output());
+        };
+        run(std::process::Command::new("sh").args(["-c", "exit 3", "--password=hunter2"]));
+        run(&mut std::process::Command::new("rga-not-installed"));
+
+        let log = std::fs::read_to_string(dir.path().join(AUDIT_LOG_FILE))?;
+        let entries = log
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["file"], "report.pdf");
+        assert_eq!(entries[0]["program"], "sh");
+        assert_eq!(
+            entries[0]["args"],
+            serde_json::json!(["-c", "exit 3", "--password=<redacted>"])
+        );
+        assert_eq!(entries[0]["exit_code"], 3);
+        assert_eq!(entries[1]["exit_code"], serde_json::Value::Null);
+        assert!(entries[1]["error"].as_str().unwrap().contains("No such file"));
+        Ok(())
+    }
+}
@@ -42,10 +42,20 @@ fn main() -> anyhow::Result<()> {
     } else {
         format!("{preproc_exe} --files-with-matches --rga-cache-max-blob-len=10M")
     };
+    // the previews of this session keep the text of recently previewed files, removed on exit
+    let session_dir = tempfile::Builder::new()
+        .prefix("rga-fzf-")
+        .tempdir()
+        .context("creating the preview session directory")?;
+    let session = session_dir
+        .path()
+        .to_str()
+        .context("temporary directory is in non-unicode path")?
+        .replace('\'', "'\\''");
     let rg_preview = if let Some(p) = &args.rg_preview_params {
-        format!("{preproc_exe} --pretty --context 5 {p} {{q}} --rga-fzf-path=_{{}}")
+        format!("{preproc_exe} --pretty --context 5 {p} {{q}} --rga-fzf-path=_{{}} '--rga-fzf-session={session}'")
     } else {
-        format!("{preproc_exe} --pretty --context 5 {{q}} --rga-fzf-path=_{{}}")
+        format!("{preproc_exe} --pretty --context 5 {{q}} --rga-fzf-path=_{{}} '--rga-fzf-session={session}'")
     };
 
     let mut cmd = Command::new("fzf");
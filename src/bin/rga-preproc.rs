@@ -4,15 +4,19 @@ use rga::print_dur;
 use ripgrep_all as rga;
 
 use anyhow::Context;
-use log::debug;
+use log::{debug, warn};
 use std::time::Instant;
 use tokio::fs::File;
-use tokio::io::BufReader;
+use tokio::io::{AsyncWriteExt, BufReader};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let mut arg_arr: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if arg_arr.len() == 2 && arg_arr[1] == rga::HANDSHAKE_ARG {
+        println!("{}", rga::version_handshake());
+        return Ok(());
+    }
     let last = arg_arr.pop().expect("No filename specified");
     let config = rga::config::parse_args(arg_arr, true)?;
     //clap::App::new("rga-preproc").arg(Arg::from_usage())
@@ -20,6 +24,7 @@ async fn main() -> anyhow::Result<()> {
         let filepath = last;
         std::env::current_dir()?.join(filepath)
     };
+    rga::audit::init(&config, Some(&path));
 
     let i = File::open(&path)
         .await
@@ -31,11 +36,28 @@ async fn main() -> anyhow::Result<()> {
         .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
         .map(|d| d.as_millis() as i64);
 
+    // the text of a file rga-fzf previewed before
+    let session = rga::fzf_session::Session::of(&config).map(|session| {
+        let key = rga::fzf_session::Session::key(&path, file_mtime_unix_ms, meta.len(), &config);
+        (session, key)
+    });
+    let hot = match &session {
+        Some((session, key)) => session.open(key).await,
+        None => None,
+    };
+    let hot_missing = hot.is_none();
+
+    // hashed next to the extraction, reported after the text
+    let companion_check = config.verify.then(|| {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || rga::verify::check_companion(&path))
+    });
+
     let i = BufReader::new(i);
     let mut o = tokio::io::stdout();
     let ai = AdaptInfo {
         inp: Box::pin(i),
-        filepath_hint: path,
+        filepath_hint: path.clone(),
         is_real_file: true,
         file_mtime_unix_ms,
         line_prefix: "".to_string(),
@@ -45,9 +67,19 @@ async fn main() -> anyhow::Result<()> {
     };
 
     let start = Instant::now();
-    let mut oup = rga_preproc(ai).await.context("during preprocessing")?;
+    let mut oup = match hot {
+        Some(hot) => hot,
+        None if ai.config.from_cache_only => cached_output(&ai.config, &path)
+            .await?
+            .with_context(|| format!("{} is not in the cache", path.display()))?,
+        None => rga_preproc(ai).await.context("during preprocessing")?,
+    };
     debug!("finding and starting adapter took {}", print_dur(start));
-    let res = tokio::io::copy(&mut oup, &mut o).await;
+    let res = match &session {
+        Some((session, key)) if hot_missing => session.copy_pinned(key, oup, &mut o).await,
+        _ => tokio::io::copy(&mut oup, &mut o).await.map(|_| ()),
+    };
+    let cancelled = res.is_err();
     if let Err(e) = res {
         if e.kind() == std::io::ErrorKind::BrokenPipe {
             // happens if e.g. ripgrep detects binary data in the pipe so it cancels reading
@@ -56,6 +88,17 @@ async fn main() -> anyhow::Result<()> {
             Err(e).context("copying adapter output to stdout")?;
         }
     }
+    if let Some(check) = companion_check.filter(|_| !cancelled) {
+        let mismatch = check
+            .await?
+            .with_context(|| format!("verifying {}", path.display()))?;
+        if let Some(mismatch) = mismatch {
+            warn!("{}: {mismatch}", path.display());
+            o.write_all(format!("{}\n", mismatch.line("")).as_bytes()).await?;
+            o.flush().await?;
+        }
+    }
     debug!("running adapter took {} total", print_dur(start));
+    rga::run_stats::finish(start.elapsed())?;
     Ok(())
 }
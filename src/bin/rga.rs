@@ -3,16 +3,17 @@ use rga::adapters::custom::map_exe_error;
 use rga::adapters::*;
 use rga::config::{RgaConfig, split_args};
 use rga::matching::*;
-use rga::print_dur;
+use rga::{print_bytes, print_dur};
 use ripgrep_all as rga;
 use clap::CommandFactory;
 
 use schemars::schema_for;
+use std::io::IsTerminal;
 use std::process::Command;
 use std::time::Instant;
 
 fn list_adapters(args: RgaConfig) -> Result<()> {
-    let (enabled_adapters, disabled_adapters) = get_all_adapters(args.custom_adapters);
+    let (enabled_adapters, disabled_adapters) = get_configured_adapters(args.custom_adapters.clone(), &args)?;
 
     println!("Adapters:\n");
     let print = |adapter: std::sync::Arc<dyn FileAdapter>| {
@@ -54,14 +55,35 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
     for adapter in disabled_adapters {
         print(adapter)
     }
+    let missing = FEATURE_GROUPS
+        .iter()
+        .filter(|(_, compiled)| !compiled)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        println!(
+            "This rga was built without the adapters of the features: {}. Rebuild with '--features {}' to include them.",
+            missing.join(", "),
+            missing.join(",")
+        );
+    }
     Ok(())
 }
-fn doctor() -> Result<()> {
+fn doctor(config: RgaConfig) -> Result<()> {
     println!("Checking ripgrep-all dependencies...\n");
-    let binaries = ["rg", "pandoc", "pdftotext", "ffmpeg", "ffprobe", "tesseract"];
-    for bin in binaries {
+    let (enabled_adapters, disabled_adapters) = get_configured_adapters(config.custom_adapters.clone(), &config)?;
+    let mut binaries = vec!["rg".to_string()];
+    for adapter in enabled_adapters.iter().chain(&disabled_adapters) {
+        for dep in &adapter.metadata().capabilities.external_deps {
+            if !binaries.contains(dep) {
+                binaries.push(dep.clone());
+            }
+        }
+    }
+    let path = rga::bundled::search_path()?;
+    for bin in &binaries {
         let arg = if bin == "pdftotext" { "-v" } else { "--version" };
-        match Command::new(bin).arg(arg).output() {
+        match Command::new(bin).arg(arg).env("PATH", &path).output() {
             Ok(output) => {
                 let stdout = String::from_utf8_lossy(&output.stdout);
                 let stderr = String::from_utf8_lossy(&output.stderr);
@@ -78,8 +100,17 @@ fn doctor() -> Result<()> {
     Ok(())
 }
 
-fn clear_cache(config: &RgaConfig) -> Result<()> {
+async fn clear_cache(config: &RgaConfig, filter: Option<&str>) -> Result<()> {
+    use rga::preproc_cache::clear;
     let path = std::path::Path::new(&config.cache.path.0);
+    if let Some(filter) = filter {
+        let (filter, what) = cache_filter(config, filter)?;
+        match clear(config, filter).await? {
+            Some(removed) => println!("✅ Removed {removed} entries {what} from the cache at {}.", path.display()),
+            None => println!("ℹ️ Cache at {} does not exist.", path.display()),
+        }
+        return Ok(());
+    }
     if path.exists() {
         std::fs::remove_dir_all(path)?;
         println!("✅ Cache at {} cleared.", path.display());
@@ -89,6 +120,126 @@ fn clear_cache(config: &RgaConfig) -> Result<()> {
     Ok(())
 }
 
+/// the cache entries `filter` selects and their description: those of a namespace for
+/// `namespace:NAME`, of an adapter if it is the name of one, else those of the files whose path
+/// matches it as a glob
+fn cache_filter<'a>(config: &RgaConfig, filter: &'a str) -> Result<(rga::preproc_cache::ClearFilter<'a>, String)> {
+    use rga::preproc_cache::ClearFilter;
+    if let Some(namespace) = filter.strip_prefix("namespace:") {
+        return Ok((ClearFilter::Namespace(namespace), format!("in namespace {namespace}")));
+    }
+    let (enabled, disabled) = get_configured_adapters(config.custom_adapters.clone(), config)?;
+    Ok(if enabled.iter().chain(&disabled).any(|a| a.metadata().name == filter) {
+        (ClearFilter::Adapter(filter), format!("of adapter {filter}"))
+    } else {
+        (ClearFilter::Glob(filter), format!("of files matching {filter}"))
+    })
+}
+
+async fn list_cache_namespaces(config: &RgaConfig) -> Result<()> {
+    let Some(namespaces) = rga::preproc_cache::namespaces(config).await? else {
+        println!("ℹ️ Cache at {} does not exist.", config.cache.path.0);
+        return Ok(());
+    };
+    println!("{:>8}  {:>10}  {:>10}  namespace", "entries", "compressed", "text");
+    for (name, usage) in namespaces {
+        let name = match name {
+            None => "(none)".to_string(),
+            // an automatic namespace of a project that was deleted, see --rga-cache-clear=namespace:
+            Some(name) if std::path::Path::new(&name).is_absolute() && !std::path::Path::new(&name).exists() => {
+                format!("{name} (deleted)")
+            }
+            Some(name) => name,
+        };
+        println!(
+            "{:>8}  {:>10}  {:>10}  {name}",
+            usage.entries,
+            print_bytes(usage.compressed_bytes as f64),
+            print_bytes(usage.text_bytes as f64)
+        );
+    }
+    Ok(())
+}
+
+async fn export_cache(config: &RgaConfig, to: &str, filter: Option<&str>) -> Result<()> {
+    let path = std::path::Path::new(&config.cache.path.0);
+    let (filter, what) = match filter {
+        Some(filter) => {
+            let (filter, what) = cache_filter(config, filter)?;
+            (Some(filter), format!(" {what}"))
+        }
+        None => (None, String::new()),
+    };
+    match rga::preproc_cache::export(config, std::path::Path::new(to), filter).await? {
+        Some(exported) => println!("✅ Exported {exported} entries{what} from the cache at {} to {to}.", path.display()),
+        None => println!("ℹ️ Cache at {} does not exist.", path.display()),
+    }
+    Ok(())
+}
+
+async fn print_cache_key(config: &RgaConfig, path: &str) -> Result<()> {
+    use rga::preproc_cache::{EntryStatus, entry_status};
+    let Some((adapter, key)) = rga::preproc::cache_key_for(config, std::path::Path::new(path)).await? else {
+        println!("No adapter matches {path}, rg searches it directly.");
+        return Ok(());
+    };
+    println!("{}", serde_json::to_string_pretty(&key.material())?);
+    println!("key: {}", key.digest());
+    println!("file mtime: {} ms, size: {} bytes", key.file_mtime_unix_ms, key.file_size);
+    let status = if config.cache.disabled {
+        "caching disabled".to_string()
+    } else if !adapter.metadata().capabilities.deterministic {
+        format!("never cached, the output of {} is not deterministic", key.adapter)
+    } else if config.cache.cache_type != "sqlite" {
+        format!("unknown, cache type {} cannot be inspected", config.cache.cache_type)
+    } else {
+        match entry_status(config, &key).await? {
+            EntryStatus::Fresh => "cached".to_string(),
+            EntryStatus::Stale { file_mtime_unix_ms, file_size } => format!(
+                "stale, cached for mtime {file_mtime_unix_ms} ms, size {file_size} bytes"
+            ),
+            EntryStatus::Expired => "expired, older than its cache.ttl".to_string(),
+            EntryStatus::Missing => "not cached".to_string(),
+        }
+    };
+    println!("status: {status}");
+    Ok(())
+}
+
+/// prints the text rg searches for the file at `path`, from the cache if it is there
+async fn show_extracted(config: &RgaConfig, path: &str) -> Result<()> {
+    let path = std::env::current_dir()?.join(path);
+    let mut text = match rga::preproc::cached_output(config, &path).await? {
+        Some(text) => {
+            eprintln!("{}: from the cache", path.display());
+            text
+        }
+        None if config.from_cache_only => {
+            anyhow::bail!("{} is not in the cache", path.display())
+        }
+        None if rga::preproc::cache_key_for(config, &path).await?.is_none() => {
+            eprintln!("{}: no adapter matches, rg searches the file as it is", path.display());
+            Box::pin(tokio::fs::File::open(&path).await?)
+        }
+        None => {
+            eprintln!("{}: not in the cache, running the adapters", path.display());
+            let ai = rga::adapters::AdaptInfo {
+                inp: Box::pin(tokio::io::BufReader::new(tokio::fs::File::open(&path).await?)),
+                filepath_hint: path.clone(),
+                is_real_file: true,
+                file_mtime_unix_ms: None,
+                line_prefix: String::new(),
+                archive_recursion_depth: 0,
+                postprocess: !config.no_prefix_filenames,
+                config: config.clone(),
+            };
+            rga::preproc::rga_preproc(ai).await?
+        }
+    };
+    tokio::io::copy(&mut text, &mut tokio::io::stdout()).await?;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // set debugging as early as possible
@@ -101,24 +252,87 @@ async fn main() -> anyhow::Result<()> {
     }
 
     let (config, mut passthrough_args) = split_args(false)?;
+    rga::audit::init(&config, None);
 
     if config.doctor {
-        return doctor();
+        return doctor(config);
+    }
+    if let Some(spec) = &config.adapter_install {
+        return rga::registry::install_adapter(spec, &config);
+    }
+    if let Some(filter) = &config.cache_clear {
+        return clear_cache(&config, filter.as_deref()).await;
+    }
+    if let Some(path) = &config.cache_key {
+        return print_cache_key(&config, path).await;
+    }
+    if let Some(path) = &config.show_extracted {
+        return show_extracted(&config, path).await;
+    }
+    if let Some(to) = &config.cache_export {
+        return export_cache(&config, to, config.cache_export_filter.as_deref()).await;
+    }
+    if let Some(from) = &config.cache_import {
+        let imported = rga::preproc_cache::import(&config, std::path::Path::new(from)).await?;
+        println!("✅ Imported {imported} entries from {from} into the cache at {}.", config.cache.path.0);
+        return Ok(());
+    }
+    if config.cache_stats {
+        match rga::preproc_cache::stats(&config).await? {
+            Some(stats) => print!("{stats}"),
+            None => println!("ℹ️ Cache at {} does not exist.", config.cache.path.0),
+        }
+        return Ok(());
+    }
+    if config.cache_namespaces {
+        return list_cache_namespaces(&config).await;
+    }
+    if let Some(path) = &config.stats {
+        print!("{}", rga::stats::collect(&config, std::path::Path::new(path)).await?);
+        return Ok(());
     }
-    if config.cache_clear {
-        return clear_cache(&config);
+    if let Some(path) = &config.dupes {
+        for dupes in rga::dupes::find(&config, std::path::Path::new(path)).await? {
+            println!("{dupes}");
+        }
+        return Ok(());
+    }
+    if let Some(path) = &config.wc {
+        print!("{}", rga::wc::collect(&config, std::path::Path::new(path)).await?);
+        return Ok(());
+    }
+    if let Some(path) = &config.warm {
+        print!("{}", rga::warm::warm(&config, std::path::Path::new(path)).await?);
+        return Ok(());
+    }
+    if let Some(dir) = &config.extract_all {
+        if let Some(flag) = passthrough_args.iter().find(|a| a.to_string_lossy().starts_with('-')) {
+            anyhow::bail!("--rga-extract-all only takes paths, not {}", flag.to_string_lossy());
+        }
+        let roots: Vec<std::path::PathBuf> = if passthrough_args.is_empty() {
+            vec![".".into()]
+        } else {
+            passthrough_args.iter().map(Into::into).collect()
+        };
+        print!("{}", rga::extract::extract_all(&config, &roots, std::path::Path::new(dir)).await?);
+        return Ok(());
     }
     if config.cache_prune {
         println!("Pruning cache is not fully implemented yet, clearing cache instead...");
-        return clear_cache(&config);
+        return clear_cache(&config, None).await;
     }
     if config.daemon {
         let path = std::path::Path::new(&config.cache.path.0);
         let port = config.cache.daemon_port;
-        rga::daemon::run_daemon(path, port).await?;
+        let max_connections = rga::concurrency::max_subprocesses(&config);
+        rga::daemon::run_daemon(path, port, max_connections).await?;
         return Ok(());
     }
 
+    if config.print_config {
+        print!("{}", rga::config::annotated(&config)?);
+        return Ok(());
+    }
     if config.print_config_schema {
         println!("{}", serde_json::to_string_pretty(&schema_for!(RgaConfig))?);
         return Ok(());
@@ -135,7 +349,7 @@ async fn main() -> anyhow::Result<()> {
         passthrough_args.push(std::ffi::OsString::from(&path[1..]));
     }
 
-    if passthrough_args.is_empty() {
+    if passthrough_args.is_empty() && !config.print_rg_args && config.monitor.is_none() {
         // rg would show help. Show own help instead.
         RgaConfig::command().print_help()?;
         println!();
@@ -145,13 +359,17 @@ async fn main() -> anyhow::Result<()> {
     let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters, &config)?;
     log::info!("enabled adapters: {}", adapters.iter().map(|a| a.metadata().name.clone()).collect::<Vec<_>>().join(", "));
 
+    let extensions = adapters
+        .iter()
+        .flat_map(|a| &a.metadata().fast_matchers)
+        .map(|m| match m {
+            FastFileMatcher::FileExtension(ext) => ext.clone(),
+        })
+        .collect::<Vec<_>>();
     let pre_glob = if !config.accurate {
-        let extensions = adapters
+        let extensions = extensions
             .iter()
-            .flat_map(|a| &a.metadata().fast_matchers)
-            .flat_map(|m| match m {
-                FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
-            })
+            .flat_map(|ext| [ext.clone(), ext.to_ascii_uppercase()])
             .collect::<Vec<_>>()
             .join(",");
         format!("*.{{{extensions}}}")
@@ -160,9 +378,20 @@ async fn main() -> anyhow::Result<()> {
     };
     log::info!("pre-glob: {}", pre_glob);
 
-    let new_path = compute_exe_path()?;
+    // remote urls (sftp://...) are mirrored locally since rg can only search the local file system
+    let wanted = if !config.remote_all && !config.accurate {
+        rga::remote::Wanted::extensions(extensions)
+    } else {
+        rga::remote::Wanted::all()
+    };
+    let mut passthrough_args = rga::remote::mirror_remote_args(passthrough_args, &config, &wanted).await?;
+    if let Some(image) = &config.docker_image {
+        passthrough_args.push(rga::docker::image_rootfs(image, &config).await?.into_os_string());
+    }
 
-    let rg_args = vec![
+    let new_path = rga::bundled::search_path()?;
+
+    let rg_args = [
         "--no-line-number",
         // smart case by default because within weird files
         // we probably can't really trust casing anyways
@@ -171,26 +400,214 @@ async fn main() -> anyhow::Result<()> {
 
     let exe = std::env::current_exe().context("Could not get executable location")?;
     let preproc_exe = exe.with_file_name("rga-preproc");
+    check_preproc(&preproc_exe)?;
+    rga::preproc_cache::check_version(&config).await?;
+
+    let pre_args: Vec<std::ffi::OsString> = rg_args
+        .iter()
+        .map(Into::into)
+        .chain(["--pre".into(), preproc_exe.clone().into_os_string()])
+        .chain(["--pre-glob".into(), pre_glob.clone().into()])
+        .chain(["--glob".into(), format!("!*{}", rga::annotate::SIDECAR_SUFFIX).into()])
+        .collect();
+
+    if config.print_rg_args {
+        return print_rg_args(&config, &pre_args);
+    }
+
+    // the confidence of OCR lines is only written for output in JSON
+    let rga_config_for = |json: bool| {
+        let config = RgaConfig { confidence_markers: json, ..config.clone() };
+        serde_json::to_string(&config).context("Could not serialize the config for rga-preproc")
+    };
+    let (rga_config, rga_config_json) = (rga_config_for(false)?, rga_config_for(true)?);
+    // not in RGA_CONFIG, which other processes of the user can read
+    let password_lines = config.archive_password_lines_file()?;
+    let password_env = password_lines
+        .as_ref()
+        .map(|file| (rga::config::ARCHIVE_PASSWORD_LINES_ENV, file.path().to_owned()));
+
+    if let Some(name) = &config.monitor {
+        let search = |args: &[String]| {
+            let mut cmd = Command::new("rg");
+            cmd.args(&pre_args)
+                .args(["--json", "--line-number"])
+                .args(args)
+                .env("RGA_CONFIG", &rga_config_json)
+                .env("PATH", &new_path)
+                .envs(password_env.clone());
+            cmd
+        };
+        // like rg, 1 if nothing new was found
+        if !rga::monitor::run(&config, name.as_deref(), search)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let rg_command_for = |extra_args: &[&str], args: &[std::ffi::OsString]| {
+        let json = extra_args.contains(&"--json") || args.iter().any(|a| a == "--json");
+        let mut cmd = Command::new("rg");
+        cmd.args(&pre_args)
+            .args(extra_args)
+            .args(args)
+            .env("RGA_CONFIG", if json { &rga_config_json } else { &rga_config })
+            .env("PATH", &new_path)
+            .envs(password_env.clone());
+        cmd
+    };
+    let rg_command = |extra_args: &[&str]| rg_command_for(extra_args, &passthrough_args);
+
+    #[cfg(feature = "tui")]
+    if config.tui {
+        let preproc = |path: &str| {
+            let mut cmd = Command::new(&preproc_exe);
+            cmd.arg(path)
+                .env("RGA_CONFIG", &rga_config)
+                .env("PATH", &new_path)
+                .envs(password_env.clone());
+            cmd
+        };
+        return rga::tui::run(rg_command(&["--json", "--line-number"]), &preproc, &adapters, &config);
+    }
+    #[cfg(not(feature = "tui"))]
+    if config.tui {
+        anyhow::bail!("rga was built without the tui feature, which is needed for --rga-tui");
+    }
 
     let before = Instant::now();
-    let mut cmd = Command::new("rg");
-    cmd.args(rg_args)
-        .arg("--pre")
-        .arg(preproc_exe)
-        .arg("--pre-glob")
-        .arg(pre_glob)
-        .args(passthrough_args)
-        .env("RGA_CONFIG", serde_json::to_string(&config).unwrap_or_else(|_| String::new()))
-        .env("PATH", new_path)
-        .stderr(std::process::Stdio::piped());
+    let sink = rga::sink::Sink::from_config(&config)?;
+    let out = sink.open()?;
+    let to_stdout = sink == rga::sink::Sink::Stdout;
+    // rg only colors its output for a terminal, not for the pipe to the redaction
+    let color = config.redact_secrets
+        && to_stdout
+        && std::io::stdout().is_terminal()
+        && !passthrough_args.iter().any(|a| a.to_string_lossy().starts_with("--color"));
+    let mut cmd = rg_command(if color { &["--color=always"] } else { &[] });
+    cmd.stderr(std::process::Stdio::piped());
+    // the rga-preproc processes append what they did, see --rga-search-stats
+    let run_stats = if config.search_stats {
+        let file = tempfile::NamedTempFile::new()?;
+        cmd.env(rga::run_stats::RUN_STATS_ENV, file.path());
+        Some(file)
+    } else {
+        None
+    };
+    let file_list = config.files_from.is_some() || config.files0_from.is_some();
+    if config.matches_manifest.is_some() || config.matches_sidecars {
+        // a single rg with --json gives the matches and the output, the roots are not searched in parallel
+        let mut args = passthrough_args.clone();
+        if file_list {
+            let (rest, mut roots) = rga::roots::split_roots(&passthrough_args);
+            if let Some(list) = &config.files_from {
+                roots.extend(rga::roots::read_list(list, b'\n')?);
+            }
+            if let Some(list) = &config.files0_from {
+                roots.extend(rga::roots::read_list(list, b'\0')?);
+            }
+            if roots.is_empty() {
+                // like rg, nothing found
+                std::process::exit(1);
+            }
+            args = rest;
+            if !args.iter().any(|a| a == "--") {
+                args.push("--".into());
+            }
+            args.extend(roots);
+        }
+        let mut cmd = rg_command_for(&["--json", "--line-number"], &args);
+        if let Some(file) = &run_stats {
+            cmd.env(rga::run_stats::RUN_STATS_ENV, file.path());
+        }
+        log::debug!("rg command to run: {:?}", cmd);
+        let layout = rga::annotate::TextLayout::from_args(&args, to_stdout && std::io::stdout().is_terminal());
+        let mut printer = rga::annotate::TextPrinter::new(out, layout, config.redact_secrets);
+        let (matches, status) = match rga::annotate::collect_with(cmd, &config, |event| printer.print(event)) {
+            // e.g. piped to head, like rg itself stop quietly
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => {
+                return Ok(());
+            }
+            collected => collected?,
+        };
+        printer.finish()?;
+        log::debug!("running rg took {}", print_dur(before));
+        if let Some(path) = &config.matches_manifest {
+            rga::annotate::write_manifest(path, &matches)?;
+        }
+        if config.matches_sidecars {
+            rga::annotate::write_sidecars(&matches)?;
+        }
+        if let Some(file) = run_stats {
+            eprint!("{}", rga::run_stats::RunStats::read(file.path(), before.elapsed())?);
+        }
+        if !status.success() {
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        return Ok(());
+    }
+    if config.parallel.is_some() || file_list {
+        let (args, mut roots) = rga::roots::split_roots(&passthrough_args);
+        if let Some(list) = &config.files_from {
+            roots.extend(rga::roots::read_list(list, b'\n')?);
+        }
+        if let Some(list) = &config.files0_from {
+            roots.extend(rga::roots::read_list(list, b'\0')?);
+        }
+        if !roots.is_empty() || file_list {
+            let color = to_stdout
+                && std::io::stdout().is_terminal()
+                && !args.iter().any(|a| a.to_string_lossy().starts_with("--color"));
+            let search = |extra_args: &[&str], args: &[std::ffi::OsString]| {
+                let mut cmd = rg_command_for(extra_args, args);
+                if let Some(file) = &run_stats {
+                    cmd.env(rga::run_stats::RUN_STATS_ENV, file.path());
+                }
+                cmd
+            };
+            let code = search_roots(&config, &args, roots, color, &search, out)?;
+            log::debug!("running rg took {}", print_dur(before));
+            if let Some(file) = run_stats {
+                eprint!("{}", rga::run_stats::RunStats::read(file.path(), before.elapsed())?);
+            }
+            if code != 0 {
+                std::process::exit(code);
+            }
+            return Ok(());
+        }
+    }
+    if config.redact_secrets || !to_stdout {
+        cmd.stdout(std::process::Stdio::piped());
+    }
     log::debug!("rg command to run: {:?}", cmd);
     let mut child = cmd
         .spawn()
         .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
 
+    if let Some(stdout) = child.stdout.take() {
+        let stdout = std::io::BufReader::new(stdout);
+        let copied = if config.redact_secrets {
+            rga::redact::copy_redacted(stdout, out)
+        } else {
+            copy_lines(stdout, out)
+        };
+        match copied {
+            // e.g. piped to head, like rg itself stop quietly
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(());
+            }
+            copied => copied?,
+        }
+    }
+
     let result = child.wait()?;
 
     log::debug!("running rg took {}", print_dur(before));
+    if let Some(file) = run_stats {
+        eprint!("{}", rga::run_stats::RunStats::read(file.path(), before.elapsed())?);
+    }
     if !result.success() {
         if let Some(mut stderr) = child.stderr.take() {
             use std::io::Read as _;
@@ -205,19 +622,122 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// add the directory that contains `rga` to PATH, so rga-preproc can find pandoc etc (if we are on Windows where we include dependent binaries)
-fn compute_exe_path() -> Result<std::ffi::OsString> {
-    use std::env;
-    let mut exe = env::current_exe().context("Could not get executable location")?;
-    // let preproc_exe = exe.with_file_name("rga-preproc");
-    exe.pop(); // dirname
-
-    let path = env::var_os("PATH").unwrap_or_default();
-    let paths = env::split_paths(&path).collect::<Vec<_>>();
-    // prepend: prefer bundled versions to system-installed versions of binaries
-    // solves https://github.com/phiresky/ripgrep-all/issues/32
-    // may be somewhat of a security issue if rga binary is in installed in unprivileged locations
-    let paths = [&[exe.to_owned(), exe.join("lib")], &paths[..]].concat();
-    let new_path = env::join_paths(paths)?;
-    Ok(new_path)
+/// how many listed files a worker of `--rga-parallel` gives to one rg
+const FILES_PER_WORKER: usize = 64;
+
+/// searches every root with its own rg (and files in batches), see `--rga-parallel` and `--rga-files-from`.
+/// Returns the exit code for all of them
+fn search_roots(
+    config: &RgaConfig,
+    args: &[std::ffi::OsString],
+    roots: Vec<std::ffi::OsString>,
+    color: bool,
+    rg_command: &dyn Fn(&[&str], &[std::ffi::OsString]) -> Command,
+    out: Box<dyn std::io::Write>,
+) -> Result<i32> {
+    if roots.is_empty() {
+        // like rg, nothing found
+        return Ok(1);
+    }
+    let workers = match config.parallel {
+        Some(Some(n)) => n.max(1),
+        Some(None) => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+        None => 1,
+    };
+    // in parallel, each worker searches a few files at a time
+    let max_files = if workers > 1 { FILES_PER_WORKER } else { usize::MAX };
+    let batches = rga::roots::batches(roots, max_files);
+    let workers = workers.min(batches.len());
+    // rg leaves out the file name if it searches a single file, a batch can be one. A --no-filename of the
+    // user comes later and still wins
+    let mut extra_args = vec!["--with-filename".to_string()];
+    // rg only colors its output for a terminal, not for the pipe to rga
+    if color {
+        extra_args.push("--color=always".to_string());
+    }
+    // the searches together use about as many threads as a single rg
+    if !args.iter().any(|a| {
+        let a = a.to_string_lossy();
+        a.starts_with("-j") || a.starts_with("--threads")
+    }) {
+        extra_args.push(format!("--threads={}", rga::roots::rg_threads(workers)));
+    }
+    let end_of_flags = !args.iter().any(|a| a == "--");
+    let commands = batches
+        .into_iter()
+        .map(|paths| {
+            let mut extra_args = extra_args.clone();
+            // rg searches files it was given one after the other with one thread, in parallel they are
+            // searched by the other workers
+            let files = workers > 1 && !std::path::Path::new(&paths[0]).is_dir();
+            if let Some(threads) = extra_args.iter_mut().find(|a| files && a.starts_with("--threads=")) {
+                *threads = "--threads=1".to_string();
+            }
+            let extra_args = extra_args.iter().map(String::as_str).collect::<Vec<_>>();
+            let mut args = args.to_vec();
+            if end_of_flags {
+                // paths from a list may start with a dash
+                args.push("--".into());
+            }
+            args.extend(paths);
+            let cmd = rg_command(&extra_args, &args);
+            log::debug!("rg command to run: {:?}", cmd);
+            cmd
+        })
+        .collect();
+    let mut merged = rga::roots::Merged::start(commands, workers);
+    let input = std::io::BufReader::new(&mut merged);
+    let copied = if config.redact_secrets {
+        rga::redact::copy_redacted(input, out)
+    } else {
+        copy_lines(input, out)
+    };
+    match copied {
+        // e.g. piped to head, the searches are stopped when `merged` is dropped
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(0),
+        copied => copied?,
+    }
+    Ok(merged.exit_code())
+}
+
+/// copies rg's output to a sink line by line
+fn copy_lines(mut inp: impl std::io::BufRead, mut out: impl std::io::Write) -> std::io::Result<()> {
+    let mut line = vec![];
+    while inp.read_until(b'\n', &mut line)? > 0 {
+        out.write_all(&line)?;
+        line.clear();
+    }
+    out.flush()
+}
+
+/// the arguments for plain rg, in the format of a ripgrep config file
+fn print_rg_args(config: &RgaConfig, pre_args: &[std::ffi::OsString]) -> Result<()> {
+    // rga-preproc only reads its configuration from the environment. The output ends up in config files
+    // and terminals, so the passwords are left out
+    println!("# run rg with RGA_CONFIG={}", rga::config::redacted(config)?);
+    for arg in pre_args {
+        println!("{}", arg.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// rg runs rga-preproc for every file, one from another install would fail on each or write a cache this
+/// rga does not understand
+fn check_preproc(preproc_exe: &std::path::Path) -> Result<()> {
+    let expected = rga::version_handshake();
+    let output = Command::new(preproc_exe)
+        .arg(rga::HANDSHAKE_ARG)
+        .output()
+        .map_err(|e| map_exe_error(e, "rga-preproc", "rga-preproc is installed together with rga, reinstall rga."))
+        .with_context(|| format!("Could not run {}", preproc_exe.display()))?;
+    let found = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if found != expected {
+        let found = if found.is_empty() { "an older version".to_string() } else { found };
+        anyhow::bail!(
+            "{} is from another install of rga ({found}, this rga is {expected}). \
+             Reinstall rga so that rga and rga-preproc are of the same version, or remove the stale one.",
+            preproc_exe.display()
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,65 @@
+//! Helper tools (pdftotext, ffmpeg, ...) shipped together with rga. A self-contained bundle puts them in a
+//! `libexec` directory next to the rga executables, or in `libexec/rga` next to the `bin` directory of an
+//! installation prefix. They are found before the ones installed on the system.
+
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// the existing libexec directories for executables in `exe_dir`
+pub fn libexec_dirs(exe_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![exe_dir.join("libexec")];
+    if let Some(prefix) = exe_dir.parent() {
+        dirs.push(prefix.join("libexec").join("rga"));
+    }
+    dirs.retain(|d| d.is_dir());
+    dirs
+}
+
+/// PATH for rg and the adapters: the directory of the rga executables (for rga-preproc), the bundled
+/// tools, then the system PATH
+pub fn search_path() -> Result<OsString> {
+    let exe = std::env::current_exe().context("Could not get executable location")?;
+    let exe_dir = exe.parent().context("executable has no directory")?;
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    // solves https://github.com/phiresky/ripgrep-all/issues/32
+    // may be somewhat of a security issue if rga binary is in installed in unprivileged locations
+    let paths = [exe_dir.to_path_buf()]
+        .into_iter()
+        .chain(libexec_dirs(exe_dir))
+        .chain([exe_dir.join("lib")])
+        .chain(std::env::split_paths(&path));
+    Ok(std::env::join_paths(paths)?)
+}
+
+/// where a tool is found in the search path
+pub fn find(binary: &str) -> Option<PathBuf> {
+    let path = search_path().ok()?;
+    let name = if cfg!(windows) {
+        format!("{binary}.exe")
+    } else {
+        binary.to_string()
+    };
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&name))
+        .find(|file| file.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_layouts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let bin = dir.path().join("bin");
+        std::fs::create_dir_all(bin.join("libexec"))?;
+        std::fs::create_dir_all(dir.path().join("libexec/rga"))?;
+        assert_eq!(
+            libexec_dirs(&bin),
+            vec![bin.join("libexec"), dir.path().join("libexec/rga")]
+        );
+        assert_eq!(libexec_dirs(&bin.join("libexec")), Vec::<PathBuf>::new());
+        Ok(())
+    }
+}
@@ -1,17 +1,76 @@
+use std::io::Write;
 use std::{future::Future, pin::Pin};
 
 use anyhow::{Context, Result};
-use async_compression::tokio::write::ZstdEncoder;
 use async_stream::stream;
 
+use crate::preproc_cache::{Blob, Codec};
 use crate::to_io_err;
 use log::*;
-use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::io::AsyncRead;
 use tokio_stream::StreamExt;
 use tokio_util::io::{ReaderStream, StreamReader};
 
 type FinishHandler =
-    dyn FnOnce((u64, Option<Vec<u8>>)) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send;
+    dyn FnOnce((u64, Option<Blob>)) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send;
+
+/// compresses into memory with one of the codecs of `cache.compression`
+enum Encoder {
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Lz4(lz4_flex::frame::FrameEncoder<Vec<u8>>),
+    None(Vec<u8>),
+}
+
+impl Encoder {
+    fn new(codec: Codec, compression_level: i32) -> Result<Self> {
+        Ok(match codec {
+            Codec::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                compression_level,
+            )?),
+            Codec::Lz4 => Encoder::Lz4(lz4_flex::frame::FrameEncoder::new(Vec::new())),
+            Codec::None => Encoder::None(Vec::new()),
+        })
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Encoder::Zstd(w) => w.write_all(bytes),
+            Encoder::Lz4(w) => w.write_all(bytes),
+            Encoder::None(w) => w.write_all(bytes),
+        }
+    }
+
+    /// what was compressed so far, without the data still buffered in the encoder
+    fn compressed_len(&self) -> usize {
+        match self {
+            Encoder::Zstd(w) => w.get_ref().len(),
+            Encoder::Lz4(w) => w.get_ref().len(),
+            Encoder::None(w) => w.len(),
+        }
+    }
+
+    /// the compressed output of `text_len` bytes
+    fn finish(self, text_len: u64) -> Result<Blob> {
+        Ok(match self {
+            Encoder::Zstd(w) => Blob {
+                codec: Codec::Zstd,
+                data: w.finish()?,
+                text_len: Some(text_len),
+            },
+            Encoder::Lz4(w) => Blob {
+                codec: Codec::Lz4,
+                data: w.finish()?,
+                text_len: Some(text_len),
+            },
+            Encoder::None(data) => Blob {
+                codec: Codec::None,
+                data,
+                text_len: Some(text_len),
+            },
+        })
+    }
+}
 /**
  * wrap a AsyncRead so that it is passthrough,
  * but also the written data is compressed and written into a buffer,
@@ -20,29 +79,27 @@ type FinishHandler =
 pub fn async_read_and_write_to_cache<'a>(
     inp: impl AsyncRead + Send + 'a,
     max_cache_size: usize,
+    codec: Codec,
     compression_level: i32,
     on_finish: Box<FinishHandler>,
 ) -> Result<Pin<Box<dyn AsyncRead + Send + 'a>>> {
     let inp = Box::pin(inp);
-    let mut zstd_writer = Some(ZstdEncoder::with_quality(
-        Vec::new(),
-        async_compression::Level::Precise(compression_level.try_into().unwrap()),
-    ));
+    let mut encoder = Some(Encoder::new(codec, compression_level)?);
     let mut bytes_written = 0;
 
     let s = stream! {
         let mut stream = ReaderStream::new(inp);
         while let Some(bytes) = stream.next().await {
             trace!("read bytes: {:?}", bytes);
-            if let (Ok(bytes), Some(writer)) = (&bytes, zstd_writer.as_mut()) {
-                writer.write_all(bytes).await?;
+            if let (Ok(bytes), Some(writer)) = (&bytes, encoder.as_mut()) {
+                writer.write_all(bytes)?;
                 bytes_written += bytes.len() as u64;
-                let compressed_len = writer.get_ref().len();
-                trace!("wrote {} to zstd, len now {}", bytes.len(), compressed_len);
+                let compressed_len = writer.compressed_len();
+                trace!("wrote {} to {codec:?}, len now {}", bytes.len(), compressed_len);
                 if compressed_len > max_cache_size {
                     debug!("cache longer than max, dropping");
                     //writer.finish();
-                    zstd_writer.take();
+                    encoder.take();
                 }
             }
             yield bytes;
@@ -50,12 +107,11 @@ pub fn async_read_and_write_to_cache<'a>(
         trace!("eof");
         // EOF, call on_finish
         let finish = {
-            match zstd_writer.take() { Some(mut writer) => {
-                writer.shutdown().await?;
-                let res = writer.into_inner();
+            match encoder.take() { Some(writer) => {
+                let res = writer.finish(bytes_written).map_err(to_io_err)?;
                 trace!("EOF");
-                if res.len() <= max_cache_size {
-                    trace!("writing {} bytes to cache", res.len());
+                if res.data.len() <= max_cache_size {
+                    trace!("writing {} bytes to cache", res.data.len());
                     (bytes_written, Some(res))
                 } else {
                     trace!("cache longer than max, dropping");
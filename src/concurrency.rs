@@ -0,0 +1,143 @@
+//! Bounds on how much work runs at the same time.
+//!
+//! Adapters are async and stream their output, so nothing is buffered per file. The expensive
+//! part is external programs: every adapter with `external_deps` in a recursion chain holds a slot until
+//! its output is consumed, and all of them share one limit per process (`--rga-max-subprocesses`).
+//!
+//! rg runs an rga-preproc per file, many at once, so the first external program of a file also waits for
+//! one of the same number of slots shared by all processes using the cache directory. They are lock files,
+//! the lock goes away with the process that held it.
+use crate::config::RgaConfig;
+use crate::recurse::max_branch_len;
+use anyhow::{Context, Result};
+use log::debug;
+use once_cell::sync::OnceCell;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+static SUBPROCESSES: OnceCell<Arc<Semaphore>> = OnceCell::new();
+/// shared slots held by this process
+static SHARED_HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// number of external programs allowed to run at once.
+///
+/// Every nesting level of spawning adapters (e.g. pandoc output fed into another custom adapter) keeps
+/// its slot while the next level runs, so at least [`max_branch_len`] slots are needed for the
+/// deepest chain to make progress.
+pub fn max_subprocesses(config: &RgaConfig) -> usize {
+    let configured = config.max_subprocesses.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4)
+    });
+    configured.max(max_branch_len(config))
+}
+
+/// a slot of this process to run an external program in, and maybe one shared with the other processes
+pub struct SubprocessPermit {
+    _local: OwnedSemaphorePermit,
+    _shared: Option<SharedSlot>,
+}
+
+/// a locked slot file, unlocked when it is closed
+struct SharedSlot {
+    _file: File,
+}
+
+impl Drop for SharedSlot {
+    fn drop(&mut self) {
+        SHARED_HELD.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// wait until another external program may be started. The slot is released when the permit is dropped
+pub async fn subprocess_permit(config: &RgaConfig) -> Result<SubprocessPermit> {
+    let local = SUBPROCESSES
+        .get_or_init(|| Arc::new(Semaphore::new(max_subprocesses(config))))
+        .clone()
+        .acquire_owned()
+        .await
+        .context("subprocess limit closed")?;
+    // programs nested in one with a shared slot run in that slot, waiting for another one could wait
+    // for processes that wait the same way
+    let shared = if SHARED_HELD.load(Ordering::Relaxed) == 0 {
+        let dir = Path::new(&config.cache.path.0).join("subprocess-slots");
+        shared_slot(&dir, max_subprocesses(config)).await
+    } else {
+        None
+    };
+    Ok(SubprocessPermit {
+        _local: local,
+        _shared: shared,
+    })
+}
+
+/// waits until one of the `slots` lock files in `dir` can be locked. None if the files can't be used,
+/// then only the limit of this process applies
+async fn shared_slot(dir: &Path, slots: usize) -> Option<SharedSlot> {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        debug!("not sharing the subprocess limit, could not create {}: {e}", dir.display());
+        return None;
+    }
+    let mut wait = Duration::from_millis(10);
+    loop {
+        for slot in 0..slots {
+            let path = dir.join(slot.to_string());
+            let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path);
+            let locked = file.and_then(|file| match file.try_lock() {
+                Ok(()) => Ok(Some(file)),
+                Err(TryLockError::WouldBlock) => Ok(None),
+                Err(TryLockError::Error(e)) => Err(e),
+            });
+            match locked {
+                Ok(Some(file)) => {
+                    SHARED_HELD.fetch_add(1, Ordering::Relaxed);
+                    return Some(SharedSlot { _file: file });
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    debug!("not sharing the subprocess limit, could not lock {}: {e}", path.display());
+                    return None;
+                }
+            }
+        }
+        tokio::time::sleep(wait).await;
+        wait = (wait * 2).min(Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MaxArchiveRecursion;
+
+    #[test]
+    fn limits() {
+        let mut config = RgaConfig {
+            max_subprocesses: Some(1),
+            ..Default::default()
+        };
+        config.max_archive_recursion = MaxArchiveRecursion(2);
+        // three containers and the conversions between them
+        assert_eq!(max_subprocesses(&config), 3 + crate::recurse::MAX_CONVERSIONS as usize);
+        config.max_subprocesses = Some(8);
+        assert_eq!(max_subprocesses(&config), 8);
+    }
+
+    #[tokio::test]
+    async fn shared_slots() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = shared_slot(dir.path(), 2).await.context("no slot")?;
+        let _second = shared_slot(dir.path(), 2).await.context("no slot")?;
+        let wait = Duration::from_millis(300);
+        // the locks are per open file, so they keep each other out like other processes would
+        assert!(tokio::time::timeout(wait, shared_slot(dir.path(), 2)).await.is_err());
+        drop(first);
+        assert!(tokio::time::timeout(wait, shared_slot(dir.path(), 2)).await?.is_some());
+        Ok(())
+    }
+}
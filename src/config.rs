@@ -1,4 +1,6 @@
-﻿use crate::{adapters::custom::CustomAdapterConfig, project_dirs};
+﻿mod sources;
+
+use crate::{adapters::custom::CustomAdapterConfig, project_dirs};
 use anyhow::{Context, Result};
 use derive_more::FromStr;
 use log::*;
@@ -6,9 +8,12 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::ffi::OsString;
 use std::io::Read;
-use std::{fs::File, io::Write, iter::IntoIterator, path::PathBuf, str::FromStr};
+use std::collections::BTreeMap;
+use std::{fs::File, io::Write, iter::IntoIterator, path::{Path, PathBuf}, str::FromStr};
 use clap::Parser;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 fn is_default<T: Default + PartialEq>(t: &T) -> bool {
     t == &T::default()
@@ -94,6 +99,121 @@ impl FromStr for CacheMaxBlobLen {
     }
 }
 
+/// Max total size of the cache, 0 for no limit. Same suffixes as [`CacheMaxBlobLen`].
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CacheMaxTotalSize(pub u64);
+
+impl std::fmt::Display for CacheMaxTotalSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for CacheMaxTotalSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(CacheMaxBlobLen::from_str(s)?.0 as u64))
+    }
+}
+
+/// How much of a file is hashed with `cache.key` "content-hash", 0 for all of it. Same suffixes as
+/// [`CacheMaxBlobLen`].
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct CacheContentHashLen(pub u64);
+
+impl std::fmt::Display for CacheContentHashLen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for CacheContentHashLen {
+    fn default() -> Self {
+        Self(1000000)
+    }
+}
+
+impl FromStr for CacheContentHashLen {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(CacheMaxBlobLen::from_str(s)?.0 as u64))
+    }
+}
+
+/// Size at which an output file is rotated, 0 for never. Same suffixes as [`CacheMaxBlobLen`].
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+pub struct OutputMaxSize(pub u64);
+
+impl std::fmt::Display for OutputMaxSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for OutputMaxSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(CacheMaxBlobLen::from_str(s)?.0 as u64))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct OutputKeep(pub usize);
+
+impl std::fmt::Display for OutputKeep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for OutputKeep {
+    fn default() -> Self {
+        Self(5)
+    }
+}
+
+/// How long the cache entries of an adapter are used, see `cache.ttl`. Seconds, or a number with one
+/// of the suffixes s m h d w.
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct CacheTtl(pub u64);
+
+const TTL_UNITS: [(char, u64); 5] = [('w', 604800), ('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+
+impl std::fmt::Display for CacheTtl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (suffix, secs) = TTL_UNITS
+            .into_iter()
+            .find(|(_, secs)| self.0.is_multiple_of(*secs) && self.0 > 0)
+            .unwrap_or(('s', 1));
+        write!(f, "{}{suffix}", self.0 / secs)
+    }
+}
+
+impl FromStr for CacheTtl {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, secs) = match TTL_UNITS.into_iter().find(|(suffix, _)| s.ends_with(*suffix)) {
+            Some((suffix, secs)) => (s.trim_end_matches(suffix), secs),
+            None => (s, 1),
+        };
+        let number = u64::from_str(number.trim())
+            .with_context(|| format!("Could not parse duration {s:?}, expected e.g. 3600, 12h or 30d"))?;
+        Ok(Self(number * secs))
+    }
+}
+
+impl TryFrom<String> for CacheTtl {
+    type Error = anyhow::Error;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<CacheTtl> for String {
+    fn from(ttl: CacheTtl) -> Self {
+        ttl.to_string()
+    }
+}
+
 /// # rga configuration
 ///
 /// This is kind of a "polyglot" struct serving multiple purposes:
@@ -118,11 +238,28 @@ pub struct RgaConfig {
     /// By default, rga will match files using file extensions.
     /// Some programs, such as sqlite3, don't care about the file extension at all, so users sometimes use any or no extension at all.
     /// With this flag, rga will try to detect the mime type of input files using the magic bytes (similar to the `file` utility), and use that to choose the adapter.
-    /// Detection is only done on the first 8KiB of the file, since we can't always seek on the input (in archives).
+    /// Detection is only done on the first 64KiB of the file (see --rga-sniff-window), since we can't always seek on the input (in archives).
     #[serde(default, skip_serializing_if = "is_default")]
     #[clap(long = "rga-accurate")]
     pub accurate: bool,
 
+    /// Isolate the adapters from malformed files.
+    ///
+    /// Panics and errors of an adapter become a warning and only end the output of the file being read, the rest of the search
+    /// goes on. Each file is limited to 256MiB of text and 5 minutes of reading, and archives are only recursed 4 levels deep.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-robust")]
+    pub robust: bool,
+
+    /// Read truncated or damaged archives and compressed files as far as possible.
+    ///
+    /// Zip files without a central directory (interrupted downloads) are read from the headers of their members, damaged zip and tar
+    /// headers are skipped by looking for the next valid one, and a truncated member or compressed file keeps the text before the
+    /// damage. Each recovery is logged as a warning.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-salvage")]
+    pub salvage: bool,
+
     /// Change which adapters to use and in which priority order (descending).
     ///
     /// - "foo,bar" means use only adapters foo and bar.
@@ -143,7 +280,9 @@ pub struct RgaConfig {
     /// Maximum depth of nested archives to recurse into.
     ///
     /// When searching in archives, rga will recurse into archives inside archives.
-    /// This option limits the depth.
+    /// This option limits the depth. Every container counts (archives, mail attachments, disk
+    /// images), conversions like decompression don't. A container that contains itself is
+    /// only read once, and `--debug` logs the branch of containers each file was found in.
     #[serde(default, skip_serializing_if = "is_default")]
     #[clap(
         default_value_t = MaxArchiveRecursion(5),
@@ -160,14 +299,79 @@ pub struct RgaConfig {
     #[clap(long = "rga-no-prefix-filenames")]
     pub no_prefix_filenames: bool,
 
+    /// Replace secrets (AWS keys, GitHub and Slack tokens, private keys) in the printed matches with placeholders.
+    ///
+    /// rg still searches the real text, so searching for a key finds it, but the output shows `[REDACTED:aws-access-key-id]` instead, e.g. when sharing the screen or logging searches.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-redact-secrets")]
+    pub redact_secrets: bool,
+
+    /// Where the output of rg goes instead of stdout, e.g. for scheduled searches that feed a log
+    /// collector.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(flatten)]
+    pub output: OutputConfig,
+
+    /// Saved searches for --rga-monitor, by name (config file only).
+    ///
+    /// E.g. `{"acme": {"args": ["-i", "acme corp", "/data/dumps"], "every": "6h"}}`. `args` are passed to rg like
+    /// the arguments of rga, `every` (same format as `cache.ttl`) is how often the search is due.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub monitors: BTreeMap<String, MonitorConfig>,
+
+    /// Check extracted files against their checksums and flag the ones that do not match.
+    ///
+    /// A file with a `.sha256` or `.md5` companion next to it (`image.iso.sha256`) is hashed while it is searched, and zip members are
+    /// checked against the CRC32 stored in the archive. A mismatch is a line `[rga:verify] mismatch ...` in the text of the file, so
+    /// corrupted files show up in searches (`rga -F '[rga:verify]'`) instead of being silently truncated.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-verify")]
+    pub verify: bool,
+
+    /// Append a JSON line for every external command the adapters run (program, arguments, duration and exit status) to `audit.jsonl` in the cache directory.
+    ///
+    /// Passwords from the config are replaced with `<redacted>` in the arguments.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-audit-log")]
+    pub audit_log: bool,
+
     #[serde(default, skip_serializing_if = "is_default")]
     #[clap(skip)] // config file only
     pub custom_adapters: Option<Vec<CustomAdapterConfig>>,
 
+    /// Adapters compiled to WebAssembly (WASI), run in a sandbox. Requires the `wasm` feature.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)] // config file only
+    pub wasm_adapters: Option<Vec<crate::adapters::wasm::WasmAdapterConfig>>,
+
     #[serde(skip)]
     #[clap(long = "rga-config-file", require_equals = true)]
     pub config_file_path: Option<String>,
 
+    /// Use the settings of a profile of the config file, over the other settings of the file.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-profile", require_equals = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Named sets of settings, selected with `--rga-profile=NAME` or `profile`. For example
+    /// `{"fast": {"adapters": ["-ffmpeg"]}, "thorough": {"accurate": true, "adapters": ["+tesseract", "+whisper"]}}`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)] // config file only
+    pub profiles: BTreeMap<String, Value>,
+
+    /// Don't read the `.rga.toml` files in the searched directories and their parents.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-no-project-config")]
+    pub no_project_config: bool,
+
+    /// Directories whose `.rga.toml` may set any key, e.g. define custom adapters or enable adapters. Those run
+    /// commands, so the `.rga.toml` of other directories can only remove adapters. Only read from the user and
+    /// system config files.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)] // config file only
+    pub trusted_projects: Vec<String>,
+
     /// Same as passing path directly, except if argument is empty.
     ///
     /// Kinda hacky, but if no file is found, `fzf` calls `rga` with empty string as path, which causes "No such file or directory from rg".
@@ -176,10 +380,26 @@ pub struct RgaConfig {
     #[clap(long = "rga-fzf-path", require_equals = true, hide = true)]
     pub fzf_path: Option<String>,
 
+    /// The session directory of rga-fzf, where rga-preproc keeps the text of recently previewed files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    #[clap(long = "rga-fzf-session", require_equals = true, hide = true)]
+    pub fzf_session: Option<String>,
+
     #[serde(skip)] // CLI only
     #[clap(long = "rga-list-adapters", help = "List all known adapters")]
     pub list_adapters: bool,
 
+    /// Print the configuration rga uses, merged from the config files, the profile, RGA_CONFIG and the command line, with where each value came from.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-print-config")]
+    pub print_config: bool,
+
+    /// the layers the config was merged from, for --rga-print-config
+    #[serde(skip)]
+    #[clap(skip)]
+    pub config_sources: Vec<(String, Value)>,
+
     #[serde(skip)] // CLI only
     #[clap(
         long = "rga-print-config-schema",
@@ -187,6 +407,13 @@ pub struct RgaConfig {
     )]
     pub print_config_schema: bool,
 
+    /// Print the arguments that make plain rg search like rga, one per line.
+    ///
+    /// They can be passed to tools that run rg directly (editors) or put in a ripgrep config file (RIPGREP_CONFIG_PATH). A comment line gives the RGA_CONFIG environment variable rg has to be run with to use the same configuration, with the passwords left out.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-print-rg-args")]
+    pub print_rg_args: bool,
+
     #[serde(skip)] // CLI only
     #[clap(long, help = "Show help for ripgrep itself")]
     pub rg_help: bool,
@@ -200,25 +427,281 @@ pub struct RgaConfig {
     pub doctor: bool,
 
     #[serde(skip)] // CLI only
-    #[clap(long = "rga-cache-clear", help = "Clear the rga cache database completely")]
-    pub cache_clear: bool,
+    #[clap(
+        long = "rga-cache-clear",
+        require_equals = true,
+        num_args = 0..=1,
+        value_name = "GLOB|ADAPTER|namespace:NAME",
+        help = "Clear the rga cache database completely, or only the entries of an adapter, of a namespace (namespace:NAME) or of the files whose path matches a glob"
+    )]
+    pub cache_clear: Option<Option<String>>,
 
     #[serde(skip)] // CLI only
     #[clap(long = "rga-cache-prune", help = "Prune the cache (remove old or missing entries)")]
     pub cache_prune: bool,
 
+    /// Print the cache key of a file and whether the cache has an entry for it.
+    ///
+    /// Shows the adapter that would be used and everything the key is derived from.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-cache-key", require_equals = true, value_name = "PATH")]
+    pub cache_key: Option<String>,
+
+    /// Print the text rga searches for a file: the output of its adapters as it is in the cache, or else the output of running them.
+    ///
+    /// Says on stderr where the text came from. Useful to find out why a match is not found.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-show-extracted", require_equals = true, value_name = "PATH")]
+    pub show_extracted: Option<String>,
+
+    /// Only print the text of a file that is in the cache, and fail if it is not. For --rga-show-extracted and rga-preproc.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-from-cache-only")]
+    pub from_cache_only: bool,
+
+    /// Print statistics of the cache: its size on disk, the number of entries, the entries and compression ratio per adapter, and the oldest and newest entries.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-cache-stats")]
+    pub cache_stats: bool,
+
+    /// List the namespaces in the cache with the number and size of their entries, see --rga-cache-namespace.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-cache-namespaces")]
+    pub cache_namespaces: bool,
+
+    /// Export the cache to a file, e.g. to give a cache built for a shared set of documents to others.
+    ///
+    /// The file is a sqlite database with the entries as they are stored, see --rga-cache-import.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-cache-export", require_equals = true, value_name = "FILE")]
+    pub cache_export: Option<String>,
+
+    /// Only export the entries of an adapter, of a namespace or of the files whose path matches a glob, like --rga-cache-clear.
+    #[serde(skip)] // CLI only
+    #[clap(
+        long = "rga-cache-export-filter",
+        require_equals = true,
+        value_name = "GLOB|ADAPTER|namespace:NAME",
+        requires = "cache_export"
+    )]
+    pub cache_export_filter: Option<String>,
+
+    /// Add the entries of a cache exported with --rga-cache-export to the cache.
+    ///
+    /// Entries the cache has a newer version of are skipped. They are used for the same files as where they were exported: the files at the same path with the same modification time, or with `cache.key` "content-hash" the files with the same content anywhere.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-cache-import", require_equals = true, value_name = "FILE")]
+    pub cache_import: Option<String>,
+
+    /// Print how many files of each type are below a path and which adapters would handle them.
+    ///
+    /// Disabled adapters that would handle some of the files are listed as `name (disabled)`, files that no adapter handles as `(no adapter)`.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-stats", require_equals = true, value_name = "PATH")]
+    pub stats: Option<String>,
+
+    /// Print statistics of the search after it.
+    ///
+    /// The files preprocessed, their time and cache hits and misses per adapter, and the bytes written to the cache are printed to stderr.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-search-stats")]
+    pub search_stats: bool,
+
+    /// Print the files below a path whose extracted text is identical or nearly identical, e.g. a PDF and the DOCX it was made from.
+    ///
+    /// Case, punctuation, whitespace and page prefixes are ignored. Files that are not in the cache yet are extracted (and cached) first.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-dupes", require_equals = true, value_name = "PATH")]
+    pub dupes: Option<String>,
+
+    /// Print the lines, words and bytes of the text rga searches in every file below a path, per file and per adapter.
+    ///
+    /// The text is read from the cache, files that are not cached yet are extracted (and cached) first. Lines of files in archives start with the path of the file in the archive, which is counted too.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-wc", require_equals = true, value_name = "PATH")]
+    pub wc: Option<String>,
+
+    /// Write the text of every file below the paths given after the options to a directory, one text file per file and per file in an archive.
+    ///
+    /// The paths are mapped into the directory: `docs/a.zip` with a member `b/c.pdf` becomes `DIR/docs/a.zip/b/c.pdf.txt`. Files no adapter handles are copied if they are text. Without paths, the current directory is extracted.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-extract-all", require_equals = true, value_name = "DIR")]
+    pub extract_all: Option<String>,
+
+    /// Fill the cache with the text of every file below a path, so the first search of a large corpus is fast too.
+    ///
+    /// Several files are extracted at a time (see --rga-max-subprocesses), files that are cached already are skipped. A progress bar is shown on a terminal.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-warm", require_equals = true, value_name = "PATH")]
+    pub warm: Option<String>,
+
+    /// Also write every match to a JSON lines file, with the path inside archives and the page of the match.
+    ///
+    /// The matches are ordered by file and line, so the files of two runs can be diffed.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-matches-manifest", require_equals = true, value_name = "PATH")]
+    pub matches_manifest: Option<String>,
+
+    /// Also write the matches in each file to a `<file>.rga-matches.jsonl` next to it, in the format of --rga-matches-manifest.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-matches-sidecars")]
+    pub matches_sidecars: bool,
+
+    /// Run the saved searches of `monitors` and print only the matches that are new since their last run.
+    ///
+    /// Without a name, the searches that are due by their `every` are run, so a frequent cron job can run searches on different schedules. With a name, that search is run right away.
+    #[serde(skip)] // CLI only
+    #[clap(
+        long = "rga-monitor",
+        require_equals = true,
+        num_args = 0..=1,
+        value_name = "NAME"
+    )]
+    pub monitor: Option<Option<String>>,
+
+    /// Browse the matches interactively instead of printing them.
+    ///
+    /// The matches are grouped by file, with the extracted text around the selected match. Enter opens the file: PDFs at the page of the match (with evince), plain text files at the line of the match in $VISUAL or $EDITOR, other files in their default application.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-tui")]
+    pub tui: bool,
+
+    /// Install an adapter from the adapter registry into the config file.
+    ///
+    /// Takes the adapter name, optionally with a version (`name@version`, otherwise the newest one), and the sha256 of its registry entry after a `#`. Without the sha256 the entry is only shown.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-adapter-install", require_equals = true, value_name = "NAME[@VERSION]#SHA256")]
+    pub adapter_install: Option<String>,
+
+    /// Location of the adapter registry index (an http(s) url or a local path to an index.json).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-adapter-registry", require_equals = true, value_name = "URL")]
+    pub adapter_registry: Option<String>,
+
     #[serde(skip)] // CLI only
     #[clap(long = "rga-daemon", help = "Start a persistent preprocessor daemon to speed up caching")]
     pub daemon: bool,
 
+    /// Search the file system of a container image.
+    ///
+    /// The image is exported with `docker save` (and pulled first if needed), its layers are merged into
+    /// the rga cache directory and searched like any other directory. Each image is only unpacked once.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-docker-image", require_equals = true, value_name = "IMAGE")]
+    pub docker_image: Option<String>,
+
+    /// Download all remote files, not only the ones an adapter reads.
+    ///
+    /// Of remote roots (sftp://, s3://, http(s)://) only the files with the extension of an enabled adapter are
+    /// downloaded by default (all of them with --rga-accurate), so e.g. plain text files on the remote are not searched.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-remote-all")]
+    pub remote_all: bool,
+
+    /// Search each root path with its own rg, N at a time (default: the number of CPUs).
+    ///
+    /// For searches over many slow roots, like a dozen mounted network shares. The output of each root is printed as a whole, in the order the roots were given, so it doesn't depend on which search finishes first. Roots are the arguments after the pattern that exist, give the values of rg flags as `--flag=value`.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-parallel", require_equals = true, num_args = 0..=1, value_name = "N")]
+    pub parallel: Option<Option<usize>>,
+
+    /// Search the files listed in FILE, one per line (`-` for stdin), after the roots on the command line.
+    ///
+    /// The files are given to rg directly, without walking any directories, e.g. to search what `fd` or `find` selected. Directories in the list are searched like roots.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-files-from", require_equals = true, value_name = "FILE")]
+    pub files_from: Option<String>,
+
+    /// Like --rga-files-from, but the files are separated by NUL bytes, as `find -print0` and `fd -0` write them.
+    #[serde(skip)] // CLI only
+    #[clap(long = "rga-files0-from", require_equals = true, value_name = "FILE")]
+    pub files0_from: Option<String>,
+
+    /// Maximum number of external programs (pandoc, pdftotext, ffmpeg, ...) an rga-preproc process runs at once.
+    ///
+    /// Defaults to the number of CPUs. The rga-preproc processes rg runs for its files also share this many
+    /// slots (lock files in the cache directory) for the first program of each file. Also bounds the number of
+    /// connections the daemon serves at once.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-max-subprocesses", require_equals = true)]
+    pub max_subprocesses: Option<usize>,
+
+    /// Directory with adapter plugins (shared libraries) to load at startup.
+    ///
+    /// Plugins take priority over the built-in adapters, but not over custom adapters. See `src/adapters/plugin.rs` for the ABI.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-plugins-dir", require_equals = true, value_name = "DIR")]
+    pub plugins_dir: Option<String>,
+
     /// Password for encrypted archives.
     #[serde(default)]
     #[clap(long = "rga-password", require_equals = true)]
     pub password: Option<String>,
 
+    /// Passwords of encrypted documents (PDFs), by glob. Only in the config file.
+    ///
+    /// E.g. `{"*statement*.pdf": "hunter2", "work/**/*.pdf": "s3cret"}`. Globs are matched against the path of the file,
+    /// globs without a slash also against its name. Files in archives are matched with their path in the archive.
+    /// If several globs match, the longest one wins. Files no glob matches get --rga-password.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub document_passwords: BTreeMap<String, String>,
+
+    /// Adapters to use for mime types in --rga-accurate mode, overriding the usual matching. Only in the config file.
+    ///
+    /// E.g. `{"text/plain": "my-log-adapter", "application/x-*": "zip"}`. Keys are mime types or globs of them,
+    /// exact mime types win over globs and longer globs over shorter ones. Custom adapters can be used as well.
+    /// Files without a detected type that look like text are `text/plain`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub mime_adapters: BTreeMap<String, String>,
+
+    /// Mime types of file extensions in --rga-accurate mode, used instead of the detected one. Only in the config file.
+    ///
+    /// E.g. `{"log": "text/plain", "tar.zst": "application/zstd"}`. Extensions are matched case-insensitively,
+    /// the longest matching extension wins.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub extension_mimes: BTreeMap<String, String>,
+
+    /// Byte signatures that select an adapter in --rga-accurate mode, before any other detection. Only in the config file.
+    ///
+    /// E.g. `[{"offset": 0, "bytes": "53 51 4c 69 74 65", "adapter": "sqlite"}]`. `bytes` is hex, spaces are ignored.
+    /// The sniff window is extended to cover all signatures.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub magics: Vec<Magic>,
+
+    /// How many bytes at the start of a file are read to detect its type with --rga-accurate.
+    ///
+    /// Some signatures are far into the file, e.g. the one of ISO 9660 images at 32 KiB. Default 64 KiB.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-sniff-window", require_equals = true, value_name = "BYTES")]
+    pub sniff_window: Option<usize>,
+
+    /// Password for encrypted zip members (ZipCrypto and WinZip AES).
+    ///
+    /// Can be given more than once, the passwords are tried in order. Members that none of them decrypts are skipped with a warning.
+    /// Note that the decrypted text is cached like any other, use --rga-no-cache to keep it out of the cache.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-archive-password", require_equals = true, value_name = "PASSWORD")]
+    pub archive_password: Vec<String>,
+
+    /// File with more archive passwords, one per line.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-archive-password-file", require_equals = true, value_name = "PATH")]
+    pub archive_password_file: Option<String>,
+
+    /// Shell command that prints more archive passwords, one per line.
+    ///
+    /// Used to get them from a keyring, e.g. `secret-tool lookup service rga` or `security find-generic-password -s rga -w`. It is run once per search, before it starts. If it fails, encrypted archives are only tried with the other passwords.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-archive-password-command", require_equals = true, value_name = "COMMAND")]
+    pub archive_password_command: Option<String>,
+
     /// Override file extensions for the built-in ZIP adapter.
     ///
-    /// If set, replaces the default list ["zip","jar","xpi","kra","snagx"].
+    /// If set, replaces the default list ["zip","jar","xpi","kra","snagx","kmz"].
     #[serde(default)]
     #[clap(
         long = "rga-zip-extensions",
@@ -227,7 +710,7 @@ pub struct RgaConfig {
     )]
     pub zip_extensions: Option<Vec<String>>,
 
-    /// Override file extensions for the built-in FFmpeg adapter.
+    /// Override file extensions for the built-in FFmpeg and whisper adapters.
     ///
     /// If set, replaces the default list ["mkv","mp4","avi","mp3","ogg","flac","webm"].
     #[serde(default)]
@@ -238,70 +721,570 @@ pub struct RgaConfig {
     )]
     pub ffmpeg_extensions: Option<Vec<String>>,
 
+    /// Only extract the subtitles in these languages from media files (ISO 639-2 codes, e.g. eng,ger).
+    ///
+    /// `und` selects subtitles without a language tag. By default all subtitle streams are extracted.
+    /// Subtitle lines are prefixed with the language of their stream.
     #[serde(default)]
-    #[clap(long = "rga-postproc-binary-marker", require_equals = true)]
-    pub postproc_binary_marker: Option<String>,
+    #[clap(
+        long = "rga-subtitle-languages",
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub subtitle_languages: Option<Vec<String>>,
 
+    /// OCR a frame of videos every this many seconds with the tesseract adapter, for text shown on screen (slides, screen recordings).
+    ///
+    /// Only keyframes are decoded, so the frames are at least this far apart. Lines are prefixed with the time of their frame, e.g. `[00:12:30] `.
+    /// A frame with the same text as the one before it is skipped. Off by default.
     #[serde(default)]
-    #[clap(long = "rga-postproc-page-prefix", require_equals = true)]
-    pub postproc_page_prefix: Option<String>,
+    #[clap(long = "rga-video-ocr-interval", require_equals = true, value_name = "SECONDS")]
+    pub video_ocr_interval: Option<u32>,
 
+    /// Speech to text program of the whisper adapter. Default: whisper-cli (from whisper.cpp).
     #[serde(default)]
-    #[clap(long = "rga-postproc-page-include-empty")] 
-    pub postproc_page_include_empty: Option<bool>,
-}
+    #[clap(long = "rga-whisper-binary", require_equals = true, value_name = "BIN")]
+    pub whisper_binary: Option<String>,
 
-impl RgaConfig {
-    pub fn config_hash(&self) -> String {
-        use std::hash::{Hash, Hasher};
-        let mut s = std::collections::hash_map::DefaultHasher::new();
-        self.accurate.hash(&mut s);
-        self.adapters.hash(&mut s);
-        self.max_archive_recursion.0.hash(&mut s);
-        self.no_prefix_filenames.hash(&mut s);
-        self.zip_extensions.hash(&mut s);
-        self.ffmpeg_extensions.hash(&mut s);
-        self.postproc_binary_marker.hash(&mut s);
-        self.postproc_page_prefix.hash(&mut s);
-        self.postproc_page_include_empty.hash(&mut s);
-        self.password.hash(&mut s);
-        // Include version to invalidate cache on updates
-        env!("CARGO_PKG_VERSION").hash(&mut s);
-        format!("{:016x}", s.finish())
-    }
-}
+    /// Model file passed to the whisper binary, e.g. ~/models/ggml-base.en.bin.
+    #[serde(default)]
+    #[clap(long = "rga-whisper-model", require_equals = true, value_name = "PATH")]
+    pub whisper_model: Option<String>,
 
-#[derive(Parser, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
-pub struct CacheConfig {
-    /// Type of cache backend to use.
-    #[serde(default, skip_serializing_if = "is_default")]
-    #[clap(
-        default_value = "sqlite",
-        long = "rga-cache-type",
-        require_equals = true
-    )]
-    pub cache_type: String,
+    /// Spoken language for the whisper adapter. Default: auto-detect.
+    #[serde(default)]
+    #[clap(long = "rga-whisper-language", require_equals = true, value_name = "LANG")]
+    pub whisper_language: Option<String>,
 
-    /// Disable caching of results.
-    ///
-    /// By default, rga caches the extracted text, if it is small enough, to a database.
-    /// This way, repeated searches on the same set of files will be much faster.
-    /// The location of the DB varies by platform:
-    /// - `${XDG_CACHE_DIR-~/.cache}/ripgrep-all` on Linux
-    /// - `~/Library/Caches/ripgrep-all` on macOS
-    /// - `C:\Users\username\AppData\Local\ripgrep-all` on Windows
+    /// Arguments for the whisper binary, replacing the ones for whisper.cpp. Only in the config file.
     ///
-    /// If you pass this flag, all caching will be disabled.
+    /// `$input` is replaced by a 16kHz mono wav file of the audio, `$model` and `$language` by the options above.
+    /// Lines the binary prints as `[start --> end] text` are prefixed with their time, others are used as they are.
+    /// `$output` is a path without extension for whisper.cpp's full JSON output (`-ojf -of $output`), which has the
+    /// confidence of the segments.
     #[serde(default, skip_serializing_if = "is_default")]
-    #[clap(long = "rga-no-cache")]
-    pub disabled: bool,
+    #[clap(skip)]
+    pub whisper_args: Option<Vec<String>>,
 
-    /// Max compressed size to cache.
+    /// Leave out the lines that OCR (tesseract) and speech to text (whisper) are less confident about than this, from 0 to 100.
     ///
-    /// Longest byte length (after compression) to store in cache.
-    /// Longer adapter outputs will not be cached and recomputed every time.
-    ///
-    /// Allowed suffixes on command line: k M G
+    /// The confidence of a line is the mean of its words or tokens. Text read from noise, stains or textures usually has a
+    /// confidence below 50, clean print above 85. Off by default.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        long = "rga-min-confidence",
+        require_equals = true,
+        value_name = "0-100",
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    pub min_confidence: Option<u8>,
+
+    /// Put `[confidence N] ` before the text of OCR and speech to text lines. Set by rga for the runs of rg with
+    /// JSON output, the matches manifest has the confidence as a field of its own.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub confidence_markers: bool,
+
+    /// Maximum number of rows to extract from a parquet file.
+    ///
+    /// Rows past this limit are not searched. By default all rows are extracted.
+    #[serde(default)]
+    #[clap(long = "rga-parquet-max-rows", require_equals = true)]
+    pub parquet_max_rows: Option<usize>,
+
+    /// Only extract these columns from parquet files.
+    ///
+    /// Nested columns are given as dotted paths, e.g. "address.city".
+    #[serde(default)]
+    #[clap(
+        long = "rga-parquet-columns",
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub parquet_columns: Option<Vec<String>>,
+
+    /// Only dump these sqlite tables or columns.
+    ///
+    /// Entries are either a table name or `table.column`. Listing columns of a table restricts the table to those columns.
+    #[serde(default)]
+    #[clap(
+        long = "rga-sqlite-include",
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub sqlite_include: Option<Vec<String>>,
+
+    /// Skip these sqlite tables or columns (`table` or `table.column`), e.g. columns with large blobs.
+    #[serde(default)]
+    #[clap(
+        long = "rga-sqlite-exclude",
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub sqlite_exclude: Option<Vec<String>>,
+
+    /// How extracted text is postprocessed (binary marker, page numbers).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(flatten)]
+    pub postproc: PostprocConfig,
+
+    /// Lines to drop from the text of single adapters before it is cached (config file only), e.g.
+    /// `{"ffmpeg": {"exclude": ["^chapter "]}}` or `{"poppler": {"exclude": ["^Confidential$"]}}`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub post_filters: BTreeMap<String, PostFilter>,
+}
+
+impl RgaConfig {
+    /// blake3 over the options that change adapter output. Serialized as a tuple so the field order
+    /// (and thus the hash) does not depend on how the config was written.
+    pub fn config_hash(&self) -> String {
+        let output_affecting = (
+            self.accurate,
+            &self.adapters,
+            (self.max_archive_recursion.0, self.robust, self.salvage),
+            (self.no_prefix_filenames, self.verify),
+            &self.zip_extensions,
+            (
+                &self.ffmpeg_extensions,
+                &self.subtitle_languages,
+                self.video_ocr_interval,
+                &self.whisper_binary,
+                &self.whisper_model,
+                &self.whisper_language,
+                &self.whisper_args,
+                (self.min_confidence, self.confidence_markers),
+            ),
+            self.parquet_max_rows,
+            &self.parquet_columns,
+            &self.sqlite_include,
+            &self.sqlite_exclude,
+            (&self.postproc, &self.post_filters),
+            &self.password,
+            &self.document_passwords,
+            (
+                &self.mime_adapters,
+                &self.extension_mimes,
+                &self.magics,
+                self.sniff_window,
+            ),
+            (
+                &self.archive_password,
+                &self.archive_password_file,
+                &self.archive_password_command,
+            ),
+        );
+        let canonical = serde_json::to_vec(&output_affecting).expect("config is serializable");
+        blake3::hash(&canonical).to_hex().to_string()
+    }
+
+    /// the password of the document at `path`, see `document_passwords`
+    pub fn document_password(&self, path: &std::path::Path) -> Option<&str> {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        let name = path.file_name().map(std::path::Path::new);
+        self.document_passwords
+            .iter()
+            .filter(|(glob, _)| match glob::Pattern::new(glob) {
+                Ok(pattern) => {
+                    pattern.matches_path(path)
+                        || (!glob.contains('/') && name.is_some_and(|n| pattern.matches_path(n)))
+                }
+                Err(e) => {
+                    warn!("invalid document password glob {glob:?}: {e}");
+                    false
+                }
+            })
+            .max_by_key(|(glob, _)| glob.len())
+            .map(|(_, password)| password.as_str())
+            .or(self.password.as_deref())
+    }
+
+    /// the mime type `extension_mimes` gives the file `filename`
+    pub fn extension_mime(&self, filename: &str) -> Option<&str> {
+        let filename = filename.to_lowercase();
+        self.extension_mimes
+            .iter()
+            .map(|(extension, mime)| (extension.trim_start_matches('.').to_lowercase(), mime))
+            .filter(|(extension, _)| {
+                filename.strip_suffix(extension.as_str()).is_some_and(|rest| rest.ends_with('.'))
+            })
+            .max_by_key(|(extension, _)| extension.len())
+            .map(|(_, mime)| mime.as_str())
+    }
+
+    /// the adapter `mime_adapters` gives files of type `mimetype`
+    pub fn mime_adapter(&self, mimetype: &str) -> Option<&str> {
+        if let Some(adapter) = self.mime_adapters.get(mimetype) {
+            return Some(adapter);
+        }
+        self.mime_adapters
+            .iter()
+            .filter(|(glob, _)| match glob::Pattern::new(glob) {
+                Ok(pattern) => pattern.matches(mimetype),
+                Err(e) => {
+                    warn!("invalid mime type glob {glob:?}: {e}");
+                    false
+                }
+            })
+            .max_by_key(|(glob, _)| glob.len())
+            .map(|(_, adapter)| adapter.as_str())
+    }
+
+    /// how many bytes to read for detecting the type of a file, see `sniff_window`
+    pub fn sniff_len(&self) -> usize {
+        self.magics
+            .iter()
+            .map(|m| m.offset + m.bytes.bytes().filter(u8::is_ascii_hexdigit).count() / 2)
+            .fold(self.sniff_window.unwrap_or(DEFAULT_SNIFF_WINDOW), usize::max)
+    }
+
+    /// the first of `magics` that `head` (the start of a file) matches
+    pub fn magic(&self, head: &[u8]) -> Option<&Magic> {
+        self.magics
+            .iter()
+            .find(|m| match m.signature() {
+                Ok(signature) => head.get(m.offset..).is_some_and(|h| h.starts_with(&signature)),
+                Err(e) => {
+                    warn!("invalid magic bytes {:?}: {e}", m.bytes);
+                    false
+                }
+            })
+    }
+
+    pub fn has_archive_passwords(&self) -> bool {
+        !self.archive_password.is_empty()
+            || self.archive_password_file.is_some()
+            || self.archive_password_command.is_some()
+            || self.password.is_some()
+    }
+
+    /// the passwords to try on encrypted archive members, in order. `--rga-password` comes last
+    pub fn archive_passwords(&self) -> Result<Vec<String>> {
+        let mut passwords = self.archive_password.clone();
+        passwords.extend(self.archive_password_lines()?);
+        passwords.extend(self.password.clone());
+        Ok(passwords)
+    }
+
+    /// writes the lines of `archive_password_file` and `archive_password_command` to a private temporary file
+    /// (deleted when it is dropped) for the rga-preproc processes of a search, which get its path in
+    /// [`ARCHIVE_PASSWORD_LINES_ENV`]. rg starts one per file, so the command (e.g. a keyring prompt) runs once
+    /// per search instead of once per archive. If it fails the other passwords are still tried. None if
+    /// neither is set
+    pub fn archive_password_lines_file(&self) -> Result<Option<tempfile::NamedTempFile>> {
+        if self.archive_password_file.is_none() && self.archive_password_command.is_none() {
+            return Ok(None);
+        }
+        let lines = self.archive_password_lines().unwrap_or_else(|e| {
+            eprintln!("Warning: {e:#}, encrypted archive members are only tried with the other passwords");
+            vec![]
+        });
+        // only readable by the user
+        let mut file = tempfile::NamedTempFile::new()?;
+        for line in lines {
+            writeln!(file, "{line}")?;
+        }
+        file.flush()?;
+        Ok(Some(file))
+    }
+
+    /// the lines of `archive_password_file` and the output of `archive_password_command`, from the file of
+    /// [`ARCHIVE_PASSWORD_LINES_ENV`] if rga gives one. They are read and run once per process, not for every
+    /// archive. If that fails, every caller gets the error
+    fn archive_password_lines(&self) -> Result<Vec<String>> {
+        type Key = (Option<String>, Option<String>);
+        // the error as text, anyhow errors can't be cloned
+        type Lines = std::result::Result<Vec<String>, String>;
+        static LINES: Lazy<Mutex<HashMap<Key, Lines>>> = Lazy::new(Default::default);
+        let key = (
+            self.archive_password_file.clone(),
+            self.archive_password_command.clone(),
+        );
+        // held while reading, so concurrent archives wait for the command instead of running it too
+        let mut cache = LINES.lock().unwrap_or_else(|e| e.into_inner());
+        let lines = cache.entry(key).or_insert_with(|| {
+            match std::env::var_os(ARCHIVE_PASSWORD_LINES_ENV) {
+                Some(path) => std::fs::read_to_string(&path)
+                    .map(|text| text.lines().map(str::to_string).collect())
+                    .with_context(|| format!("reading archive passwords from {}", Path::new(&path).display())),
+                None => self.read_archive_password_lines(),
+            }
+            .map_err(|e| format!("{e:#}"))
+        });
+        lines.clone().map_err(|e| anyhow::format_err!(e))
+    }
+
+    fn read_archive_password_lines(&self) -> Result<Vec<String>> {
+        let mut lines = vec![];
+        let mut add_lines = |text: &str| {
+            lines.extend(text.lines().filter(|l| !l.is_empty()).map(str::to_string))
+        };
+        if let Some(path) = &self.archive_password_file {
+            add_lines(
+                &std::fs::read_to_string(path)
+                    .with_context(|| format!("reading archive password file {path}"))?,
+            );
+        }
+        if let Some(command) = &self.archive_password_command {
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let out = std::process::Command::new(shell)
+                .args([flag, command])
+                .stdin(std::process::Stdio::null())
+                .output()
+                .with_context(|| format!("running archive password command {command:?}"))?;
+            if !out.status.success() {
+                anyhow::bail!(
+                    "archive password command {command:?} failed ({}): {}",
+                    out.status,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                );
+            }
+            add_lines(&String::from_utf8(out.stdout)?);
+        }
+        Ok(lines)
+    }
+}
+
+const DEFAULT_SNIFF_WINDOW: usize = 64 * 1024;
+
+/// the file rga-preproc reads the archive password lines from, see [`RgaConfig::archive_password_lines_file`]
+pub const ARCHIVE_PASSWORD_LINES_ENV: &str = "RGA_ARCHIVE_PASSWORD_LINES";
+
+/// A byte signature at a fixed offset that selects an adapter, see `RgaConfig::magics`
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Clone, PartialEq)]
+pub struct Magic {
+    #[serde(default)]
+    pub offset: usize,
+    /// hex, e.g. `"43 44 30 30 31"`
+    pub bytes: String,
+    pub adapter: String,
+}
+
+impl Magic {
+    fn signature(&self) -> Result<Vec<u8>> {
+        let hex: Vec<u8> = self.bytes.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        if hex.is_empty() || !hex.len().is_multiple_of(2) {
+            anyhow::bail!("expected an even number of hex digits");
+        }
+        hex.chunks(2)
+            .map(|pair| {
+                u8::from_str_radix(std::str::from_utf8(pair)?, 16).context("not a hex digit")
+            })
+            .collect()
+    }
+}
+
+#[derive(Parser, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PostprocOptions {
+    /// Text that replaces the content of files detected as binary.
+    ///
+    /// Default: "[rga: binary data]"
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-binary-marker", require_equals = true)]
+    pub binary_marker: Option<String>,
+
+    /// Text before the page number on each line of paged documents (PDFs).
+    ///
+    /// Default: "Page "
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-page-prefix", require_equals = true)]
+    pub page_prefix: Option<String>,
+
+    /// Write the lines of pages without any text. With false, blank pages are left out.
+    ///
+    /// Default: true
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-page-include-empty", require_equals = true)]
+    pub page_include_empty: Option<bool>,
+
+    /// Zero-pad page numbers to this many digits, e.g. 3 for "Page 007: ".
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-page-number-width", require_equals = true)]
+    pub page_number_width: Option<usize>,
+
+    /// Number of the first page, e.g. 0 for zero-based page numbers.
+    ///
+    /// Default: 1
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-first-page", require_equals = true)]
+    pub first_page: Option<i32>,
+
+    /// Text after the path of a file in an archive and after the page number in the prefixes of
+    /// extracted lines, e.g. "\t" to split them from the text with `rga --no-heading -0 | awk -F'\t'`.
+    /// It must not contain a newline or NUL, those would end rg's records (and NUL makes rg take the
+    /// text for binary data).
+    ///
+    /// Default: ": "
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-prefix-separator", require_equals = true)]
+    pub prefix_separator: Option<String>,
+
+    /// How pages and binary files are marked: "text" for the page prefix and the binary marker,
+    /// "tokens" for `[rga:page=N]` and `[rga:binary]`, which stay the same whatever the locale and
+    /// the other options so scripts can rely on them, "both" for the token followed by the text.
+    ///
+    /// Default: "text"
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-markers", require_equals = true)]
+    pub markers: Option<String>,
+
+    /// Language of the page prefix and the binary marker, e.g. "de" for "Seite 3: ", or "auto" for
+    /// the language of the locale (LC_ALL, LC_MESSAGES or LANG). A page_prefix or binary_marker
+    /// that is set is used as is.
+    ///
+    /// Default: English
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-locale", require_equals = true)]
+    pub locale: Option<String>,
+
+    /// What to do with lines that are at the same place on most pages of a document, like running
+    /// headers and page footers: "keep" them, "remove" them, or "tag" them with `[rga:repeated] `
+    /// so they can be told apart from the text. Numbers in the lines may differ between the pages.
+    ///
+    /// Default: "keep"
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-postproc-repeated-lines", require_equals = true)]
+    pub repeated_lines: Option<String>,
+}
+
+/// the language of the locale in the environment, e.g. "de" for `LANG=de_DE.UTF-8`
+fn env_language() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .map(|value| value.split(['_', '.', '@']).next().unwrap_or_default().to_lowercase())
+        .filter(|lang| !lang.is_empty() && lang != "c" && lang != "posix")
+}
+
+impl PostprocOptions {
+    /// `self`, with the options set in `overrides` replaced
+    fn overridden_by(&self, overrides: &PostprocOptions) -> PostprocOptions {
+        PostprocOptions {
+            binary_marker: overrides.binary_marker.clone().or_else(|| self.binary_marker.clone()),
+            page_prefix: overrides.page_prefix.clone().or_else(|| self.page_prefix.clone()),
+            page_include_empty: overrides.page_include_empty.or(self.page_include_empty),
+            page_number_width: overrides.page_number_width.or(self.page_number_width),
+            first_page: overrides.first_page.or(self.first_page),
+            prefix_separator: overrides.prefix_separator.clone().or_else(|| self.prefix_separator.clone()),
+            markers: overrides.markers.clone().or_else(|| self.markers.clone()),
+            locale: overrides.locale.clone().or_else(|| self.locale.clone()),
+            repeated_lines: overrides.repeated_lines.clone().or_else(|| self.repeated_lines.clone()),
+        }
+    }
+
+    /// replaces `locale: "auto"` by the language of the environment, so it is part of the config
+    /// hash and rga-preproc gets the same one
+    fn resolve_locale(&mut self) {
+        if self.locale.as_deref() == Some("auto") {
+            self.locale = Some(env_language().unwrap_or_else(|| "en".to_string()));
+        }
+    }
+
+    /// errors for options that would break the framing of rg's output
+    fn check(&self) -> Result<()> {
+        for (name, value) in [
+            ("binary_marker", &self.binary_marker),
+            ("page_prefix", &self.page_prefix),
+            ("prefix_separator", &self.prefix_separator),
+        ] {
+            if value.as_deref().is_some_and(|v| v.contains(['\n', '\r', '\0'])) {
+                anyhow::bail!("postproc.{name} must not contain a newline or NUL");
+            }
+        }
+        if let Some(markers) = &self.markers
+            && !["text", "tokens", "both"].contains(&markers.as_str())
+        {
+            anyhow::bail!("Unknown postproc.markers: {markers}, expected text, tokens or both");
+        }
+        if let Some(repeated) = &self.repeated_lines
+            && !["keep", "remove", "tag"].contains(&repeated.as_str())
+        {
+            anyhow::bail!("Unknown postproc.repeated_lines: {repeated}, expected keep, remove or tag");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PostprocConfig {
+    #[serde(flatten)]
+    #[clap(flatten)]
+    pub options: PostprocOptions,
+
+    /// Options for the output of single adapters, including files nested in it (config file only), e.g.
+    /// `{"poppler": {"page_prefix": "p. ", "page_number_width": 3}}`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub adapters: BTreeMap<String, PostprocOptions>,
+}
+
+impl PostprocConfig {
+    /// the config to use for the output of `adapter`, if it has overrides
+    pub fn for_adapter(&self, adapter: &str) -> Option<PostprocConfig> {
+        let overrides = self.adapters.get(adapter)?;
+        Some(PostprocConfig {
+            options: self.options.overridden_by(overrides),
+            adapters: self.adapters.clone(),
+        })
+    }
+}
+
+/// Before the `postproc` section, its options were top level keys named `postproc_<option>`.
+fn migrate_legacy_postproc_keys(config: &mut Value) {
+    let Some(obj) = config.as_object_mut() else {
+        return;
+    };
+    for option in ["binary_marker", "page_prefix", "page_include_empty"] {
+        let Some(value) = obj.remove(&format!("postproc_{option}")) else {
+            continue;
+        };
+        warn!("config key postproc_{option} is deprecated, use postproc.{option}");
+        if let Some(postproc) = obj
+            .entry("postproc")
+            .or_insert_with(|| Value::Object(Default::default()))
+            .as_object_mut()
+        {
+            postproc.entry(option).or_insert(value);
+        }
+    }
+}
+
+#[derive(Parser, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct CacheConfig {
+    /// Type of cache backend to use.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "sqlite",
+        long = "rga-cache-type",
+        require_equals = true
+    )]
+    pub cache_type: String,
+
+    /// Disable caching of results.
+    ///
+    /// By default, rga caches the extracted text, if it is small enough, to a database.
+    /// This way, repeated searches on the same set of files will be much faster.
+    /// The location of the DB varies by platform:
+    /// - `${XDG_CACHE_DIR-~/.cache}/ripgrep-all` on Linux
+    /// - `~/Library/Caches/ripgrep-all` on macOS
+    /// - `C:\Users\username\AppData\Local\ripgrep-all` on Windows
+    ///
+    /// If you pass this flag, all caching will be disabled.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(long = "rga-no-cache")]
+    pub disabled: bool,
+
+    /// Max compressed size to cache.
+    ///
+    /// Longest byte length (after compression) to store in cache.
+    /// Longer adapter outputs will not be cached and recomputed every time.
+    ///
+    /// Allowed suffixes on command line: k M G
     #[serde(default, skip_serializing_if = "is_default")]
     #[clap(
         default_value_t = CacheMaxBlobLen(2000000),
@@ -310,6 +1293,103 @@ pub struct CacheConfig {
     )]
     pub max_blob_len: CacheMaxBlobLen,
 
+    /// Max total size of the cache.
+    ///
+    /// When the compressed outputs in the cache add up to more than this, entries are evicted
+    /// (see --rga-cache-eviction) until they are down to 90% of it. 0 means no limit. The database file can
+    /// stay somewhat larger, sqlite reuses the space of evicted entries instead of giving it back.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value_t = CacheMaxTotalSize(0),
+        long = "rga-cache-max-total-size",
+        require_equals = true
+    )]
+    pub max_total_size: CacheMaxTotalSize,
+
+    /// Which entries to evict when the cache is larger than --rga-cache-max-total-size.
+    ///
+    /// `lru` (the default) evicts the entries that were read least recently, `lfu` the ones that
+    /// were read the fewest times (least recently first among those).
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "",
+        hide_default_value = true,
+        long = "rga-cache-eviction",
+        require_equals = true
+    )]
+    pub eviction: String,
+
+    /// How cache entries are found for a file.
+    ///
+    /// `mtime` (the default) by the path, modification time and size of the file, `content-hash`
+    /// by a hash of its size and its first bytes (see --rga-cache-content-hash-len), so copies of a
+    /// file and files restored from backups or synced with new modification times use the entry
+    /// of the original.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "",
+        hide_default_value = true,
+        long = "rga-cache-key-mode",
+        require_equals = true
+    )]
+    pub key: String,
+
+    /// How much of a file is hashed with --rga-cache-key-mode=content-hash, 0 for all of it.
+    ///
+    /// Files of the same size that only differ after this many bytes share their entry.
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value_t = CacheContentHashLen(1000000),
+        long = "rga-cache-content-hash-len",
+        require_equals = true
+    )]
+    pub content_hash_len: CacheContentHashLen,
+
+    /// Namespace of the entries written to the cache, to list, size and clear the entries of a project on their own.
+    ///
+    /// By default the namespace of a file is the root of the git, mercurial or subversion repository it is in, files
+    /// outside of one have none. See --rga-cache-namespaces and --rga-cache-clear=namespace:NAME.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "",
+        hide_default_value = true,
+        long = "rga-cache-namespace",
+        require_equals = true
+    )]
+    pub namespace: String,
+
+    /// How long cache entries are used, by adapter (config file only).
+    ///
+    /// E.g. `{"tesseract": "30d", "mail": "12h", "*": "90d"}`, `*` for all adapters not listed. Older entries are
+    /// extracted again. Entries do not expire by default, they are only replaced when the file changes or the
+    /// adapter's version does.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub ttl: BTreeMap<String, CacheTtl>,
+
+    /// How long the local copies of remote roots (sftp://, s3://, http(s)://) are kept after they were last searched (config file only).
+    ///
+    /// E.g. `"7d"`. Default 30d. Searching a root again within this time only downloads the files that changed.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(skip)]
+    pub remote_ttl: Option<CacheTtl>,
+
+    /// How adapter outputs are compressed in the cache: `zstd` (the default), `lz4` or `none`.
+    ///
+    /// lz4 and none make writing and reading large outputs faster at the cost of a larger cache, e.g. on fast
+    /// SSDs. Changing it keeps the existing entries, each entry is read with the compression it was written with.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "",
+        hide_default_value = true,
+        long = "rga-cache-compression",
+        require_equals = true
+    )]
+    pub compression: String,
+
     /// ZSTD compression level to apply to adapter outputs before storing in cache DB.
     ///
     /// Ranges from 1 - 22.
@@ -341,6 +1421,67 @@ pub struct CacheConfig {
     pub daemon_port: u16,
 }
 
+/// regexes of the lines to keep or drop in the text of an adapter, see `post_filters`
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PostFilter {
+    /// If given, only lines matching one of these are kept.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub include: Vec<String>,
+    /// Lines matching one of these are dropped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub exclude: Vec<String>,
+}
+
+/// a saved search, see `monitors`
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct MonitorConfig {
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every: Option<CacheTtl>,
+}
+
+#[derive(Parser, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct OutputConfig {
+    /// Where to write the output of rg.
+    ///
+    /// `file:<path>` appends to a file (see --rga-output-max-size), `unix:<path>` writes to a unix
+    /// socket and `syslog` or `syslog:<tag>` sends each line to syslog or journald. By default
+    /// the output goes to stdout.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value = "",
+        hide_default_value = true,
+        long = "rga-output",
+        require_equals = true
+    )]
+    pub sink: String,
+
+    /// Size at which the file of --rga-output=file:<path> is rotated.
+    ///
+    /// The file is renamed to `<path>.1` (and the older ones to `<path>.2` and so on) before a
+    /// line that starts after this size. 0 means the file is never rotated.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value_t = OutputMaxSize(0),
+        long = "rga-output-max-size",
+        require_equals = true
+    )]
+    pub max_size: OutputMaxSize,
+
+    /// How many rotated files of --rga-output=file:<path> to keep.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[clap(
+        default_value_t = OutputKeep(5),
+        long = "rga-output-keep",
+        require_equals = true
+    )]
+    pub keep: OutputKeep,
+}
+
+pub use sources::{annotated, redacted};
+
 static RGA_CONFIG: &str = "RGA_CONFIG";
 static PREPROC_ENV_CONFIG: OnceCell<serde_json::Value> = OnceCell::new();
 
@@ -358,33 +1499,101 @@ fn json_merge(a: &mut Value, b: &Value) {
     }
 }
 
-fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
+/// the config file given with `--rga-config-file`, or the default one in the user config dir
+pub fn config_file_path(path_override: Option<&str>) -> Result<PathBuf> {
+    Ok(match path_override {
+        Some(p) => PathBuf::from(p),
+        None => project_dirs()?.config_dir().join("config.jsonc"),
+    })
+}
+
+/// the system-wide config file that the user config is merged over: `RGA_SYSTEM_CONFIG` if set (empty
+/// for none), else `/etc/rga/config.jsonc`, or `%ProgramData%\rga\config.jsonc` on Windows
+pub fn system_config_file_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("RGA_SYSTEM_CONFIG") {
+        return (!path.is_empty()).then(|| PathBuf::from(path));
+    }
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| OsString::from("C:\\ProgramData"));
+        Some(PathBuf::from(program_data).join("rga").join("config.jsonc"))
+    } else {
+        Some(PathBuf::from("/etc/rga/config.jsonc"))
+    }
+}
+
+/// a config file with comments, checked against the schema
+fn read_config_json(path: &Path) -> Result<Value> {
+    let path_str = path.to_string_lossy();
+    let contents = {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config file json {path_str}"))?;
+        let mut s = String::new();
+        json_comments::StripComments::new(raw.as_bytes())
+            .read_to_string(&mut s)
+            .context("strip comments")?;
+        s
+    };
+    {
+        // just for error messages, actual deserialization happens after merging with cmd args
+        serde_json::from_str::<RgaConfig>(&contents)
+            .with_context(|| format!("Error in config file {path_str}: {contents}"))?;
+    }
+    let mut config_json: serde_json::Value =
+        serde_json::from_str(&contents).context("Could not parse config json")?;
+    migrate_legacy_postproc_keys(&mut config_json);
+    Ok(config_json)
+}
+
+/// merges the user config over the system config. Custom adapters are merged by name, so users can
+/// add their own and replace single ones of the system config
+fn merge_config_layer(system: &mut Value, user: &Value) {
+    let system_adapters = system
+        .get_mut("custom_adapters")
+        .map(Value::take)
+        .and_then(|a| match a {
+            Value::Array(a) => Some(a),
+            _ => None,
+        });
+    json_merge(system, user);
+    let (Some(mut adapters), Some(obj)) = (system_adapters, system.as_object_mut()) else {
+        return;
+    };
+    let name = |a: &Value| a.get("name").cloned();
+    if let Some(Value::Array(user_adapters)) = user.get("custom_adapters") {
+        for adapter in user_adapters {
+            match adapters.iter_mut().find(|a| name(a) == name(adapter)) {
+                Some(existing) => *existing = adapter.clone(),
+                None => adapters.push(adapter.clone()),
+            }
+        }
+    }
+    obj.insert("custom_adapters".to_string(), Value::Array(adapters));
+}
+
+fn read_config_file(
+    path_override: Option<String>,
+    sources: &mut Vec<(String, Value)>,
+) -> Result<(String, Value)> {
     let proj = project_dirs()?;
     let config_dir = proj.config_dir();
-    let config_filename = path_override
-        .as_ref()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| config_dir.join("config.jsonc"));
+    let config_filename = config_file_path(path_override.as_deref())?;
     let config_filename_str = config_filename.to_string_lossy().into_owned();
+    let system_config = match system_config_file_path().filter(|p| p.exists()) {
+        Some(path) => {
+            let config = read_config_json(&path)?;
+            sources.push((path.to_string_lossy().into_owned(), config.clone()));
+            Some(config)
+        }
+        None => None,
+    };
     if config_filename.exists() {
-        let config_file_contents = {
-            let raw = std::fs::read_to_string(config_filename).with_context(|| {
-                format!("Could not read config file json {config_filename_str}")
-            })?;
-            let mut s = String::new();
-            json_comments::StripComments::new(raw.as_bytes())
-                .read_to_string(&mut s)
-                .context("strip comments")?;
-            s
-        };
-        {
-            // just for error messages, actual deserialization happens after merging with cmd args
-            serde_json::from_str::<RgaConfig>(&config_file_contents).with_context(|| {
-                format!("Error in config file {config_filename_str}: {config_file_contents}")
-            })?;
+        let mut config_json = read_config_json(&config_filename)?;
+        sources.push((config_filename_str.clone(), config_json.clone()));
+        if let Some(mut system) = system_config {
+            merge_config_layer(&mut system, &config_json);
+            config_json = system;
         }
-        let config_json: serde_json::Value =
-            serde_json::from_str(&config_file_contents).context("Could not parse config json")?;
         Ok((config_filename_str, config_json))
     } else if let Some(p) = path_override.as_ref() {
         Err(anyhow::anyhow!("Config file not found: {}", p))?
@@ -401,10 +1610,207 @@ fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
         configfile.write_all(include_str!("../doc/config.default.jsonc").as_bytes())?;
         Ok((
             config_filename_str,
-            serde_json::Value::Object(Default::default()),
+            system_config.unwrap_or_else(|| serde_json::Value::Object(Default::default())),
         ))
     }
 }
+/// the name of the project config files, like `.ripgreprc` or `.editorconfig`
+pub static PROJECT_CONFIG_FILE: &str = ".rga.toml";
+
+/// the `.rga.toml` files in the directories of the searched paths and their parents, outermost first.
+/// Paths that don't exist (like the pattern) are skipped, without any the current directory is used
+pub fn project_config_files(search_paths: &[OsString]) -> Vec<PathBuf> {
+    let mut starts = search_paths
+        .iter()
+        .map(Path::new)
+        .filter(|p| p.exists())
+        .filter_map(|p| std::path::absolute(p).ok())
+        .map(|p| if p.is_dir() { p } else { p.parent().map(Path::to_path_buf).unwrap_or(p) })
+        .collect::<Vec<_>>();
+    if starts.is_empty() {
+        starts.extend(std::env::current_dir().ok());
+    }
+    let mut files = vec![];
+    for start in starts {
+        let mut found = start
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .filter(|file| file.is_file() && !files.contains(file))
+            .collect::<Vec<_>>();
+        found.reverse();
+        files.extend(found);
+    }
+    files
+}
+
+fn read_config_toml(path: &Path) -> Result<Value> {
+    let path_str = path.to_string_lossy();
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read config file {path_str}"))?;
+    // like read_config_json, only for error messages
+    toml::from_str::<RgaConfig>(&contents)
+        .with_context(|| format!("Error in config file {path_str}"))?;
+    let mut config: Value = toml::from_str(&contents)
+        .with_context(|| format!("Could not parse config file {path_str}"))?;
+    migrate_legacy_postproc_keys(&mut config);
+    Ok(config)
+}
+
+/// the keys a `.rga.toml` may set if its directory is not in `trusted_projects`. They only change which
+/// files are searched and how single adapters extract, nothing that runs programs, loads code, writes files
+/// or drops lines from the text. `adapters` is also taken, but only to remove adapters, see [`narrow_adapters`]
+const PROJECT_KEYS: &[&str] = &[
+    "accurate",
+    "robust",
+    "salvage",
+    "max_archive_recursion",
+    "no_prefix_filenames",
+    "redact_secrets",
+    "verify",
+    "sniff_window",
+    "zip_extensions",
+    "ffmpeg_extensions",
+    "subtitle_languages",
+    "video_ocr_interval",
+    "whisper_language",
+    "min_confidence",
+    "parquet_max_rows",
+    "parquet_columns",
+    "sqlite_include",
+    "sqlite_exclude",
+];
+
+/// the `adapters` of an untrusted project config applied to those selected so far, if it only removes
+/// adapters from them. The project's list replaces the user's when merged, so it is rewritten to remove
+/// its adapters from the user's selection instead of from the defaults
+fn narrow_adapters(selected: &[String], project: &[String]) -> Option<Vec<String>> {
+    let removed = match project.split_first() {
+        Some((first, rest)) => std::iter::once(first.strip_prefix('-')?).chain(rest.iter().map(String::as_str)),
+        None => return None,
+    };
+    match selected.first() {
+        None => Some(project.to_vec()),
+        Some(first) if first.starts_with('-') => {
+            let mut adapters = selected.to_vec();
+            for name in removed {
+                if !adapters.iter().any(|a| a.trim_start_matches('-') == name) {
+                    adapters.push(name.to_string());
+                }
+            }
+            Some(adapters)
+        }
+        // "+a,b" can't be combined with removals, and removing all of "a,b" would mean the defaults
+        Some(first) if first.starts_with('+') => None,
+        Some(_) => {
+            let removed = removed.collect::<Vec<_>>();
+            let adapters = selected
+                .iter()
+                .filter(|a| !removed.contains(&a.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+            Some(adapters).filter(|a| !a.is_empty())
+        }
+    }
+}
+
+/// merges the project config files over the user config. Files in directories that are not in
+/// `trusted_projects` of the user config can only set the [`PROJECT_KEYS`] and remove adapters, none can
+/// select or define profiles
+fn merge_project_configs(
+    config: &mut Value,
+    files: &[PathBuf],
+    sources: &mut Vec<(String, Value)>,
+) -> Result<()> {
+    // canonicalized, so a symlink to a project can't pass for a trusted directory
+    let trusted = config
+        .get("trusted_projects")
+        .cloned()
+        .map(serde_json::from_value::<Vec<String>>)
+        .transpose()
+        .context("trusted_projects")?
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|dir| std::fs::canonicalize(dir).ok())
+        .collect::<Vec<_>>();
+    for file in files {
+        // any directory above the searched paths can have one, so a broken file must not stop the search
+        let mut project = match read_config_toml(file) {
+            Ok(project) => project,
+            Err(e) => {
+                eprintln!("Warning: ignoring project config file {}: {:#}", file.display(), e);
+                continue;
+            }
+        };
+        if let Some(obj) = project.as_object_mut() {
+            let dir = file.parent().unwrap_or(file);
+            let is_trusted = std::fs::canonicalize(dir).is_ok_and(|dir| trusted.contains(&dir));
+            if !is_trusted && let Some(adapters) = obj.get_mut("adapters") {
+                let selected = config
+                    .get("adapters")
+                    .cloned()
+                    .and_then(|a| serde_json::from_value::<Vec<String>>(a).ok())
+                    .unwrap_or_default();
+                let narrowed = serde_json::from_value::<Vec<String>>(adapters.clone())
+                    .ok()
+                    .and_then(|project| narrow_adapters(&selected, &project));
+                match narrowed {
+                    Some(narrowed) => *adapters = serde_json::json!(narrowed),
+                    None => {
+                        eprintln!(
+                            "Warning: ignoring adapters of {}, project config files can only remove adapters (\"-a,b\") unless {} is in trusted_projects of your config file",
+                            file.display(),
+                            dir.display()
+                        );
+                        obj.remove("adapters");
+                    }
+                }
+            }
+            let ignored = obj
+                .keys()
+                .filter(|key| {
+                    ["trusted_projects", "profile", "profiles"].contains(&key.as_str())
+                        || !(is_trusted || key.as_str() == "adapters" || PROJECT_KEYS.contains(&key.as_str()))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            for key in &ignored {
+                obj.remove(key);
+            }
+            if !ignored.is_empty() {
+                eprintln!(
+                    "Warning: ignoring {} of {}, project config files can only set them if {} is in trusted_projects of your config file (and never profiles)",
+                    ignored.join(", "),
+                    file.display(),
+                    dir.display()
+                );
+            }
+        }
+        debug!("Project config {}: {}", file.display(), project);
+        merge_config_layer(config, &project);
+        sources.push((file.to_string_lossy().into_owned(), project));
+    }
+    Ok(())
+}
+
+/// merges the profile `name` of the `profiles` of the config over it
+fn apply_profile(config: &mut Value, name: &str, sources: &mut Vec<(String, Value)>) -> Result<()> {
+    let profile = config.get("profiles").and_then(|p| p.get(name)).cloned();
+    let Some(profile) = profile else {
+        let names = config
+            .get("profiles")
+            .and_then(Value::as_object)
+            .map(|p| p.keys().cloned().collect::<Vec<_>>().join(", "))
+            .unwrap_or_default();
+        anyhow::bail!("Unknown profile {name}, the config file has: {names}");
+    };
+    serde_json::from_value::<RgaConfig>(profile.clone())
+        .with_context(|| format!("Error in profile {name}"))?;
+    debug!("Profile {name}: {profile}");
+    merge_config_layer(config, &profile);
+    sources.push((format!("profile {name}"), profile));
+    Ok(())
+}
+
 fn read_config_env() -> Result<Value> {
     let val = std::env::var(RGA_CONFIG).ok();
     if let Some(val) = val {
@@ -423,6 +1829,15 @@ fn read_config_env_cached() -> Result<Value> {
     }
 }
 pub fn parse_args<I>(args: I, is_rga_preproc: bool) -> Result<RgaConfig>
+where
+    I: IntoIterator,
+    I::Item: Into<OsString> + Clone,
+{
+    parse_args_in(args, is_rga_preproc, &[])
+}
+
+/// like parse_args, with the project config files of the given search paths
+fn parse_args_in<I>(args: I, is_rga_preproc: bool, search_paths: &[OsString]) -> Result<RgaConfig>
 where
     I: IntoIterator,
     I::Item: Into<OsString> + Clone,
@@ -432,6 +1847,7 @@ where
     let arg_matches: RgaConfig = RgaConfig::parse_from(args);
     let args_config = serde_json::to_value(&arg_matches)?;
 
+    let mut sources = vec![];
     let merged_config = {
         if is_rga_preproc {
             // only read from env and args
@@ -441,9 +1857,28 @@ where
             merged_config
         } else {
             // read from config file, env and args
-            let (config_filename, config_file_config) =
-                read_config_file(arg_matches.config_file_path)?;
+            let (config_filename, mut config_file_config) =
+                read_config_file(arg_matches.config_file_path.clone(), &mut sources)?;
+            if !arg_matches.no_project_config {
+                let files = project_config_files(search_paths);
+                merge_project_configs(&mut config_file_config, &files, &mut sources)?;
+            }
+            let profile = arg_matches.profile.clone().or_else(|| {
+                config_file_config.get("profile").and_then(Value::as_str).map(str::to_string)
+            });
+            if let Some(profile) = profile {
+                apply_profile(&mut config_file_config, &profile, &mut sources)?;
+            }
             let env_var_config = read_config_env()?;
+            if std::env::var_os(RGA_CONFIG).is_some() {
+                sources.push((RGA_CONFIG.to_string(), env_var_config.clone()));
+            }
+            // the defaults of the flags are in args_config too
+            let cli_defaults = serde_json::to_value(RgaConfig::parse_from(["rga"]))?;
+            sources.extend(
+                sources::changed(&args_config, &cli_defaults)
+                    .map(|args| ("command line".to_string(), args)),
+            );
             let mut merged_config = config_file_config.clone();
             json_merge(&mut merged_config, &env_var_config);
             json_merge(&mut merged_config, &args_config);
@@ -472,17 +1907,55 @@ where
                 serde_json::to_string_pretty(&merged_config).expect("no tostring")
             )
         })?;
+    res.postproc.options.check()?;
+    res.postproc.options.resolve_locale();
+    for options in res.postproc.adapters.values_mut() {
+        options.check()?;
+        options.resolve_locale();
+    }
+    for (adapter, filter) in &res.post_filters {
+        crate::adapters::postproc::LineFilter::new(filter)
+            .with_context(|| format!("Invalid regex in post_filters.{adapter}"))?;
+    }
     {
         // readd values with [serde(skip)]
         res.fzf_path = arg_matches.fzf_path;
         res.list_adapters = arg_matches.list_adapters;
         res.print_config_schema = arg_matches.print_config_schema;
+        res.print_rg_args = arg_matches.print_rg_args;
         res.rg_help = arg_matches.rg_help;
         res.rg_version = arg_matches.rg_version;
         res.doctor = arg_matches.doctor;
         res.cache_clear = arg_matches.cache_clear;
         res.cache_prune = arg_matches.cache_prune;
+        res.cache_key = arg_matches.cache_key;
+        res.show_extracted = arg_matches.show_extracted;
+        res.from_cache_only = arg_matches.from_cache_only;
+        res.cache_stats = arg_matches.cache_stats;
+        res.cache_namespaces = arg_matches.cache_namespaces;
+        res.cache_export = arg_matches.cache_export;
+        res.cache_export_filter = arg_matches.cache_export_filter;
+        res.cache_import = arg_matches.cache_import;
+        res.stats = arg_matches.stats;
+        res.search_stats = arg_matches.search_stats;
+        res.dupes = arg_matches.dupes;
+        res.wc = arg_matches.wc;
+        res.extract_all = arg_matches.extract_all;
+        res.warm = arg_matches.warm;
+        res.matches_manifest = arg_matches.matches_manifest;
+        res.matches_sidecars = arg_matches.matches_sidecars;
+        res.monitor = arg_matches.monitor;
+        res.tui = arg_matches.tui;
         res.daemon = arg_matches.daemon;
+        res.docker_image = arg_matches.docker_image;
+        res.parallel = arg_matches.parallel;
+        res.files_from = arg_matches.files_from;
+        res.files0_from = arg_matches.files0_from;
+        res.adapter_install = arg_matches.adapter_install;
+        res.config_file_path = arg_matches.config_file_path;
+        res.no_project_config = arg_matches.no_project_config;
+        res.print_config = arg_matches.print_config;
+        res.config_sources = sources;
     }
     Ok(res)
 }
@@ -512,7 +1985,13 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
             }
         });
     debug!("rga (our) args: {:?}", our_args);
-    let matches = parse_args(our_args, is_rga_preproc).context("Could not parse config")?;
+    let search_paths = passthrough_args
+        .iter()
+        .filter(|a| !a.to_string_lossy().starts_with('-'))
+        .cloned()
+        .collect::<Vec<_>>();
+    let matches = parse_args_in(our_args, is_rga_preproc, &search_paths)
+        .context("Could not parse config")?;
     if matches.rg_help {
         passthrough_args.insert(0, "--help".into());
     }
@@ -523,3 +2002,285 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
     Ok((matches, passthrough_args))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_overrides() {
+        let map = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let config = RgaConfig {
+            mime_adapters: map(&[("text/*", "text-adapter"), ("text/plain", "logs"), ("application/*zip", "zip")]),
+            extension_mimes: map(&[("gz", "application/gzip"), (".TAR.GZ", "application/x-tar"), ("log", "text/plain")]),
+            ..Default::default()
+        };
+        assert_eq!(config.mime_adapter("text/plain"), Some("logs"));
+        assert_eq!(config.mime_adapter("text/csv"), Some("text-adapter"));
+        assert_eq!(config.mime_adapter("application/x-zip"), Some("zip"));
+        assert_eq!(config.mime_adapter("application/pdf"), None);
+        assert_eq!(config.extension_mime("backup.tar.gz"), Some("application/x-tar"));
+        assert_eq!(config.extension_mime("notes.GZ"), Some("application/gzip"));
+        assert_eq!(config.extension_mime("server.log"), Some("text/plain"));
+        assert_eq!(config.extension_mime("catalog"), None);
+        assert_eq!(config.extension_mime("log"), None);
+    }
+
+    #[test]
+    fn magics() {
+        let magic = |offset, bytes: &str, adapter: &str| Magic {
+            offset,
+            bytes: bytes.to_string(),
+            adapter: adapter.to_string(),
+        };
+        let config = RgaConfig {
+            magics: vec![
+                magic(4, "zz", "invalid"),
+                magic(2, "52 47 41", "rga"),
+                magic(100_000, "00", "far"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(config.magic(b"..RGA..").map(|m| m.adapter.as_str()), Some("rga"));
+        assert!(config.magic(b"RGA").is_none());
+        assert_eq!(config.sniff_len(), 100_001);
+        assert_eq!(RgaConfig::default().sniff_len(), 64 * 1024);
+    }
+
+    #[test]
+    fn system_config_layer() {
+        let mut system = serde_json::json!({
+            "cache": {"max_total_size": 1000, "eviction": "lfu"},
+            "custom_adapters": [
+                {"name": "a", "binary": "system-a"},
+                {"name": "b", "binary": "system-b"}
+            ]
+        });
+        let user = serde_json::json!({
+            "cache": {"eviction": "lru"},
+            "custom_adapters": [
+                {"name": "b", "binary": "user-b"},
+                {"name": "c", "binary": "user-c"}
+            ]
+        });
+        merge_config_layer(&mut system, &user);
+        assert_eq!(
+            system,
+            serde_json::json!({
+                "cache": {"max_total_size": 1000, "eviction": "lru"},
+                "custom_adapters": [
+                    {"name": "a", "binary": "system-a"},
+                    {"name": "b", "binary": "user-b"},
+                    {"name": "c", "binary": "user-c"}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn project_configs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let outer = dir.path().join("repo");
+        let inner = outer.join("docs");
+        std::fs::create_dir_all(&inner)?;
+        let adapter = |name: &str| {
+            format!(
+                "[[custom_adapters]]\nname = \"{name}\"\ndescription = \"\"\nversion = 1\n\
+                 extensions = [\"{name}\"]\nbinary = \"cat\"\nargs = []\n"
+            )
+        };
+        std::fs::write(
+            outer.join(PROJECT_CONFIG_FILE),
+            format!("adapters = [\"-zip\"]\n{}", adapter("outer")),
+        )?;
+        std::fs::write(
+            inner.join(PROJECT_CONFIG_FILE),
+            format!("accurate = true\n{}", adapter("inner")),
+        )?;
+        std::fs::write(inner.join("a.txt"), "")?;
+        let search = |paths: &[&Path]| {
+            project_config_files(&paths.iter().map(|p| p.as_os_str().to_owned()).collect::<Vec<_>>())
+        };
+        let files = search(&[Path::new("some pattern"), &inner.join("a.txt"), &outer]);
+        assert_eq!(files, vec![outer.join(PROJECT_CONFIG_FILE), inner.join(PROJECT_CONFIG_FILE)]);
+
+        let mut config = serde_json::json!({
+            "trusted_projects": [inner],
+            "custom_adapters": [{"name": "user"}]
+        });
+        merge_project_configs(&mut config, &files, &mut vec![])?;
+        let names = config["custom_adapters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|a| a["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["user", "inner"]);
+        assert_eq!(config["adapters"], serde_json::json!(["-zip"]));
+        assert_eq!(config["accurate"], true);
+        Ok(())
+    }
+
+    #[test]
+    fn broken_project_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let broken = dir.path().join(PROJECT_CONFIG_FILE);
+        std::fs::write(&broken, "adapters = [\"-zip\"\n")?;
+        let inner = dir.path().join("docs");
+        std::fs::create_dir(&inner)?;
+        std::fs::write(inner.join(PROJECT_CONFIG_FILE), "adapters = [\"-zip\"]\n")?;
+        let mut config = serde_json::json!({});
+        merge_project_configs(&mut config, &[broken, inner.join(PROJECT_CONFIG_FILE)], &mut vec![])?;
+        assert_eq!(config["adapters"], serde_json::json!(["-zip"]));
+        Ok(())
+    }
+
+    #[test]
+    fn untrusted_project_keys() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join(PROJECT_CONFIG_FILE);
+        let blocked = [
+            ("plugins_dir", "plugins_dir = \"/tmp/evil\""),
+            ("archive_password_command", "archive_password_command = \"touch /tmp/pwned\""),
+            ("whisper_binary", "whisper_binary = \"/tmp/evil\""),
+            ("whisper_args", "whisper_args = [\"--evil\"]"),
+            ("output", "[output]\nsink = \"file:/tmp/important\"\nkeep = 0"),
+            ("cache", "[cache]\npath = \"/tmp/planted\""),
+            ("profile", "profile = \"evil\""),
+            ("profiles", "[profiles.evil]\nplugins_dir = \"/tmp/evil\""),
+            ("custom_adapters", "[[custom_adapters]]\nname = \"evil\"\ndescription = \"\"\nversion = 1\nextensions = [\"x\"]\nbinary = \"sh\"\nargs = []"),
+            ("wasm_adapters", "[[wasm_adapters]]\nname = \"evil\"\ndescription = \"\"\nversion = 1\nmodule = \"/tmp/evil.wasm\"\nextensions = [\"x\"]\nargs = []"),
+            ("trusted_projects", "trusted_projects = [\"/\"]"),
+            ("mime_adapters", "[mime_adapters]\n\"text/plain\" = \"pandoc\""),
+            ("extension_mimes", "[extension_mimes]\ntxt = \"video/mp4\""),
+            ("magics", "[[magics]]\noffset = 0\nbytes = \"00\"\nadapter = \"ffmpeg\""),
+            ("postproc", "[postproc]\nbinary_marker = \"\""),
+            ("post_filters", "[post_filters.poppler]\nexclude = [\"password\"]"),
+            ("adapters", "adapters = [\"ffmpeg\", \"pandoc\"]"),
+            ("adapters", "adapters = [\"+whisper\"]"),
+        ];
+        for (key, toml) in blocked {
+            std::fs::write(&file, format!("accurate = true\n{toml}\n"))?;
+            let mut config = serde_json::json!({});
+            merge_project_configs(&mut config, std::slice::from_ref(&file), &mut vec![])?;
+            assert_eq!(config.get(key), None, "{key} was taken from an untrusted project");
+            assert_eq!(config["accurate"], true);
+        }
+        // trusted projects can set anything, but still not pick a profile of the user
+        std::fs::write(&file, "plugins_dir = \"plugins\"\nprofile = \"evil\"\n")?;
+        let mut config = serde_json::json!({"trusted_projects": [dir.path()]});
+        merge_project_configs(&mut config, std::slice::from_ref(&file), &mut vec![])?;
+        assert_eq!(config["plugins_dir"], "plugins");
+        assert_eq!(config.get("profile"), None);
+        // directories are compared by where they are, not by how they are named
+        #[cfg(unix)]
+        {
+            let other = tempfile::tempdir()?;
+            std::fs::create_dir(other.path().join("sub"))?;
+            std::fs::write(other.path().join(PROJECT_CONFIG_FILE), "plugins_dir = \"evil\"\n")?;
+            std::os::unix::fs::symlink(other.path().join("sub"), dir.path().join("link"))?;
+            let linked = dir.path().join("link/..").join(PROJECT_CONFIG_FILE);
+            let trusted_dir = |trusted: &Path| -> Result<Value> {
+                let mut config = serde_json::json!({"trusted_projects": [trusted]});
+                merge_project_configs(&mut config, std::slice::from_ref(&linked), &mut vec![])?;
+                Ok(config)
+            };
+            assert_eq!(trusted_dir(dir.path())?.get("plugins_dir"), None);
+            assert_eq!(trusted_dir(&dir.path().join("link/.."))?["plugins_dir"], "evil");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn untrusted_project_adapters() {
+        let narrow = |selected: &[&str], project: &[&str]| {
+            let strings = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            narrow_adapters(&strings(selected), &strings(project))
+        };
+        assert_eq!(narrow(&[], &["-zip", "tar"]).unwrap(), ["-zip", "tar"]);
+        assert_eq!(narrow(&["-zip"], &["-zip", "tar"]).unwrap(), ["-zip", "tar"]);
+        assert_eq!(narrow(&["poppler", "zip"], &["-zip"]).unwrap(), ["poppler"]);
+        assert_eq!(narrow(&["zip"], &["-zip"]), None);
+        assert_eq!(narrow(&["+whisper"], &["-zip"]), None);
+        assert_eq!(narrow(&[], &["ffmpeg"]), None);
+        assert_eq!(narrow(&[], &["+ffmpeg"]), None);
+    }
+
+    #[test]
+    fn archive_password_command_runs_once() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let runs = dir.path().join("runs");
+        let config = RgaConfig {
+            archive_password: vec!["first".to_string()],
+            archive_password_command: Some(format!("echo run >> '{}'; echo secret", runs.display())),
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            assert_eq!(config.archive_passwords()?, ["first", "secret"]);
+        }
+        assert_eq!(std::fs::read_to_string(&runs)?, "run\n");
+        let failing = RgaConfig {
+            archive_password: vec!["first".to_string()],
+            archive_password_command: Some(format!("echo run >> '{}'; exit 3", runs.display())),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let err = failing.archive_passwords().unwrap_err().to_string();
+            assert!(err.starts_with("archive password command"), "{err}");
+        }
+        assert_eq!(std::fs::read_to_string(&runs)?, "run\nrun\n");
+        Ok(())
+    }
+
+    #[test]
+    fn archive_password_lines_file() -> Result<()> {
+        assert!(RgaConfig::default().archive_password_lines_file()?.is_none());
+        let config = RgaConfig {
+            archive_password: vec!["not in the file".to_string()],
+            archive_password_command: Some("echo one; echo; echo two".to_string()),
+            ..Default::default()
+        };
+        let file = config.archive_password_lines_file()?.unwrap();
+        assert_eq!(std::fs::read_to_string(file.path())?, "one\ntwo\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(file.as_file().metadata()?.permissions().mode() & 0o777, 0o600);
+        }
+        let path = file.path().to_owned();
+        drop(file);
+        assert!(!path.exists());
+        let failing = RgaConfig {
+            archive_password_command: Some("exit 3".to_string()),
+            ..Default::default()
+        };
+        let file = failing.archive_password_lines_file()?.unwrap();
+        assert_eq!(std::fs::read_to_string(file.path())?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn profiles() -> Result<()> {
+        let mut config = serde_json::json!({
+            "adapters": ["-zip"],
+            "accurate": false,
+            "profiles": {
+                "fast": {"adapters": ["-ffmpeg", "-sqlite"]},
+                "thorough": {"accurate": true}
+            }
+        });
+        apply_profile(&mut config, "thorough", &mut vec![])?;
+        assert_eq!(config["accurate"], true);
+        assert_eq!(config["adapters"], serde_json::json!(["-zip"]));
+        apply_profile(&mut config, "fast", &mut vec![])?;
+        assert_eq!(config["adapters"], serde_json::json!(["-ffmpeg", "-sqlite"]));
+        let err = apply_profile(&mut config, "slow", &mut vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown profile slow, the config file has: fast, thorough");
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! `--rga-print-config`: the merged config as JSONC, with a comment after every value naming the config
+//! file, profile, `RGA_CONFIG` or the command line it came from.
+
+use super::RgaConfig;
+use anyhow::Result;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// the parts of `value` that differ from `base`
+pub(super) fn changed(value: &Value, base: &Value) -> Option<Value> {
+    match (value, base) {
+        (Value::Object(value), Value::Object(base)) => {
+            let changed = value
+                .iter()
+                .filter_map(|(k, v)| {
+                    let v = match base.get(k) {
+                        Some(b) => changed(v, b)?,
+                        None => v.clone(),
+                    };
+                    Some((k.clone(), v))
+                })
+                .collect::<serde_json::Map<_, _>>();
+            (!changed.is_empty()).then_some(Value::Object(changed))
+        }
+        (value, base) => (value != base).then(|| value.clone()),
+    }
+}
+
+/// values that must not be printed
+const SECRETS: &[&str] = &["/password", "/document_passwords", "/archive_password"];
+
+struct Printer<'a> {
+    sources: &'a [(String, Value)],
+    out: String,
+}
+
+impl Printer<'_> {
+    /// the last source that set the value at `pointer`
+    fn origin(&self, pointer: &str) -> &str {
+        self.sources
+            .iter()
+            .rev()
+            .find(|(_, layer)| layer.pointer(pointer).is_some())
+            .map_or("default", |(name, _)| name.as_str())
+    }
+
+    /// custom adapters are merged by name, each can come from another file
+    fn adapter_origin(&self, pointer: &str, adapter: &Value) -> &str {
+        self.sources
+            .iter()
+            .rev()
+            .find(|(_, layer)| {
+                layer
+                    .pointer(pointer)
+                    .and_then(Value::as_array)
+                    .is_some_and(|a| a.iter().any(|a| a.get("name") == adapter.get("name")))
+            })
+            .map_or("default", |(name, _)| name.as_str())
+    }
+
+    fn line(&mut self, indent: usize, text: &str, comma: bool, origin: Option<&str>) {
+        let comma = if comma { "," } else { "" };
+        let _ = write!(self.out, "{:indent$}{text}{comma}", "", indent = indent * 2);
+        if let Some(origin) = origin {
+            let _ = write!(self.out, " // {origin}");
+        }
+        self.out.push('\n');
+    }
+
+    fn object(&mut self, object: &serde_json::Map<String, Value>, pointer: &str, indent: usize) {
+        let entries = object.iter().filter(|(_, v)| !v.is_null()).collect::<Vec<_>>();
+        for (i, (key, value)) in entries.iter().enumerate() {
+            let comma = i + 1 < entries.len();
+            let pointer = format!("{pointer}/{}", key.replace('~', "~0").replace('/', "~1"));
+            let key = serde_json::to_string(key).unwrap_or_default();
+            if SECRETS.contains(&pointer.as_str()) {
+                let origin = self.origin(&pointer).to_string();
+                self.line(indent, &format!("{key}: \"<redacted>\""), comma, Some(&origin));
+                continue;
+            }
+            match value {
+                Value::Object(inner) if !inner.is_empty() => {
+                    self.line(indent, &format!("{key}: {{"), false, None);
+                    self.object(inner, &pointer, indent + 1);
+                    self.line(indent, "}", comma, None);
+                }
+                Value::Array(adapters) if pointer.ends_with("_adapters") && !adapters.is_empty() => {
+                    self.line(indent, &format!("{key}: ["), false, None);
+                    for (j, adapter) in adapters.iter().enumerate() {
+                        let origin = self.adapter_origin(&pointer, adapter).to_string();
+                        let text = serde_json::to_string(adapter).unwrap_or_default();
+                        self.line(indent + 1, &text, j + 1 < adapters.len(), Some(&origin));
+                    }
+                    self.line(indent, "]", comma, None);
+                }
+                value => {
+                    let origin = self.origin(&pointer).to_string();
+                    let text = format!("{key}: {}", serde_json::to_string(value).unwrap_or_default());
+                    self.line(indent, &text, comma, Some(&origin));
+                }
+            }
+        }
+    }
+}
+
+/// the config as JSONC, every value annotated with where it came from
+pub fn annotated(config: &RgaConfig) -> Result<String> {
+    let value = serde_json::to_value(config)?;
+    let mut printer = Printer {
+        sources: &config.config_sources,
+        out: String::new(),
+    };
+    printer.line(0, "{", false, None);
+    if let Value::Object(object) = &value {
+        printer.object(object, "", 1);
+    }
+    printer.line(0, "}", false, None);
+    Ok(printer.out)
+}
+
+/// the config as JSON, with the secrets (see [`SECRETS`]) replaced by `<redacted>`
+pub fn redacted(config: &RgaConfig) -> Result<Value> {
+    let mut value = serde_json::to_value(config)?;
+    for pointer in SECRETS {
+        if let Some(secret) = value.pointer_mut(pointer).filter(|v| !v.is_null()) {
+            *secret = Value::from("<redacted>");
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn origins() -> Result<()> {
+        let layer = |name: &str, value: Value| (name.to_string(), value);
+        let adapter = |name: &str, binary: &str| {
+            serde_json::json!({
+                "name": name, "description": "", "version": 1, "extensions": [name],
+                "binary": binary, "args": []
+            })
+        };
+        let config: RgaConfig = serde_json::from_value(serde_json::json!({
+            "accurate": true,
+            "adapters": ["-zip"],
+            "password": "hunter2",
+            "cache": {"max_blob_len": 1000},
+            "custom_adapters": [adapter("a", "user-a"), adapter("b", "project-b")]
+        }))?;
+        let config = RgaConfig {
+            config_sources: vec![
+                layer("user.jsonc", serde_json::json!({
+                    "adapters": ["-decompress"],
+                    "password": "hunter2",
+                    "cache": {"max_blob_len": 1000},
+                    "custom_adapters": [adapter("a", "user-a")]
+                })),
+                layer(".rga.toml", serde_json::json!({
+                    "adapters": ["-zip"],
+                    "custom_adapters": [adapter("b", "project-b")]
+                })),
+                layer("command line", serde_json::json!({"accurate": true})),
+            ],
+            ..config
+        };
+        let text = annotated(&config)?;
+        let line = |start: &str| {
+            text.lines()
+                .map(str::trim)
+                .find(|l| l.starts_with(start))
+                .unwrap_or_default()
+                .replace(", //", " //")
+        };
+        assert_eq!(line("\"accurate\""), "\"accurate\": true // command line");
+        assert_eq!(line("\"adapters\""), "\"adapters\": [\"-zip\"] // .rga.toml");
+        assert_eq!(line("\"password\""), "\"password\": \"<redacted>\" // user.jsonc");
+        assert_eq!(line("\"max_blob_len\""), "\"max_blob_len\": 1000 // user.jsonc");
+        assert!(line("{\"name\":\"a\"").ends_with("// user.jsonc"));
+        assert!(line("{\"name\":\"b\"").ends_with("// .rga.toml"));
+        assert!(!text.contains("hunter2"));
+        Ok(())
+    }
+
+    #[test]
+    fn changed_values() {
+        let base = serde_json::json!({"a": 1, "b": {"c": 2, "d": 3}});
+        let value = serde_json::json!({"a": 1, "b": {"c": 2, "d": 4}, "e": 5});
+        assert_eq!(
+            changed(&value, &base),
+            Some(serde_json::json!({"b": {"d": 4}, "e": 5}))
+        );
+        assert_eq!(changed(&base, &base), None);
+    }
+
+    #[test]
+    fn redacts_secrets() -> Result<()> {
+        let config = RgaConfig {
+            archive_password: vec!["hunter2".to_string()],
+            document_passwords: [("*.pdf".to_string(), "s3cret".to_string())].into(),
+            ..Default::default()
+        };
+        let value = redacted(&config)?;
+        assert!(!value.to_string().contains("hunter2"));
+        assert!(!value.to_string().contains("s3cret"));
+        assert_eq!(value["archive_password"], "<redacted>");
+        assert_eq!(value["password"], Value::Null);
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::preproc_cache::{CacheKey, PreprocCache, open_cache_db};
+use crate::preproc_cache::{Blob, CacheKey, PreprocCache, open_cache_db};
 use crate::config::RgaConfig;
 use anyhow::{Context, Result};
 use log::{error, info};
@@ -9,17 +9,18 @@ use tokio::net::{TcpListener, TcpStream};
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonRequest {
     Get(CacheKey),
-    Set(CacheKey, Vec<u8>),
+    Set(CacheKey, Blob),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DaemonResponse {
-    Get(Option<Vec<u8>>),
+    Get(Option<Blob>),
     Set,
     Error(String),
 }
 
-pub async fn run_daemon(path: &std::path::Path, port: u16) -> Result<()> {
+/// serve the cache at `path`, handling at most `max_connections` clients at once
+pub async fn run_daemon(path: &std::path::Path, port: u16, max_connections: usize) -> Result<()> {
     let addr = format!("127.0.0.1:{}", port);
     let listener = TcpListener::bind(&addr).await.context("Failed to bind to daemon address")?;
     info!("rga daemon listening on {}", addr);
@@ -28,14 +29,18 @@ pub async fn run_daemon(path: &std::path::Path, port: u16) -> Result<()> {
     config.cache.path = crate::config::CachePath(path.to_string_lossy().to_string());
     let cache = open_cache_db(&config).await?;
     let cache = std::sync::Arc::new(tokio::sync::Mutex::new(cache));
+    let connections = std::sync::Arc::new(tokio::sync::Semaphore::new(max_connections));
 
     loop {
+        // stop accepting while all slots are busy, further clients wait in the listen backlog
+        let permit = connections.clone().acquire_owned().await?;
         let (socket, _) = listener.accept().await?;
         let cache = cache.clone();
         tokio::spawn(async move {
             if let Err(e) = handle_connection(socket, cache).await {
                 error!("Error handling connection: {}", e);
             }
+            drop(permit);
         });
     }
 }
@@ -102,7 +107,7 @@ impl DaemonCacheClient {
 
 #[async_trait::async_trait]
 impl PreprocCache for DaemonCacheClient {
-    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+    async fn get(&self, key: &CacheKey) -> Result<Option<Blob>> {
         let mut stream = self.connect().await?;
         let req = DaemonRequest::Get(key.clone());
         let req_json = serde_json::to_string(&req)? + "\n";
@@ -119,7 +124,7 @@ impl PreprocCache for DaemonCacheClient {
         }
     }
 
-    async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()> {
+    async fn set(&mut self, key: &CacheKey, value: Blob) -> Result<()> {
         let mut stream = self.connect().await?;
         let req = DaemonRequest::Set(key.clone(), value);
         let req_json = serde_json::to_string(&req)? + "\n";
@@ -0,0 +1,335 @@
+//! Searching the file system of a container image (`--rga-docker-image=IMAGE`).
+//!
+//! The image is exported with `docker save` (after pulling it if it is not available locally) and its layers
+//! are applied on top of each other into `<cache path>/docker/<image id>/rootfs`, honouring the
+//! whiteout files that mark deletions. Since the directory is keyed by the image id, every image is only
+//! unpacked once and the preproc cache keeps working across searches.
+use crate::config::RgaConfig;
+use anyhow::{Context, Result, format_err};
+use async_compression::tokio::bufread::GzipDecoder;
+use log::*;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio_stream::StreamExt;
+use tokio_tar::{ArchiveBuilder, EntryType};
+
+use crate::adapters::custom::map_exe_error;
+
+/// marks a deleted file or directory in a layer: `dir/.wh.name` deletes `dir/name`
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// marks a directory whose content in lower layers is hidden
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ManifestItem {
+    layers: Vec<String>,
+}
+
+async fn docker(args: &[&str]) -> Result<std::process::Output> {
+    debug!("running docker {:?}", args);
+    crate::audit::output(Command::new("docker").args(args).stdin(Stdio::null()))
+        .await
+        .map_err(|e| {
+            map_exe_error(
+                e,
+                "docker",
+                "Make sure docker is installed to use --rga-docker-image.",
+            )
+        })
+}
+
+fn check(out: &std::process::Output, what: &str) -> Result<()> {
+    if !out.status.success() {
+        return Err(format_err!(
+            "{} failed: {:?}\n{}",
+            what,
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    Ok(())
+}
+
+async fn image_id(image: &str) -> Result<String> {
+    let out = docker(&["image", "inspect", "--format", "{{.Id}}", image]).await?;
+    if out.status.success() {
+        return Ok(String::from_utf8_lossy(&out.stdout).trim().to_string());
+    }
+    info!("{} not available locally, pulling it", image);
+    check(&docker(&["pull", "--quiet", image]).await?, "docker pull")?;
+    let out = docker(&["image", "inspect", "--format", "{{.Id}}", image]).await?;
+    check(&out, "docker image inspect")?;
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// layers are plain tar in `docker save` output, but gzipped in OCI layouts
+async fn open_layer(path: &Path) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+    let mut inp = BufReader::new(
+        tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("opening layer {}", path.display()))?,
+    );
+    let is_gzip = inp.fill_buf().await?.starts_with(&[0x1f, 0x8b]);
+    Ok(if is_gzip {
+        Box::new(GzipDecoder::new(inp))
+    } else {
+        Box::new(inp)
+    })
+}
+
+/// remove whatever is at `path`, if anything. Does not follow symlinks
+async fn remove_path(path: &Path) -> Result<()> {
+    match tokio::fs::symlink_metadata(path).await {
+        Ok(m) if m.is_dir() => tokio::fs::remove_dir_all(path).await?,
+        Ok(_) => tokio::fs::remove_file(path).await?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// only plain relative paths, so whiteouts can't delete anything outside the root
+fn is_safe(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// whether `rel` can be resolved in `root` without going through a symlink, which a lower layer could
+/// have pointed anywhere on the host
+async fn no_symlinks(root: &Path, rel: &Path) -> bool {
+    let mut path = root.to_path_buf();
+    for component in rel.components() {
+        path.push(component);
+        match tokio::fs::symlink_metadata(&path).await {
+            Ok(m) if m.file_type().is_symlink() => return false,
+            Ok(_) => {}
+            // nothing below a missing path can be a symlink
+            Err(_) => return true,
+        }
+    }
+    true
+}
+
+/// apply one layer on top of the file system in `root`
+async fn apply_layer(layer: impl AsyncRead + Unpin + Send, root: &Path) -> Result<()> {
+    let mut archive = ArchiveBuilder::new(layer)
+        .set_preserve_permissions(false)
+        .build();
+    let mut entries = archive.entries()?;
+    // paths written by this layer, an opaque whiteout must only hide the content of lower layers
+    let mut written: HashSet<PathBuf> = HashSet::new();
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !is_safe(&path) {
+            warn!("skipping suspicious path {} in layer", path.display());
+            continue;
+        }
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // whiteouts, opaque directories and creating directories would all follow the symlink
+        if !no_symlinks(root, parent).await {
+            warn!("skipping {} in layer, it is below a symlink", path.display());
+            continue;
+        }
+        if name == OPAQUE_WHITEOUT {
+            let dir = root.join(parent);
+            if let Ok(mut children) = tokio::fs::read_dir(&dir).await {
+                while let Some(child) = children.next_entry().await? {
+                    let rel = parent.join(child.file_name());
+                    if !written.iter().any(|w| w.starts_with(&rel)) {
+                        remove_path(&child.path()).await?;
+                    }
+                }
+            }
+            continue;
+        }
+        if let Some(deleted) = name.strip_prefix(WHITEOUT_PREFIX) {
+            if matches!(deleted, "" | "." | "..") {
+                warn!("skipping suspicious whiteout {} in layer", path.display());
+                continue;
+            }
+            remove_path(&root.join(parent).join(deleted)).await?;
+            continue;
+        }
+        let entry_type = entry.header().entry_type();
+        let target = root.join(&path);
+        match entry_type {
+            EntryType::Directory => {
+                // a directory may replace a file from a lower layer
+                if tokio::fs::symlink_metadata(&target)
+                    .await
+                    .is_ok_and(|m| !m.is_dir())
+                {
+                    remove_path(&target).await?;
+                }
+                tokio::fs::create_dir_all(&target).await?;
+            }
+            EntryType::Regular | EntryType::Symlink | EntryType::Link => {
+                if tokio::fs::symlink_metadata(&target)
+                    .await
+                    .is_ok_and(|m| m.is_dir())
+                {
+                    remove_path(&target).await?;
+                }
+                entry
+                    .unpack_in(root)
+                    .await
+                    .with_context(|| format!("unpacking {}", path.display()))?;
+            }
+            // device nodes, fifos, ... can't be created without root and are not searchable anyways
+            _ => continue,
+        }
+        written.insert(path);
+    }
+    Ok(())
+}
+
+/// export `image` and unpack its merged file system, returning the directory to search
+pub async fn image_rootfs(image: &str, config: &RgaConfig) -> Result<PathBuf> {
+    let id = image_id(image).await?;
+    let id_dir = id.replace(':', "_");
+    let base = Path::new(&config.cache.path.0).join("docker");
+    let image_dir = base.join(&id_dir);
+    let rootfs = image_dir.join("rootfs");
+    if rootfs.is_dir() {
+        debug!("{} ({}) already unpacked", image, id);
+        return Ok(rootfs);
+    }
+    tokio::fs::create_dir_all(&base).await?;
+    // unpack next to the final location, so it can simply be renamed once complete
+    let work = tempfile::tempdir_in(&base)?;
+    let saved = work.path().join("image.tar");
+    info!("exporting {} ({})", image, id);
+    check(
+        &docker(&["save", "--output", &saved.to_string_lossy(), image]).await?,
+        "docker save",
+    )?;
+    let export = work.path().join("export");
+    tokio::fs::create_dir_all(&export).await?;
+    ArchiveBuilder::new(BufReader::new(tokio::fs::File::open(&saved).await?))
+        .build()
+        .unpack(&export)
+        .await
+        .context("unpacking docker save output")?;
+    tokio::fs::remove_file(&saved).await?;
+
+    let manifest: Vec<ManifestItem> = serde_json::from_slice(
+        &tokio::fs::read(export.join("manifest.json"))
+            .await
+            .context("reading manifest.json of image")?,
+    )?;
+    let layers = &manifest.first().context("image manifest is empty")?.layers;
+    let new_rootfs = work.path().join("rootfs");
+    tokio::fs::create_dir_all(&new_rootfs).await?;
+    for (i, layer) in layers.iter().enumerate() {
+        debug!("applying layer {}/{}: {}", i + 1, layers.len(), layer);
+        if !is_safe(Path::new(layer)) {
+            return Err(format_err!("invalid layer path {} in manifest", layer));
+        }
+        apply_layer(open_layer(&export.join(layer)).await?, &new_rootfs)
+            .await
+            .with_context(|| format!("applying layer {layer}"))?;
+    }
+    tokio::fs::create_dir_all(&image_dir).await?;
+    tokio::fs::rename(&new_rootfs, &rootfs).await?;
+    Ok(rootfs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio_tar::{Builder, Header};
+
+    async fn layer(files: &[&str]) -> Result<Vec<u8>> {
+        let mut b = Builder::new(Vec::new());
+        for f in files {
+            let mut h = Header::new_gnu();
+            h.set_size(f.len() as u64);
+            h.set_mode(0o644);
+            h.set_cksum();
+            b.append_data(&mut h, f, f.as_bytes()).await?;
+        }
+        Ok(b.into_inner().await?)
+    }
+
+    fn list(root: &Path) -> Vec<String> {
+        let mut out = vec![];
+        for e in walk(root) {
+            out.push(e.strip_prefix(root).unwrap().display().to_string());
+        }
+        out.sort();
+        out
+    }
+    fn walk(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|e| {
+                let p = e.unwrap().path();
+                if p.is_dir() { walk(&p) } else { vec![p] }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn whiteouts() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let lower = layer(&["a/x.txt", "a/y.txt", "b/old.txt", "c/keep.txt"]).await?;
+        apply_layer(&lower[..], root.path()).await?;
+        let upper = layer(&["a/.wh.x.txt", "b/new.txt", "b/.wh..wh..opq", "c/keep.txt"]).await?;
+        apply_layer(&upper[..], root.path()).await?;
+        assert_eq!(
+            list(root.path()),
+            vec!["a/y.txt", "b/new.txt", "c/keep.txt"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn malicious_layers() -> Result<()> {
+        let host = tempfile::tempdir()?;
+        std::fs::create_dir_all(host.path().join("Documents"))?;
+        std::fs::write(host.path().join("Documents/secret.txt"), "")?;
+        std::fs::write(host.path().join("keep.txt"), "")?;
+        let root = tempfile::tempdir()?;
+        let root = root.path().join("rootfs");
+        std::fs::create_dir(&root)?;
+
+        let mut b = Builder::new(Vec::new());
+        let mut h = Header::new_gnu();
+        h.set_entry_type(EntryType::Symlink);
+        h.set_size(0);
+        h.set_mode(0o777);
+        h.set_link_name(host.path())?;
+        h.set_cksum();
+        b.append_data(&mut h, "x", &[][..]).await?;
+        let mut h = Header::new_gnu();
+        h.set_size(0);
+        h.set_mode(0o644);
+        h.set_cksum();
+        b.append_data(&mut h, "sub/a.txt", &[][..]).await?;
+        let lower = b.into_inner().await?;
+        apply_layer(&lower[..], &root).await?;
+        let upper = layer(&[
+            "x/.wh.Documents",
+            "x/.wh..wh..opq",
+            "x/evil/dropped.txt",
+            "sub/.wh...",
+            "sub/.wh..",
+        ])
+        .await?;
+        apply_layer(&upper[..], &root).await?;
+
+        assert_eq!(list(host.path()), vec!["Documents/secret.txt", "keep.txt"]);
+        assert_eq!(list(&root.join("sub")), vec!["a.txt"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! `--rga-dupes`: files below a path whose extracted text is identical or nearly identical, e.g. a
+//! PDF and the DOCX it was made from. Texts are compared by fingerprints that ignore case,
+//! punctuation, whitespace and page prefixes. The fingerprints are stored with the cache entries,
+//! files that are not cached yet are extracted (and cached) first.
+use crate::adapters::AdaptInfo;
+use crate::adapters::postproc::PageFormat;
+use crate::config::RgaConfig;
+use crate::preproc::{cache_key_for, rga_preproc, unadapted_text};
+use crate::preproc_cache;
+use crate::vfs::{LocalFs, Vfs, local_path};
+use anyhow::Result;
+use log::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+/// estimated share of common text from which files are reported as similar
+pub const SIMILAR: f64 = 0.8;
+const MINHASH_LEN: usize = 64;
+/// texts are compared as sets of runs of this many words
+const SHINGLE_WORDS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    /// blake3 of the normalized text
+    pub hash: String,
+    /// minhash of the word shingles, to estimate how similar two texts are
+    pub minhash: Vec<u64>,
+}
+
+/// the splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl Fingerprint {
+    /// the fingerprint of extracted text with pages prefixed as in `page_format`. None if it has no words
+    pub fn of(text: &[u8], page_format: &PageFormat) -> Option<Self> {
+        let text = String::from_utf8_lossy(text);
+        let words: Vec<String> = text
+            .lines()
+            .flat_map(|line| {
+                page_format
+                    .strip_page(line)
+                    .map_or(line, |(_, text)| text)
+                    .split(|c: char| !c.is_alphanumeric())
+            })
+            .filter(|w| !w.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        if words.is_empty() {
+            return None;
+        }
+        let hash = blake3::hash(words.join(" ").as_bytes())
+            .to_hex()
+            .to_string();
+        let mut minhash = vec![u64::MAX; MINHASH_LEN];
+        for shingle in words.windows(SHINGLE_WORDS.min(words.len())) {
+            let digest = blake3::hash(shingle.join(" ").as_bytes());
+            let h = u64::from_le_bytes(digest.as_bytes()[..8].try_into().unwrap());
+            for (i, min) in minhash.iter_mut().enumerate() {
+                *min = (*min).min(mix(h ^ (i as u64).wrapping_mul(0x9e3779b97f4a7c15)));
+            }
+        }
+        Some(Self { hash, minhash })
+    }
+
+    /// estimated share of shingles the texts have in common
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.hash == other.hash {
+            return 1.0;
+        }
+        let same = self
+            .minhash
+            .iter()
+            .zip(&other.minhash)
+            .filter(|(a, b)| a == b)
+            .count();
+        same as f64 / self.minhash.len().max(1) as f64
+    }
+
+    pub fn minhash_bytes(&self) -> Vec<u8> {
+        self.minhash.iter().flat_map(|m| m.to_le_bytes()).collect()
+    }
+
+    pub fn from_parts(hash: String, minhash: &[u8]) -> Self {
+        Self {
+            hash,
+            minhash: minhash
+                .chunks_exact(8)
+                .map(|m| u64::from_le_bytes(m.try_into().unwrap()))
+                .collect(),
+        }
+    }
+}
+
+/// the text rga searches in the file at `path`
+async fn extract(config: &RgaConfig, path: &Path) -> Result<Vec<u8>> {
+    let ai = AdaptInfo {
+        inp: Box::pin(tokio::io::BufReader::new(
+            tokio::fs::File::open(path).await?,
+        )),
+        filepath_hint: path.to_owned(),
+        is_real_file: true,
+        file_mtime_unix_ms: None,
+        line_prefix: String::new(),
+        archive_recursion_depth: 0,
+        postprocess: !config.no_prefix_filenames,
+        config: config.clone(),
+    };
+    let mut text = vec![];
+    rga_preproc(ai).await?.read_to_end(&mut text).await?;
+    Ok(text)
+}
+
+async fn fingerprint(config: &RgaConfig, path: &Path) -> Result<Option<Fingerprint>> {
+    let page_format = PageFormat::new(&config.postproc.options);
+    let text = match cache_key_for(config, path).await? {
+        Some((adapter, key)) => {
+            let cached = !config.cache.disabled && adapter.metadata().capabilities.deterministic;
+            if cached && let Some(fingerprint) = preproc_cache::fingerprint(config, &key).await? {
+                return Ok(Some(fingerprint));
+            }
+            // the extraction is read from the cache (or written to it), the fingerprint is only computed here
+            let text = extract(config, path).await?;
+            let fingerprint = Fingerprint::of(&text, &page_format);
+            if cached && let Some(fingerprint) = &fingerprint {
+                preproc_cache::store_fingerprint(config, &key, fingerprint).await?;
+            }
+            return Ok(fingerprint);
+        }
+        None => match unadapted_text(path).await? {
+            Some(mut inp) => {
+                let mut text = vec![];
+                inp.read_to_end(&mut text).await?;
+                text
+            }
+            None => return Ok(None),
+        },
+    };
+    Ok(Fingerprint::of(&text, &page_format))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Dupes {
+    Identical(Vec<PathBuf>),
+    /// `similarity` is the lowest of any two of the files
+    Similar {
+        similarity: f64,
+        files: Vec<PathBuf>,
+    },
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// group files with the same or similar fingerprints. Files identical to each other appear in
+/// similar groups only once
+fn group(prints: Vec<(PathBuf, Fingerprint)>) -> Vec<Dupes> {
+    let mut by_hash: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, (_, print)) in prints.iter().enumerate() {
+        by_hash.entry(&print.hash).or_default().push(i);
+    }
+    let mut out = vec![];
+    for files in by_hash.values().filter(|files| files.len() > 1) {
+        out.push(Dupes::Identical(
+            files.iter().map(|&i| prints[i].0.clone()).collect(),
+        ));
+    }
+    let representatives: Vec<usize> = by_hash.values().map(|files| files[0]).collect();
+    let mut parents: Vec<usize> = (0..representatives.len()).collect();
+    for a in 0..representatives.len() {
+        for b in a + 1..representatives.len() {
+            let (x, y) = (&prints[representatives[a]].1, &prints[representatives[b]].1);
+            if x.similarity(y) >= SIMILAR {
+                let (ra, rb) = (find_root(&mut parents, a), find_root(&mut parents, b));
+                parents[ra.max(rb)] = ra.min(rb);
+            }
+        }
+    }
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (i, &file) in representatives.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        clusters.entry(root).or_default().push(file);
+    }
+    for files in clusters.values().filter(|files| files.len() > 1) {
+        let mut similarity: f64 = 1.0;
+        for (n, &a) in files.iter().enumerate() {
+            for &b in &files[n + 1..] {
+                similarity = similarity.min(prints[a].1.similarity(&prints[b].1));
+            }
+        }
+        out.push(Dupes::Similar {
+            similarity,
+            files: files.iter().map(|&i| prints[i].0.clone()).collect(),
+        });
+    }
+    out
+}
+
+/// the identical and similar files below `root`
+pub async fn find(config: &RgaConfig, root: &Path) -> Result<Vec<Dupes>> {
+    let mut prints = vec![];
+    for entry in LocalFs::new(root).list().await? {
+        let path = local_path(root, &entry.path)?;
+        match fingerprint(config, &path).await {
+            Ok(Some(print)) => prints.push((path, print)),
+            Ok(None) => debug!("{}: no text", path.display()),
+            Err(e) => warn!("{}: {e:#}", path.display()),
+        }
+    }
+    Ok(group(prints))
+}
+
+impl fmt::Display for Dupes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files = match self {
+            Dupes::Identical(files) => {
+                writeln!(f, "identical text:")?;
+                files
+            }
+            Dupes::Similar { similarity, files } => {
+                writeln!(f, "similar text (~{:.0}%):", similarity * 100.0)?;
+                files
+            }
+        };
+        for file in files {
+            writeln!(f, "  {}", file.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn fingerprints() {
+        let pdf = Fingerprint::of(b"Page 1: The Budget, 2024\nPage 2: approved.", &PageFormat::default()).unwrap();
+        let docx = Fingerprint::of(b"the   budget 2024\n\napproved", &PageFormat::default()).unwrap();
+        assert_eq!(pdf, docx);
+        assert_eq!(Fingerprint::of(b"Page 1: \n--", &PageFormat::default()), None);
+
+        let text: String = (0..100)
+            .map(|i| format!("line {i} of the minutes "))
+            .collect();
+        let a = Fingerprint::of(text.as_bytes(), &PageFormat::default()).unwrap();
+        let b = Fingerprint::of(format!("{text} and runs away").as_bytes(), &PageFormat::default()).unwrap();
+        let c = Fingerprint::of(
+            b"lorem ipsum dolor sit amet consectetur adipiscing",
+            &PageFormat::default(),
+        )
+        .unwrap();
+        assert!(a.similarity(&b) >= SIMILAR);
+        assert!(a.similarity(&c) < 0.2);
+        assert_eq!(
+            Fingerprint::from_parts(a.hash.clone(), &a.minhash_bytes()),
+            a
+        );
+    }
+
+    #[tokio::test]
+    async fn groups() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let report: String = (0..200)
+            .map(|i| format!("finding number {i} is fine\n"))
+            .collect();
+        std::fs::write(dir.path().join("report.txt"), &report)?;
+        std::fs::write(dir.path().join("report.md"), report.to_uppercase())?;
+        std::fs::write(
+            dir.path().join("report-v2.txt"),
+            format!("{report}finding 201 is not\n"),
+        )?;
+        std::fs::write(dir.path().join("other.txt"), "nothing in common")?;
+        std::fs::write(dir.path().join("empty.txt"), "")?;
+
+        let mut dupes = find(&RgaConfig::default(), dir.path()).await?;
+        let path = |name: &str| dir.path().join(name);
+        assert_eq!(
+            dupes.remove(0),
+            Dupes::Identical(vec![path("report.md"), path("report.txt")])
+        );
+        let Dupes::Similar { files, similarity } = dupes.remove(0) else {
+            panic!("not similar");
+        };
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&path("report-v2.txt")));
+        assert!(similarity >= SIMILAR);
+        assert_eq!(dupes, []);
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! `--rga-extract-all`: writes the text rga searches in every file below some paths to a
+//! directory tree, one text file per file and per file in an archive, so rga can be used to
+//! convert a corpus to text for other tools (search engines, LLM ingestion). `docs/a.zip` with a
+//! member `b/c.pdf` becomes `OUT/docs/a.zip/b/c.pdf.txt`.
+use crate::adapters::{AdaptInfo, postproc};
+use crate::config::RgaConfig;
+use crate::preproc::{cache_key_for, rga_preproc_files, unadapted_text};
+use crate::print_bytes;
+use crate::vfs::{LocalFs, local_path};
+use anyhow::{Context, Result};
+use log::*;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub out_dir: PathBuf,
+    pub files: u64,
+    /// text files written, more than `files` if there are archives
+    pub texts: u64,
+    pub bytes: u64,
+    pub failed: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "wrote {} text files ({}) from {} files to {}",
+            self.texts,
+            print_bytes(self.bytes as f64),
+            self.files,
+            self.out_dir.display()
+        )?;
+        if self.failed > 0 {
+            writeln!(f, "{} files failed, see the warnings above", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+/// the parts of a path that stay in the output directory: no root, `.` or `..`
+fn safe_parts(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+}
+
+/// where the text of a file goes in the output directory, from the path of the searched file and
+/// the line prefix of the file in it (empty for the file itself)
+fn text_path(source: &Path, line_prefix: &str, separator: &str) -> PathBuf {
+    let mut path: PathBuf = source
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    if let Some(members) = line_prefix.strip_suffix(separator) {
+        for member in members.split(separator) {
+            path.extend(safe_parts(member));
+        }
+    }
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".txt");
+    path.set_file_name(name);
+    path
+}
+
+/// writes the text files of the file at `path`, returns how many and their size
+async fn extract_file(
+    config: &RgaConfig,
+    path: &Path,
+    out_dir: &Path,
+    written: &mut HashSet<PathBuf>,
+) -> Result<(u64, u64)> {
+    let separator = postproc::prefix_separator(&config.postproc.options).to_string();
+    let (mut texts, mut bytes) = (0, 0);
+    if cache_key_for(config, path).await?.is_none() {
+        let Some(mut text) = unadapted_text(path).await? else {
+            return Ok((0, 0));
+        };
+        let target = out_dir.join(text_path(path, "", &separator));
+        tokio::fs::create_dir_all(target.parent().unwrap_or(out_dir)).await?;
+        let mut file = tokio::fs::File::create(&target).await?;
+        let len = tokio::io::copy(&mut text, &mut file).await?;
+        file.flush().await?;
+        return Ok((1, len));
+    }
+    let ai = AdaptInfo {
+        inp: Box::pin(BufReader::new(tokio::fs::File::open(path).await?)),
+        filepath_hint: path.to_owned(),
+        is_real_file: true,
+        file_mtime_unix_ms: None,
+        line_prefix: String::new(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config: config.clone(),
+    };
+    let Some(mut files) = rga_preproc_files(ai).await? else {
+        return Ok((0, 0));
+    };
+    while let Some(file) = files.next().await {
+        let file = file?;
+        let target = out_dir.join(text_path(path, &file.line_prefix, &separator));
+        tokio::fs::create_dir_all(target.parent().unwrap_or(out_dir)).await?;
+        // a file can come in several parts, e.g. a warning after its text
+        let first = written.insert(target.clone());
+        let mut oup = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(first)
+            .append(!first)
+            .open(&target)
+            .await
+            .with_context(|| format!("writing {}", target.display()))?;
+        if first {
+            texts += 1;
+        }
+        let mut inp = BufReader::new(file.inp);
+        let mut line = vec![];
+        // the empty line that ends each file in rga's output is left out
+        let mut blank_lines = 0;
+        loop {
+            line.clear();
+            if inp.read_until(b'\n', &mut line).await? == 0 {
+                break;
+            }
+            let text = line
+                .strip_prefix(file.line_prefix.as_bytes())
+                .unwrap_or(&line);
+            if text == b"\n" {
+                blank_lines += 1;
+                continue;
+            }
+            let blank = b"\n".repeat(blank_lines);
+            blank_lines = 0;
+            oup.write_all(&blank).await?;
+            oup.write_all(text).await?;
+            bytes += (blank.len() + text.len()) as u64;
+        }
+        if blank_lines > 1 {
+            oup.write_all(&b"\n".repeat(blank_lines - 1)).await?;
+            bytes += blank_lines as u64 - 1;
+        }
+        oup.flush().await?;
+    }
+    Ok((texts, bytes))
+}
+
+/// writes the text of every file below `roots` to `out_dir`
+pub async fn extract_all(config: &RgaConfig, roots: &[PathBuf], out_dir: &Path) -> Result<Summary> {
+    tokio::fs::create_dir_all(out_dir)
+        .await
+        .with_context(|| format!("creating {}", out_dir.display()))?;
+    // the output directory may be below a root, its files are not extracted again
+    let out_canonical = tokio::fs::canonicalize(out_dir).await?;
+    let mut summary = Summary {
+        out_dir: out_dir.to_owned(),
+        ..Default::default()
+    };
+    let mut written = HashSet::new();
+    for root in roots {
+        let mut entries = LocalFs::new(root).walk();
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let path = local_path(root, &entry.path)?;
+            if tokio::fs::canonicalize(&path)
+                .await
+                .is_ok_and(|p| p.starts_with(&out_canonical))
+            {
+                continue;
+            }
+            summary.files += 1;
+            match extract_file(config, &path, out_dir, &mut written).await {
+                Ok((texts, bytes)) => {
+                    debug!("{}: {texts} text files", path.display());
+                    summary.texts += texts;
+                    summary.bytes += bytes;
+                }
+                Err(e) => {
+                    warn!("{}: {e:#}", path.display());
+                    summary.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_data_dir;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn text_paths() {
+        let path = |source: &str, prefix: &str| text_path(Path::new(source), prefix, ": ");
+        assert_eq!(path("./docs/a.pdf", ""), PathBuf::from("docs/a.pdf.txt"));
+        assert_eq!(
+            path("/data/a.zip", "b/c.tar: ../d.pdf: "),
+            PathBuf::from("data/a.zip/b/c.tar/d.pdf.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn tree() -> Result<()> {
+        let src = tempfile::tempdir()?;
+        std::fs::create_dir(src.path().join("sub"))?;
+        std::fs::write(src.path().join("notes.txt"), "plain text\n")?;
+        std::fs::write(src.path().join("blob.bin"), [0, 1, 2])?;
+        std::fs::copy(
+            test_data_dir().join("hello.gz"),
+            src.path().join("sub/hello.gz"),
+        )?;
+        let out = tempfile::tempdir()?;
+
+        let summary =
+            extract_all(&RgaConfig::default(), &[src.path().to_owned()], out.path()).await?;
+        assert_eq!((summary.files, summary.texts, summary.failed), (3, 2, 0));
+        let text = |name: &str| {
+            let path = out.path().join(text_path(&src.path().join(name), "", ": "));
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(text("notes.txt"), "plain text\n");
+        assert_eq!(text("sub/hello.gz"), "hello\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Hot previews for rga-fzf. rga-fzf creates a session directory and passes it to its preview
+//! command with `--rga-fzf-session`. rga-preproc keeps the extracted text of the files previewed in
+//! the session there as plain files, so previewing the same document again (for every change of
+//! the query, or while going through its matches) reads that text instead of extracting it or
+//! decompressing it from the cache. The directory is removed when rga-fzf exits.
+use crate::adapters::ReadBox;
+use crate::config::RgaConfig;
+use log::*;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// the most recently previewed files kept in a session
+pub const MAX_FILES: usize = 8;
+const SUFFIX: &str = ".txt";
+
+pub struct Session {
+    dir: PathBuf,
+}
+
+impl Session {
+    /// the session of `config`, None if there is none or it has ended
+    pub fn of(config: &RgaConfig) -> Option<Self> {
+        let dir = PathBuf::from(config.fzf_session.as_ref()?);
+        dir.is_dir().then_some(Self { dir })
+    }
+
+    /// the name of the extracted text of a file in the session
+    pub fn key(
+        path: &Path,
+        file_mtime_unix_ms: Option<i64>,
+        size: u64,
+        config: &RgaConfig,
+    ) -> String {
+        let material = (path, file_mtime_unix_ms, size, config.config_hash());
+        let material = serde_json::to_vec(&material).expect("key is serializable");
+        blake3::hash(&material).to_hex().to_string()
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}{SUFFIX}"))
+    }
+
+    /// the text of an earlier preview of the file, marked as recently used
+    pub async fn open(&self, key: &str) -> Option<ReadBox> {
+        let file = std::fs::File::open(self.path(key)).ok()?;
+        if let Err(e) = file.set_modified(SystemTime::now()) {
+            debug!("marking hot preview {key} as used: {e}");
+        }
+        Some(Box::pin(tokio::fs::File::from_std(file)))
+    }
+
+    /// copy `inp` to `out`, and keep it in the session once it has been read completely
+    pub async fn copy_pinned(
+        &self,
+        key: &str,
+        mut inp: ReadBox,
+        out: &mut (impl AsyncWrite + Unpin),
+    ) -> std::io::Result<()> {
+        let tmp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        let mut file = tokio::fs::File::from_std(tmp.reopen()?);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = inp.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buf[..n]).await?;
+            out.write_all(&buf[..n]).await?;
+        }
+        out.flush().await?;
+        file.flush().await?;
+        tmp.persist(self.path(key))?;
+        self.evict();
+        Ok(())
+    }
+
+    /// remove all but the [`MAX_FILES`] most recently used previews
+    fn evict(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut previews: Vec<(SystemTime, PathBuf)> = entries
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with(SUFFIX))
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        previews.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        for (_, path) in previews.into_iter().skip(MAX_FILES) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                debug!("removing hot preview {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use tokio::io::AsyncRead;
+
+    async fn read_all(mut r: impl AsyncRead + Unpin) -> Vec<u8> {
+        let mut out = vec![];
+        r.read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn pinned() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = RgaConfig {
+            fzf_session: Some(dir.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let session = Session::of(&config).unwrap();
+        let key = |n: u64| Session::key(Path::new("a.pdf"), Some(1), n, &config);
+        assert!(session.open(&key(0)).await.is_none());
+
+        let mut out = vec![];
+        session
+            .copy_pinned(&key(0), Box::pin(&b"Page 1: hello\n"[..]), &mut out)
+            .await?;
+        assert_eq!(out, b"Page 1: hello\n");
+        assert_eq!(
+            read_all(session.open(&key(0)).await.unwrap()).await,
+            b"Page 1: hello\n"
+        );
+
+        for n in 1..=MAX_FILES as u64 {
+            session
+                .copy_pinned(&key(n), Box::pin(&b"text"[..]), &mut vec![])
+                .await?;
+        }
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), MAX_FILES);
+        Ok(())
+    }
+}
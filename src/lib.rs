@@ -2,21 +2,45 @@
 
 pub mod adapted_iter;
 pub mod adapters;
+pub mod annotate;
+pub mod audit;
+pub mod bundled;
 mod caching_writer;
+pub mod concurrency;
 pub mod config;
 pub mod daemon;
+pub mod docker;
+pub mod dupes;
 pub mod expand;
+pub mod extract;
+pub mod fzf_session;
 pub mod matching;
+pub mod monitor;
 pub mod preproc;
 pub mod preproc_cache;
 pub mod recurse;
+pub mod redact;
+pub mod registry;
+pub mod remote;
+pub mod robust;
+pub mod roots;
+pub mod run_stats;
+pub mod salvage;
+pub mod sink;
+pub mod stats;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod verify;
+pub mod vfs;
+pub mod warm;
+pub mod wc;
 #[cfg(test)]
 pub mod test_utils;
 use anyhow::Context;
 use anyhow::Result;
 use async_stream::stream;
 use directories_next::ProjectDirs;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncRead;
 use tokio::task::JoinHandle;
 use tokio_util::io::StreamReader;
@@ -49,8 +73,24 @@ fn meh(float: f32, precision: usize) -> usize {
     }
 }
 
+/// the argument rga runs rga-preproc with to check that both are from the same install
+pub static HANDSHAKE_ARG: &str = "--rga-handshake";
+
+/// the answer of rga-preproc to [`HANDSHAKE_ARG`]
+pub fn version_handshake() -> String {
+    format!(
+        "ripgrep-all {} cache schema {}",
+        env!("CARGO_PKG_VERSION"),
+        preproc_cache::SCHEMA_VERSION
+    )
+}
+
 pub fn print_dur(start: Instant) -> String {
-    let mut dur = Instant::now().duration_since(start).as_secs_f32();
+    print_duration(Instant::now().duration_since(start))
+}
+
+pub fn print_duration(dur: Duration) -> String {
+    let mut dur = dur.as_secs_f32();
     let mut suffix = "";
     if dur < 0.1 {
         suffix = "m";
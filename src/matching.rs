@@ -45,7 +45,7 @@ pub struct FileMeta {
     // and since we probably only want to do only matching on ascii stuff anyways, this is the filename as a string with non-valid bytes removed
     pub lossy_filename: String,
     // only given when slow matching is enabled
-    pub mimetype: Option<&'static str>,
+    pub mimetype: Option<String>,
 }
 
 pub fn extension_to_regex(extension: &str) -> Regex {
@@ -79,14 +79,15 @@ pub fn adapter_matcher(
         }
     }
     let fname_regex_set = RegexSet::new(fname_regexes.iter().map(|p| p.0.as_str()))?;
-    let mime_regex_set = RegexSet::new(mime_regexes.iter().map(|p| p.0.as_str()))?;
+    // mime types are matched literally, `+` as in image/svg+xml is not a repetition
+    let mime_regex_set = RegexSet::new(mime_regexes.iter().map(|p| regex::escape(&p.0)))?;
     Ok(move |meta: FileMeta| {
         let fname_matches: Vec<_> = fname_regex_set
             .matches(&meta.lossy_filename)
             .into_iter()
             .collect();
         let mime_matches: Vec<_> = if slow {
-            match meta.mimetype {
+            match &meta.mimetype {
                 Some(mt) => mime_regex_set.matches(mt).into_iter().collect(),
                 None => vec![],
             }
@@ -133,3 +134,23 @@ pub fn adapter_matcher(
         }
     })
 }
+
+/// the local name of the root element of an XML document and the rest of the document after `<`
+#[cfg(any(feature = "media", feature = "office"))]
+pub(crate) fn xml_root(buf: &[u8]) -> Option<(&[u8], &[u8])> {
+    let text = buf.strip_prefix(b"\xef\xbb\xbf").unwrap_or(buf);
+    // the first element that is not the prolog, a comment or a doctype
+    let mut rest = text;
+    let root = loop {
+        let start = memchr::memchr(b'<', rest)?;
+        rest = &rest[start + 1..];
+        if !matches!(rest.first(), Some(b'?' | b'!')) {
+            break rest;
+        }
+    };
+    let name_end = root
+        .iter()
+        .position(|b| b.is_ascii_whitespace() || *b == b'>' || *b == b'/')?;
+    let name = &root[..name_end];
+    Some((name.rsplit(|b| *b == b':').next()?, root))
+}
@@ -0,0 +1,226 @@
+//! `--rga-monitor`: saved searches (`monitors` in the config) that report only the matches that
+//! are new since their last run, e.g. to watch a growing document dump for new mentions of a term.
+//! The matches of each run are kept in `<cache path>/monitor/<name>.json`. A match is identified
+//! by its file, its path inside archives and the text of its line, so it is not new again when
+//! lines are added before it. The first run of a search reports all its matches.
+use crate::annotate::{self, Match};
+use crate::config::{MonitorConfig, RgaConfig};
+use crate::sink::Sink;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// what a match is compared by between runs
+type MatchId = (String, Option<String>, String);
+
+fn id(m: &Match) -> MatchId {
+    (m.path.clone(), m.inner_path.clone(), m.text.clone())
+}
+
+/// the result of the last run of a search
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct State {
+    pub last_run_unix_ms: i64,
+    pub matches: BTreeSet<MatchId>,
+}
+
+impl State {
+    fn path(config: &RgaConfig, name: &str) -> PathBuf {
+        Path::new(&config.cache.path.0)
+            .join("monitor")
+            .join(format!("{name}.json"))
+    }
+
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read(path) {
+            Ok(json) => Ok(Some(
+                serde_json::from_slice(&json).with_context(|| format!("reading {}", path.display()))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    /// written to a temporary file first, so an interrupted run keeps the last state
+    fn save(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path.parent().context("no parent")?)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp, path).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// whether a search run every `every` is due at `now`
+    fn is_due(&self, search: &MonitorConfig, now: i64) -> bool {
+        search
+            .every
+            .is_none_or(|every| now - self.last_run_unix_ms >= every.0 as i64 * 1000)
+    }
+
+    /// the matches that were not there in the last run
+    fn new_matches<'a>(&self, matches: &'a [Match]) -> Vec<&'a Match> {
+        matches
+            .iter()
+            .filter(|m| !self.matches.contains(&id(m)))
+            .collect()
+    }
+}
+
+fn now_unix_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// a match as a line of output, like rga prints it but with the name of the search first
+fn format_match(name: &str, m: &Match) -> String {
+    let mut line = format!("{name}: {}:", m.path);
+    if let Some(inner) = &m.inner_path {
+        line += &format!("{inner}: ");
+    }
+    if let Some(page) = m.page {
+        line += &format!("Page {page}: ");
+    }
+    line + &m.text
+}
+
+/// the matches of a saved search. rg exits with 2 if any file could not be searched (e.g. a corrupt PDF),
+/// the matches in the other files are kept then. Without its summary rg did not search at all, e.g. the
+/// pattern is invalid, and the run fails so the last matches aren't replaced with none
+fn collect(name: &str, rg: Command, config: &RgaConfig) -> Result<Vec<Match>> {
+    let mut searched = false;
+    let (matches, status) = annotate::collect_with(rg, config, |event| {
+        searched |= event.get("type").and_then(|t| t.as_str()) == Some("summary");
+        Ok(())
+    })?;
+    match status.code() {
+        // 1 is no matches
+        Some(0 | 1) => {}
+        Some(2) if searched => {
+            eprintln!("Warning: {name}: some files could not be searched, see the errors above")
+        }
+        _ => anyhow::bail!("rg failed collecting the matches: {status}"),
+    }
+    Ok(matches)
+}
+
+/// Runs the saved search `only`, or all that are due, and writes their new matches to the sink of
+/// `--rga-output`. `rg` gives the rg command (with `--json`) for the arguments of a search. Returns
+/// whether there were new matches.
+pub fn run(config: &RgaConfig, only: Option<&str>, rg: impl Fn(&[String]) -> Command) -> Result<bool> {
+    if let Some(name) = only
+        && !config.monitors.contains_key(name)
+    {
+        anyhow::bail!("No saved search {name} in monitors of the config file");
+    }
+    if config.monitors.is_empty() {
+        anyhow::bail!("No saved searches, add them to monitors in the config file");
+    }
+    let mut out = Sink::from_config(config)?.open()?;
+    let mut found = false;
+    for (name, search) in &config.monitors {
+        if only.is_some_and(|only| only != name) {
+            continue;
+        }
+        let path = State::path(config, name);
+        let last = State::load(&path)?;
+        let now = now_unix_ms();
+        if only.is_none() && last.as_ref().is_some_and(|last| !last.is_due(search, now)) {
+            log::debug!("{name}: not due yet");
+            continue;
+        }
+        let matches = collect(name, rg(&search.args), config)
+            .with_context(|| format!("running saved search {name}"))?;
+        let new = last.unwrap_or_default().new_matches(&matches);
+        for m in &new {
+            writeln!(out, "{}", format_match(name, m))?;
+        }
+        out.flush()?;
+        eprintln!("{name}: {} new of {} matches", new.len(), matches.len());
+        found |= !new.is_empty();
+        State {
+            last_run_unix_ms: now,
+            matches: matches.iter().map(id).collect(),
+        }
+        .save(&path)?;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CacheTtl;
+    use pretty_assertions::assert_eq;
+
+    fn at(path: &str, inner_path: Option<&str>, line_number: u64, text: &str) -> Match {
+        Match {
+            path: path.to_string(),
+            inner_path: inner_path.map(str::to_string),
+            page: None,
+            line_number: Some(line_number),
+            text: text.to_string(),
+            submatches: vec![],
+            confidence: None,
+        }
+    }
+
+    #[test]
+    fn new_matches_and_schedule() -> Result<()> {
+        let first = [
+            at("dump/a.txt", None, 1, "acme corp was mentioned"),
+            at("dump/b.zip", Some("mail.eml"), 4, "acme corp invoice"),
+        ];
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("monitor/acme.json");
+        State {
+            last_run_unix_ms: 1000,
+            matches: first.iter().map(id).collect(),
+        }
+        .save(&path)?;
+        let state = State::load(&path)?.unwrap();
+        // a line was added before the old match, and a new member in the zip
+        let second = [
+            at("dump/a.txt", None, 2, "acme corp was mentioned"),
+            at("dump/b.zip", Some("mail.eml"), 4, "acme corp invoice"),
+            at("dump/b.zip", Some("new.eml"), 1, "acme corp again"),
+        ];
+        let new = state.new_matches(&second);
+        assert_eq!(new, vec![&second[2]]);
+        assert_eq!(
+            format_match("acme", new[0]),
+            "acme: dump/b.zip:new.eml: acme corp again"
+        );
+
+        let search = |every| MonitorConfig {
+            args: vec![],
+            every,
+        };
+        assert!(state.is_due(&search(None), 1000));
+        assert!(!state.is_due(&search(Some(CacheTtl(3600))), 1000 + 3_599_000));
+        assert!(state.is_due(&search(Some(CacheTtl(3600))), 1000 + 3_600_000));
+        Ok(())
+    }
+
+    #[test]
+    fn partial_results() -> Result<()> {
+        let rg = |events: &str, code: i32| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &format!("printf '%s\\n' {events}; exit {code}")]);
+            cmd
+        };
+        let found = r#"'{"type":"match","data":{"path":{"text":"a.txt"},"lines":{"text":"acme"},"line_number":1,"submatches":[]}}'"#;
+        let summary = r#"'{"type":"summary","data":{}}'"#;
+        let config = RgaConfig::default();
+        // a file could not be searched
+        let matches = collect("acme", rg(&format!("{found} {summary}"), 2), &config)?;
+        assert_eq!(matches, vec![at("a.txt", None, 1, "acme")]);
+        // rg did not search, e.g. the pattern is invalid
+        assert!(collect("acme", rg("", 2), &config).is_err());
+        assert_eq!(collect("acme", rg(summary, 1), &config)?, vec![]);
+        Ok(())
+    }
+}
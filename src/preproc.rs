@@ -1,16 +1,18 @@
-use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapted_iter::{AdaptedFilesIterBox, one_file};
 use crate::adapters::*;
 use crate::caching_writer::async_read_and_write_to_cache;
+use crate::concurrency::subprocess_permit;
 use crate::config::RgaConfig;
 use crate::matching::*;
-use crate::preproc_cache::CacheKey;
-use crate::recurse::concat_read_streams;
+use crate::preproc_cache::{CacheKey, Codec, file_stamp};
+use crate::recurse::{self, Branch, MAX_CONVERSIONS, concat_read_streams};
+use crate::robust;
+use crate::run_stats::{self, CacheUse};
 use crate::{
     preproc_cache::{PreprocCache, open_cache_db},
     print_bytes,
 };
 use anyhow::*;
-use async_compression::tokio::bufread::ZstdDecoder;
 use async_stream::stream;
 // use futures::future::{BoxFuture, FutureExt};
 use log::*;
@@ -20,19 +22,58 @@ use std::io::Cursor;
 use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
-use tokio::io::AsyncBufReadExt;
 use tokio::io::BufReader;
-use tokio::io::{AsyncBufRead, AsyncReadExt};
+use tokio::io::AsyncReadExt;
 
 pub type ActiveAdapters = Vec<Arc<dyn FileAdapter>>;
+/// an adapter, why it was chosen and the adapters its output is matched with
+type ChosenAdapter = (Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters);
 
-async fn choose_adapter(
+/// the mime type of a format by the start of a file
+type Sniffer = fn(&[u8]) -> Option<&'static str>;
+
+/// mime types of the formats `infer` does not know, by the adapters compiled in
+static SNIFFERS: &[Sniffer] = &[
+    #[cfg(feature = "scientific")]
+    serialized::sniff_mime,
+    #[cfg(feature = "office")]
+    xbrl::sniff_mime,
+    #[cfg(feature = "media")]
+    svg::sniff_mime,
+    gitbundle::sniff_mime,
+    #[cfg(feature = "forensics")]
+    iso::sniff_mime,
+    #[cfg(feature = "scientific")]
+    fits::sniff_mime,
+    #[cfg(feature = "media")]
+    gps::sniff_mime,
+];
+
+/// the mime type of a file from its name and its start, as --rga-accurate matches it
+pub fn sniff_mime(config: &RgaConfig, filename: &str, head: &[u8]) -> Option<String> {
+    let mimetype = if let Some(mime) = config.extension_mime(filename) {
+        Some(mime)
+    } else if head.starts_with(b"From \x0d") || head.starts_with(b"From -") {
+        Some("application/mbox")
+    } else {
+        infer::get(head)
+            .map(|t| t.mime_type())
+            .or_else(|| SNIFFERS.iter().find_map(|sniff| sniff(head)))
+            .or_else(|| (!head.is_empty() && !head.contains(&0)).then_some("text/plain"))
+    };
+    mimetype.map(str::to_string)
+}
+
+/// `head` is the start of the file (`RgaConfig::sniff_len` bytes), only used with --rga-accurate.
+/// `parent` is the adapter that produced the file, matching by content never gives text back to it
+fn choose_adapter(
     config: &RgaConfig,
     filepath_hint: &Path,
     archive_recursion_depth: i32,
-    inp: &mut (impl AsyncBufRead + Unpin),
+    head: &[u8],
     active_adapters: Option<&ActiveAdapters>,
-) -> Result<Option<(Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters)>> {
+    parent: Option<&str>,
+) -> Result<Option<ChosenAdapter>> {
     let computed_adapters;
     let active_adapters = match active_adapters {
         Some(a) => a,
@@ -47,22 +88,61 @@ async fn choose_adapter(
         .ok_or_else(|| format_err!("Empty filename"))?;
     debug!("Archive recursion depth: {}", archive_recursion_depth);
 
-    let mimetype = if config.accurate {
-        let buf = inp.fill_buf().await?; // fill but do not consume!
-        if buf.starts_with(b"From \x0d") || buf.starts_with(b"From -") {
-            Some("application/mbox")
-        } else {
-            let mimetype = infer::get(buf).map(|t| t.mime_type());
-            debug!("mimetype: {:?}", mimetype);
-            mimetype
+    let lossy_filename = filename.to_string_lossy().to_string();
+    if config.accurate
+        && let Some(magic) = config.magic(head)
+    {
+        let name = &magic.adapter;
+        match active_adapters
+            .iter()
+            .find(|a| &a.metadata().name == name && parent != Some(name))
+        {
+            Some(adapter) => {
+                return Ok(Some((
+                    adapter.clone(),
+                    FileMatcher::MimeType(format!("magic {} at {}", magic.bytes, magic.offset)),
+                    active_adapters.clone(),
+                )));
+            }
+            None => debug!("magics: adapter {name} is not enabled or produced this file"),
         }
+    }
+    let mimetype = if config.accurate {
+        let mimetype = sniff_mime(config, &lossy_filename, head);
+        debug!("mimetype: {:?}", mimetype);
+        mimetype
     } else {
         None
     };
+    if let Some(mime) = &mimetype
+        && let Some(name) = config.mime_adapter(mime)
+    {
+        match active_adapters
+            .iter()
+            .find(|a| a.metadata().name == name && parent != Some(name))
+        {
+            Some(adapter) => {
+                debug!("using adapter {name} for {mime} from mime_adapters");
+                return Ok(Some((
+                    adapter.clone(),
+                    FileMatcher::MimeType(mime.clone()),
+                    active_adapters.clone(),
+                )));
+            }
+            None => debug!("mime_adapters: adapter {name} for {mime} is not enabled or produced this file"),
+        }
+    }
+    let is_text = mimetype.as_deref() == Some("text/plain");
     let adapter = adapters(FileMeta {
         mimetype,
-        lossy_filename: filename.to_string_lossy().to_string(),
+        lossy_filename,
     });
+    if let Some((adapter, FileMatcher::MimeType(_))) = &adapter
+        && is_text
+        && parent == Some(adapter.metadata().name.as_str())
+    {
+        return Ok(None);
+    }
     Ok(adapter.map(|e| (e.0, e.1, active_adapters.clone())))
 }
 
@@ -70,22 +150,31 @@ enum Ret {
     Recurse(AdaptInfo, Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters),
     Passthrough(AdaptInfo),
 }
-async fn buf_choose_adapter(ai: AdaptInfo, active_adapters: Option<&ActiveAdapters>) -> Result<Ret> {
+async fn buf_choose_adapter(
+    ai: AdaptInfo,
+    active_adapters: Option<&ActiveAdapters>,
+    parent: Option<&str>,
+) -> Result<Ret> {
     // Only use a buffer if we need to detect mime types (accurate mode)
     // or if it's a real file (where buffering helps performance).
     // For files already in memory or from other streams, a large buffer might be redundant.
     let capacity = if ai.config.accurate { 8192 } else { 1024 };
-    let mut inp = BufReader::with_capacity(capacity, ai.inp);
+    let mut inp = ai.inp;
+    // the start of the file is read up front since signatures can be further in than a buffer
+    let mut head = vec![];
+    if ai.config.accurate {
+        (&mut inp).take(ai.config.sniff_len() as u64).read_to_end(&mut head).await?;
+    }
     let adapter = choose_adapter(
         &ai.config,
         &ai.filepath_hint,
         ai.archive_recursion_depth,
-        &mut inp,
+        &head,
         active_adapters,
-    )
-    .await?;
+        parent,
+    )?;
     let ai = AdaptInfo {
-        inp: Box::pin(inp),
+        inp: Box::pin(BufReader::with_capacity(capacity, Cursor::new(head).chain(inp))),
         ..ai
     };
     let (a, b, c) = match adapter {
@@ -128,7 +217,7 @@ pub async fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
 
     // todo: figure out when using a bufreader is a good idea and when it is not
     // seems to be good for File::open() reads, but not sure about within archives (tar, zip)
-    let (ai, adapter, detection_reason, active_adapters) = match buf_choose_adapter(ai, None).await? {
+    let (ai, adapter, detection_reason, active_adapters) = match buf_choose_adapter(ai, None, None).await? {
         Ret::Recurse(ai, a, b, c) => (ai, a, b, c),
         Ret::Passthrough(ai) => {
             return Ok(ai.inp);
@@ -140,6 +229,106 @@ pub async fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
         .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))
 }
 
+/// the files whose text `rga_preproc` would concatenate, each with the line prefix of its path in
+/// archives, for `--rga-extract-all`. The cache is not used. None if no adapter handles the file
+pub async fn rga_preproc_files(ai: AdaptInfo) -> Result<Option<AdaptedFilesIterBox>> {
+    let (ai, adapter, detection_reason, active_adapters) = match buf_choose_adapter(ai, None, None).await? {
+        Ret::Recurse(ai, a, b, c) => (ai, a, b, c),
+        Ret::Passthrough(_) => return Ok(None),
+    };
+    let path_hint_copy = ai.filepath_hint.clone();
+    let files = loop_adapt(adapter.as_ref(), detection_reason, ai, active_adapters)
+        .await
+        .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))?;
+    Ok(Some(files))
+}
+
+/// the adapter `rga-preproc` would choose for the file at `path` and the cache key it would use, for `--rga-cache-key`
+pub async fn cache_key_for(
+    config: &RgaConfig,
+    path: &Path,
+) -> Result<Option<(Arc<dyn FileAdapter>, CacheKey)>> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    let mut head = vec![];
+    if config.accurate {
+        file.take(config.sniff_len() as u64).read_to_end(&mut head).await?;
+    }
+    let Some((adapter, _, active_adapters)) = choose_adapter(config, path, 0, &head, None, None)?
+    else {
+        return Ok(None);
+    };
+    let (file_mtime_unix_ms, file_size) =
+        file_stamp(path, None, &adapter.metadata().capabilities.sidecar_suffixes);
+    let key = CacheKey::new(
+        path,
+        file_mtime_unix_ms,
+        file_size,
+        adapter.as_ref(),
+        &active_adapters,
+        config,
+    )?;
+    Ok(Some((adapter, key)))
+}
+
+/// how much of a file rg looks at to tell whether it is binary
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// the content of the file at `path` as rg searches it when no adapter handles it, for `--rga-wc`,
+/// `--rga-extract-all` and `--rga-dupes`. None if it is binary, rg skips those. Only the start of a
+/// binary file is read
+pub async fn unadapted_text(path: &Path) -> Result<Option<ReadBox>> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    (&mut file)
+        .take(BINARY_SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+    if head.contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(Box::pin(Cursor::new(head).chain(BufReader::new(file)))))
+}
+
+/// the output of the adapters for the file at `path` as it is in the cache, for
+/// `--rga-show-extracted` and `--rga-from-cache-only`. None if it is not cached
+pub async fn cached_output(config: &RgaConfig, path: &Path) -> Result<Option<ReadBox>> {
+    if config.cache.disabled {
+        return Ok(None);
+    }
+    let Some((adapter, key)) = cache_key_for(config, path).await? else {
+        return Ok(None);
+    };
+    if !adapter.metadata().capabilities.deterministic {
+        return Ok(None);
+    }
+    match open_cache_db(config).await?.get(&key).await? {
+        Some(blob) => Ok(Some(blob.into_reader()?)),
+        None => Ok(None),
+    }
+}
+
+/// the mime type of the file at `path` and the adapter out of `active_adapters` that `rga-preproc` would choose for it, for `--rga-stats`
+pub async fn detect_file(
+    config: &RgaConfig,
+    path: &Path,
+    active_adapters: &ActiveAdapters,
+) -> Result<(Option<String>, Option<Arc<dyn FileAdapter>>)> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("opening {}", path.display()))?;
+    let mut head = vec![];
+    file.take(config.sniff_len() as u64).read_to_end(&mut head).await?;
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    let mime = sniff_mime(config, &filename, &head);
+    let adapter = choose_adapter(config, path, 0, &head, Some(active_adapters), None)?;
+    Ok((mime, adapter.map(|(adapter, _, _)| adapter)))
+}
+
 async fn adapt_caching(
     ai: AdaptInfo,
     adapter: Arc<dyn FileAdapter>,
@@ -156,10 +345,16 @@ async fn adapt_caching(
         ai.filepath_hint.to_string_lossy(),
         &meta.name
     );
+    run_stats::note_adapter(&meta.name);
+    let slow = meta.capabilities.slow;
+    let cache_codec = Codec::from_config(&ai.config)?;
     let cache_compression_level = ai.config.cache.compression_level;
     let cache_max_blob_len = ai.config.cache.max_blob_len;
 
-    let cache: Option<Box<dyn PreprocCache + Send>> = if ai.is_real_file && !ai.config.cache.disabled {
+    let cache: Option<Box<dyn PreprocCache + Send>> = if ai.is_real_file
+        && !ai.config.cache.disabled
+        && meta.capabilities.deterministic
+    {
         let daemon_port = ai.config.cache.daemon_port;
         // Check if daemon is alive with a quick timeout
         let daemon_available = tokio::time::timeout(
@@ -172,38 +367,45 @@ async fn adapt_caching(
             Some(Box::new(crate::daemon::DaemonCacheClient::new(daemon_port)))
         } else {
             debug!("Daemon not found on port {}, using local sqlite cache", daemon_port);
-            Some(open_cache_db(&ai.config).await?)
+            open_cache_db(&ai.config)
+                .await
+                .map_err(|e| warn!("could not open cache, continuing without it: {e:#}"))
+                .ok()
         }
     } else {
         None
     };
 
     if let Some(mut cache) = cache {
-        let file_mtime_unix_ms = ai.file_mtime_unix_ms.unwrap_or_else(|| {
-            std::fs::metadata(&ai.filepath_hint)
-                .and_then(|m| m.modified())
-                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)))
-                .map(|d| d.as_millis() as i64)
-                .unwrap_or(0)
-        });
+        let (file_mtime_unix_ms, file_size) = file_stamp(
+            &ai.filepath_hint,
+            ai.file_mtime_unix_ms,
+            &meta.capabilities.sidecar_suffixes,
+        );
         let cache_key = CacheKey::new(
             &ai.filepath_hint,
             file_mtime_unix_ms,
+            file_size,
             adapter.as_ref(),
             &active_adapters,
             &ai.config,
         )?;
-        
-        let cached = cache.get(&cache_key).await.context("cache.get")?;
+
+        let cached = cache.get(&cache_key).await;
         match cached {
-            Some(cached) => Ok(Box::pin(ZstdDecoder::new(Cursor::new(cached)))),
-            None => {
+            Result::Ok(Some(cached)) => {
+                run_stats::note_cache(CacheUse::Hit);
+                return Ok(cached.into_reader()?);
+            }
+            Result::Ok(None) => {
                 debug!("cache MISS, running adapter with caching...");
+                run_stats::note_cache(CacheUse::Miss);
                 let inp = loop_adapt(adapter.as_ref(), detection_reason, ai, active_adapters).await?;
                 let inp = concat_read_streams(inp);
                 let inp = async_read_and_write_to_cache(
                     inp,
                     cache_max_blob_len.0,
+                    cache_codec,
                     cache_compression_level.0,
                     Box::new(move |(uncompressed_size, compressed)| {
                         Box::pin(async move {
@@ -212,28 +414,37 @@ async fn adapt_caching(
                                 print_bytes(uncompressed_size as f64)
                             );
                             if let Some(cached) = compressed {
-                                debug!("compressed output: {}", print_bytes(cached.len() as f64));
-                                cache
-                                    .set(&cache_key, cached)
-                                    .await
-                                    .context("writing to cache")?
+                                debug!("compressed output: {}", print_bytes(cached.data.len() as f64));
+                                // the output was already passed on, a failed write only costs the next search
+                                let len = cached.data.len() as u64;
+                                match cache.set(&cache_key, cached).await {
+                                    Err(e) => warn!("writing to cache failed: {e:#}"),
+                                    Result::Ok(()) => run_stats::note_cached_bytes(len),
+                                }
                             }
                             Ok(())
                         })
                     }),
                 )?;
 
-                Ok(Box::pin(inp))
+                if slow {
+                    // rg stops reading at the first match with e.g. --files-with-matches, the output would not be cached
+                    let mut out = vec![];
+                    Box::pin(inp).read_to_end(&mut out).await?;
+                    return Ok(Box::pin(Cursor::new(out)));
+                }
+                return Ok(Box::pin(inp));
             }
+            // e.g. the database stayed locked by other rga-preproc processes for longer than the busy timeout
+            Err(e) => warn!("reading from cache failed, continuing without it: {e:#}"),
         }
-    } else {
-        debug!("cache DISABLED, running adapter directly...");
-        let inp = loop_adapt(adapter.as_ref(), detection_reason, ai, active_adapters).await?;
-        Ok(concat_read_streams(inp))
     }
+    debug!("cache DISABLED, running adapter directly...");
+    let inp = loop_adapt(adapter.as_ref(), detection_reason, ai, active_adapters).await?;
+    Ok(concat_read_streams(inp))
 }
 
-async fn read_discard(mut x: ReadBox) -> Result<()> {
+pub(crate) async fn read_discard(mut x: ReadBox) -> Result<()> {
     let mut buf = [0u8; 1 << 16];
     loop {
         let n = x.read(&mut buf).await?;
@@ -244,25 +455,154 @@ async fn read_discard(mut x: ReadBox) -> Result<()> {
     Ok(())
 }
 
+/// write the input to a temporary file, for adapters that need random access.
+/// The file is deleted when the returned directory is dropped
+pub(crate) async fn spool_to_temp_file(mut ai: AdaptInfo) -> Result<(AdaptInfo, tempfile::TempDir)> {
+    let dir = tempfile::tempdir().context("creating temporary directory")?;
+    let name = ai
+        .filepath_hint
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_else(|| "input".into());
+    let path = dir.path().join(name);
+    let mut f = tokio::fs::File::create(&path).await?;
+    tokio::io::copy(&mut ai.inp, &mut f).await?;
+    drop(f);
+    debug!("spooled {} to {}", ai.filepath_hint.display(), path.display());
+    ai.inp = Box::pin(BufReader::new(tokio::fs::File::open(&path).await?));
+    ai.filepath_hint = path;
+    ai.is_real_file = true;
+    Ok((ai, dir))
+}
+
 pub fn loop_adapt(
     adapter: &dyn FileAdapter,
     detection_reason: FileMatcher,
     ai: AdaptInfo,
     active_adapters: ActiveAdapters,
 ) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
-    Box::pin(async move { loop_adapt_inner(adapter, detection_reason, ai, active_adapters).await })
+    loop_adapt_branch(adapter, detection_reason, ai, active_adapters, Branch::default())
 }
+
+pub(crate) fn loop_adapt_branch(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    ai: AdaptInfo,
+    active_adapters: ActiveAdapters,
+    branch: Branch,
+) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
+    Box::pin(async move {
+        loop_adapt_inner(adapter, detection_reason, ai, active_adapters, branch).await
+    })
+}
+
+/// the notice that replaces the output of a file that is not read
+async fn notice(ai: AdaptInfo, text: String) -> Result<AdaptInfo> {
+    // some adapters (esp. zip) assume that the entry is read fully and might hang otherwise
+    read_discard(ai.inp).await?;
+    let s = format!("{}[rga: {text}]\n", ai.line_prefix).into_bytes();
+    Ok(AdaptInfo {
+        inp: Box::pin(Cursor::new(s)),
+        ..ai
+    })
+}
+
+/// `chosen` with the lines dropped that the `post_filters` of `parent` do not keep, if it is the
+/// text `parent` wrote: a file that is only postprocessed or passed through
+fn post_filtered(chosen: Ret, parent: &str) -> Result<Ret> {
+    let (ai, adapter) = match &chosen {
+        Ret::Recurse(ai, adapter, ..) => (ai, Some(adapter.metadata().name.as_str())),
+        Ret::Passthrough(ai) => (ai, None),
+    };
+    let Some(filter) = ai.config.post_filters.get(parent) else {
+        return Ok(chosen);
+    };
+    if adapter.is_some_and(|a| a != "postprocprefix" && a != "postprocpagebreaks") {
+        return Ok(chosen);
+    }
+    let filter = postproc::LineFilter::new(filter)?;
+    Ok(match chosen {
+        Ret::Recurse(ai, adapter, detection_reason, active_adapters) => Ret::Recurse(
+            AdaptInfo {
+                inp: postproc::postproc_filter(ai.inp, filter),
+                ..ai
+            },
+            adapter,
+            detection_reason,
+            active_adapters,
+        ),
+        Ret::Passthrough(ai) => Ret::Passthrough(AdaptInfo {
+            inp: postproc::postproc_filter(ai.inp, filter),
+            ..ai
+        }),
+    })
+}
+
+/// `branch` is the adapters the file went through
 pub async fn loop_adapt_inner(
     adapter: &dyn FileAdapter,
     detection_reason: FileMatcher,
     ai: AdaptInfo,
     active_adapters: ActiveAdapters,
+    branch: Branch,
 ) -> anyhow::Result<AdaptedFilesIterBox> {
     let fph = ai.filepath_hint.clone();
-    let inp = adapter.adapt(ai, &detection_reason).await;
+    let capabilities = &adapter.metadata().capabilities;
+    let (ai, probe) = if capabilities.produces_subfiles {
+        let (probe, inp) = recurse::probe(ai.inp, &branch).await?;
+        let ai = AdaptInfo { inp, ..ai };
+        if let Some(outer) = branch.find(&probe) {
+            let text = format!("{} is {} again, not reading it twice", fph.display(), outer.display());
+            return Ok(one_file(notice(ai, text).await?));
+        }
+        (ai, Some(probe))
+    } else {
+        (ai, None)
+    };
+    let depth = branch.depth();
+    let branch = branch.push(&adapter.metadata().name, &fph, probe);
+    debug!("{}: in {depth} containers, branch {branch}", fph.display());
+    let (ai, spooled) = if capabilities.seekable_input && !ai.is_real_file {
+        let (ai, dir) = spool_to_temp_file(ai).await?;
+        (ai, Some(dir))
+    } else {
+        (ai, None)
+    };
+    // held until all output of the adapter is consumed
+    let permit = if capabilities.external_deps.is_empty() {
+        None
+    } else {
+        Some(subprocess_permit(&ai.config).await?)
+    };
+    // the output of the adapter and everything nested in it is postprocessed with its overrides
+    let ai = match ai.config.postproc.for_adapter(&adapter.metadata().name) {
+        Some(postproc) => AdaptInfo {
+            config: RgaConfig {
+                postproc,
+                ..ai.config
+            },
+            ..ai
+        },
+        None => ai,
+    };
+    let robust = ai.config.robust;
+    let max_depth = recurse::max_depth(&ai.config);
+    let adapted = adapter.adapt(ai, &detection_reason);
+    let inp = if robust {
+        match robust::CatchPanic::new(adapted).await {
+            Err(e) => {
+                warn!("{}: {} failed, skipping it: {e:#}", fph.display(), adapter.metadata().name);
+                return Ok(Box::pin(tokio_stream::empty()));
+            }
+            inp => inp,
+        }
+    } else {
+        adapted.await
+    };
     let inp = if adapter.metadata().name == "postprocprefix" {
-        // don't add confusing error context
-        inp?
+        // don't add confusing error context. The output is the final text, matching its content
+        // could pick an adapter again
+        return if robust { inp.map(robust::robust_files) } else { inp };
     } else {
         inp.with_context(|| {
             format!(
@@ -272,19 +612,39 @@ pub async fn loop_adapt_inner(
             )
         })?
     };
+    let inp: AdaptedFilesIterBox = if robust { Box::pin(robust::CatchPanic::new(inp)) } else { inp };
+    let parent = adapter.metadata().name.clone();
     let s = stream! {
+        let _resources = (spooled, permit);
         for await file in inp {
             trace!("next file");
-            match buf_choose_adapter(file?, Some(&active_adapters)).await? {
+            let mut file = match file {
+                Err(e) if robust => {
+                    warn!("{}: {e:#}, skipping the rest of it", fph.display());
+                    break;
+                }
+                file => file?,
+            };
+            file.archive_recursion_depth = branch.depth();
+            let chosen = match buf_choose_adapter(file, Some(&active_adapters), Some(&parent)).await {
+                Err(e) if robust => {
+                    warn!("{}: {e:#}, skipping a file in it", fph.display());
+                    continue;
+                }
+                chosen => chosen?,
+            };
+            let chosen = post_filtered(chosen, &parent)?;
+            match chosen {
                 Ret::Recurse(ai, adapter, detection_reason, _active_adapters) => {
-                    if ai.archive_recursion_depth >= ai.config.max_archive_recursion.0 {
-                        // some adapters (esp. zip) assume that the entry is read fully and might hang otherwise
-                        read_discard(ai.inp).await?;
-                        let s = format!("{}[rga: max archive recursion reached ({})]\n", ai.line_prefix, ai.archive_recursion_depth).into_bytes();
-                        yield Ok(AdaptInfo {
-                            inp: Box::pin(Cursor::new(s)),
-                            ..ai
-                        });
+                    let container = adapter.metadata().capabilities.produces_subfiles;
+                    if container && branch.depth() >= max_depth {
+                        let depth = branch.depth();
+                        yield notice(ai, format!("max archive recursion reached ({depth})")).await;
+                        continue;
+                    }
+                    if !container && branch.conversions() >= MAX_CONVERSIONS {
+                        debug!("{}: conversions of {branch}", ai.filepath_hint.display());
+                        yield notice(ai, format!("max conversions reached ({MAX_CONVERSIONS})")).await;
                         continue;
                     }
                     debug!(
@@ -296,10 +656,30 @@ pub async fn loop_adapt_inner(
                         ai.filepath_hint.to_string_lossy(),
                         &adapter.metadata().name
                     );
-                    for await ifile in loop_adapt(adapter.as_ref(), detection_reason, ai, active_adapters.clone()).await? {
+                    let path = ai.filepath_hint.clone();
+                    let files = match loop_adapt_branch(
+                        adapter.as_ref(),
+                        detection_reason,
+                        ai,
+                        active_adapters.clone(),
+                        branch.clone(),
+                    )
+                    .await
+                    {
+                        Err(e) if robust => {
+                            warn!("{}: {e:#}, skipping it", path.display());
+                            continue;
+                        }
+                        files => files?,
+                    };
+                    for await ifile in files {
                         yield ifile;
                     }
                 }
+                Ret::Passthrough(ai) if robust => {
+                    debug!("no adapter for {}, ending recursion", ai.filepath_hint.to_string_lossy());
+                    yield Ok(robust::robust_output(ai));
+                }
                 Ret::Passthrough(ai) => {
                     debug!("no adapter for {}, ending recursion", ai.filepath_hint.to_string_lossy());
                     yield Ok(ai);
@@ -1,32 +1,93 @@
-use crate::{adapters::FileAdapter, preproc::ActiveAdapters, config::RgaConfig};
+use crate::adapters::postproc::{PostprocPageBreaks, PostprocPrefix};
+use crate::dupes::Fingerprint;
+use crate::adapters::{FileAdapter, GetMetadata, ReadBox};
+use crate::{preproc::ActiveAdapters, config::{CacheTtl, RgaConfig}};
 use anyhow::{Context, Result};
+use async_compression::tokio::bufread::ZstdDecoder;
 use log::warn;
 use path_clean::PathClean;
 use rusqlite::{OptionalExtension, named_params};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio_rusqlite::Connection;
 
 use serde::{Deserialize, Serialize};
 
-static SCHEMA_VERSION: i32 = 3;
+pub(crate) static SCHEMA_VERSION: i32 = 10;
+/// marks the cache database and exported caches as rga's
+const APPLICATION_ID: i64 = 924716026;
+/// the columns an exported entry keeps, the access statistics start over on import
+const EXPORTED_COLUMNS: &str = "cache_key, adapter, adapter_version, created_unix_ms, file_path, \
+    namespace, file_mtime_unix_ms, file_size, text_content, codec, text_len, content_hash, content_minhash";
+/// How long to wait for other rga-preproc processes holding the database lock (rg runs one per file
+/// and thread). The caller continues without the cache after that instead of failing the file.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Version of the key material hashed by [`CacheKey::digest`]. Bump it when fields are added to
+/// [`KeyMaterial`] or their meaning changes.
+static KEY_SCHEME_VERSION: i32 = 1;
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct CacheKey {
     pub config_hash: String,
     pub adapter: String,
     pub adapter_version: i32,
     pub active_adapters: String,
+    /// versions of the postprocessing the output went through, see [`output_schema`]
+    pub output_schema: String,
+    /// canonicalized if the file exists, so the same file reached through different paths shares one entry
     pub file_path: String,
     pub file_mtime_unix_ms: i64,
+    pub file_size: i64,
+    /// with `cache.key` "content-hash", see [`content_hash`]. The entry is then found by it instead of
+    /// the path and the mtime
+    pub content_hash: Option<String>,
 }
+
+/// Everything that identifies a cache entry except the file's mtime and size. Those are stored next to
+/// the digest and compared on lookup instead, so a changed file replaces its entry rather than adding one.
+#[derive(Serialize, Debug)]
+pub struct KeyMaterial<'a> {
+    pub key_scheme: i32,
+    pub file_path: &'a str,
+    pub adapter: &'a str,
+    pub adapter_version: i32,
+    pub active_adapters: &'a str,
+    pub output_schema: &'a str,
+    pub config_hash: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<&'a str>,
+}
+
+/// The output of an adapter is cached after postprocessing (line prefixes, page markers), so the
+/// versions of the postprocessors that can have touched it are part of the key. Changing one of
+/// them only invalidates the entries it applies to, e.g. page markers only matter for adapters that
+/// produce pages or can contain files that do.
+pub fn output_schema(adapter: &dyn FileAdapter) -> String {
+    let caps = &adapter.metadata().capabilities;
+    let mut postprocessors: Vec<&dyn GetMetadata> = vec![&PostprocPrefix {}];
+    if caps.produces_pages || caps.produces_subfiles {
+        postprocessors.push(&PostprocPageBreaks {});
+    }
+    postprocessors
+        .iter()
+        .map(|p| format!("{}.v{}", p.metadata().name, p.metadata().version))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 impl CacheKey {
     pub fn new(
         filepath_hint: &Path,
         file_mtime_unix_ms: i64,
+        file_size: i64,
         adapter: &dyn FileAdapter,
         active_adapters: &ActiveAdapters,
         config: &RgaConfig,
     ) -> Result<Self> {
-        let active_adapters = if adapter.metadata().recurses {
+        let active_adapters = if adapter.metadata().capabilities.produces_subfiles {
             serde_json::to_string(
                 &active_adapters
                     .iter()
@@ -36,21 +97,208 @@ impl CacheKey {
         } else {
             "null".to_string()
         };
+        let file_path = std::fs::canonicalize(filepath_hint).unwrap_or_else(|_| filepath_hint.clean());
+        let content_hash = match KeyMode::from_config(config)? {
+            KeyMode::Mtime => None,
+            KeyMode::ContentHash => content_hash(
+                &file_path,
+                config.cache.content_hash_len.0,
+                &adapter.metadata().capabilities.sidecar_suffixes,
+            )
+            .map_err(|e| log::debug!("could not hash {}: {e}", file_path.display()))
+            .ok(),
+        };
+        // the hash covers the size, and the mtime does not matter
+        let file_mtime_unix_ms = match content_hash {
+            Some(_) => 0,
+            None => file_mtime_unix_ms,
+        };
         Ok(Self {
             config_hash: config.config_hash(),
             adapter: adapter.metadata().name.clone(),
             adapter_version: adapter.metadata().version,
-            file_path: filepath_hint.clean().to_string_lossy().to_string(),
+            file_path: file_path.to_string_lossy().to_string(),
             file_mtime_unix_ms,
+            file_size,
             active_adapters,
+            output_schema: output_schema(adapter),
+            content_hash,
         })
     }
+
+    pub fn material(&self) -> KeyMaterial<'_> {
+        KeyMaterial {
+            key_scheme: KEY_SCHEME_VERSION,
+            // the same content at another path is the same entry
+            file_path: match self.content_hash {
+                Some(_) => "",
+                None => &self.file_path,
+            },
+            adapter: &self.adapter,
+            adapter_version: self.adapter_version,
+            active_adapters: &self.active_adapters,
+            output_schema: &self.output_schema,
+            config_hash: &self.config_hash,
+            content_hash: self.content_hash.as_deref(),
+        }
+    }
+
+    /// blake3 (hex) of the canonical json of [`Self::material`], the primary key of the cache table
+    pub fn digest(&self) -> String {
+        let canonical = serde_json::to_vec(&self.material()).expect("key is serializable");
+        blake3::hash(&canonical).to_hex().to_string()
+    }
+}
+
+/// how cache entries are found for a file, `cache.key`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyMode {
+    /// by the path, mtime and size
+    Mtime,
+    /// by [`content_hash`]
+    ContentHash,
+}
+
+impl KeyMode {
+    pub fn from_config(config: &RgaConfig) -> Result<Self> {
+        match config.cache.key.as_str() {
+            "mtime" | "" => Ok(KeyMode::Mtime),
+            "content-hash" => Ok(KeyMode::ContentHash),
+            other => Err(anyhow::anyhow!("Unknown cache key mode: {}", other)),
+        }
+    }
+}
+
+/// how adapter outputs are compressed in the cache, `cache.compression`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Lz4,
+    None,
+}
+
+impl Codec {
+    pub fn from_config(config: &RgaConfig) -> Result<Self> {
+        match config.cache.compression.as_str() {
+            "zstd" | "" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "none" => Ok(Codec::None),
+            other => Err(anyhow::anyhow!("Unknown cache compression: {}", other)),
+        }
+    }
+
+    /// as stored in the `codec` column
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::None => "none",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Codec::Zstd, Codec::Lz4, Codec::None]
+            .into_iter()
+            .find(|codec| codec.name() == name)
+    }
+}
+
+/// an adapter output as stored in the cache
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Blob {
+    pub codec: Codec,
+    pub data: Vec<u8>,
+    /// the length of the output before compression, when it is known without decompressing it
+    #[serde(default)]
+    pub text_len: Option<u64>,
+}
+
+impl Blob {
+    /// the output, uncompressed
+    pub fn decode(&self) -> std::io::Result<Vec<u8>> {
+        match self.codec {
+            Codec::Zstd => zstd::decode_all(&self.data[..]),
+            Codec::Lz4 => {
+                let mut text = vec![];
+                lz4_flex::frame::FrameDecoder::new(&self.data[..]).read_to_end(&mut text)?;
+                Ok(text)
+            }
+            Codec::None => Ok(self.data.clone()),
+        }
+    }
+
+    /// a reader of the uncompressed output
+    pub fn into_reader(self) -> std::io::Result<ReadBox> {
+        Ok(match self.codec {
+            Codec::Zstd => Box::pin(ZstdDecoder::new(Cursor::new(self.data))),
+            // there is no async lz4 decoder, the outputs in the cache are small enough to inflate at once
+            Codec::Lz4 => Box::pin(Cursor::new(self.decode()?)),
+            Codec::None => Box::pin(Cursor::new(self.data)),
+        })
+    }
+}
+
+/// blake3 (hex) of the size and the first `len` bytes (all of it for 0) of a file and of its
+/// sidecar files, for `cache.key` "content-hash"
+pub fn content_hash(path: &Path, len: u64, sidecar_suffixes: &[String]) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut hash_file = |path: &Path| -> std::io::Result<()> {
+        let file = std::fs::File::open(path)?;
+        hasher.update(&file.metadata()?.len().to_le_bytes());
+        let len = if len == 0 { u64::MAX } else { len };
+        std::io::copy(&mut std::io::Read::take(file, len), &mut hasher)?;
+        Ok(())
+    };
+    hash_file(path)?;
+    for suffix in sidecar_suffixes {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        if Path::new(&sidecar).exists() {
+            hash_file(Path::new(&sidecar))?;
+        }
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// mtime and size of a file for the cache key. Sidecar files (e.g. a sqlite WAL) can change the
+/// adapter output without touching the file itself, so they count as well.
+pub fn file_stamp(path: &Path, mtime_hint: Option<i64>, sidecar_suffixes: &[String]) -> (i64, i64) {
+    fn stamp(path: &Path) -> Option<(i64, i64)> {
+        let meta = std::fs::metadata(path).ok()?;
+        let mtime = meta.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+        Some((mtime.as_millis() as i64, meta.len() as i64))
+    }
+    let (mtime, size) = stamp(path).unwrap_or((0, 0));
+    let mtime = mtime_hint.unwrap_or(mtime);
+    sidecar_suffixes
+        .iter()
+        .filter_map(|suffix| {
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(suffix);
+            stamp(Path::new(&sidecar))
+        })
+        .fold((mtime, size), |(mtime, size), (m, s)| (mtime.max(m), size + s))
+}
+
+/// state of the entry for a key in the sqlite cache, for `--rga-cache-key`
+#[derive(Debug, PartialEq)]
+pub enum EntryStatus {
+    Fresh,
+    /// an entry exists, but for a different version of the file
+    Stale {
+        file_mtime_unix_ms: i64,
+        file_size: i64,
+    },
+    /// an entry exists, but is older than the adapter's `cache.ttl`
+    Expired,
+    Missing,
 }
 
 #[async_trait::async_trait]
 pub trait PreprocCache {
-    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>>;
-    async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()>;
+    async fn get(&self, key: &CacheKey) -> Result<Option<Blob>>;
+    async fn set(&mut self, key: &CacheKey, value: Blob) -> Result<()>;
 }
 
 async fn connect_pragmas(db: &Connection) -> Result<()> {
@@ -59,26 +307,67 @@ async fn connect_pragmas(db: &Connection) -> Result<()> {
     //db.execute(&format!("pragma page_size = {};", want_page_size))
     //    .context("setup pragma 1")?;
     db.call(|db| {
-        // db.busy_timeout(Duration::from_secs(10))?;
-        db.pragma_update(None, "journal_mode", "wal")?;
+        // only changes anything (and needs the write lock) the first time, wal mode is persistent
+        if db.pragma_query_value(None, "journal_mode", |r| r.get::<_, String>(0))? != "wal" {
+            db.pragma_update(None, "journal_mode", "wal")?;
+        }
         db.pragma_update(None, "foreign_keys", "on")?;
         db.pragma_update(None, "temp_store", "memory")?;
         db.pragma_update(None, "synchronous", "off")?; // integrity isn't very important here
         db.pragma_update(None, "mmap_size", "2000000000")?;
         db.execute("
             create table if not exists preproc_cache (
-                config_hash text not null,
+                cache_key text not null primary key, -- CacheKey::digest
                 adapter text not null,
                 adapter_version integer not null,
                 created_unix_ms integer not null default (unixepoch() * 1000),
-                active_adapters text not null, -- 'null' if adapter cannot recurse
                 file_path text not null,
+                -- cache.namespace, or the repository the file is in, see `namespace_of`
+                namespace text,
                 file_mtime_unix_ms integer not null,
-                text_content_zstd blob not null
+                file_size integer not null,
+                text_content blob not null,
+                -- Codec::name of text_content
+                codec text not null,
+                -- length of the text before compression, for --rga-cache-stats
+                text_len integer,
+                -- for the eviction when the cache gets larger than cache.max_total_size
+                last_access_unix_ms integer not null default (unixepoch() * 1000),
+                access_count integer not null default 1,
+                -- Fingerprint of the text, stored by --rga-dupes
+                content_hash text,
+                content_minhash blob
             ) strict", []
         )?;
-
-        db.execute("create unique index if not exists preproc_cache_idx on preproc_cache (config_hash, adapter, adapter_version, file_path, active_adapters)", [])?;
+        // for dropping the entries of other versions of an adapter
+        db.execute(
+            "create index if not exists preproc_cache_adapter on preproc_cache (adapter, adapter_version)",
+            [],
+        )?;
+        db.execute(
+            "create index if not exists preproc_cache_namespace on preproc_cache (namespace)",
+            [],
+        )?;
+        // the total size of text_content, so the eviction doesn't have to add it up on every write. The row is
+        // created by the first eviction, until then the triggers change nothing
+        db.execute(
+            "create table if not exists cache_size (
+                id integer primary key check (id = 0),
+                total integer not null
+            ) strict",
+            [],
+        )?;
+        db.execute_batch(
+            "create trigger if not exists preproc_cache_size_insert after insert on preproc_cache begin
+                update cache_size set total = total + length(new.text_content);
+            end;
+            create trigger if not exists preproc_cache_size_update after update of text_content on preproc_cache begin
+                update cache_size set total = total - length(old.text_content) + length(new.text_content);
+            end;
+            create trigger if not exists preproc_cache_size_delete after delete on preproc_cache begin
+                update cache_size set total = total - length(old.text_content);
+            end;",
+        )?;
 
         Ok::<(), rusqlite::Error>(())
     })
@@ -86,97 +375,304 @@ async fn connect_pragmas(db: &Connection) -> Result<()> {
     let jm: i64 = db
         .call(|db| db.pragma_query_value(None, "application_id", |r| r.get(0)))
         .await?;
-    if jm != 924716026 {
+    if jm != APPLICATION_ID {
         // (probably) newly created db
-        db.call(|db| db.pragma_update(None, "application_id", "924716026"))
+        db.call(|db| db.pragma_update(None, "application_id", APPLICATION_ID))
             .await?;
     }
     Ok(())
 }
 
+/// which entries go first when the cache is larger than `cache.max_total_size`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eviction {
+    /// least recently read
+    Lru,
+    /// least often read, least recently read among those
+    Lfu,
+}
+
+impl Eviction {
+    pub fn from_config(config: &RgaConfig) -> Result<Self> {
+        match config.cache.eviction.as_str() {
+            "lru" | "" => Ok(Eviction::Lru),
+            "lfu" => Ok(Eviction::Lfu),
+            other => Err(anyhow::anyhow!("Unknown cache eviction policy: {}", other)),
+        }
+    }
+
+    fn order_by(self) -> &'static str {
+        match self {
+            Eviction::Lru => "last_access_unix_ms, access_count",
+            Eviction::Lfu => "access_count, last_access_unix_ms",
+        }
+    }
+}
+
+fn now_unix_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// the namespace of the entries of a file without `cache.namespace`: the root of the git, mercurial
+/// or subversion repository it is in, so deleting a project can also drop its entries
+pub fn namespace_of(file_path: &Path) -> Option<String> {
+    file_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| [".git", ".hg", ".svn"].iter().any(|marker| dir.join(marker).exists()))
+        .map(|root| root.to_string_lossy().into_owned())
+}
+
+/// entries of `adapter` created before this are expired according to `cache.ttl`, `None` if they
+/// do not expire
+fn expired_before(ttl: &BTreeMap<String, CacheTtl>, adapter: &str) -> Option<i64> {
+    let ttl = ttl.get(adapter).or_else(|| ttl.get("*"))?;
+    Some(now_unix_ms().saturating_sub((ttl.0 as i64).saturating_mul(1000)))
+}
+
+/// if the outputs in the cache add up to more than `max_total_size` bytes, deletes entries other than
+/// `keep` in the order of `eviction` until they are at most 90% of it, returns how many were deleted.
+/// Evicting a bit more than needed means the next writes don't all have to evict again
+fn evict(
+    db: &mut rusqlite::Connection,
+    max_total_size: u64,
+    eviction: Eviction,
+    keep: &str,
+) -> rusqlite::Result<usize> {
+    let max_total_size = max_total_size as i64;
+    let total_size = |db: &rusqlite::Connection| {
+        db.query_row("select total from cache_size", [], |r| r.get::<_, i64>(0))
+            .optional()
+    };
+    // without the write lock, so the writes that don't go over the limit don't wait for each other
+    if total_size(db)?.is_some_and(|total| total <= max_total_size) {
+        return Ok(0);
+    }
+    let tx = db.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+    let total = match total_size(&tx)? {
+        Some(total) => total,
+        // the first eviction of this cache, from now on the triggers keep the total
+        None => {
+            let total: i64 = tx.query_row(
+                "select coalesce(sum(length(text_content)), 0) from preproc_cache",
+                [],
+                |r| r.get(0),
+            )?;
+            tx.execute("insert into cache_size (id, total) values (0, ?)", [total])?;
+            total
+        }
+    };
+    if total <= max_total_size {
+        tx.commit()?;
+        return Ok(0);
+    }
+    let mut excess = total - (max_total_size - max_total_size / 10);
+    let mut victims = vec![];
+    {
+        let mut stmt = tx.prepare(&format!(
+            "select cache_key, length(text_content) from preproc_cache where cache_key != ? order by {}",
+            eviction.order_by()
+        ))?;
+        let mut rows = stmt.query([keep])?;
+        while excess > 0
+            && let Some(row) = rows.next()?
+        {
+            victims.push(row.get::<_, String>(0)?);
+            excess -= row.get::<_, i64>(1)?;
+        }
+    }
+    for key in &victims {
+        tx.execute("delete from preproc_cache where cache_key = ?", [key])?;
+    }
+    tx.commit()?;
+    Ok(victims.len())
+}
+
 struct SqliteCache {
     db: Connection,
+    /// `cache.max_total_size` if set, and what to evict to stay below it
+    limit: Option<(u64, Eviction)>,
+    /// `cache.ttl`
+    ttl: BTreeMap<String, CacheTtl>,
+    /// `cache.namespace` if set
+    namespace: Option<String>,
 }
 impl SqliteCache {
     async fn new(path: &Path) -> Result<Self> {
         let db = Connection::open(path.join("cache.sqlite3")).await?;
         db.call(|db| {
-            let schema_version: i32 = db.pragma_query_value(None, "user_version", |r| r.get(0))?;
-            if schema_version != SCHEMA_VERSION {
-                warn!("Cache schema version mismatch, clearing cache");
-                db.execute("drop table if exists preproc_cache", [])?;
-                db.pragma_update(None, "user_version", format!("{SCHEMA_VERSION}"))?;
+            db.busy_timeout(BUSY_TIMEOUT)?;
+            let schema_version = |db: &rusqlite::Connection| {
+                db.pragma_query_value(None, "user_version", |r| r.get::<_, i32>(0))
+            };
+            let version = schema_version(db)?;
+            if version > SCHEMA_VERSION {
+                // written by a newer rga, clearing it would only make the two installs fight over it
+                return Ok(Some(version));
             }
-            Ok::<(), rusqlite::Error>(())
+            if version != SCHEMA_VERSION {
+                // check again with the write lock held, another process may have just migrated
+                let tx = db.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+                if schema_version(&tx)? < SCHEMA_VERSION {
+                    warn!("Cache schema version mismatch, clearing cache");
+                    tx.execute("drop table if exists preproc_cache", [])?;
+                    tx.execute("drop table if exists cache_size", [])?;
+                    tx.pragma_update(None, "user_version", format!("{SCHEMA_VERSION}"))?;
+                }
+                tx.commit()?;
+            }
+            Ok::<_, rusqlite::Error>(None)
         })
-        .await?;
+        .await?
+        .map_or(Ok(()), |newer| {
+            Err(anyhow::anyhow!(
+                "The cache at {} was written by a newer version of rga (cache schema {newer}, this one uses {SCHEMA_VERSION}). \
+                 An old rga or rga-preproc is probably still installed and found first in PATH: remove it, or use another cache with --rga-cache-path",
+                path.display()
+            ))
+        })?;
 
         connect_pragmas(&db).await?;
 
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            limit: None,
+            ttl: BTreeMap::new(),
+            namespace: None,
+        })
     }
 }
 
 #[async_trait::async_trait]
 impl PreprocCache for SqliteCache {
-    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+    async fn get(&self, key: &CacheKey) -> Result<Option<Blob>> {
         let key = (*key).clone(); // todo: without cloning
+        let expired_before = expired_before(&self.ttl, &key.adapter);
+        let record_access = self.limit.is_some();
         Ok(self
             .db
             .call(move |db| {
-                db
+                let content = db
                     .query_row(
-                        "select text_content_zstd from preproc_cache where
-                            adapter = :adapter
-                        and config_hash = :config_hash
-                        and adapter_version = :adapter_version
-                        and active_adapters = :active_adapters
-                        and file_path = :file_path
+                        "select text_content, codec from preproc_cache where
+                            cache_key = :cache_key
                         and file_mtime_unix_ms = :file_mtime_unix_ms
+                        and file_size = :file_size
+                        and (:expired_before is null or created_unix_ms >= :expired_before)
                 ",
                         named_params! {
-                            ":config_hash": &key.config_hash,
-                            ":adapter": &key.adapter,
-                            ":adapter_version": &key.adapter_version,
-                            ":active_adapters": &key.active_adapters,
-                            ":file_path": &key.file_path,
-                            ":file_mtime_unix_ms": &key.file_mtime_unix_ms
+                            ":cache_key": &key.digest(),
+                            ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                            ":file_size": &key.file_size,
+                            ":expired_before": expired_before
                         },
-                        |r| r.get::<_, Vec<u8>>(0),
+                        |r| Ok((r.get::<_, Vec<u8>>(0)?, r.get::<_, String>(1)?)),
                     )
-                    .optional()
+                    .optional()?;
+                let content = content.and_then(|(data, codec)| match Codec::from_name(&codec) {
+                    Some(codec) => Some(Blob { codec, data, text_len: None }),
+                    None => {
+                        warn!("unknown compression {codec} of a cache entry, ignoring it");
+                        None
+                    }
+                });
+                if content.is_some() && record_access {
+                    // only for the eviction, not worth failing or waiting for the write lock over: skip it
+                    // when another process is writing, so readers stay concurrent
+                    db.busy_timeout(Duration::ZERO)?;
+                    if let Err(e) = db.execute(
+                        "update preproc_cache set last_access_unix_ms = ?, access_count = access_count + 1
+                            where cache_key = ?",
+                        rusqlite::params![now_unix_ms(), key.digest()],
+                    ) {
+                        log::debug!("could not record cache access: {e}");
+                    }
+                    db.busy_timeout(BUSY_TIMEOUT)?;
+                }
+                Ok::<_, rusqlite::Error>(content)
             })
             .await
             .context("reading from cache")?)
     }
 
-    async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()> {
+    async fn set(&mut self, key: &CacheKey, value: Blob) -> Result<()> {
         let key = (*key).clone(); // todo: without cloning
+        // only for --rga-cache-stats, the caching writer knows it
+        let text_len = match value.text_len {
+            Some(len) => Some(len as i64),
+            None => value.decode().ok().map(|text| text.len() as i64),
+        };
+        let limit = self.limit;
+        let expired_before = expired_before(&self.ttl, &key.adapter);
+        let namespace = self
+            .namespace
+            .clone()
+            .or_else(|| namespace_of(Path::new(&key.file_path)));
         log::trace!(
             "Writing to cache: {}, {}, {} byte",
             key.adapter,
             key.file_path,
-            value.len()
+            value.data.len()
         );
         Ok(self
             .db
             .call(move |db| {
+                let digest = key.digest();
                 db.execute(
-                    "insert into preproc_cache (config_hash, adapter, adapter_version, active_adapters, file_path, file_mtime_unix_ms, text_content_zstd) values
-                        (:config_hash, :adapter, :adapter_version, :active_adapters, :file_path, :file_mtime_unix_ms, :text_content_zstd)
-                    on conflict (config_hash, adapter, adapter_version, active_adapters, file_path) do update set
+                    "insert into preproc_cache (cache_key, adapter, adapter_version, file_path, namespace, file_mtime_unix_ms, file_size, text_content, codec, text_len, last_access_unix_ms) values
+                        (:cache_key, :adapter, :adapter_version, :file_path, :namespace, :file_mtime_unix_ms, :file_size, :text_content, :codec, :text_len, :now)
+                    on conflict (cache_key) do update set
+                        namespace = :namespace,
                         file_mtime_unix_ms = :file_mtime_unix_ms,
+                        file_size = :file_size,
                         created_unix_ms = unixepoch() * 1000,
-                        text_content_zstd = :text_content_zstd",
+                        text_content = :text_content,
+                        codec = :codec,
+                        text_len = :text_len,
+                        last_access_unix_ms = :now,
+                        access_count = 1,
+                        content_hash = null,
+                        content_minhash = null",
                     named_params! {
-                        ":config_hash": &key.config_hash,
+                        ":cache_key": &digest,
                         ":adapter": &key.adapter,
                         ":adapter_version": &key.adapter_version,
-                        ":active_adapters": &key.active_adapters,
                         ":file_path": &key.file_path,
+                        ":namespace": namespace,
                         ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
-                        ":text_content_zstd": value
+                        ":file_size": &key.file_size,
+                        ":text_content": value.data,
+                        ":codec": value.codec.name(),
+                        ":text_len": text_len,
+                        ":now": now_unix_ms()
                     })?;
+                // the version is part of the key, so entries of other versions of the adapter
+                // would never be read again
+                let dropped = db.execute(
+                    "delete from preproc_cache where adapter = :adapter and cache_key != :cache_key
+                        and (adapter_version != :adapter_version or created_unix_ms < :expired_before)",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":cache_key": &digest,
+                        ":adapter_version": &key.adapter_version,
+                        ":expired_before": expired_before
+                    },
+                )?;
+                if dropped > 0 {
+                    log::debug!(
+                        "dropped {dropped} expired or outdated cache entries of {}",
+                        key.adapter
+                    );
+                }
+                if let Some((max_total_size, eviction)) = limit {
+                    let evicted = evict(db, max_total_size, eviction, &digest)?;
+                    if evicted > 0 {
+                        log::debug!("evicted {evicted} entries from the cache");
+                    }
+                }
                 Ok::<(), rusqlite::Error>(())
             })
             .await?)
@@ -185,10 +681,10 @@ impl PreprocCache for SqliteCache {
 pub struct RedisCache;
 #[async_trait::async_trait]
 impl PreprocCache for RedisCache {
-    async fn get(&self, _key: &CacheKey) -> Result<Option<Vec<u8>>> {
+    async fn get(&self, _key: &CacheKey) -> Result<Option<Blob>> {
         Err(anyhow::anyhow!("Redis cache not implemented yet"))
     }
-    async fn set(&mut self, _key: &CacheKey, _value: Vec<u8>) -> Result<()> {
+    async fn set(&mut self, _key: &CacheKey, _value: Blob) -> Result<()> {
         Err(anyhow::anyhow!("Redis cache not implemented yet"))
     }
 }
@@ -196,10 +692,10 @@ impl PreprocCache for RedisCache {
 pub struct S3Cache;
 #[async_trait::async_trait]
 impl PreprocCache for S3Cache {
-    async fn get(&self, _key: &CacheKey) -> Result<Option<Vec<u8>>> {
+    async fn get(&self, _key: &CacheKey) -> Result<Option<Blob>> {
         Err(anyhow::anyhow!("S3 cache not implemented yet"))
     }
-    async fn set(&mut self, _key: &CacheKey, _value: Vec<u8>) -> Result<()> {
+    async fn set(&mut self, _key: &CacheKey, _value: Blob) -> Result<()> {
         Err(anyhow::anyhow!("S3 cache not implemented yet"))
     }
 }
@@ -207,10 +703,19 @@ impl PreprocCache for S3Cache {
 /// opens a default cache
 pub async fn open_cache_db(config: &RgaConfig) -> Result<Box<dyn PreprocCache + Send>> {
     match config.cache.cache_type.as_str() {
-        "sqlite" => {
+        // empty if the config did not go through clap, e.g. RgaConfig::default()
+        "sqlite" | "" => {
             let path = Path::new(&config.cache.path.0);
             std::fs::create_dir_all(path)?;
-            Ok(Box::new(SqliteCache::new(path).await?))
+            let eviction = Eviction::from_config(config)?;
+            let mut cache = SqliteCache::new(path).await?;
+            cache.limit = match config.cache.max_total_size.0 {
+                0 => None,
+                max_total_size => Some((max_total_size, eviction)),
+            };
+            cache.ttl = config.cache.ttl.clone();
+            cache.namespace = Some(config.cache.namespace.clone()).filter(|n| !n.is_empty());
+            Ok(Box::new(cache))
         }
         "redis" => Ok(Box::new(RedisCache)),
         "s3" => Ok(Box::new(S3Cache)),
@@ -218,11 +723,477 @@ pub async fn open_cache_db(config: &RgaConfig) -> Result<Box<dyn PreprocCache +
     }
 }
 
+/// looks up `key` in the local sqlite cache without reading the content
+pub async fn entry_status(config: &RgaConfig, key: &CacheKey) -> Result<EntryStatus> {
+    let path = Path::new(&config.cache.path.0);
+    if !path.join("cache.sqlite3").exists() {
+        return Ok(EntryStatus::Missing);
+    }
+    let cache = SqliteCache::new(path).await?;
+    let digest = key.digest();
+    let stamp = cache
+        .db
+        .call(move |db| {
+            db.query_row(
+                "select file_mtime_unix_ms, file_size, created_unix_ms from preproc_cache
+                    where cache_key = ?",
+                [digest],
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?, r.get::<_, i64>(2)?)),
+            )
+            .optional()
+        })
+        .await
+        .context("reading from cache")?;
+    let expired_before = expired_before(&config.cache.ttl, &key.adapter);
+    Ok(match stamp {
+        None => EntryStatus::Missing,
+        Some((m, s, created)) if m == key.file_mtime_unix_ms && s == key.file_size => {
+            match expired_before {
+                Some(before) if created < before => EntryStatus::Expired,
+                _ => EntryStatus::Fresh,
+            }
+        }
+        Some((file_mtime_unix_ms, file_size, _)) => EntryStatus::Stale {
+            file_mtime_unix_ms,
+            file_size,
+        },
+    })
+}
+
+/// the fingerprint of the text of a fresh entry for `key` in the sqlite cache, for `--rga-dupes`
+pub async fn fingerprint(config: &RgaConfig, key: &CacheKey) -> Result<Option<Fingerprint>> {
+    let path = Path::new(&config.cache.path.0);
+    if !matches!(config.cache.cache_type.as_str(), "sqlite" | "") || !path.join("cache.sqlite3").exists() {
+        return Ok(None);
+    }
+    let cache = SqliteCache::new(path).await?;
+    let (digest, mtime, size) = (key.digest(), key.file_mtime_unix_ms, key.file_size);
+    let parts = cache
+        .db
+        .call(move |db| {
+            db.query_row(
+                "select content_hash, content_minhash from preproc_cache where
+                    cache_key = ? and file_mtime_unix_ms = ? and file_size = ? and content_hash is not null",
+                rusqlite::params![digest, mtime, size],
+                |r| Ok((r.get::<_, String>(0)?, r.get::<_, Vec<u8>>(1)?)),
+            )
+            .optional()
+        })
+        .await
+        .context("reading from cache")?;
+    Ok(parts.map(|(hash, minhash)| Fingerprint::from_parts(hash, &minhash)))
+}
+
+/// stores the fingerprint `--rga-dupes` computed from the text of the entry for `key`, so the next run
+/// doesn't have to. Cache writes leave it out, most entries are never compared
+pub async fn store_fingerprint(config: &RgaConfig, key: &CacheKey, fingerprint: &Fingerprint) -> Result<()> {
+    let path = Path::new(&config.cache.path.0);
+    if !matches!(config.cache.cache_type.as_str(), "sqlite" | "") || !path.join("cache.sqlite3").exists() {
+        return Ok(());
+    }
+    let cache = SqliteCache::new(path).await?;
+    let (digest, mtime, size) = (key.digest(), key.file_mtime_unix_ms, key.file_size);
+    let (hash, minhash) = (fingerprint.hash.clone(), fingerprint.minhash_bytes());
+    cache
+        .db
+        .call(move |db| {
+            db.execute(
+                "update preproc_cache set content_hash = ?, content_minhash = ?
+                    where cache_key = ? and file_mtime_unix_ms = ? and file_size = ?",
+                rusqlite::params![hash, minhash, digest, mtime, size],
+            )
+        })
+        .await
+        .context("writing to cache")?;
+    Ok(())
+}
+
+/// number and sizes of cache entries
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CacheUsage {
+    pub entries: u64,
+    /// of the compressed outputs, what `cache.max_total_size` limits
+    pub compressed_bytes: u64,
+    pub text_bytes: u64,
+}
+
+impl CacheUsage {
+    fn ratio(&self) -> String {
+        match self.compressed_bytes {
+            0 => "-".to_string(),
+            compressed => format!("{:.1}", self.text_bytes as f64 / compressed as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntryInfo {
+    /// UTC, `YYYY-MM-DD HH:MM:SS`
+    pub created: String,
+    pub adapter: String,
+    pub file_path: String,
+}
+
+/// `--rga-cache-stats`
+#[derive(Debug)]
+pub struct CacheStats {
+    pub db_path: PathBuf,
+    /// of the database with its write-ahead log
+    pub disk_bytes: u64,
+    pub total: CacheUsage,
+    pub by_adapter: BTreeMap<String, CacheUsage>,
+    pub oldest: Option<CacheEntryInfo>,
+    pub newest: Option<CacheEntryInfo>,
+    pub max_total_size: u64,
+    pub eviction: Eviction,
+    pub max_blob_len: u64,
+}
+
+/// fails if the local cache was written by a newer rga, checked by rga before rg runs rga-preproc,
+/// which can only continue without the cache
+pub async fn check_version(config: &RgaConfig) -> Result<()> {
+    if config.cache.disabled || !matches!(config.cache.cache_type.as_str(), "sqlite" | "") {
+        return Ok(());
+    }
+    existing_cache(config).await.map(drop)
+}
+
+/// the local sqlite cache for maintenance, `None` if it does not exist yet
+async fn existing_cache(config: &RgaConfig) -> Result<Option<SqliteCache>> {
+    if !matches!(config.cache.cache_type.as_str(), "sqlite" | "") {
+        anyhow::bail!("Cache type {} cannot be inspected", config.cache.cache_type);
+    }
+    let path = Path::new(&config.cache.path.0);
+    if !path.join("cache.sqlite3").exists() {
+        return Ok(None);
+    }
+    Ok(Some(SqliteCache::new(path).await?))
+}
+
+/// statistics of the local sqlite cache, `None` if it does not exist yet
+pub async fn stats(config: &RgaConfig) -> Result<Option<CacheStats>> {
+    let Some(cache) = existing_cache(config).await? else {
+        return Ok(None);
+    };
+    let path = Path::new(&config.cache.path.0);
+    let db_path = path.join("cache.sqlite3");
+    let disk_bytes = ["", "-wal", "-shm"]
+        .iter()
+        .filter_map(|suffix| std::fs::metadata(path.join(format!("cache.sqlite3{suffix}"))).ok())
+        .map(|m| m.len())
+        .sum();
+    let (by_adapter, oldest, newest) = cache
+        .db
+        .call(|db| {
+            let mut by_adapter = BTreeMap::new();
+            let mut stmt = db.prepare(
+                "select adapter, count(*), sum(length(text_content)), coalesce(sum(text_len), 0)
+                    from preproc_cache group by adapter",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                by_adapter.insert(
+                    row.get::<_, String>(0)?,
+                    CacheUsage {
+                        entries: row.get::<_, i64>(1)? as u64,
+                        compressed_bytes: row.get::<_, i64>(2)? as u64,
+                        text_bytes: row.get::<_, i64>(3)? as u64,
+                    },
+                );
+            }
+            let entry = |order: &str| {
+                db.query_row(
+                    &format!(
+                        "select datetime(created_unix_ms / 1000, 'unixepoch'), adapter, file_path
+                            from preproc_cache order by created_unix_ms {order} limit 1"
+                    ),
+                    [],
+                    |r| {
+                        Ok(CacheEntryInfo {
+                            created: r.get(0)?,
+                            adapter: r.get(1)?,
+                            file_path: r.get(2)?,
+                        })
+                    },
+                )
+                .optional()
+            };
+            Ok::<_, rusqlite::Error>((by_adapter, entry("asc")?, entry("desc")?))
+        })
+        .await
+        .context("reading from cache")?;
+    let mut total = CacheUsage::default();
+    for usage in by_adapter.values() {
+        total.entries += usage.entries;
+        total.compressed_bytes += usage.compressed_bytes;
+        total.text_bytes += usage.text_bytes;
+    }
+    Ok(Some(CacheStats {
+        db_path,
+        disk_bytes,
+        total,
+        by_adapter,
+        oldest,
+        newest,
+        max_total_size: config.cache.max_total_size.0,
+        eviction: Eviction::from_config(config)?,
+        max_blob_len: config.cache.max_blob_len.0 as u64,
+    }))
+}
+
+/// number and sizes of the entries per namespace, the largest first. `None` for the entries without
+/// one, `None` altogether if the cache does not exist
+pub async fn namespaces(config: &RgaConfig) -> Result<Option<Vec<(Option<String>, CacheUsage)>>> {
+    let Some(cache) = existing_cache(config).await? else {
+        return Ok(None);
+    };
+    let namespaces = cache
+        .db
+        .call(|db| {
+            let mut stmt = db.prepare(
+                "select namespace, count(*), sum(length(text_content)), coalesce(sum(text_len), 0)
+                    from preproc_cache group by namespace order by 3 desc, 1",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    CacheUsage {
+                        entries: row.get::<_, i64>(1)? as u64,
+                        compressed_bytes: row.get::<_, i64>(2)? as u64,
+                        text_bytes: row.get::<_, i64>(3)? as u64,
+                    },
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .await
+        .context("reading from cache")?;
+    Ok(Some(namespaces))
+}
+
+/// which entries `--rga-cache-clear=...` removes and `--rga-cache-export-filter` exports
+#[derive(Debug, Clone, Copy)]
+pub enum ClearFilter<'a> {
+    /// the outputs of an adapter
+    Adapter(&'a str),
+    /// of the files whose absolute path matches a glob, sqlite's `glob` where `*` also matches `/`
+    Glob(&'a str),
+    /// in a namespace, see [`namespace_of`]
+    Namespace(&'a str),
+}
+
+impl ClearFilter<'_> {
+    /// the sql condition and its parameter
+    fn condition(self) -> (&'static str, String) {
+        match self {
+            ClearFilter::Adapter(adapter) => ("adapter = ?", adapter.to_string()),
+            ClearFilter::Glob(glob) => ("file_path glob ?", glob.to_string()),
+            ClearFilter::Namespace(namespace) => ("namespace = ?", namespace.to_string()),
+        }
+    }
+}
+
+/// removes the entries matching `filter` from the local sqlite cache and returns how many there
+/// were, `None` if the cache does not exist
+pub async fn clear(config: &RgaConfig, filter: ClearFilter<'_>) -> Result<Option<usize>> {
+    let Some(cache) = existing_cache(config).await? else {
+        return Ok(None);
+    };
+    let (condition, value) = filter.condition();
+    let removed = cache
+        .db
+        .call(move |db| db.execute(&format!("delete from preproc_cache where {condition}"), [value]))
+        .await
+        .context("clearing cache")?;
+    Ok(Some(removed))
+}
+
+/// Copies the entries matching `filter` (all without one) to a new sqlite database at `to`, which
+/// [`import`] reads on another machine. Returns how many there were, `None` if the cache does not
+/// exist.
+pub async fn export(
+    config: &RgaConfig,
+    to: &Path,
+    filter: Option<ClearFilter<'_>>,
+) -> Result<Option<usize>> {
+    if to.exists() {
+        anyhow::bail!("{} already exists", to.display());
+    }
+    let Some(cache) = existing_cache(config).await? else {
+        return Ok(None);
+    };
+    let (condition, values) = match filter.map(ClearFilter::condition) {
+        Some((condition, value)) => (condition, vec![value]),
+        None => ("true", vec![]),
+    };
+    let to = to.to_string_lossy().into_owned();
+    let exported = cache
+        .db
+        .call(move |db| {
+            db.execute("attach database ? as export", [&to])?;
+            let copied = (|| {
+                db.pragma_update(Some("export"), "application_id", APPLICATION_ID)?;
+                db.pragma_update(Some("export"), "user_version", SCHEMA_VERSION)?;
+                db.execute(
+                    &format!(
+                        "create table export.preproc_cache as
+                            select {EXPORTED_COLUMNS} from main.preproc_cache where {condition}"
+                    ),
+                    rusqlite::params_from_iter(&values),
+                )?;
+                db.query_row("select count(*) from export.preproc_cache", [], |r| {
+                    r.get::<_, i64>(0)
+                })
+            })();
+            db.execute("detach database export", [])?;
+            copied
+        })
+        .await
+        .context("exporting cache")?;
+    Ok(Some(exported as usize))
+}
+
+/// Adds the entries of a cache exported with [`export`] to the local sqlite cache, where it has no
+/// newer ones, and returns how many were added. The entries are found by the same keys as on the
+/// machine they were exported from: for the files at the same path with the same mtime, or with
+/// `cache.key` "content-hash" for the files with the same content anywhere.
+pub async fn import(config: &RgaConfig, from: &Path) -> Result<usize> {
+    if !matches!(config.cache.cache_type.as_str(), "sqlite" | "") {
+        anyhow::bail!("Cache type {} cannot be imported into", config.cache.cache_type);
+    }
+    if !from.is_file() {
+        anyhow::bail!("{} does not exist", from.display());
+    }
+    let path = Path::new(&config.cache.path.0);
+    std::fs::create_dir_all(path)?;
+    let cache = SqliteCache::new(path).await?;
+    let limit = match config.cache.max_total_size.0 {
+        0 => None,
+        max_total_size => Some((max_total_size, Eviction::from_config(config)?)),
+    };
+    let from_name = from.display().to_string();
+    let from = from.to_string_lossy().into_owned();
+    let imported = cache
+        .db
+        .call(move |db| {
+            db.execute("attach database ? as import", [&from])?;
+            let copied = (|| -> rusqlite::Result<Result<usize>> {
+                let pragma = |name: &str| db.pragma_query_value(Some("import"), name, |r| r.get::<_, i64>(0));
+                if pragma("application_id")? != APPLICATION_ID {
+                    return Ok(Err(anyhow::anyhow!("{from_name} is not an exported rga cache")));
+                }
+                let version = pragma("user_version")?;
+                if version != SCHEMA_VERSION as i64 {
+                    return Ok(Err(anyhow::anyhow!(
+                        "{from_name} was exported by another version of rga (cache schema {version}, this one uses {SCHEMA_VERSION})"
+                    )));
+                }
+                let updates = EXPORTED_COLUMNS
+                    .split(", ")
+                    .map(|c| c.trim())
+                    .filter(|c| *c != "cache_key")
+                    .map(|c| format!("{c} = excluded.{c}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                // `where true` so the `on` is not taken for a join condition
+                let imported = db.execute(
+                    &format!(
+                        "insert into main.preproc_cache ({EXPORTED_COLUMNS})
+                            select {EXPORTED_COLUMNS} from import.preproc_cache where true
+                            on conflict (cache_key) do update set {updates}
+                            where excluded.created_unix_ms > preproc_cache.created_unix_ms"
+                    ),
+                    [],
+                )?;
+                Ok(Ok(imported))
+            })();
+            db.execute("detach database import", [])?;
+            let imported = copied?;
+            if let (Ok(_), Some((max_total_size, eviction))) = (&imported, limit) {
+                evict(db, max_total_size, eviction, "")?;
+            }
+            Ok::<_, rusqlite::Error>(imported)
+        })
+        .await
+        .context("importing cache")??;
+    Ok(imported)
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use crate::print_bytes;
+        writeln!(f, "cache: {}", self.db_path.display())?;
+        write!(f, "size on disk: {}", print_bytes(self.disk_bytes as f64))?;
+        match self.max_total_size {
+            0 => writeln!(f, " (no limit)")?,
+            max => writeln!(
+                f,
+                " (limit {} of compressed text, {})",
+                print_bytes(max as f64),
+                match self.eviction {
+                    Eviction::Lru => "lru",
+                    Eviction::Lfu => "lfu",
+                }
+            )?,
+        }
+        writeln!(
+            f,
+            "largest entry: {} compressed",
+            print_bytes(self.max_blob_len as f64)
+        )?;
+        writeln!(
+            f,
+            "entries: {}, {} of text compressed to {} (ratio {})",
+            self.total.entries,
+            print_bytes(self.total.text_bytes as f64),
+            print_bytes(self.total.compressed_bytes as f64),
+            self.total.ratio()
+        )?;
+        for (name, entry) in [("oldest", &self.oldest), ("newest", &self.newest)] {
+            if let Some(e) = entry {
+                writeln!(f, "{name}: {} {} ({})", e.created, e.file_path, e.adapter)?;
+            }
+        }
+        if self.by_adapter.is_empty() {
+            return Ok(());
+        }
+        let mut adapters: Vec<_> = self.by_adapter.iter().collect();
+        adapters.sort_by(|a, b| b.1.compressed_bytes.cmp(&a.1.compressed_bytes).then(a.0.cmp(b.0)));
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>8}  {:>10}  {:>10}  {:>5}  adapter",
+            "entries", "compressed", "text", "ratio"
+        )?;
+        for (name, usage) in adapters {
+            writeln!(
+                f,
+                "{:>8}  {:>10}  {:>10}  {:>5}  {name}",
+                usage.entries,
+                print_bytes(usage.compressed_bytes as f64),
+                print_bytes(usage.text_bytes as f64),
+                usage.ratio()
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
 
+    use crate::adapters::postproc::PageFormat;
     use crate::preproc_cache::*;
 
+    /// an output stored uncompressed
+    fn raw(text: impl Into<Vec<u8>>) -> Blob {
+        Blob {
+            codec: Codec::None,
+            data: text.into(),
+            text_len: None,
+        }
+    }
+
     #[tokio::test]
     async fn test_read_write() -> anyhow::Result<()> {
         let path = tempfile::tempdir()?;
@@ -232,4 +1203,415 @@ mod test {
         // db.set();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn output_schema_in_key() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.cache_type = "sqlite".to_string();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        let mut db = open_cache_db(&config).await?;
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap();
+        let zip = enabled.iter().find(|a| a.metadata().name == "zip").unwrap();
+        let key = CacheKey::new(Path::new("a.db"), 1, 10, sqlite.as_ref(), &enabled, &config)?;
+        assert_eq!(key.output_schema, "postprocprefix.v1");
+        let zip_key = CacheKey::new(Path::new("a.zip"), 1, 10, zip.as_ref(), &enabled, &config)?;
+        assert_eq!(zip_key.output_schema, "postprocprefix.v1,postprocpagebreaks.v1");
+
+        db.set(&key, raw(b"old")).await?;
+        assert_eq!(db.get(&key).await?, Some(raw(b"old")));
+        let changed = CacheKey {
+            output_schema: "postprocprefix.v2".to_string(),
+            ..key
+        };
+        assert_eq!(db.get(&changed).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn digest_and_status() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.cache_type = "sqlite".to_string();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap();
+        let key = CacheKey::new(Path::new("./x/../a.db"), 1, 10, sqlite.as_ref(), &enabled, &config)?;
+        assert_eq!(key.file_path, "a.db");
+        assert_eq!(key.digest().len(), 64);
+        assert_eq!(
+            key.digest(),
+            CacheKey::new(Path::new("a.db"), 2, 20, sqlite.as_ref(), &enabled, &config)?.digest()
+        );
+        let mut other_config = config.clone();
+        other_config.accurate = true;
+        let other = CacheKey::new(Path::new("a.db"), 1, 10, sqlite.as_ref(), &enabled, &other_config)?;
+        assert_ne!(key.digest(), other.digest());
+
+        assert_eq!(entry_status(&config, &key).await?, EntryStatus::Missing);
+        let mut db = open_cache_db(&config).await?;
+        db.set(&key, raw(b"old")).await?;
+        assert_eq!(entry_status(&config, &key).await?, EntryStatus::Fresh);
+        // the file changed: same slot, but not served anymore
+        let changed = CacheKey {
+            file_size: 11,
+            ..key.clone()
+        };
+        assert_eq!(db.get(&changed).await?, None);
+        assert_eq!(
+            entry_status(&config, &changed).await?,
+            EntryStatus::Stale {
+                file_mtime_unix_ms: 1,
+                file_size: 10
+            }
+        );
+        db.set(&changed, raw(b"new")).await?;
+        assert_eq!(db.get(&changed).await?, Some(raw(b"new")));
+        assert_eq!(db.get(&key).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn newer_schema() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        drop(open_cache_db(&config).await?);
+        rusqlite::Connection::open(path.path().join("cache.sqlite3"))?
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)?;
+        let err = open_cache_db(&config).await.err().unwrap();
+        assert!(err.to_string().contains("written by a newer version of rga"), "{err}");
+        Ok(())
+    }
+
+    /// rg starts one rga-preproc per file and thread, all opening the database at once
+    #[tokio::test]
+    async fn concurrent_access() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap().clone();
+        let tasks = (0..16u8)
+            .map(|i| {
+                let (config, enabled, sqlite) = (config.clone(), enabled.clone(), sqlite.clone());
+                tokio::spawn(async move {
+                    let mut db = open_cache_db(&config).await?;
+                    let file = format!("{i}.db");
+                    let key = CacheKey::new(Path::new(&file), 1, 1, sqlite.as_ref(), &enabled, &config)?;
+                    db.set(&key, raw(vec![i; 1000])).await?;
+                    anyhow::Ok(db.get(&key).await? == Some(raw(vec![i; 1000])))
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            assert!(task.await??);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn eviction() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap();
+        for (policy, evicted) in [("lru", "b.db"), ("lfu", "c.db")] {
+            let path = tempfile::tempdir()?;
+            let mut config = RgaConfig::default();
+            config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+            config.cache.max_total_size = crate::config::CacheMaxTotalSize(3500);
+            config.cache.eviction = policy.to_string();
+            let mut db = open_cache_db(&config).await?;
+            let key = |file: &str| CacheKey::new(Path::new(file), 1, 1, sqlite.as_ref(), &enabled, &config);
+            // a and b are read twice, b before a, and c is written last but never read
+            db.set(&key("a.db")?, raw(vec![0; 1000])).await?;
+            db.set(&key("b.db")?, raw(vec![0; 1000])).await?;
+            for file in ["b.db", "b.db", "a.db", "a.db", "c.db"] {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                if file == "c.db" {
+                    db.set(&key(file)?, raw(vec![0; 1000])).await?;
+                } else {
+                    db.get(&key(file)?).await?;
+                }
+            }
+            db.set(&key("d.db")?, raw(vec![0; 1000])).await?;
+            for file in ["a.db", "b.db", "c.db", "d.db"] {
+                assert_eq!(db.get(&key(file)?).await?.is_none(), file == evicted, "{policy} {file}");
+            }
+            // the running total follows replaced and deleted entries
+            db.set(&key("a.db")?, raw(vec![0; 500])).await?;
+            let check = rusqlite::Connection::open(path.path().join("cache.sqlite3"))?;
+            let total = |sql: &str| check.query_row(sql, [], |r| r.get::<_, i64>(0));
+            assert_eq!(total("select total from cache_size")?, 2500);
+            check.execute("delete from preproc_cache where file_path like '%d.db'", [])?;
+            assert_eq!(total("select total from cache_size")?, 1500);
+            assert_eq!(total("select sum(length(text_content)) from preproc_cache")?, 1500);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_stats() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let adapter = |name: &str| enabled.iter().find(|a| a.metadata().name == name).unwrap().clone();
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        assert!(stats(&config).await?.is_none());
+        let mut db = open_cache_db(&config).await?;
+        let text = zstd::encode_all(&[b'x'; 1000][..], 3)?;
+        for (file, name) in [("a.db", "sqlite"), ("b.db", "sqlite"), ("c.zip", "zip")] {
+            let key = CacheKey::new(Path::new(file), 1, 1, adapter(name).as_ref(), &enabled, &config)?;
+            db.set(&key, Blob { codec: Codec::Zstd, data: text.clone(), text_len: None }).await?;
+        }
+        let stats = stats(&config).await?.unwrap();
+        let usage = |entries: u64| CacheUsage {
+            entries,
+            compressed_bytes: entries * text.len() as u64,
+            text_bytes: entries * 1000,
+        };
+        assert_eq!(stats.total, usage(3));
+        assert_eq!(
+            stats.by_adapter,
+            BTreeMap::from([("sqlite".to_string(), usage(2)), ("zip".to_string(), usage(1))])
+        );
+        assert!(stats.oldest.unwrap().file_path.ends_with("a.db"));
+        assert!(stats.disk_bytes > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn content_hash_keys() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let zip = enabled.iter().find(|a| a.metadata().name == "zip").unwrap();
+        let dir = tempfile::tempdir()?;
+        let (a, b, c) = (dir.path().join("a.zip"), dir.path().join("b.zip"), dir.path().join("c.zip"));
+        std::fs::write(&a, "same content")?;
+        std::fs::write(&b, "same content")?;
+        std::fs::write(&c, "same size!!!")?;
+        let mut config = RgaConfig::default();
+        let key = |path: &Path, mtime, config: &RgaConfig| CacheKey::new(path, mtime, 12, zip.as_ref(), &enabled, config);
+        assert_ne!(key(&a, 1, &config)?.digest(), key(&b, 2, &config)?.digest());
+        config.cache.key = "content-hash".to_string();
+        assert_eq!(key(&a, 1, &config)?.digest(), key(&b, 2, &config)?.digest());
+        assert_eq!(key(&a, 1, &config)?.file_mtime_unix_ms, 0);
+        assert_ne!(key(&a, 1, &config)?.digest(), key(&c, 1, &config)?.digest());
+        // only the first bytes are hashed
+        config.cache.content_hash_len = crate::config::CacheContentHashLen(4);
+        assert_eq!(key(&a, 1, &config)?.digest(), key(&c, 1, &config)?.digest());
+        config.cache.key = "ctime".to_string();
+        assert!(key(&a, 1, &config).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clear_selected() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let adapter = |name: &str| enabled.iter().find(|a| a.metadata().name == name).unwrap().clone();
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        assert_eq!(clear(&config, ClearFilter::Glob("*")).await?, None);
+        let mut db = open_cache_db(&config).await?;
+        let files = [("/docs/a.db", "sqlite"), ("/docs/b.zip", "zip"), ("/mail/c.zip", "zip"), ("/mail/d.db", "sqlite")];
+        let key = |(file, name): (&str, &str)| CacheKey::new(Path::new(file), 1, 1, adapter(name).as_ref(), &enabled, &config);
+        for file in files {
+            db.set(&key(file)?, raw(vec![1])).await?;
+        }
+        assert_eq!(clear(&config, ClearFilter::Adapter("zip")).await?, Some(2));
+        assert_eq!(clear(&config, ClearFilter::Glob("/docs/*")).await?, Some(1));
+        for (file, kept) in files.into_iter().zip([false, false, false, true]) {
+            assert_eq!(db.get(&key(file)?).await?.is_some(), kept, "{}", file.0);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn namespaces_by_repository() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap().clone();
+        let dir = tempfile::tempdir()?;
+        let project = dir.path().join("project");
+        std::fs::create_dir_all(project.join(".git"))?;
+        std::fs::create_dir_all(project.join("docs"))?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(dir.path().join("cache").to_string_lossy().to_string());
+        let key = |file: &Path, config: &RgaConfig| CacheKey::new(file, 1, 1, sqlite.as_ref(), &enabled, config);
+        let mut db = open_cache_db(&config).await?;
+        db.set(&key(&project.join("docs/a.db"), &config)?, raw(vec![1])).await?;
+        db.set(&key(&dir.path().join("b.db"), &config)?, raw(vec![1, 2])).await?;
+        let mut named = config.clone();
+        named.cache.namespace = "thesis".to_string();
+        let mut db = open_cache_db(&named).await?;
+        db.set(&key(&project.join("c.db"), &named)?, raw(vec![1, 2, 3])).await?;
+
+        let project_name = project.to_string_lossy().to_string();
+        let listed: Vec<_> = namespaces(&config)
+            .await?
+            .unwrap()
+            .into_iter()
+            .map(|(name, usage)| (name, usage.entries, usage.compressed_bytes))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (Some("thesis".to_string()), 1, 3),
+                (None, 1, 2),
+                (Some(project_name.clone()), 1, 1)
+            ]
+        );
+        assert_eq!(clear(&config, ClearFilter::Namespace(&project_name)).await?, Some(1));
+        assert_eq!(namespaces(&config).await?.unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_import() -> anyhow::Result<()> {
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let adapter = |name: &str| enabled.iter().find(|a| a.metadata().name == name).unwrap().clone();
+        let dir = tempfile::tempdir()?;
+        let config_at = |name: &str| {
+            let mut config = RgaConfig::default();
+            config.cache.path = crate::config::CachePath(dir.path().join(name).to_string_lossy().to_string());
+            config
+        };
+        let (here, there) = (config_at("here"), config_at("there"));
+        let files = [("/docs/a.db", "sqlite"), ("/docs/b.zip", "zip"), ("/mail/c.db", "sqlite")];
+        let key = |(file, name): (&str, &str)| CacheKey::new(Path::new(file), 1, 1, adapter(name).as_ref(), &enabled, &here);
+        let mut db = open_cache_db(&here).await?;
+        for file in files {
+            db.set(&key(file)?, raw(file.0)).await?;
+        }
+        let exported = dir.path().join("docs.rga-cache");
+        assert_eq!(export(&here, &exported, Some(ClearFilter::Glob("/docs/*"))).await?, Some(2));
+        assert!(export(&here, &exported, None).await.is_err());
+        assert_eq!(import(&there, &exported).await?, 2);
+        let db = open_cache_db(&there).await?;
+        for (file, imported) in files.into_iter().zip([true, true, false]) {
+            let text = db.get(&key(file)?).await?.map(|b| b.decode()).transpose()?;
+            assert_eq!(text, imported.then(|| file.0.as_bytes().to_vec()), "{}", file.0);
+        }
+        // nothing newer to add the second time
+        assert_eq!(import(&there, &exported).await?, 0);
+        std::fs::write(dir.path().join("empty.db"), "")?;
+        let e = import(&there, &dir.path().join("empty.db")).await.unwrap_err();
+        assert!(e.to_string().ends_with("is not an exported rga cache"), "{e}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn codecs() -> anyhow::Result<()> {
+        use tokio::io::AsyncReadExt;
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap();
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        let text: Vec<u8> = (0..2000).flat_map(|i| format!("row {i}\n").into_bytes()).collect();
+        for codec in ["zstd", "lz4", "none"] {
+            config.cache.compression = codec.to_string();
+            let codec = Codec::from_config(&config)?;
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let mut out = vec![];
+            crate::caching_writer::async_read_and_write_to_cache(
+                &text[..],
+                1_000_000,
+                codec,
+                3,
+                Box::new(move |(_, blob)| {
+                    Box::pin(async move {
+                        tx.send(blob).ok();
+                        Ok(())
+                    })
+                }),
+            )?
+            .read_to_end(&mut out)
+            .await?;
+            assert_eq!(out, text);
+            let blob = rx.await?.unwrap();
+            assert_eq!(blob.codec, codec);
+            assert_eq!(blob.data.len() < text.len(), codec != Codec::None, "{codec:?}");
+
+            let mut db = open_cache_db(&config).await?;
+            let key = CacheKey::new(Path::new(&format!("{codec:?}.db")), 1, 1, sqlite.as_ref(), &enabled, &config)?;
+            db.set(&key, blob).await?;
+            let mut out = vec![];
+            db.get(&key).await?.unwrap().into_reader()?.read_to_end(&mut out).await?;
+            assert_eq!(out, text);
+        }
+        // entries keep the codec they were written with
+        assert_eq!(stats(&config).await?.unwrap().total.text_bytes, 3 * text.len() as u64);
+        config.cache.compression = "brotli".to_string();
+        assert!(Codec::from_config(&config).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ttl_and_adapter_versions() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        config.cache.ttl = BTreeMap::from([("sqlite".to_string(), "1h".parse()?)]);
+        assert_eq!(config.cache.ttl["sqlite"], CacheTtl(3600));
+        assert_eq!(CacheTtl(86400 * 30).to_string(), "30d");
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let adapter = |name: &str| enabled.iter().find(|a| a.metadata().name == name).unwrap().clone();
+        let key = |file: &str, name: &str| CacheKey::new(Path::new(file), 1, 1, adapter(name).as_ref(), &enabled, &config);
+        let mut db = open_cache_db(&config).await?;
+        for (file, name) in [("a.db", "sqlite"), ("b.db", "sqlite"), ("c.zip", "zip")] {
+            db.set(&key(file, name)?, raw(vec![1])).await?;
+        }
+        // two hours pass
+        let backdate = rusqlite::Connection::open(path.path().join("cache.sqlite3"))?;
+        backdate.execute("update preproc_cache set created_unix_ms = created_unix_ms - 7200000", [])?;
+        assert_eq!(db.get(&key("a.db", "sqlite")?).await?, None);
+        assert_eq!(entry_status(&config, &key("a.db", "sqlite")?).await?, EntryStatus::Expired);
+        // zip has no ttl
+        assert_eq!(db.get(&key("c.zip", "zip")?).await?, Some(raw(vec![1])));
+
+        // a new version of sqlite replaces all entries of the old one
+        let upgraded = CacheKey {
+            adapter_version: key("a.db", "sqlite")?.adapter_version + 1,
+            ..key("a.db", "sqlite")?
+        };
+        db.set(&upgraded, raw(vec![2])).await?;
+        assert_eq!(db.get(&upgraded).await?, Some(raw(vec![2])));
+        let adapters: Vec<(String, i64)> = backdate
+            .prepare("select adapter, adapter_version from preproc_cache order by adapter")?
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(
+            adapters,
+            [("sqlite".to_string(), upgraded.adapter_version as i64), ("zip".to_string(), key("c.zip", "zip")?.adapter_version as i64)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stored_fingerprint() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(path.path().to_string_lossy().to_string());
+        let (enabled, _) = crate::adapters::get_all_adapters(None);
+        let sqlite = enabled.iter().find(|a| a.metadata().name == "sqlite").unwrap();
+        let key = CacheKey::new(Path::new("a.db"), 1, 10, sqlite.as_ref(), &enabled, &config)?;
+        let mut db = open_cache_db(&config).await?;
+        let text = zstd::encode_all(&b"Page 1: Hello, world"[..], 3)?;
+        db.set(&key, Blob { codec: Codec::Zstd, data: text, text_len: None }).await?;
+        // writing an entry doesn't compute it
+        assert_eq!(fingerprint(&config, &key).await?, None);
+        let hello = Fingerprint::of(b"hello world", &PageFormat::default()).unwrap();
+        store_fingerprint(&config, &key, &hello).await?;
+        assert_eq!(fingerprint(&config, &key).await?, Some(hello.clone()));
+        // nor is it kept when the entry is replaced
+        db.set(&key, Blob { codec: Codec::None, data: b"other".to_vec(), text_len: None }).await?;
+        assert_eq!(fingerprint(&config, &key).await?, None);
+        store_fingerprint(&config, &key, &hello).await?;
+        let changed = CacheKey {
+            file_size: 11,
+            ..key
+        };
+        assert_eq!(fingerprint(&config, &changed).await?, None);
+        Ok(())
+    }
 }
@@ -1,6 +1,19 @@
+//! Every file an adapter reads is on a branch from the searched file: the containers it is in
+//! (archives, mails, disk images, compressed files, every adapter that produces subfiles) and the
+//! conversions between them (a PDF to its text, ...). `--rga-max-archive-recursion` limits the
+//! containers, whatever their type, and a branch may have at most [`MAX_CONVERSIONS`] conversions.
+//! A container that is found inside itself (zip quines, a mail attached to itself) is not read again.
+//! Containers are told apart by a hash of their start, and only if that is the same as the one of a container
+//! they are in by their size and the hash of all of their content, so e.g. a backup in a backup that starts with
+//! the same files is still read.
+use std::fmt;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
 use tokio_util::io::{ReaderStream, StreamReader};
 
-use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*, to_io_err};
+use crate::config::RgaConfig;
+use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*, robust, to_io_err};
 use async_stream::stream;
 
 pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
@@ -15,3 +28,208 @@ pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
     };
     Box::pin(StreamReader::new(s))
 }
+
+/// conversions a branch may have
+pub const MAX_CONVERSIONS: i32 = 4;
+/// how much of the start of a container identifies it
+const PROBE_LEN: u64 = 1024 * 1024;
+
+/// what identifies a container, see [`probe`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    /// of the first [`PROBE_LEN`] bytes
+    pub head: blake3::Hash,
+    /// the size and hash of all of it, only computed when a container it is in has the same `head`
+    pub full: Option<(u64, blake3::Hash)>,
+}
+
+#[derive(Debug, Clone)]
+struct Link {
+    adapter: String,
+    path: PathBuf,
+    /// if the adapter is a container
+    probe: Option<Probe>,
+}
+
+/// the adapters a file went through, see [`MAX_CONVERSIONS`]
+#[derive(Debug, Clone, Default)]
+pub struct Branch {
+    links: Vec<Link>,
+}
+
+impl Branch {
+    /// how many containers the files at the end of the branch are in
+    pub fn depth(&self) -> i32 {
+        self.links.iter().filter(|l| l.probe.is_some()).count() as i32
+    }
+
+    pub fn conversions(&self) -> i32 {
+        self.links.iter().filter(|l| l.probe.is_none()).count() as i32
+    }
+
+    /// the branch of the files `adapter` produces from the file at `path`
+    pub fn push(&self, adapter: &str, path: &Path, probe: Option<Probe>) -> Self {
+        let mut links = self.links.clone();
+        links.push(Link {
+            adapter: adapter.to_string(),
+            path: path.to_owned(),
+            probe,
+        });
+        Self { links }
+    }
+
+    /// whether a container on the branch starts like the one with `head`
+    pub fn starts_like(&self, head: &blake3::Hash) -> bool {
+        self.links
+            .iter()
+            .any(|l| l.probe.as_ref().is_some_and(|p| p.head == *head))
+    }
+
+    /// the path of the container on the branch with the same content as the one of `probe`
+    pub fn find(&self, probe: &Probe) -> Option<&Path> {
+        probe.full?;
+        self.links
+            .iter()
+            .find(|l| l.probe.as_ref() == Some(probe))
+            .map(|l| l.path.as_path())
+    }
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, link) in self.links.iter().enumerate() {
+            if i > 0 {
+                write!(f, " > ")?;
+            }
+            write!(f, "{} ({})", link.path.display(), link.adapter)?;
+        }
+        Ok(())
+    }
+}
+
+/// the deepest container level that is read, --rga-max-archive-recursion or less with --rga-robust
+pub fn max_depth(config: &RgaConfig) -> i32 {
+    if config.robust {
+        config.max_archive_recursion.0.min(robust::MAX_ARCHIVE_RECURSION)
+    } else {
+        config.max_archive_recursion.0
+    }
+}
+
+/// the most adapters a branch can have
+pub fn max_branch_len(config: &RgaConfig) -> usize {
+    (config.max_archive_recursion.0.max(0) + 1 + MAX_CONVERSIONS) as usize
+}
+
+/// hashes the start of `inp`, and all of it if a container on `branch` starts the same. Returns the probe
+/// and `inp` as it was
+pub async fn probe(mut inp: ReadBox, branch: &Branch) -> std::io::Result<(Probe, ReadBox)> {
+    let mut head = vec![];
+    (&mut inp).take(PROBE_LEN).read_to_end(&mut head).await?;
+    let hash = blake3::hash(&head);
+    let inp: ReadBox = Box::pin(Cursor::new(head).chain(inp));
+    if !branch.starts_like(&hash) {
+        return Ok((Probe { head: hash, full: None }, inp));
+    }
+    let (full, inp) = hash_all(inp).await?;
+    Ok((Probe { head: hash, full: Some(full) }, inp))
+}
+
+/// the size and hash of all of `inp`, which is copied to an anonymous temporary file to be read again
+async fn hash_all(mut inp: ReadBox) -> std::io::Result<((u64, blake3::Hash), ReadBox)> {
+    let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+    let mut hasher = blake3::Hasher::new();
+    let mut size = 0;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = inp.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n]).await?;
+        size += n as u64;
+    }
+    file.flush().await?;
+    file.seek(SeekFrom::Start(0)).await?;
+    Ok(((size, hasher.finalize()), Box::pin(BufReader::new(file))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::tar::TarAdapter;
+    use crate::preproc::{loop_adapt, loop_adapt_branch};
+    use crate::test_utils::*;
+    use anyhow::Result;
+    use pretty_assertions::assert_eq;
+
+    async fn tar(members: &[(&str, &[u8])]) -> Result<Vec<u8>> {
+        let mut builder = tokio_tar::Builder::new(vec![]);
+        for (name, data) in members {
+            let mut header = tokio_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).await?;
+        }
+        Ok(builder.into_inner().await?)
+    }
+
+    #[tokio::test]
+    async fn containers_and_cycles() -> Result<()> {
+        let gz = std::fs::read(test_data_dir().join("hello.gz"))?;
+        let inner = tar(&[("a.txt", b"text"), ("hello.gz", &gz)]).await?;
+        let outer = tar(&[("inner.tar", &inner)]).await?;
+        let adapters = crate::adapters::get_all_adapters(None).0;
+        let read = |data: Vec<u8>, max: i32, branch: Branch| {
+            let adapters = adapters.clone();
+            async move {
+                let (mut a, d) = simple_adapt_info(Path::new("outer.tar"), Box::pin(Cursor::new(data)));
+                a.config.max_archive_recursion.0 = max;
+                let r = loop_adapt_branch(&TarAdapter, d, a, adapters, branch).await?;
+                anyhow::Ok(String::from_utf8(adapted_to_vec(r).await?)?)
+            }
+        };
+        // the text in inner.tar is read, only the containers count
+        assert_eq!(
+            read(outer.clone(), 1, Branch::default()).await?,
+            "PREFIX:inner.tar: [rga: max archive recursion reached (1)]\n"
+        );
+        assert_eq!(
+            read(outer.clone(), 2, Branch::default()).await?,
+            "PREFIX:inner.tar: a.txt: text\n\
+             PREFIX:inner.tar: hello.gz: [rga: max archive recursion reached (2)]\n"
+        );
+        let (a, d) = simple_adapt_info(Path::new("outer.tar"), Box::pin(Cursor::new(outer.clone())));
+        let all = String::from_utf8(adapted_to_vec(loop_adapt(&TarAdapter, d, a, adapters.clone()).await?).await?)?;
+        assert!(all.contains("hello.gz: hello"), "{all}");
+
+        // the tar is found in something of another type that it is in
+        let (start, _) = probe(Box::pin(Cursor::new(outer.clone())), &Branch::default()).await?;
+        assert_eq!(start.full, None);
+        let branch = Branch::default().push("mail", Path::new("a.eml"), Some(start));
+        let (same, _) = probe(Box::pin(Cursor::new(outer.clone())), &branch).await?;
+        assert_eq!(same.full, Some((outer.len() as u64, blake3::hash(&outer))));
+        let branch = Branch::default().push("mail", Path::new("a.eml"), Some(same));
+        assert_eq!(branch.depth(), 1);
+        assert_eq!(
+            read(outer, 5, branch).await?,
+            "PREFIX:[rga: outer.tar is a.eml again, not reading it twice]\n"
+        );
+
+        // a backup in a backup that starts with the same member is read, and it is passed on as it was
+        let zeros = vec![0; PROBE_LEN as usize];
+        let old = tar(&[("zeros", &zeros), ("inner.tar", &inner)]).await?;
+        let new = tar(&[("zeros", &zeros), ("hello.gz", &gz)]).await?;
+        let (old, _) = probe(Box::pin(Cursor::new(old)), &Branch::default()).await?;
+        let branch = Branch::default().push("tar", Path::new("backup.tar"), Some(old.clone()));
+        let (other, mut inp) = probe(Box::pin(Cursor::new(new.clone())), &branch).await?;
+        assert_eq!(other.head, old.head);
+        assert_eq!(branch.find(&other), None);
+        let mut read_back = vec![];
+        inp.read_to_end(&mut read_back).await?;
+        assert_eq!(read_back, new);
+        assert!(read(new, 5, branch).await?.contains("hello.gz: hello"));
+        Ok(())
+    }
+}
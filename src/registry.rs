@@ -0,0 +1,367 @@
+//! Installing shared adapter definitions from a registry (`--rga-adapter-install=NAME[@VERSION]`).
+//!
+//! A registry is an `index.json` served over http(s) or read from a local directory:
+//!
+//! ```json
+//! {"adapters": [
+//!   {"name": "djvu", "version": 2, "custom_adapter": {"name": "djvu", "binary": "djvutxt", ...}},
+//!   {"name": "foo", "version": 1, "wasm_adapter": {"name": "foo", "module": "foo-1.wasm", ...},
+//!    "module_sha256": "..."}
+//! ]}
+//! ```
+//!
+//! The chosen definition is added to (or replaces the same name in) `custom_adapters` / `wasm_adapters`
+//! of the user config. Wasm modules are resolved relative to the index, checked against
+//! `module_sha256` and stored next to the config file.
+//!
+//! Adapters run programs, and the index is not signed. So an adapter is only installed with the sha256
+//! of its whole entry given by the user (`NAME@VERSION#SHA256`, as the registry publishes it), which also
+//! covers the `module_sha256` of wasm modules, and registries are only fetched over https.
+use crate::adapters::custom::CustomAdapterConfig;
+use crate::adapters::wasm::WasmAdapterConfig;
+use crate::config::{RgaConfig, config_file_path};
+use anyhow::{Context, Result, format_err};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[derive(Debug, Deserialize)]
+struct Index {
+    adapters: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct IndexEntry {
+    name: String,
+    version: i32,
+    custom_adapter: Option<CustomAdapterConfig>,
+    wasm_adapter: Option<WasmAdapterConfig>,
+    module_sha256: Option<String>,
+}
+
+/// download `url` (http(s) via curl, `file://` or a plain path)
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    if url.starts_with("http://") {
+        return Err(format_err!("Refusing to fetch {url} over plain http, use https"));
+    }
+    if url.starts_with("https://") {
+        let out = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location", "--proto", "=https", url])
+            .output()
+            .map_err(|e| {
+                crate::adapters::custom::map_exe_error(
+                    e,
+                    "curl",
+                    "curl is needed to fetch adapters.",
+                )
+            })?;
+        if !out.status.success() {
+            return Err(format_err!(
+                "fetching {url} failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ));
+        }
+        return Ok(out.stdout);
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    std::fs::read(path).with_context(|| format!("reading {path}"))
+}
+
+/// `relative` resolved against the location of the index
+fn resolve(index_url: &str, relative: &str) -> String {
+    if relative.contains("://") || Path::new(relative).is_absolute() {
+        return relative.to_string();
+    }
+    match index_url.rfind('/') {
+        Some(i) => format!("{}/{}", &index_url[..i], relative),
+        None => relative.to_string(),
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// the entry as json with sorted keys, so its digest doesn't depend on how the index is formatted
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(obj) => {
+            let sorted: std::collections::BTreeMap<_, _> =
+                obj.iter().map(|(k, v)| (k.clone(), canonical(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(values) => Value::Array(values.iter().map(canonical).collect()),
+        value => value.clone(),
+    }
+}
+
+fn entry_sha256(entry: &Value) -> String {
+    sha256_hex(canonical(entry).to_string().as_bytes())
+}
+
+/// adapter names end up in file names, so only the characters allowed for adapter names
+fn is_adapter_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// the entry for `spec` (`name` for the newest version or `name@version`, both followed by `#sha256`)
+fn select(index: &Index, spec: &str) -> Result<IndexEntry> {
+    let (spec, pinned) = match spec.split_once('#') {
+        Some((spec, sha256)) => (spec, Some(sha256)),
+        None => (spec, None),
+    };
+    let (name, version) = match spec.split_once('@') {
+        Some((name, v)) => (
+            name,
+            Some(
+                v.parse::<i32>()
+                    .with_context(|| format!("invalid version {v:?}"))?,
+            ),
+        ),
+        None => (spec, None),
+    };
+    let (raw, entry) = index
+        .adapters
+        .iter()
+        .filter_map(|raw| Some((raw, serde_json::from_value::<IndexEntry>(raw.clone()).ok()?)))
+        .filter(|(_, e)| e.name == name && version.is_none_or(|v| e.version == v))
+        .max_by_key(|(_, e)| e.version)
+        .ok_or_else(|| match version {
+            Some(v) => format_err!("adapter {name} version {v} not found in registry"),
+            None => format_err!("adapter {name} not found in registry"),
+        })?;
+    if !is_adapter_name(&entry.name) {
+        return Err(format_err!(
+            "invalid adapter name {:?} in registry, must only include a-z, 0-9, _",
+            entry.name
+        ));
+    }
+    let actual = entry_sha256(raw);
+    match pinned {
+        Some(pinned) if pinned.eq_ignore_ascii_case(&actual) => Ok(entry),
+        Some(pinned) => Err(format_err!(
+            "the registry entry of {}@{} has the sha256 {actual}, not {pinned}",
+            entry.name,
+            entry.version
+        )),
+        None => Err(format_err!(
+            "adapters run programs, so they are only installed with the sha256 of their registry entry. \
+             Review the entry and install it with --rga-adapter-install={}@{}#{actual} if you trust it:\n{}",
+            entry.name,
+            entry.version,
+            serde_json::to_string_pretty(raw)?
+        )),
+    }
+}
+
+/// replace the entry named `name` in the array `key` of `config`, or append it
+fn upsert(config: &mut Value, key: &str, name: &str, entry: Value) -> Result<()> {
+    let obj = config
+        .as_object_mut()
+        .context("config file is not a json object")?;
+    let list = obj.entry(key).or_insert_with(|| Value::Array(vec![]));
+    if list.is_null() {
+        *list = Value::Array(vec![]);
+    }
+    let list = list
+        .as_array_mut()
+        .with_context(|| format!("{key} in config file is not an array"))?;
+    list.retain(|e| e.get("name").and_then(Value::as_str) != Some(name));
+    list.push(entry);
+    Ok(())
+}
+
+/// install the adapter `spec` into the config file at `config_path`. Returns a description of what was done
+fn install_into(index_url: &str, spec: &str, config_path: &Path) -> Result<String> {
+    let index: Index = serde_json::from_slice(&fetch(index_url)?)
+        .with_context(|| format!("parsing registry index {index_url}"))?;
+    let entry = select(&index, spec)?;
+
+    let mut config: Value = if config_path.exists() {
+        let mut s = String::new();
+        json_comments::StripComments::new(std::fs::read(config_path)?.as_slice())
+            .read_to_string(&mut s)?;
+        serde_json::from_str(&s).context("parsing config file")?
+    } else {
+        Value::Object(Default::default())
+    };
+
+    match (&entry.custom_adapter, &entry.wasm_adapter) {
+        (Some(custom), None) => {
+            let mut custom = custom.clone();
+            custom.name = entry.name.clone();
+            custom.version = entry.version;
+            upsert(
+                &mut config,
+                "custom_adapters",
+                &entry.name,
+                serde_json::to_value(custom)?,
+            )?;
+        }
+        (None, Some(wasm)) => {
+            let expected = entry
+                .module_sha256
+                .as_deref()
+                .context("registry entry has no module_sha256")?;
+            let module = fetch(&resolve(index_url, &wasm.module))?;
+            let actual = sha256_hex(&module);
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(format_err!(
+                    "checksum mismatch for {}: expected {expected}, got {actual}",
+                    wasm.module
+                ));
+            }
+            let dir = config_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join("adapters");
+            std::fs::create_dir_all(&dir)?;
+            let module_path = dir.join(format!("{}-{}.wasm", entry.name, entry.version));
+            std::fs::write(&module_path, module)?;
+            let mut wasm = wasm.clone();
+            wasm.name = entry.name.clone();
+            wasm.version = entry.version;
+            wasm.module = module_path.to_string_lossy().into_owned();
+            upsert(
+                &mut config,
+                "wasm_adapters",
+                &entry.name,
+                serde_json::to_value(wasm)?,
+            )?;
+        }
+        _ => {
+            return Err(format_err!(
+                "registry entry {} must have exactly one of custom_adapter and wasm_adapter",
+                entry.name
+            ));
+        }
+    }
+
+    // comments can't be preserved, keep the original around
+    let backup = if config_path.exists() {
+        let backup = backup_path(config_path);
+        std::fs::copy(config_path, &backup)?;
+        format!(", the previous one is in {}", backup.display())
+    } else {
+        String::new()
+    };
+    std::fs::write(config_path, serde_json::to_string_pretty(&config)? + "\n")?;
+    Ok(format!(
+        "installed adapter {} version {} into {}{backup}",
+        entry.name,
+        entry.version,
+        config_path.display()
+    ))
+}
+
+/// `<config>.bak`, or `<config>.bak.N` for the first N that is free, so the backup of the file as the user
+/// wrote it (with its comments) is never overwritten by a later install
+fn backup_path(config_path: &Path) -> PathBuf {
+    let with_suffix = |suffix: &str| {
+        let mut path = config_path.as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    let mut backup = with_suffix(".bak");
+    for n in 1.. {
+        if !backup.exists() {
+            break;
+        }
+        backup = with_suffix(&format!(".bak.{n}"));
+    }
+    backup
+}
+
+/// `--rga-adapter-install`
+pub fn install_adapter(spec: &str, config: &RgaConfig) -> Result<()> {
+    let index_url = config.adapter_registry.as_deref().context(
+        "no adapter registry configured. Set adapter_registry in the config file or pass --rga-adapter-registry=URL",
+    )?;
+    let config_path = config_file_path(config.config_file_path.as_deref())?;
+    println!("{}", install_into(index_url, spec, &config_path)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn untrusted_registries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let entry = serde_json::json!({"name": "../../bin/evil", "version": 1, "module_sha256": "00",
+            "wasm_adapter": {"name": "x", "description": "", "version": 1, "extensions": ["x"],
+            "module": "x.wasm", "args": []}});
+        let index_path = dir.path().join("index.json");
+        std::fs::write(&index_path, serde_json::json!({"adapters": [entry]}).to_string())?;
+        let spec = format!("../../bin/evil#{}", entry_sha256(&entry));
+        let err = install_into(&index_path.to_string_lossy(), &spec, &dir.path().join("config.jsonc"));
+        assert!(err.unwrap_err().to_string().contains("invalid adapter name"));
+        assert!(fetch("http://example.com/index.json").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn install() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let module = b"\0asm\x01\0\0\0";
+        std::fs::write(dir.path().join("foo-1.wasm"), module)?;
+        let index = serde_json::json!({"adapters": [
+            {"name": "djvu", "version": 1, "custom_adapter": {"name": "djvu", "description": "old", "version": 1,
+                "extensions": ["djvu"], "binary": "djvutxt", "args": []}},
+            {"name": "djvu", "version": 2, "custom_adapter": {"name": "djvu", "description": "new", "version": 2,
+                "extensions": ["djvu"], "binary": "djvutxt", "args": ["-", "-"]}},
+            {"name": "foo", "version": 1, "module_sha256": sha256_hex(module), "wasm_adapter": {"name": "foo",
+                "description": "", "version": 1, "extensions": ["foo"], "module": "foo-1.wasm", "args": []}},
+            {"name": "bad", "version": 1, "module_sha256": "00", "wasm_adapter": {"name": "bad",
+                "description": "", "version": 1, "extensions": ["bad"], "module": "foo-1.wasm", "args": []}},
+        ]});
+        let index_path = dir.path().join("index.json");
+        std::fs::write(&index_path, index.to_string())?;
+        let index_url = index_path.to_string_lossy().into_owned();
+        let config_path = dir.path().join("config/config.jsonc");
+        std::fs::create_dir_all(config_path.parent().unwrap())?;
+        std::fs::write(&config_path, "{\n  // mine\n  \"accurate\": true\n}")?;
+
+        let pinned = |i: usize| {
+            let entry = &index["adapters"][i];
+            format!("{}@{}#{}", entry["name"].as_str().unwrap(), entry["version"], entry_sha256(entry))
+        };
+        install_into(&index_url, &pinned(0), &config_path)?;
+        install_into(&index_url, &pinned(1), &config_path)?;
+        install_into(&index_url, &pinned(2), &config_path)?;
+        assert!(install_into(&index_url, &pinned(3), &config_path).is_err());
+        assert!(install_into(&index_url, "djvu@3", &config_path).is_err());
+        // without the sha256 of the entry, or with one of another entry
+        let err = install_into(&index_url, "djvu", &config_path).unwrap_err().to_string();
+        assert!(err.contains(&pinned(1)), "{err}");
+        assert!(install_into(&index_url, &pinned(0).replace("djvu@1", "djvu@2"), &config_path).is_err());
+
+        let config: RgaConfig = serde_json::from_str(&std::fs::read_to_string(&config_path)?)?;
+        assert!(config.accurate);
+        let custom = config.custom_adapters.unwrap();
+        assert_eq!(custom.len(), 1);
+        assert_eq!(
+            (custom[0].version, custom[0].description.as_str()),
+            (2, "new")
+        );
+        let wasm = config.wasm_adapters.unwrap();
+        assert_eq!(wasm.len(), 1);
+        assert_eq!(std::fs::read(&wasm[0].module)?, module);
+        let backup = std::fs::read_to_string(dir.path().join("config/config.jsonc.bak"))?;
+        assert!(backup.contains("// mine"), "{backup}");
+        assert!(dir.path().join("config/config.jsonc.bak.2").exists());
+        assert!(!dir.path().join("config/config.jsonc.bak.3").exists());
+        Ok(())
+    }
+}
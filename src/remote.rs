@@ -0,0 +1,616 @@
+//! Searching files that are not on the local file system (e.g. `sftp://host/path`).
+//!
+//! `sftp://` urls need the `sftp` cargo feature, `s3://bucket/prefix` and `http(s)://` directory listings the
+//! `object-store` one.
+//!
+//! Every transport is a [`Vfs`]. rg can only walk local directories, so remote roots given on the command line are mirrored into
+//! `<cache path>/remote/` first and rg is pointed at the mirror instead.
+//! Only the files an adapter reads are downloaded (see [`Wanted`]), and only if their size or version changed since
+//! the last run, as recorded in a manifest next to the mirror. Files that are not downloaded again keep their
+//! mtime, so the preproc cache keeps hitting. Mirrors that were not searched for `cache.remote_ttl` are deleted.
+#[cfg(feature = "object-store")]
+pub mod http;
+#[cfg(feature = "object-store")]
+pub mod s3;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+
+use crate::config::RgaConfig;
+use crate::vfs::{Vfs, VfsEntry, local_path};
+use anyhow::{Context, Result, format_err};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteUrl {
+    pub scheme: String,
+    /// `[user@]host[:port]`
+    pub authority: String,
+    /// path on the remote, always starting with `/`
+    pub path: String,
+}
+
+static SCHEMES: &[&str] = &["sftp", "s3", "http", "https"];
+
+impl RemoteUrl {
+    /// parse an url like `sftp://user@host:22/some/dir`. Returns None if this is not a remote url we support.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (scheme, rest) = s.split_once("://")?;
+        if !SCHEMES.contains(&scheme) {
+            return None;
+        }
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return None;
+        }
+        Some(Self {
+            scheme: scheme.to_string(),
+            authority: authority.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// local directory (or file, if the url points to a file) the remote content is mirrored to
+    pub fn mirror_root(&self, config: &RgaConfig) -> Result<PathBuf> {
+        let mut root = remote_cache_dir(config)
+            .join(&self.scheme)
+            .join(sanitize(&self.authority));
+        for part in self.path.split('/').filter(|p| !p.is_empty() && *p != ".") {
+            // the mirror, and deleting its stale files, must stay in the cache directory
+            if part == ".." {
+                return Err(format_err!("Refusing remote path {:?} with ..", self.path));
+            }
+            root.push(if part == "~" { "~home" } else { part });
+        }
+        Ok(root)
+    }
+
+    fn manifest_path(&self, config: &RgaConfig) -> PathBuf {
+        remote_cache_dir(config)
+            .join("manifests")
+            .join(format!("{}.json", sanitize(&self.to_string())))
+    }
+
+    /// the url of a path relative to the root, for messages
+    pub fn join(&self, relative: &Path) -> String {
+        if relative.as_os_str().is_empty() {
+            self.to_string()
+        } else {
+            format!("{}/{}", self.to_string().trim_end_matches('/'), relative.display())
+        }
+    }
+}
+
+impl std::fmt::Display for RemoteUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.authority, self.path)
+    }
+}
+
+/// the remote files that are downloaded: the ones with the extension of an adapter, or with `--rga-remote-all`
+/// all of them
+#[derive(Clone, Debug, Default)]
+pub struct Wanted {
+    /// lowercase, without the dot. None for all files
+    extensions: Option<Vec<String>>,
+}
+
+impl Wanted {
+    pub fn all() -> Self {
+        Self { extensions: None }
+    }
+
+    pub fn extensions(extensions: impl IntoIterator<Item = String>) -> Self {
+        let extensions = extensions.into_iter().map(|e| e.to_ascii_lowercase()).collect();
+        Self {
+            extensions: Some(extensions),
+        }
+    }
+
+    /// whether the file at `path` (relative to the root, empty for the root itself) is downloaded
+    pub fn wants(&self, path: &Path) -> bool {
+        let Some(extensions) = &self.extensions else {
+            return true;
+        };
+        // an url that points at a single file was asked for
+        let Some(name) = path.file_name() else {
+            return true;
+        };
+        let name = name.to_string_lossy().to_ascii_lowercase();
+        extensions
+            .iter()
+            .any(|e| name.strip_suffix(e.as_str()).is_some_and(|n| n.ends_with('.')))
+    }
+}
+
+fn remote_cache_dir(config: &RgaConfig) -> PathBuf {
+    Path::new(&config.cache.path.0).join("remote")
+}
+
+fn sanitize(s: &str) -> String {
+    // . and .. are not names
+    if s.chars().all(|c| c == '.') {
+        return "_".repeat(s.len());
+    }
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// the transport for `url`. Transports that can save requests for files that are not `wanted` do
+#[cfg_attr(not(feature = "object-store"), allow(unused_variables))]
+pub fn transport_for(url: &RemoteUrl, wanted: &Wanted) -> Result<Box<dyn Vfs>> {
+    match url.scheme.as_str() {
+        #[cfg(feature = "sftp")]
+        "sftp" => Ok(Box::new(sftp::SftpTransport::new(url)?)),
+        #[cfg(not(feature = "sftp"))]
+        "sftp" => Err(format_err!(
+            "rga was built without the sftp feature, which is needed to search sftp:// urls"
+        )),
+        #[cfg(feature = "object-store")]
+        "s3" => Ok(Box::new(s3::S3Transport::new(url))),
+        #[cfg(feature = "object-store")]
+        "http" | "https" => Ok(Box::new(http::HttpTransport::new(url, wanted.clone()))),
+        #[cfg(not(feature = "object-store"))]
+        "s3" | "http" | "https" => Err(format_err!(
+            "rga was built without the object-store feature, which is needed to search {}:// urls",
+            url.scheme
+        )),
+        other => Err(format_err!("Unsupported remote scheme: {}", other)),
+    }
+}
+
+/// the manifest key of a path relative to the root: the path itself, or if it is not UTF-8 a NUL (which
+/// can't be part of a name) followed by the hex of its bytes
+fn manifest_key(path: &Path) -> String {
+    match path.to_str() {
+        Some(s) => s.to_string(),
+        None => std::iter::once("\0".to_string())
+            .chain(path.as_os_str().as_encoded_bytes().iter().map(|b| format!("{b:02x}")))
+            .collect(),
+    }
+}
+
+/// the path of a [`manifest_key`]
+#[cfg(unix)]
+fn manifest_key_path(key: &str) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    let Some(hex) = key.strip_prefix('\0') else {
+        return Some(PathBuf::from(key));
+    };
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some(std::ffi::OsStr::from_bytes(&bytes).into())
+}
+
+#[cfg(not(unix))]
+fn manifest_key_path(key: &str) -> Option<PathBuf> {
+    (!key.starts_with('\0')).then(|| PathBuf::from(key))
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest {
+    /// the url of the root, to find the mirror when it expires
+    #[serde(default)]
+    url: String,
+    /// files that were fully downloaded, by [`manifest_key`]
+    entries: BTreeMap<String, ManifestEntry>,
+    /// files whose download was started. used to decide whether a partial file can be resumed
+    pending: BTreeMap<String, ManifestEntry>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct ManifestEntry {
+    size: u64,
+    version: String,
+}
+impl From<&VfsEntry> for ManifestEntry {
+    fn from(e: &VfsEntry) -> Self {
+        Self {
+            size: e.size,
+            version: e.version.clone(),
+        }
+    }
+}
+
+async fn read_manifest(path: &Path) -> Manifest {
+    match tokio::fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            warn!("ignoring broken remote manifest {}: {}", path.display(), e);
+            Manifest::default()
+        }),
+        Err(_) => Manifest::default(),
+    }
+}
+
+/// how many downloads the manifest is written after while mirroring, besides at the end and on errors.
+/// Writing it after every file would be quadratic in the number of files
+const MANIFEST_FLUSH_INTERVAL: usize = 100;
+
+async fn write_manifest(path: &Path, manifest: &Manifest) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec(manifest)?)
+        .await
+        .with_context(|| format!("writing remote manifest {}", path.display()))
+}
+
+/// file name used while a download is in progress. hidden so rg does not search it
+fn partial_path(local: &Path) -> PathBuf {
+    let name = local
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    local.with_file_name(format!(".{name}.rga-part"))
+}
+
+/// whether the local copy of `entry` needs to be (re-)downloaded
+fn is_outdated(manifest: &Manifest, entry: &VfsEntry, local_len: Option<u64>) -> bool {
+    manifest.entries.get(&manifest_key(&entry.path)) != Some(&ManifestEntry::from(entry))
+        || local_len != Some(entry.size)
+}
+
+/// byte offset to resume a partial download at, 0 if it has to start from scratch
+fn resume_offset(manifest: &Manifest, entry: &VfsEntry, partial_len: Option<u64>) -> u64 {
+    match partial_len {
+        Some(len)
+            if len < entry.size
+                && manifest.pending.get(&manifest_key(&entry.path))
+                    == Some(&ManifestEntry::from(entry)) =>
+        {
+            len
+        }
+        _ => 0,
+    }
+}
+
+async fn file_len(path: &Path) -> Option<u64> {
+    tokio::fs::metadata(path).await.ok().map(|m| m.len())
+}
+
+async fn fetch_entry(
+    transport: &dyn Vfs,
+    manifest: &mut Manifest,
+    entry: &VfsEntry,
+    local: &Path,
+) -> Result<()> {
+    if let Some(parent) = local.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let partial = partial_path(local);
+    let offset = if transport.supports_ranges() {
+        resume_offset(manifest, entry, file_len(&partial).await)
+    } else {
+        0
+    };
+    let key = manifest_key(&entry.path);
+    manifest.pending.insert(key.clone(), ManifestEntry::from(entry));
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(offset > 0)
+        .truncate(offset == 0)
+        .open(&partial)
+        .await
+        .with_context(|| format!("creating {}", partial.display()))?;
+    if offset > 0 {
+        debug!("resuming download of {} at byte {}", entry.path.display(), offset);
+    }
+    transport.read_range(entry, offset, &mut file).await?;
+    file.flush().await?;
+    let file = file.into_std().await;
+    // objects without one (s3, http) keep the time of the download, which changes whenever they do
+    if let Some(mtime) = entry.mtime_unix {
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64))
+            .with_context(|| format!("setting mtime of {}", partial.display()))?;
+    }
+    let len = file.metadata()?.len();
+    drop(file);
+    if len != entry.size {
+        return Err(format_err!(
+            "download of {} is incomplete ({} of {} bytes)",
+            entry.path.display(),
+            len,
+            entry.size
+        ));
+    }
+    tokio::fs::rename(&partial, local).await?;
+    manifest.pending.remove(&key);
+    manifest.entries.insert(key, ManifestEntry::from(entry));
+    Ok(())
+}
+
+/// bring the local mirror of the `wanted` files of `url` up to date and return its path
+pub async fn mirror(url: &RemoteUrl, config: &RgaConfig, wanted: &Wanted) -> Result<PathBuf> {
+    let transport = transport_for(url, wanted)?;
+    let root = url.mirror_root(config)?;
+    let manifest_path = url.manifest_path(config);
+    let mut manifest = read_manifest(&manifest_path).await;
+    manifest.url = url.to_string();
+    let mut listing = transport
+        .list()
+        .await
+        .with_context(|| format!("listing {url}"))?;
+    let len = listing.len();
+    listing.retain(|e| wanted.wants(&e.path));
+    info!("{}: {} remote files, {} of them wanted", url, len, listing.len());
+    let mut fetched = 0;
+    for entry in &listing {
+        let local = local_path(&root, &entry.path)?;
+        if !is_outdated(&manifest, entry, file_len(&local).await) {
+            continue;
+        }
+        debug!("fetching {} ({} bytes)", url.join(&entry.path), entry.size);
+        let fetch = fetch_entry(transport.as_ref(), &mut manifest, entry, &local)
+            .await
+            .with_context(|| format!("fetching {}", url.join(&entry.path)));
+        if let Err(e) = fetch {
+            // keep the files downloaded so far and the pending one, the next search resumes it
+            write_manifest(&manifest_path, &manifest).await?;
+            return Err(e);
+        }
+        fetched += 1;
+        if fetched % MANIFEST_FLUSH_INTERVAL == 0 {
+            write_manifest(&manifest_path, &manifest).await?;
+        }
+    }
+    // drop local copies of files that no longer exist on the remote
+    let listed: HashSet<String> = listing.iter().map(|e| manifest_key(&e.path)).collect();
+    let stale: Vec<String> = manifest
+        .entries
+        .keys()
+        .filter(|key| !listed.contains(*key))
+        .cloned()
+        .collect();
+    for key in stale {
+        if let Some(local) = manifest_key_path(&key).and_then(|p| local_path(&root, &p).ok()) {
+            let _ = tokio::fs::remove_file(local).await;
+        }
+        manifest.entries.remove(&key);
+    }
+    write_manifest(&manifest_path, &manifest).await?;
+    info!("{}: downloaded {} changed files", url, fetched);
+    Ok(root)
+}
+
+/// how long a mirror is kept after it was last searched without `cache.remote_ttl`
+const DEFAULT_MIRROR_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// deletes the mirrors whose manifest was not written (i.e. that were not searched) for `cache.remote_ttl`,
+/// except the ones of `keep`. Returns how many were deleted
+pub async fn prune_mirrors(config: &RgaConfig, keep: &[RemoteUrl]) -> Result<usize> {
+    let ttl = config
+        .cache
+        .remote_ttl
+        .map_or(DEFAULT_MIRROR_TTL, |ttl| Duration::from_secs(ttl.0));
+    let manifests = remote_cache_dir(config).join("manifests");
+    let Ok(mut dir) = tokio::fs::read_dir(&manifests).await else {
+        return Ok(0);
+    };
+    let mut pruned = 0;
+    while let Some(file) = dir.next_entry().await? {
+        let unused = file
+            .metadata()
+            .await
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+        if unused.is_none_or(|unused| unused <= ttl) {
+            continue;
+        }
+        let manifest = read_manifest(&file.path()).await;
+        let url = RemoteUrl::parse(&manifest.url);
+        if url.as_ref().is_some_and(|url| keep.contains(url)) {
+            continue;
+        }
+        if let Some(url) = url {
+            let root = url.mirror_root(config)?;
+            debug!("deleting the mirror of {} at {}, not searched for {:?}", url, root.display(), unused);
+            let removed = match tokio::fs::metadata(&root).await {
+                Ok(m) if m.is_dir() => tokio::fs::remove_dir_all(&root).await,
+                Ok(_) => tokio::fs::remove_file(&root).await,
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = removed {
+                warn!("could not delete the mirror {}: {}", root.display(), e);
+                continue;
+            }
+        }
+        tokio::fs::remove_file(file.path()).await?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+/// replace every path operand (see [`crate::roots::path_operands`]) that is a remote url with the path of
+/// its up-to-date local mirror. The pattern and the values of `-e`/`-f` are never touched, even if they look like urls
+pub async fn mirror_remote_args(
+    mut args: Vec<OsString>,
+    config: &RgaConfig,
+    wanted: &Wanted,
+) -> Result<Vec<OsString>> {
+    let mut urls = vec![];
+    for i in crate::roots::path_operands(&args) {
+        if let Some(url) = args[i].to_str().and_then(RemoteUrl::parse) {
+            args[i] = mirror(&url, config, wanted).await?.into_os_string();
+            urls.push(url);
+        }
+    }
+    match prune_mirrors(config, &urls).await {
+        Ok(0) => {}
+        Ok(pruned) => info!("deleted {} mirrors of remote roots that were not searched for a while", pruned),
+        Err(e) => warn!("could not delete old mirrors of remote roots: {:#}", e),
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn entry(path: &str, size: u64, version: &str) -> VfsEntry {
+        VfsEntry {
+            path: path.into(),
+            size,
+            mtime_unix: None,
+            version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            RemoteUrl::parse("sftp://me@example.com:2222/srv/docs"),
+            Some(RemoteUrl {
+                scheme: "sftp".to_string(),
+                authority: "me@example.com:2222".to_string(),
+                path: "/srv/docs".to_string(),
+            })
+        );
+        assert_eq!(RemoteUrl::parse("sftp://host").unwrap().path, "/");
+        assert_eq!(RemoteUrl::parse("sftp:///foo"), None);
+        assert_eq!(RemoteUrl::parse("s3://bucket").unwrap().authority, "bucket");
+        assert_eq!(RemoteUrl::parse("ftp://host/foo"), None);
+        assert_eq!(RemoteUrl::parse("./foo"), None);
+    }
+
+    #[test]
+    fn mirror_root() -> Result<()> {
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath("/cache".to_string());
+        let url = RemoteUrl::parse("sftp://me@host:22/~/docs/").unwrap();
+        assert_eq!(
+            url.mirror_root(&config)?,
+            PathBuf::from("/cache/remote/sftp/me_host_22/~home/docs")
+        );
+        assert_eq!(url.join(Path::new("a/b.pdf")), "sftp://me@host:22/~/docs/a/b.pdf");
+        let url = RemoteUrl::parse("sftp://host/./docs").unwrap();
+        assert_eq!(url.mirror_root(&config)?, PathBuf::from("/cache/remote/sftp/host/docs"));
+        assert!(RemoteUrl::parse("sftp://host/docs/../../../../home").unwrap().mirror_root(&config).is_err());
+        let url = RemoteUrl::parse("sftp://../docs").unwrap();
+        assert_eq!(url.mirror_root(&config)?, PathBuf::from("/cache/remote/sftp/__/docs"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        assert!(local_path(Path::new("/m"), Path::new("../etc/passwd")).is_err());
+        assert!(local_path(Path::new("/m"), Path::new("/etc/passwd")).is_err());
+        assert_eq!(
+            local_path(Path::new("/m"), Path::new("a/b")).unwrap(),
+            PathBuf::from("/m/a/b")
+        );
+        assert_eq!(
+            local_path(Path::new("/m/f.pdf"), Path::new("")).unwrap(),
+            PathBuf::from("/m/f.pdf")
+        );
+    }
+
+    #[tokio::test]
+    async fn url_patterns_are_not_mirrored() -> Result<()> {
+        let config = RgaConfig::default();
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        for args in [
+            args(&["https://example.com", "./docs"]),
+            args(&["-e", "https://example.com", "./docs"]),
+            args(&["--regexp=sftp://host/x", "-i"]),
+            args(&["-f", "s3://bucket/patterns"]),
+        ] {
+            assert_eq!(mirror_remote_args(args.clone(), &config, &Wanted::all()).await?, args);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prunes_unused_mirrors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(dir.path().to_string_lossy().into_owned());
+        let mirrored = |url: &str, age_days: u64| -> Result<PathBuf> {
+            let url = RemoteUrl::parse(url).unwrap();
+            let root = url.mirror_root(&config)?;
+            std::fs::create_dir_all(&root)?;
+            std::fs::write(root.join("a.pdf"), "")?;
+            let manifest = url.manifest_path(&config);
+            std::fs::create_dir_all(manifest.parent().unwrap())?;
+            std::fs::write(&manifest, serde_json::to_vec(&Manifest { url: url.to_string(), ..Default::default() })?)?;
+            let modified = std::time::SystemTime::now() - Duration::from_secs(age_days * 24 * 3600);
+            std::fs::File::options().write(true).open(&manifest)?.set_modified(modified)?;
+            Ok(root)
+        };
+        let old = mirrored("sftp://host/old", 40)?;
+        let recent = mirrored("sftp://host/recent", 10)?;
+        let searched = mirrored("sftp://host/searched", 40)?;
+        let keep = [RemoteUrl::parse("sftp://host/searched").unwrap()];
+        assert_eq!(prune_mirrors(&config, &keep).await?, 1);
+        assert!(!old.exists());
+        assert!(recent.exists() && searched.exists());
+        config.cache.remote_ttl = Some(crate::config::CacheTtl(7 * 24 * 3600));
+        assert_eq!(prune_mirrors(&config, &[]).await?, 2);
+        assert!(!recent.exists() && !searched.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn wanted_files() {
+        let wanted = Wanted::extensions(["pdf".to_string(), "tar.gz".to_string()]);
+        let wants = |path: &str| wanted.wants(Path::new(path));
+        assert!(wants("a/Report.PDF"));
+        assert!(wants("backup.tar.gz"));
+        assert!(wants(""));
+        assert!(!wants("notes.txt"));
+        assert!(!wants("pdf"));
+        assert!(!wants("pdf/readme"));
+        assert!(Wanted::all().wants(Path::new("notes.txt")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn manifest_keys() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"dir/caf\xe9.pdf"));
+        let key = manifest_key(&path);
+        assert_eq!(key, "\u{0}6469722f636166e92e706466");
+        assert_eq!(manifest_key_path(&key), Some(path));
+        assert_eq!(manifest_key(Path::new("a/b.pdf")), "a/b.pdf");
+        assert_eq!(manifest_key_path("a/b.pdf"), Some(PathBuf::from("a/b.pdf")));
+    }
+
+    #[test]
+    fn outdated_and_resume() {
+        let mut manifest = Manifest::default();
+        let e = entry("a.pdf", 10, "v1");
+        assert!(is_outdated(&manifest, &e, None));
+        manifest.entries.insert("a.pdf".to_string(), (&e).into());
+        assert!(!is_outdated(&manifest, &e, Some(10)));
+        assert!(is_outdated(&manifest, &e, Some(5)));
+        assert!(is_outdated(&manifest, &entry("a.pdf", 10, "v2"), Some(10)));
+
+        assert_eq!(resume_offset(&manifest, &e, Some(4)), 0);
+        manifest.pending.insert("a.pdf".to_string(), (&e).into());
+        assert_eq!(resume_offset(&manifest, &e, Some(4)), 4);
+        assert_eq!(
+            resume_offset(&manifest, &entry("a.pdf", 10, "v2"), Some(4)),
+            0
+        );
+        assert_eq!(resume_offset(&manifest, &e, None), 0);
+    }
+}
@@ -0,0 +1,224 @@
+//! http(s):// transport. Walks autoindex style directory listings (nginx, apache, `python -m http.server`, ...).
+//!
+//! An url ending in `/` is treated as a directory listing and every link below it is followed,
+//! any other url as a single file. The size and ETag of the files that are downloaded are asked for a few at a time.
+use super::{RemoteUrl, Wanted};
+use crate::vfs::{Vfs, VfsEntry};
+use anyhow::{Context, Result, format_err};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::*;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use regex::Regex;
+use reqwest::{Client, StatusCode, Url, header};
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinSet;
+
+lazy_static! {
+    // query-only links like `?C=N;O=D` (apache sort links) and fragments are not matched
+    static ref HREF: Regex = Regex::new(r#"(?i)href\s*=\s*["']([^"'#?]+)"#).unwrap();
+}
+
+/// characters that have to be escaped in a path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// HEAD requests sent at the same time
+const PARALLEL_HEADS: usize = 8;
+
+pub struct HttpTransport {
+    client: Client,
+    url: RemoteUrl,
+    /// the files that are listed, the others are not asked for their size
+    wanted: Wanted,
+}
+
+impl HttpTransport {
+    pub fn new(url: &RemoteUrl, wanted: Wanted) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.clone(),
+            wanted,
+        }
+    }
+
+    fn root(&self) -> Result<Url> {
+        Url::parse(&self.url.to_string()).with_context(|| format!("invalid url {}", self.url))
+    }
+}
+
+/// size and version of a single file
+async fn head(client: Client, url: Url, path: String) -> Result<VfsEntry> {
+    let resp = client.head(url.clone()).send().await?.error_for_status()?;
+    let get = |h: header::HeaderName| {
+        resp.headers()
+            .get(h)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    let size = get(header::CONTENT_LENGTH)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| format_err!("{url} has no Content-Length"))?;
+    let version = get(header::ETAG)
+        .or_else(|| get(header::LAST_MODIFIED))
+        .unwrap_or_default();
+    Ok(VfsEntry {
+        path: path.into(),
+        size,
+        // the mirror compares the ETag in its manifest instead
+        mtime_unix: None,
+        version,
+    })
+}
+
+/// all link targets in a html page
+fn parse_hrefs(html: &str) -> impl Iterator<Item = &str> {
+    HREF.captures_iter(html)
+        .filter_map(|c| c.get(1))
+        .map(|m| m.as_str())
+}
+
+/// path of `target` relative to the directory `root`, still percent-encoded.
+/// None if `target` is not strictly below `root` (parent links, other hosts, ...)
+fn relative_to(root: &Url, target: &Url) -> Option<String> {
+    if root.origin() != target.origin() {
+        return None;
+    }
+    let rel = target.path().strip_prefix(root.path())?;
+    (!rel.is_empty()).then(|| rel.to_string())
+}
+
+fn decode_path(encoded: &str) -> String {
+    percent_decode_str(encoded).decode_utf8_lossy().into_owned()
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|s| utf8_percent_encode(s, SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[async_trait]
+impl Vfs for HttpTransport {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let root = self.root()?;
+        if !root.path().ends_with('/') {
+            return Ok(vec![head(self.client.clone(), root, String::new()).await?]);
+        }
+        let mut files = vec![];
+        let mut seen = HashSet::new();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            debug!("listing {}", dir);
+            let html = self
+                .client
+                .get(dir.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            for href in parse_hrefs(&html) {
+                let Ok(target) = dir.join(href) else {
+                    continue;
+                };
+                let Some(rel) = relative_to(&root, &target) else {
+                    continue;
+                };
+                if !seen.insert(rel.clone()) {
+                    continue;
+                }
+                if rel.ends_with('/') {
+                    dirs.push(target);
+                } else if self.wanted.wants(Path::new(&decode_path(&rel))) {
+                    files.push((target, decode_path(&rel)));
+                }
+            }
+        }
+        let mut entries = Vec::with_capacity(files.len());
+        let mut files = files.into_iter();
+        let mut heads = JoinSet::new();
+        loop {
+            while heads.len() < PARALLEL_HEADS
+                && let Some((url, path)) = files.next()
+            {
+                heads.spawn(head(self.client.clone(), url, path));
+            }
+            let Some(entry) = heads.join_next().await else {
+                break;
+            };
+            entries.push(entry??);
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let url = self.root()?.join(&encode_path(&entry.path.to_string_lossy()))?;
+        let mut req = self.client.get(url);
+        if offset > 0 {
+            req = req.header(header::RANGE, format!("bytes={offset}-"));
+        }
+        let mut resp = req.send().await?.error_for_status()?;
+        // servers without range support send the whole file, drop what we already have
+        let mut skip = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            0
+        } else {
+            offset as usize
+        };
+        while let Some(chunk) = resp.chunk().await? {
+            let n = skip.min(chunk.len());
+            skip -= n;
+            oup.write_all(&chunk[n..]).await?;
+        }
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn listing_links() -> Result<()> {
+        let root = Url::parse("http://host/pub/")?;
+        let html = r#"<a href="?C=N;O=D">Name</a> <a href="../">Parent</a>
+            <a href="sub/">sub/</a> <a HREF='a%20b.pdf'>a b.pdf</a>
+            <a href="/pub/c.txt">c</a> <a href="http://other/pub/x">x</a>"#;
+        let rels: Vec<String> = parse_hrefs(html)
+            .filter_map(|h| relative_to(&root, &root.join(h).ok()?))
+            .collect();
+        assert_eq!(rels, vec!["sub/", "a%20b.pdf", "c.txt"]);
+        assert_eq!(decode_path("sub/a%20b.pdf"), "sub/a b.pdf");
+        Ok(())
+    }
+
+    #[test]
+    fn encoding_roundtrip() {
+        assert_eq!(encode_path("sub/a b#1.pdf"), "sub/a%20b%231.pdf");
+        assert_eq!(decode_path(&encode_path("ü/100%.txt")), "ü/100%.txt");
+    }
+}
@@ -0,0 +1,188 @@
+//! s3:// transport. Uses the `aws` cli, so credentials, profiles and custom endpoints
+//! (`AWS_PROFILE`, `AWS_ENDPOINT_URL`, ...) are configured the same way as for the cli itself.
+use super::RemoteUrl;
+use crate::adapters::custom::map_exe_error;
+use crate::vfs::{Vfs, VfsEntry};
+use anyhow::{Context, Result, format_err};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio::process::Command;
+
+pub struct S3Transport {
+    bucket: String,
+    /// key prefix without leading slash
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListObjectsOutput {
+    #[serde(default)]
+    contents: Vec<S3Object>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct S3Object {
+    key: String,
+    size: u64,
+    e_tag: String,
+}
+
+impl S3Transport {
+    pub fn new(url: &RemoteUrl) -> Self {
+        Self {
+            bucket: url.authority.clone(),
+            prefix: url.path.trim_start_matches('/').to_string(),
+        }
+    }
+
+    fn aws<I: IntoIterator<Item = String>>(&self, args: I) -> Command {
+        let mut cmd = Command::new("aws");
+        cmd.args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        log::debug!("running {:?}", cmd);
+        cmd
+    }
+
+    /// turn the keys of a listing into paths relative to the prefix.
+    ///
+    /// `s3://bucket/docs` matches `docs` itself and everything below `docs/`, but not `docs2/...`
+    fn relative_entries(&self, listing: ListObjectsOutput) -> Vec<VfsEntry> {
+        let mut entries: Vec<VfsEntry> = listing
+            .contents
+            .into_iter()
+            .filter(|o| !o.key.ends_with('/')) // "folder" placeholder objects
+            .filter_map(|o| {
+                let rel = o.key.strip_prefix(&self.prefix)?;
+                let rel = if self.prefix.is_empty() || self.prefix.ends_with('/') || rel.is_empty()
+                {
+                    rel
+                } else {
+                    rel.strip_prefix('/')?
+                };
+                Some(VfsEntry {
+                    path: rel.into(),
+                    size: o.size,
+                    // the mirror compares the ETag in its manifest instead
+                    mtime_unix: None,
+                    version: o.e_tag,
+                })
+            })
+            .collect();
+        // an object named exactly like the prefix can't be mirrored next to objects below it
+        if entries.len() > 1 {
+            entries.retain(|e| !e.path.as_os_str().is_empty());
+        }
+        entries
+    }
+}
+
+fn spawn_fail(e: std::io::Error) -> anyhow::Error {
+    map_exe_error(
+        e,
+        "aws",
+        "Make sure the AWS cli is installed to search s3:// urls.",
+    )
+}
+
+#[async_trait]
+impl Vfs for S3Transport {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        // the cli follows the continuation tokens itself
+        let out = crate::audit::output(&mut self.aws([
+            "s3api".to_string(),
+            "list-objects-v2".to_string(),
+            "--output=json".to_string(),
+            format!("--bucket={}", self.bucket),
+            format!("--prefix={}", self.prefix),
+        ]))
+        .await
+        .map_err(spawn_fail)?;
+        if !out.status.success() {
+            return Err(format_err!(
+                "listing s3 objects failed: {:?}\n{}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr)
+            ));
+        }
+        // an empty listing prints nothing at all
+        let listing = if out.stdout.iter().all(u8::is_ascii_whitespace) {
+            ListObjectsOutput { contents: vec![] }
+        } else {
+            serde_json::from_slice(&out.stdout).context("parsing list-objects-v2 output")?
+        };
+        Ok(self.relative_entries(listing))
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        _offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        // the paths were made from keys
+        let path = entry.path.to_string_lossy();
+        let key = if path.is_empty() {
+            self.prefix.clone()
+        } else if self.prefix.is_empty() || self.prefix.ends_with('/') {
+            format!("{}{}", self.prefix, path)
+        } else {
+            format!("{}/{}", self.prefix, path)
+        };
+        let (mut child, spawned) = crate::audit::spawn(&mut self.aws([
+            "s3".to_string(),
+            "cp".to_string(),
+            "--only-show-errors".to_string(),
+            format!("s3://{}/{}", self.bucket, key),
+            "-".to_string(),
+        ]))
+        .map_err(spawn_fail)?;
+        let mut stdout = child.stdout.take().context("aws stdout not piped")?;
+        tokio::io::copy(&mut stdout, oup).await?;
+        let status = child.wait().await?;
+        spawned.exited(&status);
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut e) = child.stderr.take() {
+                let _ = e.read_to_string(&mut stderr).await;
+            }
+            return Err(format_err!("aws s3 cp failed: {:?}\n{}", status, stderr));
+        }
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn prefix_matching() -> Result<()> {
+        let listing = r#"{"Contents": [
+            {"Key": "docs", "Size": 1, "ETag": "\"a\"", "LastModified": "2024-01-01T00:00:00+00:00"},
+            {"Key": "docs/", "Size": 0, "ETag": "\"b\""},
+            {"Key": "docs/x/report.pdf", "Size": 3, "ETag": "\"c\""},
+            {"Key": "docs2/other.pdf", "Size": 4, "ETag": "\"d\""}
+        ]}"#;
+        let t = S3Transport::new(&RemoteUrl::parse("s3://bucket/docs").unwrap());
+        let entries = t.relative_entries(serde_json::from_str(listing)?);
+        let paths: Vec<_> = entries.iter().map(|e| e.path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["x/report.pdf"]);
+        assert_eq!(entries[0].version, "\"c\"");
+
+        let t = S3Transport::new(&RemoteUrl::parse("s3://bucket/docs2/other.pdf").unwrap());
+        let entries = t.relative_entries(serde_json::from_str(listing)?);
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].path.as_os_str().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! sftp:// transport. Speaks SFTP to the `sftp` subsystem of the server, started through the system
+//! `ssh` client like the `sftp` command does, so the user's ssh config, agent and known hosts apply and
+//! accounts that are restricted to sftp (`internal-sftp`, chroots) work.
+use super::RemoteUrl;
+use crate::adapters::custom::map_exe_error;
+use crate::audit::Spawned;
+use crate::vfs::{Vfs, VfsEntry};
+use anyhow::{Context, Result, format_err};
+use async_trait::async_trait;
+use log::*;
+use openssh_sftp_client::file::TokioCompatFile;
+use openssh_sftp_client::metadata::MetaData;
+use openssh_sftp_client::{Sftp, SftpOptions};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncSeekExt, AsyncWrite};
+use tokio::process::{Child, Command};
+use tokio::sync::OnceCell;
+use tokio_stream::StreamExt;
+
+pub struct SftpTransport {
+    /// `[user@]host`
+    destination: String,
+    port: Option<String>,
+    url: RemoteUrl,
+    /// started by the first request and shared by all of them
+    session: OnceCell<Session>,
+}
+
+struct Session {
+    sftp: Sftp,
+    _ssh: Child,
+    _spawned: Spawned,
+}
+
+impl SftpTransport {
+    pub fn new(url: &RemoteUrl) -> Result<Self> {
+        // ssh would read it as an option, e.g. -oProxyCommand=...
+        if url.authority.starts_with('-') {
+            return Err(format_err!("Refusing sftp host {:?}, it starts with -", url.authority));
+        }
+        // the port is only split off if it is numeric, so ipv6 literals like [::1] stay intact
+        let (destination, port) = match url.authority.rsplit_once(':') {
+            Some((d, p)) if !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()) => {
+                (d.to_string(), Some(p.to_string()))
+            }
+            _ => (url.authority.clone(), None),
+        };
+        Ok(Self {
+            destination,
+            port,
+            url: url.clone(),
+            session: OnceCell::new(),
+        })
+    }
+
+    fn ssh(&self) -> Command {
+        let mut cmd = Command::new("ssh");
+        // never prompt, rg output would get mixed with the password prompt
+        cmd.arg("-o").arg("BatchMode=yes");
+        if let Some(port) = &self.port {
+            cmd.arg("-p").arg(port);
+        }
+        cmd.arg("-s")
+            .arg(&self.destination)
+            .arg("sftp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // ssh explains itself there when it can't connect
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        debug!("running {:?}", cmd);
+        cmd
+    }
+
+    async fn connect(&self) -> Result<Session> {
+        let (mut ssh, spawned) = crate::audit::spawn(&mut self.ssh()).map_err(spawn_fail)?;
+        let stdin = ssh.stdin.take().context("ssh stdin not piped")?;
+        let stdout = ssh.stdout.take().context("ssh stdout not piped")?;
+        let sftp = Sftp::new(stdin, stdout, SftpOptions::new())
+            .await
+            .with_context(|| format!("starting sftp on {}", self.destination))?;
+        Ok(Session {
+            sftp,
+            _ssh: ssh,
+            _spawned: spawned,
+        })
+    }
+
+    async fn sftp(&self) -> Result<&Sftp> {
+        let session = self.session.get_or_try_init(|| self.connect()).await?;
+        Ok(&session.sftp)
+    }
+
+    /// the path on the server of a path relative to the root. The server resolves relative paths
+    /// against the login directory, which is what a leading `~` stands for
+    fn remote_path(&self, relative: &Path) -> PathBuf {
+        let root = match self.url.path.strip_prefix("/~") {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => PathBuf::from(format!(".{rest}")),
+            _ => PathBuf::from(&self.url.path),
+        };
+        if relative.as_os_str().is_empty() {
+            root
+        } else {
+            root.join(relative)
+        }
+    }
+}
+
+fn spawn_fail(e: std::io::Error) -> anyhow::Error {
+    map_exe_error(
+        e,
+        "ssh",
+        "Make sure an OpenSSH client is installed to search sftp:// urls.",
+    )
+}
+
+fn entry(path: PathBuf, meta: &MetaData) -> VfsEntry {
+    let mtime_unix = meta.modified().map(|t| t.as_duration().as_secs() as i64);
+    VfsEntry {
+        path,
+        size: meta.len().unwrap_or(0),
+        mtime_unix,
+        version: mtime_unix.map(|m| m.to_string()).unwrap_or_default(),
+    }
+}
+
+#[async_trait]
+impl Vfs for SftpTransport {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        let mut fs = self.sftp().await?.fs();
+        let meta = fs
+            .metadata(self.remote_path(Path::new("")))
+            .await
+            .with_context(|| format!("reading {}", self.url))?;
+        if !meta.file_type().is_some_and(|t| t.is_dir()) {
+            return Ok(vec![entry(PathBuf::new(), &meta)]);
+        }
+        let mut out = vec![];
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            // an unreadable directory only loses its own files
+            let children = match fs.open_dir(self.remote_path(&dir)).await {
+                Ok(children) => children.read_dir(),
+                Err(e) => {
+                    warn!("skipping {}: {}", self.url.join(&dir), e);
+                    continue;
+                }
+            };
+            let mut children = std::pin::pin!(children);
+            while let Some(child) = children.next().await {
+                let child = match child {
+                    Ok(child) => child,
+                    Err(e) => {
+                        warn!("skipping the rest of {}: {}", self.url.join(&dir), e);
+                        break;
+                    }
+                };
+                let name = child.filename();
+                if name == Path::new(".") || name == Path::new("..") {
+                    continue;
+                }
+                let path = dir.join(name);
+                // symlinks are not followed, like rg does by default
+                match child.file_type() {
+                    Some(t) if t.is_dir() => dirs.push(path),
+                    Some(t) if t.is_file() => out.push(entry(path, &child.metadata())),
+                    _ => {}
+                }
+            }
+        }
+        out.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(out)
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let file = self
+            .sftp()
+            .await?
+            .open(self.remote_path(&entry.path))
+            .await
+            .with_context(|| format!("opening {}", self.url.join(&entry.path)))?;
+        let mut file = Box::pin(TokioCompatFile::new(file));
+        if offset > 0 {
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+        }
+        tokio::io::copy(&mut file, oup).await?;
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn port_split() -> Result<()> {
+        let t = SftpTransport::new(&RemoteUrl::parse("sftp://me@host:2222/x").unwrap())?;
+        assert_eq!(
+            (t.destination.as_str(), t.port.as_deref()),
+            ("me@host", Some("2222"))
+        );
+        let t = SftpTransport::new(&RemoteUrl::parse("sftp://host/x").unwrap())?;
+        assert_eq!((t.destination.as_str(), t.port), ("host", None));
+        let url = RemoteUrl::parse("sftp://-oProxyCommand=touch%20x/").unwrap();
+        assert!(SftpTransport::new(&url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn remote_paths() -> Result<()> {
+        let path = |url: &str, relative: &str| -> Result<PathBuf> {
+            let t = SftpTransport::new(&RemoteUrl::parse(url).unwrap())?;
+            Ok(t.remote_path(Path::new(relative)))
+        };
+        assert_eq!(path("sftp://host/srv/docs", "a/b.pdf")?, PathBuf::from("/srv/docs/a/b.pdf"));
+        assert_eq!(path("sftp://host/~/docs", "a.pdf")?, PathBuf::from("./docs/a.pdf"));
+        assert_eq!(path("sftp://host/~", "")?, PathBuf::from("."));
+        assert_eq!(path("sftp://host/~x/f.pdf", "")?, PathBuf::from("/~x/f.pdf"));
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! `--rga-robust`: the adapters of every file are run with panic isolation and limits, so that a
+//! malformed file only costs its own output. A panic or error while adapting or reading a file
+//! becomes a warning and the file's output ends there, the other files are still searched.
+use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapters::AdaptInfo;
+use anyhow::{Result, format_err};
+use log::*;
+use std::any::Any;
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{Instant, Sleep};
+use tokio_stream::{Stream, StreamExt};
+
+/// the most text a single file may produce
+pub const MAX_OUTPUT: u64 = 256 * 1024 * 1024;
+/// how long reading the output of a single file may take
+pub const TIMEOUT: Duration = Duration::from_secs(300);
+/// the deepest files in archives are read in
+pub const MAX_ARCHIVE_RECURSION: i32 = 4;
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// a future or stream of results whose panics become errors. It is not polled again after a panic
+pub struct CatchPanic<T> {
+    inner: Option<T>,
+}
+
+impl<T> CatchPanic<T> {
+    pub fn new(inner: T) -> Self {
+        Self { inner: Some(inner) }
+    }
+}
+
+impl<T, F: Future<Output = Result<T>> + Unpin> Future for CatchPanic<F> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Poll::Ready(Err(format_err!("polled after a panic")));
+        };
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                self.inner = None;
+                Poll::Ready(Err(format_err!("panicked: {}", panic_message(&*payload))))
+            }
+        }
+    }
+}
+
+impl<T, S: Stream<Item = Result<T>> + Unpin> Stream for CatchPanic<S> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Poll::Ready(None);
+        };
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll_next(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                self.inner = None;
+                Poll::Ready(Some(Err(format_err!(
+                    "panicked: {}",
+                    panic_message(&*payload)
+                ))))
+            }
+        }
+    }
+}
+
+/// the output of one file. Panics, errors and exceeding the limits end it with a warning instead of failing the search
+pub struct RobustRead<R> {
+    inner: Option<R>,
+    name: String,
+    read: u64,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl<R> RobustRead<R> {
+    pub fn new(inner: R, name: impl Into<String>) -> Self {
+        Self {
+            inner: Some(inner),
+            name: name.into(),
+            read: 0,
+            deadline: Box::pin(tokio::time::sleep_until(Instant::now() + TIMEOUT)),
+        }
+    }
+
+    fn stop(&mut self, reason: std::fmt::Arguments) -> Poll<std::io::Result<()>> {
+        warn!("{}: {reason}, skipping the rest of it", self.name);
+        self.inner = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for RobustRead<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        let Some(inner) = this.inner.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        if this.deadline.as_mut().poll(cx).is_ready() {
+            return this.stop(format_args!("took longer than {}s", TIMEOUT.as_secs()));
+        }
+        let before = buf.filled().len();
+        match catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll_read(cx, buf))) {
+            Ok(Poll::Ready(Ok(()))) => {
+                this.read += (buf.filled().len() - before) as u64;
+                if this.read > MAX_OUTPUT {
+                    return this.stop(format_args!("more than {MAX_OUTPUT} bytes of output"));
+                }
+                Poll::Ready(Ok(()))
+            }
+            Ok(Poll::Ready(Err(e))) => this.stop(format_args!("{e}")),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => this.stop(format_args!("panicked: {}", panic_message(&*payload))),
+        }
+    }
+}
+
+/// `ai` with its output read through a [`RobustRead`]
+pub fn robust_output(ai: AdaptInfo) -> AdaptInfo {
+    let name = format!("{}{}", ai.line_prefix, ai.filepath_hint.display());
+    AdaptInfo {
+        inp: Box::pin(RobustRead::new(ai.inp, name)),
+        ..ai
+    }
+}
+
+/// `files` with panics isolated and the output of each read through a [`RobustRead`]
+pub fn robust_files(files: AdaptedFilesIterBox) -> AdaptedFilesIterBox {
+    Box::pin(CatchPanic::new(files).map(|file| file.map(robust_output)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CacheConfig, RgaConfig};
+    use crate::preproc::rga_preproc;
+    use crate::test_utils::test_data_dir;
+    use tokio::io::AsyncReadExt;
+
+    struct Panicking;
+    impl AsyncRead for Panicking {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            panic!("malformed input")
+        }
+    }
+
+    #[tokio::test]
+    async fn panic_ends_file() -> Result<()> {
+        let mut out = vec![];
+        let read = tokio::io::AsyncReadExt::chain(&b"before "[..], Panicking);
+        RobustRead::new(Box::pin(read), "bad.bin")
+            .read_to_end(&mut out)
+            .await?;
+        assert_eq!(out, b"before ");
+        Ok(())
+    }
+
+    /// xorshift, to mutate the same way on every run
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    /// truncated and corrupted copies of the test files through the whole pipeline
+    #[tokio::test]
+    async fn mutated_inputs() -> Result<()> {
+        let config = RgaConfig {
+            robust: true,
+            accurate: true,
+            cache: CacheConfig {
+                disabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut state = 0x2545f4914f6cdd1d;
+        for entry in std::fs::read_dir(test_data_dir())? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_some_and(|e| e == "pdf") {
+                continue;
+            }
+            let original = std::fs::read(&path)?;
+            for _ in 0..8 {
+                let mut data = original.clone();
+                if data.is_empty() {
+                    break;
+                }
+                for _ in 0..1 + next(&mut state) % 16 {
+                    let at = (next(&mut state) % data.len() as u64) as usize;
+                    data[at] = next(&mut state) as u8;
+                }
+                data.truncate(1 + (next(&mut state) % data.len() as u64) as usize);
+                let ai = AdaptInfo {
+                    filepath_hint: path.clone(),
+                    is_real_file: false,
+                    file_mtime_unix_ms: None,
+                    archive_recursion_depth: 0,
+                    inp: Box::pin(std::io::Cursor::new(data)),
+                    line_prefix: String::new(),
+                    postprocess: true,
+                    config: config.clone(),
+                };
+                let mut out = vec![];
+                rga_preproc(ai).await?.read_to_end(&mut out).await?;
+            }
+        }
+        Ok(())
+    }
+}
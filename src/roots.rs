@@ -0,0 +1,347 @@
+//! Searching many roots at once (`--rga-parallel`, `--rga-files-from`): every root is searched by its own
+//! rg process, a number of them at the same time, and their output is merged in the order the roots were
+//! given. The output of the first unfinished root is passed on as it comes, the others hold back a few
+//! chunks and then wait until it's their turn.
+//!
+//! All rg processes get the same config, so their rga-preproc processes share the cache (and the daemon).
+//!
+//! Lists of files (`--rga-files-from`, `--rga-files0-from`, e.g. the output of fd or find) are searched in
+//! batches, rg gets the files directly and doesn't walk any directories.
+
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+
+/// rg flags after which no pattern is given as a positional argument
+const PATTERN_FLAGS: &[&str] = &["-e", "--regexp", "-f", "--file", "--files", "--type-list"];
+
+/// splits the rg arguments into the roots to search and the rest.
+///
+/// rg's flags are not known here, so the roots are the positional arguments after the pattern that
+/// exist. Flags with values should be given as `--flag=value`, so their values can't be mistaken for roots.
+pub fn split_roots(args: &[OsString]) -> (Vec<OsString>, Vec<OsString>) {
+    let operands = path_operands(args);
+    let (mut rest, mut roots) = (vec![], vec![]);
+    for (i, arg) in args.iter().enumerate() {
+        if operands.contains(&i) && Path::new(arg).exists() {
+            roots.push(arg.clone());
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (rest, roots)
+}
+
+/// the indices of the positional arguments after the pattern, the paths rg is asked to search. Whether
+/// they exist is not checked
+pub fn path_operands(args: &[OsString]) -> Vec<usize> {
+    let mut pattern_given = args.iter().any(|a| {
+        let a = a.to_string_lossy();
+        PATTERN_FLAGS.iter().any(|f| {
+            a == *f || a.starts_with(&format!("{f}=")) || (f.len() == 2 && a.starts_with(f))
+        })
+    });
+    let mut flags_done = false;
+    let mut value_next = false;
+    let mut operands = vec![];
+    for (i, arg) in args.iter().enumerate() {
+        if std::mem::take(&mut value_next) {
+            continue;
+        }
+        if !flags_done && ["-e", "--regexp", "-f", "--file"].iter().any(|f| arg == f) {
+            value_next = true;
+        } else if !flags_done && arg == "--" {
+            flags_done = true;
+        } else if flags_done || !arg.to_string_lossy().starts_with('-') {
+            if pattern_given {
+                operands.push(i);
+            }
+            pattern_given = true;
+        }
+    }
+    operands
+}
+
+/// the paths in a list file (`-` for stdin), separated by `separator`
+pub fn read_list(path: &str, separator: u8) -> Result<Vec<OsString>> {
+    let list = if path == "-" {
+        let mut list = vec![];
+        std::io::stdin()
+            .lock()
+            .read_to_end(&mut list)
+            .context("Could not read the file list from stdin")?;
+        list
+    } else {
+        std::fs::read(path).with_context(|| format!("Could not read the file list {path}"))?
+    };
+    Ok(list
+        .split(|&b| b == separator)
+        .map(|line| match separator {
+            b'\n' => line.strip_suffix(b"\r").unwrap_or(line),
+            _ => line,
+        })
+        .filter(|line| !line.is_empty())
+        .map(os_string)
+        .collect())
+}
+
+/// the most files searched by one rg, so the command line stays short enough on every system
+const MAX_BATCH_LEN: usize = 16 * 1024;
+
+/// the paths each rg searches: a directory on its own, files in batches of up to `max_files` of the ones next
+/// to each other. rg doesn't walk anything to find the files, and a list of thousands doesn't need thousands of rgs
+pub fn batches(paths: Vec<OsString>, max_files: usize) -> Vec<Vec<OsString>> {
+    let mut batches: Vec<Vec<OsString>> = vec![];
+    let mut len = 0;
+    for path in paths {
+        let is_dir = Path::new(&path).is_dir();
+        match batches.last_mut() {
+            Some(batch)
+                if !is_dir
+                    && !Path::new(&batch[0]).is_dir()
+                    && batch.len() < max_files
+                    && len + path.len() < MAX_BATCH_LEN =>
+            {
+                len += path.len();
+                batch.push(path);
+            }
+            _ => {
+                len = path.len();
+                batches.push(vec![path]);
+            }
+        }
+    }
+    batches
+}
+
+#[cfg(unix)]
+fn os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+/// the `--threads` for each rg so the searches together use about one thread per CPU
+pub fn rg_threads(workers: usize) -> usize {
+    let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    (cpus / workers.max(1)).max(1)
+}
+
+/// the chunks of output a search holds back until it's its turn, then its rg waits
+const BUFFERED_CHUNKS: usize = 16;
+
+enum Message {
+    Output(Vec<u8>),
+    Done(std::io::Result<ExitStatus>),
+}
+
+/// the output of rg commands run by a number of workers, in the order of the commands
+pub struct Merged {
+    jobs: VecDeque<Receiver<Message>>,
+    buf: Vec<u8>,
+    pos: usize,
+    statuses: Vec<ExitStatus>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Merged {
+    /// starts running `commands`, `workers` at a time
+    pub fn start(commands: Vec<Command>, workers: usize) -> Self {
+        let (queue, jobs): (VecDeque<_>, VecDeque<_>) = commands
+            .into_iter()
+            .map(|cmd| {
+                let (tx, rx) = sync_channel(BUFFERED_CHUNKS);
+                ((cmd, tx), rx)
+            })
+            .unzip();
+        let workers = workers.clamp(1, queue.len().max(1));
+        let queue = Arc::new(Mutex::new(queue));
+        let stopped = Arc::new(AtomicBool::new(false));
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let stopped = stopped.clone();
+            std::thread::spawn(move || loop {
+                let Some((cmd, tx)) = queue.lock().ok().and_then(|mut q| q.pop_front()) else {
+                    break;
+                };
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                run(cmd, &tx, &stopped);
+            });
+        }
+        Merged {
+            jobs,
+            buf: vec![],
+            pos: 0,
+            statuses: vec![],
+            stopped,
+        }
+    }
+
+    /// the exit code of all the searches together, like rg's: 2 if one failed, 0 if one found something
+    pub fn exit_code(&self) -> i32 {
+        exit_code(self.statuses.iter().map(|s| s.code().unwrap_or(2)))
+    }
+}
+
+fn exit_code(codes: impl Iterator<Item = i32>) -> i32 {
+    codes.fold(1, |all, code| match (all, code) {
+        (0 | 1, 0 | 1) => all.min(code),
+        (0 | 1, code) => code,
+        (all, _) => all,
+    })
+}
+
+/// runs one rg, sends its output until the receiver is gone
+fn run(mut cmd: Command, tx: &SyncSender<Message>, stopped: &AtomicBool) {
+    let mut child = match cmd.stdin(Stdio::null()).stdout(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = tx.send(Message::Done(Err(e)));
+            return;
+        }
+    };
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            match stdout.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(Message::Output(chunk[..n].to_vec())).is_err() {
+                        stopped.store(true, Ordering::Relaxed);
+                        let _ = child.kill();
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    }
+    let _ = tx.send(Message::Done(child.wait()));
+}
+
+impl Read for Merged {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.buf.len() {
+            let Some(job) = self.jobs.front() else {
+                return Ok(0);
+            };
+            match job.recv() {
+                Ok(Message::Output(chunk)) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                Ok(Message::Done(status)) => {
+                    self.jobs.pop_front();
+                    let status = status.map_err(|e| {
+                        std::io::Error::new(e.kind(), format!("Could not run rg: {e}"))
+                    })?;
+                    self.statuses.push(status);
+                }
+                Err(_) => {
+                    self.jobs.pop_front();
+                }
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for Merged {
+    fn drop(&mut self) {
+        // the workers stop when they next send output
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn merged_in_order() -> Result<()> {
+        let sh = |script: &str| {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", script]);
+            cmd
+        };
+        let commands = vec![
+            sh("sleep 0.3; echo a1; echo a2; exit 1"),
+            sh("echo b; exit 0"),
+            sh("sleep 0.1; echo c; exit 1"),
+        ];
+        let mut merged = Merged::start(commands, 3);
+        let mut out = String::new();
+        merged.read_to_string(&mut out)?;
+        assert_eq!(out, "a1\na2\nb\nc\n");
+        assert_eq!(merged.exit_code(), 0);
+        // more output than is held back waits for its turn
+        let commands = vec![sh("sleep 0.1; echo a"), sh("head -c 4000000 /dev/zero")];
+        let mut out = vec![];
+        Merged::start(commands, 2).read_to_end(&mut out)?;
+        assert_eq!(out.len(), 2 + 4000000);
+        assert_eq!(exit_code([1, 1].into_iter()), 1);
+        assert_eq!(exit_code([0, 2, 1].into_iter()), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn file_batches() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::create_dir(path("d"))?;
+        let list = dir.path().join("list");
+        std::fs::write(&list, [path("a"), path("b"), path("d"), path("c")].join("\0".as_ref()))?;
+        let files = read_list(list.to_str().unwrap(), b'\0')?;
+        let batch = |names: &[&str]| names.iter().map(|n| OsString::from(path(n))).collect::<Vec<_>>();
+        assert_eq!(batches(files.clone(), 10), vec![batch(&["a", "b"]), batch(&["d"]), batch(&["c"])]);
+        assert_eq!(batches(files, 1), vec![batch(&["a"]), batch(&["b"]), batch(&["d"]), batch(&["c"])]);
+        std::fs::write(&list, "a b\r\n\nc\n")?;
+        assert_eq!(read_list(list.to_str().unwrap(), b'\n')?, vec![OsString::from("a b"), "c".into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn roots() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a").into_os_string();
+        let b = dir.path().join("b").into_os_string();
+        std::fs::create_dir(&a)?;
+        std::fs::create_dir(&b)?;
+        let args = |args: &[&OsStr]| args.iter().map(|a| a.to_os_string()).collect::<Vec<_>>();
+        let (rest, roots) = split_roots(&args(&[
+            a.as_os_str(),
+            "--glob=*.pdf".as_ref(),
+            b.as_os_str(),
+            "missing".as_ref(),
+        ]));
+        // the first positional argument is the pattern, even if it is a directory
+        assert_eq!(rest, args(&[a.as_os_str(), "--glob=*.pdf".as_ref(), "missing".as_ref()]));
+        assert_eq!(roots, vec![b.clone()]);
+        let (rest, roots) = split_roots(&args(&["-e".as_ref(), "foo".as_ref(), a.as_os_str(), b.as_os_str()]));
+        assert_eq!(rest, args(&["-e".as_ref(), "foo".as_ref()]));
+        assert_eq!(roots, vec![a, b]);
+        // whether they exist doesn't matter for the operands
+        let urls = ["https://example.com", "sftp://host/docs", "--", "-x"].map(OsString::from);
+        assert_eq!(path_operands(&urls), vec![1, 3]);
+        let urls = ["-e", "s3://b/p", "sftp://host/docs"].map(OsString::from);
+        assert_eq!(path_operands(&urls), vec![2]);
+        Ok(())
+    }
+}
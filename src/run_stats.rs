@@ -0,0 +1,231 @@
+//! `--rga-search-stats`: statistics of a search, printed after it. rg runs one
+//! rga-preproc per file, each appends a [`Record`] of its file to the file named by
+//! [`RUN_STATS_ENV`] when it is done, and rga sums them up when rg has exited.
+use crate::{print_bytes, print_duration};
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// the file rga-preproc appends its record to
+pub const RUN_STATS_ENV: &str = "RGA_RUN_STATS";
+
+/// how the output of a file was found
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheUse {
+    Hit,
+    Miss,
+    /// the cache was disabled, or the adapter's output is never cached
+    #[default]
+    Uncached,
+}
+
+/// what rga-preproc did for one file
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Record {
+    /// None if no adapter handled the file
+    pub adapter: Option<String>,
+    pub cache: CacheUse,
+    pub duration_ms: u64,
+    /// compressed, as stored
+    pub cached_bytes: u64,
+}
+
+/// the record of the file this process preprocesses
+static CURRENT: Lazy<Mutex<Record>> = Lazy::new(Default::default);
+
+fn update(f: impl FnOnce(&mut Record)) {
+    if let Ok(mut record) = CURRENT.lock() {
+        f(&mut record);
+    }
+}
+
+/// the adapter chosen for the file itself, not for files nested in it
+pub fn note_adapter(name: &str) {
+    update(|r| {
+        r.adapter.get_or_insert_with(|| name.to_string());
+    });
+}
+
+pub fn note_cache(cache: CacheUse) {
+    update(|r| r.cache = cache);
+}
+
+pub fn note_cached_bytes(bytes: u64) {
+    update(|r| r.cached_bytes += bytes);
+}
+
+/// appends the record of this process to the file of [`RUN_STATS_ENV`], if it is set
+pub fn finish(duration: Duration) -> Result<()> {
+    let Some(path) = std::env::var_os(RUN_STATS_ENV) else {
+        return Ok(());
+    };
+    let mut record = CURRENT.lock().map(|r| r.clone()).unwrap_or_default();
+    record.duration_ms = duration.as_millis() as u64;
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    // one write, so the lines of rga-preproc processes running at the same time do not mix
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(&line)
+        .with_context(|| format!("writing {}", Path::new(&path).display()))
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Group {
+    pub files: u64,
+    pub duration_ms: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub cached_bytes: u64,
+}
+
+impl Group {
+    fn add(&mut self, record: &Record) {
+        self.files += 1;
+        self.duration_ms += record.duration_ms;
+        match record.cache {
+            CacheUse::Hit => self.hits += 1,
+            CacheUse::Miss => self.misses += 1,
+            CacheUse::Uncached => {}
+        }
+        self.cached_bytes += record.cached_bytes;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunStats {
+    pub by_adapter: BTreeMap<String, Group>,
+    pub total: Group,
+    pub wall_time: Duration,
+}
+
+impl RunStats {
+    /// reads the records rga-preproc wrote to `path`
+    pub fn read(path: &Path, wall_time: Duration) -> Result<Self> {
+        let mut stats = RunStats {
+            wall_time,
+            ..Default::default()
+        };
+        let file =
+            std::fs::File::open(path).with_context(|| format!("reading {}", path.display()))?;
+        for line in std::io::BufReader::new(file).lines() {
+            let record: Record = serde_json::from_str(&line?)?;
+            let adapter = record
+                .adapter
+                .clone()
+                .unwrap_or_else(|| crate::stats::NO_ADAPTER.to_string());
+            stats.by_adapter.entry(adapter).or_default().add(&record);
+            stats.total.add(&record);
+        }
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut groups: Vec<_> = self.by_adapter.iter().collect();
+        groups.sort_by(|a, b| b.1.duration_ms.cmp(&a.1.duration_ms).then(a.0.cmp(b.0)));
+        writeln!(
+            f,
+            "{:>8}  {:>10}  {:>6}  {:>6}  {:>10}  adapter",
+            "files", "time", "hits", "misses", "cached"
+        )?;
+        for (name, group) in groups {
+            writeln!(
+                f,
+                "{:>8}  {:>10}  {:>6}  {:>6}  {:>10}  {name}",
+                group.files,
+                print_duration(Duration::from_millis(group.duration_ms)),
+                group.hits,
+                group.misses,
+                print_bytes(group.cached_bytes as f64)
+            )?;
+        }
+        writeln!(f)?;
+        let total = &self.total;
+        write!(
+            f,
+            "{} files preprocessed in {}",
+            total.files,
+            print_duration(self.wall_time)
+        )?;
+        let lookups = total.hits + total.misses;
+        if lookups > 0 {
+            write!(
+                f,
+                ", cache hit ratio {:.0}% ({} of {lookups})",
+                total.hits as f64 * 100.0 / lookups as f64,
+                total.hits
+            )?;
+        }
+        writeln!(
+            f,
+            ", {} written to the cache",
+            print_bytes(total.cached_bytes as f64)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sums() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("run");
+        let records = [
+            Record {
+                adapter: Some("poppler".to_string()),
+                cache: CacheUse::Miss,
+                duration_ms: 1200,
+                cached_bytes: 3000,
+            },
+            Record {
+                adapter: Some("poppler".to_string()),
+                cache: CacheUse::Hit,
+                duration_ms: 20,
+                cached_bytes: 0,
+            },
+            Record {
+                adapter: None,
+                cache: CacheUse::Uncached,
+                duration_ms: 5,
+                cached_bytes: 0,
+            },
+        ];
+        let lines: Vec<String> = records
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n") + "\n")?;
+        let stats = RunStats::read(&path, Duration::from_secs(2))?;
+        assert_eq!(
+            stats.by_adapter["poppler"],
+            Group {
+                files: 2,
+                duration_ms: 1220,
+                hits: 1,
+                misses: 1,
+                cached_bytes: 3000
+            }
+        );
+        assert_eq!(stats.total.files, 3);
+        let summary = stats.to_string();
+        assert!(
+            summary.contains(", cache hit ratio 50% (1 of 2), "),
+            "{summary}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! `--rga-salvage`: archives and compressed files that are truncated or damaged (interrupted
+//! downloads, carved data) are read as far as they can be instead of failing at the first error.
+//! Zip files without a central directory are read from their local headers, damaged zip and tar
+//! headers are skipped by looking for the next valid one, and a truncated member or compressed
+//! file keeps the data before the damage. Every recovery is logged as a warning.
+use log::*;
+use miniz_oxide::inflate::stream::{InflateState, inflate};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+
+/// data that ends with a warning instead of an error where it is damaged
+pub struct SalvageRead<R> {
+    inner: Option<R>,
+    name: String,
+    read: u64,
+}
+
+impl<R> SalvageRead<R> {
+    pub fn new(inner: R, name: impl Into<String>) -> Self {
+        Self {
+            inner: Some(inner),
+            name: name.into(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SalvageRead<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        let Some(inner) = this.inner.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let before = buf.filled().len();
+        match Pin::new(inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(())) => {
+                this.read += (buf.filled().len() - before) as u64;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => {
+                warn!(
+                    "{}: damaged after {} bytes ({e}), salvaged the data before",
+                    this.name, this.read
+                );
+                this.inner = None;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// raw deflate data, inflated. Unlike the decoders of async-compression, which drop what they
+/// inflated in the same read, the data before damage or the end of the input is returned before
+/// the error
+pub struct Inflate<R> {
+    inner: R,
+    state: Box<InflateState>,
+    done: bool,
+}
+
+impl<R> Inflate<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            state: InflateState::new_boxed(DataFormat::Raw),
+            done: false,
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for Inflate<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        if this.done || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            let input = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+            let eof = input.is_empty();
+            let result = inflate(
+                &mut this.state,
+                input,
+                buf.initialize_unfilled(),
+                MZFlush::None,
+            );
+            Pin::new(&mut this.inner).consume(result.bytes_consumed);
+            buf.advance(result.bytes_written);
+            // an error comes again on the next read, after the data before it
+            match result.status {
+                Ok(MZStatus::StreamEnd) => {
+                    this.done = true;
+                    return Poll::Ready(Ok(()));
+                }
+                _ if result.bytes_written > 0 => return Poll::Ready(Ok(())),
+                _ if eof => {
+                    let e = Error::new(ErrorKind::UnexpectedEof, "deflate data ends early");
+                    return Poll::Ready(Err(e));
+                }
+                Ok(_) | Err(MZError::Buf) => continue,
+                Err(e) => {
+                    let e = Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid deflate data: {e:?}"),
+                    );
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn truncated_deflate() -> anyhow::Result<()> {
+        let text: String = (0..500)
+            .map(|i| format!("line {i} comes before the damage\n"))
+            .collect();
+        let mut deflated = flate2::write::DeflateEncoder::new(vec![], Default::default());
+        deflated.write_all(text.as_bytes())?;
+        let deflated = deflated.finish()?;
+
+        let mut out = vec![];
+        Inflate::new(&deflated[..]).read_to_end(&mut out).await?;
+        assert_eq!(out, text.as_bytes());
+
+        let cut = &deflated[..deflated.len() - 4];
+        let mut out = vec![];
+        let e = Inflate::new(cut).read_to_end(&mut out).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        assert!(out.len() > text.len() / 2 && text.as_bytes().starts_with(&out));
+        let mut out = vec![];
+        SalvageRead::new(Inflate::new(cut), "cut")
+            .read_to_end(&mut out)
+            .await?;
+        assert!(text.as_bytes().starts_with(&out) && !out.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! `--rga-output`: the output of rg can go to a file (rotated by size), a unix socket or syslog
+//! instead of stdout, so scheduled searches can feed a log collector or monitoring system without
+//! a wrapper script. rg writes into a pipe then and rga copies its output line by line, so a file
+//! is only rotated and syslog messages are only sent at the end of a line.
+use crate::config::RgaConfig;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// where syslog daemons and journald listen: Linux, macOS, the BSDs
+#[cfg(unix)]
+const SYSLOG_SOCKETS: [&str; 3] = ["/dev/log", "/var/run/syslog", "/var/run/log"];
+/// longer lines are cut, syslog daemons drop or split longer messages
+const MAX_SYSLOG_LINE: usize = 8000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    Stdout,
+    File {
+        path: PathBuf,
+        max_size: u64,
+        keep: usize,
+    },
+    Unix(PathBuf),
+    Syslog {
+        tag: String,
+    },
+}
+
+impl Sink {
+    pub fn from_config(config: &RgaConfig) -> Result<Self> {
+        let output = &config.output;
+        let (kind, arg) = match output.sink.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg)),
+            None => (output.sink.as_str(), None),
+        };
+        Ok(match (kind, arg) {
+            ("" | "stdout", None) => Sink::Stdout,
+            ("file", Some(path)) if !path.is_empty() => Sink::File {
+                path: path.into(),
+                max_size: output.max_size.0,
+                keep: output.keep.0,
+            },
+            ("unix", Some(path)) if !path.is_empty() => Sink::Unix(path.into()),
+            ("syslog", tag) => Sink::Syslog {
+                tag: tag.filter(|t| !t.is_empty()).unwrap_or("rga").to_owned(),
+            },
+            _ => anyhow::bail!(
+                "Unknown --rga-output {:?}, expected file:<path>, unix:<path> or syslog[:<tag>]",
+                output.sink
+            ),
+        })
+    }
+
+    /// opens the sink, so a missing socket is reported before rg runs
+    pub fn open(&self) -> Result<Box<dyn Write>> {
+        Ok(match self {
+            Sink::Stdout => Box::new(io::stdout().lock()),
+            Sink::File {
+                path,
+                max_size,
+                keep,
+            } => Box::new(RotatingFile::open(path, *max_size, *keep)?),
+            Sink::Unix(path) => open_unix(path)?,
+            Sink::Syslog { tag } => Box::new(Syslog::open(tag)?),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn open_unix(path: &Path) -> Result<Box<dyn Write>> {
+    let socket = std::os::unix::net::UnixStream::connect(path)
+        .with_context(|| format!("Could not connect to {}", path.display()))?;
+    Ok(Box::new(io::BufWriter::new(socket)))
+}
+
+#[cfg(not(unix))]
+fn open_unix(_path: &Path) -> Result<Box<dyn Write>> {
+    anyhow::bail!("--rga-output=unix:<path> is only supported on unix")
+}
+
+/// a file that is appended to and renamed to `<path>.1` when it reaches `max_size`
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> Result<Self> {
+        let file = Self::append(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            keep,
+            at_line_start: true,
+        })
+    }
+
+    fn append(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open {}", path.display()))
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = Self::append(&self.path).map_err(io::Error::other)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.max_size > 0 && self.size >= self.max_size && self.at_line_start {
+            self.rotate()?;
+        }
+        // the rest of a line after the first newline goes in the next call, after a rotation
+        let len = buf.iter().position(|&b| b == b'\n').map_or(buf.len(), |i| i + 1);
+        let written = self.file.write(&buf[..len])?;
+        self.size += written as u64;
+        self.at_line_start = buf[written - 1] == b'\n';
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// sends each line as a syslog message (facility user, severity info)
+pub struct Syslog {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    prefix: String,
+    line: Vec<u8>,
+}
+
+impl Syslog {
+    #[cfg(unix)]
+    pub fn open(tag: &str) -> Result<Self> {
+        SYSLOG_SOCKETS
+            .iter()
+            .find_map(|path| Self::connect(Path::new(path), tag).ok())
+            .context("No syslog socket found (/dev/log)")
+    }
+
+    #[cfg(unix)]
+    fn connect(path: &Path, tag: &str) -> Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .with_context(|| format!("Could not connect to {}", path.display()))?;
+        Ok(Self {
+            socket,
+            prefix: format!("<14>{tag}[{}]: ", std::process::id()),
+            line: vec![],
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(_tag: &str) -> Result<Self> {
+        anyhow::bail!("--rga-output=syslog is only supported on unix")
+    }
+
+    fn send_line(&mut self) -> io::Result<()> {
+        let mut message = self.prefix.clone().into_bytes();
+        let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+        message.extend_from_slice(&line[..line.len().min(MAX_SYSLOG_LINE)]);
+        self.line.clear();
+        #[cfg(unix)]
+        self.socket.send(&message)?;
+        Ok(())
+    }
+}
+
+impl Write for Syslog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.line.push(b);
+            if b == b'\n' {
+                self.send_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.send_line()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rotation() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("matches.log");
+        let mut file = RotatingFile::open(&path, 10, 2)?;
+        for line in ["a.txt:one\n", "a.txt:two\nb", ".txt:three\n", "c.txt:four\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+        file.flush()?;
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("matches.log"), "c.txt:four\n");
+        assert_eq!(read("matches.log.1"), "b.txt:three\n");
+        assert_eq!(read("matches.log.2"), "a.txt:two\n");
+        assert!(!dir.path().join("matches.log.3").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syslog_lines() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log");
+        let server = std::os::unix::net::UnixDatagram::bind(&path)?;
+        let mut syslog = Syslog::connect(&path, "nightly")?;
+        syslog.write_all(b"a.pdf:Page 1: one\na.pdf:Page 2: two")?;
+        syslog.flush()?;
+        let prefix = format!("<14>nightly[{}]: ", std::process::id());
+        for line in ["a.pdf:Page 1: one", "a.pdf:Page 2: two"] {
+            let mut buf = [0; 100];
+            let n = server.recv(&mut buf)?;
+            assert_eq!(String::from_utf8_lossy(&buf[..n]), format!("{prefix}{line}"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! `--rga-stats`: the files below a path grouped by their detected type and by the adapter that
+//! would handle them, to see which adapters are worth enabling and how much a first (uncached)
+//! search has to extract.
+use crate::adapters::{get_adapters_filtered, get_configured_adapters};
+use crate::config::RgaConfig;
+use crate::preproc::detect_file;
+use crate::print_bytes;
+use crate::vfs::{LocalFs, local_path};
+use anyhow::Result;
+use log::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use tokio_stream::StreamExt;
+
+/// group of the files no adapter handles, rg searches them directly
+pub const NO_ADAPTER: &str = "(no adapter)";
+/// group of the files whose type was not detected
+pub const UNKNOWN_TYPE: &str = "(unknown)";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Group {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub by_type: BTreeMap<String, Group>,
+    /// adapters that are disabled but would handle files are listed as `name (disabled)`
+    pub by_adapter: BTreeMap<String, Group>,
+    pub total: Group,
+    /// files that could not be read
+    pub errors: u64,
+}
+
+impl Stats {
+    fn add(&mut self, mime: Option<String>, adapter: String, bytes: u64) {
+        let type_group = self
+            .by_type
+            .entry(mime.unwrap_or_else(|| UNKNOWN_TYPE.to_string()))
+            .or_default();
+        for group in [
+            type_group,
+            self.by_adapter.entry(adapter).or_default(),
+            &mut self.total,
+        ] {
+            group.files += 1;
+            group.bytes += bytes;
+        }
+    }
+}
+
+/// collect the statistics of all files below `root`
+pub async fn collect(config: &RgaConfig, root: &Path) -> Result<Stats> {
+    let enabled = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters, config)?;
+    let (default_enabled, default_disabled) =
+        get_configured_adapters(config.custom_adapters.clone(), config)?;
+    let all = default_enabled
+        .into_iter()
+        .chain(default_disabled)
+        .collect();
+    let mut stats = Stats::default();
+    let mut entries = LocalFs::new(root).walk();
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let path = local_path(root, &entry.path)?;
+        let (mime, adapter) = match detect_file(config, &path, &enabled).await {
+            Ok(detected) => detected,
+            Err(e) => {
+                warn!("{}: {e:#}", path.display());
+                stats.errors += 1;
+                continue;
+            }
+        };
+        let adapter = match adapter {
+            Some(adapter) => adapter.metadata().name.clone(),
+            None => match detect_file(config, &path, &all).await?.1 {
+                Some(adapter) => format!("{} (disabled)", adapter.metadata().name),
+                None => NO_ADAPTER.to_string(),
+            },
+        };
+        stats.add(mime, adapter, entry.size);
+    }
+    Ok(stats)
+}
+
+fn write_groups(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    groups: &BTreeMap<String, Group>,
+) -> fmt::Result {
+    let mut groups: Vec<_> = groups.iter().collect();
+    groups.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+    writeln!(f, "{:>8}  {:>10}  {title}", "files", "size")?;
+    for (name, group) in groups {
+        writeln!(
+            f,
+            "{:>8}  {:>10}  {name}",
+            group.files,
+            print_bytes(group.bytes as f64)
+        )?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_groups(f, "adapter", &self.by_adapter)?;
+        writeln!(f)?;
+        write_groups(f, "type", &self.by_type)?;
+        writeln!(f)?;
+        write!(
+            f,
+            "{} files, {}",
+            self.total.files,
+            print_bytes(self.total.bytes as f64)
+        )?;
+        if self.errors > 0 {
+            write!(f, ", {} could not be read", self.errors)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(all(test, feature = "forensics"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_data_dir;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn groups() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data = test_data_dir();
+        std::fs::copy(data.join("hello.gz"), dir.path().join("hello.gz"))?;
+        std::fs::copy(data.join("hello.tar"), dir.path().join("hello.tar"))?;
+        std::fs::create_dir(dir.path().join("notes"))?;
+        std::fs::write(dir.path().join("notes/todo.txt"), "buy milk")?;
+        std::fs::write(dir.path().join("notes/tool.exe"), b"MZ\x90\0")?;
+
+        let stats = collect(&RgaConfig::default(), dir.path()).await?;
+        let group = |files, bytes| Group { files, bytes };
+        let size = |name: &str| std::fs::metadata(data.join(name)).unwrap().len();
+        assert_eq!(
+            stats.by_adapter,
+            BTreeMap::from([
+                (NO_ADAPTER.to_string(), group(1, 8)),
+                ("decompress".to_string(), group(1, size("hello.gz"))),
+                ("installer (disabled)".to_string(), group(1, 4)),
+                ("tar".to_string(), group(1, size("hello.tar"))),
+            ])
+        );
+        assert_eq!(
+            stats.by_type["application/gzip"],
+            group(1, size("hello.gz"))
+        );
+        assert_eq!(stats.by_type["text/plain"], group(1, 8));
+        assert_eq!(stats.total.files, 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,536 @@
+//! `--rga-tui`: browse the matches of a search interactively, grouped by file, with the extracted
+//! text around the selected match. The matches come from a run of rg with `--json` and are shown
+//! while it is still searching; the preview is the output of rga-preproc for the file.
+use crate::adapters::FileAdapter;
+use crate::adapters::custom::map_exe_error;
+use crate::adapters::postproc::PageFormat;
+use crate::annotate::{Match, Submatch, parse_event};
+use crate::config::RgaConfig;
+use crate::matching::{FastFileMatcher, extension_to_regex};
+use anyhow::{Context, Result};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use regex::Regex;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// the most lines of extracted text read for a preview
+const MAX_PREVIEW_LINES: usize = 100_000;
+const KEYS: &str = "↑/↓ select  n/N next/previous file  enter open  q quit";
+
+struct File {
+    path: String,
+    matches: Vec<Match>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Row {
+    File(usize),
+    /// (file, match)
+    Match(usize, usize),
+}
+
+/// the matches so far, and the rows of the list of them
+#[derive(Default)]
+struct Results {
+    files: Vec<File>,
+    rows: Vec<Row>,
+}
+
+impl Results {
+    /// rg prints the matches of a file together
+    fn push(&mut self, m: Match) {
+        if self.files.last().is_none_or(|f| f.path != m.path) {
+            self.files.push(File {
+                path: m.path.clone(),
+                matches: vec![],
+            });
+            self.rows.push(Row::File(self.files.len() - 1));
+        }
+        let file = self.files.len() - 1;
+        self.files[file].matches.push(m);
+        self.rows
+            .push(Row::Match(file, self.files[file].matches.len() - 1));
+    }
+
+    /// the row of the file after (or before) the one of `row`
+    fn next_file(&self, row: usize, forward: bool) -> Option<usize> {
+        let is_file = |r: &usize| matches!(self.rows[*r], Row::File(_));
+        if forward {
+            (row + 1..self.rows.len()).find(is_file)
+        } else {
+            let current = (0..=row).rev().find(is_file)?;
+            (0..current).rev().find(is_file)
+        }
+    }
+}
+
+/// where the match is in the file: the archive member, the page and the line
+fn location(m: &Match) -> String {
+    let mut location = String::new();
+    if let Some(inner_path) = &m.inner_path {
+        location += &format!("{inner_path} ");
+    }
+    if let Some(page) = m.page {
+        location += &format!("p{page} ");
+    }
+    if let Some(line) = m.line_number {
+        location += &format!("{line}:");
+    }
+    location
+}
+
+/// control characters (also tabs) would break the layout
+fn printable(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// `text` with its submatches highlighted
+fn highlighted(text: &str, submatches: &[Submatch], style: Style) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let mut at = 0;
+    for s in submatches {
+        let (Some(before), Some(matched)) = (text.get(at..s.start), text.get(s.start..s.end))
+        else {
+            continue;
+        };
+        spans.push(Span::styled(printable(before), style));
+        spans.push(Span::styled(printable(matched), style.red().bold()));
+        at = s.end;
+    }
+    spans.push(Span::styled(printable(&text[at..]), style));
+    spans
+}
+
+/// the first lines of a text, at most [`MAX_PREVIEW_LINES`]
+fn read_lines(r: impl Read) -> Result<Vec<String>> {
+    BufReader::new(r)
+        .split(b'\n')
+        .take(MAX_PREVIEW_LINES)
+        .map(|l| {
+            Ok(String::from_utf8_lossy(&l?)
+                .trim_end_matches('\r')
+                .to_string())
+        })
+        .collect()
+}
+
+/// the extracted text of a file
+struct Preview {
+    path: String,
+    lines: Vec<String>,
+}
+
+struct Browser<'a> {
+    results: Results,
+    selected: usize,
+    /// the first row shown
+    offset: usize,
+    list_height: usize,
+    preview: Option<Preview>,
+    preproc: &'a dyn Fn(&str) -> Command,
+    /// the extensions of the enabled adapters, other files are searched as they are
+    extracted: Vec<Regex>,
+    status: String,
+}
+
+impl Browser<'_> {
+    fn selection(&self) -> Option<(&File, Option<&Match>)> {
+        Some(match *self.results.rows.get(self.selected)? {
+            Row::File(f) => (&self.results.files[f], None),
+            Row::Match(f, i) => (
+                &self.results.files[f],
+                Some(&self.results.files[f].matches[i]),
+            ),
+        })
+    }
+
+    fn select(&mut self, row: usize) {
+        self.selected = row.min(self.results.rows.len().saturating_sub(1));
+    }
+
+    fn move_by(&mut self, rows: isize) {
+        self.select(self.selected.saturating_add_signed(rows));
+    }
+
+    /// run rga-preproc for the selected file, unless it is the one already shown
+    fn load_preview(&mut self) {
+        let Some((file, _)) = self.selection() else {
+            return;
+        };
+        if self.preview.as_ref().is_some_and(|p| p.path == file.path) {
+            return;
+        }
+        let path = file.path.clone();
+        let lines = self
+            .extract(&path)
+            .unwrap_or_else(|e| vec![format!("could not extract {path}: {e:#}")]);
+        self.preview = Some(Preview { path, lines });
+    }
+
+    /// the output of rga-preproc, or the file itself if no adapter handles it (rg searches it directly)
+    fn extract(&self, path: &str) -> Result<Vec<String>> {
+        let mut child = (self.preproc)(path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("running rga-preproc")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("rga-preproc stdout not piped")?;
+        let lines = read_lines(stdout)?;
+        if lines.len() == MAX_PREVIEW_LINES {
+            // the rest of the text is not needed
+            let _ = child.kill();
+        }
+        if !child.wait()?.success() && lines.is_empty() {
+            return read_lines(std::fs::File::open(path)?);
+        }
+        Ok(lines)
+    }
+
+    fn is_extracted(&self, path: &str) -> bool {
+        self.extracted.iter().any(|r| r.is_match(path))
+    }
+
+    /// open the original file: PDFs at the page of the match in evince, files rg searched
+    /// directly at the line of the match in $VISUAL or $EDITOR, anything else in its default application
+    fn open(&self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let Some((file, m)) = self.selection() else {
+            return Ok(());
+        };
+        let path = file.path.clone();
+        let in_archive = m.is_some_and(|m| m.inner_path.is_some());
+        if path.to_lowercase().ends_with(".pdf") && !in_archive {
+            let mut cmd = Command::new("evince");
+            if let Some(page) = m.and_then(|m| m.page) {
+                cmd.arg("--page-label").arg(page.to_string());
+            }
+            if let Some(s) = m.and_then(|m| m.submatches.first()) {
+                cmd.arg("--find").arg(&s.text);
+            }
+            if cmd
+                .arg(&path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR"));
+        if let (Ok(editor), false) = (editor, self.is_extracted(&path)) {
+            let mut words = editor.split_whitespace();
+            if let Some(program) = words.next() {
+                let mut cmd = Command::new(program);
+                cmd.args(words);
+                if let Some(line) = m.and_then(|m| m.line_number) {
+                    cmd.arg(format!("+{line}"));
+                }
+                ratatui::restore();
+                let status = cmd.arg(&path).status();
+                *terminal = ratatui::init();
+                status.with_context(|| format!("running {editor}"))?;
+                return Ok(());
+            }
+        }
+        open::that_detached(&path).with_context(|| format!("opening {path}"))
+    }
+
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        matches: &Receiver<Match>,
+        rg: &mut Child,
+        stderr: &mut Option<JoinHandle<String>>,
+    ) -> Result<()> {
+        let mut searching = true;
+        loop {
+            while searching {
+                match matches.try_recv() {
+                    Ok(m) => self.results.push(m),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        searching = false;
+                        let status = rg.wait()?;
+                        let stderr = stderr.take().and_then(|s| s.join().ok());
+                        // 1 is no matches
+                        if !status.success() && status.code() != Some(1) {
+                            let error = stderr.as_deref().and_then(|s| s.lines().next());
+                            self.status =
+                                format!("rg failed: {}", error.unwrap_or(&status.to_string()));
+                        }
+                    }
+                }
+            }
+            if self.selected == 0 && self.results.rows.len() > 1 {
+                // the first match rather than its file
+                self.selected = 1;
+            }
+            self.load_preview();
+            terminal.draw(|frame| self.draw(frame, searching))?;
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let page = self.list_height.max(1) as isize;
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+                KeyCode::PageDown => self.move_by(page),
+                KeyCode::PageUp => self.move_by(-page),
+                KeyCode::Home | KeyCode::Char('g') => self.select(0),
+                KeyCode::End | KeyCode::Char('G') => self.select(usize::MAX),
+                KeyCode::Char('n') => {
+                    if let Some(row) = self.results.next_file(self.selected, true) {
+                        self.select(row + 1);
+                    }
+                }
+                KeyCode::Char('N') => {
+                    if let Some(row) = self.results.next_file(self.selected, false) {
+                        self.select(row + 1);
+                    }
+                }
+                KeyCode::Enter | KeyCode::Char('o') => {
+                    if let Err(e) = self.open(terminal) {
+                        self.status = format!("{e:#}");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, searching: bool) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list, preview] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        // only the visible rows are built, there can be many
+        self.list_height = list.height.saturating_sub(2) as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + self.list_height {
+            self.offset = self.selected + 1 - self.list_height.max(1);
+        }
+        let items: Vec<ListItem> = self
+            .results
+            .rows
+            .iter()
+            .skip(self.offset)
+            .take(self.list_height)
+            .map(|row| match *row {
+                Row::File(f) => {
+                    let file = &self.results.files[f];
+                    ListItem::new(Line::from(vec![
+                        Span::styled(printable(&file.path), Style::new().magenta().bold()),
+                        Span::raw(format!(" ({})", file.matches.len())),
+                    ]))
+                }
+                Row::Match(f, i) => {
+                    let m = &self.results.files[f].matches[i];
+                    let mut spans = vec![
+                        Span::raw("  "),
+                        Span::styled(location(m), Style::new().green()),
+                    ];
+                    spans.push(Span::raw(" "));
+                    spans.extend(highlighted(m.text.trim_end(), &m.submatches, Style::new()));
+                    ListItem::new(Line::from(spans))
+                }
+            })
+            .collect();
+        let title = format!(
+            " {} matches in {} files{} ",
+            self.results.rows.len() - self.results.files.len(),
+            self.results.files.len(),
+            if searching { ", searching…" } else { "" }
+        );
+        let mut state = ListState::default()
+            .with_selected((!self.results.rows.is_empty()).then(|| self.selected - self.offset));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::new().reversed()),
+            list,
+            &mut state,
+        );
+
+        let selection = self.selection();
+        let m = selection.and_then(|(_, m)| m);
+        let height = preview.height.saturating_sub(2) as usize;
+        let (title, lines) = match (&self.preview, selection) {
+            (Some(p), Some((file, _))) if p.path == file.path => {
+                let focus = m.and_then(|m| m.line_number).unwrap_or(1) as usize;
+                let start = focus.saturating_sub(height / 2 + 1);
+                let lines = p
+                    .lines
+                    .iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(height)
+                    .map(|(i, line)| {
+                        let mut spans = vec![Span::styled(
+                            format!("{:>6} ", i + 1),
+                            Style::new().dark_gray(),
+                        )];
+                        match m.filter(|m| m.line_number == Some(i as u64 + 1)) {
+                            Some(m) if line.ends_with(m.text.as_str()) => {
+                                let prefix = &line[..line.len() - m.text.len()];
+                                spans.push(Span::styled(
+                                    printable(prefix),
+                                    Style::new().dark_gray(),
+                                ));
+                                spans.extend(highlighted(
+                                    &m.text,
+                                    &m.submatches,
+                                    Style::new().bold(),
+                                ));
+                            }
+                            _ => spans.push(Span::raw(printable(line))),
+                        }
+                        Line::from(spans)
+                    })
+                    .collect();
+                (format!(" {} ", printable(&p.path)), lines)
+            }
+            _ => (String::new(), vec![]),
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            preview,
+        );
+
+        let status_line = if self.status.is_empty() {
+            KEYS
+        } else {
+            &self.status
+        };
+        frame.render_widget(Paragraph::new(status_line).dark_gray(), status);
+    }
+}
+
+/// browse the matches of `rg` (already given `--json`). `preproc` is the rga-preproc command for a file
+pub fn run(
+    mut rg: Command,
+    preproc: &dyn Fn(&str) -> Command,
+    adapters: &[Arc<dyn FileAdapter>],
+    config: &RgaConfig,
+) -> Result<()> {
+    let format = PageFormat::new(&config.postproc.options);
+    let mut child = rg
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    let stdout = child.stdout.take().context("rg stdout not piped")?;
+    let mut stderr = child.stderr.take().context("rg stderr not piped")?;
+    let (send, matches) = channel();
+    std::thread::spawn(move || {
+        for event in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if let Some(m) = parse_event(&event, &format)
+                && send.send(m).is_err()
+            {
+                break;
+            }
+        }
+    });
+    let mut stderr = Some(std::thread::spawn(move || {
+        let mut s = String::new();
+        let _ = stderr.read_to_string(&mut s);
+        s
+    }));
+    let mut browser = Browser {
+        results: Results::default(),
+        selected: 0,
+        offset: 0,
+        list_height: 0,
+        preview: None,
+        preproc,
+        extracted: adapters
+            .iter()
+            .flat_map(|a| &a.metadata().fast_matchers)
+            .map(|FastFileMatcher::FileExtension(ext)| extension_to_regex(ext))
+            .collect(),
+        status: String::new(),
+    };
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal, &matches, &mut child, &mut stderr);
+    ratatui::restore();
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn m(path: &str, line: u64) -> Match {
+        Match {
+            path: path.to_string(),
+            inner_path: None,
+            page: None,
+            line_number: Some(line),
+            text: "a match".to_string(),
+            submatches: vec![],
+            confidence: None,
+        }
+    }
+
+    #[test]
+    fn rows() {
+        let mut results = Results::default();
+        for (path, line) in [("a.pdf", 1), ("a.pdf", 5), ("b.zip", 2)] {
+            results.push(m(path, line));
+        }
+        assert_eq!(
+            results.rows,
+            vec![
+                Row::File(0),
+                Row::Match(0, 0),
+                Row::Match(0, 1),
+                Row::File(1),
+                Row::Match(1, 0)
+            ]
+        );
+        assert_eq!(results.next_file(1, true), Some(3));
+        assert_eq!(results.next_file(4, false), Some(0));
+        assert_eq!(results.next_file(2, false), None);
+    }
+
+    #[test]
+    fn highlights() {
+        let submatches = vec![Submatch {
+            text: "wor".to_string(),
+            start: 6,
+            end: 9,
+        }];
+        let spans = highlighted("hello\tworld", &submatches, Style::new());
+        let text: Vec<_> = spans.iter().map(|s| s.content.to_string()).collect();
+        assert_eq!(text, vec!["hello ", "wor", "ld"]);
+    }
+}
@@ -0,0 +1,302 @@
+//! `--rga-verify`: checks files against the checksums that come with them, a `.sha256` or `.md5`
+//! companion file next to them or the CRC32 stored with a zip member. A mismatch is reported as a
+//! line of the extracted text, `[rga:verify] mismatch algorithm=... expected=... actual=...`, so
+//! it is found by searches instead of the file silently ending early.
+use crate::adapters::{AdaptInfo, ContainerInfo};
+use anyhow::*;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+pub const VERIFY_TOKEN: &str = "[rga:verify]";
+/// the suffixes of companion files, in the order they are looked for
+const COMPANIONS: &[(&str, Algorithm)] = &[
+    ("sha256", Algorithm::Sha256),
+    ("sha256sum", Algorithm::Sha256),
+    ("md5", Algorithm::Md5),
+    ("md5sum", Algorithm::Md5),
+];
+/// larger companion files are not checksum lists
+const MAX_COMPANION_LEN: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Md5,
+    Crc32,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Md5 => "md5",
+            Algorithm::Crc32 => "crc32",
+        }
+    }
+
+    fn hex_len(&self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Md5 => 32,
+            Algorithm::Crc32 => 8,
+        }
+    }
+}
+
+/// a checksum that does not match the data
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub algorithm: Algorithm,
+    pub expected: String,
+    pub actual: String,
+    /// where the expected checksum is from, a companion file or the archive
+    pub source: String,
+}
+
+impl Mismatch {
+    /// the line reporting the mismatch in the text of the file, without the newline. The source
+    /// is last since it may be a file name with spaces
+    pub fn line(&self, line_prefix: &str) -> String {
+        format!(
+            "{line_prefix}{VERIFY_TOKEN} mismatch algorithm={} expected={} actual={} source={}",
+            self.algorithm.name(),
+            self.expected,
+            self.actual,
+            self.source
+        )
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} mismatch, {} says {} but the data has {}",
+            self.algorithm.name(),
+            self.source,
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+/// the member of `container` that reports a mismatch of its member `name`. The path has a suffix
+/// no adapter matches, so the line is passed on as text with the prefix of the member
+pub fn mismatch_member(container: &ContainerInfo, name: &str, mismatch: &Mismatch) -> AdaptInfo {
+    let member = container.member(name, Box::pin(Cursor::new(mismatch.line("").into_bytes())));
+    AdaptInfo {
+        filepath_hint: PathBuf::from(format!("{name}.rga-verify")),
+        ..member
+    }
+}
+
+/// the checksum of the file `name` in the text of a checksum file: `<hex>  <name>` as
+/// `sha256sum` writes it, `<ALGO> (<name>) = <hex>` as BSD tools write it, or only the hex
+fn expected_in(text: &str, name: &str, algorithm: Algorithm) -> Option<String> {
+    let is_checksum =
+        |s: &str| s.len() == algorithm.hex_len() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let is_name = |s: &str| s.is_empty() || s == name || s.ends_with(&format!("/{name}"));
+    text.lines().find_map(|line| {
+        let line = line.trim();
+        let (hex, file) = match line.split_once(") = ") {
+            Some((head, hex)) => (hex, head.split_once(" (")?.1),
+            None => {
+                let (hex, file) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                (hex, file.trim_start().trim_start_matches('*'))
+            }
+        };
+        (is_checksum(hex) && is_name(file)).then(|| hex.to_ascii_lowercase())
+    })
+}
+
+/// the companion checksum of the file at `path`: the algorithm, the companion and the checksum
+pub fn companion(path: &Path) -> Option<(Algorithm, PathBuf, String)> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    COMPANIONS.iter().find_map(|(suffix, algorithm)| {
+        let companion = path.with_file_name(format!("{name}.{suffix}"));
+        let mut text = String::new();
+        std::fs::File::open(&companion)
+            .ok()?
+            .take(MAX_COMPANION_LEN)
+            .read_to_string(&mut text)
+            .ok()?;
+        let expected = expected_in(&text, &name, *algorithm)?;
+        Some((*algorithm, companion, expected))
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// the checksum of everything `inp` reads
+fn checksum(algorithm: Algorithm, mut inp: impl Read) -> Result<String> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut sha256 = Sha256::new();
+    let mut md5 = Md5::new();
+    loop {
+        let n = inp.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        match algorithm {
+            Algorithm::Sha256 => sha256.update(&buf[..n]),
+            Algorithm::Md5 => md5.update(&buf[..n]),
+            Algorithm::Crc32 => bail!("crc32 is only checked in archives"),
+        }
+    }
+    Ok(match algorithm {
+        Algorithm::Sha256 => hex(&sha256.finalize()),
+        _ => hex(&md5.finalize()),
+    })
+}
+
+/// hashes the file at `path` and compares it with its companion checksum. None if it has no
+/// companion or the checksum matches
+pub fn check_companion(path: &Path) -> Result<Option<Mismatch>> {
+    let Some((algorithm, companion, expected)) = companion(path) else {
+        return Ok(None);
+    };
+    let file = std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let actual = checksum(algorithm, file)?;
+    Ok((actual != expected).then(|| Mismatch {
+        algorithm,
+        expected,
+        actual,
+        source: companion
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    }))
+}
+
+/// MD5 (RFC 1321), still what many download pages list
+struct Md5 {
+    state: [u32; 4],
+    len: u64,
+    block: Vec<u8>,
+}
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+impl Md5 {
+    fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            len: 0,
+            block: Vec::with_capacity(64),
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let m: Vec<u32> = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+            let f = f.wrapping_add(a).wrapping_add(k).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finalize(mut self) -> [u8; 16] {
+        let bits = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        padding.resize(((119 - self.len % 64) % 64 + 1) as usize, 0);
+        padding.extend(bits.to_le_bytes());
+        self.update(&padding);
+        let mut out = [0; 16];
+        for (o, s) in out.chunks_mut(4).zip(self.state) {
+            o.copy_from_slice(&s.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn md5() -> Result<()> {
+        for (data, expected) in [
+            (&b""[..], "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+            // the padding does not fit into the last block
+            (&[b'a'; 60], "cc7ed669cf88f201c3297c6a91e1d18d"),
+        ] {
+            assert_eq!(checksum(Algorithm::Md5, data)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn companions() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let iso = dir.path().join("image.iso");
+        std::fs::write(&iso, "abc")?;
+        assert_eq!(check_companion(&iso)?, None);
+        // a list as sha256sum writes it, with the checksum of another file first
+        std::fs::write(
+            dir.path().join("image.iso.sha256"),
+            "0000000000000000000000000000000000000000000000000000000000000000  other.iso\n\
+             BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD *dist/image.iso\n",
+        )?;
+        assert_eq!(check_companion(&iso)?, None);
+        std::fs::write(&iso, "abd")?;
+        let mismatch = check_companion(&iso)?.context("a mismatch")?;
+        assert_eq!(
+            mismatch.line("image.iso: "),
+            "image.iso: [rga:verify] mismatch algorithm=sha256 \
+             expected=ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad \
+             actual=a52d159f262b2c6ddb724a61840befc36eb30c88877a4030b65cbe86298449c9 \
+             source=image.iso.sha256"
+        );
+
+        let tgz = dir.path().join("src.tgz");
+        std::fs::write(&tgz, "abc")?;
+        std::fs::write(
+            dir.path().join("src.tgz.md5"),
+            "MD5 (src.tgz) = 900150983cd24fb0d6963f7d28e17f72\n",
+        )?;
+        assert_eq!(check_companion(&tgz)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! A minimal virtual file system that containers of files (local directories, archives, remote
+//! transports, disk images) implement, so that walking them and turning their members into adapter
+//! inputs is written once.
+//!
+//! Members become [`AdaptInfo`]s through [`ContainerInfo::member`], which handles the line prefix and
+//! the recursion depth. Caching and `--rga-max-archive-recursion` are then applied by `loop_adapt`
+//! like for any other nested file.
+//!
+//! Containers with random access implement [`Vfs`] (rar, dmg, iso, zim, the remote transports), and
+//! formats that are decoded as a whole (cab, msi, installers, game archives) put their members in a
+//! [`MemoryFs`]. zip, tar, ar and cpio are read front to back from their input, which is often a member
+//! of another archive, so listing them first would mean buffering or reading them twice: their adapters
+//! stream the members and only share [`ContainerInfo::member`] with the rest.
+use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapters::{AdaptInfo, ContainerInfo, ReadBox};
+use crate::{join_handle_to_stream, to_io_err};
+use anyhow::{Context, Result, format_err};
+use async_stream::stream;
+use async_trait::async_trait;
+use ignore::WalkBuilder;
+use log::*;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
+
+/// A regular file in a [`Vfs`], as returned by [`Vfs::list`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VfsEntry {
+    /// path relative to the root, kept as the raw bytes of the names. Empty if the root itself is a file.
+    pub path: PathBuf,
+    pub size: u64,
+    /// modification time in unix seconds, if known
+    pub mtime_unix: Option<i64>,
+    /// opaque identifier that changes whenever the content changes (mtime, ETag, ...)
+    pub version: String,
+}
+
+#[async_trait]
+pub trait Vfs: Send + Sync {
+    /// recursively list all regular files below the root
+    async fn list(&self) -> Result<Vec<VfsEntry>>;
+    /// write the content of `entry` starting at byte `offset` to `oup`.
+    ///
+    /// offset is only ever non-zero if `supports_ranges()` is true
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()>;
+    /// whether `read_range` can start in the middle of a file (used to resume interrupted downloads)
+    fn supports_ranges(&self) -> bool;
+}
+
+/// open `entry` as a stream. Errors while reading are returned from the stream
+pub fn open(vfs: Arc<dyn Vfs>, entry: VfsEntry) -> ReadBox {
+    let (mut w, r) = tokio::io::duplex(128 * 1024);
+    let joiner = tokio::spawn(async move {
+        vfs.read_range(&entry, 0, &mut w)
+            .await
+            .with_context(|| format!("reading {}", entry.path.display()))
+            .map_err(to_io_err)?;
+        w.shutdown().await
+    });
+    Box::pin(r.chain(join_handle_to_stream(joiner)))
+}
+
+/// adapter input for every file in `vfs`, as members of `container`
+pub async fn adapt_vfs(vfs: Arc<dyn Vfs>, container: ContainerInfo) -> Result<AdaptedFilesIterBox> {
+    let entries = vfs
+        .list()
+        .await
+        .with_context(|| format!("listing {}", container.filepath_hint.display()))?;
+    let s = stream! {
+        for entry in entries {
+            let path = entry.path.clone();
+            let ai: AdaptInfo = container.member(path, open(vfs.clone(), entry));
+            yield Ok(ai);
+        }
+    };
+    Ok(Box::pin(s))
+}
+
+/// resolve a path relative to `root`. Rejects paths that would escape it
+pub fn local_path(root: &Path, relative: &Path) -> Result<PathBuf> {
+    if relative.as_os_str().is_empty() {
+        return Ok(root.to_path_buf());
+    }
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(format_err!("refusing suspicious path {:?}", relative));
+    }
+    Ok(root.join(relative))
+}
+
+/// a directory (or single file) on the local file system
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn entry(path: PathBuf, meta: &std::fs::Metadata) -> VfsEntry {
+        let mtime_unix = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        VfsEntry {
+            path,
+            size: meta.len(),
+            mtime_unix,
+            version: mtime_unix.map(|m| m.to_string()).unwrap_or_default(),
+        }
+    }
+
+    /// the files below the root as they are found, skipping what rg skips by default: hidden files
+    /// and the files in `.gitignore`, `.ignore` and `.rgignore`. Files and directories that can't be
+    /// read are skipped with a warning, only a missing root is an error
+    pub fn walk(&self) -> impl Stream<Item = Result<VfsEntry>> + Send + Unpin + 'static {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(256);
+        let root = self.root.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = std::fs::metadata(&root) {
+                let _ = tx.blocking_send(Err(e).with_context(|| format!("reading {}", root.display())));
+                return;
+            }
+            for dent in WalkBuilder::new(&root).sort_by_file_name(Ord::cmp).build() {
+                let dent = match dent {
+                    Ok(dent) => dent,
+                    Err(e) => {
+                        warn!("skipping {e}");
+                        continue;
+                    }
+                };
+                // symlinks are not followed, like rg does by default
+                if !dent.file_type().is_some_and(|t| t.is_file()) {
+                    continue;
+                }
+                let meta = match dent.metadata() {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        warn!("skipping {e}");
+                        continue;
+                    }
+                };
+                let path = dent.path().strip_prefix(&root).unwrap_or(dent.path());
+                if tx.blocking_send(Ok(Self::entry(path.to_path_buf(), &meta))).is_err() {
+                    // nobody is listening anymore
+                    break;
+                }
+            }
+        });
+        Box::pin(stream! {
+            while let Some(entry) = rx.recv().await {
+                yield entry;
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl Vfs for LocalFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        self.walk().collect().await
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let mut file = tokio::fs::File::open(local_path(&self.root, &entry.path)?).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        tokio::io::copy(&mut file, oup).await?;
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+/// files already extracted into memory, by containers that can only be decoded as a whole
+pub struct MemoryFs {
+    files: Vec<(VfsEntry, Vec<u8>)>,
+}
+
+impl MemoryFs {
+    /// the files as (path, content), listed in this order. Paths may repeat
+    pub fn new(files: impl IntoIterator<Item = (String, Vec<u8>)>) -> Self {
+        let files = files
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, data))| {
+                let entry = VfsEntry {
+                    path: path.into(),
+                    size: data.len() as u64,
+                    mtime_unix: None,
+                    // the content never changes, the index tells files with the same path apart
+                    version: i.to_string(),
+                };
+                (entry, data)
+            })
+            .collect();
+        Self { files }
+    }
+}
+
+#[async_trait]
+impl Vfs for MemoryFs {
+    async fn list(&self) -> Result<Vec<VfsEntry>> {
+        Ok(self.files.iter().map(|(entry, _)| entry.clone()).collect())
+    }
+
+    async fn read_range(
+        &self,
+        entry: &VfsEntry,
+        offset: u64,
+        oup: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let (_, data) = entry
+            .version
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| self.files.get(i))
+            .filter(|(e, _)| e == entry)
+            .with_context(|| format!("no file {}", entry.path.display()))?;
+        oup.write_all(data.get(offset as usize..).unwrap_or_default()).await?;
+        Ok(())
+    }
+
+    fn supports_ranges(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn local_fs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("sub"))?;
+        std::fs::write(dir.path().join("a.txt"), "hello")?;
+        std::fs::write(dir.path().join("sub/b.txt"), "world")?;
+        // skipped like rg skips them
+        std::fs::write(dir.path().join(".hidden.txt"), "")?;
+        std::fs::write(dir.path().join(".ignore"), "ignored.txt")?;
+        std::fs::write(dir.path().join("sub/ignored.txt"), "")?;
+        let vfs = LocalFs::new(dir.path());
+        let entries = vfs.list().await?;
+        assert_eq!(
+            entries.iter().map(|e| e.path.to_str().unwrap()).collect::<Vec<_>>(),
+            vec!["a.txt", "sub/b.txt"]
+        );
+        let mut buf = vec![];
+        vfs.read_range(&entries[1], 2, &mut buf).await?;
+        assert_eq!(buf, b"rld");
+        assert!(local_path(dir.path(), Path::new("../x")).is_err());
+        assert!(LocalFs::new(dir.path().join("missing")).list().await.is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn local_fs_non_utf8() -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir()?;
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        std::fs::write(dir.path().join(name), "bytes")?;
+        let vfs = LocalFs::new(dir.path());
+        let entries = vfs.list().await?;
+        assert_eq!(entries[0].path, Path::new(name));
+        let mut buf = vec![];
+        vfs.read_range(&entries[0], 0, &mut buf).await?;
+        assert_eq!(buf, b"bytes");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn members() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("sub"))?;
+        std::fs::write(dir.path().join("sub/b.txt"), "world")?;
+        let (ai, _) = simple_adapt_info(&PathBuf::from("dir"), Box::pin(tokio::io::empty()));
+        let (_, container) = ai.into_parts();
+        let mut members = adapt_vfs(Arc::new(LocalFs::new(dir.path())), container).await?;
+        let mut member = members.next().await.context("no member")??;
+        assert_eq!(member.line_prefix, "PREFIX:sub/b.txt: ");
+        assert_eq!(member.archive_recursion_depth, 1);
+        let mut content = String::new();
+        member.inp.read_to_string(&mut content).await?;
+        assert_eq!(content, "world");
+        assert!(members.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn memory_fs() -> Result<()> {
+        let vfs = MemoryFs::new([
+            ("a.txt".to_string(), b"first".to_vec()),
+            ("a.txt".to_string(), b"second".to_vec()),
+        ]);
+        let entries = vfs.list().await?;
+        assert_eq!(entries.iter().map(|e| e.size).collect::<Vec<_>>(), vec![5, 6]);
+        let mut buf = vec![];
+        vfs.read_range(&entries[1], 3, &mut buf).await?;
+        assert_eq!(buf, b"ond");
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! `--rga-warm`: extracts every file below a path that an adapter handles and that is not in the
+//! cache yet, several files at a time, so the first search of a large corpus reads from the cache
+//! instead of running the adapters.
+use crate::adapters::AdaptInfo;
+use crate::concurrency::max_subprocesses;
+use crate::config::RgaConfig;
+use crate::preproc::{cache_key_for, rga_preproc};
+use crate::preproc_cache::{EntryStatus, entry_status};
+use crate::print_bytes;
+use crate::vfs::{LocalFs, Vfs, local_path};
+use anyhow::{Result, bail};
+use log::*;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
+
+const BAR_WIDTH: usize = 30;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub files: u64,
+    /// files with a fresh cache entry already
+    pub cached: u64,
+    pub extracted: u64,
+    /// bytes of text of the extracted files
+    pub bytes: u64,
+    /// files no adapter handles or whose adapter's output is not cached
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} files: {} extracted ({} of text), {} cached already, {} not cached by rga",
+            self.files,
+            self.extracted,
+            print_bytes(self.bytes as f64),
+            self.cached,
+            self.skipped
+        )?;
+        if self.failed > 0 {
+            writeln!(f, "{} files failed, see the warnings above", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+enum Outcome {
+    Cached,
+    Extracted(u64),
+    Skipped,
+}
+
+/// extracts the file at `path` into the cache, unless it is there already
+async fn warm_file(config: &RgaConfig, path: &Path) -> Result<Outcome> {
+    let Some((adapter, key)) = cache_key_for(config, path).await? else {
+        return Ok(Outcome::Skipped);
+    };
+    if !adapter.metadata().capabilities.deterministic {
+        return Ok(Outcome::Skipped);
+    }
+    if matches!(entry_status(config, &key).await?, EntryStatus::Fresh) {
+        return Ok(Outcome::Cached);
+    }
+    let ai = AdaptInfo {
+        inp: Box::pin(tokio::io::BufReader::new(
+            tokio::fs::File::open(path).await?,
+        )),
+        filepath_hint: path.to_owned(),
+        is_real_file: true,
+        file_mtime_unix_ms: None,
+        line_prefix: String::new(),
+        archive_recursion_depth: 0,
+        postprocess: !config.no_prefix_filenames,
+        config: config.clone(),
+    };
+    // the text is written to the cache as it is read
+    let mut text = rga_preproc(ai).await?;
+    let len = tokio::io::copy(&mut text, &mut tokio::io::sink()).await?;
+    Ok(Outcome::Extracted(len))
+}
+
+/// the progress line on stderr, if it is a terminal
+fn show_progress(done: usize, total: usize, path: &Path) {
+    let mut stderr = std::io::stderr();
+    if !stderr.is_terminal() {
+        return;
+    }
+    let filled = BAR_WIDTH * done / total.max(1);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name: String = name.chars().take(40).collect();
+    let _ = write!(
+        stderr,
+        "\r\x1b[K[{}{}] {done}/{total} {name}",
+        "#".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled)
+    );
+    if done == total {
+        let _ = writeln!(stderr);
+    }
+}
+
+/// fills the cache with the text of every file below `root`
+pub async fn warm(config: &RgaConfig, root: &Path) -> Result<Summary> {
+    if config.cache.disabled {
+        bail!("the cache is disabled, there is nothing to warm");
+    }
+    let mut paths = vec![];
+    for entry in LocalFs::new(root).list().await? {
+        paths.push(local_path(root, &entry.path)?);
+    }
+    // the files share the slots for external programs, this leaves enough of them for one chain
+    // of nested adapters to finish
+    let jobs = max_subprocesses(config)
+        .saturating_sub(config.max_archive_recursion.0.max(0) as usize)
+        .max(1);
+    debug!("warming {} files, {jobs} at a time", paths.len());
+    let mut summary = Summary {
+        files: paths.len() as u64,
+        ..Default::default()
+    };
+    let total = paths.len();
+    let mut pending = paths.into_iter();
+    let mut running: JoinSet<(PathBuf, Result<Outcome>)> = JoinSet::new();
+    let mut done = 0;
+    loop {
+        while running.len() < jobs
+            && let Some(path) = pending.next()
+        {
+            let config = config.clone();
+            running.spawn(async move {
+                let outcome = warm_file(&config, &path).await;
+                (path, outcome)
+            });
+        }
+        let Some(finished) = running.join_next().await else {
+            break;
+        };
+        let (path, outcome) = finished?;
+        match outcome {
+            Ok(Outcome::Cached) => summary.cached += 1,
+            Ok(Outcome::Extracted(len)) => {
+                summary.extracted += 1;
+                summary.bytes += len;
+            }
+            Ok(Outcome::Skipped) => summary.skipped += 1,
+            Err(e) => {
+                warn!("{}: {e:#}", path.display());
+                summary.failed += 1;
+            }
+        }
+        done += 1;
+        show_progress(done, total, &path);
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_data_dir;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn warms_once() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("notes.txt"), "plain text\n")?;
+        std::fs::copy(
+            test_data_dir().join("hello.gz"),
+            dir.path().join("hello.gz"),
+        )?;
+        let mut config = RgaConfig::default();
+        config.cache.path = crate::config::CachePath(cache.path().to_string_lossy().to_string());
+
+        let summary = warm(&config, dir.path()).await?;
+        assert_eq!(
+            summary,
+            Summary {
+                files: 2,
+                extracted: 1,
+                bytes: 7,
+                skipped: 1,
+                ..Default::default()
+            }
+        );
+        let summary = warm(&config, dir.path()).await?;
+        assert_eq!((summary.cached, summary.extracted), (1, 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! `--rga-wc`: the lines, words and bytes of the text rga searches in every file below a path, per
+//! file and per adapter, to size a corpus before ingesting it somewhere else. The text is read from
+//! the cache where possible, files that are not cached yet are extracted (and cached).
+use crate::adapters::AdaptInfo;
+use crate::config::RgaConfig;
+use crate::preproc::{cache_key_for, rga_preproc, unadapted_text};
+use crate::stats::NO_ADAPTER;
+use crate::vfs::{LocalFs, local_path};
+use anyhow::Result;
+use log::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub files: u64,
+    pub lines: u64,
+    pub words: u64,
+    pub bytes: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+}
+
+/// counts lines, words and bytes as `wc` does: lines are newlines, words are runs of non-whitespace
+async fn count(mut inp: impl AsyncRead + Unpin) -> Result<Counts> {
+    let mut counts = Counts {
+        files: 1,
+        ..Default::default()
+    };
+    let mut buf = vec![0; 64 * 1024];
+    let mut in_word = false;
+    loop {
+        let n = inp.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        counts.bytes += n as u64;
+        for &b in &buf[..n] {
+            if b == b'\n' {
+                counts.lines += 1;
+            }
+            if b.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                counts.words += 1;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+#[derive(Debug, Default)]
+pub struct WordCounts {
+    /// the files with text and the adapter that extracted it, in the order they were found
+    pub by_file: Vec<(PathBuf, String, Counts)>,
+    pub by_adapter: BTreeMap<String, Counts>,
+    pub total: Counts,
+    /// binary files no adapter handles, rg skips them
+    pub binary: u64,
+    pub errors: u64,
+}
+
+/// the counts of the text of the file at `path` and the adapter it is from. None if it is binary
+/// and no adapter handles it
+async fn count_file(config: &RgaConfig, path: &Path) -> Result<Option<(String, Counts)>> {
+    let Some((adapter, _)) = cache_key_for(config, path).await? else {
+        let Some(text) = unadapted_text(path).await? else {
+            return Ok(None);
+        };
+        return Ok(Some((NO_ADAPTER.to_string(), count(text).await?)));
+    };
+    let ai = AdaptInfo {
+        inp: Box::pin(tokio::io::BufReader::new(
+            tokio::fs::File::open(path).await?,
+        )),
+        filepath_hint: path.to_owned(),
+        is_real_file: true,
+        file_mtime_unix_ms: None,
+        line_prefix: String::new(),
+        archive_recursion_depth: 0,
+        postprocess: !config.no_prefix_filenames,
+        config: config.clone(),
+    };
+    let counts = count(rga_preproc(ai).await?).await?;
+    Ok(Some((adapter.metadata().name.clone(), counts)))
+}
+
+/// count the text of all files below `root`
+pub async fn collect(config: &RgaConfig, root: &Path) -> Result<WordCounts> {
+    let mut wc = WordCounts::default();
+    let mut entries = LocalFs::new(root).walk();
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let path = local_path(root, &entry.path)?;
+        match count_file(config, &path).await {
+            Ok(Some((adapter, counts))) => {
+                wc.by_adapter
+                    .entry(adapter.clone())
+                    .or_default()
+                    .add(&counts);
+                wc.total.add(&counts);
+                wc.by_file.push((path, adapter, counts));
+            }
+            Ok(None) => wc.binary += 1,
+            Err(e) => {
+                warn!("{}: {e:#}", path.display());
+                wc.errors += 1;
+            }
+        }
+    }
+    Ok(wc)
+}
+
+fn write_counts(f: &mut fmt::Formatter<'_>, counts: &Counts, name: &str) -> fmt::Result {
+    writeln!(
+        f,
+        "{:>10} {:>10} {:>12}  {name}",
+        counts.lines, counts.words, counts.bytes
+    )
+}
+
+impl fmt::Display for WordCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>10} {:>10} {:>12}  file", "lines", "words", "bytes")?;
+        for (path, _, counts) in &self.by_file {
+            write_counts(f, counts, &path.to_string_lossy())?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>8} {:>10} {:>10} {:>12}  adapter",
+            "files", "lines", "words", "bytes"
+        )?;
+        let mut adapters: Vec<_> = self.by_adapter.iter().collect();
+        adapters.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(b.0)));
+        for (name, counts) in adapters
+            .into_iter()
+            .chain([(&"total".to_string(), &self.total)])
+        {
+            write!(f, "{:>8} ", counts.files)?;
+            write_counts(f, counts, name)?;
+        }
+        if self.binary > 0 {
+            writeln!(f, "{} binary files without text", self.binary)?;
+        }
+        if self.errors > 0 {
+            writeln!(f, "{} files could not be read", self.errors)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_data_dir;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn counts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("notes.txt"), "buy  milk\nand\teggs\n")?;
+        std::fs::write(dir.path().join("blob.bin"), [0, 1, 2])?;
+        std::fs::copy(
+            test_data_dir().join("hello.gz"),
+            dir.path().join("hello.gz"),
+        )?;
+
+        let wc = collect(&RgaConfig::default(), dir.path()).await?;
+        let counts = |files, lines, words, bytes| Counts {
+            files,
+            lines,
+            words,
+            bytes,
+        };
+        assert_eq!(
+            wc.by_adapter,
+            BTreeMap::from([
+                ("decompress".to_string(), counts(1, 2, 1, 7)),
+                (NO_ADAPTER.to_string(), counts(1, 2, 4, 19)),
+            ])
+        );
+        assert_eq!(wc.total, counts(2, 4, 5, 26));
+        assert_eq!(wc.binary, 1);
+        Ok(())
+    }
+}